@@ -1,6 +1,7 @@
 use crate::memory::MEMORY;
 use acpi::aml::AmlError;
-use acpi::{AcpiTables, Handle, PciAddress, PhysicalMapping};
+use acpi::sdt::{SdtHeader, Signature};
+use acpi::{AcpiTable, AcpiTables, Handle, PciAddress, PhysicalMapping};
 use core::marker::PhantomData;
 use core::num::NonZero;
 use core::ptr::NonNull;
@@ -212,3 +213,32 @@ pub fn parse(rsdp: &RsdpResponse) -> AcpiTables<impl acpi::Handler> {
     }
     .unwrap()
 }
+
+/// Map `len` bytes of firmware memory at `physical_address`, e.g. an AML table
+/// referenced by address rather than found through the RSDT. Unmapped on drop.
+pub fn map_bytes(physical_address: usize, len: usize) -> PhysicalMapping<impl acpi::Handler, u8> {
+    let handler = KernelAcpiHandler { phantom: PhantomData };
+    unsafe { acpi::Handler::map_physical_region(&handler, physical_address, len) }
+}
+
+#[repr(C, packed)]
+struct Fadt {
+    header: SdtHeader,
+}
+
+unsafe impl AcpiTable for Fadt {
+    const SIGNATURE: Signature = Signature::FADT;
+
+    fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+}
+
+/// Run `f` on the raw bytes of the FADT, header included. Callers read the
+/// fields they need by offset, since their presence depends on the revision.
+pub fn with_fadt<R>(acpi_tables: &AcpiTables<impl acpi::Handler>, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+    let fadt = acpi_tables.find_table::<Fadt>()?;
+    let len = fadt.header.length as usize;
+    let bytes = unsafe { core::slice::from_raw_parts(fadt.virtual_start.as_ptr() as *const u8, len) };
+    Some(f(bytes))
+}
@@ -0,0 +1,62 @@
+//! Boot-time integrity check of the Limine modules against the
+//! `module_checksums` listing the runner puts next to them.
+
+use crate::limine_requests::MODULE_REQUEST;
+use alloc::vec::Vec;
+use limine::file::File;
+use kernel_api_types::crc32::{crc32, expected_checksum, MODULE_CHECKSUMS};
+use spin::Once;
+
+/// Paths of modules whose contents don't match their recorded checksum.
+static CORRUPT: Once<Vec<&'static [u8]>> = Once::new();
+
+/// Checksum every module, log the results and remember the ones that don't
+/// match. Must run before any module is spawned or handed out.
+pub fn verify_checksums() {
+    CORRUPT.call_once(|| {
+        let mut corrupt = Vec::new();
+        let Some(response) = MODULE_REQUEST.get_response() else {
+            return corrupt;
+        };
+        let listing = response
+            .modules()
+            .iter()
+            .find(|m| name(m) == MODULE_CHECKSUMS)
+            .and_then(|m| core::str::from_utf8(bytes(m)).ok());
+        if listing.is_none() {
+            log::warn!("boot modules: no {MODULE_CHECKSUMS} listing, checksums are not verified");
+        }
+
+        for module in response.modules() {
+            let name = name(module);
+            if name == MODULE_CHECKSUMS {
+                continue;
+            }
+            let crc = crc32(bytes(module));
+            match listing.and_then(|l| expected_checksum(l, name)) {
+                Some(expected) if expected != crc => {
+                    log::error!("boot modules: {name} is corrupt (crc32 {crc:08x}, expected {expected:08x})");
+                    corrupt.push(module.path().to_bytes());
+                }
+                Some(_) => log::info!("boot modules: {name} crc32 {crc:08x} ok"),
+                None => log::info!("boot modules: {name} crc32 {crc:08x} (not listed)"),
+            }
+        }
+        corrupt
+    });
+}
+
+fn bytes(module: &File) -> &'static [u8] {
+    // Limine keeps modules mapped, unchanged, for the kernel's lifetime.
+    unsafe { core::slice::from_raw_parts(module.addr() as *const u8, module.size() as usize) }
+}
+
+/// The module's path without the leading "/".
+fn name(module: &File) -> &str {
+    module.path().to_str().unwrap_or("").trim_start_matches('/')
+}
+
+/// Whether the module at `path` (e.g. "/init_task") failed its checksum.
+pub fn is_corrupt(path: &[u8]) -> bool {
+    CORRUPT.get().is_some_and(|corrupt| corrupt.contains(&path))
+}
@@ -0,0 +1,81 @@
+//! The kernel command line, as given by the `cmdline:` entry in limine.conf.
+//!
+//! Options are separated by whitespace and are either `key=value` or a bare
+//! `key`, which has an empty value. A value may be wrapped in double quotes to
+//! hold spaces; there are no escapes. If a key appears more than once the last
+//! one wins, so options appended later override earlier ones.
+//!
+//! `init` reads the command line once, early in boot; it needs no heap. After
+//! that `get` looks options up in place.
+
+use crate::limine_requests::KERNEL_FILE_REQUEST;
+use spin::Once;
+
+static CMDLINE: Once<&'static str> = Once::new();
+
+/// Read the command line Limine passed to the kernel. A missing or non-UTF-8
+/// command line is treated as empty.
+pub fn init() {
+    CMDLINE.call_once(|| {
+        KERNEL_FILE_REQUEST
+            .get_response()
+            .and_then(|response| response.file().string().to_str().ok())
+            .unwrap_or("")
+    });
+}
+
+/// The whole command line; empty before `init`.
+pub fn raw() -> &'static str {
+    CMDLINE.get().copied().unwrap_or("")
+}
+
+/// The value of option `key`, `Some("")` for a bare flag, or `None` if it
+/// wasn't given (or `init` hasn't run).
+pub fn get(key: &str) -> Option<&'static str> {
+    lookup(raw(), key)
+}
+
+/// The value of option `key` in `cmdline`, by the same rules as `get`.
+pub fn lookup<'a>(cmdline: &'a str, key: &str) -> Option<&'a str> {
+    options(cmdline).filter(|&(k, _)| k == key).last().map(|(_, value)| value)
+}
+
+/// Every `(key, value)` pair in `cmdline`, in order, duplicates included.
+pub fn options(cmdline: &str) -> Options<'_> {
+    Options { rest: cmdline }
+}
+
+pub struct Options<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest.trim_start();
+        if rest.is_empty() {
+            self.rest = rest;
+            return None;
+        }
+        let key_end = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        let Some(after_eq) = rest[key_end..].strip_prefix('=') else {
+            self.rest = &rest[key_end..];
+            return Some((key, ""));
+        };
+        let (value, rest) = match after_eq.strip_prefix('"') {
+            // An unterminated quote runs to the end of the line.
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => {
+                let end = after_eq.find(char::is_whitespace).unwrap_or(after_eq.len());
+                after_eq.split_at(end)
+            }
+        };
+        self.rest = rest;
+        Some((key, value))
+    }
+}
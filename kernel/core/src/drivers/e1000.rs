@@ -0,0 +1,346 @@
+//! Intel 8254x (e1000) Ethernet driver, as emulated by QEMU's default NIC.
+//!
+//! The driver is polled: frames are sent by filling the next transmit
+//! descriptor and bumping the tail, and received by checking the next receive
+//! descriptor's DD bit. Descriptor rings and packet buffers live in kernel
+//! frames accessed through the HHDM; the device reaches them by DMA.
+
+use super::pci::{self, PciDevice};
+use crate::memory::MEMORY;
+use crate::memory::physical_memory::{KernelMemoryUsageType, MemoryType, OffsetMappedPhysAddr};
+use kernel_api_types::net::{MacAddress, MAX_FRAME_SIZE, MIN_FRAME_SIZE};
+use spin::{Mutex, Once};
+use x86_64::structures::paging::{PageSize, Size4KiB};
+
+const VENDOR_INTEL: u16 = 0x8086;
+/// 82540EM (QEMU `e1000`), 82545EM (QEMU `e1000-82545em`) and 82544GC.
+const DEVICE_IDS: [u16; 3] = [0x100e, 0x100f, 0x1004];
+
+// Register offsets.
+const CTRL: usize = 0x0000;
+const STATUS: usize = 0x0008;
+const IMC: usize = 0x00d8;
+const RCTL: usize = 0x0100;
+const TCTL: usize = 0x0400;
+const TIPG: usize = 0x0410;
+const RDBAL: usize = 0x2800;
+const RDBAH: usize = 0x2804;
+const RDLEN: usize = 0x2808;
+const RDH: usize = 0x2810;
+const RDT: usize = 0x2818;
+const TDBAL: usize = 0x3800;
+const TDBAH: usize = 0x3804;
+const TDLEN: usize = 0x3808;
+const TDH: usize = 0x3810;
+const TDT: usize = 0x3818;
+const MTA: usize = 0x5200;
+const RAL0: usize = 0x5400;
+const RAH0: usize = 0x5404;
+
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+const STATUS_LU: u32 = 1 << 1;
+
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_BAM: u32 = 1 << 15;
+/// Strip the Ethernet CRC so received lengths match what was sent.
+const RCTL_SECRC: u32 = 1 << 26;
+// BSIZE = 00 with BSEX = 0 selects 2048-byte receive buffers.
+
+const TCTL_EN: u32 = 1 << 1;
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x10 << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+/// Recommended inter-packet gap for IEEE 802.3 copper.
+const TIPG_DEFAULT: u32 = 10 | (8 << 10) | (6 << 20);
+
+const RAH_AV: u32 = 1 << 31;
+
+const DESC_DD: u8 = 1 << 0;
+const RX_EOP: u8 = 1 << 1;
+const TX_CMD_EOP: u8 = 1 << 0;
+const TX_CMD_IFCS: u8 = 1 << 1;
+const TX_CMD_RS: u8 = 1 << 3;
+
+const RX_RING_LEN: usize = 32;
+const TX_RING_LEN: usize = 8;
+const BUFFER_SIZE: usize = 2048;
+const BUFFERS_PER_FRAME: usize = Size4KiB::SIZE as usize / BUFFER_SIZE;
+
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct RxDescriptor {
+    address: u64,
+    length: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct TxDescriptor {
+    address: u64,
+    length: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    /// No supported NIC was found at boot.
+    NoDevice,
+    /// The frame is shorter than an Ethernet header or longer than `MAX_FRAME_SIZE`.
+    BadLength,
+    /// Every transmit descriptor is still owned by the device.
+    TxFull,
+    /// Couldn't allocate the descriptor rings or buffers.
+    OutOfMemory,
+}
+
+/// A physically contiguous 4 KiB DMA page, seen through the HHDM.
+#[derive(Clone, Copy)]
+struct DmaPage {
+    phys: u64,
+    virt: *mut u8,
+}
+
+impl DmaPage {
+    fn allocate() -> Result<Self, NetError> {
+        let memory = MEMORY.get().unwrap();
+        let frame = memory
+            .physical_memory
+            .lock()
+            .allocate_frame_with_type(MemoryType::UsedByKernel(KernelMemoryUsageType::DeviceDma))
+            .ok_or(NetError::OutOfMemory)?;
+        let virt = frame.start_address().offset_mapped().as_mut_ptr::<u8>();
+        unsafe { core::ptr::write_bytes(virt, 0, Size4KiB::SIZE as usize) };
+        Ok(Self { phys: frame.start_address().as_u64(), virt })
+    }
+
+    /// The `index`th `BUFFER_SIZE` slice of a group of pages.
+    fn buffer(pages: &[DmaPage], index: usize) -> DmaPage {
+        let page = pages[index / BUFFERS_PER_FRAME];
+        let offset = (index % BUFFERS_PER_FRAME) * BUFFER_SIZE;
+        DmaPage { phys: page.phys + offset as u64, virt: unsafe { page.virt.add(offset) } }
+    }
+}
+
+pub struct E1000 {
+    regs: *mut u8,
+    mac: MacAddress,
+    rx_ring: *mut RxDescriptor,
+    rx_buffers: [DmaPage; RX_RING_LEN / BUFFERS_PER_FRAME],
+    /// Next receive descriptor to check for a frame.
+    rx_next: usize,
+    tx_ring: *mut TxDescriptor,
+    tx_buffers: [DmaPage; TX_RING_LEN / BUFFERS_PER_FRAME],
+    /// Next transmit descriptor to fill.
+    tx_next: usize,
+}
+
+// The raw pointers are MMIO and DMA memory owned by this driver; every access
+// goes through the `NIC` mutex.
+unsafe impl Send for E1000 {}
+
+impl E1000 {
+    fn read(&self, register: usize) -> u32 {
+        unsafe { core::ptr::read_volatile(self.regs.add(register) as *const u32) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { core::ptr::write_volatile(self.regs.add(register) as *mut u32, value) }
+    }
+
+    fn new(device: &PciDevice) -> Result<Self, NetError> {
+        device.address.enable_bus_master();
+        let regs = device.map_bar(0).ok_or(NetError::NoDevice)?;
+
+        let rx_ring_page = DmaPage::allocate()?;
+        let tx_ring_page = DmaPage::allocate()?;
+        let mut rx_buffers = [rx_ring_page; RX_RING_LEN / BUFFERS_PER_FRAME];
+        for page in rx_buffers.iter_mut() {
+            *page = DmaPage::allocate()?;
+        }
+        let mut tx_buffers = [tx_ring_page; TX_RING_LEN / BUFFERS_PER_FRAME];
+        for page in tx_buffers.iter_mut() {
+            *page = DmaPage::allocate()?;
+        }
+
+        let mut nic = E1000 {
+            regs,
+            mac: [0; 6],
+            rx_ring: rx_ring_page.virt as *mut RxDescriptor,
+            rx_buffers,
+            rx_next: 0,
+            tx_ring: tx_ring_page.virt as *mut TxDescriptor,
+            tx_buffers,
+            tx_next: 0,
+        };
+        nic.reset();
+        nic.mac = nic.read_mac();
+        nic.init_rx(rx_ring_page.phys);
+        nic.init_tx(tx_ring_page.phys);
+        nic.write(CTRL, nic.read(CTRL) | CTRL_SLU);
+        Ok(nic)
+    }
+
+    fn reset(&self) {
+        self.write(IMC, u32::MAX);
+        self.write(CTRL, self.read(CTRL) | CTRL_RST);
+        // RST self-clears once the reset completes (about 1 us on real parts).
+        while self.read(CTRL) & CTRL_RST != 0 {
+            core::hint::spin_loop();
+        }
+        self.write(IMC, u32::MAX);
+    }
+
+    /// The EEPROM MAC is loaded into receive address 0 on reset.
+    fn read_mac(&self) -> MacAddress {
+        let low = self.read(RAL0).to_le_bytes();
+        let high = self.read(RAH0).to_le_bytes();
+        [low[0], low[1], low[2], low[3], high[0], high[1]]
+    }
+
+    fn init_rx(&mut self, ring_phys: u64) {
+        for i in 0..RX_RING_LEN {
+            let buffer = DmaPage::buffer(&self.rx_buffers, i);
+            unsafe {
+                self.rx_ring.add(i).write_volatile(RxDescriptor { address: buffer.phys, ..Default::default() });
+            }
+        }
+        for i in 0..128 {
+            self.write(MTA + i * 4, 0);
+        }
+        self.write(RAH0, self.read(RAH0) | RAH_AV);
+
+        self.write(RDBAL, ring_phys as u32);
+        self.write(RDBAH, (ring_phys >> 32) as u32);
+        self.write(RDLEN, (RX_RING_LEN * size_of::<RxDescriptor>()) as u32);
+        self.write(RDH, 0);
+        // Hand every descriptor but one to the device: head == tail means empty.
+        self.write(RDT, RX_RING_LEN as u32 - 1);
+        self.write(RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+    }
+
+    fn init_tx(&mut self, ring_phys: u64) {
+        for i in 0..TX_RING_LEN {
+            let buffer = DmaPage::buffer(&self.tx_buffers, i);
+            // Start with DD set so every descriptor reads as free.
+            let descriptor = TxDescriptor { address: buffer.phys, status: DESC_DD, ..Default::default() };
+            unsafe { self.tx_ring.add(i).write_volatile(descriptor) };
+        }
+        self.write(TDBAL, ring_phys as u32);
+        self.write(TDBAH, (ring_phys >> 32) as u32);
+        self.write(TDLEN, (TX_RING_LEN * size_of::<TxDescriptor>()) as u32);
+        self.write(TDH, 0);
+        self.write(TDT, 0);
+        self.write(TIPG, TIPG_DEFAULT);
+        self.write(TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+    }
+
+    pub fn mac(&self) -> MacAddress {
+        self.mac
+    }
+
+    pub fn link_up(&self) -> bool {
+        self.read(STATUS) & STATUS_LU != 0
+    }
+
+    /// Queue `frame` (destination MAC onwards, no CRC) for transmission. The
+    /// device pads short frames and appends the CRC.
+    pub fn send(&mut self, frame: &[u8]) -> Result<(), NetError> {
+        if !(MIN_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&frame.len()) {
+            return Err(NetError::BadLength);
+        }
+        let slot = unsafe { self.tx_ring.add(self.tx_next) };
+        let mut descriptor = unsafe { slot.read_volatile() };
+        if descriptor.status & DESC_DD == 0 {
+            return Err(NetError::TxFull);
+        }
+
+        let buffer = DmaPage::buffer(&self.tx_buffers, self.tx_next);
+        unsafe { core::ptr::copy_nonoverlapping(frame.as_ptr(), buffer.virt, frame.len()) };
+        descriptor.length = frame.len() as u16;
+        descriptor.cmd = TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS;
+        descriptor.status = 0;
+        unsafe { slot.write_volatile(descriptor) };
+
+        self.tx_next = (self.tx_next + 1) % TX_RING_LEN;
+        self.write(TDT, self.tx_next as u32);
+        Ok(())
+    }
+
+    /// Copy the next received frame into `buf`, returning its length, or
+    /// `None` if nothing has arrived. A frame longer than `buf` is truncated.
+    pub fn recv(&mut self, buf: &mut [u8]) -> Option<usize> {
+        loop {
+            let slot = unsafe { self.rx_ring.add(self.rx_next) };
+            let mut descriptor = unsafe { slot.read_volatile() };
+            if descriptor.status & DESC_DD == 0 {
+                return None;
+            }
+
+            // With 2 KiB buffers every legal frame fits in one descriptor; drop
+            // anything that doesn't (or arrived with errors) rather than
+            // reassembling it.
+            let whole = descriptor.status & RX_EOP != 0 && descriptor.errors == 0;
+            let len = (descriptor.length as usize).min(buf.len());
+            if whole {
+                let buffer = DmaPage::buffer(&self.rx_buffers, self.rx_next);
+                unsafe { core::ptr::copy_nonoverlapping(buffer.virt, buf.as_mut_ptr(), len) };
+            }
+
+            descriptor.status = 0;
+            unsafe { slot.write_volatile(descriptor) };
+            // Give the descriptor back: the tail trails the one we just read.
+            self.write(RDT, self.rx_next as u32);
+            self.rx_next = (self.rx_next + 1) % RX_RING_LEN;
+
+            if whole {
+                return Some(len);
+            }
+        }
+    }
+}
+
+pub static NIC: Once<Mutex<E1000>> = Once::new();
+
+/// Bring up the first supported NIC found by PCI enumeration, if any.
+pub fn init() {
+    let Some(device) = pci::devices()
+        .iter()
+        .find(|d| d.vendor_id == VENDOR_INTEL && DEVICE_IDS.contains(&d.device_id))
+    else {
+        log::info!("No e1000 NIC found");
+        return;
+    };
+    match E1000::new(device) {
+        Ok(nic) => {
+            let mac = nic.mac();
+            log::info!(
+                "e1000 at {}: MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}, link {}",
+                device.address, mac[0], mac[1], mac[2], mac[3], mac[4], mac[5],
+                if nic.link_up() { "up" } else { "down" },
+            );
+            NIC.call_once(|| Mutex::new(nic));
+        }
+        Err(e) => log::warn!("e1000 at {}: init failed: {:?}", device.address, e),
+    }
+}
+
+pub fn mac_address() -> Result<MacAddress, NetError> {
+    Ok(NIC.get().ok_or(NetError::NoDevice)?.lock().mac())
+}
+
+pub fn send(frame: &[u8]) -> Result<(), NetError> {
+    NIC.get().ok_or(NetError::NoDevice)?.lock().send(frame)
+}
+
+pub fn recv(buf: &mut [u8]) -> Result<Option<usize>, NetError> {
+    Ok(NIC.get().ok_or(NetError::NoDevice)?.lock().recv(buf))
+}
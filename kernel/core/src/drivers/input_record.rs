@@ -0,0 +1,64 @@
+//! Recording of the keyboard and mouse events the drivers queue, with
+//! timestamps, so a session can be replayed through `InjectInput`.
+//!
+//! Events are recorded from interrupt handlers, so the buffer is fixed-size
+//! and its lock is only taken with interrupts disabled.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use kernel_api_types::input::{InputEvent, RecordedInput, MAX_RECORDED_INPUTS};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+struct Recording {
+    start_ms: u64,
+    events: [RecordedInput; MAX_RECORDED_INPUTS],
+    len: usize,
+}
+
+static RECORDING: Mutex<Recording> = Mutex::new(Recording {
+    start_ms: 0,
+    events: [RecordedInput::EMPTY; MAX_RECORDED_INPUTS],
+    len: 0,
+});
+
+/// Checked before taking the lock, so input costs nothing extra when idle.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Discard any previous recording and start a new one; timestamps count from now.
+pub fn start() {
+    interrupts::without_interrupts(|| {
+        let mut recording = RECORDING.lock();
+        recording.start_ms = crate::time::now_ms();
+        recording.len = 0;
+        ACTIVE.store(true, Ordering::Release);
+    });
+}
+
+/// Stop recording and copy the events into `out`, oldest first. Returns how
+/// many were copied.
+pub fn stop(out: &mut [RecordedInput]) -> usize {
+    interrupts::without_interrupts(|| {
+        ACTIVE.store(false, Ordering::Release);
+        let recording = RECORDING.lock();
+        let n = recording.len.min(out.len());
+        out[..n].copy_from_slice(&recording.events[..n]);
+        n
+    })
+}
+
+/// Called by the input drivers for every event they queue.
+pub fn record(event: InputEvent) {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let mut recording = RECORDING.lock();
+        let len = recording.len;
+        if len == MAX_RECORDED_INPUTS {
+            return;
+        }
+        let time_ms = crate::time::now_ms().saturating_sub(recording.start_ms);
+        recording.events[len] = RecordedInput { time_ms, event };
+        recording.len += 1;
+    });
+}
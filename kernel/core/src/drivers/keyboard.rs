@@ -1,8 +1,9 @@
 use alloc::sync::Arc;
 use core::sync::atomic::{AtomicBool, Ordering};
 use kernel_api_types::KeyEvent;
+use kernel_api_types::input::InputEvent;
 use spin::Mutex;
-use crate::task::task::{Task, TaskState};
+use crate::task::task::Task;
 
 /// PS/2 Set 1 scancode-to-ASCII lookup table (unshifted)
 static NORMAL: &[u8] = &[
@@ -157,17 +158,16 @@ pub fn handle_scancode(scancode: u8) {
     }
 }
 
-fn push_event(event: KeyEvent) {
+/// Queue a decoded key event and wake the reader.
+///
+/// This is also used by `InjectInput` to queue events that didn't come from
+/// the keyboard.
+pub fn push_event(event: KeyEvent) {
+    crate::drivers::input_record::record(InputEvent::key(event));
     KEY_BUFFER.lock().push(event);
     KEY_AVAILABLE.store(true, Ordering::Release);
     if let Some((task, cpu_id)) = KEYBOARD_WAITER.lock().take() {
-        task.state.store(TaskState::Ready, Ordering::Release);
-        crate::task::local_scheduler::add(crate::memory::cpu_local_data::get_cpu(cpu_id), task);
-        let local_kernel_id = crate::memory::cpu_local_data::get_local().kernel_id;
-        if cpu_id != local_kernel_id {
-            let apic_id = crate::memory::cpu_local_data::local_apic_id_of(cpu_id);
-            crate::apic::send_fixed_ipi(apic_id, u8::from(crate::interrupt::InterruptVector::Reschedule));
-        }
+        crate::task::local_scheduler::wake(task, cpu_id);
     }
 }
 
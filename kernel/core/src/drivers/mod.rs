@@ -1,2 +1,8 @@
+pub mod e1000;
+pub mod input_record;
 pub mod keyboard;
 pub mod mouse;
+pub mod msi;
+pub mod pci;
+pub mod serial;
+pub mod vmmouse;
@@ -1,12 +1,19 @@
 use kernel_api_types::{MouseEvent, MOUSE_LEFT, MOUSE_MIDDLE, MOUSE_RIGHT};
+use kernel_api_types::input::InputEvent;
 use spin::Mutex;
+use super::vmmouse;
 
-const MOUSE_BUFFER_SIZE: usize = 64;
+pub const MOUSE_BUFFER_SIZE: usize = 64;
 
+/// Ring of pending mouse events, oldest first.
+///
+/// When full, motion is coalesced rather than events dropped: the oldest two
+/// adjacent events with the same button state are merged by summing their
+/// deltas (an absolute position simply takes the later one). Merging never hides a button press or release, because every
+/// event carries the full button state and the merged pair agreed on it.
 struct MouseBuffer {
     buffer: [MouseEvent; MOUSE_BUFFER_SIZE],
     head: usize,
-    tail: usize,
     count: usize,
 }
 
@@ -15,17 +22,49 @@ impl MouseBuffer {
         Self {
             buffer: [MouseEvent::EMPTY; MOUSE_BUFFER_SIZE],
             head: 0,
-            tail: 0,
             count: 0,
         }
     }
 
+    /// Physical index of the `i`th oldest event.
+    fn slot(&self, i: usize) -> usize {
+        (self.head + i) % MOUSE_BUFFER_SIZE
+    }
+
     fn push(&mut self, event: MouseEvent) {
-        if self.count < MOUSE_BUFFER_SIZE {
-            self.buffer[self.tail] = event;
-            self.tail = (self.tail + 1) % MOUSE_BUFFER_SIZE;
-            self.count += 1;
+        if self.count == MOUSE_BUFFER_SIZE && !self.coalesce_oldest() {
+            // Every queued event is a button transition. Fold the new event
+            // into the newest one if that loses nothing; otherwise drop it.
+            let newest = self.slot(self.count - 1);
+            if self.buffer[newest].buttons == event.buttons {
+                self.buffer[newest] = merge(self.buffer[newest], event);
+            }
+            return;
         }
+        let tail = self.slot(self.count);
+        self.buffer[tail] = event;
+        self.count += 1;
+    }
+
+    /// Merge the oldest adjacent pair of events with equal button state into
+    /// the later of the two, freeing one slot. Returns false if no such pair
+    /// exists.
+    fn coalesce_oldest(&mut self) -> bool {
+        let Some(i) = (0..self.count - 1)
+            .find(|&i| self.buffer[self.slot(i)].buttons == self.buffer[self.slot(i + 1)].buttons)
+        else {
+            return false;
+        };
+        let earlier = self.buffer[self.slot(i)];
+        let later = self.slot(i + 1);
+        self.buffer[later] = merge(earlier, self.buffer[later]);
+        // Close the gap at `i` by shifting the older events up one slot.
+        for j in (0..i).rev() {
+            self.buffer[self.slot(j + 1)] = self.buffer[self.slot(j)];
+        }
+        self.head = (self.head + 1) % MOUSE_BUFFER_SIZE;
+        self.count -= 1;
+        true
     }
 
     fn pop(&mut self) -> Option<MouseEvent> {
@@ -39,13 +78,28 @@ impl MouseBuffer {
     }
 }
 
+/// One event equivalent to `earlier` followed by `later`: the motion summed,
+/// and the later button state and absolute position.
+fn merge(earlier: MouseEvent, later: MouseEvent) -> MouseEvent {
+    MouseEvent {
+        dx: earlier.dx.saturating_add(later.dx),
+        dy: earlier.dy.saturating_add(later.dy),
+        ..later
+    }
+}
+
 static MOUSE_BUFFER: Mutex<MouseBuffer> = Mutex::new(MouseBuffer::new());
 
 /// 3-byte PS/2 packet accumulator
 static PACKET: Mutex<[u8; 3]> = Mutex::new([0u8; 3]);
 static PACKET_IDX: Mutex<u8> = Mutex::new(0);
 
-fn push_event(event: MouseEvent) {
+/// Queue a decoded mouse event.
+///
+/// This is also used by `InjectInput` to queue events that didn't come from
+/// the mouse.
+pub fn push_event(event: MouseEvent) {
+    crate::drivers::input_record::record(InputEvent::mouse(event));
     MOUSE_BUFFER.lock().push(event);
 }
 
@@ -54,11 +108,23 @@ pub fn try_read_mouse() -> Option<MouseEvent> {
     MOUSE_BUFFER.lock().pop()
 }
 
-/// Called from the mouse interrupt handler. Reads one byte from port 0x60,
-/// accumulates into a 3-byte packet, and emits a MouseEvent on the 3rd byte.
+/// Called from the mouse interrupt handler.
 pub fn on_mouse_interrupt() {
     let byte: u8 = unsafe { x86::io::inb(0x60) };
+    // With an absolute pointer the PS/2 packets are only a doorbell; the
+    // events themselves come from the vmmouse queue.
+    if vmmouse::is_active() {
+        vmmouse::poll(push_event);
+        return;
+    }
+    handle_byte(byte);
+}
 
+/// Accumulate one PS/2 byte into a 3-byte packet and emit a MouseEvent on the
+/// 3rd byte.
+///
+/// This is also used by tests to feed packets without doing port I/O.
+pub fn handle_byte(byte: u8) {
     let mut idx = PACKET_IDX.lock();
     let mut pkt = PACKET.lock();
 
@@ -96,10 +162,18 @@ pub fn on_mouse_interrupt() {
         drop(pkt);
         drop(idx);
 
-        push_event(MouseEvent { dx, dy, buttons });
+        push_event(MouseEvent { dx, dy, buttons, ..MouseEvent::EMPTY });
     }
 }
 
+/// Reset the event buffer and packet accumulator.
+/// Used by tests to ensure a clean state between test cases.
+pub fn reset() {
+    *MOUSE_BUFFER.lock() = MouseBuffer::new();
+    *PACKET.lock() = [0u8; 3];
+    *PACKET_IDX.lock() = 0;
+}
+
 fn ps2_wait_write() {
     loop {
         let status: u8 = unsafe { x86::io::inb(0x64) };
@@ -158,5 +232,9 @@ pub fn init() {
         log::info!("PS/2 mouse: drained ACK byte {:#04x}", ack);
     }
 
-    log::info!("PS/2 mouse initialized");
+    if vmmouse::init() {
+        log::info!("PS/2 mouse initialized, vmmouse absolute pointer enabled");
+    } else {
+        log::info!("PS/2 mouse initialized");
+    }
 }
@@ -0,0 +1,135 @@
+//! Message Signalled Interrupts (MSI and MSI-X) for PCI devices.
+//!
+//! Instead of asserting an INTx pin routed through the IOAPIC, the device
+//! writes `message_data` to `message_address`, which the chipset turns into an
+//! interrupt on the local APIC named in the address. The IDT only has a
+//! handler at `InterruptVector::Msi`; everything programmed here should use
+//! that vector and register a handler with `register_handler`.
+
+use super::pci::{PciAddress, PciDevice};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+pub const CAPABILITY_MSI: u8 = 0x05;
+pub const CAPABILITY_MSIX: u8 = 0x11;
+
+// MSI message control bits.
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_MULTIPLE_MESSAGE_ENABLE: u16 = 0b111 << 4;
+const MSI_64_BIT: u16 = 1 << 7;
+
+// MSI-X message control bits.
+const MSIX_TABLE_SIZE: u16 = 0x7ff;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+
+const MSIX_ENTRY_SIZE: usize = 16;
+const MSIX_VECTOR_MASKED: u32 = 1;
+
+/// Base of the interrupt message window on x86.
+const MESSAGE_ADDRESS_BASE: u64 = 0xfee0_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsiError {
+    /// The function has no MSI (or MSI-X) capability.
+    Unsupported,
+    /// The requested MSI-X table entry is past the end of the table.
+    InvalidEntry,
+    /// The MSI-X table lives in a BAR that isn't a memory BAR.
+    TableUnmapped,
+}
+
+/// Number of MSIs taken on `InterruptVector::Msi`.
+pub static MSI_COUNT: AtomicU64 = AtomicU64::new(0);
+
+static HANDLERS: Mutex<Vec<fn()>> = Mutex::new(Vec::new());
+
+/// Address a device writes to interrupt the local APIC `apic_id`, with
+/// physical destination mode and no redirection hint.
+pub fn message_address(apic_id: u32) -> u64 {
+    MESSAGE_ADDRESS_BASE | ((apic_id as u64 & 0xff) << 12)
+}
+
+/// Data for an edge-triggered, fixed-delivery interrupt on `vector`.
+pub fn message_data(vector: u8) -> u32 {
+    vector as u32
+}
+
+/// Program the device's MSI capability with a single message to `vector` on
+/// `apic_id` and enable it. Legacy INTx is disabled and bus mastering enabled,
+/// since the message is a memory write.
+pub fn enable_msi(device: &PciDevice, vector: u8, apic_id: u32) -> Result<(), MsiError> {
+    let address = device.address;
+    let cap = address.find_capability(CAPABILITY_MSI).ok_or(MsiError::Unsupported)?;
+    let control = address.read_u16(cap + 2);
+
+    let message = message_address(apic_id);
+    address.write_u32(cap + 4, message as u32);
+    let data_offset = if control & MSI_64_BIT != 0 {
+        address.write_u32(cap + 8, (message >> 32) as u32);
+        cap + 12
+    } else {
+        cap + 8
+    };
+    address.write_u16(data_offset, message_data(vector) as u16);
+
+    address.enable_bus_master();
+    address.disable_intx();
+    // One message only: Multiple Message Enable = 0.
+    address.write_u16(cap + 2, (control & !MSI_MULTIPLE_MESSAGE_ENABLE) | MSI_ENABLE);
+    Ok(())
+}
+
+/// Number of entries in the device's MSI-X table, if it has one.
+pub fn msix_table_size(address: PciAddress) -> Option<u16> {
+    let cap = address.find_capability(CAPABILITY_MSIX)?;
+    Some((address.read_u16(cap + 2) & MSIX_TABLE_SIZE) + 1)
+}
+
+/// Route MSI-X table entry `entry` to `vector` on `apic_id`, unmask it and
+/// enable MSI-X. Other entries keep whatever mask state they had.
+pub fn enable_msix(device: &PciDevice, entry: u16, vector: u8, apic_id: u32) -> Result<(), MsiError> {
+    let address = device.address;
+    let cap = address.find_capability(CAPABILITY_MSIX).ok_or(MsiError::Unsupported)?;
+    let control = address.read_u16(cap + 2);
+    if entry > control & MSIX_TABLE_SIZE {
+        return Err(MsiError::InvalidEntry);
+    }
+
+    // The low three bits select the BAR; the rest is the offset into it.
+    let table = address.read_u32(cap + 4);
+    let bar = device.map_bar((table & 0x7) as usize).ok_or(MsiError::TableUnmapped)?;
+
+    address.enable_bus_master();
+    address.disable_intx();
+    // Mask the whole function while the entry is half-written.
+    address.write_u16(cap + 2, control | MSIX_ENABLE | MSIX_FUNCTION_MASK);
+
+    let message = message_address(apic_id);
+    unsafe {
+        let entry = bar.add((table & !0x7) as usize + entry as usize * MSIX_ENTRY_SIZE) as *mut u32;
+        entry.write_volatile(message as u32);
+        entry.add(1).write_volatile((message >> 32) as u32);
+        entry.add(2).write_volatile(message_data(vector));
+        let vector_control = entry.add(3).read_volatile();
+        entry.add(3).write_volatile(vector_control & !MSIX_VECTOR_MASKED);
+    }
+
+    address.write_u16(cap + 2, (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK);
+    Ok(())
+}
+
+/// Add a handler to run on every MSI. The vector is shared, so handlers must
+/// check their own device's interrupt status.
+pub fn register_handler(handler: fn()) {
+    // The MSI handler takes the same lock.
+    x86_64::instructions::interrupts::without_interrupts(|| HANDLERS.lock().push(handler));
+}
+
+pub fn on_msi_interrupt() {
+    MSI_COUNT.fetch_add(1, Ordering::Relaxed);
+    for handler in HANDLERS.lock().iter() {
+        handler();
+    }
+}
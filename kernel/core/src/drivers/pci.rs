@@ -0,0 +1,337 @@
+//! PCI enumeration through the legacy configuration mechanism (ports 0xCF8/0xCFC).
+//!
+//! Every bus/device/function is probed once at boot; the result is kept in
+//! `DEVICES` for drivers to search by vendor/device id or class.
+
+use crate::memory::MEMORY;
+use alloc::vec::Vec;
+use core::num::NonZero;
+use spin::{Mutex, Once};
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+const VENDOR_NONE: u16 = 0xffff;
+
+// Offsets into the common configuration header.
+const REG_ID: u8 = 0x00;
+const REG_COMMAND: u8 = 0x04;
+const REG_STATUS: u8 = 0x06;
+const REG_CLASS: u8 = 0x08;
+const REG_HEADER_TYPE: u8 = 0x0c;
+const REG_BAR0: u8 = 0x10;
+const REG_CAPABILITIES: u8 = 0x34;
+const REG_INTERRUPT: u8 = 0x3c;
+
+const COMMAND_IO_SPACE: u32 = 1 << 0;
+const COMMAND_MEMORY_SPACE: u32 = 1 << 1;
+const COMMAND_BUS_MASTER: u32 = 1 << 2;
+const COMMAND_INTX_DISABLE: u32 = 1 << 10;
+
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+
+const HEADER_TYPE_MULTI_FUNCTION: u8 = 1 << 7;
+
+/// The address register and data window are shared by every access.
+static CONFIG_LOCK: Mutex<()> = Mutex::new(());
+
+pub static DEVICES: Once<Vec<PciDevice>> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self { bus, device, function }
+    }
+
+    fn config_address(self, offset: u8) -> u32 {
+        1 << 31
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset & 0xfc) as u32
+    }
+
+    pub fn read_u32(self, offset: u8) -> u32 {
+        let _guard = CONFIG_LOCK.lock();
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(self.config_address(offset));
+            Port::<u32>::new(CONFIG_DATA).read()
+        }
+    }
+
+    pub fn write_u32(self, offset: u8, value: u32) {
+        let _guard = CONFIG_LOCK.lock();
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(self.config_address(offset));
+            Port::<u32>::new(CONFIG_DATA).write(value);
+        }
+    }
+
+    pub fn read_u16(self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// Read-modify-write of the containing dword; `offset` must be 2-aligned.
+    pub fn write_u16(self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(offset) & !(0xffff << shift);
+        self.write_u32(offset, dword | (value as u32) << shift);
+    }
+
+    /// Set bits in the command register. The status half is written as zeros,
+    /// which leaves its write-one-to-clear bits alone.
+    fn set_command_bits(self, bits: u32) {
+        let command = self.read_u32(REG_COMMAND) & 0xffff;
+        self.write_u32(REG_COMMAND, command | bits);
+    }
+
+    /// Let the function issue memory writes, which DMA and MSI both need.
+    pub fn enable_bus_master(self) {
+        self.set_command_bits(COMMAND_BUS_MASTER | COMMAND_MEMORY_SPACE);
+    }
+
+    /// Stop the function from asserting its legacy INTx pin.
+    pub fn disable_intx(self) {
+        self.set_command_bits(COMMAND_INTX_DISABLE);
+    }
+
+    /// Walk the capability list, yielding `(capability id, config offset)`.
+    pub fn capabilities(self) -> impl Iterator<Item = (u8, u8)> {
+        let mut next = if self.read_u16(REG_STATUS) & STATUS_CAPABILITIES_LIST != 0 {
+            self.read_u8(REG_CAPABILITIES) & !0x3
+        } else {
+            0
+        };
+        // 48 dword-aligned slots fit after the header; a longer list is a loop.
+        let mut remaining = 48;
+        core::iter::from_fn(move || {
+            if next == 0 || remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            let offset = next;
+            let header = self.read_u16(offset);
+            next = (header >> 8) as u8 & !0x3;
+            Some((header as u8, offset))
+        })
+    }
+
+    /// Config-space offset of the first capability with id `id`.
+    pub fn find_capability(self, id: u8) -> Option<u8> {
+        self.capabilities().find(|&(cap, _)| cap == id).map(|(_, offset)| offset)
+    }
+}
+
+impl core::fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory { address: u64, size: u64, prefetchable: bool },
+    Io { port: u32, size: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    /// Indexed by BAR number. The upper half of a 64-bit BAR is `None`.
+    pub bars: [Option<Bar>; 6],
+    /// Legacy (PIC/IOAPIC) IRQ line; 0xff if unconnected.
+    pub interrupt_line: u8,
+    /// INTA#..INTD# as 1..4; 0 if the function uses no legacy interrupt.
+    pub interrupt_pin: u8,
+}
+
+impl PciDevice {
+    /// Map memory BAR `index` uncached into kernel space.
+    ///
+    /// Each call creates a new mapping, so drivers should map a BAR once and
+    /// keep the pointer. `None` for I/O BARs and unimplemented ones.
+    pub fn map_bar(&self, index: usize) -> Option<*mut u8> {
+        let Some(Bar::Memory { address, size, .. }) = *self.bars.get(index)? else {
+            return None;
+        };
+        Some(map_mmio(address, size))
+    }
+}
+
+fn map_mmio(phys_addr: u64, size: u64) -> *mut u8 {
+    let memory = MEMORY.get().unwrap();
+    let mut physical_memory = memory.physical_memory.lock();
+    let mut virtual_memory = memory.virtual_memory.lock();
+
+    let first: PhysFrame<Size4KiB> = PhysFrame::containing_address(PhysAddr::new(phys_addr));
+    let last: PhysFrame<Size4KiB> = PhysFrame::containing_address(PhysAddr::new(phys_addr + size - 1));
+    let frames = PhysFrame::range_inclusive(first, last);
+    let page = virtual_memory
+        .allocate_kernel_contiguous_pages(NonZero::new(frames.count() as u64).unwrap())
+        .expect("Failed to allocate virtual pages for PCI BAR");
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH;
+
+    let mut mapper = unsafe { virtual_memory.mapper() };
+    let mut frame_allocator = physical_memory.get_kernel_frame_allocator();
+    for (i, frame) in frames.enumerate() {
+        let page: Page<Size4KiB> = page + i as u64;
+        unsafe {
+            mapper.map_to(page, frame, flags, &mut frame_allocator)
+                .expect("Failed to map PCI BAR")
+                .flush();
+        }
+    }
+
+    (page.start_address() + (phys_addr & 0xfff)).as_mut_ptr()
+}
+
+/// Probe every bus, device and function.
+pub fn enumerate() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            let Some(first) = probe(PciAddress::new(bus, device, 0)) else {
+                continue;
+            };
+            let multi_function = first.header_type & HEADER_TYPE_MULTI_FUNCTION != 0;
+            devices.push(first);
+            if multi_function {
+                devices.extend((1..8).filter_map(|function| probe(PciAddress::new(bus, device, function))));
+            }
+        }
+    }
+    devices
+}
+
+fn probe(address: PciAddress) -> Option<PciDevice> {
+    let id = address.read_u32(REG_ID);
+    let vendor_id = id as u16;
+    if vendor_id == VENDOR_NONE {
+        return None;
+    }
+    let class = address.read_u32(REG_CLASS);
+    let header_type = address.read_u8(REG_HEADER_TYPE);
+    let interrupt = address.read_u32(REG_INTERRUPT);
+
+    // Only ordinary functions (header type 0) have six BARs; bridges have two.
+    let bar_count = match header_type & !HEADER_TYPE_MULTI_FUNCTION {
+        0 => 6,
+        1 => 2,
+        _ => 0,
+    };
+
+    Some(PciDevice {
+        address,
+        vendor_id,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+        revision: class as u8,
+        header_type,
+        bars: read_bars(address, bar_count),
+        interrupt_line: interrupt as u8,
+        interrupt_pin: (interrupt >> 8) as u8,
+    })
+}
+
+/// Decode and size the first `count` BARs.
+///
+/// Sizing writes all ones to each BAR and reads back the writable bits, so
+/// I/O and memory decoding are switched off meanwhile and restored afterwards.
+fn read_bars(address: PciAddress, count: usize) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+    let command = address.read_u32(REG_COMMAND);
+    address.write_u32(REG_COMMAND, command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE));
+
+    let mut i = 0;
+    while i < count {
+        let offset = REG_BAR0 + 4 * i as u8;
+        let (original, mask) = probe_register(address, offset);
+
+        if original & 1 == 1 {
+            // I/O ports are 16 bits wide on x86.
+            let mask = mask & 0xfffc;
+            if mask != 0 {
+                bars[i] = Some(Bar::Io { port: original & !0x3, size: (!mask & 0xffff) + 1 });
+            }
+            i += 1;
+            continue;
+        }
+
+        let is_64 = (original >> 1) & 0x3 == 0x2 && i + 1 < count;
+        let prefetchable = original & (1 << 3) != 0;
+        let (base, mask) = if is_64 {
+            let (high, high_mask) = probe_register(address, offset + 4);
+            (
+                (high as u64) << 32 | (original & !0xf) as u64,
+                (high_mask as u64) << 32 | (mask & !0xf) as u64,
+            )
+        } else {
+            // Sign-extend the mask so the size computation below works in 64 bits.
+            let low = mask & !0xf;
+            ((original & !0xf) as u64, if low == 0 { 0 } else { 0xffff_ffff_0000_0000 | low as u64 })
+        };
+        if mask != 0 {
+            bars[i] = Some(Bar::Memory { address: base, size: (!mask).wrapping_add(1), prefetchable });
+        }
+        i += if is_64 { 2 } else { 1 };
+    }
+
+    address.write_u32(REG_COMMAND, command);
+    bars
+}
+
+/// Write all ones to a BAR and read back which bits stick, restoring it after.
+/// Returns `(original, mask)`.
+fn probe_register(address: PciAddress, offset: u8) -> (u32, u32) {
+    let original = address.read_u32(offset);
+    address.write_u32(offset, u32::MAX);
+    let mask = address.read_u32(offset);
+    address.write_u32(offset, original);
+    (original, mask)
+}
+
+/// Enumerate the bus once and log what was found.
+pub fn init() {
+    let devices = DEVICES.call_once(enumerate);
+    for d in devices {
+        log::info!(
+            "PCI {} {:04x}:{:04x} class {:02x}.{:02x}.{:02x}",
+            d.address, d.vendor_id, d.device_id, d.class, d.subclass, d.prog_if,
+        );
+    }
+}
+
+/// Devices found by `init`, or none if it hasn't run.
+pub fn devices() -> &'static [PciDevice] {
+    DEVICES.get().map_or(&[], |d| d.as_slice())
+}
+
+pub fn find(vendor_id: u16, device_id: u16) -> Option<&'static PciDevice> {
+    devices().iter().find(|d| d.vendor_id == vendor_id && d.device_id == device_id)
+}
@@ -0,0 +1,115 @@
+//! Raw byte access to COM1 for the userspace "serial" service.
+//!
+//! The kernel logger owns the port and initialises it. Transmitting happens
+//! under the logger's lock so bytes never land inside a log line. Received
+//! bytes are moved from the UART's FIFO into `RX_BUFFER` by the receive
+//! interrupt (routed at boot to `InterruptVector::Serial`), and `read` takes
+//! them from there. Receiving never takes the logger's lock: the interrupt
+//! may arrive while this CPU is in the middle of a log line.
+
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86::io::{inb, outb};
+
+const COM1: u16 = 0x3f8;
+const INTERRUPT_ENABLE: u16 = COM1 + 1;
+const LINE_STATUS: u16 = COM1 + 5;
+const IER_DATA_AVAILABLE: u8 = 1 << 0;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+const RX_BUFFER_SIZE: usize = 1024;
+
+/// Bytes received but not yet read. Only locked with interrupts disabled.
+struct RxBuffer {
+    buffer: [u8; RX_BUFFER_SIZE],
+    head: usize,
+    count: usize,
+}
+
+impl RxBuffer {
+    const fn new() -> Self {
+        Self { buffer: [0; RX_BUFFER_SIZE], head: 0, count: 0 }
+    }
+
+    /// Move everything the UART holds into the buffer. Bytes that don't fit
+    /// are still taken from the UART, so its interrupt clears, and dropped.
+    fn fill_from_uart(&mut self) {
+        while unsafe { inb(LINE_STATUS) } & LSR_DATA_READY != 0 {
+            let byte = unsafe { inb(COM1) };
+            if self.count < RX_BUFFER_SIZE {
+                self.buffer[(self.head + self.count) % RX_BUFFER_SIZE] = byte;
+                self.count += 1;
+            } else {
+                RX_DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.count == 0 {
+            return None;
+        }
+        let byte = self.buffer[self.head];
+        self.head = (self.head + 1) % RX_BUFFER_SIZE;
+        self.count -= 1;
+        Some(byte)
+    }
+}
+
+static RX_BUFFER: Mutex<RxBuffer> = Mutex::new(RxBuffer::new());
+
+/// Number of interrupts taken on `InterruptVector::Serial`.
+pub static SERIAL_INTERRUPT_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes lost because `RX_BUFFER` was full.
+pub static RX_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Transmit `bytes` exactly as given (no newline translation).
+pub fn write(bytes: &[u8]) {
+    crate::logger::with_serial_port(|| {
+        for &byte in bytes {
+            while unsafe { inb(LINE_STATUS) } & LSR_TRANSMIT_EMPTY == 0 {
+                spin_loop();
+            }
+            unsafe { outb(COM1, byte) };
+        }
+    });
+}
+
+/// Move received bytes into `buf`, without waiting. Returns the number of
+/// bytes read.
+///
+/// The UART is drained first, so this also works while its interrupt is not
+/// routed, as long as it is called before the FIFO overflows.
+pub fn read(buf: &mut [u8]) -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut rx = RX_BUFFER.lock();
+        rx.fill_from_uart();
+        let mut n = 0;
+        while n < buf.len() && let Some(byte) = rx.pop() {
+            buf[n] = byte;
+            n += 1;
+        }
+        n
+    })
+}
+
+/// Number of received bytes waiting for `read`.
+pub fn buffered() -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| RX_BUFFER.lock().count)
+}
+
+/// Have the UART raise its IRQ whenever a byte is received.
+pub fn enable_rx_interrupt() {
+    crate::logger::with_serial_port(|| unsafe {
+        let ier = inb(INTERRUPT_ENABLE);
+        outb(INTERRUPT_ENABLE, ier | IER_DATA_AVAILABLE);
+    });
+}
+
+pub fn on_serial_interrupt() {
+    SERIAL_INTERRUPT_COUNT.fetch_add(1, Ordering::Relaxed);
+    RX_BUFFER.lock().fill_from_uart();
+}
@@ -0,0 +1,133 @@
+//! VMware-compatible absolute pointer ("vmmouse"), as emulated by QEMU with
+//! `-machine vmport=on`.
+//!
+//! The device rides on the PS/2 mouse: it still raises IRQ12 (with dummy
+//! packets) whenever the pointer changes, but the real position is read
+//! through the VMware backdoor I/O port, in device coordinates
+//! 0..=`MOUSE_ABS_MAX` across the screen. QEMU's USB tablet would give the
+//! same thing, but needs a USB host controller driver this kernel lacks.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use kernel_api_types::{MouseEvent, MOUSE_ABSOLUTE, MOUSE_LEFT, MOUSE_MIDDLE, MOUSE_RIGHT};
+
+const BACKDOOR_MAGIC: u32 = 0x564d_5868;
+const BACKDOOR_PORT: u16 = 0x5658;
+
+const CMD_GETVERSION: u32 = 10;
+const CMD_ABSPOINTER_DATA: u32 = 39;
+const CMD_ABSPOINTER_STATUS: u32 = 40;
+const CMD_ABSPOINTER_COMMAND: u32 = 41;
+
+const ABSPOINTER_ENABLE: u32 = 0x4541_4552;
+const ABSPOINTER_DISABLE: u32 = 0x0000_00f5;
+const ABSPOINTER_REQUEST_ABSOLUTE: u32 = 0x5342_4152;
+/// First word queued after enabling, identifying the protocol.
+const VERSION_ID: u32 = 0x3442_554a;
+const STATUS_ERROR: u32 = 0xffff_0000;
+/// Words per queued packet: buttons/flags, x, y, wheel.
+const PACKET_WORDS: u32 = 4;
+
+const PACKET_LEFT: u32 = 0x20;
+const PACKET_RIGHT: u32 = 0x10;
+const PACKET_MIDDLE: u32 = 0x08;
+/// Set when the packet carries relative motion instead of a position.
+const PACKET_RELATIVE: u32 = 0x1_0000;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Issue a backdoor call; returns (eax, ebx, ecx, edx).
+fn backdoor(command: u32, arg: u32) -> (u32, u32, u32, u32) {
+    let (eax, ecx, edx): (u32, u32, u32);
+    let mut ebx = arg as u64;
+    unsafe {
+        // LLVM reserves rbx, so swap the argument in and the result out.
+        core::arch::asm!(
+            "xchg rbx, {b}",
+            "in eax, dx",
+            "xchg rbx, {b}",
+            b = inout(reg) ebx,
+            inout("eax") BACKDOOR_MAGIC => eax,
+            inout("ecx") command => ecx,
+            inout("edx") BACKDOOR_PORT as u32 => edx,
+            options(nostack, preserves_flags),
+        );
+    }
+    (eax, ebx as u32, ecx, edx)
+}
+
+/// Number of words queued, or None if the device reports an error.
+fn queued_words() -> Option<u32> {
+    let (status, ..) = backdoor(CMD_ABSPOINTER_STATUS, 0);
+    (status != STATUS_ERROR).then_some(status & 0xffff)
+}
+
+/// Enable the device and consume the version word it queues in response,
+/// then ask for absolute positions.
+fn enable() -> bool {
+    backdoor(CMD_ABSPOINTER_COMMAND, ABSPOINTER_ENABLE);
+    if queued_words() != Some(1) || backdoor(CMD_ABSPOINTER_DATA, 1).0 != VERSION_ID {
+        backdoor(CMD_ABSPOINTER_COMMAND, ABSPOINTER_DISABLE);
+        return false;
+    }
+    backdoor(CMD_ABSPOINTER_COMMAND, ABSPOINTER_REQUEST_ABSOLUTE);
+    true
+}
+
+/// Probe for the device and switch it to absolute mode. Must run after the
+/// PS/2 mouse is initialised and before IRQ12 is unmasked.
+pub fn init() -> bool {
+    let (version, magic, ..) = backdoor(CMD_GETVERSION, 0);
+    if magic != BACKDOOR_MAGIC || version == u32::MAX {
+        return false;
+    }
+    if !enable() {
+        log::warn!("vmmouse: backdoor present but enabling failed; staying relative");
+        return false;
+    }
+    ACTIVE.store(true, Ordering::Release);
+    true
+}
+
+/// True once `init` has put the device in absolute mode.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// Decode one queued packet.
+///
+/// This is also used by tests to check the decoding without the device.
+pub fn decode_packet(status: u32, x: u32, y: u32) -> MouseEvent {
+    let mut buttons = 0;
+    if status & PACKET_LEFT != 0 { buttons |= MOUSE_LEFT; }
+    if status & PACKET_RIGHT != 0 { buttons |= MOUSE_RIGHT; }
+    if status & PACKET_MIDDLE != 0 { buttons |= MOUSE_MIDDLE; }
+
+    if status & PACKET_RELATIVE != 0 {
+        // Relative packets carry signed 32-bit deltas, y already screen-down.
+        let clamp = |v: u32| (v as i32).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        MouseEvent { dx: clamp(x), dy: clamp(y), buttons, ..MouseEvent::EMPTY }
+    } else {
+        MouseEvent { buttons, flags: MOUSE_ABSOLUTE, abs_x: x as u16, abs_y: y as u16, ..MouseEvent::EMPTY }
+    }
+}
+
+/// Drain every queued packet into `push`. Called from the mouse interrupt
+/// in place of PS/2 packet decoding.
+pub fn poll(mut push: impl FnMut(MouseEvent)) {
+    loop {
+        let Some(words) = queued_words() else {
+            // The queue overflowed; re-enable to recover, or fall back to
+            // the plain PS/2 packets.
+            log::warn!("vmmouse: device error, resetting");
+            if !enable() {
+                ACTIVE.store(false, Ordering::Release);
+            }
+            return;
+        };
+        if words < PACKET_WORDS {
+            return;
+        }
+        let (status, x, y, _wheel) = backdoor(CMD_ABSPOINTER_DATA, PACKET_WORDS);
+        push(decode_packet(status, x, y));
+    }
+}
@@ -6,14 +6,40 @@ use embedded_graphics::geometry::Dimensions;
 use embedded_graphics::pixelcolor::Rgb888;
 use embedded_graphics::prelude::DrawTarget;
 use embedded_graphics::primitives::Rectangle;
-use kernel_api_types::graphics::DisplayInfo;
+use kernel_api_types::graphics::{DisplayInfo, KERNEL_DISPLAY_EVENTS_SERVICE};
+use crate::memory::MEMORY;
+use core::num::NonZero;
 use limine::response::FramebufferResponse;
+use x86_64::structures::paging::{Mapper, PageSize, PageTableFlags, Size4KiB};
 
+/// Queue depth of each `KERNEL_DISPLAY_EVENTS_SERVICE` subscriber. Only the
+/// latest change matters, so a few are plenty.
+const DISPLAY_EVENTS_CAPACITY: usize = 4;
 
+/// Broadcast endpoint registered as `KERNEL_DISPLAY_EVENTS_SERVICE`.
+static DISPLAY_EVENTS: spin::Once<u64> = spin::Once::new();
 
 /// TaskId (as u64) of the current display owner. u64::MAX = no owner.
 pub static DISPLAY_OWNER: AtomicU64 = AtomicU64::new(u64::MAX);
 
+/// TaskId (as u64) the display goes back to when its owner exits without
+/// handing it on: the init task. u64::MAX = none.
+pub static DISPLAY_HOME: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Called as task `task_id` ends. If it owned the display, ownership goes back
+/// to `DISPLAY_HOME` while that task is alive, and otherwise to no one, so
+/// `DISPLAY_OWNER` never names a dead task.
+pub fn release_display(task_id: u64) {
+    let home = DISPLAY_HOME.load(Ordering::Relaxed);
+    let next = if home != task_id && crate::task::lookup(home).is_some() { home } else { u64::MAX };
+    if DISPLAY_OWNER.compare_exchange(task_id, next, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+        match next {
+            u64::MAX => log::info!("display: owner {} exited, display has no owner", task_id),
+            _ => log::info!("display: owner {} exited, display back to {}", task_id, next),
+        }
+    }
+}
+
 pub fn is_display_owner() -> bool {
     let cpu = crate::memory::cpu_local_data::get_local();
     let rq = cpu.run_queue.get().unwrap().lock();
@@ -108,6 +134,35 @@ impl Display {
         }
     }
 
+    /// Turn drawing into the kernel's back buffer on or off (see
+    /// `init_back_buffer`). While it is on, nothing drawn reaches the screen
+    /// until `present`. Returns whether drawing is now buffered.
+    pub fn set_buffered(&self, on: bool) -> bool {
+        let mut inner = self.inner.lock();
+        let fb = inner.fb.as_mut().expect("Display not initialized");
+        fb.set_buffered(on)
+    }
+
+    /// Copy the back buffer to the screen in one go, if drawing is buffered.
+    pub fn present(&self) {
+        let mut inner = self.inner.lock();
+        let fb = inner.fb.as_mut().expect("Display not initialized");
+        fb.present();
+    }
+
+    /// Release the display lock, whoever holds it. A CPU stopped by a panic
+    /// NMI while drawing, or the panicking CPU itself, may have left it
+    /// locked for good.
+    ///
+    /// # Safety
+    /// Only for the panic path, once every other CPU is stopped: nothing else
+    /// may be using the display.
+    pub unsafe fn force_unlock(&self) {
+        if self.inner.is_locked() {
+            unsafe { self.inner.force_unlock() };
+        }
+    }
+
     /// Get the framebuffer's physical address and total size in bytes.
     /// Used by syscall to map the framebuffer into user space.
     pub fn get_fb_phys_and_size(&self) -> (x86_64::PhysAddr, u64) {
@@ -148,3 +203,64 @@ pub fn init(framebuffer: &'static FramebufferResponse) {
     let info = (&frame_buffer).into();
     inner.fb = Some(unsafe { FrameBufferEmbeddedGraphics::new(addr, info) });
 }
+
+/// Allocate a framebuffer-sized back buffer, so a full-screen drawing like
+/// the panic screen can be composed off screen and shown at once with
+/// `Display::present`. Needs the memory system. Without it (if this fails)
+/// drawing simply stays unbuffered.
+pub fn init_back_buffer() {
+    let len = {
+        let inner = DISPLAY.inner.lock();
+        let fb = inner.fb.as_ref().expect("Display not initialized");
+        (fb.info.pitch * fb.info.height) as usize / size_of::<u32>()
+    };
+    match alloc_kernel_buffer(len) {
+        Some(back_buffer) => DISPLAY.inner.lock().fb.as_mut().unwrap().set_back_buffer(back_buffer),
+        None => log::warn!("No memory for a display back buffer; the panic screen is drawn unbuffered"),
+    }
+}
+
+/// Map `len` `u32`s of fresh, uninitialised kernel memory. The kernel heap is
+/// far too small for a framebuffer, so this maps frames directly.
+fn alloc_kernel_buffer(len: usize) -> Option<&'static mut [u32]> {
+    let memory = MEMORY.get()?;
+    let mut physical_memory = memory.physical_memory.lock();
+    let mut virtual_memory = memory.virtual_memory.lock();
+
+    let n_pages = (len * size_of::<u32>()) as u64;
+    let n_pages = n_pages.div_ceil(Size4KiB::SIZE);
+    let start_page = virtual_memory.allocate_kernel_contiguous_pages(NonZero::new(n_pages)?)?;
+
+    let mut mapper = unsafe { virtual_memory.mapper() };
+    let mut frame_allocator = physical_memory.get_kernel_frame_allocator();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    for i in 0..n_pages {
+        let frame = frame_allocator.allocate_frame_4kib()?;
+        unsafe {
+            mapper.map_to(start_page + i, frame, flags, &mut frame_allocator).ok()?.flush();
+        }
+    }
+    Some(unsafe { core::slice::from_raw_parts_mut(start_page.start_address().as_mut_ptr(), len) })
+}
+
+/// Create and register the display-change broadcast, once; later calls return
+/// the same endpoint. Needs the heap, so it can't happen in `init`.
+pub fn init_events() -> u64 {
+    *DISPLAY_EVENTS.call_once(|| {
+        let ep = crate::ipc::create_broadcast(DISPLAY_EVENTS_CAPACITY);
+        crate::service_registry::register_kernel(KERNEL_DISPLAY_EVENTS_SERVICE, ep)
+            .expect("display events service registered twice");
+        ep
+    })
+}
+
+/// Tell `KERNEL_DISPLAY_EVENTS_SERVICE` subscribers the framebuffer geometry
+/// changed. Limine sets the mode once at boot, so for now only tests call
+/// this; a mode-setting driver would call it after switching.
+pub fn notify_display_changed() {
+    let info = DISPLAY.get_display_info();
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&info as *const DisplayInfo as *const u8, size_of::<DisplayInfo>())
+    };
+    let _ = crate::ipc::try_send(init_events(), bytes);
+}
@@ -10,6 +10,9 @@ use embedded_graphics::primitives::Rectangle;
 
 pub struct FrameBufferEmbeddedGraphics<'a> {
     buffer: &'a mut [u32],
+    /// Off-screen copy of `buffer`, drawn into instead while `buffered` is set.
+    back_buffer: Option<&'a mut [u32]>,
+    buffered: bool,
     pub info: FrameBufferInfo,
     pub pixel_pitch: usize,
     pub bounding_box: Rectangle,
@@ -35,6 +38,8 @@ impl FrameBufferEmbeddedGraphics<'_> {
                     // Safety: This memory is mapped
                     unsafe { ptr.as_mut() }
                 },
+                back_buffer: None,
+                buffered: false,
                 info,
                 pixel_pitch: info.pitch as usize / size_of::<u32>(),
                 bounding_box: Rectangle {
@@ -50,9 +55,48 @@ impl FrameBufferEmbeddedGraphics<'_> {
         }
     }
 
+    /// Give the framebuffer a back buffer for `set_buffered`. It must be as
+    /// long as the framebuffer itself.
+    pub fn set_back_buffer(&mut self, back_buffer: &'a mut [u32]) {
+        assert_eq!(back_buffer.len(), self.buffer.len());
+        self.back_buffer = Some(back_buffer);
+    }
+
+    /// Turn buffering on or off. While it is on, drawing goes to the back
+    /// buffer and only reaches the screen on `present`. Turning it on seeds
+    /// the back buffer with what is on screen. Returns whether drawing is now
+    /// buffered, which it can't be without a back buffer.
+    pub fn set_buffered(&mut self, on: bool) -> bool {
+        match self.back_buffer.as_deref_mut() {
+            Some(back) if on => {
+                if !self.buffered {
+                    back.copy_from_slice(self.buffer);
+                }
+                self.buffered = true;
+            }
+            _ => self.buffered = false,
+        }
+        self.buffered
+    }
+
+    /// Copy the back buffer to the screen, if drawing is buffered.
+    pub fn present(&mut self) {
+        if let Some(back) = self.back_buffer.as_deref().filter(|_| self.buffered) {
+            self.buffer.copy_from_slice(back);
+        }
+    }
+
+    /// The buffer drawing goes to.
+    fn target(&mut self) -> &mut [u32] {
+        match self.back_buffer.as_deref_mut() {
+            Some(back) if self.buffered => back,
+            _ => &mut *self.buffer,
+        }
+    }
+
     /// Replace whole buffer
     pub fn put_buffer(&mut self, frame: u32) {
-        self.buffer.fill(frame);
+        self.target().fill(frame);
     }
 
     pub fn put_pixel(&mut self, x: usize, y: usize, color: Rgb888) {
@@ -61,7 +105,8 @@ impl FrameBufferEmbeddedGraphics<'_> {
         }
 
         let idx = y * self.pixel_pitch + x;
-        self.buffer[idx] = self.info.pixel.build(color);
+        let pixel = self.info.pixel.build(color);
+        self.target()[idx] = pixel;
     }
 
     pub fn fill_rect(&mut self, area: Rectangle, color: Rgb888) {
@@ -70,15 +115,18 @@ impl FrameBufferEmbeddedGraphics<'_> {
 
         let width = area.size.width as usize;
         let x0 = area.top_left.x as usize;
+        let pitch = self.pixel_pitch;
+        let target = self.target();
 
         for y in area.top_left.y as usize..area.top_left.y as usize + area.size.height as usize {
-            let idx = y * self.pixel_pitch + x0;
-            self.buffer[idx..idx + width].fill(pixel);
+            let idx = y * pitch + x0;
+            target[idx..idx + width].fill(pixel);
         }
     }
 
     pub fn shift_up(&mut self, amount: usize) {
-        self.buffer.copy_within(amount * self.pixel_pitch.., 0);
+        let pitch = self.pixel_pitch;
+        self.target().copy_within(amount * pitch.., 0);
     }
 
     /// Copy a dirty rectangle from a user-space pixel buffer into the framebuffer.
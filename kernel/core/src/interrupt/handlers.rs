@@ -1,12 +1,17 @@
 use crate::{hlt_loop};
 use crate::memory::cpu_local_data::{get_local, local_apic_id_of, try_get_local, CURRENT_CONTEXT_PTR_OFFSET, IN_SYSCALL_HANDLER_OFFSET};
 use crate::memory::guarded_stack::STACK_GUARD_PAGES;
+use crate::symbols::Addr;
 use crate::task::task::{
-    CpuContext, CTX_RAX, CTX_RBP, CTX_RBX, CTX_RCX, CTX_RDI, CTX_RDX, CTX_RSI,
+    CpuContext, Task, CTX_RAX, CTX_RBP, CTX_RBX, CTX_RCX, CTX_RDI, CTX_RDX, CTX_RSI,
     CTX_R8, CTX_R9, CTX_R10, CTX_R11, CTX_R12, CTX_R13, CTX_R14, CTX_R15,
     CTX_RIP, CTX_CS, CTX_RFLAGS, CTX_RSP, CTX_SS,
 };
-use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use kernel_api_types::EXIT_STACK_OVERFLOW;
+use x86_64::PrivilegeLevel;
+use x86_64::instructions::segmentation::GS;
 use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
 
@@ -21,11 +26,37 @@ pub extern "x86-interrupt" fn page_fault_handler(
     error_code: PageFaultErrorCode,
 ) {
     let accessed_address = Cr2::read_raw();
-    log::error!(
-        "Page fault at {:#x}, error: {error_code:#?}, ip: {:#x}",
-        accessed_address,
-        stack_frame.instruction_pointer.as_u64()
-    );
+    // A fault from ring 3 arrives with the user's GS base; swap in the
+    // per-CPU data for the handler, and back if we return to the task.
+    let from_user = stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3;
+    if from_user {
+        unsafe { GS::swap() };
+    }
+    // First touch of an `MMAP_LAZY` page: map it and let the access retry.
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && crate::memory::demand_paging::handle_fault(accessed_address, from_user)
+    {
+        if from_user {
+            unsafe { GS::swap() };
+        }
+        return;
+    }
+    // A user task ran off the bottom of its stack: kill just that task.
+    if from_user && hit_user_stack_guard(accessed_address) {
+        crate::syscall_handlers::sys_exit(EXIT_STACK_OVERFLOW);
+    }
+    match current_task() {
+        Some(task) => log::error!(
+            "Page fault in task {task} at {:#x}, error: {error_code:#?}, ip: {}",
+            accessed_address,
+            Addr(stack_frame.instruction_pointer.as_u64())
+        ),
+        None => log::error!(
+            "Page fault at {:#x}, error: {error_code:#?}, ip: {}",
+            accessed_address,
+            Addr(stack_frame.instruction_pointer.as_u64())
+        ),
+    }
     let accessed_address = x86_64::VirtAddr::new(accessed_address);
     if let Some(stack) = STACK_GUARD_PAGES
         .lock()
@@ -46,6 +77,31 @@ pub extern "x86-interrupt" fn page_fault_handler(
     }
 }
 
+/// The task running on this CPU, if that can be found without blocking.
+fn current_task() -> Option<Arc<Task>> {
+    try_get_local()?
+        .run_queue
+        .get()
+        .and_then(|rq| rq.try_lock())
+        .and_then(|rq| rq.current_task.clone())
+}
+
+/// Whether `addr` is in the current user task's stack guard page. Logs the
+/// overflow if so.
+fn hit_user_stack_guard(addr: u64) -> bool {
+    let Some(task) = current_task() else {
+        return false;
+    };
+    let Some(inner) = task.inner.try_lock() else {
+        return false;
+    };
+    if !inner.overlaps_user_stack_guard(addr, addr + 1) {
+        return false;
+    }
+    log::warn!("User stack overflow in task {task} at {addr:#x}; killing it");
+    true
+}
+
 pub extern "x86-interrupt" fn general_protection_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
@@ -62,12 +118,15 @@ pub extern "x86-interrupt" fn general_protection_fault_handler(
             let iretq_rsp = *ptr.add(3);
             let iretq_ss = *ptr.add(4);
             log::error!(
-                "IRETQ frame at RSP {:#x}: rip={:#x} cs={:#x} rflags={:#x} rsp={:#x} ss={:#x}",
-                rsp, iretq_rip, iretq_cs, iretq_rflags, iretq_rsp, iretq_ss
+                "IRETQ frame at RSP {:#x}: rip={} cs={:#x} rflags={:#x} rsp={:#x} ss={:#x}",
+                rsp, Addr(iretq_rip), iretq_cs, iretq_rflags, iretq_rsp, iretq_ss
             );
         }
     }
-    panic!("General Protection Fault! Stack frame: {stack_frame:#?}. Error code: {error_code}.")
+    panic!(
+        "General Protection Fault at {}! Stack frame: {stack_frame:#?}. Error code: {error_code}.",
+        Addr(stack_frame.instruction_pointer.as_u64())
+    )
 }
 
 pub extern "x86-interrupt" fn double_fault_handler(
@@ -81,8 +140,19 @@ pub extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFram
     log::info!("Breakpoint! Stack frame: {stack_frame:#?}");
 }
 
-pub extern "x86-interrupt" fn nmi_handler(_stack_frame: InterruptStackFrame) {
-    handle_panic_from_other_cpu()
+pub extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    // A watchdog NMI can land in ring 3 if the CPU recovered on the way;
+    // swap in the per-CPU data as the page fault handler does.
+    let from_user = stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3;
+    if from_user {
+        unsafe { GS::swap() };
+    }
+    if !crate::interrupt::watchdog::on_nmi(&stack_frame) {
+        stop_for_panic()
+    }
+    if from_user {
+        unsafe { GS::swap() };
+    }
 }
 
 /// Timer interrupt handler that saves/restores context from CpuContext structs.
@@ -586,15 +656,132 @@ pub extern "C" fn mouse_interrupt_handler() {
     )
 }
 
+extern "C" fn msi_interrupt_inner() {
+    crate::drivers::msi::on_msi_interrupt();
+    let cpu = get_local();
+    unsafe {
+        let local_apic = &mut *cpu.local_apic.get().unwrap().get();
+        local_apic.end_of_interrupt();
+    }
+}
+
+#[unsafe(naked)]
+pub extern "C" fn msi_interrupt_handler() {
+    core::arch::naked_asm!(
+        "push r11",
+        "mov r11, [rsp + 16]",
+        "test r11, 3",
+        "jz 4f",
+        "swapgs",
+        "4:",
+        "pop r11",
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "call {inner}",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "mov rax, [rsp + 16]",
+        "and rax, 3",
+        "cmp rax, 3",
+        "jne 2f",
+        "mov rax, [rsp + 40]",
+        "or  rax, 3",
+        "mov [rsp + 40], rax",
+        "2:",
+        "mov rax, [rsp + 16]",
+        "test rax, 3",
+        "jz 5f",
+        "swapgs",
+        "5:",
+        "pop rax",
+        "iretq",
+        inner = sym msi_interrupt_inner,
+    )
+}
+
+extern "C" fn serial_interrupt_inner() {
+    crate::drivers::serial::on_serial_interrupt();
+    let cpu = get_local();
+    unsafe {
+        let local_apic = &mut *cpu.local_apic.get().unwrap().get();
+        local_apic.end_of_interrupt();
+    }
+}
+
+#[unsafe(naked)]
+pub extern "C" fn serial_interrupt_handler() {
+    core::arch::naked_asm!(
+        "push r11",
+        "mov r11, [rsp + 16]",
+        "test r11, 3",
+        "jz 4f",
+        "swapgs",
+        "4:",
+        "pop r11",
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "call {inner}",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "mov rax, [rsp + 16]",
+        "and rax, 3",
+        "cmp rax, 3",
+        "jne 2f",
+        "mov rax, [rsp + 40]",
+        "or  rax, 3",
+        "mov [rsp + 40], rax",
+        "2:",
+        "mov rax, [rsp + 16]",
+        "test rax, 3",
+        "jz 5f",
+        "swapgs",
+        "5:",
+        "pop rax",
+        "iretq",
+        inner = sym serial_interrupt_inner,
+    )
+}
+
 extern "C" fn reschedule_eoi() {
     let cpu = get_local();
     unsafe {
         let local_apic = &mut *cpu.local_apic.get().unwrap().get();
         local_apic.end_of_interrupt();
     }
+    // A TLB shootdown is signalled with this IPI too.
+    crate::memory::tlb::catch_up();
+    crate::task::local_scheduler::request_reschedule();
 }
 
-/// Reschedule IPI handler: send EOI then return with swapgs and SS RPL fix.
+/// Reschedule IPI handler: send EOI, catch up on any TLB shootdown, and have
+/// the timer interrupt run the scheduler as soon as this returns (see
+/// `request_reschedule`), then return with swapgs and SS RPL fix.
 ///
 /// Same swapgs + KVM SS-stripping workarounds as `keyboard_interrupt_handler`.
 #[unsafe(naked)]
@@ -651,34 +838,72 @@ pub extern "C" fn reschedule_ipi_handler() {
 }
 
 // -- NMI ---
-pub fn handle_panic_from_other_cpu() -> ! {
-    if let Some(local) = try_get_local()
-        && let Some(nmi_handler_states) = NMI_HANDLER_STATES.get()
+/// CPUs that have stopped on a panic NMI, counted so the panicking CPU can
+/// wait for them before drawing the crash dump.
+pub static CPUS_STOPPED_FOR_PANIC: AtomicU32 = AtomicU32::new(0);
+
+/// How many times `stop_other_cpus_for_panic` polls before drawing anyway. A
+/// CPU that doesn't answer is usually already stuck inside an NMI handler,
+/// where a second NMI can't reach it.
+const PANIC_STOP_POLLS: u64 = 100_000_000;
+
+/// Mark every other CPU as panicked and NMI those that can take it. Returns
+/// how many NMIs were sent. A CPU whose handler isn't set up yet isn't sent
+/// one: it halts in `idt::init` when it finds the state.
+fn send_panic_nmis() -> u32 {
+    let (Some(local), Some(nmi_handler_states)) = (try_get_local(), NMI_HANDLER_STATES.get()) else {
+        return 0;
+    };
+    let local_apic = unsafe {
+        &mut *local
+            .local_apic
+            .get()
+            .expect("local APIC not initialized")
+            .get()
+    };
+
+    let mut sent = 0;
+    for (cpu_id, nmi_handler_state) in nmi_handler_states
+        .iter()
+        .enumerate()
+        .filter(|(cpu_id, _)| *cpu_id as u32 != local.kernel_id)
     {
-        let local_apic = unsafe {
-            &mut *local
-                .local_apic
-                .get()
-                .expect("local APIC not initialized")
-                .get()
-        };
-
-        for (cpu_id, nmi_handler_state) in nmi_handler_states
-            .iter()
-            .enumerate()
-            .filter(|(cpu_id, _)| *cpu_id as u32 != local.kernel_id)
-        {
-            if nmi_handler_state.swap(
-                NmiHandlerState::KernelPanicked,
-                Ordering::Release,
-            ) == NmiHandlerState::NmiHandlerSet
-            {
+        // A CPU already in `WatchdogDump` has an NMI on its way, which now
+        // stops it instead.
+        match nmi_handler_state.swap(NmiHandlerState::KernelPanicked, Ordering::Release) {
+            NmiHandlerState::NmiHandlerSet => {
                 unsafe {
                     local_apic.send_nmi(local_apic_id_of(cpu_id as u32));
                 }
+                sent += 1;
             }
+            NmiHandlerState::WatchdogDump => sent += 1,
+            _ => {}
         }
     }
+    sent
+}
 
+/// Stop every other CPU before the crash dump is drawn, so nothing (a
+/// compositor presenting from another CPU, say) writes VRAM after it. Waits,
+/// up to `PANIC_STOP_POLLS` polls, for each CPU sent an NMI to halt.
+pub fn stop_other_cpus_for_panic() {
+    let sent = send_panic_nmis();
+    for _ in 0..PANIC_STOP_POLLS {
+        if CPUS_STOPPED_FOR_PANIC.load(Ordering::Acquire) >= sent {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+pub fn handle_panic_from_other_cpu() -> ! {
+    send_panic_nmis();
     hlt_loop()
 }
+
+/// Halt this CPU for good on another CPU's panic NMI, after telling it so.
+fn stop_for_panic() -> ! {
+    CPUS_STOPPED_FOR_PANIC.fetch_add(1, Ordering::Release);
+    handle_panic_from_other_cpu()
+}
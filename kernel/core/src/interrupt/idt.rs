@@ -2,7 +2,7 @@ use core::sync::atomic::Ordering;
 use x86_64::structures::idt::InterruptDescriptorTable;
 use x86_64::VirtAddr;
 use crate::gdt::IstStackIndexes;
-use crate::interrupt::handlers::{breakpoint_handler, double_fault_handler, general_protection_fault_handler, handle_panic_from_other_cpu, keyboard_interrupt_handler, mouse_interrupt_handler, nmi_handler, page_fault_handler, reschedule_ipi_handler, timer_interrupt_handler};
+use crate::interrupt::handlers::{breakpoint_handler, double_fault_handler, general_protection_fault_handler, handle_panic_from_other_cpu, keyboard_interrupt_handler, mouse_interrupt_handler, msi_interrupt_handler, nmi_handler, page_fault_handler, reschedule_ipi_handler, serial_interrupt_handler, timer_interrupt_handler};
 use crate::interrupt::InterruptVector;
 use crate::interrupt::nmi_handler_state::{NmiHandlerState, NMI_HANDLER_STATES};
 use crate::memory::cpu_local_data::get_local;
@@ -41,6 +41,10 @@ pub fn init() {
                 .set_handler_addr(VirtAddr::new(reschedule_ipi_handler as u64));
             idt[u8::from(InterruptVector::Mouse)]
                 .set_handler_addr(VirtAddr::new(mouse_interrupt_handler as u64));
+            idt[u8::from(InterruptVector::Msi)]
+                .set_handler_addr(VirtAddr::new(msi_interrupt_handler as u64));
+            idt[u8::from(InterruptVector::Serial)]
+                .set_handler_addr(VirtAddr::new(serial_interrupt_handler as u64));
         }
         idt
     });
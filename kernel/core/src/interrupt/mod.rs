@@ -3,6 +3,7 @@ use num_enum::IntoPrimitive;
 pub mod idt;
 pub mod nmi_handler_state;
 pub mod handlers;
+pub mod watchdog;
 
 #[derive(Debug, IntoPrimitive)]
 #[repr(u8)]
@@ -13,4 +14,6 @@ pub enum InterruptVector {
     Keyboard = 0x23,
     Reschedule = 0x24,
     Mouse = 0x25,
-}
\ No newline at end of file
+    Msi = 0x26,
+    Serial = 0x27,
+}
@@ -4,10 +4,12 @@ use atomic_enum::atomic_enum;
 use spin::once::Once;
 
 #[atomic_enum]
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum NmiHandlerState {
     NmiHandlerNotSet,
     NmiHandlerSet,
+    /// The watchdog sent this CPU an NMI to dump where it is stuck.
+    WatchdogDump,
     KernelPanicked,
 }
 
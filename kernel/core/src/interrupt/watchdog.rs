@@ -0,0 +1,138 @@
+//! NMI watchdog for CPUs that stop taking their timer tick.
+//!
+//! Every CPU bumps its `timer_interrupts` counter in `time::on_timer_tick`;
+//! that counter is the heartbeat. The BSP checks the other CPUs' heartbeats
+//! on each of its own ticks. A CPU that is busy (not in its idle wait, where
+//! the tick is stretched on purpose) and whose heartbeat has not moved for
+//! `STALL_MS` has interrupts off somewhere it shouldn't: usually spinning on a
+//! lock during a scheduler handoff. The BSP sends it an NMI, and the NMI
+//! handler logs where that CPU was stuck.
+//!
+//! The request travels in the CPU's `NmiHandlerState`: the BSP moves it from
+//! `NmiHandlerSet` to `WatchdogDump` before sending the NMI, and the handler
+//! moves it back after the dump. While it is `WatchdogDump` no second
+//! watchdog NMI is sent. A panic in between swaps in `KernelPanicked` without
+//! sending its own NMI (one is already on its way), and the handler, finding
+//! its state changed, halts as for a panic.
+
+use crate::interrupt::nmi_handler_state::{NmiHandlerState, NMI_HANDLER_STATES};
+use crate::memory::cpu_local_data::{cpus_count, get_local, local_apic_id_of, try_get_ready_cpu, CpuLocalData};
+use crate::symbols::Addr;
+use crate::time;
+use alloc::boxed::Box;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Once;
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// How long a busy CPU may go without a timer tick before it is dumped: a
+/// thousand 1 ms ticks. A CPU still stuck is dumped again after as long.
+pub const STALL_MS: u64 = 1000;
+
+/// What the BSP last saw of one CPU's heartbeat.
+#[derive(Default)]
+struct Heartbeat {
+    seen: AtomicU64,
+    /// `now_ms` when `seen` last changed, or 0 before the first check.
+    since_ms: AtomicU64,
+}
+
+static HEARTBEATS: Once<Box<[Heartbeat]>> = Once::new();
+
+/// Dumps sent since boot.
+pub static WATCHDOG_NMIS: AtomicU64 = AtomicU64::new(0);
+
+/// Look for stalled CPUs and send each one an NMI. Called from the BSP's
+/// timer tick with interrupts disabled.
+pub fn check() {
+    let Some(states) = NMI_HANDLER_STATES.get() else { return };
+    let heartbeats = HEARTBEATS.call_once(|| (0..cpus_count()).map(|_| Heartbeat::default()).collect());
+    let me = get_local();
+    let now = time::now_ms();
+    for (id, heartbeat) in heartbeats.iter().enumerate() {
+        let id = id as u32;
+        if id == me.kernel_id {
+            continue;
+        }
+        let Some(cpu) = try_get_ready_cpu(id) else { continue };
+        let beat = cpu.timer_interrupts.load(Ordering::Relaxed);
+        let since = heartbeat.since_ms.load(Ordering::Relaxed);
+        // An idle CPU stretches its tick, so a silent one isn't stuck.
+        let idle = cpu.idle_since_ns.load(Ordering::Relaxed) != 0;
+        if since == 0 || idle || beat != heartbeat.seen.load(Ordering::Relaxed) {
+            heartbeat.seen.store(beat, Ordering::Relaxed);
+            heartbeat.since_ms.store(now, Ordering::Relaxed);
+            continue;
+        }
+        if now.saturating_sub(since) < STALL_MS {
+            continue;
+        }
+        heartbeat.since_ms.store(now, Ordering::Relaxed);
+        if states[id as usize]
+            .compare_exchange(
+                NmiHandlerState::NmiHandlerSet,
+                NmiHandlerState::WatchdogDump,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            WATCHDOG_NMIS.fetch_add(1, Ordering::Relaxed);
+            send_nmi(me, id);
+        }
+    }
+}
+
+fn send_nmi(from: &CpuLocalData, to: u32) {
+    let local_apic = unsafe { &mut *from.local_apic.get().expect("local APIC not initialized").get() };
+    unsafe { local_apic.send_nmi(local_apic_id_of(to)) };
+}
+
+/// Handle an NMI if the watchdog sent it: log where this CPU was and return
+/// `true`. Returns `false` for any other NMI, which means a panic.
+///
+/// The caller must have the kernel GS base loaded.
+pub fn on_nmi(stack_frame: &InterruptStackFrame) -> bool {
+    let Some(states) = NMI_HANDLER_STATES.get() else { return false };
+    let cpu = get_local();
+    let state = &states[cpu.kernel_id as usize];
+    if state.load(Ordering::Acquire) != NmiHandlerState::WatchdogDump {
+        return false;
+    }
+    // The run queue lock may be exactly what this CPU is stuck holding.
+    let task = match cpu.run_queue.get().and_then(|rq| rq.try_lock()) {
+        Some(rq) => rq.current_task.as_ref().map_or(CurrentTask::None, |t| CurrentTask::Id(t.id.to_u64())),
+        None => CurrentTask::Unknown,
+    };
+    let _ = crate::logger::log_from_nmi(format_args!(
+        "watchdog: no timer tick for {STALL_MS} ms, rip={} rsp={:#x} task={task}",
+        Addr(stack_frame.instruction_pointer.as_u64()),
+        stack_frame.stack_pointer.as_u64(),
+    ));
+    state
+        .compare_exchange(
+            NmiHandlerState::WatchdogDump,
+            NmiHandlerState::NmiHandlerSet,
+            Ordering::Release,
+            Ordering::Relaxed,
+        )
+        .is_ok()
+}
+
+/// The task a stalled CPU was running, as far as the NMI handler can tell.
+enum CurrentTask {
+    Id(u64),
+    None,
+    /// The run queue was locked.
+    Unknown,
+}
+
+impl fmt::Display for CurrentTask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CurrentTask::Id(id) => write!(f, "{id}"),
+            CurrentTask::None => f.write_str("none"),
+            CurrentTask::Unknown => f.write_str("? (run queue locked)"),
+        }
+    }
+}
@@ -13,6 +13,8 @@ struct IoApicInfo {
     base: *mut u32,
     /// GSI base for this IOAPIC.
     gsi_base: u32,
+    /// Index of the last redirection entry.
+    max_entry: u8,
 }
 
 // Safety: IOAPIC MMIO is accessed only with proper synchronization via the
@@ -140,6 +142,7 @@ pub fn init(acpi_tables: &AcpiTables<impl acpi::Handler>) {
         let info = IoApicInfo {
             base: virt_addr,
             gsi_base: io_apic.global_system_interrupt_base,
+            max_entry: max_redirection_entries(virt_addr),
         };
 
         // Mask all pins initially
@@ -174,103 +177,113 @@ fn disable_legacy_pic() {
     log::info!("Legacy 8259 PIC disabled");
 }
 
-/// Route ISA IRQ1 (keyboard) to the specified APIC vector on the given destination APIC.
-///
-/// Handles ACPI interrupt source overrides (ISA IRQ1 may be remapped to a different GSI).
-pub fn enable_keyboard_irq(vector: u8, dest_apic_id: u32) {
-    let state = IOAPIC.get().expect("IOAPIC not initialized");
-
-    // Check for interrupt source override for ISA IRQ 1
-    let (gsi, polarity, trigger_mode) = state
-        .interrupt_source_overrides
-        .iter()
-        .find(|iso| iso.isa_source == 1)
-        .map(|iso| (iso.global_system_interrupt, iso.polarity, iso.trigger_mode))
-        .unwrap_or((1, Polarity::SameAsBus, TriggerMode::SameAsBus));
-
-    // Calculate the IOAPIC pin from the GSI
-    let pin = (gsi - state.info.gsi_base) as u8;
-
-    // Build the redirection table entry
-    let mut entry_low: u32 = vector as u32; // bits 0-7: vector
-
-    // Delivery mode: fixed (000)
-    // Destination mode: physical (bit 11 = 0)
-
-    // Polarity (bit 13)
-    match polarity {
-        Polarity::ActiveLow => entry_low |= 1 << 13,
-        _ => {}
-    }
-
-    // Trigger mode (bit 15)
-    match trigger_mode {
-        TriggerMode::Level => entry_low |= 1 << 15,
-        _ => {}
-    }
-
-    // Unmask (bit 16 = 0, already clear)
-
-    let entry_high: u32 = (dest_apic_id & 0xFF) << 24; // bits 56-63: destination
-
-    let reg_low = IOREDTBL_BASE + pin * 2;
-    let reg_high = reg_low + 1;
+/// ISA IRQ of the PS/2 keyboard.
+pub const ISA_IRQ_KEYBOARD: u8 = 1;
+/// ISA IRQ of COM1.
+pub const ISA_IRQ_COM1: u8 = 4;
+/// ISA IRQ of the PS/2 mouse.
+pub const ISA_IRQ_MOUSE: u8 = 12;
+
+/// Electrical characteristics of an IOAPIC input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqFlags {
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
 
-    write_register(state.info.base, reg_high, entry_high);
-    write_register(state.info.base, reg_low, entry_low);
+impl IrqFlags {
+    /// ISA bus default: edge-triggered, active high.
+    pub const ISA: Self = Self { active_low: false, level_triggered: false };
+    /// PCI INTx default: level-triggered, active low.
+    pub const PCI: Self = Self { active_low: true, level_triggered: true };
+}
 
-    log::info!(
-        "IOAPIC: Keyboard IRQ1 -> GSI {} -> pin {} -> vector {:#x}, dest APIC {}",
-        gsi,
-        pin,
-        vector,
-        dest_apic_id,
-    );
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoApicError {
+    /// The GSI is not an input of the IOAPIC.
+    GsiOutOfRange,
 }
 
-/// Route ISA IRQ12 (PS/2 mouse) to the specified APIC vector on the given destination APIC.
-///
-/// Handles ACPI interrupt source overrides (ISA IRQ12 may be remapped to a different GSI).
-pub fn enable_mouse_irq(vector: u8, dest_apic_id: u32) {
+/// The GSI and flags that ISA `irq` arrives on, after applying the ACPI
+/// interrupt source overrides (e.g. the PIT's IRQ0 is usually wired to GSI 2).
+pub fn isa_irq_to_gsi(irq: u8) -> (u32, IrqFlags) {
     let state = IOAPIC.get().expect("IOAPIC not initialized");
-
-    // Check for interrupt source override for ISA IRQ 12
-    let (gsi, polarity, trigger_mode) = state
+    let Some(iso) = state
         .interrupt_source_overrides
         .iter()
-        .find(|iso| iso.isa_source == 12)
-        .map(|iso| (iso.global_system_interrupt, iso.polarity, iso.trigger_mode))
-        .unwrap_or((12, Polarity::SameAsBus, TriggerMode::SameAsBus));
+        .find(|iso| iso.isa_source == irq)
+    else {
+        return (irq as u32, IrqFlags::ISA);
+    };
+    let flags = IrqFlags {
+        active_low: match iso.polarity {
+            Polarity::ActiveLow => true,
+            Polarity::ActiveHigh => false,
+            Polarity::SameAsBus => IrqFlags::ISA.active_low,
+        },
+        level_triggered: match iso.trigger_mode {
+            TriggerMode::Level => true,
+            TriggerMode::Edge => false,
+            TriggerMode::SameAsBus => IrqFlags::ISA.level_triggered,
+        },
+    };
+    (iso.global_system_interrupt, flags)
+}
+
+/// The IOAPIC pin of `gsi`.
+fn pin_of(state: &IoApicState, gsi: u32) -> Result<u8, IoApicError> {
+    gsi.checked_sub(state.info.gsi_base)
+        .filter(|&pin| pin <= state.info.max_entry as u32)
+        .map(|pin| pin as u8)
+        .ok_or(IoApicError::GsiOutOfRange)
+}
 
-    // Calculate the IOAPIC pin from the GSI
-    let pin = (gsi - state.info.gsi_base) as u8;
+/// Deliver `gsi` as `vector` to the local APIC `dest_apic_id` (fixed
+/// delivery, physical destination) and unmask it.
+pub fn route_irq(gsi: u32, vector: u8, dest_apic_id: u32, flags: IrqFlags) -> Result<(), IoApicError> {
+    let state = IOAPIC.get().expect("IOAPIC not initialized");
+    let pin = pin_of(state, gsi)?;
 
-    // Build the redirection table entry
+    // Bits 0-7: vector. Delivery mode fixed (000), destination mode physical
+    // (bit 11 = 0) and unmasked (bit 16 = 0).
     let mut entry_low: u32 = vector as u32;
-
-    match polarity {
-        Polarity::ActiveLow => entry_low |= 1 << 13,
-        _ => {}
+    if flags.active_low {
+        entry_low |= 1 << 13;
     }
-
-    match trigger_mode {
-        TriggerMode::Level => entry_low |= 1 << 15,
-        _ => {}
+    if flags.level_triggered {
+        entry_low |= 1 << 15;
     }
-
-    let entry_high: u32 = (dest_apic_id & 0xFF) << 24;
+    let entry_high: u32 = (dest_apic_id & 0xFF) << 24; // bits 56-63: destination
 
     let reg_low = IOREDTBL_BASE + pin * 2;
     let reg_high = reg_low + 1;
 
+    // Write the destination first so the entry is never live with a stale one.
     write_register(state.info.base, reg_high, entry_high);
     write_register(state.info.base, reg_low, entry_low);
 
     log::info!(
-        "IOAPIC: Mouse IRQ12 -> GSI {} -> pin {} -> vector {:#x}, dest APIC {}",
+        "IOAPIC: GSI {} -> pin {} -> vector {:#x}, dest APIC {}",
         gsi,
         pin,
         vector,
         dest_apic_id,
     );
+    Ok(())
+}
+
+/// Route ISA `irq` to `vector` on `dest_apic_id`, following any interrupt
+/// source override for it.
+pub fn route_isa_irq(irq: u8, vector: u8, dest_apic_id: u32) -> Result<(), IoApicError> {
+    let (gsi, flags) = isa_irq_to_gsi(irq);
+    route_irq(gsi, vector, dest_apic_id, flags)
+}
+
+/// Stop delivering `gsi`. The rest of its redirection entry is kept.
+pub fn mask_irq(gsi: u32) -> Result<(), IoApicError> {
+    let state = IOAPIC.get().expect("IOAPIC not initialized");
+    let reg_low = IOREDTBL_BASE + pin_of(state, gsi)? * 2;
+    let low = read_register(state.info.base, reg_low);
+    write_register(state.info.base, reg_low, low | (1 << 16));
+    Ok(())
 }
@@ -1,16 +1,29 @@
 use alloc::collections::BTreeMap;
 use alloc::collections::VecDeque;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
-use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use spin::Mutex;
-use crate::task::task::{Task, TaskState};
+use crate::task::task::{Privilege, Task, TaskState};
 
 type WaiterQueue = Mutex<VecDeque<(Arc<Task>, u32)>>;
 
-pub const MAX_MESSAGE_SIZE: usize = 4096;
+pub use kernel_api_types::MAX_MESSAGE_SIZE;
 pub const DEFAULT_CHANNEL_CAPACITY: usize = 16;
-pub const MAX_CHANNEL_CAPACITY: usize = 256;
+/// Deepest queue a channel can have, and what a system task may ask for.
+/// Every slot can hold a `MAX_MESSAGE_SIZE` message, so a full channel this
+/// deep pins 4 MiB of kernel heap.
+pub const MAX_CHANNEL_CAPACITY: usize = 1024;
+/// Deepest queue a user task may ask for: at most 256 KiB per channel.
+pub const MAX_USER_CHANNEL_CAPACITY: usize = 64;
+
+/// The largest capacity a task with `privilege` gets for a new channel.
+pub fn max_channel_capacity(privilege: Privilege) -> usize {
+    match privilege {
+        Privilege::System => MAX_CHANNEL_CAPACITY,
+        Privilege::User => MAX_USER_CHANNEL_CAPACITY,
+    }
+}
 
 static NEXT_ENDPOINT_ID: AtomicU64 = AtomicU64::new(1);
 pub static ENDPOINT_REGISTRY: Mutex<BTreeMap<u64, Endpoint>> = Mutex::new(BTreeMap::new());
@@ -19,6 +32,9 @@ pub static ENDPOINT_REGISTRY: Mutex<BTreeMap<u64, Endpoint>> = Mutex::new(BTreeM
 pub enum EndpointRole {
     Send,
     Recv,
+    /// Send side of a broadcast channel: each message is copied to every
+    /// subscriber's queue (see `subscribe`). Its channel holds no messages.
+    Broadcast,
 }
 
 pub struct Endpoint {
@@ -28,19 +44,106 @@ pub struct Endpoint {
 
 pub struct Channel {
     pub inner: Mutex<ChannelInner>,
-    pub send_closed: AtomicBool,
-    pub recv_closed: AtomicBool,
+    /// Open send endpoints (the original plus any dups). The send side is
+    /// closed once this drops to zero.
+    pub send_refs: AtomicUsize,
+    /// Open recv endpoints, as `send_refs`.
+    pub recv_refs: AtomicUsize,
     /// Tasks sleeping waiting to receive; woken (one at a time) when try_send succeeds.
     pub recv_waiters: WaiterQueue,
     /// Tasks sleeping waiting to send (channel full); woken (one at a time) when try_recv succeeds.
     pub send_waiters: WaiterQueue,
+    /// The last task to send on this channel, lent priority by tasks waiting to receive.
+    pub sender: Mutex<Weak<Task>>,
+    /// The last task to receive on this channel, lent priority by tasks
+    /// waiting to send and by the messages queued for it.
+    pub receiver: Mutex<Weak<Task>>,
+    /// For a broadcast channel, one channel per subscriber, each with a
+    /// queue of `capacity`. Empty otherwise.
+    pub subscribers: Mutex<Vec<Arc<Channel>>>,
 }
 
 pub struct ChannelInner {
-    pub queue: VecDeque<Vec<u8>>,
+    pub queue: VecDeque<Envelope>,
     pub capacity: usize,
 }
 
+impl Drop for ChannelInner {
+    /// Endpoints still in the queue have no one left to receive them.
+    fn drop(&mut self) {
+        for envelope in self.queue.drain(..) {
+            if let Some(endpoint) = envelope.endpoint {
+                let _ = close_endpoint(endpoint);
+            }
+        }
+    }
+}
+
+/// A queued message, with the endpoint moved along with it by
+/// `try_send_with_endpoint`, if any. Until it is received, that endpoint
+/// belongs to no task; it is closed if the message is dropped instead.
+pub struct Envelope {
+    pub message: Message,
+    pub endpoint: Option<u64>,
+}
+
+/// Longest message stored inline in a `Message`.
+pub const INLINE_MESSAGE_SIZE: usize = 32;
+
+/// A queued message. Short ones, like input events and window commands, are
+/// stored inline so sending them doesn't allocate; it derefs to its bytes
+/// either way.
+#[derive(Clone)]
+pub enum Message {
+    Inline { len: u8, bytes: [u8; INLINE_MESSAGE_SIZE] },
+    Heap(Vec<u8>),
+}
+
+impl Message {
+    pub fn new(data: &[u8]) -> Self {
+        if data.len() <= INLINE_MESSAGE_SIZE {
+            let mut bytes = [0; INLINE_MESSAGE_SIZE];
+            bytes[..data.len()].copy_from_slice(data);
+            Message::Inline { len: data.len() as u8, bytes }
+        } else {
+            Message::Heap(data.to_vec())
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            Message::Inline { len, bytes } => &bytes[..*len as usize],
+            Message::Heap(data) => data,
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        matches!(self, Message::Inline { .. })
+    }
+}
+
+impl core::ops::Deref for Message {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl PartialEq for Message {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for Message {}
+
+impl core::fmt::Debug for Message {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IpcError {
     InvalidEndpoint,
@@ -52,23 +155,30 @@ pub enum IpcError {
     InvalidArgs,
 }
 
-pub fn create_channel(capacity: usize) -> (u64, u64) {
+fn new_channel(capacity: usize) -> Arc<Channel> {
     let capacity = if capacity == 0 {
         DEFAULT_CHANNEL_CAPACITY
     } else {
         capacity.clamp(1, MAX_CHANNEL_CAPACITY)
     };
 
-    let channel = Arc::new(Channel {
+    Arc::new(Channel {
         inner: Mutex::new(ChannelInner {
             queue: VecDeque::new(),
             capacity,
         }),
-        send_closed: AtomicBool::new(false),
-        recv_closed: AtomicBool::new(false),
+        send_refs: AtomicUsize::new(1),
+        recv_refs: AtomicUsize::new(1),
         recv_waiters: Mutex::new(VecDeque::new()),
         send_waiters: Mutex::new(VecDeque::new()),
-    });
+        sender: Mutex::new(Weak::new()),
+        receiver: Mutex::new(Weak::new()),
+        subscribers: Mutex::new(Vec::new()),
+    })
+}
+
+pub fn create_channel(capacity: usize) -> (u64, u64) {
+    let channel = new_channel(capacity);
 
     let send_id = NEXT_ENDPOINT_ID.fetch_add(1, Ordering::Relaxed);
     let recv_id = NEXT_ENDPOINT_ID.fetch_add(1, Ordering::Relaxed);
@@ -89,33 +199,148 @@ pub fn create_channel(capacity: usize) -> (u64, u64) {
     (send_id, recv_id)
 }
 
-fn wake_waiter(waiters: &WaiterQueue) {
-    if let Some((task, cpu_id)) = waiters.lock().pop_front() {
-        task.state.store(TaskState::Ready, Ordering::Release);
-        crate::task::local_scheduler::add(crate::memory::cpu_local_data::get_cpu(cpu_id), task);
-        let local_kernel_id = crate::memory::cpu_local_data::get_local().kernel_id;
-        if cpu_id != local_kernel_id {
-            let apic_id = crate::memory::cpu_local_data::local_apic_id_of(cpu_id);
-            crate::apic::send_fixed_ipi(apic_id, u8::from(crate::interrupt::InterruptVector::Reschedule));
+/// Create a broadcast channel and return its `Broadcast` endpoint. Each
+/// subscriber gets its own queue of `capacity` messages.
+pub fn create_broadcast(capacity: usize) -> u64 {
+    let id = NEXT_ENDPOINT_ID.fetch_add(1, Ordering::Relaxed);
+    let endpoint = Endpoint {
+        role: EndpointRole::Broadcast,
+        channel: new_channel(capacity),
+    };
+    ENDPOINT_REGISTRY.lock().insert(id, endpoint);
+    id
+}
+
+/// Attach a new receive queue to the broadcast channel of `endpoint_id` and
+/// return its recv endpoint, which gets every message broadcast from now on.
+/// Its send side is the broadcast channel: it closes when the last
+/// `Broadcast` endpoint does.
+pub fn subscribe(endpoint_id: u64) -> Result<u64, IpcError> {
+    let mut registry = ENDPOINT_REGISTRY.lock();
+    let ep = registry.get(&endpoint_id).ok_or(IpcError::InvalidEndpoint)?;
+    if ep.role != EndpointRole::Broadcast {
+        return Err(IpcError::WrongDirection);
+    }
+    let hub = ep.channel.clone();
+    let subscriber = new_channel(hub.inner.lock().capacity);
+    hub.subscribers.lock().push(subscriber.clone());
+
+    let recv_id = NEXT_ENDPOINT_ID.fetch_add(1, Ordering::Relaxed);
+    registry.insert(recv_id, Endpoint { role: EndpointRole::Recv, channel: subscriber });
+    Ok(recv_id)
+}
+
+impl Channel {
+    pub fn send_closed(&self) -> bool {
+        self.send_refs.load(Ordering::Acquire) == 0
+    }
+
+    pub fn recv_closed(&self) -> bool {
+        self.recv_refs.load(Ordering::Acquire) == 0
+    }
+
+    /// Record that `task` sent a message. The receiver inherits the sender's
+    /// base priority until it has drained its queue, so a request from a
+    /// high-priority client is served at that priority. Only the base is
+    /// passed on, so a boosted server doesn't boost everyone it replies to.
+    pub fn sent_by(&self, task: &Arc<Task>) {
+        *self.sender.lock() = Arc::downgrade(task);
+        let receiver = self.receiver.lock().upgrade();
+        if let Some(receiver) = receiver {
+            receiver.inherit_priority(task.base_priority.load(Ordering::Relaxed));
         }
     }
+
+    /// Record that `task` received a message.
+    pub fn received_by(&self, task: &Arc<Task>) {
+        *self.receiver.lock() = Arc::downgrade(task);
+    }
+}
+
+/// Put `task` to sleep until the channel has room. The receiver inherits the
+/// waiter's priority, so a low-priority receiver can't hold up a
+/// high-priority sender behind medium-priority work.
+pub fn wait_to_send(channel: &Channel, task: Arc<Task>, cpu_id: u32) {
+    *channel.sender.lock() = Arc::downgrade(&task);
+    let receiver = channel.receiver.lock().upgrade();
+    if let Some(receiver) = receiver {
+        receiver.inherit_priority(task.priority());
+    }
+    task.set_state(TaskState::Sleeping);
+    channel.send_waiters.lock().push_back((task, cpu_id));
+}
+
+/// Put `task` to sleep until a message arrives. The last sender inherits the
+/// waiter's priority, as in `wait_to_send`.
+///
+/// A task waiting to receive has finished whatever it was lent priority
+/// for, so it drops back to its base priority here.
+pub fn wait_to_recv(channel: &Channel, task: Arc<Task>, cpu_id: u32) {
+    task.inherited_priority.store(0, Ordering::Relaxed);
+    *channel.receiver.lock() = Arc::downgrade(&task);
+    let sender = channel.sender.lock().upgrade();
+    if let Some(sender) = sender.filter(|s| !Arc::ptr_eq(s, &task)) {
+        sender.inherit_priority(task.priority());
+    }
+    task.set_state(TaskState::Sleeping);
+    channel.recv_waiters.lock().push_back((task, cpu_id));
+}
+
+fn wake_waiter(waiters: &WaiterQueue) {
+    let waiter = waiters.lock().pop_front();
+    if let Some((task, cpu_id)) = waiter {
+        wake(task, cpu_id);
+    }
+}
+
+/// Wake every waiter, e.g. so they all see `PeerClosed`.
+fn wake_all_waiters(waiters: &WaiterQueue) {
+    let drained: VecDeque<_> = core::mem::take(&mut *waiters.lock());
+    for (task, cpu_id) in drained {
+        wake(task, cpu_id);
+    }
+}
+
+fn wake(task: Arc<Task>, cpu_id: u32) {
+    crate::task::local_scheduler::wake(task, cpu_id);
 }
 
 pub fn try_send(endpoint_id: u64, data: &[u8]) -> Result<(), IpcError> {
+    try_send_with_endpoint(endpoint_id, data, None)
+}
+
+/// Like `try_send`, but `transfer` goes along with the message: it is handed
+/// to whoever receives it. The caller keeps the task-side bookkeeping (see
+/// `TaskInner::owned_endpoints`). A broadcast can't carry an endpoint, and an
+/// endpoint can't carry itself.
+pub fn try_send_with_endpoint(endpoint_id: u64, data: &[u8], transfer: Option<u64>) -> Result<(), IpcError> {
     if data.len() > MAX_MESSAGE_SIZE {
         return Err(IpcError::MessageTooLarge);
     }
+    if transfer == Some(endpoint_id) {
+        return Err(IpcError::InvalidArgs);
+    }
 
     let channel = {
         let registry = ENDPOINT_REGISTRY.lock();
         let ep = registry.get(&endpoint_id).ok_or(IpcError::InvalidEndpoint)?;
-        if ep.role != EndpointRole::Send {
-            return Err(IpcError::WrongDirection);
+        if transfer.is_some_and(|t| !registry.contains_key(&t)) {
+            return Err(IpcError::InvalidEndpoint);
+        }
+        match ep.role {
+            EndpointRole::Send => ep.channel.clone(),
+            EndpointRole::Broadcast if transfer.is_some() => return Err(IpcError::InvalidArgs),
+            EndpointRole::Broadcast => {
+                let hub = ep.channel.clone();
+                drop(registry);
+                broadcast(&hub, data);
+                return Ok(());
+            }
+            EndpointRole::Recv => return Err(IpcError::WrongDirection),
         }
-        ep.channel.clone()
     };
 
-    if channel.recv_closed.load(Ordering::Acquire) {
+    if channel.recv_closed() {
         return Err(IpcError::PeerClosed);
     }
 
@@ -124,14 +349,63 @@ pub fn try_send(endpoint_id: u64, data: &[u8]) -> Result<(), IpcError> {
         return Err(IpcError::ChannelFull);
     }
 
-    inner.queue.push_back(data.to_vec());
+    inner.queue.push_back(Envelope { message: Message::new(data), endpoint: transfer });
     drop(inner);
     // Wake any task that was sleeping waiting to receive
     wake_waiter(&channel.recv_waiters);
     Ok(())
 }
 
-pub fn try_recv(endpoint_id: u64) -> Result<Vec<u8>, IpcError> {
+/// Queue `data` for every subscriber of `hub`. A subscriber whose queue is
+/// full misses this message, so one slow subscriber can't hold up the
+/// sender or the others. Subscribers that closed their end are dropped.
+fn broadcast(hub: &Channel, data: &[u8]) {
+    let subscribers = {
+        let mut subscribers = hub.subscribers.lock();
+        subscribers.retain(|s| !s.recv_closed());
+        subscribers.clone()
+    };
+    let message = Message::new(data);
+    for subscriber in subscribers {
+        let mut inner = subscriber.inner.lock();
+        if inner.queue.len() >= inner.capacity {
+            continue;
+        }
+        inner.queue.push_back(Envelope { message: message.clone(), endpoint: None });
+        drop(inner);
+        wake_waiter(&subscriber.recv_waiters);
+    }
+}
+
+/// Dequeue the head message. An endpoint sent along with it is closed.
+pub fn try_recv(endpoint_id: u64) -> Result<Message, IpcError> {
+    recv_dropping_endpoint(endpoint_id, usize::MAX)
+}
+
+/// Like `try_recv`, but a message longer than `max_len` isn't dequeued: it
+/// stays at the head of the queue and a copy of it is returned, so the
+/// receiver can see its length and try again with room for all of it.
+pub fn try_recv_keep_oversized(endpoint_id: u64, max_len: usize) -> Result<Message, IpcError> {
+    recv_dropping_endpoint(endpoint_id, max_len)
+}
+
+/// Like `try_recv_keep_oversized`, but also returns the endpoint sent along
+/// with the message. It then belongs to the caller, who must record it with
+/// the receiving task. A message left queued keeps its endpoint.
+pub fn try_recv_with_endpoint(endpoint_id: u64, max_len: usize) -> Result<(Message, Option<u64>), IpcError> {
+    recv_front(endpoint_id, max_len)
+}
+
+fn recv_dropping_endpoint(endpoint_id: u64, max_len: usize) -> Result<Message, IpcError> {
+    let (msg, endpoint) = recv_front(endpoint_id, max_len)?;
+    if let Some(endpoint) = endpoint {
+        let _ = close_endpoint(endpoint);
+    }
+    Ok(msg)
+}
+
+/// Dequeue the head message if it is at most `max_len` bytes, else copy it.
+fn recv_front(endpoint_id: u64, max_len: usize) -> Result<(Message, Option<u64>), IpcError> {
     let channel = {
         let registry = ENDPOINT_REGISTRY.lock();
         let ep = registry.get(&endpoint_id).ok_or(IpcError::InvalidEndpoint)?;
@@ -142,30 +416,101 @@ pub fn try_recv(endpoint_id: u64) -> Result<Vec<u8>, IpcError> {
     };
 
     let mut inner = channel.inner.lock();
-    if let Some(msg) = inner.queue.pop_front() {
+    if let Some(front) = inner.queue.front().filter(|e| e.message.len() > max_len) {
+        return Ok((front.message.clone(), None));
+    }
+    if let Some(envelope) = inner.queue.pop_front() {
         drop(inner);
         // Wake any task that was sleeping waiting to send (queue was full)
         wake_waiter(&channel.send_waiters);
-        return Ok(msg);
+        return Ok((envelope.message, envelope.endpoint));
     }
 
-    if channel.send_closed.load(Ordering::Acquire) {
+    if channel.send_closed() {
         return Err(IpcError::PeerClosed);
     }
 
     Err(IpcError::WouldBlock)
 }
 
+/// Number of messages queued on a recv endpoint, without dequeuing any.
+/// An empty channel whose send side is closed reports `PeerClosed`, as
+/// `try_recv` would.
+pub fn poll(endpoint_id: u64) -> Result<usize, IpcError> {
+    let channel = {
+        let registry = ENDPOINT_REGISTRY.lock();
+        let ep = registry.get(&endpoint_id).ok_or(IpcError::InvalidEndpoint)?;
+        if ep.role != EndpointRole::Recv {
+            return Err(IpcError::WrongDirection);
+        }
+        ep.channel.clone()
+    };
+
+    let queued = channel.inner.lock().queue.len();
+    if queued == 0 && channel.send_closed() {
+        return Err(IpcError::PeerClosed);
+    }
+    Ok(queued)
+}
+
+/// Messages queued on the channel of `endpoint_id` and the capacity it was
+/// granted, as `(len, capacity)`. Either endpoint of a channel will do, since
+/// both see the same queue. A broadcast endpoint reports its subscribers'
+/// queue capacity, with nothing queued.
+pub fn channel_stats(endpoint_id: u64) -> Result<(usize, usize), IpcError> {
+    let channel = {
+        let registry = ENDPOINT_REGISTRY.lock();
+        let ep = registry.get(&endpoint_id).ok_or(IpcError::InvalidEndpoint)?;
+        ep.channel.clone()
+    };
+
+    let inner = channel.inner.lock();
+    Ok((inner.queue.len(), inner.capacity))
+}
+
 pub fn close_endpoint(endpoint_id: u64) -> Result<(), IpcError> {
     let ep = {
         let mut registry = ENDPOINT_REGISTRY.lock();
         registry.remove(&endpoint_id).ok_or(IpcError::InvalidEndpoint)?
     };
 
-    match ep.role {
-        EndpointRole::Send => ep.channel.send_closed.store(true, Ordering::Release),
-        EndpointRole::Recv => ep.channel.recv_closed.store(true, Ordering::Release),
+    // The last endpoint of a side closes it; wake the other side's sleepers
+    // so they observe `PeerClosed` instead of waiting forever.
+    let refs = match ep.role {
+        EndpointRole::Send | EndpointRole::Broadcast => &ep.channel.send_refs,
+        EndpointRole::Recv => &ep.channel.recv_refs,
+    };
+    if refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+        match ep.role {
+            EndpointRole::Send => wake_all_waiters(&ep.channel.recv_waiters),
+            EndpointRole::Recv => wake_all_waiters(&ep.channel.send_waiters),
+            EndpointRole::Broadcast => {
+                let subscribers = core::mem::take(&mut *ep.channel.subscribers.lock());
+                for subscriber in subscribers {
+                    subscriber.send_refs.store(0, Ordering::Release);
+                    wake_all_waiters(&subscriber.recv_waiters);
+                }
+            }
+        }
     }
 
     Ok(())
 }
+
+/// Create a new endpoint with the same channel and role as `endpoint_id`.
+/// The channel side stays open until every endpoint of that role is closed.
+pub fn dup_endpoint(endpoint_id: u64) -> Result<u64, IpcError> {
+    let mut registry = ENDPOINT_REGISTRY.lock();
+    let ep = registry.get(&endpoint_id).ok_or(IpcError::InvalidEndpoint)?;
+    let role = ep.role;
+    let channel = ep.channel.clone();
+
+    match role {
+        EndpointRole::Send | EndpointRole::Broadcast => channel.send_refs.fetch_add(1, Ordering::AcqRel),
+        EndpointRole::Recv => channel.recv_refs.fetch_add(1, Ordering::AcqRel),
+    };
+
+    let new_id = NEXT_ENDPOINT_ID.fetch_add(1, Ordering::Relaxed);
+    registry.insert(new_id, Endpoint { role, channel });
+    Ok(new_id)
+}
@@ -6,13 +6,18 @@ extern crate alloc;
 
 pub mod acpi;
 pub mod apic;
+pub mod boot_modules;
+pub mod cmdline;
 pub mod gdt;
 pub mod graphics;
 pub mod drivers;
 pub mod ioapic;
 pub mod limine_requests;
 pub mod memory;
+pub mod numa;
+pub mod power;
 pub mod ipc;
+pub mod random;
 pub mod raw_syscall_handler;
 pub mod syscall_handlers;
 pub mod task;
@@ -27,6 +32,7 @@ pub mod logger;
 pub mod consts;
 pub mod service_registry;
 pub mod shared_buf;
+pub mod symbols;
 
 pub mod reexports {
     pub use x86_64;
@@ -6,8 +6,13 @@ use limine::request::{ExecutableFileRequest, FramebufferRequest, HhdmRequest, Me
 
 pub const INIT_TASK_PATH: &CStr = c"/init_task";
 pub const DISPLAY_SERVER_PATH: &CStr = c"/display_server";
+pub const NET_SERVER_PATH: &CStr = c"/net_server";
+pub const NET_STACK_PATH: &CStr = c"/net_stack";
+pub const SERIAL_SERVER_PATH: &CStr = c"/serial_server";
 pub const BOUNCING_CUBE_1_PATH: &CStr = c"/bouncing_cube_1";
 pub const BOUNCING_CUBE_2_PATH: &CStr = c"/bouncing_cube_2";
+/// Expected CRC-32 of the other modules; see `boot_modules`.
+pub const MODULE_CHECKSUMS_PATH: &CStr = c"/module_checksums";
 
 #[used]
 #[unsafe(link_section = ".requests")]
@@ -39,8 +44,12 @@ pub static MODULE_REQUEST: ModuleRequest =
     ModuleRequest::new().with_internal_modules(&[
         &InternalModule::new().with_path(INIT_TASK_PATH),
         &InternalModule::new().with_path(DISPLAY_SERVER_PATH),
+        &InternalModule::new().with_path(NET_SERVER_PATH),
+        &InternalModule::new().with_path(NET_STACK_PATH),
+        &InternalModule::new().with_path(SERIAL_SERVER_PATH),
         &InternalModule::new().with_path(BOUNCING_CUBE_1_PATH),
         &InternalModule::new().with_path(BOUNCING_CUBE_2_PATH),
+        &InternalModule::new().with_path(MODULE_CHECKSUMS_PATH),
     ]);
 
 #[used]
@@ -1,11 +1,25 @@
+//! The kernel log, written to COM1.
+//!
+//! The logger lock is held with interrupts disabled, so the timer or a
+//! device interrupt can't come in on a CPU that is mid-line and then wait for
+//! that CPU forever. Exceptions and NMIs can still arrive there. A line
+//! logged by one of them, on the CPU that holds the lock, doesn't wait:
+//! errors are written straight to the port, where they may cut into the
+//! interrupted line, and anything less urgent is kept in that CPU's
+//! `DeferredLog` and written once the interrupted line is done.
+
 use core::fmt::Display;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
 use log::{Level, LevelFilter, Log};
 use owo_colors::OwoColorize;
 use uart_16550::SerialPort;
 use unicode_segmentation::UnicodeSegmentation;
 use crate::memory;
 
+/// COM1, which the log is written to.
+const SERIAL_PORT: u16 = 0x3f8;
+
 struct Inner {
     serial_port: SerialPort,
 }
@@ -25,6 +39,28 @@ impl Inner {
         let mut writer = WriterWithCr::new(&mut self.serial_port);
         write!(writer, "{string}").unwrap();
     }
+
+    fn write_record(&mut self, level: Level, args: &core::fmt::Arguments) {
+        self.write_with_color(
+            match level {
+                Level::Error => Color::BrightRed,
+                Level::Warn => Color::BrightYellow,
+                Level::Info => Color::BrightBlue,
+                Level::Debug => Color::BrightCyan,
+                Level::Trace => Color::BrightMagenta,
+            },
+            format_args!("{level:5} "),
+        );
+        let cpu_id =
+            memory::cpu_local_data::try_get_local().map_or(0, |data| data.kernel_id);
+        let width = match memory::cpu_local_data::cpus_count() {
+            1 => 1,
+            n => (n - 1).ilog(16) as usize + 1,
+        };
+        self.write_with_color(Color::Gray, format_args!("[{cpu_id:0width$X}] "));
+        self.write_with_color(Color::Default, args);
+        self.write_with_color(Color::Default, "\n");
+    }
 }
 
 struct KernelLogger {
@@ -33,37 +69,68 @@ struct KernelLogger {
 
 static LOGGER: KernelLogger = KernelLogger {
     inner: spin::Mutex::new(Inner {
-        serial_port: unsafe { SerialPort::new(0x3f8) },
+        serial_port: unsafe { SerialPort::new(SERIAL_PORT) },
     }),
 };
 
+/// APIC ID of the CPU holding the logger lock, or `NO_OWNER`.
+static OWNER: AtomicU32 = AtomicU32::new(NO_OWNER);
+const NO_OWNER: u32 = u32::MAX;
+
+/// Lines logged since boot that were lost because a CPU's `DeferredLog` was
+/// full, or busy with another nested line.
+pub static DROPPED_LINES: AtomicU32 = AtomicU32::new(0);
+
+/// This CPU's APIC ID. CPUID has it before per-CPU data is set up, as it
+/// isn't yet on an AP that logs its first steps.
+fn current_cpu() -> u32 {
+    match memory::cpu_local_data::try_get_local() {
+        Some(data) => data.local_apic_id,
+        None => unsafe { core::arch::x86_64::__cpuid(1) }.ebx >> 24,
+    }
+}
+
+/// Whether this CPU was interrupted while holding the logger lock, so
+/// waiting for it would never end.
+fn lock_held_here() -> bool {
+    OWNER.load(Ordering::Relaxed) == current_cpu()
+}
+
+/// Run `f` on the logger with interrupts disabled, then write out whatever
+/// exceptions on this CPU logged meanwhile.
+fn with_inner<R>(f: impl FnOnce(&mut Inner) -> R) -> R {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut inner = LOGGER.inner.lock();
+        OWNER.store(current_cpu(), Ordering::Relaxed);
+        let result = f(&mut inner);
+        inner.write_deferred();
+        OWNER.store(NO_OWNER, Ordering::Relaxed);
+        result
+    })
+}
+
+/// Write a line without the lock, for an error logged over a line this CPU
+/// is in the middle of. It starts on a fresh line; the interrupted one
+/// carries on after it.
+fn write_unlocked(level: Level, args: &core::fmt::Arguments) {
+    let mut inner = Inner { serial_port: unsafe { SerialPort::new(SERIAL_PORT) } };
+    inner.write_with_color(Color::Default, "\n");
+    inner.write_record(level, args);
+}
+
 impl Log for KernelLogger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
         metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &log::Record) {
-        let mut inner = self.inner.lock();
-        let level = record.level();
-        inner.write_with_color(
-            match level {
-                Level::Error => Color::BrightRed,
-                Level::Warn => Color::BrightYellow,
-                Level::Info => Color::BrightBlue,
-                Level::Debug => Color::BrightCyan,
-                Level::Trace => Color::BrightMagenta,
-            },
-            format_args!("{level:5} "),
-        );
-        let cpu_id =
-            memory::cpu_local_data::try_get_local().map_or(0, |data| data.kernel_id);
-        let width = match memory::cpu_local_data::cpus_count() {
-            1 => 1,
-            n => (n - 1).ilog(16) as usize + 1,
-        };
-        inner.write_with_color(Color::Gray, format_args!("[{cpu_id:0width$X}] "));
-        inner.write_with_color(Color::Default, record.args());
-        inner.write_with_color(Color::Default, "\n");
+        if !lock_held_here() {
+            with_inner(|inner| inner.write_record(record.level(), record.args()));
+        } else if record.level() == Level::Error {
+            write_unlocked(record.level(), record.args());
+        } else {
+            defer(record.level(), record.args());
+        }
     }
 
     fn flush(&self) {}
@@ -76,6 +143,135 @@ pub fn init() -> Result<(), log::SetLoggerError> {
     log::set_logger(&LOGGER)
 }
 
+/// Lower the max level to the command line's `loglevel=` (`error`, `warn`,
+/// `info`, `debug`, `trace` or `off`), if given. Runs after `cmdline::init`.
+pub fn apply_cmdline_level() {
+    let Some(value) = crate::cmdline::get("loglevel") else { return };
+    match value.parse::<LevelFilter>() {
+        Ok(level) => log::set_max_level(level),
+        Err(_) => log::warn!("cmdline: unknown loglevel {value:?}, keeping {}", log::max_level()),
+    }
+}
+
+/// Run `f` with exclusive use of the logger's serial port, for raw access to
+/// COM1 that must not interleave with log lines.
+pub fn with_serial_port<R>(f: impl FnOnce() -> R) -> R {
+    with_inner(|_| f())
+}
+
+/// Lock attempts an NMI handler makes before giving up on the logger.
+const NMI_LOCK_ATTEMPTS: u32 = 1_000_000;
+
+/// Log an error line from an NMI handler. Another CPU may be stuck holding
+/// the logger lock, so this gives up after a bounded wait rather than
+/// deadlock. If this CPU holds it, the line is written at once, as for any
+/// nested error. Returns whether the line was written.
+pub fn log_from_nmi(args: core::fmt::Arguments) -> bool {
+    if lock_held_here() {
+        write_unlocked(Level::Error, &args);
+        return true;
+    }
+    for _ in 0..NMI_LOCK_ATTEMPTS {
+        if let Some(mut inner) = LOGGER.inner.try_lock() {
+            inner.write_record(Level::Error, &args);
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+/// Bytes of held-back lines a CPU can keep.
+const DEFERRED_LOG_SIZE: usize = 1024;
+
+/// Lines a CPU logged from an exception while it held the logger lock, in
+/// order. Each is stored as its level, its length as a little-endian `u16`,
+/// then its text.
+pub struct DeferredLog {
+    buf: [u8; DEFERRED_LOG_SIZE],
+    len: usize,
+}
+
+impl DeferredLog {
+    pub const fn new() -> Self {
+        DeferredLog { buf: [0; DEFERRED_LOG_SIZE], len: 0 }
+    }
+
+    /// Append a line, cut to the space left. Returns false if not even its
+    /// header fits.
+    fn push(&mut self, level: Level, args: &core::fmt::Arguments) -> bool {
+        const HEADER: usize = 3;
+        if DEFERRED_LOG_SIZE - self.len <= HEADER {
+            return false;
+        }
+        let start = self.len + HEADER;
+        let mut text = Truncating { buf: &mut self.buf[start..], len: 0 };
+        let _ = text.write_fmt(*args);
+        let text_len = text.len.min(u16::MAX as usize);
+        self.buf[self.len] = level as usize as u8;
+        self.buf[self.len + 1..start].copy_from_slice(&(text_len as u16).to_le_bytes());
+        self.len = start + text_len;
+        true
+    }
+}
+
+/// Keep a line for this CPU to write once it releases the logger lock.
+fn defer(level: Level, args: &core::fmt::Arguments) {
+    let kept = memory::cpu_local_data::try_get_local()
+        .and_then(|cpu| cpu.deferred_log.try_lock())
+        .is_some_and(|mut deferred| deferred.push(level, args));
+    if !kept {
+        DROPPED_LINES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Inner {
+    /// Write out and clear this CPU's held-back lines.
+    fn write_deferred(&mut self) {
+        let Some(cpu) = memory::cpu_local_data::try_get_local() else { return };
+        let Some(mut deferred) = cpu.deferred_log.try_lock() else { return };
+        let mut pos = 0;
+        while pos < deferred.len {
+            let level = level_from_byte(deferred.buf[pos]);
+            let text_len = u16::from_le_bytes([deferred.buf[pos + 1], deferred.buf[pos + 2]]) as usize;
+            let text = &deferred.buf[pos + 3..pos + 3 + text_len];
+            // The cut may land inside a character; drop the partial one.
+            let text = match core::str::from_utf8(text) {
+                Ok(s) => s,
+                Err(e) => core::str::from_utf8(&text[..e.valid_up_to()]).unwrap_or_default(),
+            };
+            self.write_record(level, &format_args!("{text}"));
+            pos += 3 + text_len;
+        }
+        deferred.len = 0;
+    }
+}
+
+fn level_from_byte(byte: u8) -> Level {
+    match byte {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+/// Formats into a fixed buffer, dropping whatever doesn't fit.
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
 struct WriterWithCr<T> {
     writer: T,
 }
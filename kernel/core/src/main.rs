@@ -11,15 +11,16 @@ use core::fmt::Write;
 use core::sync::atomic::{AtomicBool, Ordering};
 use embedded_graphics::geometry::Point;
 use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
-use kernel::graphics::display::{self, DISPLAY, DISPLAY_OWNER};
+use kernel::graphics::display::{self, DISPLAY, DISPLAY_HOME, DISPLAY_OWNER};
 use kernel::graphics::writer::Writer;
 use kernel::limine_requests::{BASE_REVISION, MP_REQUEST, RSDP_REQUEST};
 use kernel::user_task_from_elf::create_user_task_from_elf;
 use kernel::memory::cpu_local_data::{get_local, mark_current_cpu_crashed, mark_current_cpu_ready};
 use kernel::memory::guarded_stack::{GuardedStack, StackId, StackType, NORMAL_STACK_SIZE};
-use kernel::{acpi, apic, gdt, hlt_loop, interrupt, ioapic, logger, project_version, raw_syscall_handler, time};
+use kernel::{acpi, apic, boot_modules, cmdline, gdt, hlt_loop, interrupt, ioapic, logger, numa, power, project_version, raw_syscall_handler, symbols, time};
 use kernel::interrupt::nmi_handler_state;
-use kernel::task::global_scheduler::{spawn_task, spawn_local_task};
+use kernel::task::global_scheduler::{spawn_idle_task, spawn_task};
+use kernel::task::idle::idle_task;
 use kernel::task::local_scheduler::init_run_queue;
 use kernel::task::task::Task;
 
@@ -33,6 +34,8 @@ unsafe extern "C" fn kernel_main() -> ! {
 
     // Enable logger
     logger::init().unwrap();
+    cmdline::init();
+    logger::apply_cmdline_level();
     log::info!("Welcome to Bos! V:{}", project_version());
 
     let memory_map = MEMORY_MAP_REQUEST.get_response().unwrap();
@@ -41,6 +44,8 @@ unsafe extern "C" fn kernel_main() -> ! {
         kernel::memory::cpu_local_data::init_bsp();
     }
     log::info!("BSP memory initialized.");
+    display::init_back_buffer();
+    symbols::init();
 
     GuardedStack::new_kernel(
         NORMAL_STACK_SIZE,
@@ -65,31 +70,56 @@ extern "sysv64" fn init_bsp() -> ! {
 
     let rsdp = RSDP_REQUEST.get_response().unwrap();
     let acpi_tables = acpi::parse(rsdp);
+    numa::init(&acpi_tables);
+    power::init(&acpi_tables);
+    time::rtc::init(&acpi_tables);
     apic::init_bsp(&acpi_tables);
     apic::init_local_apic();
 
     ioapic::init(&acpi_tables);
-    ioapic::enable_keyboard_irq(
+    ioapic::route_isa_irq(
+        ioapic::ISA_IRQ_KEYBOARD,
         u8::from(interrupt::InterruptVector::Keyboard),
         get_local().local_apic_id,
-    );
-    ioapic::enable_mouse_irq(
+    )
+    .expect("Failed to route the keyboard IRQ");
+    ioapic::route_isa_irq(
+        ioapic::ISA_IRQ_MOUSE,
         u8::from(interrupt::InterruptVector::Mouse),
         get_local().local_apic_id,
-    );
+    )
+    .expect("Failed to route the mouse IRQ");
+    ioapic::route_isa_irq(
+        ioapic::ISA_IRQ_COM1,
+        u8::from(interrupt::InterruptVector::Serial),
+        get_local().local_apic_id,
+    )
+    .expect("Failed to route the serial IRQ");
+    kernel::drivers::serial::enable_rx_interrupt();
     kernel::drivers::mouse::init();
+    kernel::drivers::pci::init();
+    kernel::drivers::e1000::init();
 
+    time::hpet::init(&acpi_tables);
     time::tsc::calibrate();
     time::lapic_timer::init();
     time::lapic_timer::set_deadline(1_000_000);
 
     raw_syscall_handler::init();
+    display::init_events();
     init_run_queue();
 
-    spawn_local_task(Task::new(idle_task));
-    let init_task = create_user_task_from_elf();
-    DISPLAY_OWNER.store(init_task.id.to_u64(), core::sync::atomic::Ordering::SeqCst);
-    spawn_task(init_task);
+    spawn_idle_task(Task::new_named(idle_task, "idle"));
+    boot_modules::verify_checksums();
+    match create_user_task_from_elf() {
+        Ok(init_task) => {
+            DISPLAY_OWNER.store(init_task.id.to_u64(), core::sync::atomic::Ordering::SeqCst);
+            DISPLAY_HOME.store(init_task.id.to_u64(), core::sync::atomic::Ordering::SeqCst);
+            spawn_task(init_task);
+        }
+        // The kernel keeps running, with only kernel tasks and the panic screen.
+        Err(e) => log::error!("BSP: could not create init_task: {:?}", e),
+    }
 
     let mp_response = MP_REQUEST.get_response().unwrap();
     for cpu in mp_response.cpus() {
@@ -143,7 +173,7 @@ extern "sysv64" fn init_ap() -> ! {
     time::lapic_timer::set_deadline(1_000_000);
     mark_current_cpu_ready();
 
-    spawn_local_task(Task::new(idle_task));
+    spawn_idle_task(Task::new_named(idle_task, "idle"));
 
     x86_64::instructions::interrupts::enable();
 
@@ -152,21 +182,27 @@ extern "sysv64" fn init_ap() -> ! {
     hlt_loop()
 }
 
-fn idle_task() -> ! {
-    loop {
-        x86_64::instructions::hlt();
-    }
-}
-
 static DID_PANIC: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 fn rust_panic(_info: &core::panic::PanicInfo) -> ! {
     mark_current_cpu_crashed();
+    // The swap elects one CPU to draw the crash dump; being a single atomic
+    // read-modify-write it does that under any ordering. The ordering that
+    // matters is between the other CPUs' last VRAM writes and the dump, and
+    // that comes from `stop_other_cpus_for_panic`. A CPU that loses the
+    // election halts below and is stopped like the rest by the winner's NMI.
     if !DID_PANIC.swap(true, Ordering::Relaxed) {
         log::error!("{_info}");
 
-        // Take over the framebuffer for a visual crash dump
+        // Nothing may draw after the crash dump, so stop the other CPUs first.
+        interrupt::handlers::stop_other_cpus_for_panic();
+        unsafe { DISPLAY.force_unlock() };
+
+        // Take over the framebuffer for a visual crash dump. It is drawn off
+        // screen, if there is a back buffer, and shown in one copy, so the
+        // screen never shows it half drawn.
+        DISPLAY.set_buffered(true);
         let bb = DISPLAY.bounding_box();
         let _ = DISPLAY.fill_solid(&bb, Rgb888::new(0, 0, 128)); // dark blue
         let mut position = Point::new(10, 10);
@@ -175,6 +211,7 @@ fn rust_panic(_info: &core::panic::PanicInfo) -> ! {
             text_color: Rgb888::WHITE,
         };
         let _ = write!(writer, "KERNEL PANIC\n\n{_info}");
+        DISPLAY.present();
 
         hlt_loop();
     } else {
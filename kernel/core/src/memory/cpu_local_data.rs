@@ -1,7 +1,9 @@
 use crate::gdt::Gdt;
 use crate::limine_requests::MP_REQUEST;
+use crate::logger::DeferredLog;
 use crate::task::local_scheduler::RunQueue;
 use crate::task::task::CpuContext;
+use crate::time::timers::TimerQueue;
 use alloc::boxed::Box;
 use core::cell::UnsafeCell;
 use core::default::Default;
@@ -48,6 +50,9 @@ pub struct CpuLocalData {
     pub current_task_kernel_stack_top: AtomicU64,
     /// Pointer to the current task's CpuContext (used by timer handler for save/restore)
     pub current_context_ptr: AtomicPtr<CpuContext>,
+    /// Pointer to this CPU's idle task's CpuContext, or null before it exists.
+    /// Lets the scheduler see it is idling without taking the run queue lock.
+    pub idle_context_ptr: AtomicPtr<CpuContext>,
     /// Set to 1 while inside a syscall handler. When the timer fires with this flag set,
     /// it skips saving registers (user state was already saved at syscall entry).
     pub in_syscall_handler: AtomicU8,
@@ -56,6 +61,19 @@ pub struct CpuLocalData {
     pub ready_count: core::sync::atomic::AtomicUsize,
     /// Lifecycle state — guards task dispatch and crash handling.
     pub state: AtomicCpuState,
+    /// One-shot timers registered on this CPU, fired from its timer interrupt.
+    pub timers: Mutex<TimerQueue>,
+    /// Timer interrupts taken; an idle CPU takes far fewer (see `task::idle`).
+    pub timer_interrupts: AtomicU64,
+    /// Nanoseconds the idle task has spent waiting for an interrupt.
+    pub idle_ns: AtomicU64,
+    /// When the idle task's current wait began (`time::now_ns`), or 0 if it
+    /// isn't waiting.
+    pub idle_since_ns: AtomicU64,
+    /// Lines this CPU logged while it was itself holding the logger lock.
+    pub deferred_log: Mutex<DeferredLog>,
+    /// Last `memory::tlb` shootdown generation this CPU has flushed for.
+    pub tlb_generation: AtomicU64,
 }
 
 /// Offset of current_context_ptr in CpuLocalData for assembly access
@@ -120,9 +138,16 @@ fn init_cpu(kernel_id: u32, local_apic_id: u32) {
             current_task_kernel_stack_top: AtomicU64::new(0),
             run_queue: Once::new(),
             current_context_ptr: AtomicPtr::new(core::ptr::null_mut()),
+            idle_context_ptr: AtomicPtr::new(core::ptr::null_mut()),
             in_syscall_handler: AtomicU8::new(0),
             ready_count: core::sync::atomic::AtomicUsize::new(0),
             state: AtomicCpuState::new(CpuState::Initializing),
+            timers: Mutex::new(TimerQueue::new()),
+            timer_interrupts: AtomicU64::new(0),
+            idle_ns: AtomicU64::new(0),
+            idle_since_ns: AtomicU64::new(0),
+            deferred_log: Mutex::new(DeferredLog::new()),
+            tlb_generation: AtomicU64::new(0),
         }),
     )
 }
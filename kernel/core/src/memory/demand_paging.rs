@@ -0,0 +1,172 @@
+//! Backing for user ranges reserved with `MMAP_LAZY`. Their pages are mapped on
+//! first touch by the page fault handler, or up front by `sys_populate`.
+
+use crate::memory::MEMORY;
+use crate::memory::cpu_local_data::try_get_local;
+use crate::memory::hhdm_offset::hhdm_offset;
+use crate::memory::physical_memory::{MemoryType, OffsetMappedPhysAddr, PhysicalMemory};
+use crate::memory::user_vaddr;
+use crate::task::task::{Task, TaskKind};
+use kernel_api_types::SysError;
+use spin::{Mutex, MutexGuard};
+use x86_64::structures::paging::{
+    Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size2MiB,
+    Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The user page table whose L4 frame is at `cr3`, accessed through the HHDM.
+pub fn user_mapper(cr3: u64) -> OffsetPageTable<'static> {
+    let hhdm_offset = hhdm_offset();
+    let user_l4_frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(cr3));
+    let l4_virt_addr = VirtAddr::new(hhdm_offset.as_u64() + user_l4_frame.start_address().as_u64());
+    let l4_table = unsafe { &mut *l4_virt_addr.as_mut_ptr::<PageTable>() };
+    unsafe { OffsetPageTable::new(l4_table, VirtAddr::new(hhdm_offset.as_u64())) }
+}
+
+/// Allocate a zeroed frame and map it at `page`. Returns false if memory ran out,
+/// in which case nothing is left mapped.
+pub fn map_zeroed_page(
+    mapper: &mut OffsetPageTable,
+    physical_memory: &mut PhysicalMemory,
+    page: Page<Size4KiB>,
+    flags: PageTableFlags,
+) -> bool {
+    let Some(frame) = physical_memory.allocate_frame_with_type(MemoryType::UsedByUserMode) else {
+        return false;
+    };
+
+    // Security: zero the frame before giving it to user space
+    let frame_virt = frame.start_address().offset_mapped();
+    unsafe {
+        core::ptr::write_bytes(frame_virt.as_mut_ptr::<u8>(), 0, Size4KiB::SIZE as usize);
+    }
+
+    let mut frame_allocator = physical_memory.get_user_mode_frame_allocator();
+    let map_result = unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) };
+    drop(frame_allocator);
+
+    match map_result {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        Err(_) => {
+            let _ = physical_memory.free_frame(frame, MemoryType::UsedByUserMode);
+            false
+        }
+    }
+}
+
+/// Like `map_zeroed_page`, but for a 2 MiB page backed by one 2 MiB frame.
+/// Returns false if no such frame is free or the slot already holds a 4 KiB
+/// page table; nothing is left mapped, so the caller can fall back to 4 KiB pages.
+pub fn map_zeroed_huge_page(
+    mapper: &mut OffsetPageTable,
+    physical_memory: &mut PhysicalMemory,
+    page: Page<Size2MiB>,
+    flags: PageTableFlags,
+) -> bool {
+    let Some(frame) = physical_memory.allocate_frame_2mib(MemoryType::UsedByUserMode) else {
+        return false;
+    };
+
+    let frame_virt = frame.start_address().offset_mapped();
+    unsafe {
+        core::ptr::write_bytes(frame_virt.as_mut_ptr::<u8>(), 0, Size2MiB::SIZE as usize);
+    }
+
+    let mut frame_allocator = physical_memory.get_user_mode_frame_allocator();
+    let map_result = unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) };
+    drop(frame_allocator);
+
+    match map_result {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        Err(_) => {
+            let _ = physical_memory.free_frame_2mib(frame, MemoryType::UsedByUserMode);
+            false
+        }
+    }
+}
+
+/// Map the page containing `addr` if the current user task reserved it lazily.
+/// Called by the page fault handler for not-present faults; returns true if the
+/// faulting access can be retried.
+///
+/// A fault from ring 3 (`from_user`) interrupted no kernel code, so this CPU
+/// holds none of the run queue, task or physical memory locks and it waits for
+/// them like any other path. A fault raised in the kernel, e.g. a syscall
+/// touching a lazy user page, may have interrupted code holding one of them, so
+/// there each is only try-locked. If any is busy the fault is left to the
+/// handler's usual panic rather than deadlocking.
+pub fn handle_fault(addr: u64, from_user: bool) -> bool {
+    let Ok(vaddr) = VirtAddr::try_new(addr) else {
+        return false;
+    };
+    let page = Page::<Size4KiB>::containing_address(vaddr);
+
+    let Some(cpu) = try_get_local() else {
+        return false;
+    };
+    let task = {
+        let Some(rq) = cpu.run_queue.get().and_then(|rq| lock(rq, from_user)) else {
+            return false;
+        };
+        match &rq.current_task {
+            Some(t) if t.kind == TaskKind::User => t.clone(),
+            _ => return false,
+        }
+    };
+
+    let Some(inner) = lock(&task.inner, from_user) else {
+        return false;
+    };
+    let Some(&flags) = inner.lazy_regions.get_at_point(page.start_address().as_u64()) else {
+        return false;
+    };
+    let Some(mut physical_memory) = lock(&MEMORY.get().unwrap().physical_memory, from_user) else {
+        return false;
+    };
+
+    map_zeroed_page(&mut user_mapper(task.cr3), &mut physical_memory, page, flags)
+}
+
+/// Take `mutex`, waiting for it if `block`, else only if it is free.
+fn lock<T>(mutex: &Mutex<T>, block: bool) -> Option<MutexGuard<'_, T>> {
+    if block { Some(mutex.lock()) } else { mutex.try_lock() }
+}
+
+/// Map every not-yet-touched lazy page in `[addr, addr + n_pages * 4 KiB)`.
+///
+/// The range must lie within the task's reservations; pages that are already
+/// resident (eager `sys_mmap`, or lazy pages touched before) are skipped. If memory
+/// runs out part-way, the pages mapped so far stay mapped.
+pub fn populate(task: &Task, addr: u64, n_pages: u64) -> Result<(), SysError> {
+    let len = n_pages.checked_mul(Size4KiB::SIZE).ok_or(SysError::InvalidArgs)?;
+
+    let inner = task.inner.lock();
+    if !user_vaddr::is_range_reserved(&inner.user_vaddr_set, addr, len) {
+        return Err(SysError::InvalidArgs);
+    }
+
+    let mut mapper = user_mapper(task.cr3);
+    let mut physical_memory = MEMORY.get().unwrap().physical_memory.lock();
+
+    for i in 0..n_pages {
+        let vaddr = addr + i * Size4KiB::SIZE;
+        let Some(&flags) = inner.lazy_regions.get_at_point(vaddr) else {
+            continue;
+        };
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(vaddr));
+        if mapper.translate_page(page).is_ok() {
+            continue;
+        }
+        if !map_zeroed_page(&mut mapper, &mut physical_memory, page, flags) {
+            return Err(SysError::OutOfMemory);
+        }
+    }
+    Ok(())
+}
@@ -1,6 +1,9 @@
 use crate::memory::physical_memory::OffsetMappedPhysAddr;
+use core::alloc::{GlobalAlloc, Layout};
 use core::mem::MaybeUninit;
 use core::ptr::{NonNull, slice_from_raw_parts_mut};
+use core::sync::atomic::{AtomicU64, Ordering};
+use kernel_api_types::AllocStats;
 use limine::memory_map::EntryType;
 use limine::response::MemoryMapResponse;
 use talc::{ErrOnOom, Talc, Talck};
@@ -12,10 +15,75 @@ pub const GLOBAL_ALLOCATOR_SIZE: u64 = {
 };
 
 #[global_allocator]
-pub static GLOBAL_ALLOCATOR: Talck<spin::Mutex<()>, ErrOnOom> = Talck::new({
-    // Initially, there is no memory backing `Talc`. We will add memory at run time
-    Talc::new(ErrOnOom)
-});
+pub static GLOBAL_ALLOCATOR: CountingAllocator = CountingAllocator {
+    inner: Talck::new({
+        // Initially, there is no memory backing `Talc`. We will add memory at run time
+        Talc::new(ErrOnOom)
+    }),
+    allocated_bytes: AtomicU64::new(0),
+    allocation_count: AtomicU64::new(0),
+    free_count: AtomicU64::new(0),
+};
+
+/// The heap allocator, counting what passes through it so leaks show up in
+/// `stats()`.
+pub struct CountingAllocator {
+    inner: Talck<spin::Mutex<()>, ErrOnOom>,
+    allocated_bytes: AtomicU64,
+    allocation_count: AtomicU64,
+    free_count: AtomicU64,
+}
+
+impl CountingAllocator {
+    fn record_alloc(&self, size: usize) {
+        self.allocated_bytes.fetch_add(size as u64, Ordering::Relaxed);
+        self.allocation_count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            self.record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            self.record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+        self.allocated_bytes.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+        self.free_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A reallocation stays one live allocation; only its size changes.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            self.allocated_bytes.fetch_add(new_size as u64, Ordering::Relaxed);
+            self.allocated_bytes.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// What the heap holds now, and how many allocations and frees it has seen
+/// since boot. `allocation_count - free_count` is the number of live blocks.
+pub fn stats() -> AllocStats {
+    AllocStats {
+        allocated_bytes: GLOBAL_ALLOCATOR.allocated_bytes.load(Ordering::Relaxed),
+        allocation_count: GLOBAL_ALLOCATOR.allocation_count.load(Ordering::Relaxed),
+        free_count: GLOBAL_ALLOCATOR.free_count.load(Ordering::Relaxed),
+    }
+}
 
 /// Finds unused physical memory for the global allocator and initializes the global allocator.
 /// Returns the start address of the physical memory used for the global allocator.
@@ -44,7 +112,7 @@ pub unsafe fn init(memory_map: &'static MemoryMapResponse) -> PhysAddr {
         // Safety: Physical memory must be reserved and offset mapped
         unsafe { ptr.as_mut() }
     };
-    let mut talc = GLOBAL_ALLOCATOR.lock();
+    let mut talc = GLOBAL_ALLOCATOR.inner.lock();
     let span = global_allocator_mem.into();
     // Safety: Span must be from valid memory
     unsafe { talc.claim(span) }.unwrap();
@@ -7,11 +7,13 @@ use x86_64::registers::control::{Cr3, Cr3Flags};
 use x86_64::structures::paging::{PhysFrame, Size4KiB};
 
 pub mod cpu_local_data;
+pub mod demand_paging;
 pub mod global_allocator;
 pub mod guarded_stack;
 pub mod hhdm_offset;
 pub mod page_tables;
 pub mod physical_memory;
+pub mod tlb;
 pub mod user_vaddr;
 pub mod vaddr_allocator;
 
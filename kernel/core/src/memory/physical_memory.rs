@@ -1,9 +1,11 @@
 use crate::memory::global_allocator;
 use crate::memory::hhdm_offset::hhdm_offset;
+use crate::numa::{self, NodeId};
+use alloc::vec::Vec;
 use limine::memory_map::EntryType;
 use limine::response::MemoryMapResponse;
-use nodit::{Interval, NoditMap};
-use x86_64::structures::paging::{FrameAllocator, Page, PageSize, PhysFrame, Size4KiB};
+use nodit::{InclusiveInterval, Interval, NoditMap};
+use x86_64::structures::paging::{FrameAllocator, Page, PageSize, PhysFrame, Size2MiB, Size4KiB};
 use x86_64::{PhysAddr, VirtAddr};
 use crate::exceptions::FreeError;
 
@@ -12,6 +14,8 @@ pub enum KernelMemoryUsageType {
     PageTables,
     GlobalAllocatorHeap,
     Stack,
+    /// Descriptor rings and packet buffers that devices access by DMA.
+    DeviceDma,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -27,10 +31,22 @@ pub enum MemoryType {
     SharedBuffer,
 }
 
+/// Frames taken from `map` per scan when a node's free list runs dry.
+const REFILL_FRAMES: usize = 64;
+/// A free list never grows past this many entries. A frame freed beyond it is
+/// still `Usable` in `map`, so a later refill finds it.
+const MAX_FREE_LIST_LEN: usize = 16 * 1024;
+
 #[derive(Debug)]
 pub struct PhysicalMemory {
     /// A map of used physical memory
     map: NoditMap<u64, Interval<u64>, MemoryType>,
+    /// Per NUMA node, a stack of addresses of frames that were `Usable` when
+    /// pushed, so allocation usually doesn't have to scan `map`. `map` stays the
+    /// authority: frames can be claimed behind the list's back (by `map_mut`
+    /// users, or by a scan while the list was empty), so every popped entry is
+    /// checked and dropped if it is no longer `Usable`.
+    free_lists: Vec<(NodeId, Vec<u64>)>,
 }
 
 /// Track used physical memory
@@ -73,6 +89,7 @@ impl PhysicalMemory {
                 .unwrap();
                 map
             },
+            free_lists: Vec::new(),
         }
     }
 
@@ -90,26 +107,62 @@ impl PhysicalMemory {
         }
     }
 
+    /// Allocate a frame, preferring one on the current CPU's NUMA node.
     pub fn allocate_frame_with_type(
         &mut self,
         memory_type: MemoryType,
+    ) -> Option<PhysFrame<Size4KiB>> {
+        self.allocate_frame_near(memory_type, numa::current_node())
+    }
+
+    /// Allocate a frame on `node` if one is free there, otherwise on any node.
+    ///
+    /// Frames come from the node's free list; only when it is empty is `map`
+    /// scanned, and then for a batch of frames at once.
+    pub fn allocate_frame_near(
+        &mut self,
+        memory_type: MemoryType,
+        node: NodeId,
     ) -> Option<PhysFrame<Size4KiB>> {
         let size = Size4KiB::SIZE; // 4096
 
-        let aligned_start = self.map.iter().find_map(|(interval, m_type)| {
-            if let MemoryType::Usable = m_type {
-                let aligned_start = (*interval.start()).next_multiple_of(size);
-                let required_end = aligned_start + size;
+        let aligned_start = self
+            .pop_free(node)
+            .or_else(|| {
+                self.refill(node);
+                self.pop_free(node)
+            })
+            .or_else(|| self.pop_free_any())
+            .or_else(|| {
+                self.usable_ranges()
+                    .find_map(|(start, end)| aligned_blocks(start, end, size).next())
+            })?;
 
-                if required_end <= *interval.end() {
-                    Some(aligned_start)
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        })?;
+        let range = aligned_start..aligned_start + size;
+        let _ = self.map.cut(&Interval::from(range.clone()));
+        self.map
+            .insert_merge_touching_if_values_equal(range.into(), memory_type)
+            .unwrap();
+
+        Some(PhysFrame::containing_address(PhysAddr::new(aligned_start)))
+    }
+
+    /// Allocate a 2 MiB frame: 512 contiguous 4 KiB frames starting on a
+    /// 2 MiB boundary, preferring the current CPU's NUMA node. These are rare
+    /// and large, so they bypass the free lists and scan `map` directly.
+    pub fn allocate_frame_2mib(&mut self, memory_type: MemoryType) -> Option<PhysFrame<Size2MiB>> {
+        let size = Size2MiB::SIZE;
+        let node = numa::current_node();
+        let topology = numa::topology();
+
+        let aligned_start = self
+            .usable_ranges()
+            .flat_map(|(start, end)| topology.ranges_on_node(node, start, end))
+            .find_map(|(start, end)| aligned_blocks(start, end, size).next())
+            .or_else(|| {
+                self.usable_ranges()
+                    .find_map(|(start, end)| aligned_blocks(start, end, size).next())
+            })?;
 
         let range = aligned_start..aligned_start + size;
         let _ = self.map.cut(&Interval::from(range.clone()));
@@ -120,13 +173,95 @@ impl PhysicalMemory {
         Some(PhysFrame::containing_address(PhysAddr::new(aligned_start)))
     }
 
+    fn usable_ranges(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.map
+            .iter()
+            .filter(|(_, m_type)| matches!(m_type, MemoryType::Usable))
+            .map(|(interval, _)| (*interval.start(), *interval.end()))
+    }
+
+    /// Whether the whole frame at `start` is `Usable` in `map`.
+    fn is_usable(&self, start: u64) -> bool {
+        let frame = Interval::from(start..start + Size4KiB::SIZE);
+        let mut overlapping = self.map.overlapping(frame);
+        matches!(
+            (overlapping.next(), overlapping.next()),
+            (Some((interval, MemoryType::Usable)), None) if interval.contains_interval(&frame)
+        )
+    }
+
+    fn free_list_mut(&mut self, node: NodeId) -> Option<&mut Vec<u64>> {
+        self.free_lists.iter_mut().find(|(n, _)| *n == node).map(|(_, list)| list)
+    }
+
+    /// Pop frames off `node`'s free list until one is still usable.
+    fn pop_free(&mut self, node: NodeId) -> Option<u64> {
+        loop {
+            let start = self.free_list_mut(node)?.pop()?;
+            if self.is_usable(start) {
+                return Some(start);
+            }
+        }
+    }
+
+    fn pop_free_any(&mut self) -> Option<u64> {
+        (0..self.free_lists.len()).find_map(|i| self.pop_free(self.free_lists[i].0))
+    }
+
+    /// Push `start` onto its node's free list, if there is room. Dropping it
+    /// is harmless: the frame is `Usable` in `map` either way.
+    fn push_free(&mut self, node: NodeId, start: u64) {
+        if self.free_list_mut(node).is_none() {
+            if self.free_lists.try_reserve(1).is_err() {
+                return;
+            }
+            self.free_lists.push((node, Vec::new()));
+        }
+        let list = self.free_list_mut(node).unwrap();
+        if list.len() < MAX_FREE_LIST_LEN && list.try_reserve(1).is_ok() {
+            list.push(start);
+        }
+    }
+
+    /// Scan `map` once for up to `REFILL_FRAMES` usable frames on `node` and
+    /// push them, lowest address on top.
+    fn refill(&mut self, node: NodeId) {
+        let topology = numa::topology();
+        let mut found = [0u64; REFILL_FRAMES];
+        let mut n = 0;
+        for start in self
+            .usable_ranges()
+            .flat_map(|(start, end)| topology.ranges_on_node(node, start, end))
+            .flat_map(|(start, end)| aligned_blocks(start, end, Size4KiB::SIZE))
+            .take(REFILL_FRAMES)
+        {
+            found[n] = start;
+            n += 1;
+        }
+        for &start in found[..n].iter().rev() {
+            self.push_free(node, start);
+        }
+    }
+
     pub fn free_frame(
         &mut self,
         frame: PhysFrame<Size4KiB>,
         expected: MemoryType,
     ) -> Result<(), FreeError> {
-        let start = frame.start_address().as_u64();
-        let size = Size4KiB::SIZE;
+        self.free_range(frame.start_address().as_u64(), Size4KiB::SIZE, expected)
+    }
+
+    /// Free a frame from `allocate_frame_2mib`. Its 4 KiB frames all go on
+    /// the free list.
+    pub fn free_frame_2mib(
+        &mut self,
+        frame: PhysFrame<Size2MiB>,
+        expected: MemoryType,
+    ) -> Result<(), FreeError> {
+        self.free_range(frame.start_address().as_u64(), Size2MiB::SIZE, expected)
+    }
+
+    fn free_range(&mut self, start: u64, size: u64, expected: MemoryType) -> Result<(), FreeError> {
         let end = start + size - 1;
 
         // Check if the frame exists in our map and matches the type
@@ -153,6 +288,10 @@ impl PhysicalMemory {
                 MemoryType::Usable,
             )
             .unwrap();
+        let node = numa::topology().node_of_addr(start);
+        for frame in (start..start + size).step_by(Size4KiB::SIZE as usize) {
+            self.push_free(node, frame);
+        }
 
         Ok(())
     }
@@ -171,6 +310,14 @@ impl PhysicalMemory {
     }
 }
 
+/// Start addresses of the `size`-aligned blocks of `size` bytes that fit in
+/// `[start, end]`.
+fn aligned_blocks(start: u64, end: u64, size: u64) -> impl Iterator<Item = u64> {
+    (start.next_multiple_of(size)..)
+        .step_by(size as usize)
+        .take_while(move |&frame| frame + size <= end)
+}
+
 pub struct PhysicalMemoryFrameAllocator<'a> {
     physical_memory: &'a mut PhysicalMemory,
     memory_type: MemoryType,
@@ -0,0 +1,60 @@
+//! TLB shootdown across CPUs.
+//!
+//! Unmapping a page only flushes it from the local TLB. Another CPU that is
+//! running the same address space can keep using the old translation, so
+//! before a frame unmapped from a task that may be running elsewhere is freed
+//! or the range reused, every CPU has to drop it.
+//!
+//! `shootdown` bumps a global generation and sends every other ready CPU a
+//! reschedule IPI, whose handler calls `catch_up`: a full flush, after which
+//! the CPU records the generation it has seen. `shootdown` returns once every
+//! CPU it signalled has caught up.
+//!
+//! Syscalls run with interrupts disabled, so a CPU only takes the IPI once it
+//! has left any lock it is spinning on. Call `shootdown` holding no lock that
+//! another CPU could be waiting for. It catches up itself while it waits, so
+//! two CPUs shooting down at once don't wait on each other.
+
+use crate::interrupt::InterruptVector;
+use crate::memory::cpu_local_data::{cpus_count, get_local, local_apic_id_of, try_get_ready_cpu};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::tlb;
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Flush this CPU's TLB if a shootdown has happened since its last flush.
+pub fn catch_up() {
+    let cpu = get_local();
+    let generation = GENERATION.load(Ordering::Acquire);
+    if cpu.tlb_generation.load(Ordering::Relaxed) < generation {
+        tlb::flush_all();
+        cpu.tlb_generation.store(generation, Ordering::Release);
+    }
+}
+
+/// Flush every CPU's TLB and wait until they have all done it.
+pub fn shootdown() {
+    let generation = GENERATION.fetch_add(1, Ordering::AcqRel) + 1;
+    let local_id = get_local().kernel_id;
+
+    // A CPU that becomes ready after this has never run the old mappings.
+    let targets: Vec<u32> = (0..cpus_count() as u32)
+        .filter(|&id| id != local_id && try_get_ready_cpu(id).is_some())
+        .collect();
+    for &id in &targets {
+        crate::apic::send_fixed_ipi(local_apic_id_of(id), u8::from(InterruptVector::Reschedule));
+    }
+
+    catch_up();
+    for &id in &targets {
+        // A CPU that crashes meanwhile stops being ready and isn't waited for.
+        while let Some(cpu) = try_get_ready_cpu(id) {
+            if cpu.tlb_generation.load(Ordering::Acquire) >= generation {
+                break;
+            }
+            catch_up();
+            core::hint::spin_loop();
+        }
+    }
+}
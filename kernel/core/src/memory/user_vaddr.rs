@@ -6,18 +6,31 @@ const PAGE_SIZE: u64 = 4096;
 
 /// Find a gap in the user vaddr set large enough for `n_pages` contiguous 4 KiB pages,
 /// insert the range, and return the start virtual address.
+///
+/// Returns `None` when no gap is large enough (including requests bigger than
+/// the whole user half); the set is left untouched in that case.
 pub fn allocate_user_pages(
     set: &mut NoditSet<u64, Interval<u64>>,
     n_pages: u64,
 ) -> Option<u64> {
-    let total_bytes = n_pages * PAGE_SIZE;
+    allocate_user_pages_aligned(set, n_pages, PAGE_SIZE)
+}
+
+/// Like `allocate_user_pages`, but the start address is a multiple of `align`
+/// (a power of two, at least one page), e.g. so the range can hold 2 MiB pages.
+pub fn allocate_user_pages_aligned(
+    set: &mut NoditSet<u64, Interval<u64>>,
+    n_pages: u64,
+    align: u64,
+) -> Option<u64> {
+    let total_bytes = n_pages.checked_mul(PAGE_SIZE).filter(|&b| b > 0)?;
     let range = ii(USER_MIN, USER_MAX);
 
     let interval = set
         .gaps_trimmed(&range)
         .find_map(|gap| {
-            let aligned_start = gap.start().next_multiple_of(PAGE_SIZE);
-            let end = aligned_start + total_bytes - 1;
+            let aligned_start = gap.start().checked_next_multiple_of(align)?;
+            let end = aligned_start.checked_add(total_bytes - 1)?;
             let interval = ii(aligned_start, end);
             gap.contains_interval(&interval).then_some(interval)
         })?;
@@ -27,6 +40,34 @@ pub fn allocate_user_pages(
     Some(*interval.start())
 }
 
+/// Reserve exactly `n_pages` pages starting at `addr`, if that range is entirely free.
+/// Used to grow an existing allocation in place. Returns `true` on success.
+pub fn reserve_user_pages_at(
+    set: &mut NoditSet<u64, Interval<u64>>,
+    addr: u64,
+    n_pages: u64,
+) -> bool {
+    if n_pages == 0 || addr < USER_MIN || addr % PAGE_SIZE != 0 {
+        return false;
+    }
+    let end = match addr.checked_add(n_pages * PAGE_SIZE - 1) {
+        Some(e) if e <= USER_MAX => e,
+        _ => return false,
+    };
+    let interval = ii(addr, end);
+
+    let free = set
+        .gaps_trimmed(&interval)
+        .next()
+        .is_some_and(|gap| *gap.start() == addr && *gap.end() == end);
+    if !free {
+        return false;
+    }
+
+    set.insert_merge_touching(interval).expect("no overlap");
+    true
+}
+
 /// Verify that the range `[addr, addr+size)` is fully contained within the user vaddr set,
 /// and remove it. Returns `true` on success.
 pub fn free_user_pages(
@@ -34,7 +75,10 @@ pub fn free_user_pages(
     addr: u64,
     size: u64,
 ) -> bool {
-    let end = addr + size - 1;
+    let end = match size.checked_sub(1).and_then(|s| addr.checked_add(s)) {
+        Some(e) => e,
+        None => return false,
+    };
     let interval = ii(addr, end);
 
     // Check that the range is fully covered by existing allocations
@@ -58,3 +102,14 @@ pub fn is_user_vaddr_valid_range(
     let interval = ii(start.as_u64(), end.as_u64() - 1);
     set.iter().any(|existing| existing.contains_interval(&interval))
 }
+
+/// Whether `[addr, addr + size)` lies inside one reservation in `set`.
+/// Unlike `is_user_vaddr_valid_range` this takes plain integers, so a range
+/// ending exactly at the top of the lower half can be checked.
+pub fn is_range_reserved(set: &NoditSet<u64, Interval<u64>>, addr: u64, size: u64) -> bool {
+    let Some(last) = size.checked_sub(1).and_then(|s| addr.checked_add(s)) else {
+        return false;
+    };
+    let interval = ii(addr, last);
+    set.iter().any(|existing| existing.contains_interval(&interval))
+}
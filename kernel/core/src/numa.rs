@@ -0,0 +1,173 @@
+//! NUMA topology from the ACPI SRAT (System Resource Affinity Table).
+//!
+//! The SRAT assigns each memory range and each local APIC a proximity domain,
+//! which we use directly as the node id. Machines without an SRAT (including
+//! default single-socket QEMU) are treated as one node, 0, covering everything.
+
+use crate::memory::cpu_local_data::try_get_local;
+use acpi::sdt::{SdtHeader, Signature};
+use acpi::{AcpiTable, AcpiTables};
+use alloc::vec::Vec;
+use spin::Once;
+
+pub type NodeId = u32;
+
+/// A physical memory range `[base, base + length)` on `node`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAffinity {
+    pub base: u64,
+    pub length: u64,
+    pub node: NodeId,
+}
+
+/// A local APIC (or x2APIC) id on `node`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuAffinity {
+    pub apic_id: u32,
+    pub node: NodeId,
+}
+
+#[derive(Debug, Default)]
+pub struct Topology {
+    pub memory: Vec<MemoryAffinity>,
+    pub cpus: Vec<CpuAffinity>,
+}
+
+impl Topology {
+    /// Node of the physical address `addr`. Addresses not covered by any SRAT
+    /// range belong to node 0.
+    pub fn node_of_addr(&self, addr: u64) -> NodeId {
+        self.memory
+            .iter()
+            .find(|m| addr >= m.base && addr - m.base < m.length)
+            .map_or(0, |m| m.node)
+    }
+
+    /// Node of the CPU with local APIC id `apic_id`, or 0 if it isn't listed.
+    pub fn node_of_apic(&self, apic_id: u32) -> NodeId {
+        self.cpus
+            .iter()
+            .find(|c| c.apic_id == apic_id)
+            .map_or(0, |c| c.node)
+    }
+
+    /// Number of distinct nodes. Always at least 1.
+    pub fn node_count(&self) -> usize {
+        let mut nodes: Vec<NodeId> = self
+            .memory
+            .iter()
+            .map(|m| m.node)
+            .chain(self.cpus.iter().map(|c| c.node))
+            .collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+        nodes.len().max(1)
+    }
+
+    /// The parts of the inclusive range `[start, end]` that lie on `node`, as
+    /// inclusive ranges. Without SRAT memory entries every address is on node 0.
+    pub fn ranges_on_node(
+        &self,
+        node: NodeId,
+        start: u64,
+        end: u64,
+    ) -> impl Iterator<Item = (u64, u64)> + '_ {
+        let whole = (self.memory.is_empty() && node == 0).then_some((start, end));
+        let parts = self
+            .memory
+            .iter()
+            .filter(move |m| m.node == node && m.length > 0)
+            .filter_map(move |m| {
+                let lo = start.max(m.base);
+                let hi = end.min(m.base.saturating_add(m.length - 1));
+                (lo <= hi).then_some((lo, hi))
+            });
+        whole.into_iter().chain(parts)
+    }
+}
+
+/// Parse the entries of a raw SRAT, header included. Disabled entries and
+/// unknown entry types are skipped; a truncated entry ends parsing.
+pub fn parse_srat(table: &[u8]) -> Topology {
+    // 36-byte SDT header, then 4 reserved bytes and 8 more reserved bytes.
+    const ENTRIES_OFFSET: usize = 48;
+
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+    let u64_at = |b: &[u8], i: usize| u64::from_le_bytes(b[i..i + 8].try_into().unwrap());
+
+    let mut topology = Topology::default();
+    let mut offset = ENTRIES_OFFSET;
+    while let Some(&[entry_type, len]) = table.get(offset..offset + 2) {
+        let len = len as usize;
+        let Some(entry) = table.get(offset..offset + len).filter(|_| len >= 2) else {
+            break;
+        };
+        match (entry_type, len) {
+            // Processor Local APIC/SAPIC Affinity
+            (0, 16) if u32_at(entry, 4) & 1 != 0 => {
+                let node = u32::from_le_bytes([entry[2], entry[9], entry[10], entry[11]]);
+                topology.cpus.push(CpuAffinity { apic_id: entry[3] as u32, node });
+            }
+            // Memory Affinity
+            (1, 40) if u32_at(entry, 28) & 1 != 0 => {
+                topology.memory.push(MemoryAffinity {
+                    base: u64_at(entry, 8),
+                    length: u64_at(entry, 16),
+                    node: u32_at(entry, 2),
+                });
+            }
+            // Processor Local x2APIC Affinity
+            (2, 24) if u32_at(entry, 12) & 1 != 0 => {
+                topology.cpus.push(CpuAffinity { apic_id: u32_at(entry, 8), node: u32_at(entry, 4) });
+            }
+            _ => {}
+        }
+        offset += len;
+    }
+    topology
+}
+
+#[repr(C, packed)]
+struct Srat {
+    header: SdtHeader,
+    _reserved: [u8; 12],
+}
+
+unsafe impl AcpiTable for Srat {
+    const SIGNATURE: Signature = Signature::SRAT;
+
+    fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+}
+
+pub static TOPOLOGY: Once<Topology> = Once::new();
+
+pub fn init(acpi_tables: &AcpiTables<impl acpi::Handler>) {
+    let topology = match acpi_tables.find_table::<Srat>() {
+        Some(srat) => {
+            let len = srat.header.length as usize;
+            let bytes = unsafe { core::slice::from_raw_parts(srat.virtual_start.as_ptr() as *const u8, len) };
+            parse_srat(bytes)
+        }
+        None => Topology::default(),
+    };
+    log::info!(
+        "NUMA: {} node(s), {} memory range(s), {} CPU(s) in SRAT",
+        topology.node_count(),
+        topology.memory.len(),
+        topology.cpus.len(),
+    );
+    TOPOLOGY.call_once(|| topology);
+}
+
+/// The topology, or an empty single-node one before `init` has run.
+pub fn topology() -> &'static Topology {
+    static EMPTY: Topology = Topology { memory: Vec::new(), cpus: Vec::new() };
+    TOPOLOGY.get().unwrap_or(&EMPTY)
+}
+
+/// Node of the CPU this runs on; 0 before CPU-local data exists.
+pub fn current_node() -> NodeId {
+    try_get_local().map_or(0, |cpu| topology().node_of_apic(cpu.local_apic_id))
+}
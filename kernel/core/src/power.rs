@@ -0,0 +1,273 @@
+//! Powering the machine off and resetting it.
+//!
+//! ACPI S5 ("soft off") is entered by writing `SLP_TYPx | SLP_EN` to the FADT's
+//! PM1a/PM1b control registers. The `SLP_TYPx` values come from the `\_S5`
+//! package in the DSDT, which we find by scanning the AML rather than running
+//! an interpreter.
+//!
+//! Reboot writes the FADT's `RESET_VALUE` to its `RESET_REG` when the firmware
+//! advertises one, then falls back to the PCI reset control port and finally a
+//! triple fault.
+
+use acpi::sdt::SdtHeader;
+use acpi::AcpiTables;
+use spin::Once;
+
+/// Port of QEMU's `isa-debug-exit` device, as configured by the test runner.
+pub const DEBUG_EXIT_PORT: u16 = 0xf4;
+
+const SLP_EN: u16 = 1 << 13;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_TYP_MASK: u16 = 0b111 << SLP_TYP_SHIFT;
+/// PM1 control bit set once the firmware has handed ACPI over to the OS.
+const SCI_EN: u16 = 1;
+
+/// FADT flag: `RESET_REG` and `RESET_VALUE` are valid.
+const RESET_REG_SUP: u32 = 1 << 10;
+/// PCI reset control register found on most chipsets (PIIX, ICH, ...).
+const RESET_CONTROL_PORT: u16 = 0xcf9;
+
+/// Everything needed to enter S5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct S5 {
+    pub pm1a_cnt: u16,
+    pub pm1b_cnt: Option<u16>,
+    pub slp_typ_a: u16,
+    pub slp_typ_b: u16,
+    /// `(SMI_CMD port, ACPI_ENABLE value)`, if the firmware must be asked to enable ACPI.
+    pub acpi_enable: Option<(u16, u8)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMethod {
+    AcpiS5(S5),
+    DebugExit,
+}
+
+/// The FADT's reset register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetRegister {
+    Io { port: u16, value: u8 },
+    Memory { address: u64, value: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootMethod {
+    AcpiReset(ResetRegister),
+    /// Port 0xCF9, then a triple fault.
+    ResetControl,
+}
+
+static S5_STATE: Once<Option<S5>> = Once::new();
+static RESET_REGISTER: Once<Option<ResetRegister>> = Once::new();
+
+/// Read the FADT, its reset register and the DSDT's `\_S5` object. Without
+/// them, shutdown and reboot use their fallbacks.
+pub fn init(acpi_tables: &AcpiTables<impl acpi::Handler>) {
+    let (s5, reset) = crate::acpi::with_fadt(acpi_tables, |fadt| {
+        (s5_from_fadt(fadt), reset_register_from_fadt(fadt))
+    })
+    .unwrap_or((None, None));
+    match &s5 {
+        Some(s5) => log::info!("ACPI S5: PM1a_CNT={:#x} SLP_TYPa={}", s5.pm1a_cnt, s5.slp_typ_a),
+        None => log::warn!("ACPI S5 unavailable; shutdown will use the debug-exit port"),
+    }
+    match &reset {
+        Some(reg) => log::info!("ACPI reset register: {:x?}", reg),
+        None => log::info!("No ACPI reset register; reboot will use port {:#x}", RESET_CONTROL_PORT),
+    }
+    S5_STATE.call_once(|| s5);
+    RESET_REGISTER.call_once(|| reset);
+}
+
+/// Decode `RESET_REG`/`RESET_VALUE` from a raw FADT. `None` if the table is
+/// too old to have them, the firmware doesn't set `RESET_REG_SUP`, or the
+/// register is in an address space we can't write (e.g. PCI configuration).
+pub fn reset_register_from_fadt(fadt: &[u8]) -> Option<ResetRegister> {
+    const SYSTEM_MEMORY: u8 = 0;
+    const SYSTEM_IO: u8 = 1;
+
+    let flags = u32::from_le_bytes(fadt.get(112..116)?.try_into().ok()?);
+    if flags & RESET_REG_SUP == 0 {
+        return None;
+    }
+    // Generic Address Structure: space id, bit width, bit offset, access size, address.
+    let space = *fadt.get(116)?;
+    let address = u64::from_le_bytes(fadt.get(120..128)?.try_into().ok()?);
+    let value = *fadt.get(128)?;
+    match space {
+        SYSTEM_IO => Some(ResetRegister::Io { port: u16::try_from(address).ok()?, value }),
+        SYSTEM_MEMORY if address != 0 => Some(ResetRegister::Memory { address, value }),
+        _ => None,
+    }
+}
+
+fn s5_from_fadt(fadt: &[u8]) -> Option<S5> {
+    let u32_at = |i: usize| Some(u32::from_le_bytes(fadt.get(i..i + 4)?.try_into().ok()?));
+
+    let pm1a_cnt = u32_at(72)? as u16;
+    if pm1a_cnt == 0 {
+        return None;
+    }
+    let pm1b_cnt = Some(u32_at(76)? as u16).filter(|&p| p != 0);
+    let smi_cmd = u32_at(48)? as u16;
+    let acpi_enable = *fadt.get(52)?;
+
+    // ACPI 2.0+ tables carry a 64-bit X_DSDT that takes precedence when set.
+    let x_dsdt = fadt
+        .get(140..148)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .filter(|&a| a != 0);
+    let dsdt = x_dsdt.unwrap_or(u32_at(40)? as u64) as usize;
+    if dsdt == 0 {
+        return None;
+    }
+
+    let header = crate::acpi::map_bytes(dsdt, size_of::<SdtHeader>());
+    let dsdt_len = unsafe { (*(header.virtual_start.as_ptr() as *const SdtHeader)).length } as usize;
+    drop(header);
+    let table = crate::acpi::map_bytes(dsdt, dsdt_len);
+    let aml = unsafe { core::slice::from_raw_parts(table.virtual_start.as_ptr(), dsdt_len) };
+    let (slp_typ_a, slp_typ_b) = find_s5(aml.get(size_of::<SdtHeader>()..)?)?;
+
+    Some(S5 {
+        pm1a_cnt,
+        pm1b_cnt,
+        slp_typ_a: slp_typ_a as u16,
+        slp_typ_b: slp_typ_b as u16,
+        acpi_enable: (smi_cmd != 0 && acpi_enable != 0).then_some((smi_cmd, acpi_enable)),
+    })
+}
+
+/// Find `Name(\_S5, Package() { SLP_TYPa, SLP_TYPb, ... })` in an AML byte
+/// stream and return the two sleep types.
+pub fn find_s5(aml: &[u8]) -> Option<(u8, u8)> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+
+    let pos = aml.windows(4).enumerate().find_map(|(i, w)| {
+        let named = (i >= 1 && aml[i - 1] == NAME_OP)
+            || (i >= 2 && aml[i - 2] == NAME_OP && aml[i - 1] == b'\\');
+        (w == b"_S5_" && named && aml.get(i + 4) == Some(&PACKAGE_OP)).then_some(i + 5)
+    })?;
+
+    // PkgLength: the top two bits of the lead byte count the extra bytes.
+    let pkg_len_bytes = (*aml.get(pos)? >> 6) as usize + 1;
+    let mut rest = aml.get(pos + pkg_len_bytes + 1..)?; // skip PkgLength and NumElements
+    let a = aml_byte_integer(&mut rest)?;
+    let b = aml_byte_integer(&mut rest)?;
+    Some((a, b))
+}
+
+/// Decode a small integer constant (`ZeroOp`, `OneOp` or `BytePrefix n`) and
+/// advance past it.
+fn aml_byte_integer(aml: &mut &[u8]) -> Option<u8> {
+    let (value, len) = match *aml.first()? {
+        0x00 => (0, 1),
+        0x01 => (1, 1),
+        0x0A => (*aml.get(1)?, 2),
+        _ => return None,
+    };
+    *aml = &aml[len..];
+    Some(value)
+}
+
+/// How `shutdown` will power off: ACPI S5 if the FADT and `\_S5` were found.
+pub fn shutdown_method() -> ShutdownMethod {
+    match S5_STATE.get() {
+        Some(Some(s5)) => ShutdownMethod::AcpiS5(*s5),
+        _ => ShutdownMethod::DebugExit,
+    }
+}
+
+/// Power off the machine.
+///
+/// `exit_code` is written to the debug-exit port first: under QEMU with
+/// `isa-debug-exit` that ends the VM with the code the test runner expects, and
+/// on real hardware nothing listens there. If the machine is still running
+/// afterwards, it enters ACPI S5.
+pub fn shutdown(exit_code: u8) -> ! {
+    unsafe { x86::io::outb(DEBUG_EXIT_PORT, exit_code) };
+
+    if let ShutdownMethod::AcpiS5(s5) = shutdown_method() {
+        unsafe { enter_s5(&s5) };
+        log::error!("ACPI S5 did not power off the machine");
+    }
+    crate::hlt_loop();
+}
+
+/// How `reboot` will try first: the ACPI reset register if the FADT has one.
+pub fn reboot_method() -> RebootMethod {
+    match RESET_REGISTER.get() {
+        Some(Some(reg)) => RebootMethod::AcpiReset(*reg),
+        _ => RebootMethod::ResetControl,
+    }
+}
+
+/// Reset the machine. Each method is tried in turn if the previous one
+/// returned: ACPI reset register, port 0xCF9, then a triple fault.
+pub fn reboot() -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    if let RebootMethod::AcpiReset(reg) = reboot_method() {
+        match reg {
+            ResetRegister::Io { port, value } => unsafe { x86::io::outb(port, value) },
+            ResetRegister::Memory { address, value } => {
+                let mapping = crate::acpi::map_bytes(address as usize, 1);
+                unsafe { core::ptr::write_volatile(mapping.virtual_start.as_ptr(), value) };
+            }
+        }
+        spin_briefly();
+    }
+
+    // Bit 1 selects a hard reset, bit 2 starts it on the 0 -> 1 transition.
+    unsafe {
+        x86::io::outb(RESET_CONTROL_PORT, 0x02);
+        x86::io::outb(RESET_CONTROL_PORT, 0x06);
+    }
+    spin_briefly();
+
+    // With an empty IDT the breakpoint can't be delivered, nor can the
+    // resulting double fault: the CPU triple faults and resets.
+    unsafe {
+        let empty = x86_64::structures::DescriptorTablePointer {
+            limit: 0,
+            base: x86_64::VirtAddr::zero(),
+        };
+        x86_64::instructions::tables::lidt(&empty);
+    }
+    x86_64::instructions::interrupts::int3();
+    crate::hlt_loop();
+}
+
+/// Give a reset request time to take effect before trying the next one.
+fn spin_briefly() {
+    for _ in 0..10_000_000 {
+        core::hint::spin_loop();
+    }
+}
+
+unsafe fn enter_s5(s5: &S5) {
+    use x86::io::{inw, outb, outw};
+
+    unsafe {
+        if let Some((smi_cmd, enable)) = s5.acpi_enable
+            && inw(s5.pm1a_cnt) & SCI_EN == 0
+        {
+            outb(smi_cmd, enable);
+            for _ in 0..1_000_000 {
+                if inw(s5.pm1a_cnt) & SCI_EN != 0 {
+                    break;
+                }
+                core::hint::spin_loop();
+            }
+        }
+
+        let pm1a = inw(s5.pm1a_cnt) & !SLP_TYP_MASK;
+        outw(s5.pm1a_cnt, pm1a | (s5.slp_typ_a << SLP_TYP_SHIFT) | SLP_EN);
+        if let Some(pm1b_cnt) = s5.pm1b_cnt {
+            let pm1b = inw(pm1b_cnt) & !SLP_TYP_MASK;
+            outw(pm1b_cnt, pm1b | (s5.slp_typ_b << SLP_TYP_SHIFT) | SLP_EN);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Pseudo-random bytes for the `Random` syscall.
+//!
+//! A SplitMix64 generator: a shared counter steps by a fixed odd constant,
+//! and each step is run through a mixing function. Every call takes its own
+//! steps off the counter, and keys them with the caller's task ID and the TSC
+//! at the time of the call, so two tasks never see the same stream even if
+//! they ask at once. Good enough for demos and tests; not for secrets.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The SplitMix64 step: 2^64 divided by the golden ratio, made odd.
+const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// SplitMix64's output function: every input bit affects every output bit.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Fill `buf` with pseudo-random bytes. `salt` (the caller's task ID) is
+/// mixed into the key along with the TSC.
+pub fn fill(buf: &mut [u8], salt: u64) {
+    let key = mix(salt.wrapping_mul(GAMMA) ^ crate::time::tsc::value());
+    let steps = buf.len().div_ceil(8) as u64;
+    let mut state = COUNTER.fetch_add(steps.wrapping_mul(GAMMA), Ordering::Relaxed);
+    for chunk in buf.chunks_mut(8) {
+        state = state.wrapping_add(GAMMA);
+        let bytes = mix(state ^ key).to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::memory::cpu_local_data::{CpuLocalData, IN_SYSCALL_HANDLER_OFFSET, CURRENT_CONTEXT_PTR_OFFSET, CURRENT_TASK_KERNEL_STACK_TOP_OFFSET, get_local};
-use crate::syscall_handlers::{sys_channel_close, sys_channel_create, sys_channel_recv, sys_channel_send, sys_create_shared_buf, sys_debug_log, sys_destroy_shared_buf, sys_exit, sys_get_bounding_box, sys_get_display_info, sys_get_module, sys_lookup_service, sys_map_shared_buf, sys_mmap, sys_munmap, sys_read_key, sys_read_mouse, sys_register_service, sys_shutdown, sys_spawn, sys_transfer_display, sys_waitpid, sys_yield};
+use crate::syscall_handlers::{sys_batch, sys_broadcast_create, sys_channel_close, sys_channel_create, sys_channel_dup, sys_channel_poll, sys_channel_recv, sys_channel_recv_with_endpoint, sys_channel_send, sys_channel_send_with_endpoint, sys_channel_stats, sys_channel_subscribe, sys_create_shared_buf, sys_debug_log, sys_debug_log_str, sys_destroy_shared_buf, sys_exit, sys_get_bounding_box, sys_get_cycles, sys_get_display_info, sys_get_module, sys_get_module_chunk, sys_heap_stats, sys_get_task_id, sys_get_tsc_hz, sys_get_wallclock, sys_grant_shared_buf, sys_inject_input, sys_input_record, sys_is_display_owner, sys_kill, sys_list_tasks, sys_lookup_service, sys_map_shared_buf, sys_mincore, sys_mmap, sys_munmap, sys_net_get_mac, sys_net_recv, sys_net_send, sys_null, sys_populate, sys_random, sys_read_key, sys_read_mouse, sys_reboot, sys_register_service, sys_resize_shared_buf, sys_ring_enter, sys_serial_read, sys_serial_write, sys_set_priority, sys_set_syscall_trace, sys_set_task_name, sys_shutdown, sys_sleep, sys_spawn, sys_transfer_display, sys_waitpid, sys_yield};
 use crate::task::task::{
     CTX_RAX, CTX_RBP, CTX_RBX, CTX_RCX, CTX_RDI, CTX_RDX, CTX_RSI,
     CTX_R8, CTX_R9, CTX_R10, CTX_R11, CTX_R12, CTX_R13, CTX_R14, CTX_R15,
@@ -7,13 +7,22 @@ use crate::task::task::{
 };
 use core::arch::{asm, naked_asm};
 use core::mem::offset_of;
-use core::sync::atomic::Ordering;
-use kernel_api_types::SysCallNumber;
+use core::sync::atomic::{AtomicBool, Ordering};
+use kernel_api_types::{SysCallNumber, SysError};
 use x86_64::VirtAddr;
 use x86_64::registers::control::{Efer, EferFlags};
 use x86_64::registers::model_specific::{LStar, SFMask, Star};
 use x86_64::registers::rflags::RFlags;
 
+/// Set to log every syscall to the serial console before tracing is switched
+/// on from userspace (e.g. to see what init does during boot).
+const TRACE_SYSCALLS_FROM_BOOT: bool = false;
+
+/// When set, each syscall's number, caller and first three arguments are
+/// logged before dispatch and its return value after. Flipped at runtime with
+/// `SysCallNumber::SetSyscallTrace`.
+pub static SYSCALL_TRACE: AtomicBool = AtomicBool::new(TRACE_SYSCALLS_FROM_BOOT);
+
 #[unsafe(naked)]
 unsafe extern "sysv64" fn raw_syscall_handler() -> ! {
     naked_asm!(
@@ -132,14 +141,22 @@ unsafe extern "sysv64" fn syscall_handler(
     return_instruction_pointer: u64,
     return_stack_pointer: u64,
 ) -> ! {
+    let inputs = [input1, input2, input3, input4, input5, input6];
+    let trace = SYSCALL_TRACE.load(Ordering::Relaxed);
+    if trace {
+        trace_entry(input0, &inputs);
+    }
+
     // Handle Exit specially since it diverges (never returns to sysretq)
     // input0 = rdi = syscall number; input1 = rsi = exit code
     if input0 == SysCallNumber::Exit as u64 {
         sys_exit(input1); // -> !, never returns
     }
 
-    let inputs = [input1, input2, input3, input4, input5, input6];
     let ret = dispatch_syscall(input0, &inputs);
+    if trace {
+        trace_exit(input0, ret);
+    }
 
     // Clear in_syscall flag before returning to user mode
     get_local().in_syscall_handler.store(0, Ordering::Relaxed);
@@ -169,16 +186,37 @@ unsafe extern "sysv64" fn syscall_handler(
     }
 }
 
+#[cold]
+fn trace_entry(syscall_number: u64, args: &[u64; 6]) {
+    let task_id = get_local().run_queue.get().unwrap().lock()
+        .current_task.as_ref().map(|t| t.id.to_u64());
+    log::info!(
+        "SYSCALL[{:?}] {:?}({:#x}, {:#x}, {:#x})",
+        task_id,
+        SysCallNumber::from_u64(syscall_number),
+        args[0], args[1], args[2],
+    );
+}
+
+#[cold]
+fn trace_exit(syscall_number: u64, ret: u64) {
+    match SysError::from_ret(ret) {
+        Ok(v) => log::info!("SYSCALL {:?} -> {:#x}", SysCallNumber::from_u64(syscall_number), v),
+        Err(e) => log::info!("SYSCALL {:?} -> {:?}", SysCallNumber::from_u64(syscall_number), e),
+    }
+}
+
 type SyscallFn = fn(u64, u64, u64, u64, u64, u64) -> u64;
 static SYSCALL_TABLE: spin::Once<[Option<SyscallFn>; 256]> = spin::Once::new();
 
-fn dispatch_syscall(syscall_number: u64, args: &[u64; 6]) -> u64 {
+pub(crate) fn dispatch_syscall(syscall_number: u64, args: &[u64; 6]) -> u64 {
     let table = SYSCALL_TABLE.get().expect("syscall table not initialized");
-    if let Some(f) = table[syscall_number as usize] {
+    let handler = usize::try_from(syscall_number).ok().and_then(|n| table.get(n).copied().flatten());
+    if let Some(f) = handler {
         f(args[0], args[1], args[2], args[3], args[4], args[5])
     } else {
         log::error!("SYSCALL: unknown syscall number {}", syscall_number);
-        0xFFFF_FFFF_FFFF_FFFF
+        SysError::NoSys as u64
     }
 }
 
@@ -214,6 +252,8 @@ pub fn init() {
         table[SysCallNumber::ChannelCreate as usize] = Some(sys_channel_create);
         table[SysCallNumber::ChannelSend as usize] = Some(sys_channel_send);
         table[SysCallNumber::ChannelRecv as usize] = Some(sys_channel_recv);
+        table[SysCallNumber::ChannelSendWithEndpoint as usize] = Some(sys_channel_send_with_endpoint);
+        table[SysCallNumber::ChannelRecvWithEndpoint as usize] = Some(sys_channel_recv_with_endpoint);
         table[SysCallNumber::ChannelClose as usize] = Some(sys_channel_close);
         table[SysCallNumber::TransferDisplay as usize] = Some(sys_transfer_display);
         table[SysCallNumber::GetModule as usize] = Some(sys_get_module);
@@ -226,6 +266,41 @@ pub fn init() {
         table[SysCallNumber::CreateSharedBuf as usize] = Some(sys_create_shared_buf);
         table[SysCallNumber::MapSharedBuf as usize] = Some(sys_map_shared_buf);
         table[SysCallNumber::DestroySharedBuf as usize] = Some(sys_destroy_shared_buf);
+        table[SysCallNumber::Null as usize] = Some(sys_null);
+        table[SysCallNumber::ResizeSharedBuf as usize] = Some(sys_resize_shared_buf);
+        table[SysCallNumber::Batch as usize] = Some(sys_batch);
+        table[SysCallNumber::RingEnter as usize] = Some(sys_ring_enter);
+        table[SysCallNumber::GrantSharedBuf as usize] = Some(sys_grant_shared_buf);
+        table[SysCallNumber::GetTaskId as usize] = Some(sys_get_task_id);
+        table[SysCallNumber::SetSyscallTrace as usize] = Some(sys_set_syscall_trace);
+        table[SysCallNumber::DebugLogStr as usize] = Some(sys_debug_log_str);
+        table[SysCallNumber::Populate as usize] = Some(sys_populate);
+        table[SysCallNumber::Mincore as usize] = Some(sys_mincore);
+        table[SysCallNumber::ChannelDup as usize] = Some(sys_channel_dup);
+        table[SysCallNumber::Reboot as usize] = Some(sys_reboot);
+        table[SysCallNumber::GetCycles as usize] = Some(sys_get_cycles);
+        table[SysCallNumber::GetTscHz as usize] = Some(sys_get_tsc_hz);
+        table[SysCallNumber::GetWallClock as usize] = Some(sys_get_wallclock);
+        table[SysCallNumber::NetSend as usize] = Some(sys_net_send);
+        table[SysCallNumber::NetRecv as usize] = Some(sys_net_recv);
+        table[SysCallNumber::NetGetMac as usize] = Some(sys_net_get_mac);
+        table[SysCallNumber::Sleep as usize] = Some(sys_sleep);
+        table[SysCallNumber::SerialWrite as usize] = Some(sys_serial_write);
+        table[SysCallNumber::SerialRead as usize] = Some(sys_serial_read);
+        table[SysCallNumber::InputRecord as usize] = Some(sys_input_record);
+        table[SysCallNumber::InjectInput as usize] = Some(sys_inject_input);
+        table[SysCallNumber::SetPriority as usize] = Some(sys_set_priority);
+        table[SysCallNumber::ChannelPoll as usize] = Some(sys_channel_poll);
+        table[SysCallNumber::ChannelStats as usize] = Some(sys_channel_stats);
+        table[SysCallNumber::SetTaskName as usize] = Some(sys_set_task_name);
+        table[SysCallNumber::ListTasks as usize] = Some(sys_list_tasks);
+        table[SysCallNumber::Kill as usize] = Some(sys_kill);
+        table[SysCallNumber::BroadcastCreate as usize] = Some(sys_broadcast_create);
+        table[SysCallNumber::ChannelSubscribe as usize] = Some(sys_channel_subscribe);
+        table[SysCallNumber::GetModuleChunk as usize] = Some(sys_get_module_chunk);
+        table[SysCallNumber::HeapStats as usize] = Some(sys_heap_stats);
+        table[SysCallNumber::Random as usize] = Some(sys_random);
+        table[SysCallNumber::IsDisplayOwner as usize] = Some(sys_is_display_owner);
         table
     });
 }
@@ -1,5 +1,5 @@
 use alloc::collections::BTreeMap;
-use kernel_api_types::MAX_SERVICE_NAME_LEN;
+use kernel_api_types::{SysError, MAX_SERVICE_NAME_LEN};
 use spin::Mutex;
 use crate::task::task::TaskId;
 
@@ -7,23 +7,32 @@ pub type ServiceName = [u8; MAX_SERVICE_NAME_LEN];
 
 struct ServiceEntry {
     send_endpoint_id: u64,
-    #[allow(dead_code)]
-    owner_task_id: TaskId,
+    /// `None` for services the kernel registers itself, which live forever.
+    owner_task_id: Option<TaskId>,
 }
 
 static SERVICE_REGISTRY: Mutex<BTreeMap<ServiceName, ServiceEntry>> =
     Mutex::new(BTreeMap::new());
 
 /// Register a send endpoint under the given name.
-/// Returns `Err(SVC_ERR_ALREADY_REGISTERED)` if the name is already taken.
-pub fn register(name_bytes: &[u8], send_ep: u64, owner: TaskId) -> Result<(), u64> {
+/// Returns `Err(SysError::AlreadyExists)` if the name is already taken.
+pub fn register(name_bytes: &[u8], send_ep: u64, owner: TaskId) -> Result<(), SysError> {
+    insert(name_bytes, send_ep, Some(owner))
+}
+
+/// Register an endpoint the kernel owns. It stays registered for good.
+pub fn register_kernel(name_bytes: &[u8], send_ep: u64) -> Result<(), SysError> {
+    insert(name_bytes, send_ep, None)
+}
+
+fn insert(name_bytes: &[u8], send_ep: u64, owner: Option<TaskId>) -> Result<(), SysError> {
     let mut name: ServiceName = [0u8; MAX_SERVICE_NAME_LEN];
     let copy_len = name_bytes.len().min(MAX_SERVICE_NAME_LEN);
     name[..copy_len].copy_from_slice(&name_bytes[..copy_len]);
 
     let mut registry = SERVICE_REGISTRY.lock();
     if registry.contains_key(&name) {
-        return Err(kernel_api_types::SVC_ERR_ALREADY_REGISTERED);
+        return Err(SysError::AlreadyExists);
     }
     registry.insert(name, ServiceEntry { send_endpoint_id: send_ep, owner_task_id: owner });
     Ok(())
@@ -41,5 +50,5 @@ pub fn lookup(name_bytes: &[u8]) -> Option<u64> {
 /// Remove all services registered by the given task (called on task exit).
 pub fn unregister_all_for_task(owner: TaskId) {
     let mut registry = SERVICE_REGISTRY.lock();
-    registry.retain(|_, entry| entry.owner_task_id != owner);
+    registry.retain(|_, entry| entry.owner_task_id != Some(owner));
 }
@@ -1,6 +1,9 @@
 use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, Ordering};
+use kernel_api_types::SysError;
+use nodit::interval::ii;
 use spin::Mutex;
 
 use x86_64::structures::paging::{
@@ -10,188 +13,537 @@ use x86_64::{PhysAddr, VirtAddr};
 
 use crate::memory::MEMORY;
 use crate::memory::hhdm_offset::hhdm_offset;
-use crate::memory::physical_memory::{MemoryType, OffsetMappedPhysAddr};
+use crate::memory::physical_memory::{MemoryType, OffsetMappedPhysAddr, PhysicalMemory};
 use crate::memory::user_vaddr;
-use crate::task::task::Task;
+use crate::task::task::{Task, TaskId, TaskInner};
 
 pub type SharedBufId = u64;
 
+/// One task's view of a shared buffer.
+#[derive(Clone)]
+struct Mapping {
+    task_id: TaskId,
+    /// The mapper, for editing its page table later. Once it can't be
+    /// upgraded the task's address space is gone, and the mapping with it.
+    task: Weak<Task>,
+    vaddr: u64,
+    /// Page flags this task mapped the buffer with; reused when a resize remaps it.
+    flags: PageTableFlags,
+}
+
 struct SharedBuf {
+    /// Creator; the only task that may grant access or destroy the buffer.
+    owner: TaskId,
+    /// Tasks other than the owner that may map the buffer.
+    granted: Vec<TaskId>,
     frames: Vec<PhysFrame<Size4KiB>>,
+    /// Every address space the frames are currently mapped into (creator included).
+    /// Kept so a resize can patch all of them.
+    mappings: Vec<Mapping>,
+    /// Old ranges of mappers a resize relocated, still reserved and guarded.
+    stale: Vec<StaleRange>,
+}
+
+/// A range a mapper lost when a resize moved its mapping. The task may still
+/// hold pointers into it, so it stays reserved (and faults if touched) until
+/// the task asks for the new address with `map_shared_buf` or unmaps it.
+#[derive(Clone, Copy)]
+struct StaleRange {
+    task_id: TaskId,
+    vaddr: u64,
+    pages: u64,
 }
 
 static NEXT_BUF_ID: AtomicU64 = AtomicU64::new(1);
+// Lock order: SHARED_BUF_REGISTRY → Task::inner → physical_memory.
 static SHARED_BUF_REGISTRY: Mutex<BTreeMap<SharedBufId, SharedBuf>> =
     Mutex::new(BTreeMap::new());
 
+const SHARED_PAGE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+const SHARED_PAGE_FLAGS_READONLY: PageTableFlags =
+    SHARED_PAGE_FLAGS.difference(PageTableFlags::WRITABLE);
+
 /// Allocate `n_pages` physical pages tagged `SharedBuffer`, map them into `task`'s
 /// address space, and register them in the global registry.
 ///
-/// Returns `(id, start_vaddr)` or `None` on allocation failure.
-pub fn create_shared_buf(task: &Task, n_pages: u64) -> Option<(SharedBufId, u64)> {
+/// Returns `(id, start_vaddr)` or `SysError::OutOfMemory`.
+pub fn create_shared_buf(task: &Arc<Task>, n_pages: u64) -> Result<(SharedBufId, u64), SysError> {
     let mut inner = task.inner.lock();
 
-    let start_vaddr = user_vaddr::allocate_user_pages(&mut inner.user_vaddr_set, n_pages)?;
+    let start_vaddr = user_vaddr::allocate_user_pages(&mut inner.user_vaddr_set, n_pages)
+        .ok_or(SysError::OutOfMemory)?;
 
-    let page_flags = PageTableFlags::PRESENT
-        | PageTableFlags::WRITABLE
-        | PageTableFlags::USER_ACCESSIBLE
-        | PageTableFlags::NO_EXECUTE;
-
-    let hhdm = hhdm_offset();
-    let user_l4_frame =
-        PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(task.cr3));
-    let l4_virt =
-        VirtAddr::new(hhdm.as_u64() + user_l4_frame.start_address().as_u64());
-    let l4_table = unsafe { &mut *l4_virt.as_mut_ptr::<PageTable>() };
-    let mut mapper =
-        unsafe { OffsetPageTable::new(l4_table, VirtAddr::new(hhdm.as_u64())) };
+    let mut mapper = unsafe { user_mapper(task.cr3) };
 
     let memory = MEMORY.get().unwrap();
     let mut phys_mem = memory.physical_memory.lock();
 
-    let mut frames: Vec<PhysFrame<Size4KiB>> = Vec::new();
+    let frames = match allocate_zeroed_frames(&mut phys_mem, n_pages) {
+        Some(f) => f,
+        None => {
+            user_vaddr::free_user_pages(
+                &mut inner.user_vaddr_set,
+                start_vaddr,
+                n_pages * Size4KiB::SIZE,
+            );
+            return Err(SysError::OutOfMemory);
+        }
+    };
 
-    for i in 0..n_pages {
-        let vaddr = VirtAddr::new(start_vaddr + i * Size4KiB::SIZE);
-        let page: Page<Size4KiB> = Page::containing_address(vaddr);
+    if !map_frames(&mut mapper, &mut phys_mem, start_vaddr, &frames, SHARED_PAGE_FLAGS) {
+        for &frame in &frames {
+            let _ = phys_mem.free_frame(frame, MemoryType::SharedBuffer);
+        }
+        user_vaddr::free_user_pages(
+            &mut inner.user_vaddr_set,
+            start_vaddr,
+            n_pages * Size4KiB::SIZE,
+        );
+        return Err(SysError::OutOfMemory);
+    }
 
-        let frame = match phys_mem.allocate_frame_with_type(MemoryType::SharedBuffer) {
-            Some(f) => f,
-            None => {
-                rollback(&mut mapper, &mut phys_mem, start_vaddr, i, true);
-                user_vaddr::free_user_pages(
-                    &mut inner.user_vaddr_set,
-                    start_vaddr,
-                    n_pages * Size4KiB::SIZE,
-                );
-                return None;
-            }
-        };
+    drop(phys_mem);
+    drop(inner);
 
-        // Zero before handing to user space
-        unsafe {
-            core::ptr::write_bytes(
-                frame.start_address().offset_mapped().as_mut_ptr::<u8>(),
-                0,
-                Size4KiB::SIZE as usize,
-            );
+    let id = NEXT_BUF_ID.fetch_add(1, Ordering::Relaxed);
+    let mappings = alloc::vec![Mapping {
+        task_id: task.id,
+        task: Arc::downgrade(task),
+        vaddr: start_vaddr,
+        flags: SHARED_PAGE_FLAGS,
+    }];
+    SHARED_BUF_REGISTRY.lock().insert(
+        id,
+        SharedBuf { owner: task.id, granted: Vec::new(), frames, mappings, stale: Vec::new() },
+    );
+
+    Ok((id, start_vaddr))
+}
+
+/// Allow `grantee` to map shared buffer `id`. Only the owner may grant;
+/// granting the same task twice is harmless.
+///
+/// Fails with `NotFound` if the ID is unknown or `PermissionDenied` if `caller`
+/// is not the owner.
+pub fn grant_shared_buf(id: SharedBufId, caller: &Task, grantee: TaskId) -> Result<(), SysError> {
+    let mut registry = SHARED_BUF_REGISTRY.lock();
+    let buf = registry.get_mut(&id).ok_or(SysError::NotFound)?;
+    if buf.owner != caller.id {
+        return Err(SysError::PermissionDenied);
+    }
+    if grantee != buf.owner && !buf.granted.contains(&grantee) {
+        buf.granted.push(grantee);
+    }
+    Ok(())
+}
+
+/// Map an existing shared buffer into `task`'s address space. `task` must be
+/// the owner or have been granted access with `grant_shared_buf`.
+///
+/// With `read_only` the pages are mapped without `WRITABLE`, so the task can
+/// read what the owner publishes but a stray write faults instead of
+/// corrupting it.
+///
+/// If `task` already maps the buffer, its existing address is returned (with
+/// its original permissions); this is also how a mapper finds the buffer again
+/// after a resize relocated it. Either way, any old ranges a relocation left
+/// guarded in `task` are released.
+///
+/// Returns the start virtual address. Fails with `NotFound` if the ID is
+/// unknown, `PermissionDenied` if `task` has no access, or `OutOfMemory`.
+pub fn map_shared_buf(id: SharedBufId, task: &Arc<Task>, read_only: bool) -> Result<u64, SysError> {
+    let mut registry = SHARED_BUF_REGISTRY.lock();
+    let buf = registry.get_mut(&id).ok_or(SysError::NotFound)?;
+
+    if task.id != buf.owner && !buf.granted.contains(&task.id) {
+        return Err(SysError::PermissionDenied);
+    }
+
+    release_stale_ranges(buf, task);
+
+    if let Some(existing) = buf.mappings.iter().find(|m| m.task_id == task.id) {
+        return Ok(existing.vaddr);
+    }
+
+    let n_pages = buf.frames.len() as u64;
+
+    let mut inner = task.inner.lock();
+
+    let start_vaddr = user_vaddr::allocate_user_pages(&mut inner.user_vaddr_set, n_pages)
+        .ok_or(SysError::OutOfMemory)?;
+
+    let mut mapper = unsafe { user_mapper(task.cr3) };
+
+    let memory = MEMORY.get().unwrap();
+    let mut phys_mem = memory.physical_memory.lock();
+
+    let flags = if read_only { SHARED_PAGE_FLAGS_READONLY } else { SHARED_PAGE_FLAGS };
+    if !map_frames(&mut mapper, &mut phys_mem, start_vaddr, &buf.frames, flags) {
+        user_vaddr::free_user_pages(
+            &mut inner.user_vaddr_set,
+            start_vaddr,
+            n_pages * Size4KiB::SIZE,
+        );
+        return Err(SysError::OutOfMemory);
+    }
+
+    buf.mappings.push(Mapping { task_id: task.id, task: Arc::downgrade(task), vaddr: start_vaddr, flags });
+    Ok(start_vaddr)
+}
+
+/// Give `task` back the old ranges earlier relocations left guarded, now that
+/// it has asked for the buffer's current address.
+fn release_stale_ranges(buf: &mut SharedBuf, task: &Task) {
+    if !buf.stale.iter().any(|s| s.task_id == task.id) {
+        return;
+    }
+    let mut inner = task.inner.lock();
+    buf.stale.retain(|s| {
+        if s.task_id != task.id {
+            return true;
         }
+        let size = s.pages * Size4KiB::SIZE;
+        let _ = inner.unmapped_guards.cut(&ii(s.vaddr, s.vaddr + size - 1));
+        user_vaddr::free_user_pages(&mut inner.user_vaddr_set, s.vaddr, size);
+        false
+    });
+}
 
-        let mut pt_alloc = phys_mem.get_user_mode_frame_allocator();
-        let result = unsafe { mapper.map_to(page, frame, page_flags, &mut pt_alloc) };
-        drop(pt_alloc);
+/// Grow or shrink a shared buffer to `new_pages`, keeping its id and contents.
+///
+/// `caller` must currently map the buffer. Every live mapper's page table is
+/// updated: the mapping is extended in place when the virtual range after it is
+/// free, otherwise it is moved to a fresh range (other mappers can recover the
+/// new address with `map_shared_buf`). Another task's old range is unmapped but
+/// kept reserved as a guard until it re-queries the buffer or unmaps the range,
+/// so a stale pointer faults instead of aliasing a later allocation. Shrinking
+/// unmaps and frees the tail pages.
+///
+/// Mappers may be running on other CPUs, so unmapped pages go through a TLB
+/// shootdown before their frames are freed or their addresses reused.
+///
+/// Returns the caller's (possibly relocated) start address. Fails with
+/// `NotFound` if the id is unknown, `PermissionDenied` if the caller doesn't map
+/// it, or `OutOfMemory`. On failure nothing is changed.
+pub fn resize_shared_buf(id: SharedBufId, caller: &Arc<Task>, new_pages: u64) -> Result<u64, SysError> {
+    if new_pages == 0 {
+        return Err(SysError::InvalidArgs);
+    }
 
-        if result.is_err() {
+    let mut registry = SHARED_BUF_REGISTRY.lock();
+    let buf = registry.get_mut(&id).ok_or(SysError::NotFound)?;
+    if !buf.mappings.iter().any(|m| m.task_id == caller.id) {
+        return Err(SysError::PermissionDenied);
+    }
+
+    // Pair every mapper with its task; drop records whose address space is gone.
+    let mappers: Vec<(Mapping, Arc<Task>)> = buf
+        .mappings
+        .iter()
+        .filter_map(|m| m.task.upgrade().map(|t| (m.clone(), t)))
+        .collect();
+
+    let old_pages = buf.frames.len() as u64;
+    let memory = MEMORY.get().unwrap();
+
+    if new_pages < old_pages {
+        let tail_start = new_pages * Size4KiB::SIZE;
+        let tail_pages = old_pages - new_pages;
+        for (mapping, task) in &mappers {
+            let _inner = task.inner.lock();
+            let mut mapper = unsafe { user_mapper(task.cr3) };
+            unmap_range(&mut mapper, mapping.vaddr + tail_start, tail_pages);
+        }
+        let tail: Vec<PhysFrame<Size4KiB>> = buf.frames.drain(new_pages as usize..).collect();
+        buf.mappings = mappers.iter().map(|(m, _)| m.clone()).collect();
+        let vaddr = caller_vaddr(buf, caller);
+        drop(registry);
+
+        crate::memory::tlb::shootdown();
+        for (mapping, task) in &mappers {
+            let mut inner = task.inner.lock();
+            user_vaddr::free_user_pages(&mut inner.user_vaddr_set, mapping.vaddr + tail_start, tail_pages * Size4KiB::SIZE);
+        }
+        let mut phys_mem = memory.physical_memory.lock();
+        for frame in tail {
             let _ = phys_mem.free_frame(frame, MemoryType::SharedBuffer);
-            rollback(&mut mapper, &mut phys_mem, start_vaddr, i, true);
+        }
+        return vaddr;
+    }
+
+    if new_pages == old_pages {
+        buf.mappings = mappers.iter().map(|(m, _)| m.clone()).collect();
+        return caller_vaddr(buf, caller);
+    }
+
+    // Growing: allocate the extra frames up front so a shortage fails cleanly.
+    let extra = {
+        let mut phys_mem = memory.physical_memory.lock();
+        allocate_zeroed_frames(&mut phys_mem, new_pages - old_pages).ok_or(SysError::OutOfMemory)?
+    };
+    let mut all_frames = buf.frames.clone();
+    all_frames.extend_from_slice(&extra);
+
+    // Phase 1: give every mapper its new pages without touching the old mapping.
+    // `staged[i]` is the range newly mapped for mapper i and whether it is a
+    // relocation (full copy at a new address) or an in-place tail extension.
+    let mut staged: Vec<(u64, u64, bool)> = Vec::with_capacity(mappers.len());
+    let mut failed = false;
+    for (mapping, task) in &mappers {
+        let mut inner = task.inner.lock();
+        let mut mapper = unsafe { user_mapper(task.cr3) };
+
+        let tail_vaddr = mapping.vaddr + old_pages * Size4KiB::SIZE;
+        let (start, frames, relocated) = if user_vaddr::reserve_user_pages_at(
+            &mut inner.user_vaddr_set,
+            tail_vaddr,
+            new_pages - old_pages,
+        ) {
+            (tail_vaddr, &extra[..], false)
+        } else {
+            match user_vaddr::allocate_user_pages(&mut inner.user_vaddr_set, new_pages) {
+                Some(v) => (v, &all_frames[..], true),
+                None => {
+                    failed = true;
+                    break;
+                }
+            }
+        };
+
+        let mut phys_mem = memory.physical_memory.lock();
+        if !map_frames(&mut mapper, &mut phys_mem, start, frames, mapping.flags) {
             user_vaddr::free_user_pages(
                 &mut inner.user_vaddr_set,
-                start_vaddr,
-                n_pages * Size4KiB::SIZE,
+                start,
+                frames.len() as u64 * Size4KiB::SIZE,
             );
-            return None;
+            failed = true;
+            break;
         }
+        staged.push((start, frames.len() as u64, relocated));
+    }
 
-        frames.push(frame);
+    if failed {
+        // Undo phase 1 for the mappers that made it through, then release the
+        // frames. Only this call ever mapped them, so no TLB elsewhere holds them.
+        for ((_, task), &(start, n, _)) in mappers.iter().zip(&staged) {
+            let mut inner = task.inner.lock();
+            let mut mapper = unsafe { user_mapper(task.cr3) };
+            unmap_range(&mut mapper, start, n);
+            user_vaddr::free_user_pages(&mut inner.user_vaddr_set, start, n * Size4KiB::SIZE);
+        }
+        let mut phys_mem = memory.physical_memory.lock();
+        for frame in extra {
+            let _ = phys_mem.free_frame(frame, MemoryType::SharedBuffer);
+        }
+        return Err(SysError::OutOfMemory);
     }
 
-    let id = NEXT_BUF_ID.fetch_add(1, Ordering::Relaxed);
-    SHARED_BUF_REGISTRY.lock().insert(id, SharedBuf { frames });
+    // Phase 2: commit. Relocated mappers drop their old mapping; only the
+    // caller, who gets the new address back, drops the old range too.
+    let mut new_mappings = Vec::with_capacity(mappers.len());
+    let mut any_moved = false;
+    let mut caller_old_vaddr = None;
+    for ((mapping, task), &(start, _, relocated)) in mappers.iter().zip(&staged) {
+        if relocated {
+            let mut inner = task.inner.lock();
+            let mut mapper = unsafe { user_mapper(task.cr3) };
+            unmap_range(&mut mapper, mapping.vaddr, old_pages);
+            if task.id == caller.id {
+                caller_old_vaddr = Some(mapping.vaddr);
+            } else {
+                log::debug!(
+                    "shared_buf {}: task {} mapping moved from {:#x} to {:#x}",
+                    id, task.id.to_u64(), mapping.vaddr, start
+                );
+                leave_unmapped_guard(&mut inner, mapping.vaddr, old_pages);
+                buf.stale.push(StaleRange { task_id: task.id, vaddr: mapping.vaddr, pages: old_pages });
+            }
+            any_moved = true;
+            new_mappings.push(Mapping { vaddr: start, ..mapping.clone() });
+        } else {
+            new_mappings.push(mapping.clone());
+        }
+    }
+
+    buf.frames = all_frames;
+    buf.mappings = new_mappings;
+    let vaddr = caller_vaddr(buf, caller);
+    drop(registry);
 
-    Some((id, start_vaddr))
+    // The old ranges still point at live buffer frames in other CPUs' TLBs;
+    // flush them before the caller's old address can be handed out again.
+    if any_moved {
+        crate::memory::tlb::shootdown();
+        if let Some(old_vaddr) = caller_old_vaddr {
+            let mut inner = caller.inner.lock();
+            user_vaddr::free_user_pages(&mut inner.user_vaddr_set, old_vaddr, old_pages * Size4KiB::SIZE);
+        }
+    }
+    vaddr
+}
+
+/// The caller's mapping after a resize. It was alive when the resize started,
+/// so it can only be missing if the caller is exiting concurrently.
+fn caller_vaddr(buf: &SharedBuf, caller: &Task) -> Result<u64, SysError> {
+    buf.mappings
+        .iter()
+        .find(|m| m.task_id == caller.id)
+        .map(|m| m.vaddr)
+        .ok_or(SysError::NotFound)
+}
+
+/// Forget any of `task_id`'s mappings that start inside `[addr, addr + size)`,
+/// and any of its stale ranges that overlap it.
+///
+/// Called after `sys_munmap` so a later resize or re-map doesn't touch a range
+/// the task has given back (and may since have reused for something else).
+pub fn forget_mappings(task_id: TaskId, addr: u64, size: u64) {
+    let end = addr.saturating_add(size);
+    let mut registry = SHARED_BUF_REGISTRY.lock();
+    for buf in registry.values_mut() {
+        buf.mappings
+            .retain(|m| m.task_id != task_id || m.vaddr < addr || m.vaddr >= end);
+        buf.stale.retain(|s| {
+            s.task_id != task_id || s.vaddr + s.pages * Size4KiB::SIZE <= addr || s.vaddr >= end
+        });
+    }
 }
 
-/// Map an existing shared buffer into `task`'s address space.
+/// Free the physical pages backing a shared buffer. Only the owner may do this.
 ///
-/// Returns the start virtual address, or `None` if the ID is unknown or
-/// address-space allocation fails.
-pub fn map_shared_buf(id: SharedBufId, task: &Task) -> Option<u64> {
-    // Snapshot the frame list while holding the registry lock briefly.
-    let frames: Vec<PhysFrame<Size4KiB>> = {
-        let registry = SHARED_BUF_REGISTRY.lock();
-        registry.get(&id)?.frames.clone()
+/// The pages are first unmapped from every task still mapping the buffer, the
+/// owner included, and a TLB shootdown runs before the frames are freed. Each
+/// range is left behind as an unmapped guard, so a stale pointer into it
+/// faults until the task gives the range back with `sys_munmap`.
+///
+/// Fails with `NotFound` if the ID is unknown or `PermissionDenied` if
+/// `caller` is not the owner.
+pub fn destroy_shared_buf(id: SharedBufId, caller: &Task) -> Result<(), SysError> {
+    let buf = {
+        let mut registry = SHARED_BUF_REGISTRY.lock();
+        match registry.get(&id) {
+            Some(buf) if buf.owner == caller.id => registry.remove(&id),
+            Some(_) => return Err(SysError::PermissionDenied),
+            None => return Err(SysError::NotFound),
+        }
+    };
+    let Some(buf) = buf else {
+        return Ok(());
     };
-    let n_pages = frames.len() as u64;
 
-    let mut inner = task.inner.lock();
+    let n_pages = buf.frames.len() as u64;
+    for mapping in &buf.mappings {
+        let Some(task) = mapping.task.upgrade() else {
+            continue;
+        };
+        let mut inner = task.inner.lock();
+        let mut mapper = unsafe { user_mapper(task.cr3) };
+        unmap_range(&mut mapper, mapping.vaddr, n_pages);
+        leave_unmapped_guard(&mut inner, mapping.vaddr, n_pages);
+    }
+    if !buf.mappings.is_empty() {
+        crate::memory::tlb::shootdown();
+    }
 
-    let start_vaddr =
-        user_vaddr::allocate_user_pages(&mut inner.user_vaddr_set, n_pages)?;
+    let memory = MEMORY.get().unwrap();
+    let mut phys_mem = memory.physical_memory.lock();
+    for frame in buf.frames {
+        let _ = phys_mem.free_frame(frame, MemoryType::SharedBuffer);
+    }
+    Ok(())
+}
 
-    let page_flags = PageTableFlags::PRESENT
-        | PageTableFlags::WRITABLE
-        | PageTableFlags::USER_ACCESSIBLE
-        | PageTableFlags::NO_EXECUTE;
+/// Keep `[vaddr, vaddr + n_pages)` reserved in `inner`'s address space but
+/// have syscalls refuse it, now that nothing is mapped there.
+fn leave_unmapped_guard(inner: &mut TaskInner, vaddr: u64, n_pages: u64) {
+    let _ = inner.unmapped_guards.insert_merge_touching(ii(vaddr, vaddr + n_pages * Size4KiB::SIZE - 1));
+}
 
+/// Build a mapper for the user address space rooted at `cr3`.
+///
+/// # Safety
+/// `cr3` must be the L4 frame of a live user page table, and the caller must
+/// hold that task's `inner` lock so no one else edits it concurrently.
+unsafe fn user_mapper(cr3: u64) -> OffsetPageTable<'static> {
     let hhdm = hhdm_offset();
-    let user_l4_frame =
-        PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(task.cr3));
+    let user_l4_frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(cr3));
     let l4_virt =
         VirtAddr::new(hhdm.as_u64() + user_l4_frame.start_address().as_u64());
     let l4_table = unsafe { &mut *l4_virt.as_mut_ptr::<PageTable>() };
-    let mut mapper =
-        unsafe { OffsetPageTable::new(l4_table, VirtAddr::new(hhdm.as_u64())) };
+    unsafe { OffsetPageTable::new(l4_table, VirtAddr::new(hhdm.as_u64())) }
+}
 
-    let memory = MEMORY.get().unwrap();
-    let mut phys_mem = memory.physical_memory.lock();
+/// Allocate `n_pages` zeroed `SharedBuffer` frames, or none at all.
+fn allocate_zeroed_frames(
+    phys_mem: &mut PhysicalMemory,
+    n_pages: u64,
+) -> Option<Vec<PhysFrame<Size4KiB>>> {
+    let mut frames: Vec<PhysFrame<Size4KiB>> = Vec::new();
+    for _ in 0..n_pages {
+        let frame = match phys_mem.allocate_frame_with_type(MemoryType::SharedBuffer) {
+            Some(f) => f,
+            None => {
+                for frame in frames {
+                    let _ = phys_mem.free_frame(frame, MemoryType::SharedBuffer);
+                }
+                return None;
+            }
+        };
 
+        // Zero before handing to user space
+        unsafe {
+            core::ptr::write_bytes(
+                frame.start_address().offset_mapped().as_mut_ptr::<u8>(),
+                0,
+                Size4KiB::SIZE as usize,
+            );
+        }
+        frames.push(frame);
+    }
+    Some(frames)
+}
+
+/// Map `frames` contiguously starting at `start_vaddr` with `flags`. On failure,
+/// every page mapped so far is unmapped again (the shared frames themselves are kept).
+fn map_frames(
+    mapper: &mut OffsetPageTable,
+    phys_mem: &mut PhysicalMemory,
+    start_vaddr: u64,
+    frames: &[PhysFrame<Size4KiB>],
+    flags: PageTableFlags,
+) -> bool {
     for (i, &frame) in frames.iter().enumerate() {
         let vaddr = VirtAddr::new(start_vaddr + i as u64 * Size4KiB::SIZE);
         let page: Page<Size4KiB> = Page::containing_address(vaddr);
 
         let mut pt_alloc = phys_mem.get_user_mode_frame_allocator();
-        let result = unsafe { mapper.map_to(page, frame, page_flags, &mut pt_alloc) };
+        let result = unsafe { mapper.map_to(page, frame, flags, &mut pt_alloc) };
         drop(pt_alloc);
 
-        if result.is_err() {
-            // Rollback page table entries only — do NOT free the shared frames.
-            rollback(&mut mapper, &mut phys_mem, start_vaddr, i as u64, false);
-            user_vaddr::free_user_pages(
-                &mut inner.user_vaddr_set,
-                start_vaddr,
-                n_pages * Size4KiB::SIZE,
-            );
-            return None;
-        }
-    }
-
-    Some(start_vaddr)
-}
-
-/// Free the physical pages backing a shared buffer.
-///
-/// The caller must ensure all page-table mappings to these frames have been
-/// removed (via `sys_munmap`) before calling this, otherwise the frames may
-/// be reused while still virtually accessible.
-pub fn destroy_shared_buf(id: SharedBufId) {
-    let buf = SHARED_BUF_REGISTRY.lock().remove(&id);
-    if let Some(buf) = buf {
-        let memory = MEMORY.get().unwrap();
-        let mut phys_mem = memory.physical_memory.lock();
-        for frame in buf.frames {
-            let _ = phys_mem.free_frame(frame, MemoryType::SharedBuffer);
+        match result {
+            Ok(flush) => flush.flush(),
+            Err(_) => {
+                unmap_range(mapper, start_vaddr, i as u64);
+                return false;
+            }
         }
     }
+    true
 }
 
-/// Unmap `count` pages starting at `start_vaddr`.
-/// If `free_frames` is true, also free each frame as `SharedBuffer`.
-fn rollback(
-    mapper: &mut OffsetPageTable,
-    phys_mem: &mut crate::memory::physical_memory::PhysicalMemory,
-    start_vaddr: u64,
-    count: u64,
-    free_frames: bool,
-) {
+/// Unmap `count` pages starting at `start_vaddr` without freeing the frames —
+/// they belong to the shared buffer, not to the address space.
+fn unmap_range(mapper: &mut OffsetPageTable, start_vaddr: u64, count: u64) {
     for j in 0..count {
         let vaddr = VirtAddr::new(start_vaddr + j * Size4KiB::SIZE);
         let page: Page<Size4KiB> = Page::containing_address(vaddr);
-        if let Ok((frame, _, flush)) = mapper.unmap(page) {
+        if let Ok((_, _, flush)) = mapper.unmap(page) {
             flush.flush();
-            if free_frames {
-                let _ = phys_mem.free_frame(frame, MemoryType::SharedBuffer);
-            }
         }
     }
 }
@@ -0,0 +1,84 @@
+//! Function names for kernel addresses, from the kernel ELF's symbol table.
+//!
+//! Limine hands the kernel its own executable file, which it keeps mapped for
+//! the kernel's lifetime. `init` reads the `.symtab` in it once, keeping only
+//! function symbols, sorted by address; the names point into the file rather
+//! than being copied. After that `resolve` takes no locks, so it is safe from
+//! fault and NMI handlers. Names are as the linker has them, i.e. mangled.
+
+use crate::limine_requests::KERNEL_FILE_REQUEST;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use elf::ElfBytes;
+use elf::abi::STT_FUNC;
+use elf::endian::AnyEndian;
+use spin::Once;
+
+struct Symbol {
+    addr: u64,
+    size: u64,
+    name: &'static str,
+}
+
+static SYMBOLS: Once<Box<[Symbol]>> = Once::new();
+
+/// Load the function symbols of the kernel ELF. Needs the heap. Without a
+/// kernel file or symbol table the table stays empty and `resolve` finds
+/// nothing.
+pub fn init() {
+    SYMBOLS.call_once(|| {
+        let symbols = load().unwrap_or_default();
+        if symbols.is_empty() {
+            log::warn!("symbols: no kernel symbol table, addresses won't be named");
+        } else {
+            log::info!("symbols: {} kernel functions", symbols.len());
+        }
+        symbols.into_boxed_slice()
+    });
+}
+
+fn load() -> Option<Vec<Symbol>> {
+    let file = KERNEL_FILE_REQUEST.get_response()?.file();
+    // Limine keeps the kernel file mapped, unchanged, for the kernel's lifetime.
+    let bytes: &'static [u8] = unsafe { core::slice::from_raw_parts(file.addr() as *const u8, file.size() as usize) };
+    let elf = ElfBytes::<AnyEndian>::minimal_parse(bytes).ok()?;
+    let (table, strings) = elf.symbol_table().ok()??;
+    let mut symbols: Vec<Symbol> = table
+        .iter()
+        .filter(|sym| sym.st_symtype() == STT_FUNC && sym.st_value != 0)
+        .filter_map(|sym| {
+            let name = strings.get(sym.st_name as usize).ok()?;
+            Some(Symbol { addr: sym.st_value, size: sym.st_size, name })
+        })
+        .collect();
+    symbols.sort_unstable_by_key(|sym| sym.addr);
+    symbols.shrink_to_fit();
+    Some(symbols)
+}
+
+/// The function `addr` is in, and how far into it. `None` before `init`, or
+/// if `addr` isn't inside any kernel function.
+pub fn resolve(addr: u64) -> Option<(&'static str, usize)> {
+    let symbols = SYMBOLS.get()?;
+    let index = symbols.partition_point(|sym| sym.addr <= addr).checked_sub(1)?;
+    let sym = &symbols[index];
+    let offset = addr - sym.addr;
+    // A symbol without a size only names its first byte.
+    (offset < sym.size.max(1)).then_some((sym.name, offset as usize))
+}
+
+/// Formats a code address as `0x... <function+0x...>`, or just the address
+/// if it doesn't resolve.
+#[derive(Clone, Copy)]
+pub struct Addr(pub u64);
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x}", self.0)?;
+        match resolve(self.0) {
+            Some((name, offset)) => write!(f, " <{name}+{offset:#x}>"),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,10 +1,10 @@
 use crate::graphics::display::{DISPLAY, DISPLAY_OWNER};
 use crate::memory::MEMORY;
 use crate::memory::hhdm_offset::hhdm_offset;
-use crate::task::global_scheduler::TASK_TABLE;
-use crate::task::task::TaskId;
+use crate::task::task::TaskKind;
 use core::sync::atomic::Ordering;
-use kernel_api_types::graphics::{DisplayInfo, GraphicsResult, Rect, FRAMEBUFFER_USER_VADDR};
+use kernel_api_types::graphics::{DisplayInfo, Rect, FRAMEBUFFER_USER_VADDR};
+use kernel_api_types::SysError;
 use nodit::interval::ii;
 use x86_64::structures::paging::{
     Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB,
@@ -13,12 +13,16 @@ use x86_64::{PhysAddr, VirtAddr};
 use super::validate_user_ptr;
 
 /// Syscall: return the bounding box of the framebuffer.
+///
+/// Arguments: rect_out_ptr
+/// Returns: 0, `SysError::PermissionDenied` if the caller doesn't own the
+/// display, or `SysError::InvalidArgs` for a bad pointer.
 pub fn sys_get_bounding_box(rect_out_ptr: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     if !crate::graphics::display::is_display_owner() {
-        return GraphicsResult::PermissionDenied as u64;
+        return SysError::PermissionDenied as u64;
     }
     if !validate_user_ptr(rect_out_ptr, core::mem::size_of::<Rect>() as u64) {
-        return GraphicsResult::InvalidInput as u64;
+        return SysError::InvalidArgs as u64;
     }
 
     let rect_out = unsafe { &mut *(rect_out_ptr as *mut Rect) };
@@ -28,41 +32,53 @@ pub fn sys_get_bounding_box(rect_out_ptr: u64, _: u64, _: u64, _: u64, _: u64, _
     rect_out.width = bb.size.width;
     rect_out.height = bb.size.height;
 
-    GraphicsResult::Ok as u64
+    0
+}
+
+/// Syscall: ask whether the caller owns the display.
+///
+/// Arguments: none
+/// Returns: 1 if the calling task is the display owner, else 0.
+pub fn sys_is_display_owner(_: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    crate::graphics::display::is_display_owner() as u64
 }
 
 /// Syscall: get display info (dimensions and pixel format).
 ///
 /// Arguments: info_out_ptr
-/// Returns: GraphicsResult code.
+/// Returns: 0, or `SysError::InvalidArgs` for a bad pointer.
 pub fn sys_get_display_info(info_out_ptr: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     if !validate_user_ptr(info_out_ptr, core::mem::size_of::<DisplayInfo>() as u64) {
-        return GraphicsResult::InvalidInput as u64;
+        return SysError::InvalidArgs as u64;
     }
 
     let info = DISPLAY.get_display_info();
     unsafe { core::ptr::write(info_out_ptr as *mut DisplayInfo, info) };
 
-    GraphicsResult::Ok as u64
+    0
 }
 
 /// Syscall: transfer display ownership to another task.
 ///
 /// Arguments: new_owner_task_id
-/// Returns: 0 on success, 1 if caller is not the current owner,
-///          2 if target task not found, 3 if mapping failed.
+/// Returns: 0 on success, `SysError::PermissionDenied` if the caller is not the
+/// current owner, `SysError::NotFound` if the target task doesn't exist or has
+/// exited, `SysError::InvalidArgs` if it is a kernel task, or
+/// `SysError::OutOfMemory` if mapping the framebuffer failed. On any error the
+/// caller keeps the display.
 pub fn sys_transfer_display(new_owner_id: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     if !crate::graphics::display::is_display_owner() {
-        return 1;
+        return SysError::PermissionDenied as u64;
     }
 
-    let target_task = {
-        let table = TASK_TABLE.lock();
-        match table.get(&TaskId::from_u64(new_owner_id)) {
-            Some(task) => task.clone(),
-            None => return 2,
-        }
+    let Some(target_task) = crate::task::lookup(new_owner_id) else {
+        return SysError::NotFound as u64;
     };
+    // Kernel tasks share the kernel's page tables, which must never get a
+    // user-accessible framebuffer mapping.
+    if target_task.kind != TaskKind::User {
+        return SysError::InvalidArgs as u64;
+    }
 
     let (fb_phys_addr, fb_size) = DISPLAY.get_fb_phys_and_size();
     let user_fb_virt = VirtAddr::new(FRAMEBUFFER_USER_VADDR);
@@ -103,7 +119,7 @@ pub fn sys_transfer_display(new_owner_id: u64, _: u64, _: u64, _: u64, _: u64, _
                 mapping.ignore();
             } else {
                 log::error!("TransferDisplay: map_to failed at page {}", i);
-                return 3;
+                return SysError::OutOfMemory as u64;
             }
         }
     }
@@ -0,0 +1,75 @@
+use alloc::vec;
+use core::mem::{offset_of, size_of};
+use kernel_api_types::input::{
+    InputEvent, RecordedInput, INPUT_KEY, INPUT_MOUSE, INPUT_RECORD_START, INPUT_RECORD_STOP,
+    MAX_RECORDED_INPUTS,
+};
+use kernel_api_types::{KeyEvent, KeyEventType, SysError};
+use crate::drivers::{input_record, keyboard, mouse};
+use super::validate_user_ptr;
+
+/// Syscall: start or stop recording keyboard and mouse input. Debug kernels only.
+///
+/// Arguments: command, buf_ptr, buf_cap
+/// - `INPUT_RECORD_START`: discard any previous recording and start a new one.
+/// - `INPUT_RECORD_STOP`: stop, and copy up to `buf_cap` `RecordedInput`s to `buf_ptr`.
+///
+/// Returns: 0 for start, the number of events copied for stop; `NoSys` in
+/// release kernels.
+pub fn sys_input_record(command: u64, buf_ptr: u64, buf_cap: u64, _: u64, _: u64, _: u64) -> u64 {
+    if !cfg!(debug_assertions) {
+        return SysError::NoSys as u64;
+    }
+    match command {
+        INPUT_RECORD_START => {
+            input_record::start();
+            0
+        }
+        INPUT_RECORD_STOP => {
+            let cap = buf_cap.min(MAX_RECORDED_INPUTS as u64);
+            let Some(size) = cap.checked_mul(size_of::<RecordedInput>() as u64) else {
+                return SysError::InvalidArgs as u64;
+            };
+            if cap == 0 || !validate_user_ptr(buf_ptr, size) {
+                return SysError::InvalidArgs as u64;
+            }
+            // Copy out of the recorder first: its lock is a spinlock.
+            let mut events = vec![RecordedInput::EMPTY; cap as usize];
+            let n = input_record::stop(&mut events);
+            unsafe {
+                core::ptr::copy_nonoverlapping(events.as_ptr(), buf_ptr as *mut RecordedInput, n)
+            };
+            n as u64
+        }
+        _ => SysError::InvalidArgs as u64,
+    }
+}
+
+/// Syscall: queue a keyboard or mouse event as if the device had sent it.
+/// Debug kernels only.
+///
+/// Arguments: event_ptr — an `InputEvent`.
+/// Returns: 0; `InvalidArgs` for an unknown kind or key type; `NoSys` in
+/// release kernels.
+pub fn sys_inject_input(event_ptr: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if !cfg!(debug_assertions) {
+        return SysError::NoSys as u64;
+    }
+    if !validate_user_ptr(event_ptr, size_of::<InputEvent>() as u64) {
+        return SysError::InvalidArgs as u64;
+    }
+    let mut raw = [0u8; size_of::<InputEvent>()];
+    unsafe { core::ptr::copy_nonoverlapping(event_ptr as *const u8, raw.as_mut_ptr(), raw.len()) };
+    // `KeyEvent` holds an enum, so check its tag before reading the bytes as an event.
+    let key_type = raw[offset_of!(InputEvent, key) + offset_of!(KeyEvent, event_type)];
+    if key_type > KeyEventType::ArrowDown as u8 {
+        return SysError::InvalidArgs as u64;
+    }
+    let event = unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const InputEvent) };
+    match event.kind {
+        INPUT_KEY => keyboard::push_event(event.key),
+        INPUT_MOUSE => mouse::push_event(event.mouse),
+        _ => return SysError::InvalidArgs as u64,
+    }
+    0
+}
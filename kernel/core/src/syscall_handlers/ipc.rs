@@ -1,25 +1,21 @@
 use crate::memory::cpu_local_data::get_local;
-use crate::task::task::TaskState;
+use crate::task::task::Privilege;
 use core::sync::atomic::Ordering;
+use kernel_api_types::{RecvInfo, SysError, NO_ENDPOINT, RECV_KEEP_TRUNCATED};
 use super::{current_task_and_cpu, validate_user_ptr};
 
 /// Syscall: create a new IPC channel.
 ///
 /// Arguments: send_ep_out_ptr, recv_ep_out_ptr, capacity
-/// Writes the two endpoint IDs to the output pointers.
-/// Returns: IPC status code.
+/// Writes the two endpoint IDs to the output pointers. The capacity is
+/// clamped to what the caller's privilege allows (see `granted_capacity`).
+/// Returns: 0, or a negative `SysError` code.
 pub fn sys_channel_create(send_ep_out_ptr: u64, recv_ep_out_ptr: u64, capacity: u64, _: u64, _: u64, _: u64) -> u64 {
     if !validate_user_ptr(send_ep_out_ptr, 8) || !validate_user_ptr(recv_ep_out_ptr, 8) {
-        return kernel_api_types::IPC_ERR_INVALID_ARGS;
+        return SysError::InvalidArgs as u64;
     }
 
-    let cap = if capacity == 0 {
-        crate::ipc::DEFAULT_CHANNEL_CAPACITY
-    } else {
-        (capacity as usize).clamp(1, crate::ipc::MAX_CHANNEL_CAPACITY)
-    };
-
-    let (send_id, recv_id) = crate::ipc::create_channel(cap);
+    let (send_id, recv_id) = crate::ipc::create_channel(granted_capacity(capacity));
 
     unsafe {
         core::ptr::write(send_ep_out_ptr as *mut u64, send_id);
@@ -37,18 +33,65 @@ pub fn sys_channel_create(send_ep_out_ptr: u64, recv_ep_out_ptr: u64, capacity:
         }
     }
 
-    kernel_api_types::IPC_OK
+    0
+}
+
+/// The capacity the calling task gets when it asks for `capacity`: the
+/// default for 0, else at most `max_channel_capacity` of its privilege.
+/// Without a current task, the user limit applies.
+fn granted_capacity(capacity: u64) -> usize {
+    if capacity == 0 {
+        return crate::ipc::DEFAULT_CHANNEL_CAPACITY;
+    }
+    let privilege = current_task_and_cpu().map_or(Privilege::User, |(task, _)| task.privilege);
+    (capacity as usize).clamp(1, crate::ipc::max_channel_capacity(privilege))
 }
 
 /// Syscall: send a message on a channel endpoint.
 ///
 /// Blocks (via sleep+hlt) if the channel is full, woken by the receiver.
+/// Returns: 0, or a negative `SysError` code (`WouldBlock` if interrupted while full).
 pub fn sys_channel_send(endpoint_id: u64, msg_ptr: u64, msg_len: u64, _: u64, _: u64, _: u64) -> u64 {
+    send_blocking(endpoint_id, msg_ptr, msg_len, None)
+}
+
+/// Syscall: send a message and move an endpoint to its receiver.
+///
+/// Arguments: endpoint_id, msg_ptr, msg_len, transfer_ep
+/// Blocks like `sys_channel_send`. Once the message is queued, `transfer_ep`
+/// no longer belongs to the caller: it is not closed when the caller exits,
+/// and is handed to the task that receives the message with
+/// `sys_channel_recv_with_endpoint`. The caller must own `transfer_ep`.
+/// Returns: 0, or a negative `SysError` code (`InvalidEndpoint` if the caller
+/// doesn't own `transfer_ep`, `InvalidArgs` if it is `endpoint_id` or
+/// `endpoint_id` is a broadcast endpoint).
+pub fn sys_channel_send_with_endpoint(
+    endpoint_id: u64,
+    msg_ptr: u64,
+    msg_len: u64,
+    transfer_ep: u64,
+    _: u64,
+    _: u64,
+) -> u64 {
+    let Some((task, _)) = current_task_and_cpu() else {
+        return SysError::InvalidEndpoint as u64;
+    };
+    if !task.inner.lock().owned_endpoints.contains(&transfer_ep) {
+        return SysError::InvalidEndpoint as u64;
+    }
+    let ret = send_blocking(endpoint_id, msg_ptr, msg_len, Some(transfer_ep));
+    if ret == 0 {
+        task.inner.lock().owned_endpoints.retain(|&ep| ep != transfer_ep);
+    }
+    ret
+}
+
+fn send_blocking(endpoint_id: u64, msg_ptr: u64, msg_len: u64, transfer: Option<u64>) -> u64 {
     if msg_len > crate::ipc::MAX_MESSAGE_SIZE as u64 {
-        return kernel_api_types::IPC_ERR_MSG_TOO_LARGE;
+        return SysError::MessageTooLarge as u64;
     }
     if msg_len > 0 && !validate_user_ptr(msg_ptr, msg_len) {
-        return kernel_api_types::IPC_ERR_INVALID_ARGS;
+        return SysError::InvalidArgs as u64;
     }
 
     let data = if msg_len > 0 {
@@ -61,25 +104,31 @@ pub fn sys_channel_send(endpoint_id: u64, msg_ptr: u64, msg_len: u64, _: u64, _:
     let channel_arc = {
         let registry = crate::ipc::ENDPOINT_REGISTRY.lock();
         match registry.get(&endpoint_id) {
-            Some(ep) if ep.role == crate::ipc::EndpointRole::Send => ep.channel.clone(),
-            Some(_) => return kernel_api_types::IPC_ERR_WRONG_DIRECTION,
-            None => return kernel_api_types::IPC_ERR_INVALID_ENDPOINT,
+            Some(ep) if matches!(ep.role, crate::ipc::EndpointRole::Send | crate::ipc::EndpointRole::Broadcast) => {
+                ep.channel.clone()
+            }
+            Some(_) => return SysError::WrongDirection as u64,
+            None => return SysError::InvalidEndpoint as u64,
         }
     };
 
     loop {
-        match crate::ipc::try_send(endpoint_id, data) {
-            Ok(()) => return kernel_api_types::IPC_OK,
+        match crate::ipc::try_send_with_endpoint(endpoint_id, data, transfer) {
+            Ok(()) => {
+                if let Some((task, _)) = current_task_and_cpu() {
+                    channel_arc.sent_by(&task);
+                }
+                return 0;
+            }
             Err(crate::ipc::IpcError::ChannelFull) => {
                 // Set fallback return value in CpuContext
                 let ctx_ptr = get_local().current_context_ptr.load(Ordering::Relaxed);
                 if !ctx_ptr.is_null() {
-                    unsafe { (*ctx_ptr).rax = kernel_api_types::IPC_ERR_CHANNEL_FULL; }
+                    unsafe { (*ctx_ptr).rax = SysError::WouldBlock as u64; }
                 }
                 // Register as send waiter and sleep
                 if let Some((task, cpu_id)) = current_task_and_cpu() {
-                    channel_arc.send_waiters.lock().push_back((task.clone(), cpu_id));
-                    task.state.store(TaskState::Sleeping, Ordering::Release);
+                    crate::ipc::wait_to_send(&channel_arc, task, cpu_id);
                 }
                 x86_64::instructions::interrupts::enable();
                 x86_64::instructions::hlt();
@@ -92,42 +141,102 @@ pub fn sys_channel_send(endpoint_id: u64, msg_ptr: u64, msg_len: u64, _: u64, _:
 
 /// Syscall: receive a message from a channel endpoint.
 ///
+/// Arguments: endpoint_id, buf_ptr, buf_cap, bytes_read_out_ptr, flags
 /// Blocks (via sleep+hlt) if channel is empty, woken by the sender.
-pub fn sys_channel_recv(endpoint_id: u64, buf_ptr: u64, buf_cap: u64, bytes_read_out_ptr: u64, _: u64, _: u64) -> u64 {
-    if !validate_user_ptr(buf_ptr, buf_cap) || !validate_user_ptr(bytes_read_out_ptr, 8) {
-        return kernel_api_types::IPC_ERR_INVALID_ARGS;
+/// A message longer than `buf_cap` is truncated to fit; the bytes copied go
+/// to `*bytes_read_out_ptr` and the full length is returned. The message is
+/// consumed unless `flags` has `RECV_KEEP_TRUNCATED`.
+/// Returns: the message length, or a negative `SysError` code (`WouldBlock` if
+/// interrupted while empty).
+pub fn sys_channel_recv(endpoint_id: u64, buf_ptr: u64, buf_cap: u64, bytes_read_out_ptr: u64, flags: u64, _: u64) -> u64 {
+    if !validate_user_ptr(bytes_read_out_ptr, 8) {
+        return SysError::InvalidArgs as u64;
+    }
+    recv_blocking(endpoint_id, buf_ptr, buf_cap, flags, |bytes_read, endpoint| {
+        unsafe { core::ptr::write(bytes_read_out_ptr as *mut u64, bytes_read) };
+        if let Some(endpoint) = endpoint {
+            let _ = crate::ipc::close_endpoint(endpoint);
+        }
+    })
+}
+
+/// Syscall: receive a message and any endpoint sent along with it.
+///
+/// Arguments: endpoint_id, buf_ptr, buf_cap, info_out_ptr, flags
+/// Like `sys_channel_recv`, but writes a `RecvInfo` to `*info_out_ptr`: the
+/// bytes copied, and the endpoint moved by `sys_channel_send_with_endpoint`
+/// (`NO_ENDPOINT` if none). That endpoint now belongs to the caller and is
+/// closed when it exits. A message left queued by `RECV_KEEP_TRUNCATED`
+/// keeps its endpoint.
+/// Returns: the message length, or a negative `SysError` code.
+pub fn sys_channel_recv_with_endpoint(
+    endpoint_id: u64,
+    buf_ptr: u64,
+    buf_cap: u64,
+    info_out_ptr: u64,
+    flags: u64,
+    _: u64,
+) -> u64 {
+    if !validate_user_ptr(info_out_ptr, size_of::<RecvInfo>() as u64) {
+        return SysError::InvalidArgs as u64;
     }
+    recv_blocking(endpoint_id, buf_ptr, buf_cap, flags, |bytes_read, endpoint| {
+        if let (Some(endpoint), Some((task, _))) = (endpoint, current_task_and_cpu()) {
+            task.inner.lock().owned_endpoints.push(endpoint);
+        }
+        let info = RecvInfo { bytes_read, endpoint: endpoint.unwrap_or(NO_ENDPOINT) };
+        unsafe { core::ptr::write(info_out_ptr as *mut RecvInfo, info) };
+    })
+}
+
+/// Receive into `buf_ptr`, then hand the bytes copied and the endpoint that
+/// came with the message to `deliver`.
+fn recv_blocking(
+    endpoint_id: u64,
+    buf_ptr: u64,
+    buf_cap: u64,
+    flags: u64,
+    deliver: impl FnOnce(u64, Option<u64>),
+) -> u64 {
+    if !validate_user_ptr(buf_ptr, buf_cap) {
+        return SysError::InvalidArgs as u64;
+    }
+    if flags & !RECV_KEEP_TRUNCATED != 0 {
+        return SysError::InvalidArgs as u64;
+    }
+    let keep_truncated = flags & RECV_KEEP_TRUNCATED != 0;
 
     // Get the channel Arc once so we can access its recv_waiter on WouldBlock
     let channel_arc = {
         let registry = crate::ipc::ENDPOINT_REGISTRY.lock();
         match registry.get(&endpoint_id) {
             Some(ep) if ep.role == crate::ipc::EndpointRole::Recv => ep.channel.clone(),
-            Some(_) => return kernel_api_types::IPC_ERR_WRONG_DIRECTION,
-            None => return kernel_api_types::IPC_ERR_INVALID_ENDPOINT,
+            Some(_) => return SysError::WrongDirection as u64,
+            None => return SysError::InvalidEndpoint as u64,
         }
     };
 
     loop {
-        match crate::ipc::try_recv(endpoint_id) {
-            Ok(msg) => {
+        let max_len = if keep_truncated { buf_cap as usize } else { usize::MAX };
+        match crate::ipc::try_recv_with_endpoint(endpoint_id, max_len) {
+            Ok((msg, endpoint)) => {
                 let copy_len = msg.len().min(buf_cap as usize);
-                unsafe {
-                    core::ptr::copy_nonoverlapping(msg.as_ptr(), buf_ptr as *mut u8, copy_len);
-                    core::ptr::write(bytes_read_out_ptr as *mut u64, copy_len as u64);
+                unsafe { core::ptr::copy_nonoverlapping(msg.as_ptr(), buf_ptr as *mut u8, copy_len) };
+                deliver(copy_len as u64, endpoint);
+                if let Some((task, _)) = current_task_and_cpu() {
+                    channel_arc.received_by(&task);
                 }
-                return kernel_api_types::IPC_OK;
+                return msg.len() as u64;
             }
             Err(crate::ipc::IpcError::WouldBlock) => {
                 // Set fallback return value in CpuContext (EINTR/EAGAIN semantics)
                 let ctx_ptr = get_local().current_context_ptr.load(Ordering::Relaxed);
                 if !ctx_ptr.is_null() {
-                    unsafe { (*ctx_ptr).rax = kernel_api_types::IPC_ERR_CHANNEL_FULL; }
+                    unsafe { (*ctx_ptr).rax = SysError::WouldBlock as u64; }
                 }
                 // Register as recv waiter and sleep
                 if let Some((task, cpu_id)) = current_task_and_cpu() {
-                    channel_arc.recv_waiters.lock().push_back((task.clone(), cpu_id));
-                    task.state.store(TaskState::Sleeping, Ordering::Release);
+                    crate::ipc::wait_to_recv(&channel_arc, task, cpu_id);
                 }
                 x86_64::instructions::interrupts::enable();
                 x86_64::instructions::hlt();
@@ -138,25 +247,170 @@ pub fn sys_channel_recv(endpoint_id: u64, buf_ptr: u64, buf_cap: u64, bytes_read
     }
 }
 
+/// Syscall: count the messages waiting on a recv endpoint.
+///
+/// Arguments: endpoint_id
+/// Lets an event loop check for work without committing to a blocking recv.
+/// Returns: the number of queued messages (0 if empty), or a negative `SysError` code.
+pub fn sys_channel_poll(endpoint_id: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    match crate::ipc::poll(endpoint_id) {
+        Ok(queued) => queued as u64,
+        Err(e) => ipc_error_to_code(e),
+    }
+}
+
+/// Syscall: report how full a channel is.
+///
+/// Arguments: endpoint_id (either end), len_out_ptr, capacity_out_ptr
+/// Writes the number of queued messages and the capacity the channel was
+/// granted, so a sender can check its headroom before a burst.
+/// Returns: 0, or a negative `SysError` code.
+pub fn sys_channel_stats(endpoint_id: u64, len_out_ptr: u64, capacity_out_ptr: u64, _: u64, _: u64, _: u64) -> u64 {
+    if !validate_user_ptr(len_out_ptr, 8) || !validate_user_ptr(capacity_out_ptr, 8) {
+        return SysError::InvalidArgs as u64;
+    }
+
+    let (len, capacity) = match crate::ipc::channel_stats(endpoint_id) {
+        Ok(stats) => stats,
+        Err(e) => return ipc_error_to_code(e),
+    };
+    unsafe {
+        core::ptr::write(len_out_ptr as *mut u64, len as u64);
+        core::ptr::write(capacity_out_ptr as *mut u64, capacity as u64);
+    }
+    0
+}
+
+/// Non-blocking send for the syscall ring: a full channel completes with
+/// `SysError::WouldBlock` instead of sleeping.
+pub(super) fn channel_send_nonblocking(endpoint_id: u64, msg_ptr: u64, msg_len: u64) -> u64 {
+    if msg_len > crate::ipc::MAX_MESSAGE_SIZE as u64 {
+        return SysError::MessageTooLarge as u64;
+    }
+    if msg_len > 0 && !validate_user_ptr(msg_ptr, msg_len) {
+        return SysError::InvalidArgs as u64;
+    }
+    let data = if msg_len > 0 {
+        unsafe { core::slice::from_raw_parts(msg_ptr as *const u8, msg_len as usize) }
+    } else {
+        &[]
+    };
+    match crate::ipc::try_send(endpoint_id, data) {
+        Ok(()) => 0,
+        Err(e) => ipc_error_to_code(e),
+    }
+}
+
+/// Non-blocking receive for the syscall ring. Returns `(status, bytes_read)`;
+/// an empty channel completes with `SysError::WouldBlock` (same code the
+/// blocking path reports when interrupted).
+pub(super) fn channel_recv_nonblocking(endpoint_id: u64, buf_ptr: u64, buf_cap: u64) -> (u64, u64) {
+    if !validate_user_ptr(buf_ptr, buf_cap) {
+        return (SysError::InvalidArgs as u64, 0);
+    }
+    match crate::ipc::try_recv(endpoint_id) {
+        Ok(msg) => {
+            let copy_len = msg.len().min(buf_cap as usize);
+            unsafe { core::ptr::copy_nonoverlapping(msg.as_ptr(), buf_ptr as *mut u8, copy_len) };
+            (0, copy_len as u64)
+        }
+        Err(e) => (ipc_error_to_code(e), 0),
+    }
+}
+
 /// Syscall: close a channel endpoint.
 ///
 /// Arguments: endpoint_id
-/// Returns: IPC status code.
+/// Returns: 0, or a negative `SysError` code.
 pub fn sys_channel_close(endpoint_id: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     match crate::ipc::close_endpoint(endpoint_id) {
-        Ok(()) => kernel_api_types::IPC_OK,
+        Ok(()) => 0,
         Err(e) => ipc_error_to_code(e),
     }
 }
 
-fn ipc_error_to_code(e: crate::ipc::IpcError) -> u64 {
-    match e {
-        crate::ipc::IpcError::InvalidEndpoint => kernel_api_types::IPC_ERR_INVALID_ENDPOINT,
-        crate::ipc::IpcError::WrongDirection  => kernel_api_types::IPC_ERR_WRONG_DIRECTION,
-        crate::ipc::IpcError::PeerClosed      => kernel_api_types::IPC_ERR_PEER_CLOSED,
-        crate::ipc::IpcError::ChannelFull     => kernel_api_types::IPC_ERR_CHANNEL_FULL,
-        crate::ipc::IpcError::WouldBlock      => kernel_api_types::IPC_ERR_CHANNEL_FULL,
-        crate::ipc::IpcError::MessageTooLarge => kernel_api_types::IPC_ERR_MSG_TOO_LARGE,
-        crate::ipc::IpcError::InvalidArgs     => kernel_api_types::IPC_ERR_INVALID_ARGS,
+/// Syscall: duplicate a channel endpoint.
+///
+/// Arguments: endpoint_id, new_ep_out_ptr
+/// Writes the ID of a new endpoint with the same channel and direction. Each
+/// copy is closed independently; the peer only sees `PeerClosed` once all of
+/// them are.
+/// Returns: 0, or a negative `SysError` code.
+pub fn sys_channel_dup(endpoint_id: u64, new_ep_out_ptr: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if !validate_user_ptr(new_ep_out_ptr, 8) {
+        return SysError::InvalidArgs as u64;
     }
+
+    let new_id = match crate::ipc::dup_endpoint(endpoint_id) {
+        Ok(id) => id,
+        Err(e) => return ipc_error_to_code(e),
+    };
+    unsafe { core::ptr::write(new_ep_out_ptr as *mut u64, new_id) };
+
+    if let Some((task, _)) = current_task_and_cpu() {
+        task.inner.lock().owned_endpoints.push(new_id);
+    }
+
+    0
+}
+
+/// Syscall: create a broadcast channel.
+///
+/// Arguments: ep_out_ptr, capacity (per subscriber; 0 for the default,
+/// clamped as for `sys_channel_create`)
+/// Writes the ID of its broadcast endpoint. `sys_channel_send` on it copies
+/// the message to every subscriber; a subscriber whose queue is full misses
+/// it, and the send still succeeds.
+/// Returns: 0, or a negative `SysError` code.
+pub fn sys_broadcast_create(ep_out_ptr: u64, capacity: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if !validate_user_ptr(ep_out_ptr, 8) {
+        return SysError::InvalidArgs as u64;
+    }
+
+    let id = crate::ipc::create_broadcast(granted_capacity(capacity));
+    unsafe { core::ptr::write(ep_out_ptr as *mut u64, id) };
+
+    if let Some((task, _)) = current_task_and_cpu() {
+        task.inner.lock().owned_endpoints.push(id);
+    }
+
+    0
+}
+
+/// Syscall: subscribe to a broadcast channel.
+///
+/// Arguments: endpoint_id (a broadcast endpoint), recv_ep_out_ptr
+/// Writes the ID of a new recv endpoint that gets every message broadcast
+/// from then on. It sees `PeerClosed` once every broadcast endpoint is closed.
+/// Returns: 0, or a negative `SysError` code (`WrongDirection` if
+/// `endpoint_id` isn't a broadcast endpoint).
+pub fn sys_channel_subscribe(endpoint_id: u64, recv_ep_out_ptr: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if !validate_user_ptr(recv_ep_out_ptr, 8) {
+        return SysError::InvalidArgs as u64;
+    }
+
+    let recv_id = match crate::ipc::subscribe(endpoint_id) {
+        Ok(id) => id,
+        Err(e) => return ipc_error_to_code(e),
+    };
+    unsafe { core::ptr::write(recv_ep_out_ptr as *mut u64, recv_id) };
+
+    if let Some((task, _)) = current_task_and_cpu() {
+        task.inner.lock().owned_endpoints.push(recv_id);
+    }
+
+    0
+}
+
+fn ipc_error_to_code(e: crate::ipc::IpcError) -> u64 {
+    let err = match e {
+        crate::ipc::IpcError::InvalidEndpoint => SysError::InvalidEndpoint,
+        crate::ipc::IpcError::WrongDirection  => SysError::WrongDirection,
+        crate::ipc::IpcError::PeerClosed      => SysError::PeerClosed,
+        crate::ipc::IpcError::ChannelFull     => SysError::WouldBlock,
+        crate::ipc::IpcError::WouldBlock      => SysError::WouldBlock,
+        crate::ipc::IpcError::MessageTooLarge => SysError::MessageTooLarge,
+        crate::ipc::IpcError::InvalidArgs     => SysError::InvalidArgs,
+    };
+    err as u64
 }
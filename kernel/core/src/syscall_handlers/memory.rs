@@ -1,22 +1,29 @@
 use crate::memory::MEMORY;
 use crate::memory::cpu_local_data::get_local;
-use crate::memory::hhdm_offset::hhdm_offset;
-use crate::memory::physical_memory::{MemoryType, OffsetMappedPhysAddr, PhysicalMemory};
+use crate::memory::demand_paging::{self, map_zeroed_huge_page, map_zeroed_page, user_mapper};
+use crate::memory::physical_memory::{MemoryType, PhysicalMemory};
 use crate::memory::user_vaddr;
-use crate::task::task::TaskKind;
-use kernel_api_types::{MMAP_EXEC, MMAP_WRITE};
+use crate::task::task::{TaskId, TaskKind};
+use kernel_api_types::{SysError, MMAP_EXEC, MMAP_HUGE, MMAP_LAZY, MMAP_WRITE, SHBUF_READONLY};
+use nodit::interval::ii;
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
 use x86_64::structures::paging::{
-    Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    Mapper, OffsetPageTable, Page, PageSize, PageTableFlags, PhysFrame, Size2MiB, Size4KiB, Translate,
 };
-use x86_64::{PhysAddr, VirtAddr};
+use x86_64::VirtAddr;
 
 /// Syscall: allocate virtual memory for the calling user task.
 ///
-/// Arguments: size (bytes), flags (MMAP_WRITE | MMAP_EXEC)
-/// Returns: start virtual address, or 0 on failure.
+/// Arguments: size (bytes), flags (MMAP_WRITE | MMAP_EXEC | MMAP_LAZY | MMAP_HUGE)
+/// Returns: start virtual address, or a negative `SysError` code. Running out of
+/// user address space or physical memory part-way gives `OutOfMemory`; any pages
+/// mapped so far and the reserved range are released. With `MMAP_LAZY` only the
+/// range is reserved and pages are mapped as they are first touched. With
+/// `MMAP_HUGE` a range of at least 2 MiB starts on a 2 MiB boundary and each
+/// whole 2 MiB of it gets a 2 MiB page if a 2 MiB frame is free.
 pub fn sys_mmap(size: u64, flags: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     if size == 0 {
-        return 0;
+        return SysError::InvalidArgs as u64;
     }
 
     let n_pages = size.div_ceil(Size4KiB::SIZE);
@@ -26,15 +33,17 @@ pub fn sys_mmap(size: u64, flags: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
         let rq = cpu.run_queue.get().unwrap().lock();
         match &rq.current_task {
             Some(t) if t.kind == TaskKind::User => t.clone(),
-            _ => return 0,
+            _ => return SysError::PermissionDenied as u64,
         }
     };
 
     let mut inner = task.inner.lock();
 
-    let start_vaddr = match user_vaddr::allocate_user_pages(&mut inner.user_vaddr_set, n_pages) {
+    let huge = (flags & MMAP_HUGE) != 0 && (flags & MMAP_LAZY) == 0 && size >= Size2MiB::SIZE;
+    let align = if huge { Size2MiB::SIZE } else { Size4KiB::SIZE };
+    let start_vaddr = match user_vaddr::allocate_user_pages_aligned(&mut inner.user_vaddr_set, n_pages, align) {
         Some(addr) => addr,
-        None => return 0,
+        None => return SysError::OutOfMemory as u64,
     };
 
     let mut page_flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
@@ -45,44 +54,40 @@ pub fn sys_mmap(size: u64, flags: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
         page_flags |= PageTableFlags::NO_EXECUTE;
     }
 
-    let hhdm_offset = hhdm_offset();
-    let user_l4_frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(task.cr3));
-    let l4_virt_addr = VirtAddr::new(hhdm_offset.as_u64() + user_l4_frame.start_address().as_u64());
-    let l4_table = unsafe { &mut *l4_virt_addr.as_mut_ptr::<PageTable>() };
-    let mut mapper = unsafe { OffsetPageTable::new(l4_table, VirtAddr::new(hhdm_offset.as_u64())) };
+    if (flags & MMAP_LAZY) != 0 {
+        let last = start_vaddr + n_pages * Size4KiB::SIZE - 1;
+        inner
+            .lazy_regions
+            .insert_merge_touching_if_values_equal(ii(start_vaddr, last), page_flags)
+            .unwrap();
+        return start_vaddr;
+    }
+
+    let mut mapper = user_mapper(task.cr3);
 
     let memory = MEMORY.get().unwrap();
     let mut physical_memory = memory.physical_memory.lock();
 
-    for i in 0..n_pages {
-        let vaddr = VirtAddr::new(start_vaddr + i * Size4KiB::SIZE);
-        let page: Page<Size4KiB> = Page::containing_address(vaddr);
-
-        let frame = match physical_memory.allocate_frame_with_type(MemoryType::UsedByUserMode) {
-            Some(f) => f,
-            None => {
-                rollback_mmap(&mut mapper, &mut physical_memory, start_vaddr, i);
-                user_vaddr::free_user_pages(&mut inner.user_vaddr_set, start_vaddr, n_pages * Size4KiB::SIZE);
-                return 0;
+    let end_vaddr = start_vaddr + n_pages * Size4KiB::SIZE;
+    let mut vaddr = start_vaddr;
+    while vaddr < end_vaddr {
+        // A 2 MiB page where one fits; if none can be had, this 2 MiB stretch
+        // is mapped with 4 KiB pages instead.
+        if huge && vaddr % Size2MiB::SIZE == 0 && end_vaddr - vaddr >= Size2MiB::SIZE {
+            let page: Page<Size2MiB> = Page::containing_address(VirtAddr::new(vaddr));
+            if map_zeroed_huge_page(&mut mapper, &mut physical_memory, page, page_flags) {
+                vaddr += Size2MiB::SIZE;
+                continue;
             }
-        };
-
-        // Security: zero the frame before giving it to user space
-        let frame_virt = frame.start_address().offset_mapped();
-        unsafe {
-            core::ptr::write_bytes(frame_virt.as_mut_ptr::<u8>(), 0, Size4KiB::SIZE as usize);
         }
 
-        let mut frame_allocator = physical_memory.get_user_mode_frame_allocator();
-        let map_result = unsafe { mapper.map_to(page, frame, page_flags, &mut frame_allocator) };
-        drop(frame_allocator);
-
-        if map_result.is_err() {
-            let _ = physical_memory.free_frame(frame, MemoryType::UsedByUserMode);
-            rollback_mmap(&mut mapper, &mut physical_memory, start_vaddr, i);
+        let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(vaddr));
+        if !map_zeroed_page(&mut mapper, &mut physical_memory, page, page_flags) {
+            unmap_user_range(&mut mapper, &mut physical_memory, start_vaddr, vaddr - start_vaddr);
             user_vaddr::free_user_pages(&mut inner.user_vaddr_set, start_vaddr, n_pages * Size4KiB::SIZE);
-            return 0;
+            return SysError::OutOfMemory as u64;
         }
+        vaddr += Size4KiB::SIZE;
     }
 
     start_vaddr
@@ -91,10 +96,13 @@ pub fn sys_mmap(size: u64, flags: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
 /// Syscall: unmap and free virtual memory pages previously allocated with sys_mmap.
 ///
 /// Arguments: addr (start virtual address), size (bytes)
-/// Returns: 0 on success, !0 on failure.
+/// Returns: 0 on success, or `SysError::InvalidArgs` if the range isn't a live allocation.
+/// A 2 MiB page only partly inside the range is first split into 4 KiB pages;
+/// if there is no memory for the page table that takes, nothing is unmapped
+/// and the result is `SysError::OutOfMemory`.
 pub fn sys_munmap(addr: u64, size: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     if size == 0 || addr % Size4KiB::SIZE != 0 {
-        return !0u64;
+        return SysError::InvalidArgs as u64;
     }
 
     let n_pages = size.div_ceil(Size4KiB::SIZE);
@@ -104,47 +112,138 @@ pub fn sys_munmap(addr: u64, size: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
         let rq = cpu.run_queue.get().unwrap().lock();
         match &rq.current_task {
             Some(t) if t.kind == TaskKind::User => t.clone(),
-            _ => return !0u64,
+            _ => return SysError::PermissionDenied as u64,
         }
     };
 
+    let Some(total_size) = n_pages.checked_mul(Size4KiB::SIZE) else {
+        return SysError::InvalidArgs as u64;
+    };
+
     let mut inner = task.inner.lock();
 
-    let total_size = n_pages * Size4KiB::SIZE;
-    if !user_vaddr::free_user_pages(&mut inner.user_vaddr_set, addr, total_size) {
-        return !0u64;
+    // The stack guard page stays reserved for the life of the task.
+    if inner.overlaps_user_stack_guard(addr, addr.saturating_add(total_size)) {
+        return SysError::InvalidArgs as u64;
+    }
+    if !user_vaddr::is_range_reserved(&inner.user_vaddr_set, addr, total_size) {
+        return SysError::InvalidArgs as u64;
     }
 
-    let hhdm_offset = hhdm_offset();
-    let user_l4_frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(task.cr3));
-    let l4_virt_addr = VirtAddr::new(hhdm_offset.as_u64() + user_l4_frame.start_address().as_u64());
-    let l4_table = unsafe { &mut *l4_virt_addr.as_mut_ptr::<PageTable>() };
-    let mut mapper = unsafe { OffsetPageTable::new(l4_table, VirtAddr::new(hhdm_offset.as_u64())) };
+    let mut mapper = user_mapper(task.cr3);
 
     let memory = MEMORY.get().unwrap();
     let mut physical_memory = memory.physical_memory.lock();
 
-    for i in 0..n_pages {
-        let vaddr = VirtAddr::new(addr + i * Size4KiB::SIZE);
-        let page: Page<Size4KiB> = Page::containing_address(vaddr);
+    if !split_huge_page_at(&mut mapper, &mut physical_memory, addr)
+        || !split_huge_page_at(&mut mapper, &mut physical_memory, addr + total_size)
+    {
+        return SysError::OutOfMemory as u64;
+    }
 
-        if let Ok((frame, _, flush)) = mapper.unmap(page) {
-            flush.flush();
-            let _ = physical_memory.free_frame(frame, MemoryType::UsedByUserMode);
+    user_vaddr::free_user_pages(&mut inner.user_vaddr_set, addr, total_size);
+    // Lazy pages never touched have no mapping; unmapping below skips them.
+    let _ = inner.lazy_regions.cut(&ii(addr, addr + total_size - 1));
+    let _ = inner.unmapped_guards.cut(&ii(addr, addr + total_size - 1));
+
+    unmap_user_range(&mut mapper, &mut physical_memory, addr, total_size);
+
+    drop(physical_memory);
+    drop(inner);
+    crate::shared_buf::forget_mappings(task.id, addr, total_size);
+
+    0
+}
+
+/// Syscall: map every page of an `MMAP_LAZY` range now, so touching it later
+/// doesn't fault.
+///
+/// Arguments: addr (page-aligned), size (bytes)
+/// Returns: 0, `SysError::InvalidArgs` if the range isn't inside one allocation,
+/// or `SysError::OutOfMemory`. Pages that are already resident are left alone.
+pub fn sys_populate(addr: u64, size: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if size == 0 || addr % Size4KiB::SIZE != 0 {
+        return SysError::InvalidArgs as u64;
+    }
+
+    let cpu = get_local();
+    let task = {
+        let rq = cpu.run_queue.get().unwrap().lock();
+        match &rq.current_task {
+            Some(t) if t.kind == TaskKind::User => t.clone(),
+            _ => return SysError::PermissionDenied as u64,
+        }
+    };
+
+    match demand_paging::populate(&task, addr, size.div_ceil(Size4KiB::SIZE)) {
+        Ok(()) => 0,
+        Err(e) => e as u64,
+    }
+}
+
+/// Pages `sys_mincore` looks up per hold of the task lock.
+const MINCORE_CHUNK_PAGES: usize = 256;
+
+/// Syscall: report which pages of a range are resident.
+///
+/// Arguments: addr (page-aligned), size (bytes), vec_ptr
+/// Writes one byte per page to `vec_ptr`: 1 if the page is mapped, 0 if it is an
+/// untouched `MMAP_LAZY` page.
+/// Returns: 0, or `SysError::InvalidArgs` if the range isn't inside one allocation
+/// or `vec_ptr` can't hold the result.
+pub fn sys_mincore(addr: u64, size: u64, vec_ptr: u64, _: u64, _: u64, _: u64) -> u64 {
+    if size == 0 || addr % Size4KiB::SIZE != 0 {
+        return SysError::InvalidArgs as u64;
+    }
+    let n_pages = size.div_ceil(Size4KiB::SIZE);
+    if !super::validate_user_ptr(vec_ptr, n_pages) {
+        return SysError::InvalidArgs as u64;
+    }
+
+    let cpu = get_local();
+    let task = {
+        let rq = cpu.run_queue.get().unwrap().lock();
+        match &rq.current_task {
+            Some(t) if t.kind == TaskKind::User => t.clone(),
+            _ => return SysError::PermissionDenied as u64,
         }
+    };
+
+    if !user_vaddr::is_range_reserved(&task.inner.lock().user_vaddr_set, addr, n_pages * Size4KiB::SIZE) {
+        return SysError::InvalidArgs as u64;
     }
 
+    // `vec_ptr` may itself be an untouched lazy page, whose fault needs the
+    // task lock, so each chunk is worked out under the lock and copied out
+    // after dropping it.
+    let mut chunk = [0u8; MINCORE_CHUNK_PAGES];
+    let mut done = 0;
+    while done < n_pages {
+        let count = (n_pages - done).min(MINCORE_CHUNK_PAGES as u64) as usize;
+        {
+            let _inner = task.inner.lock();
+            let mapper = user_mapper(task.cr3);
+            for (i, byte) in chunk[..count].iter_mut().enumerate() {
+                let vaddr = addr + (done + i as u64) * Size4KiB::SIZE;
+                // Translating the address rather than a 4 KiB page also sees 2 MiB pages.
+                *byte = mapper.translate_addr(VirtAddr::new(vaddr)).is_some() as u8;
+            }
+        }
+        // The caller's page table is active, so `vec_ptr` is directly writable.
+        unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), (vec_ptr + done) as *mut u8, count) };
+        done += count as u64;
+    }
     0
 }
 
 /// Syscall: allocate a shared physical buffer and map it into the caller's address space.
 ///
 /// Arguments: size (bytes), vaddr_out_ptr
-/// Returns: SharedBufId, or u64::MAX on failure.
+/// Returns: SharedBufId, or a negative `SysError` code.
 /// Writes the mapped virtual address to `vaddr_out_ptr`.
 pub fn sys_create_shared_buf(size: u64, vaddr_out_ptr: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     if size == 0 || !super::validate_user_ptr(vaddr_out_ptr, 8) {
-        return u64::MAX;
+        return SysError::InvalidArgs as u64;
     }
 
     let n_pages = size.div_ceil(Size4KiB::SIZE);
@@ -154,60 +253,181 @@ pub fn sys_create_shared_buf(size: u64, vaddr_out_ptr: u64, _: u64, _: u64, _: u
         let rq = cpu.run_queue.get().unwrap().lock();
         match &rq.current_task {
             Some(t) if t.kind == TaskKind::User => t.clone(),
-            _ => return u64::MAX,
+            _ => return SysError::PermissionDenied as u64,
         }
     };
 
     match crate::shared_buf::create_shared_buf(&task, n_pages) {
-        Some((id, vaddr)) => {
+        Ok((id, vaddr)) => {
             unsafe { core::ptr::write(vaddr_out_ptr as *mut u64, vaddr) };
             id
         }
-        None => u64::MAX,
+        Err(e) => e as u64,
     }
 }
 
 /// Syscall: map an existing shared buffer into the caller's address space.
 ///
-/// Arguments: shared_buf_id
-/// Returns: start virtual address, or 0 on failure.
-pub fn sys_map_shared_buf(id: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+/// Arguments: shared_buf_id, flags (`SHBUF_READONLY` maps the pages read-only)
+/// Returns: start virtual address, or a negative `SysError` code
+/// (`PermissionDenied` if the caller is neither the owner nor granted access).
+/// If the caller already maps the buffer, its current address is returned
+/// instead of mapping it twice.
+pub fn sys_map_shared_buf(id: u64, flags: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if flags & !SHBUF_READONLY != 0 {
+        return SysError::InvalidArgs as u64;
+    }
+
     let cpu = get_local();
     let task = {
         let rq = cpu.run_queue.get().unwrap().lock();
         match &rq.current_task {
             Some(t) if t.kind == TaskKind::User => t.clone(),
-            _ => return 0,
+            _ => return SysError::PermissionDenied as u64,
         }
     };
 
-    match crate::shared_buf::map_shared_buf(id, &task) {
-        Some(vaddr) => vaddr,
-        None => 0,
+    SysError::encode(crate::shared_buf::map_shared_buf(id, &task, flags & SHBUF_READONLY != 0))
+}
+
+/// Syscall: grow or shrink a shared buffer, keeping its id and contents.
+///
+/// Arguments: shared_buf_id, new_size (bytes)
+/// Returns: the caller's start virtual address for the buffer (which may have
+/// moved), or a negative `SysError` code. Other mappers are updated in place where
+/// possible; if their mapping had to move they can find it again with `sys_map_shared_buf`,
+/// and until they do their old range stays reserved but unmapped.
+pub fn sys_resize_shared_buf(id: u64, new_size: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if new_size == 0 {
+        return SysError::InvalidArgs as u64;
     }
+
+    let n_pages = new_size.div_ceil(Size4KiB::SIZE);
+
+    let cpu = get_local();
+    let task = {
+        let rq = cpu.run_queue.get().unwrap().lock();
+        match &rq.current_task {
+            Some(t) if t.kind == TaskKind::User => t.clone(),
+            _ => return SysError::PermissionDenied as u64,
+        }
+    };
+
+    SysError::encode(crate::shared_buf::resize_shared_buf(id, &task, n_pages))
 }
 
-/// Syscall: free the physical pages backing a shared buffer.
+/// Syscall: allow another task to map a shared buffer the caller owns.
+///
+/// Arguments: shared_buf_id, task_id
+/// Returns: 0, `SysError::NotFound` for an unknown ID, or
+/// `SysError::PermissionDenied` if the caller isn't the owner.
+pub fn sys_grant_shared_buf(id: u64, task_id: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    let cpu = get_local();
+    let task = {
+        let rq = cpu.run_queue.get().unwrap().lock();
+        match &rq.current_task {
+            Some(t) if t.kind == TaskKind::User => t.clone(),
+            _ => return SysError::PermissionDenied as u64,
+        }
+    };
+
+    match crate::shared_buf::grant_shared_buf(id, &task, TaskId::from_u64(task_id)) {
+        Ok(()) => 0,
+        Err(e) => e as u64,
+    }
+}
+
+/// Syscall: free the physical pages backing a shared buffer, unmapping it
+/// from every task that maps it. The ranges stay reserved until each task
+/// calls `sys_munmap` on them.
 ///
 /// Arguments: shared_buf_id
-/// Returns: 0 (always succeeds; no-op if ID unknown).
+/// Returns: 0, `SysError::NotFound` for an unknown ID, or
+/// `SysError::PermissionDenied` if the caller isn't the owner.
 pub fn sys_destroy_shared_buf(id: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
-    crate::shared_buf::destroy_shared_buf(id);
-    0
+    let cpu = get_local();
+    let task = {
+        let rq = cpu.run_queue.get().unwrap().lock();
+        match &rq.current_task {
+            Some(t) if t.kind == TaskKind::User => t.clone(),
+            _ => return SysError::PermissionDenied as u64,
+        }
+    };
+
+    match crate::shared_buf::destroy_shared_buf(id, &task) {
+        Ok(()) => 0,
+        Err(e) => e as u64,
+    }
 }
 
-fn rollback_mmap(
+/// Unmap `[start, start + len)` and free its frames, whether each part is
+/// mapped with 4 KiB or 2 MiB pages. Unmapped holes are skipped. A 2 MiB page is
+/// freed whole, so it must not stick out of the range (see `split_huge_page_at`).
+fn unmap_user_range(
     mapper: &mut OffsetPageTable,
     physical_memory: &mut PhysicalMemory,
-    start_vaddr: u64,
-    count: u64,
+    start: u64,
+    len: u64,
 ) {
-    for j in 0..count {
-        let vaddr = VirtAddr::new(start_vaddr + j * Size4KiB::SIZE);
-        let page: Page<Size4KiB> = Page::containing_address(vaddr);
+    let end = start + len;
+    let mut vaddr = start;
+    while vaddr < end {
+        let addr = VirtAddr::new(vaddr);
+        if let TranslateResult::Mapped { frame: MappedFrame::Size2MiB(_), .. } = mapper.translate(addr) {
+            let page: Page<Size2MiB> = Page::containing_address(addr);
+            if let Ok((frame, _, flush)) = mapper.unmap(page) {
+                flush.flush();
+                let _ = physical_memory.free_frame_2mib(frame, MemoryType::UsedByUserMode);
+            }
+            vaddr = page.start_address().as_u64() + Size2MiB::SIZE;
+            continue;
+        }
+
+        let page: Page<Size4KiB> = Page::containing_address(addr);
         if let Ok((frame, _, flush)) = mapper.unmap(page) {
             flush.flush();
             let _ = physical_memory.free_frame(frame, MemoryType::UsedByUserMode);
         }
+        vaddr += Size4KiB::SIZE;
+    }
+}
+
+/// If `vaddr` lies strictly inside a 2 MiB page, remap that page as 512 4 KiB
+/// pages onto the same frames, so a range can begin or end at `vaddr`.
+/// Returns false if the new page table couldn't be allocated, in which case
+/// the 2 MiB page is put back as it was.
+fn split_huge_page_at(mapper: &mut OffsetPageTable, physical_memory: &mut PhysicalMemory, vaddr: u64) -> bool {
+    if vaddr % Size2MiB::SIZE == 0 {
+        return true;
+    }
+    let addr = VirtAddr::new(vaddr);
+    let TranslateResult::Mapped { frame: MappedFrame::Size2MiB(_), .. } = mapper.translate(addr) else {
+        return true;
+    };
+    let huge_page: Page<Size2MiB> = Page::containing_address(addr);
+    let Ok((huge_frame, flags, flush)) = mapper.unmap(huge_page) else {
+        return false;
+    };
+    flush.flush();
+
+    let flags = flags - PageTableFlags::HUGE_PAGE;
+    let first_page: Page<Size4KiB> = Page::containing_address(huge_page.start_address());
+    let first_frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(huge_frame.start_address());
+    for i in 0..Size2MiB::SIZE / Size4KiB::SIZE {
+        let mut frame_allocator = physical_memory.get_user_mode_frame_allocator();
+        match unsafe { mapper.map_to(first_page + i, first_frame + i, flags, &mut frame_allocator) } {
+            // The pages were unmapped and flushed above, so there is no stale entry.
+            Ok(flush) => flush.ignore(),
+            Err(_) => {
+                // Only the first mapping allocates (the new page table), so
+                // nothing has been mapped yet.
+                let mut frame_allocator = physical_memory.get_user_mode_frame_allocator();
+                if let Ok(flush) = unsafe { mapper.map_to(huge_page, huge_frame, flags, &mut frame_allocator) } {
+                    flush.flush();
+                }
+                return false;
+            }
+        }
     }
+    true
 }
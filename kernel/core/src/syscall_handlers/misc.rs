@@ -1,18 +1,89 @@
 use crate::limine_requests::MODULE_REQUEST;
 
-/// Syscall: write to the isa-debug-exit port and halt (exits QEMU).
+/// Syscall: power off the machine.
 ///
-/// Arguments: exit_code — written directly to port 0xf4.
-/// QEMU exits with code `(exit_code << 1) | 1`.
+/// Arguments: exit_code — written to the isa-debug-exit port (0xf4) first, so
+/// QEMU exits with code `(exit_code << 1) | 1`; elsewhere the machine enters
+/// ACPI S5 (see `power::shutdown`).
 /// Convention: 0x10 = all tests passed (exit 33), 0x11 = any failure (exit 35).
 pub fn sys_shutdown(exit_code: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
-    unsafe { x86::io::outb(0xf4, exit_code as u8) }
-    loop {}
+    crate::power::shutdown(exit_code as u8)
 }
+
+/// Syscall: read the time stamp counter.
+///
+/// Returns: the raw TSC value. Combine with `GetTscHz` to convert to time.
+pub fn sys_get_cycles(_: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    crate::time::tsc::value()
+}
+
+/// Syscall: the TSC frequency measured at boot, in Hz.
+///
+/// Returns: ticks per second, or 0 if the TSC hasn't been calibrated. The
+/// rate is the median of several PIT intervals at boot, replaced by an HPET
+/// measurement if the two disagree by more than 10% (see `tsc::calibrate`).
+/// It is only millisecond-resolution, so precise clients should measure the
+/// rate themselves.
+pub fn sys_get_tsc_hz(_: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    // TSC_HZ holds ticks per millisecond.
+    crate::time::tsc::TSC_HZ.load(Ordering::Relaxed) * 1000
+}
+
+/// Syscall: read the wall-clock date and time from the CMOS RTC.
+///
+/// Arguments: out_ptr — a `DateTime` to fill in.
+/// Returns: 0, or `SysError::InvalidArgs` if `out_ptr` isn't writable user memory.
+pub fn sys_get_wallclock(out_ptr: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if !validate_user_ptr(out_ptr, size_of::<DateTime>() as u64) {
+        return SysError::InvalidArgs as u64;
+    }
+    let now = crate::time::rtc::read_datetime();
+    unsafe { core::ptr::write_unaligned(out_ptr as *mut DateTime, now) };
+    0
+}
+
+/// Syscall: read the kernel heap's counters (see `global_allocator::stats`).
+///
+/// Arguments: out_ptr — an `AllocStats` to fill in.
+/// Returns: 0, or `SysError::InvalidArgs` if `out_ptr` isn't writable user memory.
+pub fn sys_heap_stats(out_ptr: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if !validate_user_ptr(out_ptr, size_of::<AllocStats>() as u64) {
+        return SysError::InvalidArgs as u64;
+    }
+    let stats = crate::memory::global_allocator::stats();
+    unsafe { core::ptr::write_unaligned(out_ptr as *mut AllocStats, stats) };
+    0
+}
+
+/// Syscall: fill a user buffer with pseudo-random bytes (see `random::fill`).
+///
+/// Arguments: buf_ptr, len
+/// Returns: 0, or `SysError::InvalidArgs` if the buffer isn't writable user memory.
+pub fn sys_random(buf_ptr: u64, len: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if len == 0 {
+        return 0;
+    }
+    if !validate_user_ptr(buf_ptr, len) {
+        return SysError::InvalidArgs as u64;
+    }
+    let task_id = current_task_and_cpu().map_or(0, |(task, _)| task.id.to_u64());
+    let buf = unsafe { core::slice::from_raw_parts_mut(buf_ptr as *mut u8, len as usize) };
+    crate::random::fill(buf, task_id);
+    0
+}
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use crate::memory::cpu_local_data::get_local;
-use crate::task::task::TaskState;
+use crate::task::task::{Privilege, TaskState};
 use core::sync::atomic::Ordering;
-use super::{current_task_and_cpu, validate_user_ptr};
+use kernel_api_types::{AllocStats, DateTime, SysCallNumber, SysError, SyscallOp, BATCH_OP_NOT_RUN, MAX_BATCH_OPS, MAX_DEBUG_LOG_STR_LEN};
+use super::{current_task_and_cpu, validate_user_ptr, wake_task};
+
+/// Syscall: reset the machine (see `power::reboot`). Does not return.
+pub fn sys_reboot(_: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    crate::power::reboot()
+}
 
 /// Syscall: emit a debug value to the serial console.
 ///
@@ -22,13 +93,155 @@ pub fn sys_debug_log(value: u64, tag: u64, _: u64, _: u64, _: u64, _: u64) -> u6
     0
 }
 
+/// Syscall: log a string from user memory to the serial console.
+///
+/// Arguments: ptr, len (bytes)
+/// Returns: 0, or `SysError::InvalidArgs` if the buffer isn't readable user memory.
+/// Only the first `MAX_DEBUG_LOG_STR_LEN` bytes are logged. Bytes that aren't
+/// valid UTF-8 are logged in hex instead.
+pub fn sys_debug_log_str(ptr: u64, len: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    let n = (len as usize).min(MAX_DEBUG_LOG_STR_LEN);
+    if n > 0 && !validate_user_ptr(ptr, n as u64) {
+        return SysError::InvalidArgs as u64;
+    }
+
+    let mut buf = [0u8; MAX_DEBUG_LOG_STR_LEN];
+    if n > 0 {
+        unsafe { core::ptr::copy_nonoverlapping(ptr as *const u8, buf.as_mut_ptr(), n) };
+    }
+    let bytes = &buf[..n];
+    let truncated = if n < len as usize { " [truncated]" } else { "" };
+    match core::str::from_utf8(bytes) {
+        Ok(s) => log::info!("DBG: {}{}", s, truncated),
+        Err(_) => log::info!("DBG (not UTF-8): {:02x?}{}", bytes, truncated),
+    }
+    0
+}
+
+/// Syscall: do nothing and return 0.
+///
+/// Exists so the raw SYSCALL/SYSRET + context save/restore cost can be measured
+/// without any handler work mixed in.
+pub fn sys_null(_: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    0
+}
+
+/// Syscall: turn syscall tracing to the serial console on or off.
+///
+/// Arguments: enabled (0 = off, anything else = on)
+/// Tracing is global and logs every task's syscall arguments, so only a
+/// system task may switch it.
+/// Returns: 1 if tracing was on before the call, 0 otherwise, or
+/// `SysError::PermissionDenied` for a user task.
+pub fn sys_set_syscall_trace(enabled: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if current_task_and_cpu().is_some_and(|(task, _)| task.privilege != Privilege::System) {
+        return SysError::PermissionDenied as u64;
+    }
+    crate::raw_syscall_handler::SYSCALL_TRACE.swap(enabled != 0, Ordering::Relaxed) as u64
+}
+
+/// Syscall: execute an array of encoded syscalls in one kernel entry.
+///
+/// Arguments: ops_ptr (array of `SyscallOp`), count (at most `MAX_BATCH_OPS`)
+/// Returns: number of ops executed, or `SysError::InvalidArgs` if the array is invalid.
+///
+/// Each op's return value is written back into its `result` field. Only
+/// syscalls `in_batch` allows run; the rest are skipped and keep
+/// `BATCH_OP_NOT_RUN`. The ops are copied into the kernel first and the
+/// results copied back at the end, so an op that unmaps the array itself
+/// doesn't fault the kernel: if the array is gone, the results are dropped.
+/// If a send blocks on a full channel and the timer interrupts it, the batch
+/// is abandoned there: results up to that op have been copied back and later
+/// ops keep `BATCH_OP_NOT_RUN`, so callers should trust the per-op results
+/// rather than the return value.
+pub fn sys_batch(ops_ptr: u64, count: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if count == 0 || count > MAX_BATCH_OPS as u64 {
+        return SysError::InvalidArgs as u64;
+    }
+    let size = count * core::mem::size_of::<SyscallOp>() as u64;
+    if ops_ptr % core::mem::align_of::<SyscallOp>() as u64 != 0 || !validate_user_ptr(ops_ptr, size) {
+        return SysError::InvalidArgs as u64;
+    }
+    let mut ops: Vec<SyscallOp> =
+        unsafe { core::slice::from_raw_parts(ops_ptr as *const SyscallOp, count as usize) }.to_vec();
+
+    // Mark everything as not-run first so an abandoned batch is unambiguous.
+    for op in ops.iter_mut() {
+        op.result = BATCH_OP_NOT_RUN;
+    }
+    if !write_back_ops(ops_ptr, &ops) {
+        return SysError::InvalidArgs as u64;
+    }
+
+    let mut executed = 0;
+    for i in 0..ops.len() {
+        let SyscallOp { number, args, .. } = ops[i];
+        match in_batch(number) {
+            InBatch::No => continue,
+            // If it blocks it may never return here, so save what has run.
+            InBatch::MayBlock => {
+                if !write_back_ops(ops_ptr, &ops) {
+                    break;
+                }
+            }
+            InBatch::Yes => {}
+        }
+        ops[i].result = crate::raw_syscall_handler::dispatch_syscall(number, &args);
+        executed += 1;
+    }
+    write_back_ops(ops_ptr, &ops);
+    executed
+}
+
+/// Whether a syscall may run inside `sys_batch`.
+enum InBatch {
+    Yes,
+    /// Runs, but can block; the batch is abandoned if it is interrupted.
+    MayBlock,
+    /// Diverges, blocks until something happens, or nests a batch.
+    No,
+}
+
+/// Every syscall is listed, so a new one can't join batches without a decision.
+fn in_batch(number: u64) -> InBatch {
+    use SysCallNumber::*;
+    // Unknown numbers run and get `NoSys` from the dispatcher.
+    let Some(number) = SysCallNumber::from_u64(number) else {
+        return InBatch::Yes;
+    };
+    match number {
+        Exit | Shutdown | Reboot | Batch | RingEnter | Yield | ReadKey | Waitpid | Sleep | ChannelRecv
+        | ChannelRecvWithEndpoint => InBatch::No,
+        ChannelSend | ChannelSendWithEndpoint => InBatch::MayBlock,
+        GetBoundingBox | Spawn | Mmap | Munmap | ChannelCreate | ChannelClose | TransferDisplay | GetModule
+        | GetDisplayInfo | DebugLog | RegisterService | LookupService | ReadMouse | CreateSharedBuf
+        | MapSharedBuf | DestroySharedBuf | Null | ResizeSharedBuf | GrantSharedBuf | GetTaskId
+        | SetSyscallTrace | DebugLogStr | Populate | Mincore | ChannelDup | GetCycles | GetTscHz
+        | GetWallClock | NetSend | NetRecv | NetGetMac | SerialWrite | SerialRead | InputRecord
+        | InjectInput | SetPriority | ChannelPoll | SetTaskName | ListTasks | Kill | BroadcastCreate
+        | ChannelSubscribe | GetModuleChunk | HeapStats | ChannelStats | Random | IsDisplayOwner => InBatch::Yes,
+    }
+}
+
+/// Copy `ops` over the caller's array, if the caller still has it. Returns
+/// false if it doesn't.
+fn write_back_ops(ops_ptr: u64, ops: &[SyscallOp]) -> bool {
+    if !validate_user_ptr(ops_ptr, core::mem::size_of_val(ops) as u64) {
+        return false;
+    }
+    unsafe { core::ptr::copy_nonoverlapping(ops.as_ptr(), ops_ptr as *mut SyscallOp, ops.len()) };
+    true
+}
+
 /// Syscall: read a key event (blocking).
 ///
 /// Registers the current task as the keyboard waiter, sets it Sleeping,
 /// enables interrupts, and halts. The keyboard ISR wakes it when a key arrives.
+/// Returns: 0, `SysError::InvalidArgs` for a bad pointer, or `SysError::WouldBlock`
+/// if the wait was interrupted before a key arrived.
 pub fn sys_read_key(key_event_out_ptr: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     if !validate_user_ptr(key_event_out_ptr, core::mem::size_of::<kernel_api_types::KeyEvent>() as u64) {
-        return 1;
+        return SysError::InvalidArgs as u64;
     }
     let out = key_event_out_ptr as *mut kernel_api_types::KeyEvent;
 
@@ -41,27 +254,63 @@ pub fn sys_read_key(key_event_out_ptr: u64, _: u64, _: u64, _: u64, _: u64, _: u
         // Set CpuContext.rax so the task sees a valid return value if woken early
         let ctx_ptr = get_local().current_context_ptr.load(Ordering::Relaxed);
         if !ctx_ptr.is_null() {
-            unsafe { (*ctx_ptr).rax = 1; }
+            unsafe { (*ctx_ptr).rax = SysError::WouldBlock as u64; }
         }
 
         // Register waiter and sleep
         if let Some((task, cpu_id)) = current_task_and_cpu() {
             *crate::drivers::keyboard::KEYBOARD_WAITER.lock() = Some((task.clone(), cpu_id));
-            task.state.store(TaskState::Sleeping, Ordering::Release);
+            task.set_state(TaskState::Sleeping);
+        }
+
+        x86_64::instructions::interrupts::enable();
+        x86_64::instructions::hlt();
+        x86_64::instructions::interrupts::disable();
+    }
+}
+
+/// Syscall: block the calling task for at least `ms` milliseconds.
+///
+/// Registers a one-shot timer on this CPU whose callback wakes the task, then
+/// sleeps like `sys_read_key`. Resolution is the 1 ms scheduler tick.
+/// Returns: 0.
+pub fn sys_sleep(ms: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    let Some((task, cpu_id)) = current_task_and_cpu() else {
+        return 0;
+    };
+
+    // The task resumes from its saved context once woken, so preset the result.
+    let ctx_ptr = get_local().current_context_ptr.load(Ordering::Relaxed);
+    if !ctx_ptr.is_null() {
+        unsafe { (*ctx_ptr).rax = 0; }
+    }
+
+    task.set_state(TaskState::Sleeping);
+    // The timer holds the task's only other Arc until it fires.
+    let mut waiter = Some(task.clone());
+    crate::time::timers::register_timer(ms, Box::new(move || {
+        if let Some(task) = waiter.take() {
+            wake_task(task, cpu_id);
         }
+    }));
 
+    // If nothing else is runnable the scheduler keeps this task current, and
+    // the halt returns here instead of switching away.
+    while task.state.load(Ordering::Acquire) == TaskState::Sleeping {
         x86_64::instructions::interrupts::enable();
         x86_64::instructions::hlt();
         x86_64::instructions::interrupts::disable();
     }
+    0
 }
 
 /// Syscall: try to read a mouse event (non-blocking).
 ///
-/// Returns 0 and writes the event if one is available, or 1 if the buffer is empty.
+/// Returns 0 and writes the event if one is available, or `SysError::WouldBlock`
+/// if the buffer is empty.
 pub fn sys_read_mouse(mouse_event_out_ptr: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     if !validate_user_ptr(mouse_event_out_ptr, core::mem::size_of::<kernel_api_types::MouseEvent>() as u64) {
-        return 1;
+        return SysError::InvalidArgs as u64;
     }
     let out = mouse_event_out_ptr as *mut kernel_api_types::MouseEvent;
 
@@ -70,29 +319,24 @@ pub fn sys_read_mouse(mouse_event_out_ptr: u64, _: u64, _: u64, _: u64, _: u64,
             unsafe { core::ptr::write(out, event) };
             0
         }
-        None => 1,
+        None => SysError::WouldBlock as u64,
     }
 }
 
-/// Syscall: load a Limine boot module by name.
-///
-/// Arguments: name_ptr, name_len, buf_ptr, buf_cap
-///
-/// Size query: if buf_ptr == 0 && buf_cap == 0, returns the module size (or 0 if not found).
-/// Copy: copies module bytes to buf, returns bytes written (or 0 on failure).
-pub fn sys_get_module(name_ptr: u64, name_len: u64, buf_ptr: u64, buf_cap: u64, _: u64, _: u64) -> u64 {
+/// Find the boot module called `name` (without the leading "/"), given as a
+/// user pointer and length. Fails with `SysError::InvalidArgs` for a bad name,
+/// `SysError::NotFound` for an unknown module, or `SysError::Corrupted` for
+/// one that failed its boot-time checksum.
+fn find_module(name_ptr: u64, name_len: u64) -> Result<&'static [u8], SysError> {
     if name_len == 0 || name_len > 256 {
-        return 0;
+        return Err(SysError::InvalidArgs);
     }
     if !validate_user_ptr(name_ptr, name_len) {
-        return 0;
+        return Err(SysError::InvalidArgs);
     }
 
     let name_bytes = unsafe { core::slice::from_raw_parts(name_ptr as *const u8, name_len as usize) };
-    let name = match core::str::from_utf8(name_bytes) {
-        Ok(s) => s,
-        Err(_) => return 0,
-    };
+    let name = core::str::from_utf8(name_bytes).map_err(|_| SysError::InvalidArgs)?;
 
     // Build path by prepending "/" to name
     let mut path_buf = [0u8; 258];
@@ -100,17 +344,34 @@ pub fn sys_get_module(name_ptr: u64, name_len: u64, buf_ptr: u64, buf_cap: u64,
     path_buf[1..1 + name.len()].copy_from_slice(name.as_bytes());
     let path = &path_buf[..1 + name.len()];
 
-    let response = match MODULE_REQUEST.get_response() {
-        Some(r) => r,
-        None => return 0,
-    };
+    let response = MODULE_REQUEST.get_response().ok_or(SysError::NotFound)?;
+    let module = response
+        .modules()
+        .iter()
+        .find(|m| m.path().to_bytes() == path)
+        .ok_or(SysError::NotFound)?;
+    if crate::boot_modules::is_corrupt(path) {
+        return Err(SysError::Corrupted);
+    }
 
-    let module = match response.modules().iter().find(|m| m.path().to_bytes() == path) {
-        Some(m) => m,
-        None => return 0,
-    };
+    // Limine keeps modules mapped, unchanged, for the kernel's lifetime.
+    Ok(unsafe { core::slice::from_raw_parts(module.addr() as *const u8, module.size() as usize) })
+}
 
-    let module_size = module.size();
+/// Syscall: load a Limine boot module by name.
+///
+/// Arguments: name_ptr, name_len, buf_ptr, buf_cap
+///
+/// Size query: if buf_ptr == 0 && buf_cap == 0, returns the module size.
+/// Copy: copies module bytes to buf, returns bytes written.
+/// Fails with `SysError::NotFound` for an unknown module, or `SysError::InvalidArgs`
+/// for a bad name or a buffer that is too small or not mapped.
+pub fn sys_get_module(name_ptr: u64, name_len: u64, buf_ptr: u64, buf_cap: u64, _: u64, _: u64) -> u64 {
+    let module = match find_module(name_ptr, name_len) {
+        Ok(m) => m,
+        Err(e) => return e as u64,
+    };
+    let module_size = module.len() as u64;
 
     // Size query mode
     if buf_ptr == 0 && buf_cap == 0 {
@@ -118,19 +379,45 @@ pub fn sys_get_module(name_ptr: u64, name_len: u64, buf_ptr: u64, buf_cap: u64,
     }
 
     if buf_cap < module_size {
-        return 0;
+        return SysError::InvalidArgs as u64;
     }
     if !validate_user_ptr(buf_ptr, buf_cap) {
-        return 0;
+        return SysError::InvalidArgs as u64;
     }
 
     unsafe {
-        core::ptr::copy_nonoverlapping(
-            module.addr() as *const u8,
-            buf_ptr as *mut u8,
-            module_size as usize,
-        );
+        core::ptr::copy_nonoverlapping(module.as_ptr(), buf_ptr as *mut u8, module.len());
     }
 
     module_size
 }
+
+/// Syscall: copy part of a Limine boot module, so a large module can be
+/// streamed through a small buffer.
+///
+/// Arguments: name_ptr, name_len, offset, buf_ptr, buf_cap
+///
+/// Copies up to buf_cap bytes starting at `offset` into buf and returns the
+/// number copied, which is 0 once `offset` reaches the end of the module.
+/// Fails with `SysError::NotFound` for an unknown module, or
+/// `SysError::InvalidArgs` for a bad name, an offset past the end, or a
+/// buffer that is not mapped.
+pub fn sys_get_module_chunk(name_ptr: u64, name_len: u64, offset: u64, buf_ptr: u64, buf_cap: u64, _: u64) -> u64 {
+    let module = match find_module(name_ptr, name_len) {
+        Ok(m) => m,
+        Err(e) => return e as u64,
+    };
+    let Some(rest) = module.get(offset as usize..) else {
+        return SysError::InvalidArgs as u64;
+    };
+    if !validate_user_ptr(buf_ptr, buf_cap) {
+        return SysError::InvalidArgs as u64;
+    }
+
+    let len = rest.len().min(buf_cap as usize);
+    unsafe {
+        core::ptr::copy_nonoverlapping(rest.as_ptr(), buf_ptr as *mut u8, len);
+    }
+
+    len as u64
+}
@@ -4,19 +4,26 @@ mod ipc;
 mod graphics;
 mod misc;
 mod service;
+mod ring;
+mod net;
+mod serial;
+mod input;
 
-pub use task::{sys_exit, sys_yield, sys_spawn, sys_waitpid};
-pub use memory::{sys_mmap, sys_munmap, sys_create_shared_buf, sys_map_shared_buf, sys_destroy_shared_buf};
-pub use ipc::{sys_channel_create, sys_channel_send, sys_channel_recv, sys_channel_close};
-pub use graphics::{sys_get_bounding_box, sys_get_display_info, sys_transfer_display};
-pub use misc::{sys_debug_log, sys_read_key, sys_read_mouse, sys_get_module, sys_shutdown};
+pub use task::{sys_exit, sys_yield, sys_spawn, sys_waitpid, sys_get_task_id, sys_set_priority, sys_set_task_name, sys_list_tasks, sys_kill};
+pub use memory::{sys_mmap, sys_munmap, sys_populate, sys_mincore, sys_create_shared_buf, sys_map_shared_buf, sys_resize_shared_buf, sys_grant_shared_buf, sys_destroy_shared_buf};
+pub use ipc::{sys_channel_create, sys_channel_send, sys_channel_recv, sys_channel_send_with_endpoint, sys_channel_recv_with_endpoint, sys_channel_close, sys_channel_dup, sys_channel_poll, sys_channel_stats, sys_broadcast_create, sys_channel_subscribe};
+pub use graphics::{sys_get_bounding_box, sys_get_display_info, sys_is_display_owner, sys_transfer_display};
+pub use misc::{sys_batch, sys_debug_log, sys_debug_log_str, sys_null, sys_read_key, sys_read_mouse, sys_get_module, sys_set_syscall_trace, sys_shutdown, sys_reboot, sys_get_cycles, sys_get_tsc_hz, sys_get_wallclock, sys_sleep, sys_get_module_chunk, sys_heap_stats, sys_random};
 pub use service::{sys_register_service, sys_lookup_service};
+pub use ring::sys_ring_enter;
+pub use net::{sys_net_send, sys_net_recv, sys_net_get_mac};
+pub use serial::{sys_serial_write, sys_serial_read};
+pub use input::{sys_input_record, sys_inject_input};
 
 use alloc::sync::Arc;
-use crate::memory::cpu_local_data::{get_cpu, get_local, local_apic_id_of};
+use crate::memory::cpu_local_data::get_local;
 use crate::task::local_scheduler;
-use crate::task::task::{Task, TaskKind, TaskState};
-use core::sync::atomic::Ordering;
+use crate::task::task::{Task, TaskKind};
 
 /// Returns true if [ptr, ptr+size) is fully within the current user task's
 /// allocated virtual address space and within canonical lower-half bounds.
@@ -39,6 +46,10 @@ fn validate_user_ptr(ptr: u64, size: u64) -> bool {
     };
     drop(rq);
     let inner = task.inner.lock();
+    // The stack guard page and unmapped guards are reserved but never mapped.
+    if inner.overlaps_user_stack_guard(ptr, end) || inner.overlaps_unmapped_guard(ptr, end) {
+        return false;
+    }
     crate::memory::user_vaddr::is_user_vaddr_valid_range(
         &inner.user_vaddr_set,
         x86_64::VirtAddr::new(ptr),
@@ -55,11 +66,5 @@ fn current_task_and_cpu() -> Option<(Arc<Task>, u32)> {
 
 /// Wake a sleeping task and add it to its CPU's run queue; sends a reschedule IPI if cross-CPU.
 fn wake_task(task: Arc<Task>, target_cpu_id: u32) {
-    task.state.store(TaskState::Ready, Ordering::Release);
-    local_scheduler::add(get_cpu(target_cpu_id), task);
-    let local_id = get_local().kernel_id;
-    if target_cpu_id != local_id {
-        let apic_id = local_apic_id_of(target_cpu_id);
-        crate::apic::send_fixed_ipi(apic_id, u8::from(crate::interrupt::InterruptVector::Reschedule));
-    }
+    local_scheduler::wake(task, target_cpu_id);
 }
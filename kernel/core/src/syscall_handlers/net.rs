@@ -0,0 +1,72 @@
+use kernel_api_types::net::{MacAddress, MAX_FRAME_SIZE};
+use kernel_api_types::SysError;
+use crate::drivers::e1000::{self, NetError};
+use super::validate_user_ptr;
+
+/// Syscall: transmit one raw Ethernet frame on the NIC.
+///
+/// Arguments: frame_ptr, frame_len — the frame without CRC, at most `MAX_FRAME_SIZE` bytes.
+/// Returns: 0; `NotFound` if there is no NIC; `WouldBlock` if the transmit ring is full.
+pub fn sys_net_send(frame_ptr: u64, frame_len: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if frame_len > MAX_FRAME_SIZE as u64 || !validate_user_ptr(frame_ptr, frame_len) {
+        return SysError::InvalidArgs as u64;
+    }
+    // Copy out of user memory before taking the NIC lock (see `sys_net_recv`).
+    let mut frame = [0u8; MAX_FRAME_SIZE];
+    let frame = &mut frame[..frame_len as usize];
+    unsafe { core::ptr::copy_nonoverlapping(frame_ptr as *const u8, frame.as_mut_ptr(), frame.len()) };
+    match e1000::send(frame) {
+        Ok(()) => 0,
+        Err(e) => net_error_to_code(e),
+    }
+}
+
+/// Syscall: take the next received Ethernet frame, if any. Does not block.
+///
+/// Arguments: buf_ptr, buf_cap — frames longer than `buf_cap` are truncated.
+/// Returns: the number of bytes written; `WouldBlock` if nothing has arrived;
+/// `NotFound` if there is no NIC.
+pub fn sys_net_recv(buf_ptr: u64, buf_cap: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if buf_cap == 0 || !validate_user_ptr(buf_ptr, buf_cap) {
+        return SysError::InvalidArgs as u64;
+    }
+    // Receive into a kernel buffer first: the NIC lock is a spinlock, and a
+    // copy to user memory could page fault while holding it.
+    let mut frame = [0u8; MAX_FRAME_SIZE];
+    match e1000::recv(&mut frame) {
+        Ok(Some(len)) => {
+            let len = len.min(buf_cap as usize);
+            unsafe { core::ptr::copy_nonoverlapping(frame.as_ptr(), buf_ptr as *mut u8, len) };
+            len as u64
+        }
+        Ok(None) => SysError::WouldBlock as u64,
+        Err(e) => net_error_to_code(e),
+    }
+}
+
+/// Syscall: the NIC's MAC address.
+///
+/// Arguments: out_ptr — a `MacAddress` to fill in.
+/// Returns: 0, or `NotFound` if there is no NIC.
+pub fn sys_net_get_mac(out_ptr: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if !validate_user_ptr(out_ptr, size_of::<MacAddress>() as u64) {
+        return SysError::InvalidArgs as u64;
+    }
+    match e1000::mac_address() {
+        Ok(mac) => {
+            unsafe { core::ptr::write_unaligned(out_ptr as *mut MacAddress, mac) };
+            0
+        }
+        Err(e) => net_error_to_code(e),
+    }
+}
+
+fn net_error_to_code(e: NetError) -> u64 {
+    let err = match e {
+        NetError::NoDevice    => SysError::NotFound,
+        NetError::BadLength   => SysError::InvalidArgs,
+        NetError::TxFull      => SysError::WouldBlock,
+        NetError::OutOfMemory => SysError::OutOfMemory,
+    };
+    err as u64
+}
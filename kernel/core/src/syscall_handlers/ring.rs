@@ -0,0 +1,75 @@
+use core::ops::Range;
+use core::sync::atomic::Ordering;
+use kernel_api_types::ring::{CompletionEntry, RingOp, SubmissionEntry, SyscallRing, RING_ENTRIES};
+use kernel_api_types::SysError;
+use super::ipc::{channel_recv_nonblocking, channel_send_nonblocking};
+use super::memory::{sys_mmap, sys_munmap};
+use super::validate_user_ptr;
+
+/// Syscall: ring doorbell — drain the submission queue of a `SyscallRing`.
+///
+/// Arguments: ring_ptr (a `SyscallRing` in the caller's memory)
+/// Returns: number of submissions completed, or `SysError::InvalidArgs` if the
+/// ring pointer or its indices are invalid.
+///
+/// Stops early if the completion queue is full; the remaining submissions stay
+/// queued for the next doorbell. A `Munmap` that would take any of the ring's
+/// own pages completes with `SysError::InvalidArgs` and unmaps nothing.
+pub fn sys_ring_enter(ring_ptr: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    let size = core::mem::size_of::<SyscallRing>() as u64;
+    if ring_ptr % core::mem::align_of::<SyscallRing>() as u64 != 0
+        || !validate_user_ptr(ring_ptr, size)
+    {
+        return SysError::InvalidArgs as u64;
+    }
+    let ring = unsafe { &mut *(ring_ptr as *mut SyscallRing) };
+    let ring_pages = ring_ptr & !0xFFF..(ring_ptr + size + 0xFFF) & !0xFFF;
+
+    let mut sq_head = ring.sq_head.load(Ordering::Relaxed);
+    let sq_tail = ring.sq_tail.load(Ordering::Acquire);
+    let mut cq_tail = ring.cq_tail.load(Ordering::Relaxed);
+    if sq_tail.wrapping_sub(sq_head) as usize > RING_ENTRIES {
+        return SysError::InvalidArgs as u64;
+    }
+
+    let mut completed = 0;
+    while sq_head != sq_tail {
+        let cq_head = ring.cq_head.load(Ordering::Acquire);
+        if cq_tail.wrapping_sub(cq_head) as usize >= RING_ENTRIES {
+            break;
+        }
+
+        // Copy the entry out first — userspace may scribble on the slot concurrently.
+        let sqe: SubmissionEntry =
+            unsafe { core::ptr::read_volatile(&ring.sq[sq_head as usize % RING_ENTRIES]) };
+        let cqe = execute(&sqe, &ring_pages);
+        unsafe {
+            core::ptr::write_volatile(&mut ring.cq[cq_tail as usize % RING_ENTRIES], cqe);
+        }
+
+        cq_tail = cq_tail.wrapping_add(1);
+        sq_head = sq_head.wrapping_add(1);
+        ring.cq_tail.store(cq_tail, Ordering::Release);
+        ring.sq_head.store(sq_head, Ordering::Release);
+        completed += 1;
+    }
+
+    completed
+}
+
+/// Run one submission. `ring_pages` is the range the ring lives in, which the
+/// completion writes still need.
+fn execute(sqe: &SubmissionEntry, ring_pages: &Range<u64>) -> CompletionEntry {
+    let [a0, a1, a2, _] = sqe.args;
+    let (result, aux) = match RingOp::from_u32(sqe.op) {
+        Some(RingOp::Mmap) => (sys_mmap(a0, a1, 0, 0, 0, 0), 0),
+        Some(RingOp::Munmap) if a0 < ring_pages.end && a0.saturating_add(a1) > ring_pages.start => {
+            (SysError::InvalidArgs as u64, 0)
+        }
+        Some(RingOp::Munmap) => (sys_munmap(a0, a1, 0, 0, 0, 0), 0),
+        Some(RingOp::ChannelSend) => (channel_send_nonblocking(a0, a1, a2), 0),
+        Some(RingOp::ChannelRecv) => channel_recv_nonblocking(a0, a1, a2),
+        None => (SysError::NoSys as u64, 0),
+    };
+    CompletionEntry { user_data: sqe.user_data, result, aux }
+}
@@ -0,0 +1,39 @@
+use kernel_api_types::serial::SERIAL_CHUNK_SIZE;
+use kernel_api_types::SysError;
+use crate::drivers::serial;
+use super::validate_user_ptr;
+
+/// Syscall: transmit bytes on COM1.
+///
+/// Arguments: buf_ptr, len — at most `SERIAL_CHUNK_SIZE` bytes.
+/// Returns: 0, or `InvalidArgs`.
+pub fn sys_serial_write(buf_ptr: u64, len: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if len > SERIAL_CHUNK_SIZE as u64 || !validate_user_ptr(buf_ptr, len) {
+        return SysError::InvalidArgs as u64;
+    }
+    // Copy out of user memory first: the port lock is a spinlock.
+    let mut bytes = [0u8; SERIAL_CHUNK_SIZE];
+    let bytes = &mut bytes[..len as usize];
+    unsafe { core::ptr::copy_nonoverlapping(buf_ptr as *const u8, bytes.as_mut_ptr(), bytes.len()) };
+    serial::write(bytes);
+    0
+}
+
+/// Syscall: take the bytes COM1 has received. Does not block.
+///
+/// Arguments: buf_ptr, buf_cap — at most `SERIAL_CHUNK_SIZE` bytes are read.
+/// Returns: the number of bytes written; `WouldBlock` if nothing has arrived.
+pub fn sys_serial_read(buf_ptr: u64, buf_cap: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if buf_cap == 0 || !validate_user_ptr(buf_ptr, buf_cap) {
+        return SysError::InvalidArgs as u64;
+    }
+    let mut bytes = [0u8; SERIAL_CHUNK_SIZE];
+    let cap = bytes.len().min(buf_cap as usize);
+    match serial::read(&mut bytes[..cap]) {
+        0 => SysError::WouldBlock as u64,
+        n => {
+            unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), buf_ptr as *mut u8, n) };
+            n as u64
+        }
+    }
+}
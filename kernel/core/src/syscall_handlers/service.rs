@@ -1,20 +1,25 @@
-use kernel_api_types::{MAX_SERVICE_NAME_LEN, SVC_ERR_INVALID_ARGS, SVC_ERR_NOT_FOUND, SVC_OK};
+use kernel_api_types::{SysError, MAX_SERVICE_NAME_LEN};
 use crate::ipc::{EndpointRole, ENDPOINT_REGISTRY};
 
+/// Syscall: register a send endpoint under a service name.
+///
+/// Arguments: name_ptr, name_len, send_ep
+/// A broadcast endpoint may be registered too, so subscribers can find it.
+/// Returns: 0, or a negative `SysError` code (`AlreadyExists` if the name is taken).
 pub fn sys_register_service(name_ptr: u64, name_len: u64, send_ep: u64, _: u64, _: u64, _: u64) -> u64 {
     if name_len == 0 || name_len > MAX_SERVICE_NAME_LEN as u64 {
-        return SVC_ERR_INVALID_ARGS;
+        return SysError::InvalidArgs as u64;
     }
     if !super::validate_user_ptr(name_ptr, name_len) {
-        return SVC_ERR_INVALID_ARGS;
+        return SysError::InvalidArgs as u64;
     }
 
-    // Verify send_ep exists and is a Send endpoint
+    // Verify send_ep exists and is a Send (or Broadcast) endpoint
     {
         let registry = ENDPOINT_REGISTRY.lock();
         match registry.get(&send_ep) {
-            Some(ep) if ep.role == EndpointRole::Send => {}
-            _ => return SVC_ERR_INVALID_ARGS,
+            Some(ep) if matches!(ep.role, EndpointRole::Send | EndpointRole::Broadcast) => {}
+            _ => return SysError::InvalidArgs as u64,
         }
     }
 
@@ -27,7 +32,7 @@ pub fn sys_register_service(name_ptr: u64, name_len: u64, send_ep: u64, _: u64,
             let id = t.id;
             (t, (id, cpu))
         }
-        None => return SVC_ERR_INVALID_ARGS,
+        None => return SysError::InvalidArgs as u64,
     };
 
     match crate::service_registry::register(name_bytes, send_ep, task_id.0) {
@@ -37,21 +42,26 @@ pub fn sys_register_service(name_ptr: u64, name_len: u64, send_ep: u64, _: u64,
             let copy_len = name_bytes.len().min(MAX_SERVICE_NAME_LEN);
             name_arr[..copy_len].copy_from_slice(&name_bytes[..copy_len]);
             task.inner.lock().registered_services.push(name_arr);
-            SVC_OK
+            0
         }
-        Err(code) => code,
+        Err(e) => e as u64,
     }
 }
 
+/// Syscall: look up a service by name.
+///
+/// Arguments: name_ptr, name_len, ep_out_ptr
+/// Returns: 0 and writes the send endpoint to `ep_out_ptr`, or a negative
+/// `SysError` code (`NotFound` if no such service is registered).
 pub fn sys_lookup_service(name_ptr: u64, name_len: u64, ep_out_ptr: u64, _: u64, _: u64, _: u64) -> u64 {
     if name_len == 0 || name_len > MAX_SERVICE_NAME_LEN as u64 {
-        return SVC_ERR_INVALID_ARGS;
+        return SysError::InvalidArgs as u64;
     }
     if !super::validate_user_ptr(name_ptr, name_len) {
-        return SVC_ERR_INVALID_ARGS;
+        return SysError::InvalidArgs as u64;
     }
     if !super::validate_user_ptr(ep_out_ptr, 8) {
-        return SVC_ERR_INVALID_ARGS;
+        return SysError::InvalidArgs as u64;
     }
 
     let name_bytes = unsafe {
@@ -61,8 +71,8 @@ pub fn sys_lookup_service(name_ptr: u64, name_len: u64, ep_out_ptr: u64, _: u64,
     match crate::service_registry::lookup(name_bytes) {
         Some(id) => {
             unsafe { core::ptr::write(ep_out_ptr as *mut u64, id) };
-            SVC_OK
+            0
         }
-        None => SVC_ERR_NOT_FOUND,
+        None => SysError::NotFound as u64,
     }
 }
@@ -1,52 +1,111 @@
-use crate::memory::cpu_local_data::get_local;
+use crate::interrupt::InterruptVector;
+use crate::memory::cpu_local_data::{cpus_count, get_local, local_apic_id_of, try_get_ready_cpu};
 use crate::task::global_scheduler::TASK_TABLE;
-use crate::task::task::{TaskId, TaskKind, TaskState};
+use crate::task::local_scheduler;
+use crate::task::task::{Privilege, Task, TaskId, TaskKind, TaskState};
+use crate::user_task_from_elf::SpawnError;
+use alloc::sync::Arc;
 use core::sync::atomic::Ordering;
+use kernel_api_types::task::{
+    TaskInfo, MAX_SPAWN_IMAGE_SIZE, SPAWN_SYSTEM, TASK_KIND_KERNEL, TASK_KIND_USER, TASK_STATE_INITIALIZING, TASK_STATE_READY,
+    TASK_STATE_RUNNING, TASK_STATE_SLEEPING, TASK_STATE_ZOMBIE,
+};
+use kernel_api_types::{SysError, EXIT_KILLED, MAX_PRIORITY, MAX_TASK_NAME_LEN};
 use super::{current_task_and_cpu, wake_task};
 
 /// Syscall: exit the current task.
 ///
-/// Closes owned IPC endpoints, stores the exit code, wakes any waitpid waiter
-/// (or removes from TASK_TABLE immediately if detached), then halts.
+/// Tears the task down with `terminate`, then halts until the next tick
+/// schedules it away for good.
 pub fn sys_exit(exit_code: u64) -> ! {
-    let cpu = get_local();
-
-    // 1. Collect task Arc and owned endpoints (interrupts still disabled by SFMask)
-    let (task_arc, endpoints) = {
-        let rq = cpu.run_queue.get().unwrap().lock();
-        let t = rq.current_task.clone();
-        let eps = t.as_ref()
-            .map(|t| t.inner.lock().owned_endpoints.clone())
-            .unwrap_or_default();
-        (t, eps)
-    };
+    // Interrupts are still disabled by SFMask.
+    if let Some((task, _)) = current_task_and_cpu() {
+        terminate(&task, exit_code);
+    }
+
+    x86_64::instructions::interrupts::enable();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
 
-    // 2. Close owned IPC endpoints
+/// End `task` with `exit_code`, whether it exited or was killed.
+///
+/// Claims the task by removing it from the registry, so a task that exits
+/// while being killed is only torn down once. Then closes its IPC endpoints,
+/// unregisters its services, gives back the display if it owned it, stores
+/// the exit code and marks it a zombie, and wakes any waitpid waiter.
+///
+/// A zombie whose parent is still alive stays in TASK_TABLE until it is
+/// reaped, so the parent can collect the exit code even if it calls waitpid
+/// late. One without a live parent is removed at once, and a parent that ends
+/// takes its unreaped zombie children with it. Its address space and kernel
+/// stack are freed when the last `Arc` goes.
+/// Returns false if the task had already ended.
+fn terminate(task: &Arc<Task>, exit_code: u64) -> bool {
+    if !crate::task::registry::unregister(task.id) {
+        return false;
+    }
+
+    let endpoints = task.inner.lock().owned_endpoints.clone();
     for ep in endpoints {
         let _ = crate::ipc::close_endpoint(ep);
     }
+    crate::service_registry::unregister_all_for_task(task.id);
+    crate::graphics::display::release_display(task.id.to_u64());
 
-    // 2b. Unregister any services this task registered
-    if let Some(task) = &task_arc {
-        crate::service_registry::unregister_all_for_task(task.id);
+    task.exit_code.store(exit_code, Ordering::Release);
+    task.state.store(TaskState::Zombie, Ordering::Release);
+    let waiter = task.exit_waiter.lock().take();
+    if let Some((waiter, w_cpu)) = waiter {
+        wake_task(waiter, w_cpu);
     }
 
-    // 3. Set exit code + Zombie, wake waiter or detach
-    if let Some(task) = task_arc {
-        task.exit_code.store(exit_code, Ordering::Release);
-        task.state.store(TaskState::Zombie, Ordering::Relaxed);
-        if let Some((waiter, w_cpu)) = task.exit_waiter.lock().take() {
-            wake_task(waiter, w_cpu);
-        } else {
-            // No waiter: detached task — remove immediately
-            TASK_TABLE.lock().remove(&task.id);
-        }
+    // Both checks run under TASK_TABLE, after this task left the registry and
+    // became a zombie, so a child and parent ending together can't each leave
+    // the other's cleanup to the other.
+    let mut table = TASK_TABLE.lock();
+    let parent_alive = task.parent.is_some_and(|id| crate::task::lookup(id.to_u64()).is_some());
+    if !parent_alive {
+        table.remove(&task.id);
     }
+    table.retain(|_, child| child.parent != Some(task.id) || child.state.load(Ordering::Acquire) != TaskState::Zombie);
+    true
+}
 
-    x86_64::instructions::interrupts::enable();
-    loop {
-        x86_64::instructions::hlt();
+/// Syscall: kill a task the caller spawned.
+///
+/// Arguments: task_id
+/// The task ends as if it had exited with `EXIT_KILLED` (see `terminate`). It
+/// is taken out of every run queue; if it is running on another CPU, that CPU
+/// gets a reschedule IPI and drops it on its next tick.
+/// Returns: 0, `SysError::NotFound` if the task doesn't exist or has already
+/// exited, or `SysError::PermissionDenied` if the caller didn't spawn it.
+pub fn sys_kill(task_id: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    let Some((caller, local_id)) = current_task_and_cpu() else {
+        return SysError::NotFound as u64;
+    };
+    let Some(target) = crate::task::lookup(task_id) else {
+        return SysError::NotFound as u64;
+    };
+    if target.parent != Some(caller.id) {
+        return SysError::PermissionDenied as u64;
+    }
+
+    // Before anything wakes the task's waiter, so the waiter never sees it
+    // back in a live state it stored on the way into a syscall.
+    target.killed.store(true, Ordering::Release);
+    if !terminate(&target, EXIT_KILLED) {
+        return SysError::NotFound as u64;
     }
+
+    for id in 0..cpus_count() as u32 {
+        let Some(cpu) = try_get_ready_cpu(id) else { continue };
+        if local_scheduler::evict(cpu, &target) && id != local_id {
+            crate::apic::send_fixed_ipi(local_apic_id_of(id), u8::from(InterruptVector::Reschedule));
+        }
+    }
+    0
 }
 
 /// Syscall: yield the current timeslice.
@@ -66,57 +125,186 @@ pub fn sys_yield(_: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     0
 }
 
+/// Syscall: return the calling task's ID (as returned by `sys_spawn` to its parent).
+///
+/// Returns: task ID, or `SysError::NotFound` if there is no current task.
+pub fn sys_get_task_id(_: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    match current_task_and_cpu() {
+        Some((task, _)) => task.id.to_u64(),
+        None => SysError::NotFound as u64,
+    }
+}
+
+/// Syscall: set the calling task's scheduling priority.
+///
+/// Arguments: priority, 0 (lowest) to `MAX_PRIORITY`
+/// Only a system task may raise its priority. A user task, which starts at
+/// `DEFAULT_PRIORITY`, may only lower it, so it can't starve the servers.
+/// Returns: 0, `SysError::InvalidArgs` for an out-of-range priority, or
+/// `SysError::PermissionDenied` if a user task asks for a higher one.
+pub fn sys_set_priority(priority: u64, _: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if priority > MAX_PRIORITY {
+        return SysError::InvalidArgs as u64;
+    }
+    let Some((task, _)) = current_task_and_cpu() else {
+        return SysError::NotFound as u64;
+    };
+    if task.privilege != Privilege::System && priority > task.base_priority.load(Ordering::Relaxed) as u64 {
+        return SysError::PermissionDenied as u64;
+    }
+    task.base_priority.store(priority as u8, Ordering::Relaxed);
+    0
+}
+
+/// Syscall: name the calling task, for logs and task listings.
+///
+/// Arguments: name_ptr, name_len
+/// The name must be UTF-8. Names longer than `MAX_TASK_NAME_LEN` bytes are
+/// truncated at a character boundary.
+/// Returns: 0, or `SysError::InvalidArgs` for a bad buffer or invalid UTF-8.
+pub fn sys_set_task_name(name_ptr: u64, name_len: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if name_len > 0 && !super::validate_user_ptr(name_ptr, name_len) {
+        return SysError::InvalidArgs as u64;
+    }
+    let len = (name_len as usize).min(MAX_TASK_NAME_LEN);
+    let bytes = if len > 0 {
+        unsafe { core::slice::from_raw_parts(name_ptr as *const u8, len) }
+    } else {
+        &[]
+    };
+    let name = match core::str::from_utf8(bytes) {
+        Ok(name) => name,
+        // Cut inside a multi-byte character: keep the characters before it.
+        Err(e) if e.error_len().is_none() => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+        Err(_) => return SysError::InvalidArgs as u64,
+    };
+    match current_task_and_cpu() {
+        Some((task, _)) => {
+            task.set_name(name);
+            0
+        }
+        None => SysError::NotFound as u64,
+    }
+}
+
+/// Syscall: describe every task in the system.
+///
+/// Arguments: buf_ptr, buf_cap (bytes)
+/// Writes one `TaskInfo` per task to the buffer, as many as fit, in task ID
+/// order. Tasks are read from `TASK_TABLE`, which holds every task from spawn
+/// until it is reaped, rather than from the per-CPU run queues: the scheduler
+/// never takes `TASK_TABLE`, so holding it here can't deadlock with a tick on
+/// any CPU, and it also covers sleeping tasks, which sit in no run queue.
+/// Returns: the total number of tasks, which may exceed what fit, or a
+/// negative `SysError` code.
+pub fn sys_list_tasks(buf_ptr: u64, buf_cap: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
+    if buf_cap > 0 && !super::validate_user_ptr(buf_ptr, buf_cap) {
+        return SysError::InvalidArgs as u64;
+    }
+    let slots = buf_cap as usize / size_of::<TaskInfo>();
+
+    let table = TASK_TABLE.lock();
+    for (i, task) in table.values().take(slots).enumerate() {
+        let info = task_info(task);
+        unsafe { (buf_ptr as *mut TaskInfo).add(i).write_unaligned(info) };
+    }
+    table.len() as u64
+}
+
+fn task_info(task: &Task) -> TaskInfo {
+    let mut info = TaskInfo {
+        id: task.id.to_u64(),
+        cpu_ticks: task.cpu_ticks.load(Ordering::Relaxed),
+        kind: match task.kind {
+            TaskKind::Kernel => TASK_KIND_KERNEL,
+            TaskKind::User => TASK_KIND_USER,
+        },
+        state: match task.run_state() {
+            TaskState::Initializing => TASK_STATE_INITIALIZING,
+            TaskState::Running => TASK_STATE_RUNNING,
+            TaskState::Ready => TASK_STATE_READY,
+            TaskState::Sleeping => TASK_STATE_SLEEPING,
+            TaskState::Zombie => TASK_STATE_ZOMBIE,
+        },
+        priority: task.priority(),
+        cpu: task.cpu.load(Ordering::Relaxed),
+        ..TaskInfo::EMPTY
+    };
+    if let Some(name) = task.name() {
+        info.set_name(&name);
+    }
+    info
+}
+
 /// Syscall: spawn a new user task from ELF bytes in the caller's memory.
 ///
-/// Arguments: elf_ptr, elf_len, child_arg
-/// Returns: task ID on success, 0 on failure.
-pub fn sys_spawn(elf_ptr: u64, elf_len: u64, child_arg: u64, _: u64, _: u64, _: u64) -> u64 {
-    if elf_len == 0 || elf_len > 64 * 1024 * 1024 {
-        return 0;
+/// Arguments: elf_ptr, elf_len, child_arg, flags
+/// The child is a user task, or a system task with `SPAWN_SYSTEM`, which
+/// only a system task may ask for.
+/// Returns: task ID on success, `SysError::InvalidArgs` for a buffer that is
+/// empty, over `MAX_SPAWN_IMAGE_SIZE`, or not entirely mapped user memory, or
+/// for unknown flags, `SysError::InvalidElf` for an image that isn't a
+/// loadable ELF, `SysError::OutOfMemory` if building the task ran out of
+/// memory, or `SysError::PermissionDenied` from a kernel task or for
+/// `SPAWN_SYSTEM` from a user task.
+pub fn sys_spawn(elf_ptr: u64, elf_len: u64, child_arg: u64, flags: u64, _: u64, _: u64) -> u64 {
+    if elf_len == 0 || elf_len > MAX_SPAWN_IMAGE_SIZE || flags & !SPAWN_SYSTEM != 0 {
+        return SysError::InvalidArgs as u64;
     }
+    // Checked before the slice exists, so a kernel address can't be read.
     if !super::validate_user_ptr(elf_ptr, elf_len) {
-        return 0;
+        return SysError::InvalidArgs as u64;
     }
 
-    let cpu = get_local();
-    {
-        let rq = cpu.run_queue.get().unwrap().lock();
-        match &rq.current_task {
-            Some(t) if t.kind == TaskKind::User => {}
-            _ => return 0,
+    let parent = match current_task_and_cpu() {
+        Some((t, _)) if t.kind == TaskKind::User => t,
+        _ => return SysError::PermissionDenied as u64,
+    };
+    let privilege = if flags & SPAWN_SYSTEM != 0 {
+        if parent.privilege != Privilege::System {
+            return SysError::PermissionDenied as u64;
         }
-    }
+        Privilege::System
+    } else {
+        Privilege::User
+    };
 
     let elf_bytes = unsafe {
         core::slice::from_raw_parts(elf_ptr as *const u8, elf_len as usize)
     };
 
     match crate::user_task_from_elf::create_user_task_from_elf_bytes(elf_bytes, child_arg) {
-        Ok(task) => {
+        Ok(mut task) => {
+            task.parent = Some(parent.id);
+            task.privilege = privilege;
             let id = task.id.to_u64();
             crate::task::global_scheduler::spawn_task(task);
             id
         }
-        Err(_) => 0,
+        Err(SpawnError::InvalidElf) => SysError::InvalidElf as u64,
+        Err(SpawnError::OutOfMemory) => SysError::OutOfMemory as u64,
     }
 }
 
 /// Syscall: wait for a task to exit and collect its exit code.
 ///
 /// Arguments: target_task_id, exit_code_out_ptr
-/// Returns: 0 on success, 1 on error (task not found or invalid pointer).
+/// Returns: 0 on success, `SysError::InvalidArgs` for a bad pointer, or
+/// `SysError::NotFound` if the task doesn't exist (or was already reaped).
 pub fn sys_waitpid(target_id: u64, exit_code_out_ptr: u64, _: u64, _: u64, _: u64, _: u64) -> u64 {
     if !super::validate_user_ptr(exit_code_out_ptr, 8) {
-        return 1;
+        return SysError::InvalidArgs as u64;
     }
 
     loop {
         let target = TASK_TABLE.lock().get(&TaskId::from_u64(target_id)).cloned();
         let target = match target {
             Some(t) => t,
-            None => return 1,
+            None => return SysError::NotFound as u64,
         };
 
+        // `terminate` stores the exit code before `Zombie`, and nothing moves
+        // a task out of `Zombie` again.
         if target.state.load(Ordering::Acquire) == TaskState::Zombie {
             let code = target.exit_code.load(Ordering::Acquire);
             TASK_TABLE.lock().remove(&TaskId::from_u64(target_id));
@@ -124,10 +312,16 @@ pub fn sys_waitpid(target_id: u64, exit_code_out_ptr: u64, _: u64, _: u64, _: u6
             return 0;
         }
 
-        // Target still alive: register as exit waiter and sleep
+        // Target still alive: register as exit waiter and sleep. `terminate`
+        // stores `Zombie` before it takes the waiter, so if the target is not
+        // a zombie under the lock, it will find this waiter.
         if let Some((self_task, cpu_id)) = current_task_and_cpu() {
-            *target.exit_waiter.lock() = Some((self_task.clone(), cpu_id));
-            self_task.state.store(TaskState::Sleeping, Ordering::Release);
+            let mut exit_waiter = target.exit_waiter.lock();
+            if target.state.load(Ordering::Acquire) == TaskState::Zombie {
+                continue;
+            }
+            *exit_waiter = Some((self_task.clone(), cpu_id));
+            self_task.set_state(TaskState::Sleeping);
         }
 
         x86_64::instructions::interrupts::enable();
@@ -13,7 +13,7 @@ static NEXT_SPAWN_CPU: AtomicUsize = AtomicUsize::new(0);
 
 pub fn spawn_task(task: Task) {
     let task_id = task.id;
-    let target_id = interrupts::without_interrupts(|| {
+    let (target_id, arc_task) = interrupts::without_interrupts(|| {
         task.state.store(TaskState::Ready, Ordering::Relaxed);
         let arc_task = Arc::new(task);
 
@@ -23,6 +23,7 @@ pub fn spawn_task(task: Task) {
             panic!("Task with the same ID already exists");
         }
         drop(tasks);
+        crate::task::registry::register(&arc_task);
 
         // Round-robin dispatch: pick a CPU that is fully initialized (has a run queue).
         // During early boot, only the BSP is ready; APs join as they initialize.
@@ -37,7 +38,8 @@ pub fn spawn_task(task: Task) {
                 let local = get_local();
                 (local.kernel_id as usize, local)
             });
-        crate::task::local_scheduler::add(target_cpu, arc_task);
+        arc_task.cpu.store(target_id as u32, Ordering::Relaxed);
+        crate::task::local_scheduler::add(target_cpu, arc_task.clone());
 
         // If target is a different CPU, send reschedule IPI to wake it from hlt
         let local = get_local();
@@ -46,24 +48,25 @@ pub fn spawn_task(task: Task) {
             crate::apic::send_fixed_ipi(apic_id, u8::from(InterruptVector::Reschedule));
         }
 
-        target_id
+        (target_id, arc_task)
     });
 
     log::info!(
-        "Task {:?} scheduled on CPU {} and pushed to ready queue",
-        task_id,
+        "Task {} scheduled on CPU {} and pushed to ready queue",
+        arc_task,
         target_id
     );
 }
 
-/// Spawn a task pinned to the calling CPU's run queue.
+/// Make `task` the calling CPU's idle task.
 ///
-/// Unlike `spawn_task`, this bypasses round-robin dispatch and always places
-/// the task on the local CPU. Used for idle tasks so each CPU gets its own.
-pub fn spawn_local_task(task: Task) {
+/// Unlike `spawn_task`, this bypasses round-robin dispatch and always uses the
+/// local CPU, so each CPU gets its own. The idle task is not put in the ready
+/// queue: the scheduler runs it only when nothing else on this CPU is runnable.
+pub fn spawn_idle_task(task: Task) {
     let task_id = task.id;
     let cpu = get_local();
-    interrupts::without_interrupts(|| {
+    let arc_task = interrupts::without_interrupts(|| {
         task.state.store(TaskState::Ready, Ordering::Relaxed);
         let arc_task = Arc::new(task);
 
@@ -72,13 +75,16 @@ pub fn spawn_local_task(task: Task) {
             panic!("Task with the same ID already exists");
         }
         drop(tasks);
+        crate::task::registry::register(&arc_task);
 
-        crate::task::local_scheduler::add(cpu, arc_task);
+        arc_task.cpu.store(cpu.kernel_id, Ordering::Relaxed);
+        crate::task::local_scheduler::set_idle(cpu, arc_task.clone());
+        arc_task
     });
 
     log::info!(
-        "Task {:?} pinned to local CPU {}",
-        task_id,
+        "Task {} is the idle task of CPU {}",
+        arc_task,
         cpu.kernel_id
     );
 }
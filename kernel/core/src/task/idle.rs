@@ -0,0 +1,119 @@
+//! The idle task, and what a CPU does to save power while it has nothing to run.
+//!
+//! The scheduler runs a CPU's idle task only when nothing in its run queue
+//! can run. Instead of taking the 1 ms scheduler tick while halted, the idle
+//! task pushes the LAPIC deadline out to this CPU's next one-shot timer, at
+//! most `MAX_IDLE_MS` away, so an idle CPU wakes a few times a second rather
+//! than a thousand. Anything that gives it work still wakes it at once: a
+//! reschedule IPI, an interrupt on this CPU, or, when waiting in MWAIT, the
+//! write to `ready_count` itself. On waking the 1 ms tick is restored, so the
+//! scheduler picks the new work up on the next tick as before.
+//!
+//! Where MWAIT is available the CPU waits in the deepest C-state it
+//! advertises; otherwise it uses HLT (C1). Time spent waiting is added to the
+//! CPU's `idle_ns`.
+
+use crate::memory::cpu_local_data::{get_local, CpuLocalData};
+use crate::time::{self, lapic_timer};
+use core::sync::atomic::Ordering;
+use raw_cpuid::CpuId;
+use spin::Once;
+use x86_64::instructions::interrupts;
+
+/// Longest the LAPIC timer is left unarmed on an idle CPU.
+pub const MAX_IDLE_MS: u64 = 100;
+
+/// The scheduler tick, restored whenever an idle CPU wakes.
+const TICK_NS: u64 = 1_000_000;
+
+static MWAIT_HINT: Once<Option<u32>> = Once::new();
+
+/// Each CPU's idle task: wait for work, forever.
+pub fn idle_task() -> ! {
+    let cpu = get_local();
+    loop {
+        idle_once(cpu);
+    }
+}
+
+/// Wait once for an interrupt, stretching the tick first if this CPU has
+/// nothing queued. Returns with interrupts enabled.
+pub fn idle_once(cpu: &CpuLocalData) {
+    interrupts::disable();
+    let stretched = cpu.ready_count.load(Ordering::Relaxed) == 0;
+    if stretched {
+        lapic_timer::set_deadline(idle_deadline_ms() * 1_000_000);
+    }
+    cpu.idle_since_ns.store(time::now_ns().max(1), Ordering::Relaxed);
+    wait(cpu);
+    interrupts::disable();
+    end_idle(cpu);
+    if stretched {
+        lapic_timer::set_deadline(TICK_NS);
+    }
+    interrupts::enable();
+}
+
+/// Close this CPU's open idle interval, if any, and add it to `idle_ns`.
+/// The timer interrupt calls this before the scheduler can switch away from
+/// the idle task, so time spent running the task it switches to is not
+/// counted as idle.
+pub fn end_idle(cpu: &CpuLocalData) {
+    let since = cpu.idle_since_ns.swap(0, Ordering::Relaxed);
+    if since != 0 {
+        cpu.idle_ns.fetch_add(time::now_ns().saturating_sub(since), Ordering::Relaxed);
+    }
+}
+
+/// Milliseconds until this CPU's next one-shot timer is due, capped at
+/// `MAX_IDLE_MS` and at least one tick.
+fn idle_deadline_ms() -> u64 {
+    let now = time::now_ms();
+    let next = crate::time::timers::next_deadline_ms().unwrap_or(u64::MAX);
+    next.saturating_sub(now).clamp(1, MAX_IDLE_MS)
+}
+
+/// Enable interrupts and wait for one, in MWAIT if possible.
+fn wait(cpu: &CpuLocalData) {
+    match *MWAIT_HINT.call_once(mwait_hint) {
+        Some(hint) => unsafe {
+            // Arm the monitor before the last check, so an `add` from
+            // another CPU after it still ends the wait.
+            core::arch::asm!("monitor", in("rax") cpu.ready_count.as_ptr(), in("ecx") 0, in("edx") 0, options(nostack));
+            if cpu.ready_count.load(Ordering::Relaxed) == 0 {
+                // STI's one-instruction shadow keeps an interrupt from
+                // landing between it and MWAIT.
+                core::arch::asm!("sti", "mwait", in("eax") hint, in("ecx") 0, options(nostack));
+            } else {
+                interrupts::enable();
+            }
+        },
+        None => interrupts::enable_and_hlt(),
+    }
+}
+
+/// The MWAIT hint for the deepest C-state this CPU supports, or `None` if it
+/// has no MWAIT. Without an always-running APIC timer (ARAT) the timer may
+/// stop below C1, and it is what wakes an idle CPU, so C1 is the limit then.
+fn mwait_hint() -> Option<u32> {
+    let cpuid = CpuId::new();
+    if !cpuid.get_feature_info()?.has_monitor_mwait() {
+        return None;
+    }
+    if !cpuid.get_thermal_power_info().is_some_and(|t| t.has_arat()) {
+        return Some(0);
+    }
+    let mwait = cpuid.get_monitor_mwait_info()?;
+    let substates = [
+        mwait.supported_c1_states(),
+        mwait.supported_c2_states(),
+        mwait.supported_c3_states(),
+        mwait.supported_c4_states(),
+        mwait.supported_c5_states(),
+        mwait.supported_c6_states(),
+        mwait.supported_c7_states(),
+    ];
+    // The hint names C-state n + 1 as n in bits 7:4.
+    let deepest = substates.iter().rposition(|&n| n > 0).unwrap_or(0);
+    Some((deepest as u32) << 4)
+}
@@ -1,4 +1,5 @@
-use crate::memory::cpu_local_data::{CpuLocalData, get_local};
+use crate::interrupt::InterruptVector;
+use crate::memory::cpu_local_data::{CpuLocalData, get_cpu, get_local, local_apic_id_of};
 use crate::memory::MEMORY;
 use crate::task::task::{CpuContext, Task, TaskState};
 use alloc::collections::VecDeque;
@@ -12,6 +13,9 @@ use x86_64::PhysAddr;
 pub struct RunQueue {
     pub current_task: Option<Arc<Task>>,
     pub ready: VecDeque<Arc<Task>>,
+    /// Runs only when no task in `ready` can, so it never takes a quantum from
+    /// real work. It is never queued in `ready` itself.
+    pub idle_task: Option<Arc<Task>>,
 }
 
 /// Safety: cpu_init must be called before
@@ -22,53 +26,158 @@ pub fn init_run_queue() {
         spin::Mutex::new(RunQueue {
             current_task: None,
             ready: VecDeque::new(),
+            idle_task: None,
         })
     });
 }
 
-/// Add a task to the local run queue for scheduling.
+/// Add a task to the local run queue for scheduling. A killed task is
+/// dropped instead.
 pub fn add(cpu: &CpuLocalData, task: Arc<Task>) {
     interrupts::without_interrupts(|| {
         let mut rq = cpu.run_queue.get().unwrap().lock();
+        // Checked under the lock so a concurrent `evict` either sees the task
+        // queued or this sees it killed.
+        if task.is_killed() {
+            return;
+        }
         rq.ready.push_back(task);
         cpu.ready_count.fetch_add(1, Ordering::Relaxed);
     });
 }
 
+/// Make a sleeping `task` ready on CPU `cpu_id`, and have that CPU's
+/// scheduler look at it now rather than at its next tick: another CPU gets a
+/// reschedule IPI, and on this CPU the timer fires early if `task` outranks
+/// the running one. A killed task is left asleep.
+pub fn wake(task: Arc<Task>, cpu_id: u32) {
+    // A killed task stays a zombie; dropping this Arc may free it.
+    if task.is_killed() {
+        return;
+    }
+    task.set_state(TaskState::Ready);
+    let priority = task.priority();
+    add(get_cpu(cpu_id), task);
+
+    let local = get_local();
+    if cpu_id != local.kernel_id {
+        crate::apic::send_fixed_ipi(local_apic_id_of(cpu_id), u8::from(InterruptVector::Reschedule));
+    } else if interrupts::without_interrupts(|| outranks_current(local, priority)) {
+        request_reschedule();
+    }
+}
+
+/// Whether a task of `priority` should take this CPU from its current task.
+/// Any task outranks the idle task.
+fn outranks_current(cpu: &CpuLocalData, priority: u8) -> bool {
+    let rq = cpu.run_queue.get().unwrap().lock();
+    match &rq.current_task {
+        Some(current) if !rq.idle_task.as_ref().is_some_and(|idle| Arc::ptr_eq(idle, current)) => {
+            priority > current.priority()
+        }
+        _ => true,
+    }
+}
+
+/// Run this CPU's scheduler as soon as interrupts are enabled, instead of at
+/// the next tick, by making the LAPIC timer fire now. Its handler saves the
+/// interrupted context and picks the next task as on any tick (and charges
+/// the outgoing task a quantum).
+pub fn request_reschedule() {
+    crate::time::lapic_timer::set_deadline(0);
+}
+
+/// Take a killed `task` out of this CPU's ready queue. Returns true if it is
+/// instead the task running on this CPU, which the next tick drops.
+pub fn evict(cpu: &CpuLocalData, task: &Arc<Task>) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut rq = cpu.run_queue.get().unwrap().lock();
+        let queued = rq.ready.len();
+        rq.ready.retain(|t| !Arc::ptr_eq(t, task));
+        cpu.ready_count.fetch_sub(queued - rq.ready.len(), Ordering::Relaxed);
+        rq.current_task.as_ref().is_some_and(|t| Arc::ptr_eq(t, task))
+    })
+}
+
+/// Make `task` this CPU's idle task.
+pub fn set_idle(cpu: &CpuLocalData, task: Arc<Task>) {
+    interrupts::without_interrupts(|| {
+        let mut rq = cpu.run_queue.get().unwrap().lock();
+        // The context lives inside the Arc, so the pointer stays valid while `rq` holds it.
+        let ctx_ptr = &mut task.inner.lock().context as *mut CpuContext;
+        cpu.idle_context_ptr.store(ctx_ptr, Ordering::Relaxed);
+        rq.idle_task = Some(task);
+    });
+}
+
+/// Index and priority of the first of the highest-priority tasks in `ready`.
+/// Taking the first keeps equal priorities round-robin.
+fn highest_priority(ready: &VecDeque<Arc<Task>>) -> Option<(usize, u8)> {
+    let mut best: Option<(usize, u8)> = None;
+    for (index, task) in ready.iter().enumerate() {
+        let priority = task.priority();
+        if best.is_none_or(|(_, p)| priority > p) {
+            best = Some((index, priority));
+        }
+    }
+    best
+}
+
 /// Interrupt-safe scheduling: returns pointer to next task's CpuContext.
 ///
 /// The caller (timer interrupt handler) has already saved the current task's
 /// context to its CpuContext struct. This function:
 /// 1. Re-queues the current task if it's still runnable
-/// 2. Picks the next task from the ready queue
+/// 2. Picks the highest-priority ready task, unless the current task outranks
+///    it; if none is ready, keeps running the current task, or falls back to the
+///    idle task if the current one can't run
 /// 3. Switches CR3 and TSS.RSP0 as needed
 /// 4. Returns pointer to next task's context (for the timer handler to restore)
 ///
 /// This function only locks the per-CPU run queue — it never touches TASK_TABLE,
 /// so it cannot deadlock with code that holds TASK_TABLE when interrupted.
 pub fn schedule_from_interrupt(cpu: &CpuLocalData) -> *mut CpuContext {
-    // Fast path: nothing queued — skip lock acquisition entirely.
+    // Get pointer to current context (saved by timer handler)
+    let current_ctx_ptr = cpu.current_context_ptr.load(Ordering::Relaxed);
+
+    // Fast path: idling with nothing queued — skip lock acquisition entirely.
     // ready_count is a hint (another CPU may add a task between this check and the
     // lock), so a missed tick is fine; the task will be picked up next time.
-    if cpu.ready_count.load(Ordering::Relaxed) == 0 {
-        return cpu.current_context_ptr.load(Ordering::Relaxed);
+    // A real task must take the lock even then, in case it has just gone to sleep.
+    if cpu.ready_count.load(Ordering::Relaxed) == 0
+        && current_ctx_ptr == cpu.idle_context_ptr.load(Ordering::Relaxed)
+    {
+        return current_ctx_ptr;
     }
 
     let mut rq = cpu.run_queue.get().unwrap().lock();
 
-    // Get pointer to current context (saved by timer handler)
-    let current_ctx_ptr = cpu.current_context_ptr.load(Ordering::Relaxed);
-
-    let next_task = match rq.ready.pop_front() {
-        Some(task) => {
+    let current_runnable = rq.current_task.as_ref().is_some_and(|t| {
+        !t.is_killed() && !matches!(t.state.load(Ordering::Relaxed), TaskState::Zombie | TaskState::Sleeping)
+    });
+    // Any ready task beats the idle task, whatever their priorities.
+    let current_priority = rq.current_task.as_ref()
+        .filter(|t| current_runnable && !rq.idle_task.as_ref().is_some_and(|idle| Arc::ptr_eq(idle, t)))
+        .map(|t| t.priority());
+    let next_task = match highest_priority(&rq.ready) {
+        // A ready task of equal priority takes its turn; a lower one waits.
+        Some((index, priority)) if current_priority.is_none_or(|current| priority >= current) => {
             cpu.ready_count.fetch_sub(1, Ordering::Relaxed);
-            task
+            rq.ready.remove(index).unwrap()
         }
-        None => {
-            // ready_count was non-zero but the queue is empty — a concurrent steal
-            // or pop raced with us. Nothing to switch to.
+        // Nothing else wants the CPU more: keep the current task rather than
+        // interleaving the idle task with it.
+        _ if current_runnable => {
+            if let Some(current) = &rq.current_task {
+                current.cpu_ticks.fetch_add(1, Ordering::Relaxed);
+            }
             return current_ctx_ptr;
         }
+        // The current task can't run either (the idle task always can).
+        _ => match rq.idle_task.clone() {
+            Some(idle) => idle,
+            None => return current_ctx_ptr,
+        },
     };
 
     // Re-queue the current task if it's still runnable
@@ -76,19 +185,24 @@ pub fn schedule_from_interrupt(cpu: &CpuLocalData) -> *mut CpuContext {
         // Charge one quantum to the outgoing task
         prev_task.cpu_ticks.fetch_add(1, Ordering::Relaxed);
 
+        let is_idle = rq.idle_task.as_ref().is_some_and(|idle| Arc::ptr_eq(idle, &prev_task));
         match prev_task.state.load(Ordering::Relaxed) {
             // Zombie: being cleaned up by scheduler drop
             // Sleeping: waiter slot holds the only remaining Arc; just drop this one
             TaskState::Zombie | TaskState::Sleeping => {}
+            // Killed: its state may be stale, but it must not run again
+            _ if prev_task.is_killed() => {}
+            // The idle task waits in `idle_task`, not in the queue
+            _ if is_idle => prev_task.set_state(TaskState::Ready),
             _ => {
-                prev_task.state.store(TaskState::Ready, Ordering::Relaxed);
+                prev_task.set_state(TaskState::Ready);
                 rq.ready.push_back(prev_task);
                 cpu.ready_count.fetch_add(1, Ordering::Relaxed);
             }
         }
     }
 
-    next_task.state.store(TaskState::Running, Ordering::Relaxed);
+    next_task.set_state(TaskState::Running);
     let mut next_inner = next_task.inner.lock();
     let next_kernel_stack_top = next_inner.kernel_stack_top;
 
@@ -118,8 +232,8 @@ pub fn schedule_from_interrupt(cpu: &CpuLocalData) -> *mut CpuContext {
         // CS should be 0x08 (kernel) or 0x23 (user)
         if ctx.cs != 0x08 && ctx.cs != 0x23 {
             panic!(
-                "SCHED: task {} has invalid context: rip={:#x} cs={:#x} fl={:#x} rsp={:#x} ss={:#x}",
-                next_task.id.to_u64(), ctx.rip, ctx.cs, ctx.rflags, ctx.rsp, ctx.ss
+                "SCHED: task {} has invalid context: rip={} cs={:#x} fl={:#x} rsp={:#x} ss={:#x}",
+                next_task.id.to_u64(), crate::symbols::Addr(ctx.rip), ctx.cs, ctx.rflags, ctx.rsp, ctx.ss
             );
         }
     }
@@ -1,6 +1,10 @@
 
 
 pub mod global_scheduler;
+pub mod idle;
 pub mod local_scheduler;
+pub mod registry;
 pub mod task;
-pub mod context;
\ No newline at end of file
+pub mod context;
+
+pub use registry::lookup;
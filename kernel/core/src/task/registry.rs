@@ -0,0 +1,35 @@
+//! Every live task by ID, for code that has to turn a task ID from user space
+//! into a `Task`.
+//!
+//! Entries are `Weak`, so the registry never keeps a task alive: the run
+//! queues and `TASK_TABLE` own tasks, and a lookup of one that has since been
+//! dropped fails (and prunes the stale entry). A task is added when it is
+//! spawned and removed when it exits, so exited tasks waiting to be reaped by
+//! `waitpid` are not found either.
+
+use crate::task::task::{Task, TaskId};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use spin::Mutex;
+
+static TASK_REGISTRY: Mutex<BTreeMap<TaskId, Weak<Task>>> = Mutex::new(BTreeMap::new());
+
+pub fn register(task: &Arc<Task>) {
+    TASK_REGISTRY.lock().insert(task.id, Arc::downgrade(task));
+}
+
+/// Returns false if `id` was not registered.
+pub fn unregister(id: TaskId) -> bool {
+    TASK_REGISTRY.lock().remove(&id).is_some()
+}
+
+/// The live task with ID `id`, if any.
+pub fn lookup(id: u64) -> Option<Arc<Task>> {
+    let id = TaskId::from_u64(id);
+    let mut registry = TASK_REGISTRY.lock();
+    let task = registry.get(&id)?.upgrade();
+    if task.is_none() {
+        registry.remove(&id);
+    }
+    task
+}
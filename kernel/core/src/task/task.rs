@@ -1,16 +1,18 @@
 use crate::memory::guarded_stack::{GuardedStack, StackId, StackType, NORMAL_STACK_SIZE};
 use crate::memory::MEMORY;
 use crate::memory::physical_memory::MemoryType;
+use kernel_api_types::{DEFAULT_PRIORITY, MAX_TASK_NAME_LEN};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use atomic_enum::atomic_enum;
-use core::sync::atomic::{AtomicU64, Ordering};
-use nodit::{Interval, NoditSet};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use nodit::interval::ii;
+use nodit::{Interval, NoditMap, NoditSet};
 use spin::mutex::Mutex;
 use crate::memory::cpu_local_data::get_local;
 use x86_64::instructions::segmentation::{CS, SS, Segment};
 use x86_64::registers::control::Cr3;
-use x86_64::structures::paging::{PageTable, PageTableFlags, PhysFrame};
+use x86_64::structures::paging::{PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB};
 use x86_64::{PhysAddr, VirtAddr};
 
 /// CPU context saved/restored on task switches.
@@ -96,6 +98,20 @@ impl TaskId {
     }
 }
 
+/// A task's name, for logs and task listings.
+pub type TaskName = heapless::String<MAX_TASK_NAME_LEN>;
+
+/// `name` cut to `MAX_TASK_NAME_LEN` bytes, at a character boundary.
+pub fn task_name(name: &str) -> TaskName {
+    let mut out = TaskName::new();
+    for c in name.chars() {
+        if out.push(c).is_err() {
+            break;
+        }
+    }
+    out
+}
+
 #[atomic_enum]
 #[derive(PartialEq)]
 pub enum TaskState {
@@ -113,6 +129,16 @@ pub enum TaskKind {
     User,
 }
 
+/// How much of the kernel's shared resources a task may claim, such as how
+/// deep its channels' queues can be (see `ipc::max_channel_capacity`).
+/// Kernel tasks and init are `System`; init passes it on to the servers it
+/// spawns with `SPAWN_SYSTEM`, and every other task is `User`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    System,
+    User,
+}
+
 /// Parts of the task that can be modified after creation
 pub struct TaskInner {
     pub context: CpuContext,
@@ -123,13 +149,38 @@ pub struct TaskInner {
     /// Tracks user-space virtual address allocations (ELF segments, stack, mmap).
     /// Empty for kernel tasks.
     pub user_vaddr_set: NoditSet<u64, Interval<u64>>,
+    /// Parts of `user_vaddr_set` reserved with `MMAP_LAZY`, with the flags their
+    /// pages are mapped with on first touch.
+    pub lazy_regions: NoditMap<u64, Interval<u64>, PageTableFlags>,
+    /// Start of the never-mapped page below the user stack. It is reserved in
+    /// `user_vaddr_set` so nothing else is placed there. None for kernel tasks.
+    pub user_stack_guard: Option<u64>,
+    /// Parts of `user_vaddr_set` left reserved but unmapped where a shared
+    /// buffer was taken away, so a stale pointer into one faults instead of
+    /// reaching whatever reuses the address. Syscalls reject them like the
+    /// stack guard; `sys_munmap` gives them back.
+    pub unmapped_guards: NoditSet<u64, Interval<u64>>,
     /// IPC endpoint IDs owned by this task; closed on exit.
     pub owned_endpoints: Vec<u64>,
     /// Service names registered by this task; removed from the registry on exit.
     pub registered_services: Vec<[u8; 64]>,
 }
 
-/// Walk L4 entries 0..256 (user space) and free all page table frames and data frames.
+impl TaskInner {
+    /// Whether `[start, end)` touches the user stack guard page.
+    pub fn overlaps_user_stack_guard(&self, start: u64, end: u64) -> bool {
+        self.user_stack_guard
+            .is_some_and(|guard| start < guard + Size4KiB::SIZE && end > guard)
+    }
+
+    /// Whether `[start, end)` touches any of `unmapped_guards`.
+    pub fn overlaps_unmapped_guard(&self, start: u64, end: u64) -> bool {
+        start < end && self.unmapped_guards.overlaps(&ii(start, end - 1))
+    }
+}
+
+/// Walk L4 entries 0..256 (user space) and free all page table frames and data frames,
+/// including 2 MiB data frames mapped directly by an L2 entry.
 /// All user frames are `UsedByUserMode`.
 ///
 /// # Safety
@@ -159,6 +210,14 @@ unsafe fn free_user_address_space(
                 if !l2e.flags().contains(PageTableFlags::PRESENT) {
                     continue;
                 }
+                if l2e.flags().contains(PageTableFlags::HUGE_PAGE) {
+                    // A 2 MiB page from `MMAP_HUGE`: a data frame, not an L1 table.
+                    let _ = phys_mem.free_frame_2mib(
+                        PhysFrame::containing_address(l2e.addr()),
+                        MemoryType::UsedByUserMode,
+                    );
+                    continue;
+                }
                 let l1_phys = l2e.addr();
                 let l1 = unsafe { &*VirtAddr::new(hhdm + l1_phys.as_u64()).as_ptr::<PageTable>() };
                 for l1e in l1.iter() {
@@ -208,6 +267,7 @@ pub struct Task {
     pub id: TaskId,
     pub state: AtomicTaskState,
     pub kind: TaskKind,
+    pub privilege: Privilege,
     /// Physical address of the L4 page table for this task.
     /// For kernel tasks, this is the kernel CR3.
     pub cr3: u64,
@@ -217,6 +277,22 @@ pub struct Task {
     pub exit_waiter: Mutex<Option<(Arc<Task>, u32)>>,
     /// Number of scheduler quanta this task has consumed. One tick ≈ 1 ms.
     pub cpu_ticks: AtomicU64,
+    /// Kernel ID of the CPU whose run queue holds the task. Set when it is
+    /// spawned; tasks don't migrate.
+    pub cpu: AtomicU32,
+    /// Priority set by `sys_set_priority`, 0..=`MAX_PRIORITY`.
+    pub base_priority: AtomicU8,
+    /// Priority lent by a higher-priority task waiting on this one over IPC
+    /// (see `ipc`), or 0. Cleared when this task next waits to receive.
+    pub inherited_priority: AtomicU8,
+    /// Set with `Task::new_named`, `set_name` or `sys_set_task_name`.
+    pub name: Mutex<Option<TaskName>>,
+    /// The task that spawned this one with `sys_spawn`, and so may kill it.
+    pub parent: Option<TaskId>,
+    /// Set by `sys_kill`. The scheduler drops a killed task rather than run or
+    /// queue it again, whatever `state` says: the kill may land while the task
+    /// is in a syscall on another CPU, about to mark itself `Sleeping`.
+    pub killed: AtomicBool,
 }
 
 impl Task {
@@ -259,19 +335,36 @@ impl Task {
                 kernel_stack_top: stack_top,
                 user_page_table: None,
                 user_vaddr_set: NoditSet::default(),
+                lazy_regions: NoditMap::default(),
+                user_stack_guard: None,
+                unmapped_guards: NoditSet::default(),
                 owned_endpoints: Vec::new(),
                 registered_services: Vec::new(),
             }),
             id: TaskId::new(),
             state: AtomicTaskState::new(TaskState::Initializing),
             kind: TaskKind::Kernel,
+            privilege: Privilege::System,
             cr3,
             exit_code: AtomicU64::new(0),
             exit_waiter: Mutex::new(None),
             cpu_ticks: AtomicU64::new(0),
+            cpu: AtomicU32::new(0),
+            base_priority: AtomicU8::new(DEFAULT_PRIORITY as u8),
+            inherited_priority: AtomicU8::new(0),
+            name: Mutex::new(None),
+            parent: None,
+            killed: AtomicBool::new(false),
         }
     }
 
+    /// Create a new kernel-mode task called `name`.
+    pub fn new_named(entry: fn() -> !, name: &str) -> Self {
+        let task = Self::new(entry);
+        task.set_name(name);
+        task
+    }
+
     /// Create a new user-mode task.
     ///
     /// - `entry_rip`: User-space entry point (ELF entry)
@@ -280,6 +373,8 @@ impl Task {
     /// - `cr3`: Physical address of the user page table's L4 frame
     /// - `user_cs`: User code segment selector
     /// - `user_ss`: User data segment selector
+    /// - `lazy_regions`: Reserved ranges mapped on first touch, e.g. the user stack
+    /// - `user_stack_guard`: Start of the unmapped page below the user stack
     pub fn new_user(
         entry_rip: u64,
         user_rsp: u64,
@@ -288,6 +383,8 @@ impl Task {
         user_cs: u16,
         user_ss: u16,
         user_vaddr_set: NoditSet<u64, Interval<u64>>,
+        lazy_regions: NoditMap<u64, Interval<u64>, PageTableFlags>,
+        user_stack_guard: u64,
         arg: u64,
     ) -> Self {
         // Allocate a kernel stack for this user task (used for interrupts/syscalls)
@@ -320,28 +417,87 @@ impl Task {
                 kernel_stack_top,
                 user_page_table: Some(page_table),
                 user_vaddr_set,
+                lazy_regions,
+                user_stack_guard: Some(user_stack_guard),
+                unmapped_guards: NoditSet::default(),
                 owned_endpoints: Vec::new(),
                 registered_services: Vec::new(),
             }),
             id: TaskId::new(),
             state: AtomicTaskState::new(TaskState::Initializing),
             kind: TaskKind::User,
+            privilege: Privilege::User,
             cr3,
             exit_code: AtomicU64::new(0),
             exit_waiter: Mutex::new(None),
             cpu_ticks: AtomicU64::new(0),
+            cpu: AtomicU32::new(0),
+            base_priority: AtomicU8::new(DEFAULT_PRIORITY as u8),
+            inherited_priority: AtomicU8::new(0),
+            name: Mutex::new(None),
+            parent: None,
+            killed: AtomicBool::new(false),
         }
     }
 
     pub fn run_state(&self) -> TaskState { self.state.load(Ordering::Relaxed) }
 
+    /// Move the task to `state`, unless it is already a zombie: only
+    /// `terminate` makes a task one, and nothing brings it back. A killed task
+    /// can still be passing through the scheduler or a syscall when that
+    /// happens, so every other state change goes through here.
     pub fn set_state(&self, state: TaskState) {
-        self.state.store(state, Ordering::Relaxed);
+        let mut current = self.state.load(Ordering::Acquire);
+        while current != TaskState::Zombie {
+            match self.state.compare_exchange(current, state, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(actual) => current = actual,
+            }
+        }
     }
 
     pub fn is_runnable(&self) -> bool {
         self.run_state() == TaskState::Ready
     }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Acquire)
+    }
+
+    /// Rename the task; names longer than `MAX_TASK_NAME_LEN` are truncated.
+    pub fn set_name(&self, name: &str) {
+        *self.name.lock() = Some(task_name(name));
+    }
+
+    pub fn name(&self) -> Option<TaskName> {
+        self.name.lock().clone()
+    }
+
+    /// The priority the scheduler uses: the base priority, or a higher one
+    /// inherited over IPC.
+    pub fn priority(&self) -> u8 {
+        self.base_priority.load(Ordering::Relaxed)
+            .max(self.inherited_priority.load(Ordering::Relaxed))
+    }
+
+    /// Raise the inherited priority to at least `priority`.
+    pub fn inherit_priority(&self, priority: u8) {
+        self.inherited_priority.fetch_max(priority, Ordering::Relaxed);
+    }
+}
+
+/// The id, then the name in parentheses if there is one. Only tries the name
+/// lock, so this is safe to log from fault handlers.
+impl core::fmt::Display for Task {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.id.to_u64())?;
+        if let Some(name) = self.name.try_lock() {
+            if let Some(name) = name.as_ref() {
+                write!(f, " ({name})")?;
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Loads the actual function from the first register
@@ -0,0 +1,160 @@
+//! High Precision Event Timer, used as a free-running monotonic counter.
+//!
+//! Only the main counter is used; the comparators stay disabled. The counter
+//! period (in femtoseconds) comes from the capabilities register.
+
+use crate::memory::MEMORY;
+use acpi::sdt::{SdtHeader, Signature};
+use acpi::{AcpiTable, AcpiTables};
+use core::num::NonZero;
+use spin::Once;
+use x86_64::structures::paging::{Mapper, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+const GENERAL_CAPABILITIES: usize = 0x000;
+const GENERAL_CONFIGURATION: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0f0;
+
+const ENABLE_CNF: u64 = 1;
+
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+pub struct Hpet {
+    base: *mut u64,
+    /// Length of one counter tick in femtoseconds.
+    period_fs: u64,
+}
+
+// The registers are only read after `init`, and the counter is read-only.
+unsafe impl Send for Hpet {}
+unsafe impl Sync for Hpet {}
+
+impl Hpet {
+    fn read(&self, offset: usize) -> u64 {
+        unsafe { core::ptr::read_volatile(self.base.byte_add(offset)) }
+    }
+
+    fn write(&self, offset: usize, value: u64) {
+        unsafe { core::ptr::write_volatile(self.base.byte_add(offset), value) }
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.read(MAIN_COUNTER)
+    }
+
+    pub fn period_fs(&self) -> u64 {
+        self.period_fs
+    }
+
+    /// Counter frequency in Hz.
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period_fs
+    }
+
+    pub fn ticks_to_nanos(&self, ticks: u64) -> u64 {
+        (ticks as u128 * self.period_fs as u128 / FEMTOS_PER_NANO as u128) as u64
+    }
+}
+
+#[repr(C, packed)]
+struct HpetTable {
+    header: SdtHeader,
+    _event_timer_block_id: u32,
+    address_space_id: u8,
+    _register_bit_width: u8,
+    _register_bit_offset: u8,
+    _reserved: u8,
+    base_address: u64,
+    _hpet_number: u8,
+    _clock_tick_unit: u16,
+    _page_protection: u8,
+}
+
+unsafe impl AcpiTable for HpetTable {
+    const SIGNATURE: Signature = Signature::HPET;
+
+    fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+}
+
+pub static HPET: Once<Hpet> = Once::new();
+
+/// Find the HPET through ACPI, map its registers and start the main counter.
+/// Does nothing if the firmware doesn't describe one.
+pub fn init(acpi_tables: &AcpiTables<impl acpi::Handler>) {
+    const SYSTEM_MEMORY: u8 = 0;
+
+    let Some(table) = acpi_tables.find_table::<HpetTable>() else {
+        log::info!("No HPET in ACPI tables");
+        return;
+    };
+    let (space, phys) = (table.address_space_id, table.base_address);
+    drop(table);
+    if space != SYSTEM_MEMORY || phys == 0 {
+        log::warn!("HPET registers not in system memory (space {}), ignoring it", space);
+        return;
+    }
+
+    let base = map_hpet_mmio(phys);
+    let hpet = Hpet { base, period_fs: 0 };
+    let period_fs = hpet.read(GENERAL_CAPABILITIES) >> 32;
+    // The spec caps the period at 100 ns; anything else is a broken table.
+    if period_fs == 0 || period_fs > 100_000_000 {
+        log::warn!("HPET reports a bogus period of {} fs, ignoring it", period_fs);
+        return;
+    }
+    let hpet = Hpet { base, period_fs };
+    hpet.write(GENERAL_CONFIGURATION, hpet.read(GENERAL_CONFIGURATION) | ENABLE_CNF);
+
+    log::info!("HPET at {:#x}: {} Hz", phys, hpet.frequency());
+    HPET.call_once(|| hpet);
+}
+
+/// Nanoseconds counted by the HPET since it was enabled, if there is one.
+pub fn nanos() -> Option<u64> {
+    let hpet = HPET.get()?;
+    Some(hpet.ticks_to_nanos(hpet.counter()))
+}
+
+/// Busy-wait for `nanos` nanoseconds on the HPET counter. Returns false
+/// without waiting if there is no HPET.
+pub fn spin_wait_ns(nanos: u64) -> bool {
+    let Some(hpet) = HPET.get() else {
+        return false;
+    };
+    let ticks = (nanos as u128 * FEMTOS_PER_NANO as u128 / hpet.period_fs as u128) as u64;
+    let start = hpet.counter();
+    while hpet.counter().wrapping_sub(start) < ticks {
+        core::hint::spin_loop();
+    }
+    true
+}
+
+/// Map the HPET register block (one page) writable and uncached; like the
+/// APIC pages, it is device MMIO outside the HHDM.
+fn map_hpet_mmio(phys_addr: u64) -> *mut u64 {
+    let memory = MEMORY.get().unwrap();
+    let mut physical_memory = memory.physical_memory.lock();
+    let mut virtual_memory = memory.virtual_memory.lock();
+
+    let frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(PhysAddr::new(phys_addr));
+    let page = virtual_memory
+        .allocate_kernel_contiguous_pages(NonZero::new(1).unwrap())
+        .expect("Failed to allocate virtual page for HPET");
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH;
+
+    let mut mapper = unsafe { virtual_memory.mapper() };
+    let mut frame_allocator = physical_memory.get_kernel_frame_allocator();
+    unsafe {
+        mapper.map_to(page, frame, flags, &mut frame_allocator)
+            .expect("Failed to map HPET MMIO")
+            .flush();
+    }
+
+    (page.start_address() + (phys_addr & 0xfff)).as_mut_ptr()
+}
@@ -1,9 +1,40 @@
 pub mod pit;
 pub mod lapic_timer;
 pub mod tsc;
-mod rtc;
+pub mod hpet;
+pub mod rtc;
+pub mod timers;
+
+use core::sync::atomic::Ordering;
+
+/// Monotonic nanoseconds: the HPET counter when there is one, otherwise the
+/// TSC scaled by its calibrated rate (0 before calibration).
+pub fn now_ns() -> u64 {
+    if let Some(ns) = hpet::nanos() {
+        return ns;
+    }
+    let ticks_per_ms = tsc::TSC_HZ.load(Ordering::Relaxed);
+    if ticks_per_ms == 0 {
+        return 0;
+    }
+    (tsc::value() as u128 * 1_000_000 / ticks_per_ms as u128) as u64
+}
+
+/// `now_ns` in milliseconds.
+pub fn now_ms() -> u64 {
+    now_ns() / 1_000_000
+}
 
 pub fn on_timer_tick() {
+    let cpu = crate::memory::cpu_local_data::get_local();
+    cpu.timer_interrupts.fetch_add(1, Ordering::Relaxed);
+    crate::task::idle::end_idle(cpu);
+    timers::run_expired();
+    if cpu.kernel_id == 0 {
+        crate::interrupt::watchdog::check();
+    }
+    // Re-arm after the callbacks: a task they wake is picked by this tick,
+    // so a reschedule they request would only be a wasted second one.
     lapic_timer::set_deadline(1_000_000); // 1 ms
 }
 
@@ -55,8 +55,11 @@ pub fn sleep_qs(microseconds: u32) -> Result<(), &'static str> {
         port_61.write(port_61_val | 0x1); // set bit 0
         // here, PIT channel 2 timer has started counting
 
-        // wait for PIT timer to reach 0, which is tested by checking bit 5
+        // In mode 1, OUT (bit 5) drops on the first clock after the gate edge and
+        // rises again at terminal count. Waiting only while it is high would
+        // return as soon as the count starts.
         while port_61.read() & 0x20 != 0 { }
+        while port_61.read() & 0x20 == 0 { }
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,6 +1,55 @@
+//! CMOS real-time clock: the only wall-clock time source.
+
+use acpi::AcpiTables;
+use core::sync::atomic::{AtomicU8, Ordering};
+use kernel_api_types::DateTime;
+use spin::Mutex;
 use x86_64::instructions::port::Port;
 
-#[allow(dead_code)]
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY_OF_MONTH: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+
+/// Status A: an update cycle is in progress and the time registers may be torn.
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Status B: hours are 0-23 rather than 1-12 with a PM flag.
+const HOUR_24: u8 = 1 << 1;
+/// Status B: values are binary rather than BCD.
+const BINARY_MODE: u8 = 1 << 2;
+/// Set in the hours register for PM in 12-hour mode.
+const HOUR_PM: u8 = 1 << 7;
+
+/// The CMOS index/data port pair must be used as a unit.
+static CMOS: Mutex<()> = Mutex::new(());
+
+/// CMOS index of the century register from the FADT, or 0 if there isn't one.
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(0);
+
+/// The RTC registers as read, before BCD/12-hour decoding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RawRtc {
+    pub second: u8,
+    pub minute: u8,
+    pub hour: u8,
+    pub day: u8,
+    pub month: u8,
+    pub year: u8,
+    /// 0 if the machine has no century register.
+    pub century: u8,
+}
+
+/// Look up the century register in the FADT.
+pub fn init(acpi_tables: &AcpiTables<impl acpi::Handler>) {
+    let century = crate::acpi::with_fadt(acpi_tables, |fadt| fadt.get(108).copied().unwrap_or(0))
+        .unwrap_or(0);
+    CENTURY_REGISTER.store(century, Ordering::Relaxed);
+}
+
 fn read_rtc_register(register: u8) -> u8 {
     unsafe {
         Port::new(0x70).write(register);
@@ -8,15 +57,69 @@ fn read_rtc_register(register: u8) -> u8 {
     }
 }
 
-#[allow(dead_code)]
-pub fn read_time() -> (u8, u8, u8) {
-    let seconds_bcd = read_rtc_register(0x00);
-    let minutes_bcd = read_rtc_register(0x02);
-    let hours_bcd = read_rtc_register(0x04);
+fn read_raw(century_register: u8) -> RawRtc {
+    while read_rtc_register(STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    RawRtc {
+        second: read_rtc_register(SECONDS),
+        minute: read_rtc_register(MINUTES),
+        hour: read_rtc_register(HOURS),
+        day: read_rtc_register(DAY_OF_MONTH),
+        month: read_rtc_register(MONTH),
+        year: read_rtc_register(YEAR),
+        century: if century_register != 0 { read_rtc_register(century_register) } else { 0 },
+    }
+}
+
+/// Read the current date and time.
+///
+/// An update can start between the in-progress check and the last register
+/// read, so the registers are read until two consecutive reads agree.
+pub fn read_datetime() -> DateTime {
+    let century_register = CENTURY_REGISTER.load(Ordering::Relaxed);
+    let _guard = CMOS.lock();
+
+    let mut last = read_raw(century_register);
+    loop {
+        let raw = read_raw(century_register);
+        if raw == last {
+            break;
+        }
+        last = raw;
+    }
+    decode(last, read_rtc_register(STATUS_B))
+}
 
-    let seconds = (seconds_bcd & 0x0F) + ((seconds_bcd >> 4) * 10);
-    let minutes = (minutes_bcd & 0x0F) + ((minutes_bcd >> 4) * 10);
-    let hours = (hours_bcd & 0x0F) + ((hours_bcd >> 4) * 10);
+fn from_bcd(v: u8) -> u8 {
+    (v & 0x0f) + (v >> 4) * 10
+}
 
-    (hours, minutes, seconds)
-}
\ No newline at end of file
+/// Turn raw register values into a `DateTime`, following the format bits in
+/// status register B. Without a century register, years are taken to be 20xx.
+pub fn decode(raw: RawRtc, status_b: u8) -> DateTime {
+    let binary = status_b & BINARY_MODE != 0;
+    let field = |v: u8| if binary { v } else { from_bcd(v) };
+
+    let pm = raw.hour & HOUR_PM != 0;
+    let mut hour = field(raw.hour & !HOUR_PM);
+    if status_b & HOUR_24 == 0 {
+        // 12 AM is hour 0, 12 PM is hour 12.
+        hour = match (hour, pm) {
+            (12, false) => 0,
+            (12, true) => 12,
+            (h, true) => h + 12,
+            (h, false) => h,
+        };
+    }
+
+    let century = if raw.century != 0 { field(raw.century) as u16 } else { 20 };
+    DateTime {
+        year: century * 100 + field(raw.year) as u16,
+        month: field(raw.month),
+        day: field(raw.day),
+        hour,
+        minute: field(raw.minute),
+        second: field(raw.second),
+    }
+}
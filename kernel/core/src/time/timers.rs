@@ -0,0 +1,114 @@
+//! One-shot kernel timers: "call me back after N ms".
+//!
+//! Each CPU keeps a min-heap of pending timers ordered by deadline, checked on
+//! every LAPIC timer tick. A timer fires on the CPU that registered it.
+//!
+//! Callbacks run in interrupt context: inside the timer interrupt handler,
+//! with interrupts disabled, before the EOI and before the scheduler picks the
+//! next task. They must not block, and must not take a lock that code on this
+//! CPU can hold with interrupts enabled. The heap lock is not held while a
+//! callback runs, so callbacks may register further timers.
+
+use crate::memory::cpu_local_data::get_local;
+use alloc::boxed::Box;
+use alloc::collections::BinaryHeap;
+use core::cmp::Ordering;
+use core::sync::atomic::{self, AtomicU64};
+use x86_64::instructions::interrupts;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(0);
+
+struct Timer {
+    deadline_ms: u64,
+    id: TimerId,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+impl Timer {
+    /// Earliest deadline first; timers due at the same time fire in
+    /// registration order.
+    fn key(&self) -> (u64, TimerId) {
+        (self.deadline_ms, self.id)
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    // Reversed: `BinaryHeap` is a max-heap.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key().cmp(&self.key())
+    }
+}
+
+/// A CPU's pending timers.
+pub struct TimerQueue {
+    heap: BinaryHeap<Timer>,
+}
+
+impl TimerQueue {
+    pub const fn new() -> Self {
+        Self { heap: BinaryHeap::new() }
+    }
+
+    /// Remove the earliest timer if its deadline is at or before `now_ms`.
+    fn pop_due(&mut self, now_ms: u64) -> Option<Timer> {
+        if self.heap.peek()?.deadline_ms <= now_ms {
+            self.heap.pop()
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for TimerQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run `callback` once, on this CPU, on the first timer tick at least `ms`
+/// milliseconds from now. A zero delay still waits for the next tick.
+pub fn register_timer(ms: u64, callback: Box<dyn FnMut() + Send>) -> TimerId {
+    let id = TimerId(NEXT_TIMER_ID.fetch_add(1, atomic::Ordering::Relaxed));
+    // Round up so the callback never fires before `ms` has fully elapsed, and
+    // so a callback that re-registers itself can't fire twice in one tick.
+    let deadline_ms = super::now_ms() + ms.max(1);
+    interrupts::without_interrupts(|| {
+        get_local().timers.lock().heap.push(Timer { deadline_ms, id, callback });
+    });
+    id
+}
+
+/// When this CPU's earliest timer is due, in `now_ms` time.
+pub fn next_deadline_ms() -> Option<u64> {
+    interrupts::without_interrupts(|| get_local().timers.lock().heap.peek().map(|t| t.deadline_ms))
+}
+
+/// Fire every timer on this CPU whose deadline has passed. Called from the
+/// timer interrupt with interrupts disabled.
+pub fn run_expired() {
+    let now = super::now_ms();
+    loop {
+        // Pop one timer at a time so the lock is free while its callback runs.
+        let Some(mut timer) = get_local().timers.lock().pop_due(now) else {
+            break;
+        };
+        (timer.callback)();
+    }
+}
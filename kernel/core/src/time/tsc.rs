@@ -1,6 +1,8 @@
 use core::arch::x86_64::{__cpuid, __rdtscp, _mm_lfence, _rdtsc};
+use core::ops::RangeInclusive;
 use core::sync::atomic::{AtomicU64, Ordering};
-use crate::time::pit;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::time::{hpet, pit};
 
 pub static TSC_HZ: AtomicU64 = AtomicU64::new(0);
 
@@ -27,23 +29,76 @@ fn has_rdtscp() -> bool {
     (res.edx & (1 << 27)) != 0
 }
 
-fn calibrate_with_pit() -> u64 {
-    const PIT_WAIT_QS: u32 = 10_000;
+/// Plausible ticks per ms: a 0.5-6 GHz TSC.
+pub const PLAUSIBLE_TICKS_PER_MS: RangeInclusive<u64> = 500_000..=6_000_000;
 
+/// Ticks per ms over several PIT one-shot intervals, with outliers dropped.
+fn calibrate_with_pit() -> Option<u64> {
+    // 10 ms gives an exact-enough PIT divisor (11931) and a short boot delay.
+    const PIT_WAIT_US: u32 = 10_000;
+    const SAMPLES: usize = 7;
+
+    let mut samples = [0u64; SAMPLES];
+    for sample in samples.iter_mut() {
+        // An interrupt landing inside the interval would only lengthen it.
+        let elapsed = without_interrupts(|| {
+            let start = value();
+            pit::sleep_qs(PIT_WAIT_US).ok()?;
+            value().checked_sub(start)
+        })?;
+        *sample = elapsed * 1000 / PIT_WAIT_US as u64;
+    }
+    log::info!("Tsc PIT samples (ticks per ms): {:?}", samples);
+    Some(robust_median(&mut samples))
+}
+
+/// Median of `samples` after discarding those more than 5% away from the
+/// overall median. Sorts `samples` in place.
+pub fn robust_median(samples: &mut [u64]) -> u64 {
+    if samples.is_empty() {
+        return 0;
+    }
+    samples.sort_unstable();
+    let median = samples[samples.len() / 2];
+    let tolerance = median / 20;
+    let kept: &[u64] = {
+        let lo = samples.partition_point(|&s| s < median - tolerance);
+        let hi = samples.partition_point(|&s| s <= median + tolerance);
+        &samples[lo..hi]
+    };
+    kept[kept.len() / 2]
+}
+
+/// Ticks per ms measured against the HPET, if there is one.
+fn calibrate_with_hpet() -> Option<u64> {
+    const HPET_WAIT_NS: u64 = 10_000_000;
+
+    hpet::HPET.get()?;
     let start = value();
-    let _ = pit::sleep_qs(PIT_WAIT_QS);
+    hpet::spin_wait_ns(HPET_WAIT_NS);
     let end = value();
-    log::info!("{}, {}", start, end);
-
-    let elapsed = end.checked_sub(start).unwrap();
-    elapsed * 1000 / PIT_WAIT_QS as u64
+    Some(end.checked_sub(start)? * 1_000_000 / HPET_WAIT_NS)
 }
 
 /// Safety: must be called once during early boot
 pub fn calibrate() {
     //TODO: Check if cpu has invariant tsc
 
-    let tms = calibrate_with_pit();
+    let mut tms = calibrate_with_pit().unwrap_or(0);
+    if !PLAUSIBLE_TICKS_PER_MS.contains(&tms) {
+        log::warn!("Tsc PIT calibration gave an implausible {} ticks per ms", tms);
+    }
+
+    // PIT emulation can be slow and jittery under virtualisation; if the HPET
+    // disagrees by more than 10%, trust the HPET.
+    if let Some(hpet_tms) = calibrate_with_hpet() {
+        if tms.abs_diff(hpet_tms) > hpet_tms / 10 {
+            log::warn!("Tsc PIT calibration ({} ticks per ms) disagrees with HPET ({}), using HPET", tms, hpet_tms);
+            tms = hpet_tms;
+        } else {
+            log::info!("Tsc HPET cross-check: {} ticks per ms", hpet_tms);
+        }
+    }
 
     log::info!("Tsc {} ticks per ms", tms);
     TSC_HZ.store(tms, Ordering::SeqCst);
@@ -2,10 +2,12 @@ use crate::limine_requests::{MODULE_REQUEST, INIT_TASK_PATH};
 use crate::memory::MEMORY;
 use crate::memory::cpu_local_data::get_local;
 use crate::memory::hhdm_offset::hhdm_offset;
-use crate::memory::physical_memory::{MemoryType, OffsetMappedPhysAddr};
+use crate::memory::demand_paging::map_zeroed_page;
+use crate::memory::physical_memory::{MemoryType, OffsetMappedPhysAddr, PhysicalMemory};
 use crate::memory::vaddr_allocator::OffsetMappedVirtAddr;
-use crate::task::task::Task;
+use crate::task::task::{Privilege, Task};
 use bitflags::bitflags;
+use core::fmt;
 use core::num::NonZero;
 use core::ops::Range;
 use core::ptr::{NonNull, slice_from_raw_parts_mut};
@@ -13,7 +15,7 @@ use elf::ElfBytes;
 use elf::endian::AnyEndian;
 use elf::segment::ProgramHeader;
 use nodit::interval::ie;
-use nodit::{Interval, NoditSet};
+use nodit::{Interval, NoditMap, NoditSet};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use x86_64::{PhysAddr, VirtAddr};
 use x86_64::structures::paging::{Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB};
@@ -31,6 +33,46 @@ fn elf_flags_to_page_table_flags(elf_flags: ElfSegmentFlags) -> PageTableFlags {
     flags
 }
 
+/// Initial RSP of every user task. LOWER_HALF_END is 0x7FFFFFFFFFFF (inclusive);
+/// the page at the very top of the lower half is left unused.
+pub const USER_STACK_TOP: u64 = (LOWER_HALF_END + 1) - 0x1000;
+/// How far a user stack can grow before it runs into its guard page.
+pub const USER_STACK_MAX_SIZE: u64 = 1024 * 1024;
+
+/// Address-space bookkeeping for a freshly mapped user stack.
+struct UserStack {
+    /// The whole stack plus its guard page, for `user_vaddr_set`.
+    reserved: Interval<u64>,
+    /// Everything below the top page: mapped on first touch, like `MMAP_LAZY`.
+    lazy_regions: NoditMap<u64, Interval<u64>, PageTableFlags>,
+    /// Start of the never-mapped page below the stack.
+    guard: u64,
+}
+
+/// Map the top page of a user stack ending at `USER_STACK_TOP`. The page fault
+/// handler maps the rest downward as the stack grows, up to
+/// `USER_STACK_MAX_SIZE`; below that is the guard page, where an overflow faults.
+fn map_user_stack(
+    mapper: &mut OffsetPageTable,
+    physical_memory: &mut PhysicalMemory,
+) -> Result<UserStack, SpawnError> {
+    let page_size = Size4KiB::SIZE;
+    let stack_flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let top_page = Page::<Size4KiB>::containing_address(VirtAddr::new(USER_STACK_TOP - page_size));
+    if !map_zeroed_page(mapper, physical_memory, top_page, stack_flags) {
+        return Err(SpawnError::OutOfMemory);
+    }
+
+    let bottom = USER_STACK_TOP - USER_STACK_MAX_SIZE;
+    let guard = bottom - page_size;
+    let mut lazy_regions = NoditMap::default();
+    lazy_regions
+        .insert_strict(ie(bottom, top_page.start_address().as_u64()), stack_flags)
+        .expect("empty map");
+    Ok(UserStack { reserved: ie(guard, USER_STACK_TOP), lazy_regions, guard })
+}
+
 /// Allocate a new user-mode L4 page table, zero it, copy kernel higher-half
 /// entries (256..512), and return the L4 frame plus an `OffsetPageTable` mapper.
 ///
@@ -39,11 +81,8 @@ fn elf_flags_to_page_table_flags(elf_flags: ElfSegmentFlags) -> PageTableFlags {
 /// The caller must ensure it does not outlive the physical frame.
 unsafe fn create_user_page_table(
     phys: &mut crate::memory::physical_memory::PhysicalMemory,
-) -> (PhysFrame<Size4KiB>, OffsetPageTable<'static>) {
-    let l4_frame = phys
-        .get_user_mode_frame_allocator()
-        .allocate_frame_4kib()
-        .expect("Failed to allocate L4 frame for user page table");
+) -> Option<(PhysFrame<Size4KiB>, OffsetPageTable<'static>)> {
+    let l4_frame = phys.get_user_mode_frame_allocator().allocate_frame_4kib()?;
 
     let hhdm = VirtAddr::new(hhdm_offset().as_u64());
     let l4_virt = hhdm + l4_frame.start_address().as_u64();
@@ -65,22 +104,90 @@ unsafe fn create_user_page_table(
             &mut *l4_virt.as_mut_ptr::<PageTable>(),
             hhdm,
         );
-        (l4_frame, mapper)
+        Some((l4_frame, mapper))
+    }
+}
+
+/// A LOAD segment whose file data runs past the end of the ELF image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentOutOfBounds {
+    /// Index of the segment in the program header table.
+    pub index: usize,
+    pub offset: u64,
+    pub file_size: u64,
+    pub image_size: u64,
+}
+
+impl fmt::Display for SegmentOutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "LOAD segment {} needs file bytes {:#x}..{:#x}, but the image is only {:#x} bytes",
+            self.index,
+            self.offset,
+            self.offset as u128 + self.file_size as u128,
+            self.image_size,
+        )
     }
 }
 
-/// Create a user-mode task from the first Limine module matching INIT_TASK_PATH.
+/// Check that the file data of every LOAD segment lies within the first
+/// `image_size` bytes, before anything is mapped from it.
+pub fn check_segment_bounds(elf: &ElfBytes<AnyEndian>, image_size: u64) -> Result<(), SegmentOutOfBounds> {
+    for (index, segment) in elf.segments().into_iter().flatten().enumerate() {
+        if ElfSegmentType::try_from(segment.p_type) != Ok(ElfSegmentType::Load) {
+            continue;
+        }
+        let end = segment.p_offset.checked_add(segment.p_filesz);
+        if end.is_none_or(|end| end > image_size) {
+            return Err(SegmentOutOfBounds {
+                index,
+                offset: segment.p_offset,
+                file_size: segment.p_filesz,
+                image_size,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The name of the module the first task starts from: `init=<name>` on the
+/// kernel command line, or `INIT_TASK_PATH` without its leading slash.
+fn init_module_name() -> &'static str {
+    crate::cmdline::get("init")
+        .map(|name| name.trim_start_matches('/'))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| INIT_TASK_PATH.to_str().unwrap_or_default().trim_start_matches('/'))
+}
+
+/// Create a user-mode task from the Limine module picked by `init_module_name`.
+/// If no module has that name, falls back to INIT_TASK_PATH.
 ///
 /// This parses the ELF, creates a new address space, maps ELF segments and a
 /// user stack, then returns a `Task` ready to be scheduled.
-pub fn create_user_task_from_elf() -> Task {
-    let module = MODULE_REQUEST
-        .get_response()
-        .unwrap()
-        .modules()
+///
+/// Running out of frames fails with `SpawnError::OutOfMemory`. Frames mapped
+/// before that point are not given back. A malformed or corrupt module still
+/// panics: there is no system without it.
+pub fn create_user_task_from_elf() -> Result<Task, SpawnError> {
+    let modules = MODULE_REQUEST.get_response().unwrap().modules();
+    let name = init_module_name();
+    let module = match modules
         .iter()
-        .find(|module| module.path() == INIT_TASK_PATH)
-        .unwrap();
+        .find(|module| module.path().to_bytes().strip_prefix(b"/") == Some(name.as_bytes()))
+    {
+        Some(module) => module,
+        None => {
+            log::warn!("cmdline: no module named {name:?}, starting {INIT_TASK_PATH:?}");
+            modules.iter().find(|module| module.path() == INIT_TASK_PATH).unwrap()
+        }
+    };
+    let path = module.path().to_bytes();
+    assert!(
+        !crate::boot_modules::is_corrupt(path),
+        "init module {:?} failed its checksum",
+        module.path()
+    );
 
     let ptr = NonNull::new(slice_from_raw_parts_mut(
         module.addr(),
@@ -92,6 +199,9 @@ pub fn create_user_task_from_elf() -> Task {
     let elf_bytes = unsafe { ptr.as_ref() };
 
     let elf = ElfBytes::<AnyEndian>::minimal_parse(elf_bytes).expect("Failed to parse ELF");
+    if let Err(e) = check_segment_bounds(&elf, module.size()) {
+        panic!("init module {:?} is malformed: {e}", module.path());
+    }
 
     // Track user-space virtual address allocations
     let mut user_vaddr_set: NoditSet<u64, Interval<u64>> = NoditSet::default();
@@ -99,7 +209,8 @@ pub fn create_user_task_from_elf() -> Task {
     // Create new address space for user mode
     let memory = MEMORY.get().unwrap();
     let mut physical_memory = memory.physical_memory.lock();
-    let (l4_frame, mut mapper) = unsafe { create_user_page_table(&mut physical_memory) };
+    let (l4_frame, mut mapper) = unsafe { create_user_page_table(&mut physical_memory) }
+        .ok_or(SpawnError::OutOfMemory)?;
     let cr3 = l4_frame.start_address().as_u64();
 
     // Remove the module from physical memory map
@@ -120,9 +231,6 @@ pub fn create_user_task_from_elf() -> Task {
             continue;
         }
 
-        // Make sure the segment is only referencing file memory contained within the ELF
-        assert!(segment.p_offset + segment.p_filesz <= module.size());
-
         let start_page: Page<Size4KiB> = Page::containing_address(
             VirtAddr::new(segment.p_vaddr),
         );
@@ -144,7 +252,7 @@ pub fn create_user_task_from_elf() -> Task {
             let frame = start_frame + i;
             let mut frame_allocator = physical_memory.get_user_mode_frame_allocator();
             unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) }
-                .unwrap()
+                .map_err(|_| SpawnError::OutOfMemory)?
                 .ignore();
         }
 
@@ -187,14 +295,14 @@ pub fn create_user_task_from_elf() -> Task {
                 let page = extra_start_page + i;
                 let frame = physical_memory
                     .allocate_frame_with_type(MemoryType::UsedByUserMode)
-                    .unwrap();
+                    .ok_or(SpawnError::OutOfMemory)?;
                 let frame_ptr =
                     NonNull::new(frame.start_address().offset_mapped().as_mut_ptr::<u8>()).unwrap();
                 // Safety: we own the frame
                 unsafe { frame_ptr.write_bytes(0, page_size as usize) };
                 let mut frame_allocator = physical_memory.get_user_mode_frame_allocator();
                 unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) }
-                    .unwrap()
+                    .map_err(|_| SpawnError::OutOfMemory)?
                     .ignore();
             }
             total_pages += extra_pages_len;
@@ -247,34 +355,11 @@ pub fn create_user_task_from_elf() -> Task {
         unsafe { ptr.write_bytes(0, count) };
     }
 
-    // Allocate a user stack at the top of the canonical lower half.
-    // LOWER_HALF_END is 0x7FFFFFFFFFFF (inclusive). Align down to page boundary
-    // for the stack top, then subtract one page so RSP starts within mapped pages.
-    let rsp = (LOWER_HALF_END + 1) - 0x1000;
-    {
-        let stack_size: u64 = 64 * 0x400;
-        let pages_len = stack_size.div_ceil(page_size);
-        let stack_start_vaddr = rsp - pages_len * page_size;
-        let start_page: Page<Size4KiB> = Page::containing_address(
-            VirtAddr::new(stack_start_vaddr),
-        );
-        let stack_flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE
-            | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
-        for i in 0..pages_len {
-            let page = start_page + i;
-            let frame = physical_memory
-                .allocate_frame_with_type(MemoryType::UsedByUserMode)
-                .unwrap();
-            let mut frame_allocator = physical_memory.get_user_mode_frame_allocator();
-            unsafe { mapper.map_to(page, frame, stack_flags, &mut frame_allocator) }
-                .unwrap()
-                .ignore();
-        }
-        // Track the stack virtual address range
-        user_vaddr_set
-            .insert_merge_touching(ie(stack_start_vaddr, stack_start_vaddr + pages_len * page_size))
-            .expect("user stack vaddr overlap");
-    };
+    // Map the top page of the user stack; the rest is mapped as it grows
+    let user_stack = map_user_stack(&mut mapper, &mut physical_memory)?;
+    user_vaddr_set
+        .insert_merge_touching(user_stack.reserved)
+        .expect("user stack vaddr overlap");
 
     // Release memory lock
     drop(physical_memory);
@@ -285,10 +370,19 @@ pub fn create_user_task_from_elf() -> Task {
     let user_cs = gdt.user_code_selector().0;
     let user_ss = gdt.user_data_selector().0;
 
-    Task::new_user(entry_point.get(), rsp, l4_frame, cr3, user_cs, user_ss, user_vaddr_set, 0)
+    let mut task = Task::new_user(
+        entry_point.get(), USER_STACK_TOP, l4_frame, cr3, user_cs, user_ss,
+        user_vaddr_set, user_stack.lazy_regions, user_stack.guard, 0,
+    );
+    task.privilege = Privilege::System;
+    // Tasks spawned later name themselves; this one is named after its module.
+    if let Ok(path) = core::str::from_utf8(path) {
+        task.set_name(path.trim_start_matches('/'));
+    }
+    Ok(task)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SpawnError {
     InvalidElf,
     OutOfMemory,
@@ -301,6 +395,7 @@ pub enum SpawnError {
 pub fn create_user_task_from_elf_bytes(elf_bytes: &[u8], child_arg: u64) -> Result<Task, SpawnError> {
     let elf = ElfBytes::<AnyEndian>::minimal_parse(elf_bytes)
         .map_err(|_| SpawnError::InvalidElf)?;
+    check_segment_bounds(&elf, elf_bytes.len() as u64).map_err(|_| SpawnError::InvalidElf)?;
 
     let mut user_vaddr_set: NoditSet<u64, Interval<u64>> = NoditSet::default();
 
@@ -308,7 +403,8 @@ pub fn create_user_task_from_elf_bytes(elf_bytes: &[u8], child_arg: u64) -> Resu
     let mut physical_memory = memory.physical_memory.lock();
     let (l4_frame, mut mapper) = unsafe {
         create_user_page_table(&mut physical_memory)
-    };
+    }
+    .ok_or(SpawnError::OutOfMemory)?;
     let cr3 = l4_frame.start_address().as_u64();
 
     let page_size = Size4KiB::SIZE;
@@ -319,11 +415,6 @@ pub fn create_user_task_from_elf_bytes(elf_bytes: &[u8], child_arg: u64) -> Resu
             continue;
         }
 
-        // Validate the segment references data within the ELF bytes
-        if segment.p_offset + segment.p_filesz > elf_bytes.len() as u64 {
-            return Err(SpawnError::InvalidElf);
-        }
-
         let start_page: Page<Size4KiB> = Page::containing_address(
             VirtAddr::new(segment.p_vaddr),
         );
@@ -415,31 +506,11 @@ pub fn create_user_task_from_elf_bytes(elf_bytes: &[u8], child_arg: u64) -> Resu
     // Parse entry point
     let entry_point = NonZero::new(elf.ehdr.e_entry).ok_or(SpawnError::InvalidElf)?;
 
-    // Allocate a user stack at the top of the canonical lower half
-    let rsp = (LOWER_HALF_END + 1) - 0x1000;
-    {
-        let stack_size: u64 = 64 * 0x400;
-        let pages_len = stack_size.div_ceil(page_size);
-        let stack_start_vaddr = rsp - pages_len * page_size;
-        let start_page: Page<Size4KiB> = Page::containing_address(
-            VirtAddr::new(stack_start_vaddr),
-        );
-        let stack_flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE
-            | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
-        for i in 0..pages_len {
-            let page = start_page + i;
-            let frame = physical_memory
-                .allocate_frame_with_type(MemoryType::UsedByUserMode)
-                .ok_or(SpawnError::OutOfMemory)?;
-            let mut frame_allocator = physical_memory.get_user_mode_frame_allocator();
-            unsafe { mapper.map_to(page, frame, stack_flags, &mut frame_allocator) }
-                .map_err(|_| SpawnError::OutOfMemory)?
-                .ignore();
-        }
-        user_vaddr_set
-            .insert_merge_touching(ie(stack_start_vaddr, stack_start_vaddr + pages_len * page_size))
-            .expect("user stack vaddr overlap");
-    }
+    // An ELF can't put segments where the stack may grow to
+    let user_stack = map_user_stack(&mut mapper, &mut physical_memory)?;
+    user_vaddr_set
+        .insert_merge_touching(user_stack.reserved)
+        .map_err(|_| SpawnError::InvalidElf)?;
 
     drop(physical_memory);
 
@@ -448,7 +519,10 @@ pub fn create_user_task_from_elf_bytes(elf_bytes: &[u8], child_arg: u64) -> Resu
     let user_cs = gdt.user_code_selector().0;
     let user_ss = gdt.user_data_selector().0;
 
-    Ok(Task::new_user(entry_point.get(), rsp, l4_frame, cr3, user_cs, user_ss, user_vaddr_set, child_arg))
+    Ok(Task::new_user(
+        entry_point.get(), USER_STACK_TOP, l4_frame, cr3, user_cs, user_ss,
+        user_vaddr_set, user_stack.lazy_regions, user_stack.guard, child_arg,
+    ))
 }
 
 bitflags! {
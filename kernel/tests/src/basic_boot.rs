@@ -2,9 +2,9 @@
 #![no_main]
 
 use kernel::graphics::display;
-use kernel::limine_requests::{FRAME_BUFFER_REQUEST, KERNEL_FILE_REQUEST, MEMORY_MAP_REQUEST, MP_REQUEST, RSDP_REQUEST};
+use kernel::limine_requests::{FRAME_BUFFER_REQUEST, MEMORY_MAP_REQUEST, MP_REQUEST, RSDP_REQUEST};
 use kernel::interrupt::nmi_handler_state;
-use kernel::{acpi, apic, gdt, interrupt, logger, time};
+use kernel::{acpi, apic, gdt, interrupt, ioapic, logger, numa, power, time};
 
 #[unsafe(no_mangle)]
 unsafe extern "C" fn kernel_main() -> ! {
@@ -14,6 +14,8 @@ unsafe extern "C" fn kernel_main() -> ! {
 
     // Enable logger
     logger::init().unwrap();
+    kernel::cmdline::init();
+    logger::apply_cmdline_level();
     log::info!("Welcome to Bos! V:0.3.0");
 
     let _ = MP_REQUEST.get_response(); // Ensure MP response is available
@@ -23,6 +25,8 @@ unsafe extern "C" fn kernel_main() -> ! {
         kernel::memory::cpu_local_data::init_bsp();
     }
     log::info!("BSP memory initialized.");
+    display::init_back_buffer();
+    kernel::symbols::init();
 
     // Initialize core kernel features before tests
     nmi_handler_state::init();
@@ -31,20 +35,24 @@ unsafe extern "C" fn kernel_main() -> ! {
 
     let rsdp = RSDP_REQUEST.get_response().unwrap();
     let acpi_tables = acpi::parse(rsdp);
+    numa::init(&acpi_tables);
+    power::init(&acpi_tables);
+    time::rtc::init(&acpi_tables);
     apic::init_bsp(&acpi_tables);
     apic::init_local_apic();
+    ioapic::init(&acpi_tables);
+    kernel::drivers::pci::init();
+    kernel::drivers::e1000::init();
 
+    time::hpet::init(&acpi_tables);
     time::tsc::calibrate();
     time::lapic_timer::init();
     time::lapic_timer::set_deadline(1_000_000);
 
     kernel::task::local_scheduler::init_run_queue();
 
-    // Read the kernel cmdline from Limine to determine which test group to run.
-    let group_filter = KERNEL_FILE_REQUEST
-        .get_response()
-        .map(|r| tests::parse_test_group(r.file().string().to_bytes()))
-        .flatten();
+    // The kernel cmdline's test_suite= picks which test group to run.
+    let group_filter = tests::parse_test_group(kernel::cmdline::raw().as_bytes());
 
     tests::run_tests(group_filter);
 }
\ No newline at end of file
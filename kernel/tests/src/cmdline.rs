@@ -0,0 +1,55 @@
+use alloc::vec::Vec;
+use kernel::cmdline;
+use crate::{TestGroup, TestResult};
+
+/// Bare flags, quoted values, duplicates and odd spacing parse as documented.
+pub fn cmdline_parses_options() -> TestResult {
+    let line = "  test_suite=elf quiet init=\"/my init\"  loglevel=info loglevel=debug empty= open=\"no end";
+    let options: Vec<(&str, &str)> = cmdline::options(line).collect();
+    let want = [
+        ("test_suite", "elf"),
+        ("quiet", ""),
+        ("init", "/my init"),
+        ("loglevel", "info"),
+        ("loglevel", "debug"),
+        ("empty", ""),
+        ("open", "no end"),
+    ];
+    if options != want {
+        return TestResult::Failed(alloc::format!("Parsed {:?}", options));
+    }
+    let lookups = [
+        cmdline::lookup(line, "loglevel"),
+        cmdline::lookup(line, "quiet"),
+        cmdline::lookup(line, "init"),
+        cmdline::lookup(line, "missing"),
+        cmdline::lookup("", "quiet"),
+    ];
+    if lookups != [Some("debug"), Some(""), Some("/my init"), None, None] {
+        return TestResult::Failed(alloc::format!("Looked up {:?}", lookups));
+    }
+    TestResult::Ok
+}
+
+/// The test group still comes from `test_suite=`, wherever it sits on the line.
+pub fn cmdline_selects_test_group() -> TestResult {
+    let got = [
+        crate::parse_test_group(b"test_suite=elf"),
+        crate::parse_test_group(b"loglevel=warn test_suite=\"sched-noelf\""),
+        crate::parse_test_group(b"test_suite=mem test_suite=net"),
+        crate::parse_test_group(b"my_test_suite=mem"),
+        crate::parse_test_group(b"test_suite=bogus"),
+    ];
+    let want = [
+        Some(TestGroup::Elf),
+        Some(TestGroup::SchedulerNoElf),
+        Some(TestGroup::Net),
+        None,
+        None,
+    ];
+    if got == want {
+        TestResult::Ok
+    } else {
+        TestResult::Failed(alloc::format!("Selected {:?}", got))
+    }
+}
@@ -0,0 +1,37 @@
+use crate::TestResult;
+use alloc::format;
+use kernel::graphics::display::{init_events, notify_display_changed, DISPLAY};
+use kernel::ipc;
+use kernel::service_registry;
+use kernel_api_types::graphics::{DisplayInfo, KERNEL_DISPLAY_EVENTS_SERVICE};
+
+/// The display-change broadcast is registered under its service name, and a
+/// notification reaches a subscriber as the current `DisplayInfo`.
+pub fn test_display_change_reaches_subscriber() -> TestResult {
+    let broadcast_id = init_events();
+    if service_registry::lookup(KERNEL_DISPLAY_EVENTS_SERVICE) != Some(broadcast_id) {
+        return TestResult::Failed("display events broadcast not registered".into());
+    }
+    let subscriber = match ipc::subscribe(broadcast_id) {
+        Ok(ep) => ep,
+        Err(e) => return TestResult::Failed(format!("subscribe failed: {:?}", e)),
+    };
+
+    notify_display_changed();
+    let received = ipc::try_recv(subscriber);
+    let _ = ipc::close_endpoint(subscriber);
+
+    let message = match received {
+        Ok(m) => m,
+        Err(e) => return TestResult::Failed(format!("Expected a display change message, got {:?}", e)),
+    };
+    if message.len() != size_of::<DisplayInfo>() {
+        return TestResult::Failed(format!("Expected {} bytes, got {}", size_of::<DisplayInfo>(), message.len()));
+    }
+    let info = unsafe { core::ptr::read_unaligned(message.as_ptr() as *const DisplayInfo) };
+    let current = DISPLAY.get_display_info();
+    if info != current {
+        return TestResult::Failed(format!("Expected {:?}, got {:?}", current, info));
+    }
+    TestResult::Ok
+}
@@ -1,9 +1,13 @@
+use crate::scheduler::with_scratch_run_queue;
 use crate::TestResult;
 use alloc::format;
+use alloc::sync::Arc;
 use core::sync::atomic::Ordering;
-use kernel::graphics::display::{DISPLAY_OWNER, is_display_owner};
-use kernel::syscall_handlers::{sys_get_bounding_box, sys_transfer_display};
-use kernel_api_types::graphics::GraphicsResult;
+use kernel::graphics::display::{DISPLAY_HOME, DISPLAY_OWNER, is_display_owner};
+use kernel::syscall_handlers::{sys_get_bounding_box, sys_is_display_owner, sys_kill, sys_transfer_display};
+use kernel::task::registry;
+use kernel::task::task::Task;
+use kernel_api_types::SysError;
 
 /// Helper: save and restore DISPLAY_OWNER around a test closure.
 fn with_display_owner<F: FnOnce() -> TestResult>(owner: u64, f: F) -> TestResult {
@@ -41,42 +45,159 @@ pub fn test_no_owner_is_not_owner() -> TestResult {
 pub fn test_non_owner_get_bounding_box_rejected() -> TestResult {
     with_display_owner(u64::MAX, || {
         let ret = sys_get_bounding_box(0, 0, 0, 0, 0, 0);
-        if ret == GraphicsResult::PermissionDenied as u64 {
+        if ret == SysError::PermissionDenied as u64 {
             TestResult::Ok
         } else {
             TestResult::Failed(format!(
-                "Expected PermissionDenied ({}), got {}",
-                GraphicsResult::PermissionDenied as u64,
+                "Expected PermissionDenied ({:#x}), got {:#x}",
+                SysError::PermissionDenied as u64,
                 ret
             ))
         }
     })
 }
 
-/// A non-owner calling sys_transfer_display returns 1 (not owner).
+/// A non-owner calling sys_transfer_display gets PermissionDenied.
 pub fn test_transfer_display_not_owner() -> TestResult {
     with_display_owner(0xBEEF, || {
         let ret = sys_transfer_display(0xDEAD, 0, 0, 0, 0, 0);
-        if ret == 1 {
+        if ret == SysError::PermissionDenied as u64 {
             TestResult::Ok
         } else {
-            TestResult::Failed(format!("Expected 1 (not owner), got {}", ret))
+            TestResult::Failed(format!("Expected PermissionDenied (not owner), got {:#x}", ret))
         }
     })
 }
 
-/// Calling sys_transfer_display with a non-existent target task returns 2,
+/// Calling sys_transfer_display with a non-existent target task returns NotFound,
 /// but only if the caller is the owner. Since tests have no current_task,
-/// we can't be the owner — so this will return 1. We verify that behavior.
+/// we can't be the owner — so this will return PermissionDenied. We verify that behavior.
 pub fn test_transfer_display_no_current_task() -> TestResult {
     // Even if DISPLAY_OWNER matches nothing, current_task is None so
-    // is_display_owner() returns false -> returns 1.
+    // is_display_owner() returns false -> returns PermissionDenied.
     with_display_owner(u64::MAX, || {
         let ret = sys_transfer_display(0xDEADBEEF, 0, 0, 0, 0, 0);
-        if ret == 1 {
+        if ret == SysError::PermissionDenied as u64 {
             TestResult::Ok
         } else {
-            TestResult::Failed(format!("Expected 1 (not owner), got {}", ret))
+            TestResult::Failed(format!("Expected PermissionDenied (not owner), got {:#x}", ret))
+        }
+    })
+}
+
+fn spin() -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// The owner transferring to a task that doesn't exist, or to a kernel task,
+/// gets an error and keeps the display.
+pub fn test_transfer_display_invalid_target() -> TestResult {
+    with_scratch_run_queue(|cpu| {
+        let owner = Arc::new(Task::new(spin));
+        let kernel_task = Arc::new(Task::new(spin));
+        registry::register(&kernel_task);
+        cpu.run_queue.get().unwrap().lock().current_task = Some(owner.clone());
+
+        let result = with_display_owner(owner.id.to_u64(), || {
+            let missing = sys_transfer_display(u64::MAX, 0, 0, 0, 0, 0);
+            let to_kernel = sys_transfer_display(kernel_task.id.to_u64(), 0, 0, 0, 0, 0);
+            let still_owner = DISPLAY_OWNER.load(Ordering::Relaxed) == owner.id.to_u64();
+            if missing != SysError::NotFound as u64 || to_kernel != SysError::InvalidArgs as u64 {
+                TestResult::Failed(format!(
+                    "Expected NotFound/InvalidArgs for a missing/kernel target, got {:#x}/{:#x}",
+                    missing, to_kernel
+                ))
+            } else if !still_owner {
+                TestResult::Failed("A failed transfer changed the display owner".into())
+            } else {
+                TestResult::Ok
+            }
+        });
+
+        registry::unregister(kernel_task.id);
+        cpu.run_queue.get().unwrap().lock().current_task = None;
+        result
+    })
+}
+
+/// IsDisplayOwner answers 1 for the owner and 0 for any other task, and 0
+/// with no current task.
+pub fn test_is_display_owner_query() -> TestResult {
+    with_scratch_run_queue(|cpu| {
+        let owner = Arc::new(Task::new(spin));
+        let other = Arc::new(Task::new(spin));
+
+        let result = with_display_owner(owner.id.to_u64(), || {
+            let mut answers = [0; 3];
+            for (answer, current) in answers.iter_mut().zip([Some(&owner), Some(&other), None]) {
+                cpu.run_queue.get().unwrap().lock().current_task = current.cloned();
+                *answer = sys_is_display_owner(0, 0, 0, 0, 0, 0);
+            }
+            if answers == [1, 0, 0] {
+                TestResult::Ok
+            } else {
+                TestResult::Failed(format!("Expected [1, 0, 0] for owner/other/none, got {:?}", answers))
+            }
+        });
+
+        cpu.run_queue.get().unwrap().lock().current_task = None;
+        result
+    })
+}
+
+/// Kill a child of a scratch current task while the child owns the display,
+/// with `home` as DISPLAY_HOME, then check who owns the display.
+fn kill_owner_then(home: Option<&Arc<Task>>, check: impl FnOnce(u64) -> TestResult) -> TestResult {
+    with_scratch_run_queue(|cpu| {
+        let parent = Arc::new(Task::new(spin));
+        let mut child = Task::new(spin);
+        child.parent = Some(parent.id);
+        let child = Arc::new(child);
+        registry::register(&child);
+        cpu.run_queue.get().unwrap().lock().current_task = Some(parent.clone());
+
+        let saved_home = DISPLAY_HOME.swap(home.map_or(u64::MAX, |t| t.id.to_u64()), Ordering::Relaxed);
+        let result = with_display_owner(child.id.to_u64(), || {
+            let ret = sys_kill(child.id.to_u64(), 0, 0, 0, 0, 0);
+            if ret != 0 {
+                return TestResult::Failed(format!("sys_kill of the owner returned {:#x}", ret));
+            }
+            check(DISPLAY_OWNER.load(Ordering::Relaxed))
+        });
+        DISPLAY_HOME.store(saved_home, Ordering::Relaxed);
+
+        registry::unregister(child.id);
+        cpu.run_queue.get().unwrap().lock().current_task = None;
+        result
+    })
+}
+
+/// When the owner ends without transferring, the display goes back to the
+/// home (init) task.
+pub fn test_owner_exit_returns_display_home() -> TestResult {
+    let home = Arc::new(Task::new(spin));
+    registry::register(&home);
+    let result = kill_owner_then(Some(&home), |owner| {
+        if owner == home.id.to_u64() {
+            TestResult::Ok
+        } else {
+            TestResult::Failed(format!("Expected the home task {} to own the display, got {:#x}", home.id.to_u64(), owner))
+        }
+    });
+    registry::unregister(home.id);
+    result
+}
+
+/// With no live home task, the display is left with no owner rather than
+/// naming the dead one.
+pub fn test_owner_exit_without_home_clears_owner() -> TestResult {
+    kill_owner_then(None, |owner| {
+        if owner == u64::MAX {
+            TestResult::Ok
+        } else {
+            TestResult::Failed(format!("Expected no display owner, got {:#x}", owner))
         }
     })
 }
@@ -276,7 +276,10 @@ pub fn test_direct_elf_entry_matches() -> TestResult {
     };
     let expected_entry = elf.ehdr.e_entry;
 
-    let task = kernel::user_task_from_elf::create_user_task_from_elf();
+    let task = match kernel::user_task_from_elf::create_user_task_from_elf() {
+        Ok(task) => task,
+        Err(e) => return TestResult::Failed(format!("create_user_task_from_elf failed: {:?}", e)),
+    };
     let rip = task.inner.lock().context.rip;
 
     if rip != expected_entry {
@@ -288,6 +291,55 @@ pub fn test_direct_elf_entry_matches() -> TestResult {
     TestResult::Ok
 }
 
+/// Free-list link in a frame held by `take_all_frames`: no more frames.
+const NO_FRAME: u64 = u64::MAX;
+
+/// Allocate every free frame. Each one holds the address of the one taken
+/// before it, so the chain needs no heap. Returns the address of the last.
+fn take_all_frames() -> u64 {
+    use kernel::memory::physical_memory::{KernelMemoryUsageType, MemoryType, OffsetMappedPhysAddr};
+
+    let mut pm = kernel::memory::MEMORY.get().unwrap().physical_memory.lock();
+    let mut last = NO_FRAME;
+    while let Some(frame) = pm.allocate_frame_with_type(MemoryType::UsedByKernel(KernelMemoryUsageType::PageTables)) {
+        unsafe { frame.start_address().offset_mapped().as_mut_ptr::<u64>().write(last) };
+        last = frame.start_address().as_u64();
+    }
+    last
+}
+
+/// Free the chain of frames from `take_all_frames`.
+fn give_back_frames(mut next: u64) {
+    use kernel::memory::physical_memory::{KernelMemoryUsageType, MemoryType, OffsetMappedPhysAddr};
+    use kernel::reexports::x86_64::structures::paging::PhysFrame;
+    use kernel::reexports::x86_64::PhysAddr;
+
+    let mut pm = kernel::memory::MEMORY.get().unwrap().physical_memory.lock();
+    while next != NO_FRAME {
+        let addr = PhysAddr::new(next);
+        next = unsafe { addr.offset_mapped().as_ptr::<u64>().read() };
+        let _ = pm.free_frame(
+            PhysFrame::containing_address(addr),
+            MemoryType::UsedByKernel(KernelMemoryUsageType::PageTables),
+        );
+    }
+}
+
+/// With every frame taken, creating the init task fails with `OutOfMemory`
+/// rather than panicking.
+pub fn test_direct_elf_out_of_memory() -> TestResult {
+    use kernel::user_task_from_elf::{create_user_task_from_elf, SpawnError};
+
+    let taken = take_all_frames();
+    let result = create_user_task_from_elf();
+    give_back_frames(taken);
+    match result {
+        Err(SpawnError::OutOfMemory) => TestResult::Ok,
+        Err(e) => TestResult::Failed(format!("Expected OutOfMemory, got {:?}", e)),
+        Ok(_) => TestResult::Failed("created the init task with no frames free".into()),
+    }
+}
+
 /// After `create_user_task_from_elf_bytes`, switch to the user page table
 /// and verify:
 ///   1. The entry point vaddr is readable (non-null bytes present).
@@ -446,3 +498,70 @@ pub fn test_spawn_bss_zeroed() -> TestResult {
         None => TestResult::Ok,
     }
 }
+
+/// A LOAD segment whose file data runs past the end of the image is reported
+/// with its program header index and bounds, and refused by the spawn loader.
+pub fn test_segment_out_of_bounds_reported() -> TestResult {
+    use kernel::user_task_from_elf::{check_segment_bounds, create_user_task_from_elf_bytes, SpawnError};
+
+    let (bytes, elf) = match parse_elf(b"/init_task") {
+        Ok(t) => t,
+        Err(r) => return r,
+    };
+    if let Err(e) = check_segment_bounds(&elf, bytes.len() as u64) {
+        return TestResult::Failed(format!("intact init_task rejected: {e}"));
+    }
+    let Some(index) = elf.segments().and_then(|s| s.iter().position(|s| s.p_type == PT_LOAD)) else {
+        return TestResult::Failed("init_task has no PT_LOAD segment".into());
+    };
+
+    // Stretch that segment's p_filesz (offset 32 in an Elf64_Phdr) past the
+    // end of the file.
+    let mut bad = bytes.to_vec();
+    let phdr = elf.ehdr.e_phoff as usize + index * elf.ehdr.e_phentsize as usize;
+    let filesz = bad.len() as u64 + 0x1000;
+    bad[phdr + 32..phdr + 40].copy_from_slice(&filesz.to_le_bytes());
+
+    let bad_elf = match ElfBytes::<AnyEndian>::minimal_parse(&bad) {
+        Ok(elf) => elf,
+        Err(e) => return TestResult::Failed(format!("patched ELF no longer parses: {e}")),
+    };
+    let err = match check_segment_bounds(&bad_elf, bad.len() as u64) {
+        Ok(()) => return TestResult::Failed("oversized segment not detected".into()),
+        Err(e) => e,
+    };
+    if err.index != index || err.file_size != filesz || err.image_size != bad.len() as u64 {
+        return TestResult::Failed(format!("wrong diagnostic: {err:?}"));
+    }
+    let message = format!("{err}");
+    if !message.contains(&format!("segment {index}")) {
+        return TestResult::Failed(format!("message doesn't name the segment: {message}"));
+    }
+
+    match create_user_task_from_elf_bytes(&bad, 0) {
+        Err(SpawnError::InvalidElf) => TestResult::Ok,
+        Err(e) => TestResult::Failed(format!("spawn failed with {e:?}, expected InvalidElf")),
+        Ok(_) => TestResult::Failed("spawned a task from a malformed ELF".into()),
+    }
+}
+
+/// A kernel function's own address resolves to its name at offset 0, and an
+/// address inside it to the same name further in.
+pub fn test_symbols_resolve_kernel_function() -> TestResult {
+    let addr = test_symbols_resolve_kernel_function as usize as u64;
+    let Some((name, offset)) = kernel::symbols::resolve(addr) else {
+        return TestResult::Failed(format!("{addr:#x} doesn't resolve"));
+    };
+    if !name.contains("test_symbols_resolve_kernel_function") || offset != 0 {
+        return TestResult::Failed(format!("{addr:#x} resolved to {name}+{offset:#x}"));
+    }
+    match kernel::symbols::resolve(addr + 1) {
+        Some((inner, 1)) if inner == name => {}
+        other => return TestResult::Failed(format!("{:#x} resolved to {other:?}", addr + 1)),
+    }
+    let shown = format!("{}", kernel::symbols::Addr(addr));
+    if !shown.contains(name) {
+        return TestResult::Failed(format!("Addr shows {shown}"));
+    }
+    TestResult::Ok
+}
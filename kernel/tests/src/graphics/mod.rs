@@ -24,3 +24,34 @@ pub fn bounding_box_valid() -> TestResult {
         TestResult::Failed(alloc::format!("Invalid bounding box: {:?}", bb))
     }
 }
+
+/// First pixel as it is in VRAM, read through the HHDM.
+fn vram_first_pixel() -> u32 {
+    let (phys, _) = DISPLAY.get_fb_phys_and_size();
+    let hhdm = kernel::memory::hhdm_offset::hhdm_offset().as_u64();
+    unsafe { core::ptr::read_volatile((hhdm + phys.as_u64()) as *const u32) }
+}
+
+/// While drawing is buffered nothing reaches VRAM until `present`.
+pub fn buffered_draw_waits_for_present() -> TestResult {
+    let pixel = Rectangle::new(Point::new(0, 0), Size::new(1, 1));
+    let _ = DISPLAY.fill_solid(&pixel, Rgb888::new(0x12, 0x34, 0x56));
+    let before = vram_first_pixel();
+
+    if !DISPLAY.set_buffered(true) {
+        return TestResult::Failed(alloc::string::String::from("No display back buffer"));
+    }
+    let _ = DISPLAY.fill_solid(&pixel, Rgb888::new(0x65, 0x43, 0x21));
+    let unpresented = vram_first_pixel();
+    DISPLAY.present();
+    let presented = vram_first_pixel();
+    DISPLAY.set_buffered(false);
+
+    if unpresented != before {
+        return TestResult::Failed(alloc::format!("VRAM changed before present: {before:#x} -> {unpresented:#x}"));
+    }
+    if presented == before {
+        return TestResult::Failed(alloc::format!("present left VRAM at {before:#x}"));
+    }
+    TestResult::Ok
+}
@@ -0,0 +1,121 @@
+use alloc::format;
+use core::sync::atomic::Ordering;
+use kernel::drivers::serial::{self, SERIAL_INTERRUPT_COUNT};
+use kernel::interrupt::InterruptVector;
+use kernel::ioapic;
+use kernel::logger;
+use kernel::memory::cpu_local_data::get_local;
+use kernel::time::tsc;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use crate::TestResult;
+
+const COM1: u16 = 0x3f8;
+const MODEM_CONTROL: u16 = COM1 + 4;
+const LINE_STATUS: u16 = COM1 + 5;
+const MCR_LOOPBACK: u8 = 1 << 4;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+const TEST_BYTE: u8 = b'Z';
+
+/// Route COM1's IRQ through the IOAPIC like the kernel does at boot.
+fn route_serial_irq() -> Result<u32, TestResult> {
+    let (gsi, flags) = ioapic::isa_irq_to_gsi(ioapic::ISA_IRQ_COM1);
+    if let Err(e) = ioapic::route_irq(gsi, u8::from(InterruptVector::Serial), get_local().local_apic_id, flags) {
+        return Err(TestResult::Failed(format!("route_irq(GSI {gsi}) failed: {e:?}")));
+    }
+    serial::enable_rx_interrupt();
+    Ok(gsi)
+}
+
+/// Loop `bytes` back through the UART, as if they had been typed into QEMU's
+/// serial console, and wait until `done` holds (with interrupts enabled) or
+/// 100 ms pass. Returns the final `done()`.
+fn loop_back(bytes: &[u8], done: impl Fn() -> bool) -> bool {
+    let interrupts_enabled = interrupts::are_enabled();
+    // No logging until loopback is off again: it would never reach the console.
+    let mut mcr = Port::<u8>::new(MODEM_CONTROL);
+    let saved_mcr = logger::with_serial_port(|| unsafe {
+        let saved = mcr.read();
+        mcr.write(saved | MCR_LOOPBACK);
+        let mut data = Port::<u8>::new(COM1);
+        let mut status = Port::<u8>::new(LINE_STATUS);
+        for &byte in bytes {
+            while status.read() & LSR_TRANSMIT_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+            data.write(byte);
+        }
+        saved
+    });
+    interrupts::enable();
+
+    let timeout = tsc::TSC_HZ.load(Ordering::SeqCst) * 100; // 100 ms
+    let start_tsc = tsc::value();
+    while !done() && tsc::value() - start_tsc < timeout {
+        core::hint::spin_loop();
+    }
+    let result = done();
+
+    logger::with_serial_port(|| unsafe { mcr.write(saved_mcr) });
+    if !interrupts_enabled {
+        interrupts::disable();
+    }
+    result
+}
+
+/// A byte arriving on COM1 raises its IRQ through the IOAPIC.
+pub fn serial_irq_delivered() -> TestResult {
+    let gsi = match route_serial_irq() {
+        Ok(gsi) => gsi,
+        Err(failed) => return failed,
+    };
+    // Anything already waiting would keep the IRQ line high and hide our edge.
+    let mut buf = [0u8; 16];
+    while serial::read(&mut buf) != 0 {}
+
+    let initial_count = SERIAL_INTERRUPT_COUNT.load(Ordering::SeqCst);
+    let fired = loop_back(&[TEST_BYTE], || SERIAL_INTERRUPT_COUNT.load(Ordering::SeqCst) != initial_count);
+    let n = serial::read(&mut buf);
+    let _ = ioapic::mask_irq(gsi);
+
+    if !fired {
+        return TestResult::Failed(format!("No serial interrupt within 100 ms (GSI {gsi})"));
+    }
+    if buf[..n] != [TEST_BYTE] {
+        return TestResult::Failed(format!("Expected to receive {:?}, got {:?}", [TEST_BYTE], &buf[..n]));
+    }
+    TestResult::Ok
+}
+
+/// The receive interrupt moves input into the kernel's buffer without anyone
+/// calling `read`, and `read` hands it out in order.
+pub fn serial_rx_fills_buffer() -> TestResult {
+    const INPUT: &[u8] = b"hello, bos";
+    let gsi = match route_serial_irq() {
+        Ok(gsi) => gsi,
+        Err(failed) => return failed,
+    };
+    let mut buf = [0u8; 32];
+    while serial::read(&mut buf) != 0 {}
+
+    let filled = loop_back(INPUT, || serial::buffered() == INPUT.len());
+    let buffered = serial::buffered();
+    let n = serial::read(&mut buf);
+    let _ = ioapic::mask_irq(gsi);
+
+    if !filled {
+        return TestResult::Failed(format!("Only {buffered} of {} bytes were buffered", INPUT.len()));
+    }
+    if &buf[..n] != INPUT {
+        return TestResult::Failed(format!("Expected {:?}, read {:?}", INPUT, &buf[..n]));
+    }
+    TestResult::Ok
+}
+
+/// A GSI past the IOAPIC's last pin is rejected rather than written.
+pub fn route_irq_rejects_unknown_gsi() -> TestResult {
+    match ioapic::route_irq(u32::MAX, u8::from(InterruptVector::Serial), 0, ioapic::IrqFlags::ISA) {
+        Err(ioapic::IoApicError::GsiOutOfRange) => TestResult::Ok,
+        other => TestResult::Failed(format!("Expected GsiOutOfRange, got {other:?}")),
+    }
+}
@@ -0,0 +1,59 @@
+use kernel::logger::{self, DROPPED_LINES};
+use kernel::time::{lapic_timer, timers, tsc};
+use alloc::boxed::Box;
+use alloc::format;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+use crate::TestResult;
+
+static TIMER_LINES: AtomicUsize = AtomicUsize::new(0);
+
+/// Timer callbacks log while the main path logs in a loop. Before the
+/// logger held its lock with interrupts off, a tick landing mid-line spun on
+/// the lock its own CPU held.
+pub fn log_from_timer_while_logging() -> TestResult {
+    const TIMERS: usize = 20;
+    TIMER_LINES.store(0, Ordering::SeqCst);
+
+    let interrupts_enabled = interrupts::are_enabled();
+    interrupts::without_interrupts(|| {
+        for i in 0..TIMERS {
+            timers::register_timer(i as u64 % 5, Box::new(move || {
+                log::info!("logger test: timer {i} logging from interrupt context");
+                TIMER_LINES.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+    });
+    interrupts::enable();
+    lapic_timer::set_deadline(1_000_000);
+
+    let timeout = tsc::TSC_HZ.load(Ordering::SeqCst) * 1000; // 1 s
+    let start = tsc::value();
+    let mut main_lines = 0;
+    while TIMER_LINES.load(Ordering::SeqCst) < TIMERS && tsc::value() - start < timeout {
+        log::info!("logger test: main path line {main_lines}");
+        main_lines += 1;
+    }
+    if !interrupts_enabled {
+        interrupts::disable();
+    }
+
+    let fired = TIMER_LINES.load(Ordering::SeqCst);
+    if fired != TIMERS {
+        return TestResult::Failed(format!("{fired} of {TIMERS} timer callbacks logged within 1 s"));
+    }
+    TestResult::Ok
+}
+
+/// A breakpoint taken while this CPU holds the logger lock logs from the
+/// handler without waiting for the lock. The line is held back and written
+/// after the interrupted one, not dropped.
+pub fn log_from_exception_while_holding_logger() -> TestResult {
+    let dropped = DROPPED_LINES.load(Ordering::SeqCst);
+    logger::with_serial_port(interrupts::int3);
+    let now_dropped = DROPPED_LINES.load(Ordering::SeqCst);
+    if now_dropped != dropped {
+        return TestResult::Failed(format!("{} nested lines dropped", now_dropped - dropped));
+    }
+    TestResult::Ok
+}
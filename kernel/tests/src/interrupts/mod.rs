@@ -1,4 +1,7 @@
+pub mod ioapic;
+pub mod logger;
 pub mod timer;
+pub mod watchdog;
 
 use crate::TestResult;
 use x86_64::registers::segmentation::{Segment, CS};
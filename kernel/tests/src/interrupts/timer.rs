@@ -1,7 +1,10 @@
 use kernel::interrupt::handlers::TIMER_INTERRUPT_COUNT;
-use kernel::time::lapic_timer;
-use kernel::time::tsc;
-use core::sync::atomic::Ordering;
+use kernel::time::{self, lapic_timer, timers, tsc};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
 use crate::TestResult;
 
 pub fn timer_interrupt_fires() -> TestResult {
@@ -49,3 +52,57 @@ pub fn timer_interrupt_fires() -> TestResult {
 
     result
 }
+
+// Firing order and time (ms) of each one-shot timer, indexed by tag.
+static FIRED_COUNT: AtomicUsize = AtomicUsize::new(0);
+static FIRED_ORDER: [AtomicU32; 4] = [const { AtomicU32::new(u32::MAX) }; 4];
+static FIRED_AT: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+fn record_fired(tag: u32) {
+    let slot = FIRED_COUNT.fetch_add(1, Ordering::SeqCst);
+    if let Some(order) = FIRED_ORDER.get(slot) {
+        order.store(tag, Ordering::SeqCst);
+    }
+    FIRED_AT[tag as usize].store(time::now_ms(), Ordering::SeqCst);
+}
+
+pub fn one_shot_timers_fire_in_order() -> TestResult {
+    // (delay_ms, tag): tags are the expected firing order. 1 and 2 share a
+    // deadline, so one tick has to drain both, in registration order.
+    let timers = [(5, 3), (2, 1), (2, 2), (0, 0)];
+    FIRED_COUNT.store(0, Ordering::SeqCst);
+
+    let start_ms = time::now_ms();
+    let interrupts_enabled = interrupts::are_enabled();
+    interrupts::without_interrupts(|| {
+        for (ms, tag) in timers {
+            timers::register_timer(ms, Box::new(move || record_fired(tag)));
+        }
+    });
+    interrupts::enable();
+    lapic_timer::set_deadline(1_000_000);
+
+    let timeout = tsc::TSC_HZ.load(Ordering::SeqCst) * 1000; // 1 s
+    let start_tsc = tsc::value();
+    while FIRED_COUNT.load(Ordering::SeqCst) < timers.len() && tsc::value() - start_tsc < timeout {
+        core::hint::spin_loop();
+    }
+    if !interrupts_enabled {
+        interrupts::disable();
+    }
+
+    let order: Vec<u32> = FIRED_ORDER.iter().map(|tag| tag.load(Ordering::SeqCst)).collect();
+    if FIRED_COUNT.load(Ordering::SeqCst) != timers.len() || order != [0, 1, 2, 3] {
+        return TestResult::Failed(format!(
+            "{} timers fired, in order {:?}; expected [0, 1, 2, 3]",
+            FIRED_COUNT.load(Ordering::SeqCst), order
+        ));
+    }
+    for (ms, tag) in timers {
+        let elapsed = FIRED_AT[tag as usize].load(Ordering::SeqCst) - start_ms;
+        if elapsed < ms {
+            return TestResult::Failed(format!("Timer {} fired after {} ms, before its {} ms delay", tag, elapsed, ms));
+        }
+    }
+    TestResult::Ok
+}
@@ -0,0 +1,34 @@
+use alloc::format;
+use core::sync::atomic::Ordering;
+use kernel::interrupt::nmi_handler_state::{NmiHandlerState, NMI_HANDLER_STATES};
+use kernel::interrupt::watchdog;
+use kernel::memory::cpu_local_data::get_local;
+use kernel::time::tsc;
+use crate::TestResult;
+
+/// A watchdog NMI dumps this CPU and returns to it instead of halting, and
+/// leaves the CPU ready for the next one.
+pub fn watchdog_nmi_returns() -> TestResult {
+    let cpu = get_local();
+    let state = &NMI_HANDLER_STATES.get().unwrap()[cpu.kernel_id as usize];
+    if let Err(s) = state.compare_exchange(
+        NmiHandlerState::NmiHandlerSet,
+        NmiHandlerState::WatchdogDump,
+        Ordering::Acquire,
+        Ordering::Relaxed,
+    ) {
+        return TestResult::Failed(format!("NMI state was {s:?}, expected NmiHandlerSet"));
+    }
+    let local_apic = unsafe { &mut *cpu.local_apic.get().unwrap().get() };
+    unsafe { local_apic.send_nmi(cpu.local_apic_id) };
+
+    let timeout = tsc::TSC_HZ.load(Ordering::SeqCst) * 10; // 10 ms
+    let start_tsc = tsc::value();
+    while state.load(Ordering::Acquire) == NmiHandlerState::WatchdogDump && tsc::value() - start_tsc < timeout {
+        core::hint::spin_loop();
+    }
+    match state.load(Ordering::Acquire) {
+        NmiHandlerState::NmiHandlerSet => TestResult::Ok,
+        s => TestResult::Failed(format!("NMI state {s:?} after the watchdog NMI (threshold {} ms)", watchdog::STALL_MS)),
+    }
+}
@@ -1,6 +1,12 @@
 use crate::TestResult;
 use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::Ordering;
 use kernel::ipc;
+use kernel::memory::global_allocator;
+use kernel::task::local_scheduler::{add, schedule_from_interrupt};
+use kernel::task::task::Task;
 
 pub fn test_channel_create() -> TestResult {
     let (send_id, recv_id) = ipc::create_channel(16);
@@ -206,3 +212,339 @@ pub fn test_fifo_order() -> TestResult {
     let _ = ipc::close_endpoint(recv_id);
     TestResult::Ok
 }
+
+pub fn test_dup_keeps_channel_open() -> TestResult {
+    let (send_id, recv_id) = ipc::create_channel(16);
+    let dup_id = match ipc::dup_endpoint(send_id) {
+        Ok(id) => id,
+        Err(e) => {
+            let _ = ipc::close_endpoint(send_id);
+            let _ = ipc::close_endpoint(recv_id);
+            return TestResult::Failed(format!("dup_endpoint failed: {:?}", e));
+        }
+    };
+
+    // Closing the original must leave the dup usable and the receiver unaware.
+    let _ = ipc::close_endpoint(send_id);
+    if let Err(e) = ipc::try_send(dup_id, b"via dup") {
+        let _ = ipc::close_endpoint(dup_id);
+        let _ = ipc::close_endpoint(recv_id);
+        return TestResult::Failed(format!("send on dup failed: {:?}", e));
+    }
+    match ipc::try_recv(recv_id) {
+        Ok(msg) if msg.as_slice() == b"via dup" => {}
+        other => {
+            let _ = ipc::close_endpoint(dup_id);
+            let _ = ipc::close_endpoint(recv_id);
+            return TestResult::Failed(format!("Expected the dup's message, got {:?}", other));
+        }
+    }
+    match ipc::try_recv(recv_id) {
+        Err(ipc::IpcError::WouldBlock) => {}
+        other => {
+            let _ = ipc::close_endpoint(dup_id);
+            let _ = ipc::close_endpoint(recv_id);
+            return TestResult::Failed(format!("Expected WouldBlock with a dup still open, got {:?}", other));
+        }
+    }
+
+    // Closing the last send endpoint closes the send side.
+    let _ = ipc::close_endpoint(dup_id);
+    let result = match ipc::try_recv(recv_id) {
+        Err(ipc::IpcError::PeerClosed) => TestResult::Ok,
+        other => TestResult::Failed(format!("Expected PeerClosed after closing every dup, got {:?}", other)),
+    };
+    let _ = ipc::close_endpoint(recv_id);
+    result
+}
+
+pub fn test_dup_invalid_endpoint() -> TestResult {
+    match ipc::dup_endpoint(u64::MAX) {
+        Err(ipc::IpcError::InvalidEndpoint) => TestResult::Ok,
+        other => TestResult::Failed(format!("Expected InvalidEndpoint, got {:?}", other)),
+    }
+}
+
+pub fn test_close_refcounts() -> TestResult {
+    let (send_id, recv_id) = ipc::create_channel(16);
+    let channel = ipc::ENDPOINT_REGISTRY.lock().get(&send_id).unwrap().channel.clone();
+    let refs = || {
+        (
+            channel.send_refs.load(core::sync::atomic::Ordering::Acquire),
+            channel.recv_refs.load(core::sync::atomic::Ordering::Acquire),
+        )
+    };
+
+    let mut seen = alloc::vec![refs()];
+    let recv_dup = ipc::dup_endpoint(recv_id).unwrap();
+    seen.push(refs());
+    let _ = ipc::close_endpoint(recv_id);
+    seen.push(refs());
+    let half_open = !channel.recv_closed() && !channel.send_closed();
+    let _ = ipc::close_endpoint(recv_dup);
+    seen.push(refs());
+    let recv_closed = channel.recv_closed() && !channel.send_closed();
+    let send_result = ipc::try_send(send_id, b"x");
+    let _ = ipc::close_endpoint(send_id);
+    seen.push(refs());
+
+    if seen != [(1, 1), (1, 2), (1, 1), (1, 0), (0, 0)] {
+        return TestResult::Failed(format!("Unexpected (send, recv) refs: {:?}", seen));
+    }
+    if !half_open || !recv_closed || !channel.send_closed() {
+        return TestResult::Failed("Closed state did not follow the refcounts".into());
+    }
+    match send_result {
+        Err(ipc::IpcError::PeerClosed) => TestResult::Ok,
+        other => TestResult::Failed(format!("Expected PeerClosed once every recv endpoint closed, got {:?}", other)),
+    }
+}
+
+/// `poll` counts queued messages without dequeuing them.
+pub fn test_poll_counts_queued_messages() -> TestResult {
+    let (send_id, recv_id) = ipc::create_channel(4);
+    let _ = ipc::try_send(send_id, b"one");
+    let _ = ipc::try_send(send_id, b"two");
+
+    let mut seen = alloc::vec![ipc::poll(recv_id)];
+    let _ = ipc::try_recv(recv_id);
+    seen.push(ipc::poll(recv_id));
+    let _ = ipc::try_recv(recv_id);
+    seen.push(ipc::poll(recv_id));
+    let on_send = ipc::poll(send_id);
+
+    let _ = ipc::close_endpoint(send_id);
+    let _ = ipc::close_endpoint(recv_id);
+
+    if seen != [Ok(2), Ok(1), Ok(0)] {
+        return TestResult::Failed(format!("Expected poll to give 2, 1, 0; got {:?}", seen));
+    }
+    match on_send {
+        Err(ipc::IpcError::WrongDirection) => {}
+        other => return TestResult::Failed(format!("Expected WrongDirection polling a send endpoint, got {:?}", other)),
+    }
+    match ipc::poll(recv_id) {
+        Err(ipc::IpcError::InvalidEndpoint) => TestResult::Ok,
+        other => TestResult::Failed(format!("Expected InvalidEndpoint after close, got {:?}", other)),
+    }
+}
+
+/// `channel_stats` reports the fill level and granted capacity from either end.
+pub fn test_channel_stats() -> TestResult {
+    let (send_id, recv_id) = ipc::create_channel(4);
+    let _ = ipc::try_send(send_id, b"one");
+    let _ = ipc::try_send(send_id, b"two");
+    let from_send = ipc::channel_stats(send_id);
+    let from_recv = ipc::channel_stats(recv_id);
+    let _ = ipc::close_endpoint(send_id);
+    let _ = ipc::close_endpoint(recv_id);
+
+    if from_send != Ok((2, 4)) || from_recv != Ok((2, 4)) {
+        return TestResult::Failed(format!(
+            "Expected (2, 4) from both ends, got {:?} and {:?}",
+            from_send, from_recv
+        ));
+    }
+
+    let (send_id, recv_id) = ipc::create_channel(ipc::MAX_CHANNEL_CAPACITY + 1);
+    let clamped = ipc::channel_stats(send_id);
+    let _ = ipc::close_endpoint(send_id);
+    let _ = ipc::close_endpoint(recv_id);
+    if clamped != Ok((0, ipc::MAX_CHANNEL_CAPACITY)) {
+        return TestResult::Failed(format!("Expected the capacity clamped to the maximum, got {:?}", clamped));
+    }
+    match ipc::channel_stats(recv_id) {
+        Err(ipc::IpcError::InvalidEndpoint) => TestResult::Ok,
+        other => TestResult::Failed(format!("Expected InvalidEndpoint after close, got {:?}", other)),
+    }
+}
+
+/// Messages up to `INLINE_MESSAGE_SIZE` bytes are queued inline and longer
+/// ones on the heap; both come back byte for byte.
+pub fn test_inline_and_heap_messages_roundtrip() -> TestResult {
+    let (send_id, recv_id) = ipc::create_channel(1);
+    let sizes = [0, 1, ipc::INLINE_MESSAGE_SIZE, ipc::INLINE_MESSAGE_SIZE + 1, ipc::MAX_MESSAGE_SIZE];
+    let mut result = TestResult::Ok;
+    for size in sizes {
+        let sent: alloc::vec::Vec<u8> = (0..size).map(|i| (i * 7) as u8).collect();
+        let received = ipc::try_send(send_id, &sent).and_then(|()| ipc::try_recv(recv_id));
+        let expect_inline = size <= ipc::INLINE_MESSAGE_SIZE;
+        match received {
+            Ok(msg) if msg.as_slice() == sent.as_slice() && msg.is_inline() == expect_inline => {}
+            other => {
+                result = TestResult::Failed(format!(
+                    "{size}-byte message (inline: {expect_inline}) came back as {:?}",
+                    other.map(|m| (m.len(), m.is_inline()))
+                ));
+                break;
+            }
+        }
+    }
+    let _ = ipc::close_endpoint(send_id);
+    let _ = ipc::close_endpoint(recv_id);
+    result
+}
+
+/// A broadcast reaches both subscribers. One whose queue is full misses the
+/// next message while the other still gets it, and subscribers see
+/// `PeerClosed` once the broadcast endpoint closes.
+pub fn test_broadcast_fans_out() -> TestResult {
+    let broadcast_id = ipc::create_broadcast(1);
+    let subscribers = [ipc::subscribe(broadcast_id), ipc::subscribe(broadcast_id)];
+    let [Ok(first), Ok(second)] = subscribers else {
+        let _ = ipc::close_endpoint(broadcast_id);
+        return TestResult::Failed(format!("subscribe failed: {:?}", subscribers));
+    };
+
+    let sent = ipc::try_send(broadcast_id, b"event");
+    let both = [ipc::try_recv(first), ipc::try_recv(second)];
+    // Leave "one" queued for `first` only, so `first` misses "two".
+    let _ = ipc::try_send(broadcast_id, b"one");
+    let _ = ipc::try_recv(second);
+    let _ = ipc::try_send(broadcast_id, b"two");
+    let after_full = [ipc::try_recv(first), ipc::try_recv(second)];
+    let on_broadcast = ipc::try_recv(broadcast_id);
+    let _ = ipc::close_endpoint(broadcast_id);
+    let closed = [ipc::try_recv(first), ipc::try_recv(second)];
+    let _ = ipc::close_endpoint(first);
+    let _ = ipc::close_endpoint(second);
+
+    if sent.is_err() || both.iter().any(|m| m.as_deref() != Ok(b"event".as_slice())) {
+        return TestResult::Failed(format!("Expected both subscribers to get the message, got {:?} / {:?}", sent, both));
+    }
+    if after_full[0].as_deref() != Ok(b"one".as_slice()) || after_full[1].as_deref() != Ok(b"two".as_slice()) {
+        return TestResult::Failed(format!("Expected the full subscriber to miss only \"two\", got {:?}", after_full));
+    }
+    if on_broadcast != Err(ipc::IpcError::WrongDirection) {
+        return TestResult::Failed(format!("Expected WrongDirection receiving on a broadcast endpoint, got {:?}", on_broadcast));
+    }
+    if closed.iter().any(|r| *r != Err(ipc::IpcError::PeerClosed)) {
+        return TestResult::Failed(format!("Expected PeerClosed after closing the broadcast endpoint, got {:?}", closed));
+    }
+    TestResult::Ok
+}
+
+/// Open channels, leave inline and heap messages queued on them, and close
+/// them again; do the same with a broadcast and its subscriber.
+fn churn_channels() {
+    let big = [0x5a; ipc::INLINE_MESSAGE_SIZE * 4];
+    for _ in 0..8 {
+        let (send_id, recv_id) = ipc::create_channel(4);
+        let _ = ipc::try_send(send_id, b"inline");
+        let _ = ipc::try_send(send_id, &big);
+        let _ = ipc::try_recv(recv_id);
+        let _ = ipc::try_send(send_id, &big);
+        let _ = ipc::close_endpoint(send_id);
+        let _ = ipc::close_endpoint(recv_id);
+    }
+    let broadcast_id = ipc::create_broadcast(2);
+    let subscriber = ipc::subscribe(broadcast_id);
+    let _ = ipc::try_send(broadcast_id, &big);
+    let _ = ipc::close_endpoint(broadcast_id);
+    if let Ok(subscriber) = subscriber {
+        let _ = ipc::close_endpoint(subscriber);
+    }
+}
+
+/// Creating and closing channels, with messages still queued, gives back
+/// every byte and block it took from the heap.
+pub fn test_closed_channels_free_their_memory() -> TestResult {
+    // The first round may leave the endpoint registry's tree a different
+    // shape; measure a second one.
+    churn_channels();
+    let before = global_allocator::stats();
+    churn_channels();
+    let after = global_allocator::stats();
+
+    if after.allocation_count == before.allocation_count {
+        return TestResult::Failed("Expected the channels to allocate, but the counters didn't move".into());
+    }
+    let live = |s: &kernel_api_types::AllocStats| s.allocation_count - s.free_count;
+    if after.allocated_bytes != before.allocated_bytes || live(&after) != live(&before) {
+        return TestResult::Failed(format!(
+            "Heap grew: {} bytes in {} blocks before, {} bytes in {} blocks after",
+            before.allocated_bytes, live(&before), after.allocated_bytes, live(&after)
+        ));
+    }
+    TestResult::Ok
+}
+
+fn spin() -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+fn task_with_priority(priority: u8) -> Arc<Task> {
+    let task = Task::new(spin);
+    task.base_priority.store(priority, Ordering::Relaxed);
+    Arc::new(task)
+}
+
+fn channel_of(endpoint_id: u64) -> Arc<ipc::Channel> {
+    ipc::ENDPOINT_REGISTRY.lock().get(&endpoint_id).unwrap().channel.clone()
+}
+
+/// A high-priority client calls a low-priority server, the way
+/// `sys_channel_call` does, while a medium-priority task is ready. The server
+/// runs the request at the client's priority, ahead of the medium task, and
+/// drops back once it waits for the next request. None of the tasks runs:
+/// the test drives the wait and wake paths on their behalf.
+pub fn test_priority_inheritance() -> TestResult {
+    crate::scheduler::with_scratch_run_queue(|cpu| {
+        let client = task_with_priority(6);
+        let server = task_with_priority(1);
+        let medium = task_with_priority(4);
+        let (req_send, req_recv) = ipc::create_channel(4);
+        let (reply_send, reply_recv) = ipc::create_channel(1);
+        let (requests, replies) = (channel_of(req_send), channel_of(reply_send));
+
+        let result = (|| -> Result<(), String> {
+            ipc::wait_to_recv(&requests, server.clone(), cpu.kernel_id);
+            add(cpu, medium.clone());
+
+            // The client's request wakes the server, which inherits its priority.
+            ipc::try_send(req_send, b"request").map_err(|e| format!("request send: {e:?}"))?;
+            requests.sent_by(&client);
+            ipc::wait_to_recv(&replies, client.clone(), cpu.kernel_id);
+            if server.priority() != 6 {
+                return Err(format!("server at priority {} while the client waits, expected 6", server.priority()));
+            }
+            schedule_from_interrupt(cpu);
+            let picked_server = cpu.run_queue.get().unwrap().lock()
+                .current_task.as_ref().is_some_and(|t| Arc::ptr_eq(t, &server));
+            if !picked_server {
+                return Err("boosted server not picked over the medium-priority task".into());
+            }
+
+            // Serving and replying keeps the boost; the reply doesn't pass it on.
+            ipc::try_recv(req_recv).map_err(|e| format!("request recv: {e:?}"))?;
+            requests.received_by(&server);
+            ipc::try_send(reply_send, b"reply").map_err(|e| format!("reply send: {e:?}"))?;
+            replies.sent_by(&server);
+            if client.priority() != 6 || server.priority() != 6 {
+                return Err(format!("client/server at {}/{} after the reply, expected 6/6", client.priority(), server.priority()));
+            }
+
+            ipc::wait_to_recv(&requests, server.clone(), cpu.kernel_id);
+            if server.priority() != 1 {
+                return Err(format!("server at priority {} once idle again, expected 1", server.priority()));
+            }
+            Ok(())
+        })();
+
+        for ep in [req_send, req_recv, reply_send, reply_recv] {
+            let _ = ipc::close_endpoint(ep);
+        }
+        let mut rq = cpu.run_queue.get().unwrap().lock();
+        rq.current_task = None;
+        rq.ready.clear();
+        drop(rq);
+        cpu.ready_count.store(0, Ordering::Relaxed);
+
+        match result {
+            Ok(()) => TestResult::Ok,
+            Err(msg) => TestResult::Failed(msg),
+        }
+    })
+}
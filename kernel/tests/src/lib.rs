@@ -14,11 +14,16 @@ pub mod interrupts;
 pub mod graphics;
 pub mod user_mode;
 pub mod keyboard;
+pub mod mouse;
 pub mod ipc;
 pub mod display;
 pub mod scheduler;
 pub mod elf;
+pub mod cmdline;
 pub mod syscalls;
+pub mod power;
+pub mod pci;
+pub mod net;
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     log::error!("[failed]");
@@ -54,17 +59,20 @@ pub enum TestResult {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TestGroup {
-    Memory,           // physical_memory, vaddr_allocator, mmap
+    Memory,           // physical_memory, vaddr_allocator, mmap, numa
     Time,             // time
-    Interrupts,       // interrupts, timer_interrupt
+    Interrupts,       // interrupts, timer_interrupt, ioapic
     Graphics,         // graphics
     UserMode,         // user_mode (diagnostic + scheduler handoff)
-    Keyboard,         // keyboard
+    Keyboard,         // keyboard, mouse
     Ipc,              // ipc
     Display,          // display_owner, get_module
     Scheduler,        // scheduler, spawn
-    Elf,              // ELF parsing and mapping validation
+    Elf,              // ELF parsing and mapping validation, kernel symbols, cmdline
     Syscalls,         // syscall handler API tests
+    Power,            // ACPI shutdown and reboot
+    Pci,              // PCI enumeration, MSI
+    Net,              // e1000 driver
     SchedulerHandoff, // kernel-tasks-only scheduler handoff (diverges, exits QEMU)
     SchedulerNoElf,   // like test_user_task_runs but no ELF — isolates user-mode vs ELF
 }
@@ -76,10 +84,7 @@ pub struct TestEntry {
 
 pub fn parse_test_group(cmdline: &[u8]) -> Option<TestGroup> {
     let s = core::str::from_utf8(cmdline).ok()?;
-    let prefix = "test_suite=";
-    let pos = s.find(prefix)?;
-    let value = s[pos + prefix.len()..].split_whitespace().next()?;
-    match value {
+    match kernel::cmdline::lookup(s, "test_suite")? {
         "mem"        => Some(TestGroup::Memory),
         "time"       => Some(TestGroup::Time),
         "interrupts" => Some(TestGroup::Interrupts),
@@ -93,6 +98,9 @@ pub fn parse_test_group(cmdline: &[u8]) -> Option<TestGroup> {
         "syscalls"   => Some(TestGroup::Syscalls),
         "sched"      => Some(TestGroup::SchedulerHandoff),
         "sched-noelf" => Some(TestGroup::SchedulerNoElf),
+        "power"      => Some(TestGroup::Power),
+        "pci"        => Some(TestGroup::Pci),
+        "net"        => Some(TestGroup::Net),
         _            => None,
     }
 }
@@ -102,6 +110,13 @@ pub fn tests() -> &'static [TestEntry] {
         // Time
         TestEntry { group: TestGroup::Time, test: &time::tsc_calibration },
         TestEntry { group: TestGroup::Time, test: &time::pit_sleep },
+        TestEntry { group: TestGroup::Time, test: &time::tsc_robust_median },
+        TestEntry { group: TestGroup::Time, test: &time::pit_sleep_duration },
+        TestEntry { group: TestGroup::Time, test: &time::hpet_discovered },
+        TestEntry { group: TestGroup::Time, test: &time::hpet_counter_monotonic },
+        TestEntry { group: TestGroup::Time, test: &time::now_ns_monotonic },
+        TestEntry { group: TestGroup::Time, test: &time::rtc_year_plausible },
+        TestEntry { group: TestGroup::Time, test: &time::rtc_decode_formats },
 
         // Memory — virtual address allocator
         TestEntry { group: TestGroup::Memory, test: &memory::vaddr::allocate_kernel_page },
@@ -122,21 +137,38 @@ pub fn tests() -> &'static [TestEntry] {
         TestEntry { group: TestGroup::Memory, test: &memory::physical::user_type },
         TestEntry { group: TestGroup::Memory, test: &memory::physical::exhaustion },
         TestEntry { group: TestGroup::Memory, test: &memory::physical::duplicate_allocation },
+        TestEntry { group: TestGroup::Memory, test: &memory::physical::free_list_allocation_is_constant_time },
+
+        // Memory — NUMA
+        TestEntry { group: TestGroup::Memory, test: &memory::numa::topology_has_a_node },
+        TestEntry { group: TestGroup::Memory, test: &memory::numa::parse_srat_entries },
+        TestEntry { group: TestGroup::Memory, test: &memory::numa::parse_srat_stops_at_truncated_entry },
+        TestEntry { group: TestGroup::Memory, test: &memory::numa::allocation_respects_node },
+        TestEntry { group: TestGroup::Memory, test: &memory::tlb::shootdown_reaches_every_cpu },
 
         // Interrupts
         TestEntry { group: TestGroup::Interrupts, test: &interrupts::gdt_loaded },
         TestEntry { group: TestGroup::Interrupts, test: &interrupts::idt_loaded },
         TestEntry { group: TestGroup::Interrupts, test: &interrupts::breakpoint_exception },
         TestEntry { group: TestGroup::Interrupts, test: &interrupts::timer::timer_interrupt_fires },
+        TestEntry { group: TestGroup::Interrupts, test: &interrupts::timer::one_shot_timers_fire_in_order },
+        TestEntry { group: TestGroup::Interrupts, test: &interrupts::ioapic::serial_irq_delivered },
+        TestEntry { group: TestGroup::Interrupts, test: &interrupts::ioapic::serial_rx_fills_buffer },
+        TestEntry { group: TestGroup::Interrupts, test: &interrupts::ioapic::route_irq_rejects_unknown_gsi },
+        TestEntry { group: TestGroup::Interrupts, test: &interrupts::watchdog::watchdog_nmi_returns },
+        TestEntry { group: TestGroup::Interrupts, test: &interrupts::logger::log_from_timer_while_logging },
+        TestEntry { group: TestGroup::Interrupts, test: &interrupts::logger::log_from_exception_while_holding_logger },
 
         // Graphics
         TestEntry { group: TestGroup::Graphics, test: &graphics::basic_draw },
         TestEntry { group: TestGroup::Graphics, test: &graphics::bounding_box_valid },
+        TestEntry { group: TestGroup::Graphics, test: &graphics::buffered_draw_waits_for_present },
 
         // User mode (diagnostic, no scheduler handoff)
         TestEntry { group: TestGroup::UserMode, test: &user_mode::test_user_selector_rpl },
         TestEntry { group: TestGroup::UserMode, test: &user_mode::test_lower_half_end_canonical },
         TestEntry { group: TestGroup::UserMode, test: &user_mode::test_user_task_creation },
+        TestEntry { group: TestGroup::UserMode, test: &user_mode::test_user_stack_guard_page },
         TestEntry { group: TestGroup::UserMode, test: &user_mode::test_user_page_table_kernel_mapped },
         TestEntry { group: TestGroup::UserMode, test: &user_mode::test_user_task_iretq_frame },
 
@@ -149,6 +181,10 @@ pub fn tests() -> &'static [TestEntry] {
         TestEntry { group: TestGroup::Keyboard, test: &keyboard::test_buffer_empty_after_drain },
         TestEntry { group: TestGroup::Keyboard, test: &keyboard::test_multiple_keys_order },
         TestEntry { group: TestGroup::Keyboard, test: &keyboard::test_capslock_toggle },
+        TestEntry { group: TestGroup::Keyboard, test: &mouse::test_mouse_packet_decoded },
+        TestEntry { group: TestGroup::Keyboard, test: &mouse::test_mouse_overflow_keeps_buttons },
+        TestEntry { group: TestGroup::Keyboard, test: &mouse::test_vmmouse_packet_decoded },
+        TestEntry { group: TestGroup::Keyboard, test: &mouse::test_input_recording_captures_events },
 
         // IPC
         TestEntry { group: TestGroup::Ipc, test: &ipc::test_channel_create },
@@ -159,6 +195,15 @@ pub fn tests() -> &'static [TestEntry] {
         TestEntry { group: TestGroup::Ipc, test: &ipc::test_channel_full },
         TestEntry { group: TestGroup::Ipc, test: &ipc::test_recv_closed_then_send_fails },
         TestEntry { group: TestGroup::Ipc, test: &ipc::test_fifo_order },
+        TestEntry { group: TestGroup::Ipc, test: &ipc::test_dup_keeps_channel_open },
+        TestEntry { group: TestGroup::Ipc, test: &ipc::test_dup_invalid_endpoint },
+        TestEntry { group: TestGroup::Ipc, test: &ipc::test_close_refcounts },
+        TestEntry { group: TestGroup::Ipc, test: &ipc::test_poll_counts_queued_messages },
+        TestEntry { group: TestGroup::Ipc, test: &ipc::test_channel_stats },
+        TestEntry { group: TestGroup::Ipc, test: &ipc::test_inline_and_heap_messages_roundtrip },
+        TestEntry { group: TestGroup::Ipc, test: &ipc::test_broadcast_fans_out },
+        TestEntry { group: TestGroup::Ipc, test: &ipc::test_closed_channels_free_their_memory },
+        TestEntry { group: TestGroup::Ipc, test: &ipc::test_priority_inheritance },
 
         // Display
         TestEntry { group: TestGroup::Display, test: &display::owner::test_no_current_task_is_not_owner },
@@ -166,25 +211,39 @@ pub fn tests() -> &'static [TestEntry] {
         TestEntry { group: TestGroup::Display, test: &display::owner::test_non_owner_get_bounding_box_rejected },
         TestEntry { group: TestGroup::Display, test: &display::owner::test_transfer_display_not_owner },
         TestEntry { group: TestGroup::Display, test: &display::owner::test_transfer_display_no_current_task },
+        TestEntry { group: TestGroup::Display, test: &display::owner::test_transfer_display_invalid_target },
+        TestEntry { group: TestGroup::Display, test: &display::owner::test_is_display_owner_query },
+        TestEntry { group: TestGroup::Display, test: &display::owner::test_owner_exit_returns_display_home },
+        TestEntry { group: TestGroup::Display, test: &display::owner::test_owner_exit_without_home_clears_owner },
         TestEntry { group: TestGroup::Display, test: &display::owner::test_display_owner_atomic },
         TestEntry { group: TestGroup::Display, test: &display::modules::test_init_task_module_exists },
         TestEntry { group: TestGroup::Display, test: &display::modules::test_display_server_module_exists },
         TestEntry { group: TestGroup::Display, test: &display::modules::test_nonexistent_module_missing },
         TestEntry { group: TestGroup::Display, test: &display::modules::test_module_has_nonzero_size },
+        TestEntry { group: TestGroup::Display, test: &display::events::test_display_change_reaches_subscriber },
 
         // ELF parsing
         TestEntry { group: TestGroup::Elf, test: &elf::test_elf_header_valid },
         TestEntry { group: TestGroup::Elf, test: &elf::test_elf_has_load_segments },
         TestEntry { group: TestGroup::Elf, test: &elf::test_elf_entry_in_load_segment },
         TestEntry { group: TestGroup::Elf, test: &elf::test_elf_segment_file_bounds },
+        TestEntry { group: TestGroup::Elf, test: &elf::test_segment_out_of_bounds_reported },
         TestEntry { group: TestGroup::Elf, test: &elf::test_elf_load_segments_no_overlap },
         TestEntry { group: TestGroup::Elf, test: &elf::test_spawn_rip_matches_elf_entry },
         TestEntry { group: TestGroup::Elf, test: &elf::test_direct_elf_entry_matches },
+        TestEntry { group: TestGroup::Elf, test: &elf::test_direct_elf_out_of_memory },
         TestEntry { group: TestGroup::Elf, test: &elf::test_spawn_data_integrity },
         TestEntry { group: TestGroup::Elf, test: &elf::test_spawn_bss_zeroed },
+        TestEntry { group: TestGroup::Elf, test: &elf::test_symbols_resolve_kernel_function },
+        TestEntry { group: TestGroup::Elf, test: &cmdline::cmdline_parses_options },
+        TestEntry { group: TestGroup::Elf, test: &cmdline::cmdline_selects_test_group },
 
         // Syscall handler API
         TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_debug_log_always_ok },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_set_syscall_trace_toggles },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_set_syscall_trace_needs_privilege },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_debug_log_str_validates_ptr },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_null_returns_zero },
         TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_channel_create_null_send_ptr },
         TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_channel_create_null_recv_ptr },
         TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_channel_send_invalid_endpoint },
@@ -197,8 +256,47 @@ pub fn tests() -> &'static [TestEntry] {
         TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_channel_create_returns_endpoints },
         TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_mmap_returns_valid_addr },
         TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_mmap_write_and_read },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_mmap_huge_page },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_populate_lazy_mmap },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_populate_unreserved_range },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_debug_log_str_from_user },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_random_fills_buffer },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_spawn_rejects_bad_image_buffer },
         TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_channel_send_recv_roundtrip },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_channel_recv_truncates },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_channel_pass_endpoint_roundtrip },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_channel_create_capacity_by_privilege },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_spawn_system_needs_privilege },
         TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_get_display_info_success },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_mmap_vaddr_exhaustion },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_resize_shared_buf_preserves_data },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_resize_shared_buf_guards_moved_range },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_resize_shared_buf_unknown_id },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_map_shared_buf_readonly },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_map_shared_buf_unknown_flags },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_grant_shared_buf },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_destroy_shared_buf_owner_only },
+        TestEntry { group: TestGroup::Syscalls, test: &syscalls::test_sys_destroy_shared_buf_unmaps_all },
+
+        // Power
+        TestEntry { group: TestGroup::Power, test: &power::shutdown_selects_acpi_s5 },
+        TestEntry { group: TestGroup::Power, test: &power::find_s5_in_aml },
+        TestEntry { group: TestGroup::Power, test: &power::find_s5_missing },
+        TestEntry { group: TestGroup::Power, test: &power::reset_register_parsed_from_fadt },
+        TestEntry { group: TestGroup::Power, test: &power::reboot_selects_acpi_reset_register },
+
+        // PCI
+        TestEntry { group: TestGroup::Pci, test: &pci::devices_found },
+        TestEntry { group: TestGroup::Pci, test: &pci::host_bridge_present },
+        TestEntry { group: TestGroup::Pci, test: &pci::bars_plausible },
+        TestEntry { group: TestGroup::Pci, test: &pci::capabilities_walk },
+        TestEntry { group: TestGroup::Pci, test: &pci::msi_message_encoding },
+        TestEntry { group: TestGroup::Pci, test: &pci::msi_delivered },
+
+        // Net
+        TestEntry { group: TestGroup::Net, test: &net::nic_present },
+        TestEntry { group: TestGroup::Net, test: &net::send_rejects_bad_length },
+        TestEntry { group: TestGroup::Net, test: &net::arp_gateway_replies },
 
         // Kernel-only scheduler handoff — enables interrupts and never returns.
         // Skipped when running all tests (cargo ktest); run via cargo ktest-sched.
@@ -212,7 +310,15 @@ pub fn tests() -> &'static [TestEntry] {
         TestEntry { group: TestGroup::Scheduler, test: &scheduler::spawn::test_spawn_error_invalid_elf },
         TestEntry { group: TestGroup::Scheduler, test: &scheduler::spawn::test_spawn_creates_task },
         TestEntry { group: TestGroup::Scheduler, test: &scheduler::spawn::test_spawn_child_arg },
+        TestEntry { group: TestGroup::Scheduler, test: &scheduler::spawn::test_spawned_task_in_registry },
         TestEntry { group: TestGroup::Scheduler, test: &scheduler::simple_task_creation },
+        TestEntry { group: TestGroup::Scheduler, test: &scheduler::test_task_names },
+        TestEntry { group: TestGroup::Scheduler, test: &scheduler::test_killed_task_leaves_run_queue },
+        TestEntry { group: TestGroup::Scheduler, test: &scheduler::test_zombie_state_is_final },
+        TestEntry { group: TestGroup::Scheduler, test: &scheduler::idle::test_idle_does_not_share_cpu_with_ready_tasks },
+        TestEntry { group: TestGroup::Scheduler, test: &scheduler::idle::test_idle_runs_only_when_nothing_else_can },
+        TestEntry { group: TestGroup::Scheduler, test: &scheduler::idle::test_idle_cpu_is_tickless },
+        TestEntry { group: TestGroup::Scheduler, test: &scheduler::priority::test_higher_priority_runs_first },
         TestEntry { group: TestGroup::Scheduler, test: &scheduler::stack::test_context_switch_registers },
         TestEntry { group: TestGroup::Scheduler, test: &scheduler::stack::test_stack_alignment },
         TestEntry { group: TestGroup::Scheduler, test: &scheduler::stack::test_timer_stack_alignment },
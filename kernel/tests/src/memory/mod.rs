@@ -1,3 +1,5 @@
 pub mod mmap;
+pub mod numa;
 pub mod physical;
+pub mod tlb;
 pub mod vaddr;
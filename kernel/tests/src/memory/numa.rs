@@ -0,0 +1,110 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use kernel::memory::MEMORY;
+use kernel::memory::physical_memory::{KernelMemoryUsageType, MemoryType};
+use kernel::numa::{self, CpuAffinity, MemoryAffinity};
+use crate::TestResult;
+
+/// Build a raw SRAT: 48 bytes of header/reserved followed by `entries`.
+fn srat(entries: &[&[u8]]) -> Vec<u8> {
+    let mut table = alloc::vec![0u8; 48];
+    table[0..4].copy_from_slice(b"SRAT");
+    for entry in entries {
+        table.extend_from_slice(entry);
+    }
+    let len = table.len() as u32;
+    table[4..8].copy_from_slice(&len.to_le_bytes());
+    table
+}
+
+fn memory_entry(base: u64, length: u64, node: u32, enabled: bool) -> [u8; 40] {
+    let mut e = [0u8; 40];
+    e[0] = 1;
+    e[1] = 40;
+    e[2..6].copy_from_slice(&node.to_le_bytes());
+    e[8..16].copy_from_slice(&base.to_le_bytes());
+    e[16..24].copy_from_slice(&length.to_le_bytes());
+    e[28..32].copy_from_slice(&(enabled as u32).to_le_bytes());
+    e
+}
+
+fn apic_entry(apic_id: u8, node: u32) -> [u8; 16] {
+    let node = node.to_le_bytes();
+    let mut e = [0u8; 16];
+    e[0] = 0;
+    e[1] = 16;
+    e[2] = node[0];
+    e[3] = apic_id;
+    e[4] = 1; // enabled
+    e[9..12].copy_from_slice(&node[1..4]);
+    e
+}
+
+pub fn topology_has_a_node() -> TestResult {
+    let Some(topology) = numa::TOPOLOGY.get() else {
+        return TestResult::Failed(String::from("numa::init did not run"));
+    };
+    if topology.node_count() >= 1 {
+        TestResult::Ok
+    } else {
+        TestResult::Failed(String::from("Topology has no nodes"))
+    }
+}
+
+pub fn parse_srat_entries() -> TestResult {
+    let table = srat(&[
+        &memory_entry(0, 0x8000_0000, 0, true),
+        &memory_entry(0x8000_0000, 0x8000_0000, 1, true),
+        &memory_entry(0x1_0000_0000, 0x1000, 2, false),
+        &apic_entry(0, 0),
+        &apic_entry(1, 0x0102_0301),
+    ]);
+    let topology = numa::parse_srat(&table);
+
+    let expected_memory = [
+        MemoryAffinity { base: 0, length: 0x8000_0000, node: 0 },
+        MemoryAffinity { base: 0x8000_0000, length: 0x8000_0000, node: 1 },
+    ];
+    let expected_cpus = [
+        CpuAffinity { apic_id: 0, node: 0 },
+        CpuAffinity { apic_id: 1, node: 0x0102_0301 },
+    ];
+    if topology.memory != expected_memory || topology.cpus != expected_cpus {
+        return TestResult::Failed(format!("Unexpected topology: {:?}", topology));
+    }
+    if topology.node_of_addr(0x9000_0000) != 1 || topology.node_of_apic(1) != 0x0102_0301 {
+        return TestResult::Failed(String::from("Lookup returned the wrong node"));
+    }
+    TestResult::Ok
+}
+
+pub fn parse_srat_stops_at_truncated_entry() -> TestResult {
+    let mut table = srat(&[&memory_entry(0, 0x1000, 0, true)]);
+    table.extend_from_slice(&memory_entry(0x1000, 0x1000, 1, true)[..20]);
+    let topology = numa::parse_srat(&table);
+    if topology.memory.len() == 1 && topology.node_count() == 1 {
+        TestResult::Ok
+    } else {
+        TestResult::Failed(format!("Unexpected topology: {:?}", topology))
+    }
+}
+
+/// Frames come from the current CPU's node, which on single-node QEMU is every frame.
+pub fn allocation_respects_node() -> TestResult {
+    let mut pm = MEMORY.get().unwrap().physical_memory.lock();
+    let memory_type = MemoryType::UsedByKernel(KernelMemoryUsageType::PageTables);
+
+    let Some(frame) = pm.allocate_frame_with_type(memory_type) else {
+        return TestResult::Failed(String::from("Failed to allocate frame"));
+    };
+    let node = numa::topology().node_of_addr(frame.start_address().as_u64());
+    let _ = pm.free_frame(frame, memory_type);
+
+    let current = numa::current_node();
+    if node == current {
+        TestResult::Ok
+    } else {
+        TestResult::Failed(format!("Frame on node {} but CPU is on node {}", node, current))
+    }
+}
@@ -1,8 +1,10 @@
 use alloc::format;
 use alloc::string::String;
+use alloc::vec::Vec;
 use kernel::memory::MEMORY;
 use kernel::memory::physical_memory::{KernelMemoryUsageType, MemoryType, PhysicalMemory};
 use kernel::reexports::x86_64::structures::paging::{PhysFrame, Size4KiB};
+use kernel::reexports::x86_64::instructions::interrupts;
 use kernel::reexports::x86_64::PhysAddr;
 use crate::TestResult;
 
@@ -202,3 +204,68 @@ pub fn free_and_reuse_kernel_frame() -> TestResult {
         TestResult::Failed(String::from("Freed frame was not reused"))
     }
 }
+
+const PAGE_TABLES: MemoryType = MemoryType::UsedByKernel(KernelMemoryUsageType::PageTables);
+
+/// Allocate up to `n` kernel frames into `out`; returns the TSC cycles spent.
+fn timed_alloc(pm: &mut PhysicalMemory, n: usize, out: &mut Vec<PhysFrame<Size4KiB>>) -> u64 {
+    let start = unsafe { core::arch::x86_64::_rdtsc() };
+    for _ in 0..n {
+        match pm.allocate_frame_with_type(PAGE_TABLES) {
+            Some(frame) => out.push(frame),
+            None => break,
+        }
+    }
+    unsafe { core::arch::x86_64::_rdtsc() } - start
+}
+
+/// Allocate thousands of frames, free every other one so the map is full of
+/// one-frame holes, then allocate the holes again. With the free list, an
+/// allocation from the fragmented map costs about the same as one from a fresh
+/// region, and no frame is handed out twice.
+pub fn free_list_allocation_is_constant_time() -> TestResult {
+    const FRAMES: usize = 4096;
+    let mut pm = MEMORY.get().unwrap().physical_memory.lock();
+    let mut frames = Vec::with_capacity(FRAMES);
+    let mut refilled = Vec::with_capacity(FRAMES / 2);
+
+    let (fresh_cycles, hole_cycles) = interrupts::without_interrupts(|| {
+        let fresh = timed_alloc(&mut pm, FRAMES, &mut frames);
+        for frame in frames.iter().skip(1).step_by(2) {
+            let _ = pm.free_frame(*frame, PAGE_TABLES);
+        }
+        let holes = timed_alloc(&mut pm, FRAMES / 2, &mut refilled);
+        (fresh, holes)
+    });
+
+    let allocated = frames.len();
+    let mut live: Vec<u64> = frames
+        .iter()
+        .step_by(2)
+        .chain(refilled.iter())
+        .map(|f| f.start_address().as_u64())
+        .collect();
+    for frame in frames.iter().step_by(2).chain(refilled.iter()) {
+        let _ = pm.free_frame(*frame, PAGE_TABLES);
+    }
+
+    let live_count = live.len();
+    live.sort_unstable();
+    live.dedup();
+
+    if allocated < FRAMES || refilled.len() < FRAMES / 2 {
+        return TestResult::Failed(format!("only {} + {} frames allocated", allocated, refilled.len()));
+    }
+    if live.len() != live_count {
+        return TestResult::Failed(format!("{} frames handed out twice", live_count - live.len()));
+    }
+    let fresh_per_frame = fresh_cycles / FRAMES as u64;
+    let hole_per_frame = hole_cycles / (FRAMES / 2) as u64;
+    if hole_per_frame > 4 * fresh_per_frame.max(1) {
+        return TestResult::Failed(format!(
+            "allocating from holes took {} cycles/frame, fresh {} cycles/frame",
+            hole_per_frame, fresh_per_frame
+        ));
+    }
+    TestResult::Ok
+}
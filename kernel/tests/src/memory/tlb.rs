@@ -0,0 +1,24 @@
+use crate::TestResult;
+use core::sync::atomic::Ordering;
+use kernel::memory::cpu_local_data::{cpus_count, get_local, try_get_ready_cpu};
+use kernel::memory::tlb;
+
+/// A shootdown returns, and by then this CPU and every ready CPU have flushed
+/// for it.
+pub fn shootdown_reaches_every_cpu() -> TestResult {
+    let before = get_local().tlb_generation.load(Ordering::Acquire);
+    tlb::shootdown();
+    let after = get_local().tlb_generation.load(Ordering::Acquire);
+    if after <= before {
+        return TestResult::Failed(alloc::format!("Local generation went from {} to {}", before, after));
+    }
+    for id in 0..cpus_count() as u32 {
+        if let Some(cpu) = try_get_ready_cpu(id) {
+            let seen = cpu.tlb_generation.load(Ordering::Acquire);
+            if seen < after {
+                return TestResult::Failed(alloc::format!("CPU {} flushed for generation {}, not {}", id, seen, after));
+            }
+        }
+    }
+    TestResult::Ok
+}
@@ -0,0 +1,131 @@
+use crate::TestResult;
+use alloc::format;
+use alloc::vec::Vec;
+use kernel::drivers::mouse::{self, MOUSE_BUFFER_SIZE};
+use kernel::drivers::vmmouse;
+use kernel_api_types::{MouseEvent, MOUSE_ABSOLUTE, MOUSE_LEFT, MOUSE_RIGHT};
+
+/// Helper: feed one PS/2 packet for a movement of (dx, dy) in screen
+/// coordinates (positive y = down) with the given buttons held.
+fn feed_packet(dx: i16, dy: i16, buttons: u8) {
+    let raw_dy = -dy;
+    let mut status = 0x08;
+    if buttons & MOUSE_LEFT != 0 { status |= 0x01; }
+    if buttons & MOUSE_RIGHT != 0 { status |= 0x02; }
+    if dx < 0 { status |= 0x10; }
+    if raw_dy < 0 { status |= 0x20; }
+    mouse::handle_byte(status);
+    mouse::handle_byte(dx as u8);
+    mouse::handle_byte(raw_dy as u8);
+}
+
+fn drain() -> Vec<MouseEvent> {
+    let mut events = Vec::new();
+    while let Some(ev) = mouse::try_read_mouse() {
+        events.push(ev);
+    }
+    events
+}
+
+/// Collapse runs of equal button state, leaving just the transitions.
+fn button_states(events: &[MouseEvent]) -> Vec<u8> {
+    let mut states: Vec<u8> = events.iter().map(|ev| ev.buttons).collect();
+    states.dedup();
+    states
+}
+
+/// A single packet decodes to one event with the Y axis flipped.
+pub fn test_mouse_packet_decoded() -> TestResult {
+    mouse::reset();
+    feed_packet(5, -3, MOUSE_LEFT);
+    let events = drain();
+    match events.as_slice() {
+        [ev] if ev.dx == 5 && ev.dy == -3 && ev.buttons == MOUSE_LEFT => TestResult::Ok,
+        _ => TestResult::Failed(format!("Expected one event (5, -3, left), got {:?}", events)),
+    }
+}
+
+/// Overflowing the buffer with motion coalesces it, but keeps every button
+/// press and release and the total distance moved.
+pub fn test_mouse_overflow_keeps_buttons() -> TestResult {
+    mouse::reset();
+    let packets = MOUSE_BUFFER_SIZE * 4;
+    let mut expected = Vec::new();
+    for i in 0..packets {
+        // Toggle the left button every 10 packets, the right every 25.
+        let left = if (i / 10) % 2 == 1 { MOUSE_LEFT } else { 0 };
+        let right = if (i / 25) % 2 == 1 { MOUSE_RIGHT } else { 0 };
+        let buttons = left | right;
+        expected.push(buttons);
+        feed_packet(1, 2, buttons);
+    }
+    expected.dedup();
+
+    let events = drain();
+    if events.len() > MOUSE_BUFFER_SIZE {
+        return TestResult::Failed(format!("{} events queued, capacity is {}", events.len(), MOUSE_BUFFER_SIZE));
+    }
+    let states = button_states(&events);
+    if states != expected {
+        return TestResult::Failed(format!("Button states {:?}, expected {:?}", states, expected));
+    }
+    let (dx, dy) = events.iter().fold((0i32, 0i32), |(x, y), ev| (x + ev.dx as i32, y + ev.dy as i32));
+    if dx != packets as i32 || dy != 2 * packets as i32 {
+        return TestResult::Failed(format!("Total motion ({}, {}), expected ({}, {})", dx, dy, packets, 2 * packets));
+    }
+    TestResult::Ok
+}
+
+/// vmmouse packets decode to absolute events, or to motion when the device
+/// flags them relative.
+pub fn test_vmmouse_packet_decoded() -> TestResult {
+    // Left button (0x20), absolute position.
+    let ev = vmmouse::decode_packet(0x20, 0x8000, 0x4000);
+    if ev.flags != MOUSE_ABSOLUTE || (ev.abs_x, ev.abs_y) != (0x8000, 0x4000) || ev.buttons != MOUSE_LEFT {
+        return TestResult::Failed(format!("Absolute packet decoded as {:?}", ev));
+    }
+    // Right button (0x10), relative flag (0x10000), motion (-3, 4).
+    let ev = vmmouse::decode_packet(0x1_0010, -3i32 as u32, 4);
+    if ev.flags != 0 || (ev.dx, ev.dy) != (-3, 4) || ev.buttons != MOUSE_RIGHT {
+        return TestResult::Failed(format!("Relative packet decoded as {:?}", ev));
+    }
+    TestResult::Ok
+}
+
+/// While recording, every key and mouse event the drivers queue is captured
+/// in order with non-decreasing timestamps; after stopping, nothing more is.
+pub fn test_input_recording_captures_events() -> TestResult {
+    use kernel::drivers::{input_record, keyboard};
+    use kernel_api_types::input::{InputEvent, RecordedInput};
+    use kernel_api_types::KeyEvent;
+
+    keyboard::reset();
+    mouse::reset();
+    input_record::start();
+    keyboard::handle_scancode(0x1E); // 'a'
+    feed_packet(4, 1, MOUSE_LEFT);
+    keyboard::handle_scancode(0x1C); // Enter
+    let mut recorded = [RecordedInput::EMPTY; 8];
+    let n = input_record::stop(&mut recorded);
+    keyboard::handle_scancode(0x1E);
+    let again = input_record::stop(&mut [RecordedInput::EMPTY; 8]);
+    keyboard::reset();
+    mouse::reset();
+
+    let expected = [
+        InputEvent::key(KeyEvent::char('a')),
+        InputEvent::mouse(MouseEvent { dx: 4, dy: 1, buttons: MOUSE_LEFT, ..MouseEvent::EMPTY }),
+        InputEvent::key(KeyEvent::enter()),
+    ];
+    let recorded = &recorded[..n];
+    if !recorded.iter().map(|r| r.event).eq(expected) {
+        return TestResult::Failed(format!("Expected {expected:?}, recorded {recorded:?}"));
+    }
+    if !recorded.windows(2).all(|w| w[0].time_ms <= w[1].time_ms) {
+        return TestResult::Failed(format!("Timestamps go backwards: {recorded:?}"));
+    }
+    if again != n {
+        return TestResult::Failed(format!("{} events recorded after stopping", again - n));
+    }
+    TestResult::Ok
+}
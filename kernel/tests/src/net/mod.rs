@@ -0,0 +1,81 @@
+use alloc::format;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+use kernel::drivers::e1000::{self, NetError};
+use kernel::time::tsc;
+use kernel_api_types::net::{MacAddress, BROADCAST_MAC, MAX_FRAME_SIZE};
+use crate::TestResult;
+
+// QEMU user networking: the guest is 10.0.2.15 and the gateway 10.0.2.2.
+const GUEST_IP: [u8; 4] = [10, 0, 2, 15];
+const GATEWAY_IP: [u8; 4] = [10, 0, 2, 2];
+
+const ETHERTYPE_ARP: [u8; 2] = [0x08, 0x06];
+
+/// An ARP request asking who has `target_ip`.
+fn arp_request(mac: MacAddress, target_ip: [u8; 4]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(42);
+    frame.extend_from_slice(&BROADCAST_MAC);
+    frame.extend_from_slice(&mac);
+    frame.extend_from_slice(&ETHERTYPE_ARP);
+    // Ethernet/IPv4, 6-byte and 4-byte addresses, opcode 1 (request).
+    frame.extend_from_slice(&[0, 1, 0x08, 0x00, 6, 4, 0, 1]);
+    frame.extend_from_slice(&mac);
+    frame.extend_from_slice(&GUEST_IP);
+    frame.extend_from_slice(&[0; 6]);
+    frame.extend_from_slice(&target_ip);
+    frame
+}
+
+pub fn nic_present() -> TestResult {
+    match e1000::mac_address() {
+        Ok(mac) if mac != [0; 6] && mac[0] & 1 == 0 => TestResult::Ok,
+        Ok(mac) => TestResult::Failed(format!("Implausible MAC {:02x?}", mac)),
+        Err(e) => TestResult::Failed(format!("No NIC: {:?}", e)),
+    }
+}
+
+pub fn send_rejects_bad_length() -> TestResult {
+    let runt = e1000::send(&[0; 4]);
+    let giant = e1000::send(&[0; MAX_FRAME_SIZE + 1]);
+    if runt == Err(NetError::BadLength) && giant == Err(NetError::BadLength) {
+        TestResult::Ok
+    } else {
+        TestResult::Failed(format!("Expected BadLength twice, got {:?} and {:?}", runt, giant))
+    }
+}
+
+/// Ask QEMU's gateway for its MAC and wait for the ARP reply.
+pub fn arp_gateway_replies() -> TestResult {
+    let mac = match e1000::mac_address() {
+        Ok(mac) => mac,
+        Err(e) => return TestResult::Failed(format!("No NIC: {:?}", e)),
+    };
+    if let Err(e) = e1000::send(&arp_request(mac, GATEWAY_IP)) {
+        return TestResult::Failed(format!("send failed: {:?}", e));
+    }
+
+    let start = tsc::value();
+    let timeout = tsc::TSC_HZ.load(Ordering::SeqCst) * 1000; // 1 s
+    let mut buf = [0u8; MAX_FRAME_SIZE];
+    while tsc::value() - start < timeout {
+        let len = match e1000::recv(&mut buf) {
+            Ok(Some(len)) => len,
+            Ok(None) => {
+                core::hint::spin_loop();
+                continue;
+            }
+            Err(e) => return TestResult::Failed(format!("recv failed: {:?}", e)),
+        };
+        let frame = &buf[..len];
+        let is_reply = len >= 42
+            && frame[12..14] == ETHERTYPE_ARP
+            && frame[20..22] == [0, 2]
+            && frame[28..32] == GATEWAY_IP
+            && frame[32..38] == mac;
+        if is_reply {
+            return TestResult::Ok;
+        }
+    }
+    TestResult::Failed("No ARP reply from 10.0.2.2 within 1 s".into())
+}
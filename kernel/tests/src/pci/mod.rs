@@ -0,0 +1,160 @@
+use alloc::format;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use kernel::drivers::msi::{self, MSI_COUNT};
+use kernel::drivers::pci::{self, Bar, PciAddress};
+use kernel::interrupt::InterruptVector;
+use kernel::memory::cpu_local_data::get_local;
+use kernel::time::tsc;
+use crate::TestResult;
+
+const VENDOR_INTEL: u16 = 0x8086;
+const CLASS_BRIDGE: u8 = 0x06;
+const SUBCLASS_HOST_BRIDGE: u8 = 0x00;
+
+// QEMU `edu` device, added by the runner for kernel tests.
+const EDU_VENDOR: u16 = 0x1234;
+const EDU_DEVICE: u16 = 0x11e8;
+const EDU_INTERRUPT_STATUS: usize = 0x24;
+const EDU_INTERRUPT_RAISE: usize = 0x60;
+const EDU_INTERRUPT_ACK: usize = 0x64;
+
+static EDU_REGS: AtomicPtr<u32> = AtomicPtr::new(core::ptr::null_mut());
+
+/// QEMU always has at least a host bridge, an ISA bridge and a display adapter.
+pub fn devices_found() -> TestResult {
+    let devices = pci::devices();
+    if devices.len() < 2 {
+        return TestResult::Failed(format!("Expected several PCI devices, found {}", devices.len()));
+    }
+    if let Some(d) = devices.iter().find(|d| d.vendor_id == 0xffff || d.vendor_id == 0) {
+        return TestResult::Failed(format!("Device {} has invalid vendor id {:#x}", d.address, d.vendor_id));
+    }
+    TestResult::Ok
+}
+
+/// Both of QEMU's machine types put an Intel host bridge (i440FX or Q35) at 00:00.0.
+pub fn host_bridge_present() -> TestResult {
+    let Some(bridge) = pci::devices().iter().find(|d| d.address == PciAddress::new(0, 0, 0)) else {
+        return TestResult::Failed("No device at 00:00.0".into());
+    };
+    if bridge.vendor_id != VENDOR_INTEL
+        || bridge.class != CLASS_BRIDGE
+        || bridge.subclass != SUBCLASS_HOST_BRIDGE
+    {
+        return TestResult::Failed(format!(
+            "00:00.0 is {:04x}:{:04x} class {:02x}.{:02x}, expected an Intel host bridge",
+            bridge.vendor_id, bridge.device_id, bridge.class, bridge.subclass,
+        ));
+    }
+    if pci::find(bridge.vendor_id, bridge.device_id).map(|d| d.address) != Some(bridge.address) {
+        return TestResult::Failed("find() did not return the host bridge".into());
+    }
+    TestResult::Ok
+}
+
+/// Every decoded BAR has a power-of-two size and is aligned to it.
+pub fn bars_plausible() -> TestResult {
+    for d in pci::devices() {
+        for (i, bar) in d.bars.iter().enumerate() {
+            let (base, size) = match *bar {
+                Some(Bar::Memory { address, size, .. }) => (address, size),
+                Some(Bar::Io { port, size }) => (port as u64, size as u64),
+                None => continue,
+            };
+            if !size.is_power_of_two() || base & (size - 1) != 0 {
+                return TestResult::Failed(format!(
+                    "{} BAR{}: base {:#x} size {:#x} is not a naturally aligned power of two",
+                    d.address, i, base, size,
+                ));
+            }
+        }
+    }
+    TestResult::Ok
+}
+
+/// The edu device advertises MSI, and walking the list terminates.
+pub fn capabilities_walk() -> TestResult {
+    let Some(edu) = pci::find(EDU_VENDOR, EDU_DEVICE) else {
+        return TestResult::Failed("edu device not found".into());
+    };
+    if edu.address.find_capability(msi::CAPABILITY_MSI).is_none() {
+        return TestResult::Failed(format!(
+            "edu has no MSI capability; list: {:?}",
+            edu.address.capabilities().collect::<alloc::vec::Vec<_>>(),
+        ));
+    }
+    for d in pci::devices() {
+        if d.address.capabilities().any(|(_, offset)| offset < 0x40) {
+            return TestResult::Failed(format!("{} has a capability inside the header", d.address));
+        }
+    }
+    TestResult::Ok
+}
+
+pub fn msi_message_encoding() -> TestResult {
+    let cases = [
+        (msi::message_address(0), 0xfee0_0000),
+        (msi::message_address(3), 0xfee0_3000),
+        (msi::message_address(0xff), 0xfeef_f000),
+    ];
+    for (got, want) in cases {
+        if got != want {
+            return TestResult::Failed(format!("message address {:#x}, expected {:#x}", got, want));
+        }
+    }
+    if msi::message_data(0x26) != 0x26 {
+        return TestResult::Failed(format!("message data {:#x}", msi::message_data(0x26)));
+    }
+    TestResult::Ok
+}
+
+fn edu_ack() {
+    let regs = EDU_REGS.load(Ordering::Acquire);
+    if regs.is_null() {
+        return;
+    }
+    unsafe {
+        let status = regs.byte_add(EDU_INTERRUPT_STATUS).read_volatile();
+        regs.byte_add(EDU_INTERRUPT_ACK).write_volatile(status);
+    }
+}
+
+/// Point edu's MSI at this CPU, ask it to raise an interrupt, and wait for
+/// the MSI handler to run.
+pub fn msi_delivered() -> TestResult {
+    let Some(edu) = pci::find(EDU_VENDOR, EDU_DEVICE) else {
+        return TestResult::Failed("edu device not found".into());
+    };
+    let Some(regs) = edu.map_bar(0) else {
+        return TestResult::Failed("edu BAR0 is not a memory BAR".into());
+    };
+    EDU_REGS.store(regs as *mut u32, Ordering::Release);
+    msi::register_handler(edu_ack);
+
+    let vector = u8::from(InterruptVector::Msi);
+    if let Err(e) = msi::enable_msi(edu, vector, get_local().local_apic_id) {
+        return TestResult::Failed(format!("enable_msi failed: {:?}", e));
+    }
+
+    let before = MSI_COUNT.load(Ordering::SeqCst);
+    let interrupts_enabled = x86_64::instructions::interrupts::are_enabled();
+    x86_64::instructions::interrupts::enable();
+    unsafe { (regs as *mut u32).byte_add(EDU_INTERRUPT_RAISE).write_volatile(1) };
+
+    let start = tsc::value();
+    let timeout = tsc::TSC_HZ.load(Ordering::SeqCst) * 100;
+    let result = loop {
+        if MSI_COUNT.load(Ordering::SeqCst) > before {
+            break TestResult::Ok;
+        }
+        if tsc::value() - start > timeout {
+            break TestResult::Failed("No MSI within 100 ms".into());
+        }
+        core::hint::spin_loop();
+    };
+
+    if !interrupts_enabled {
+        x86_64::instructions::interrupts::disable();
+    }
+    result
+}
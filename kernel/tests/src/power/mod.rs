@@ -0,0 +1,76 @@
+use alloc::format;
+use alloc::string::String;
+use kernel::power::{self, RebootMethod, ResetRegister, ShutdownMethod};
+use crate::TestResult;
+
+/// QEMU provides a FADT with PM1a control and a DSDT with `\_S5`.
+pub fn shutdown_selects_acpi_s5() -> TestResult {
+    match power::shutdown_method() {
+        ShutdownMethod::AcpiS5(s5) if s5.pm1a_cnt != 0 => TestResult::Ok,
+        other => TestResult::Failed(format!("Expected ACPI S5, got {:?}", other)),
+    }
+}
+
+pub fn find_s5_in_aml() -> TestResult {
+    // Name (\_S5, Package (0x04) { 0x05, Zero, Zero, Zero }), after some noise.
+    let aml = [
+        0x10, 0x05, b'_', b'S', b'5', b'_', // a Scope named like _S5_, not a Name
+        0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x08, 0x04, 0x0A, 0x05, 0x00, 0x00, 0x00,
+    ];
+    match power::find_s5(&aml) {
+        Some((5, 0)) => TestResult::Ok,
+        other => TestResult::Failed(format!("Unexpected \\_S5 parse: {:?}", other)),
+    }
+}
+
+pub fn find_s5_missing() -> TestResult {
+    let aml = [0x08, b'_', b'S', b'3', b'_', 0x12, 0x06, 0x04, 0x01, 0x01, 0x00, 0x00];
+    match power::find_s5(&aml) {
+        None => TestResult::Ok,
+        Some(v) => TestResult::Failed(String::from("Found \\_S5 where there is none: ") + &format!("{:?}", v)),
+    }
+}
+
+/// A minimal FADT: only the fields `reset_register_from_fadt` reads are set.
+fn fadt_with_reset(flags: u32, space: u8, address: u64, value: u8) -> [u8; 129] {
+    let mut fadt = [0u8; 129];
+    fadt[112..116].copy_from_slice(&flags.to_le_bytes());
+    fadt[116] = space;
+    fadt[117] = 8; // bit width
+    fadt[120..128].copy_from_slice(&address.to_le_bytes());
+    fadt[128] = value;
+    fadt
+}
+
+pub fn reset_register_parsed_from_fadt() -> TestResult {
+    let io = fadt_with_reset(1 << 10, 1, 0xcf9, 0x0f);
+    let memory = fadt_with_reset(1 << 10, 0, 0xfee0_0000, 0x01);
+    let unsupported = fadt_with_reset(0, 1, 0xcf9, 0x0f);
+    let pci = fadt_with_reset(1 << 10, 2, 0xcf9, 0x0f);
+
+    let results = (
+        power::reset_register_from_fadt(&io),
+        power::reset_register_from_fadt(&memory),
+        power::reset_register_from_fadt(&unsupported),
+        power::reset_register_from_fadt(&pci),
+        power::reset_register_from_fadt(&io[..116]),
+    );
+    match results {
+        (
+            Some(ResetRegister::Io { port: 0xcf9, value: 0x0f }),
+            Some(ResetRegister::Memory { address: 0xfee0_0000, value: 0x01 }),
+            None,
+            None,
+            None,
+        ) => TestResult::Ok,
+        other => TestResult::Failed(format!("Unexpected reset registers: {:?}", other)),
+    }
+}
+
+/// QEMU's FADT advertises a reset register, so reboot should try it first.
+pub fn reboot_selects_acpi_reset_register() -> TestResult {
+    match power::reboot_method() {
+        RebootMethod::AcpiReset(_) => TestResult::Ok,
+        other => TestResult::Failed(format!("Expected the ACPI reset register, got {:?}", other)),
+    }
+}
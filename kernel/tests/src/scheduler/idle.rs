@@ -0,0 +1,164 @@
+//! The idle task must only get the CPU when no other task on it can run.
+//!
+//! These tests call `schedule_from_interrupt` directly, with interrupts off, on
+//! a run queue set up by hand, so none of the tasks actually runs: each call
+//! stands for one timer tick, and a task's `cpu_ticks` counts the quanta it was
+//! given.
+
+use super::with_scratch_run_queue;
+use crate::TestResult;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::sync::Arc;
+use core::sync::atomic::Ordering;
+use kernel::memory::cpu_local_data::CpuLocalData;
+use kernel::task::local_scheduler::{add, schedule_from_interrupt, set_idle};
+use kernel::task::task::{Task, TaskState};
+
+const TICKS: u64 = 100;
+
+fn spin() -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+fn tick(cpu: &CpuLocalData, n: u64) {
+    for _ in 0..n {
+        schedule_from_interrupt(cpu);
+    }
+}
+
+fn is_current(cpu: &CpuLocalData, task: &Arc<Task>) -> bool {
+    let rq = cpu.run_queue.get().unwrap().lock();
+    rq.current_task.as_ref().is_some_and(|t| Arc::ptr_eq(t, task))
+}
+
+/// Quanta given to two always-runnable counter tasks (and the idle task, if
+/// `with_idle`) over `TICKS` ticks.
+fn counter_quanta(cpu: &CpuLocalData, with_idle: bool) -> (u64, u64, u64) {
+    let a = Arc::new(Task::new(spin));
+    let b = Arc::new(Task::new(spin));
+    let idle = Arc::new(Task::new(spin));
+    if with_idle {
+        set_idle(cpu, idle.clone());
+    }
+    add(cpu, a.clone());
+    add(cpu, b.clone());
+
+    // The first tick only picks a task; every later one ends a quantum.
+    tick(cpu, TICKS + 1);
+
+    {
+        let mut rq = cpu.run_queue.get().unwrap().lock();
+        rq.current_task = None;
+        rq.ready = VecDeque::new();
+        rq.idle_task = None;
+    }
+    cpu.ready_count.store(0, Ordering::Relaxed);
+    cpu.current_context_ptr.store(core::ptr::null_mut(), Ordering::Relaxed);
+    cpu.idle_context_ptr.store(core::ptr::null_mut(), Ordering::Relaxed);
+
+    let quanta = |t: &Arc<Task>| t.cpu_ticks.load(Ordering::Relaxed);
+    (quanta(&a), quanta(&b), quanta(&idle))
+}
+
+/// Two busy tasks split the CPU between them exactly as they would with no
+/// idle task at all; the idle task gets nothing.
+pub fn test_idle_does_not_share_cpu_with_ready_tasks() -> TestResult {
+    with_scratch_run_queue(|cpu| {
+        let with_idle = counter_quanta(cpu, true);
+        let without_idle = counter_quanta(cpu, false);
+
+        let (a, b, idle) = with_idle;
+        if idle != 0 {
+            return TestResult::Failed(format!("idle task got {idle} of {TICKS} quanta"));
+        }
+        if a + b != TICKS || a.abs_diff(b) > 1 {
+            return TestResult::Failed(format!("counters got {a} and {b} of {TICKS} quanta"));
+        }
+        if (a, b) != (without_idle.0, without_idle.1) {
+            return TestResult::Failed(format!(
+                "counters got {a}/{b} quanta with the idle task, {}/{} without",
+                without_idle.0, without_idle.1
+            ));
+        }
+        TestResult::Ok
+    })
+}
+
+/// The idle task takes over once the only real task sleeps, is never queued
+/// itself, and gives the CPU straight back when the task wakes. A lone busy
+/// task keeps the CPU instead of alternating with idle.
+pub fn test_idle_runs_only_when_nothing_else_can() -> TestResult {
+    with_scratch_run_queue(|cpu| {
+        let task = Arc::new(Task::new(spin));
+        let idle = Arc::new(Task::new(spin));
+        set_idle(cpu, idle.clone());
+        add(cpu, task.clone());
+
+        tick(cpu, 1);
+        if !is_current(cpu, &task) {
+            return TestResult::Failed("ready task not picked over idle".into());
+        }
+        tick(cpu, 5);
+        if !is_current(cpu, &task) {
+            return TestResult::Failed("lone busy task lost the CPU to idle".into());
+        }
+
+        task.state.store(TaskState::Sleeping, Ordering::Relaxed);
+        tick(cpu, 3);
+        if !is_current(cpu, &idle) {
+            return TestResult::Failed("idle task not run while the only task sleeps".into());
+        }
+
+        task.state.store(TaskState::Ready, Ordering::Relaxed);
+        add(cpu, task.clone());
+        tick(cpu, 1);
+        if !is_current(cpu, &task) {
+            return TestResult::Failed("woken task did not preempt idle".into());
+        }
+        let queued = cpu.run_queue.get().unwrap().lock().ready.len();
+        if queued != 0 {
+            return TestResult::Failed(format!("{queued} task(s) queued behind the running one, expected none"));
+        }
+
+        let mut rq = cpu.run_queue.get().unwrap().lock();
+        rq.current_task = None;
+        rq.idle_task = None;
+        TestResult::Ok
+    })
+}
+
+/// An idle CPU spends nearly all its time waiting and, with the tick
+/// stretched, takes far fewer than the 1 ms tick's timer interrupts.
+pub fn test_idle_cpu_is_tickless() -> TestResult {
+    use kernel::memory::cpu_local_data::get_local;
+    use kernel::task::idle::{idle_once, MAX_IDLE_MS};
+    use kernel::time::now_ns;
+
+    const PERIOD_MS: u64 = 300;
+
+    let cpu = get_local();
+    if cpu.ready_count.load(Ordering::Relaxed) != 0 {
+        return TestResult::Failed("Expected no queued tasks on this CPU".into());
+    }
+    let (idle_before, interrupts_before) =
+        (cpu.idle_ns.load(Ordering::Relaxed), cpu.timer_interrupts.load(Ordering::Relaxed));
+    let start = now_ns();
+    while now_ns() - start < PERIOD_MS * 1_000_000 {
+        idle_once(cpu);
+    }
+    let elapsed = now_ns() - start;
+    let idle = cpu.idle_ns.load(Ordering::Relaxed) - idle_before;
+    let interrupts = cpu.timer_interrupts.load(Ordering::Relaxed) - interrupts_before;
+
+    if idle * 100 < elapsed * 95 {
+        return TestResult::Failed(format!("Idle for only {idle} of {elapsed} ns"));
+    }
+    // One per MAX_IDLE_MS, plus slack; the 1 ms tick would take ~PERIOD_MS.
+    if interrupts > PERIOD_MS / MAX_IDLE_MS + 5 {
+        return TestResult::Failed(format!("{interrupts} timer interrupts in {PERIOD_MS} ms"));
+    }
+    TestResult::Ok
+}
@@ -1,4 +1,6 @@
 pub mod context_switch;
+pub mod idle;
+pub mod priority;
 pub mod spawn;
 pub mod stack;
 
@@ -8,6 +10,38 @@ use kernel::task::global_scheduler::spawn_task;
 use kernel::time::tsc;
 use core::sync::atomic::{AtomicU64, Ordering};
 use alloc::format;
+use alloc::sync::Arc;
+use kernel::memory::cpu_local_data::{get_local, CpuLocalData};
+use x86_64::instructions::interrupts;
+
+/// Run `f` on an empty run queue for this CPU, then put the real one back.
+pub(crate) fn with_scratch_run_queue(f: impl FnOnce(&CpuLocalData) -> TestResult) -> TestResult {
+    interrupts::without_interrupts(|| {
+        let cpu = get_local();
+        let (current, ready, idle) = {
+            let mut rq = cpu.run_queue.get().unwrap().lock();
+            (rq.current_task.take(), core::mem::take(&mut rq.ready), rq.idle_task.take())
+        };
+        let ctx = cpu.current_context_ptr.swap(core::ptr::null_mut(), Ordering::Relaxed);
+        let idle_ctx = cpu.idle_context_ptr.swap(core::ptr::null_mut(), Ordering::Relaxed);
+        let ready_count = cpu.ready_count.swap(0, Ordering::Relaxed);
+        let rsp0 = cpu.current_task_kernel_stack_top.load(Ordering::Relaxed);
+
+        let result = f(cpu);
+
+        {
+            let mut rq = cpu.run_queue.get().unwrap().lock();
+            rq.current_task = current;
+            rq.ready = ready;
+            rq.idle_task = idle;
+        }
+        cpu.current_context_ptr.store(ctx, Ordering::Relaxed);
+        cpu.idle_context_ptr.store(idle_ctx, Ordering::Relaxed);
+        cpu.ready_count.store(ready_count, Ordering::Relaxed);
+        unsafe { cpu.set_tss_rsp0(rsp0) };
+        result
+    })
+}
 
 static TEST_COUNTER: AtomicU64 = AtomicU64::new(0);
 
@@ -21,8 +55,8 @@ fn task_increment() -> ! {
 /// Checker task for `test_kernel_tasks_run`.
 ///
 /// Logs immediately on entry so the serial output shows it started even if
-/// something goes wrong afterward. Waits up to `TSC_HZ * 1000` ticks for
-/// both increment tasks to have run, then exits QEMU with the result.
+/// something goes wrong afterward. Waits up to one second for both increment
+/// tasks to have run, then exits QEMU with the result.
 fn kernel_tasks_checker() -> ! {
     let count_at_start = TEST_COUNTER.load(Ordering::SeqCst);
     log::info!(
@@ -30,11 +64,9 @@ fn kernel_tasks_checker() -> ! {
         count_at_start
     );
 
-    // TSC_HZ may be miscalibrated (~27 000 ticks/ms on this machine instead of
-    // ~3 000 000), so multiply by 1000 to get a timeout that is generous even
-    // with a badly calibrated TSC.
+    // TSC_HZ is ticks per ms: 1000 ms.
     let start_tsc = tsc::value();
-    let timeout = tsc::TSC_HZ.load(Ordering::SeqCst).saturating_mul(1000);
+    let timeout = tsc::TSC_HZ.load(Ordering::SeqCst) * 1000;
 
     while TEST_COUNTER.load(Ordering::SeqCst) < 2 {
         if tsc::value().wrapping_sub(start_tsc) > timeout {
@@ -93,3 +125,91 @@ pub fn simple_task_creation() -> TestResult {
         TestResult::Failed(format!("Task state should be Initializing, but is {:?}", task.run_state()))
     }
 }
+
+/// Over-long names are truncated at a character boundary, not rejected, and
+/// show up in the task's log form.
+pub fn test_task_names() -> TestResult {
+    let task = Task::new_named(|| loop {}, "a-name-that-is-far-too-long-to-keep");
+    let name = task.name();
+    if name.as_deref() != Some("a-name-that-is-far-too-long-to-k") {
+        return TestResult::Failed(format!("Expected the name cut to 32 bytes, got {:?}", name));
+    }
+
+    // 'é' is two bytes, so the 32-byte limit falls inside the last one.
+    task.set_name("xéééééééééééééééé");
+    let name = task.name();
+    if name.as_deref() != Some("xééééééééééééééé") {
+        return TestResult::Failed(format!("Expected the last character dropped, got {:?}", name));
+    }
+
+    task.set_name("worker");
+    let shown = format!("{task}");
+    if shown != format!("{} (worker)", task.id.to_u64()) {
+        return TestResult::Failed(format!("Unexpected log form {shown:?}"));
+    }
+    if Task::new(|| loop {}).name().is_some() {
+        return TestResult::Failed("Task::new should leave the task unnamed".into());
+    }
+    TestResult::Ok
+}
+
+/// A killed task is taken out of the ready queue, dropped when it is running
+/// and the CPU next reschedules, and never queued again.
+pub fn test_killed_task_leaves_run_queue() -> TestResult {
+    use kernel::task::local_scheduler::{add, evict, schedule_from_interrupt};
+
+    with_scratch_run_queue(|cpu| {
+        let running = Arc::new(Task::new(|| loop {}));
+        let queued = Arc::new(Task::new(|| loop {}));
+        let other = Arc::new(Task::new(|| loop {}));
+        add(cpu, running.clone());
+        add(cpu, queued.clone());
+        add(cpu, other.clone());
+        schedule_from_interrupt(cpu);
+
+        queued.killed.store(true, Ordering::Release);
+        let queued_was_current = evict(cpu, &queued);
+        running.killed.store(true, Ordering::Release);
+        let running_was_current = evict(cpu, &running);
+        schedule_from_interrupt(cpu);
+        add(cpu, running.clone());
+
+        let mut rq = cpu.run_queue.get().unwrap().lock();
+        let current_is_other = rq.current_task.as_ref().is_some_and(|t| Arc::ptr_eq(t, &other));
+        let ready = rq.ready.len();
+        rq.current_task = None;
+        rq.ready.clear();
+        drop(rq);
+        let ready_count = cpu.ready_count.swap(0, Ordering::Relaxed);
+
+        if queued_was_current || !running_was_current {
+            return TestResult::Failed("evict misreported which task was running".into());
+        }
+        if !current_is_other || ready != 0 || ready_count != 0 {
+            return TestResult::Failed(format!(
+                "Expected only the live task left, got {ready} ready (count {ready_count})"
+            ));
+        }
+        TestResult::Ok
+    })
+}
+
+/// Once a task is a zombie, the state changes a killed task can still pass
+/// through on its way out (sleeping in a syscall, being requeued or run)
+/// leave it one.
+pub fn test_zombie_state_is_final() -> TestResult {
+    let task = Task::new(|| loop {});
+    task.state.store(TaskState::Zombie, Ordering::Release);
+    for state in [TaskState::Sleeping, TaskState::Ready, TaskState::Running] {
+        task.set_state(state);
+    }
+    let live = Task::new(|| loop {});
+    live.set_state(TaskState::Sleeping);
+    if task.state.load(Ordering::Acquire) != TaskState::Zombie {
+        return TestResult::Failed("A later state change brought a zombie back".into());
+    }
+    if live.state.load(Ordering::Acquire) != TaskState::Sleeping {
+        return TestResult::Failed("set_state didn't change a live task's state".into());
+    }
+    TestResult::Ok
+}
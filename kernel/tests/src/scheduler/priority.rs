@@ -0,0 +1,75 @@
+//! The scheduler runs the highest-priority ready task, round-robin among
+//! equals. Ticks are simulated as in `idle`.
+
+use super::with_scratch_run_queue;
+use crate::TestResult;
+use alloc::format;
+use alloc::sync::Arc;
+use core::sync::atomic::Ordering;
+use kernel::memory::cpu_local_data::CpuLocalData;
+use kernel::task::local_scheduler::{add, schedule_from_interrupt};
+use kernel::task::task::{Task, TaskState};
+
+const TICKS: u64 = 100;
+
+fn spin() -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+fn task_with_priority(priority: u8) -> Arc<Task> {
+    let task = Task::new(spin);
+    task.base_priority.store(priority, Ordering::Relaxed);
+    Arc::new(task)
+}
+
+fn tick(cpu: &CpuLocalData, n: u64) {
+    for _ in 0..n {
+        schedule_from_interrupt(cpu);
+    }
+}
+
+fn quanta(task: &Arc<Task>) -> u64 {
+    task.cpu_ticks.load(Ordering::Relaxed)
+}
+
+/// Two high-priority tasks share the CPU evenly and starve a low-priority
+/// one, which only runs once both sleep.
+pub fn test_higher_priority_runs_first() -> TestResult {
+    with_scratch_run_queue(|cpu| {
+        let low = task_with_priority(1);
+        let high_a = task_with_priority(6);
+        let high_b = task_with_priority(6);
+        // Queue the low-priority task first so plain FIFO order would pick it.
+        add(cpu, low.clone());
+        add(cpu, high_a.clone());
+        add(cpu, high_b.clone());
+
+        tick(cpu, TICKS + 1);
+        let (a, b) = (quanta(&high_a), quanta(&high_b));
+        if quanta(&low) != 0 {
+            return TestResult::Failed(format!("low-priority task got {} quanta", quanta(&low)));
+        }
+        if a + b != TICKS || a.abs_diff(b) > 1 {
+            return TestResult::Failed(format!("high-priority tasks got {a} and {b} of {TICKS} quanta"));
+        }
+
+        high_a.state.store(TaskState::Sleeping, Ordering::Relaxed);
+        high_b.state.store(TaskState::Sleeping, Ordering::Relaxed);
+        tick(cpu, 3);
+        let running_low = cpu.run_queue.get().unwrap().lock()
+            .current_task.as_ref().is_some_and(|t| Arc::ptr_eq(t, &low));
+
+        let mut rq = cpu.run_queue.get().unwrap().lock();
+        rq.current_task = None;
+        rq.ready.clear();
+        drop(rq);
+        cpu.ready_count.store(0, Ordering::Relaxed);
+
+        if !running_low {
+            return TestResult::Failed("low-priority task not run once the others slept".into());
+        }
+        TestResult::Ok
+    })
+}
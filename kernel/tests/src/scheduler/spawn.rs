@@ -1,14 +1,61 @@
 use crate::TestResult;
 use alloc::format;
+use alloc::sync::Arc;
+use kernel::memory::MEMORY;
+use kernel::memory::physical_memory::{MemoryType, OffsetMappedPhysAddr};
+use kernel::reexports::x86_64::structures::paging::{PhysFrame, Size4KiB};
+use kernel::reexports::x86_64::PhysAddr;
+use kernel::task::registry;
+use kernel::user_task_from_elf::{create_user_task_from_elf_bytes, SpawnError};
 use kernel::task::task::{TaskKind, TaskState};
 
-/// Calling create_user_task_from_elf_bytes with garbage bytes should return InvalidElf.
+/// create_user_task_from_elf_bytes tells a bad image from a lack of memory:
+/// garbage and a truncated ELF give InvalidElf, and a valid ELF with every
+/// frame taken gives OutOfMemory.
 pub fn test_spawn_error_invalid_elf() -> TestResult {
     let garbage = [0xDE, 0xAD, 0xBE, 0xEF, 0x00, 0x01, 0x02, 0x03];
-    match kernel::user_task_from_elf::create_user_task_from_elf_bytes(&garbage, 0) {
-        Err(_) => TestResult::Ok,
-        Ok(_) => TestResult::Failed("Expected InvalidElf error for garbage bytes".into()),
+    let elf = get_user_elf_bytes();
+    let truncated = &elf[..elf.len() / 2];
+
+    let errors = [
+        create_user_task_from_elf_bytes(&garbage, 0).err(),
+        create_user_task_from_elf_bytes(truncated, 0).err(),
+        with_no_free_frames(|| create_user_task_from_elf_bytes(elf, 0).err()),
+    ];
+    let expected = [Some(SpawnError::InvalidElf), Some(SpawnError::InvalidElf), Some(SpawnError::OutOfMemory)];
+    if errors != expected {
+        return TestResult::Failed(format!(
+            "Expected {:?} for garbage/truncated/no memory, got {:?}",
+            expected, errors
+        ));
+    }
+    TestResult::Ok
+}
+
+/// Run `f` with every free frame allocated, then free them again. The taken
+/// frames are chained through their first word, so holding them needs no heap.
+fn with_no_free_frames<R>(f: impl FnOnce() -> R) -> R {
+    let memory = MEMORY.get().unwrap();
+    let next_of = |frame: PhysFrame<Size4KiB>| frame.start_address().offset_mapped().as_mut_ptr::<u64>();
+
+    let mut head = 0u64;
+    {
+        let mut physical_memory = memory.physical_memory.lock();
+        while let Some(frame) = physical_memory.allocate_frame_with_type(MemoryType::UsedByUserMode) {
+            unsafe { next_of(frame).write(head) };
+            head = frame.start_address().as_u64();
+        }
+    }
+
+    let result = f();
+
+    let mut physical_memory = memory.physical_memory.lock();
+    while head != 0 {
+        let frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(head));
+        head = unsafe { next_of(frame).read() };
+        let _ = physical_memory.free_frame(frame, MemoryType::UsedByUserMode);
     }
+    result
 }
 
 /// Create a task from the Limine module ELF bytes via create_user_task_from_elf_bytes
@@ -71,6 +118,37 @@ pub fn test_spawn_child_arg() -> TestResult {
     }
 }
 
+/// A spawned task can be looked up by ID until it exits, and the registry's
+/// weak reference does not keep a dropped task alive.
+pub fn test_spawned_task_in_registry() -> TestResult {
+    let elf_bytes = get_user_elf_bytes();
+    let task = match kernel::user_task_from_elf::create_user_task_from_elf_bytes(elf_bytes, 0) {
+        Ok(task) => Arc::new(task),
+        Err(e) => return TestResult::Failed(format!("Failed to create task: {:?}", e)),
+    };
+    let id = task.id.to_u64();
+    // What spawn_task does once the task is in an Arc; it is not queued to
+    // run here.
+    registry::register(&task);
+    match kernel::task::lookup(id) {
+        Some(found) if Arc::ptr_eq(&found, &task) => {}
+        _ => return TestResult::Failed(format!("Task {id} not found after registering")),
+    }
+
+    // What sys_exit does.
+    registry::unregister(task.id);
+    if kernel::task::lookup(id).is_some() {
+        return TestResult::Failed(format!("Task {id} still found after exit"));
+    }
+
+    registry::register(&task);
+    drop(task);
+    if kernel::task::lookup(id).is_some() {
+        return TestResult::Failed(format!("Dropped task {id} still found"));
+    }
+    TestResult::Ok
+}
+
 fn get_user_elf_bytes() -> &'static [u8] {
     use core::ptr::NonNull;
     use core::ptr::slice_from_raw_parts_mut;
@@ -10,14 +10,12 @@ use crate::TestResult;
 use alloc::{format, sync::Arc};
 use kernel::ipc;
 use kernel::memory::cpu_local_data::get_local;
+use kernel::task::task::Privilege;
 use kernel::user_task_from_elf::create_user_task_from_elf_bytes;
-use kernel_api_types::{
-    graphics::GraphicsResult, IPC_ERR_INVALID_ARGS, IPC_ERR_INVALID_ENDPOINT,
-    IPC_ERR_MSG_TOO_LARGE, IPC_ERR_WRONG_DIRECTION, IPC_OK, MMAP_WRITE,
-};
+use kernel_api_types::{SysError, MMAP_WRITE};
 use x86_64::instructions::interrupts;
 use x86_64::registers::control::Cr3;
-use x86_64::structures::paging::{PhysFrame, Size4KiB};
+use x86_64::structures::paging::{PageTableFlags, PhysFrame, Size4KiB};
 use x86_64::PhysAddr;
 
 // ─── helpers ────────────────────────────────────────────────────────────────
@@ -44,8 +42,16 @@ fn get_init_task_elf() -> &'static [u8] {
 /// The kernel higher-half is shared with the user page table, so kernel code,
 /// GS.Base, and the stack remain accessible throughout the switch.
 fn with_user_context(f: impl FnOnce() -> TestResult) -> TestResult {
+    with_user_context_as(Privilege::User, f)
+}
+
+/// `with_user_context`, with the task given `privilege`.
+fn with_user_context_as(privilege: Privilege, f: impl FnOnce() -> TestResult) -> TestResult {
     let task = match create_user_task_from_elf_bytes(get_init_task_elf(), 0) {
-        Ok(t) => Arc::new(t),
+        Ok(mut t) => {
+            t.privilege = privilege;
+            Arc::new(t)
+        }
         Err(e) => {
             return TestResult::Failed(format!("failed to create user task: {:?}", e));
         }
@@ -77,6 +83,15 @@ fn with_user_context(f: impl FnOnce() -> TestResult) -> TestResult {
     })
 }
 
+/// Check that a raw syscall return value is the error `want`.
+fn expect_err(ret: u64, want: SysError) -> TestResult {
+    if ret == want as u64 {
+        TestResult::Ok
+    } else {
+        TestResult::Failed(format!("expected {want:?}, got {ret:#x} ({:?})", SysError::from_ret(ret)))
+    }
+}
+
 // ─── 1. Argument-validation tests (no user context needed) ──────────────────
 
 /// sys_debug_log always succeeds regardless of arguments.
@@ -88,7 +103,73 @@ pub fn test_sys_debug_log_always_ok() -> TestResult {
     TestResult::Ok
 }
 
-/// Null send_ep_out pointer → IPC_ERR_INVALID_ARGS.
+/// sys_null does no work and always returns 0.
+pub fn test_sys_null_returns_zero() -> TestResult {
+    let ret = kernel::syscall_handlers::sys_null(1, 2, 3, 4, 5, 6);
+    if ret != 0 {
+        return TestResult::Failed(format!("sys_null returned {ret:#x}, expected 0"));
+    }
+    TestResult::Ok
+}
+
+/// sys_debug_log_str rejects a bad pointer but accepts an empty string without one.
+pub fn test_sys_debug_log_str_validates_ptr() -> TestResult {
+    use kernel::syscall_handlers::sys_debug_log_str;
+
+    if let TestResult::Failed(msg) = expect_err(sys_debug_log_str(0, 5, 0, 0, 0, 0), SysError::InvalidArgs) {
+        return TestResult::Failed(format!("null pointer: {msg}"));
+    }
+    let empty = sys_debug_log_str(0, 0, 0, 0, 0, 0);
+    if empty != 0 {
+        return TestResult::Failed(format!("empty string returned {empty:#x}"));
+    }
+    TestResult::Ok
+}
+
+/// sys_set_syscall_trace flips the trace flag and reports its previous state.
+pub fn test_sys_set_syscall_trace_toggles() -> TestResult {
+    use core::sync::atomic::Ordering;
+    use kernel::raw_syscall_handler::SYSCALL_TRACE;
+    use kernel::syscall_handlers::sys_set_syscall_trace;
+
+    let saved = SYSCALL_TRACE.load(Ordering::Relaxed);
+    let was_on = sys_set_syscall_trace(0, 0, 0, 0, 0, 0);
+    let enable = sys_set_syscall_trace(1, 0, 0, 0, 0, 0);
+    let on_after_enable = SYSCALL_TRACE.load(Ordering::Relaxed);
+    let disable = sys_set_syscall_trace(0, 0, 0, 0, 0, 0);
+    let on_after_disable = SYSCALL_TRACE.load(Ordering::Relaxed);
+    SYSCALL_TRACE.store(saved, Ordering::Relaxed);
+
+    if was_on != saved as u64 {
+        return TestResult::Failed(format!("first call returned {was_on}, flag was {saved}"));
+    }
+    if enable != 0 || !on_after_enable {
+        return TestResult::Failed(format!("enable returned {enable}, flag now {on_after_enable}"));
+    }
+    if disable != 1 || on_after_disable {
+        return TestResult::Failed(format!("disable returned {disable}, flag now {on_after_disable}"));
+    }
+    TestResult::Ok
+}
+
+/// A user task can't switch the global syscall trace.
+pub fn test_sys_set_syscall_trace_needs_privilege() -> TestResult {
+    use core::sync::atomic::Ordering;
+    use kernel::raw_syscall_handler::SYSCALL_TRACE;
+    use kernel::syscall_handlers::sys_set_syscall_trace;
+
+    let saved = SYSCALL_TRACE.load(Ordering::Relaxed);
+    let result = with_user_context(|| {
+        expect_err(sys_set_syscall_trace(!saved as u64, 0, 0, 0, 0, 0), SysError::PermissionDenied)
+    });
+    if SYSCALL_TRACE.load(Ordering::Relaxed) != saved {
+        SYSCALL_TRACE.store(saved, Ordering::Relaxed);
+        return TestResult::Failed("a user task flipped the trace flag".into());
+    }
+    result
+}
+
+/// Null send_ep_out pointer → `SysError::InvalidArgs`.
 pub fn test_sys_channel_create_null_send_ptr() -> TestResult {
     let mut dummy: u64 = 0;
     let ret = kernel::syscall_handlers::sys_channel_create(
@@ -99,15 +180,10 @@ pub fn test_sys_channel_create_null_send_ptr() -> TestResult {
         0,
         0,
     );
-    if ret != IPC_ERR_INVALID_ARGS {
-        return TestResult::Failed(format!(
-            "expected IPC_ERR_INVALID_ARGS ({IPC_ERR_INVALID_ARGS:#x}), got {ret:#x}"
-        ));
-    }
-    TestResult::Ok
+    expect_err(ret, SysError::InvalidArgs)
 }
 
-/// Null recv_ep_out pointer → IPC_ERR_INVALID_ARGS.
+/// Null recv_ep_out pointer → `SysError::InvalidArgs`.
 pub fn test_sys_channel_create_null_recv_ptr() -> TestResult {
     let mut dummy: u64 = 0;
     let ret = kernel::syscall_handlers::sys_channel_create(
@@ -118,27 +194,17 @@ pub fn test_sys_channel_create_null_recv_ptr() -> TestResult {
         0,
         0,
     );
-    if ret != IPC_ERR_INVALID_ARGS {
-        return TestResult::Failed(format!(
-            "expected IPC_ERR_INVALID_ARGS ({IPC_ERR_INVALID_ARGS:#x}), got {ret:#x}"
-        ));
-    }
-    TestResult::Ok
+    expect_err(ret, SysError::InvalidArgs)
 }
 
 /// Send to a non-existent endpoint (msg_len=0 skips the buf-pointer check)
-/// → IPC_ERR_INVALID_ENDPOINT.
+/// → `SysError::InvalidEndpoint`.
 pub fn test_sys_channel_send_invalid_endpoint() -> TestResult {
     let ret = kernel::syscall_handlers::sys_channel_send(99999, 0, 0, 0, 0, 0);
-    if ret != IPC_ERR_INVALID_ENDPOINT {
-        return TestResult::Failed(format!(
-            "expected IPC_ERR_INVALID_ENDPOINT ({IPC_ERR_INVALID_ENDPOINT:#x}), got {ret:#x}"
-        ));
-    }
-    TestResult::Ok
+    expect_err(ret, SysError::InvalidEndpoint)
 }
 
-/// Message larger than MAX_MESSAGE_SIZE → IPC_ERR_MSG_TOO_LARGE (checked before
+/// Message larger than MAX_MESSAGE_SIZE → `SysError::MessageTooLarge` (checked before
 /// any pointer validation, so no user context is needed).
 pub fn test_sys_channel_send_too_large() -> TestResult {
     let ret = kernel::syscall_handlers::sys_channel_send(
@@ -149,58 +215,38 @@ pub fn test_sys_channel_send_too_large() -> TestResult {
         0,
         0,
     );
-    if ret != IPC_ERR_MSG_TOO_LARGE {
-        return TestResult::Failed(format!(
-            "expected IPC_ERR_MSG_TOO_LARGE ({IPC_ERR_MSG_TOO_LARGE:#x}), got {ret:#x}"
-        ));
-    }
-    TestResult::Ok
+    expect_err(ret, SysError::MessageTooLarge)
 }
 
-/// Null buf_ptr → IPC_ERR_INVALID_ARGS on channel_recv.
+/// Null buf_ptr → `SysError::InvalidArgs` on channel_recv.
 pub fn test_sys_channel_recv_null_ptr() -> TestResult {
     let ret = kernel::syscall_handlers::sys_channel_recv(1, 0, 0, 0, 0, 0);
-    if ret != IPC_ERR_INVALID_ARGS {
-        return TestResult::Failed(format!(
-            "expected IPC_ERR_INVALID_ARGS ({IPC_ERR_INVALID_ARGS:#x}), got {ret:#x}"
-        ));
-    }
-    TestResult::Ok
+    expect_err(ret, SysError::InvalidArgs)
 }
 
-/// Closing a non-existent endpoint must not return IPC_OK.
+/// Closing a non-existent endpoint → `SysError::InvalidEndpoint`.
 pub fn test_sys_channel_close_invalid_endpoint() -> TestResult {
     let ret = kernel::syscall_handlers::sys_channel_close(99999, 0, 0, 0, 0, 0);
-    if ret == IPC_OK {
-        return TestResult::Failed("closing a non-existent endpoint returned IPC_OK".into());
-    }
-    TestResult::Ok
+    expect_err(ret, SysError::InvalidEndpoint)
 }
 
-/// sys_mmap(0, …) returns 0 — zero-size allocation is rejected before any
-/// task-context check.
+/// sys_mmap(0, …) → `SysError::InvalidArgs` — zero-size allocation is rejected
+/// before any task-context check.
 pub fn test_sys_mmap_zero_size() -> TestResult {
     let ret = kernel::syscall_handlers::sys_mmap(0, MMAP_WRITE, 0, 0, 0, 0);
-    if ret != 0 {
-        return TestResult::Failed(format!("sys_mmap(0) returned {ret:#x}, expected 0"));
-    }
-    TestResult::Ok
+    expect_err(ret, SysError::InvalidArgs)
 }
 
-/// sys_munmap with an unaligned address returns an error before any task-context check.
+/// sys_munmap with an unaligned address → `SysError::InvalidArgs`, before any
+/// task-context check.
 pub fn test_sys_munmap_unaligned() -> TestResult {
     let ret = kernel::syscall_handlers::sys_munmap(1, 4096, 0, 0, 0, 0);
-    if ret == 0 {
-        return TestResult::Failed(
-            "sys_munmap with unaligned addr returned 0 (success)".into(),
-        );
-    }
-    TestResult::Ok
+    expect_err(ret, SysError::InvalidArgs)
 }
 
-/// Sending on a recv-endpoint returns IPC_ERR_WRONG_DIRECTION.
-/// Receiving on a send-endpoint is rejected (either WRONG_DIRECTION or INVALID_ARGS
-/// from the null-ptr check — either way, not IPC_OK).
+/// Sending on a recv-endpoint returns `SysError::WrongDirection`.
+/// Receiving on a send-endpoint is rejected (either WrongDirection or InvalidArgs
+/// from the null-ptr check — either way, an error).
 pub fn test_sys_channel_wrong_direction() -> TestResult {
     let (send_id, recv_id) = ipc::create_channel(4);
 
@@ -212,13 +258,11 @@ pub fn test_sys_channel_wrong_direction() -> TestResult {
     let _ = ipc::close_endpoint(send_id);
     let _ = ipc::close_endpoint(recv_id);
 
-    if send_on_recv != IPC_ERR_WRONG_DIRECTION {
-        return TestResult::Failed(format!(
-            "send on recv-endpoint: expected IPC_ERR_WRONG_DIRECTION ({IPC_ERR_WRONG_DIRECTION:#x}), got {send_on_recv:#x}"
-        ));
+    if let TestResult::Failed(msg) = expect_err(send_on_recv, SysError::WrongDirection) {
+        return TestResult::Failed(format!("send on recv-endpoint: {msg}"));
     }
-    if recv_on_send == IPC_OK {
-        return TestResult::Failed("recv on send-endpoint returned IPC_OK".into());
+    if !SysError::is_error(recv_on_send) {
+        return TestResult::Failed(format!("recv on send-endpoint returned {recv_on_send:#x}"));
     }
     TestResult::Ok
 }
@@ -226,12 +270,12 @@ pub fn test_sys_channel_wrong_direction() -> TestResult {
 // ─── 2. Full-flow tests (user task context + user CR3) ──────────────────────
 
 /// sys_channel_create writes two non-zero, distinct endpoint IDs into user memory
-/// and returns IPC_OK.
+/// and returns 0.
 pub fn test_sys_channel_create_returns_endpoints() -> TestResult {
     with_user_context(|| {
         // 16 bytes: two u64 output slots
         let ep_buf = kernel::syscall_handlers::sys_mmap(16, MMAP_WRITE, 0, 0, 0, 0);
-        if ep_buf == 0 {
+        if SysError::is_error(ep_buf) {
             return TestResult::Failed("sys_mmap for ep_buf failed".into());
         }
 
@@ -243,7 +287,7 @@ pub fn test_sys_channel_create_returns_endpoints() -> TestResult {
         let _ = ipc::close_endpoint(send_id);
         let _ = ipc::close_endpoint(recv_id);
 
-        if ret != IPC_OK {
+        if ret != 0 {
             return TestResult::Failed(format!("sys_channel_create returned {ret:#x}"));
         }
         if send_id == 0 || recv_id == 0 {
@@ -262,7 +306,7 @@ pub fn test_sys_channel_create_returns_endpoints() -> TestResult {
 pub fn test_sys_mmap_returns_valid_addr() -> TestResult {
     with_user_context(|| {
         let addr = kernel::syscall_handlers::sys_mmap(4096, MMAP_WRITE, 0, 0, 0, 0);
-        if addr == 0 {
+        if SysError::is_error(addr) {
             return TestResult::Failed("sys_mmap returned 0".into());
         }
         if addr % 4096 != 0 {
@@ -285,7 +329,7 @@ pub fn test_sys_mmap_returns_valid_addr() -> TestResult {
 pub fn test_sys_mmap_write_and_read() -> TestResult {
     with_user_context(|| {
         let addr = kernel::syscall_handlers::sys_mmap(4096, MMAP_WRITE, 0, 0, 0, 0);
-        if addr == 0 {
+        if SysError::is_error(addr) {
             return TestResult::Failed("sys_mmap returned 0".into());
         }
 
@@ -308,6 +352,233 @@ pub fn test_sys_mmap_write_and_read() -> TestResult {
     })
 }
 
+/// `MMAP_HUGE` backs the first 2 MiB of a 2 MiB + 8 KiB mapping with one 2 MiB
+/// page and the rest with 4 KiB pages. Unmapping a single page out of the
+/// middle splits the 2 MiB page without losing its contents, and unmapping the
+/// rest returns the whole 2 MiB frame.
+pub fn test_sys_mmap_huge_page() -> TestResult {
+    use kernel::memory::demand_paging::user_mapper;
+    use kernel::memory::physical_memory::MemoryType;
+    use kernel::memory::MEMORY;
+    use kernel::reexports::x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+    use kernel::reexports::x86_64::structures::paging::Translate;
+    use kernel::reexports::x86_64::VirtAddr;
+    use kernel::syscall_handlers::{sys_mmap, sys_munmap};
+    use kernel_api_types::MMAP_HUGE;
+
+    const HUGE: u64 = 2 * 1024 * 1024;
+    const SIZE: u64 = HUGE + 2 * 4096;
+
+    with_user_context(|| {
+        let addr = sys_mmap(SIZE, MMAP_WRITE | MMAP_HUGE, 0, 0, 0, 0);
+        if SysError::is_error(addr) {
+            return TestResult::Failed(format!("sys_mmap(MMAP_HUGE) returned {addr:#x}"));
+        }
+        if addr % HUGE != 0 {
+            return TestResult::Failed(format!("{addr:#x} is not 2 MiB aligned"));
+        }
+
+        let cr3 = get_local().run_queue.get().unwrap().lock().current_task.as_ref().unwrap().cr3;
+        let mapper = user_mapper(cr3);
+        let translate = |vaddr: u64| mapper.translate(VirtAddr::new(vaddr));
+        let huge_frame = match translate(addr) {
+            TranslateResult::Mapped { frame: MappedFrame::Size2MiB(frame), .. } => frame,
+            other => return TestResult::Failed(format!("start not mapped by a 2 MiB page: {other:?}")),
+        };
+        if !matches!(translate(addr + HUGE), TranslateResult::Mapped { frame: MappedFrame::Size4KiB(_), .. }) {
+            return TestResult::Failed("tail past 2 MiB not mapped with 4 KiB pages".into());
+        }
+
+        let last = addr + HUGE - 8;
+        unsafe {
+            core::ptr::write_volatile(addr as *mut u64, 0x1111);
+            core::ptr::write_volatile(last as *mut u64, 0x2222);
+        }
+
+        let middle = addr + HUGE / 2;
+        if sys_munmap(middle, 4096, 0, 0, 0, 0) != 0 {
+            return TestResult::Failed("sys_munmap of one page inside the 2 MiB page failed".into());
+        }
+        if !matches!(translate(addr), TranslateResult::Mapped { frame: MappedFrame::Size4KiB(_), .. })
+            || !matches!(translate(middle), TranslateResult::NotMapped)
+        {
+            return TestResult::Failed("2 MiB page was not split around the unmapped page".into());
+        }
+        let (first, end) = unsafe {
+            (core::ptr::read_volatile(addr as *const u64), core::ptr::read_volatile(last as *const u64))
+        };
+        if (first, end) != (0x1111, 0x2222) {
+            return TestResult::Failed(format!("contents lost by the split: {first:#x}, {end:#x}"));
+        }
+
+        if sys_munmap(addr, middle - addr, 0, 0, 0, 0) != 0
+            || sys_munmap(middle + 4096, addr + SIZE - middle - 4096, 0, 0, 0, 0) != 0
+        {
+            return TestResult::Failed("sys_munmap of the remainder failed".into());
+        }
+
+        let start = huge_frame.start_address().as_u64();
+        let mut pm = MEMORY.get().unwrap().physical_memory.lock();
+        let freed = pm.map_mut().iter().any(|(interval, t)| {
+            *interval.start() <= start && start + HUGE - 1 <= *interval.end() && *t == MemoryType::Usable
+        });
+        if !freed {
+            return TestResult::Failed(format!("2 MiB frame at {start:#x} not returned"));
+        }
+        TestResult::Ok
+    })
+}
+
+/// An `MMAP_LAZY` range has no resident pages until sys_populate maps them all.
+pub fn test_sys_populate_lazy_mmap() -> TestResult {
+    use kernel::syscall_handlers::{sys_mincore, sys_mmap, sys_munmap, sys_populate};
+    use kernel_api_types::MMAP_LAZY;
+
+    const PAGES: u64 = 4;
+    with_user_context(|| {
+        let size = PAGES * 4096;
+        let addr = sys_mmap(size, MMAP_WRITE | MMAP_LAZY, 0, 0, 0, 0);
+        if SysError::is_error(addr) {
+            return TestResult::Failed(format!("sys_mmap(MMAP_LAZY) returned {addr:#x}"));
+        }
+        let vec = sys_mmap(4096, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(vec) {
+            return TestResult::Failed("sys_mmap for mincore vector failed".into());
+        }
+        let resident = || unsafe { core::slice::from_raw_parts(vec as *const u8, PAGES as usize) };
+
+        let before_ret = sys_mincore(addr, size, vec, 0, 0, 0);
+        let before_ok = before_ret == 0 && resident().iter().all(|&r| r == 0);
+        let populate_ret = sys_populate(addr, size, 0, 0, 0, 0);
+        let after_ret = sys_mincore(addr, size, vec, 0, 0, 0);
+        let after_ok = after_ret == 0 && resident().iter().all(|&r| r == 1);
+        // Populated pages are zeroed like eagerly mapped ones.
+        let zeroed = after_ok && unsafe { core::ptr::read((addr + 4096) as *const u64) } == 0;
+
+        sys_munmap(addr, size, 0, 0, 0, 0);
+        sys_munmap(vec, 4096, 0, 0, 0, 0);
+
+        if !before_ok {
+            return TestResult::Failed(format!("lazy range resident before populate (mincore {before_ret:#x})"));
+        }
+        if populate_ret != 0 {
+            return TestResult::Failed(format!("sys_populate returned {populate_ret:#x}"));
+        }
+        if !after_ok || !zeroed {
+            return TestResult::Failed(format!("pages not resident and zeroed after populate (mincore {after_ret:#x})"));
+        }
+        TestResult::Ok
+    })
+}
+
+/// sys_populate rejects misaligned addresses and ranges outside any allocation.
+pub fn test_sys_populate_unreserved_range() -> TestResult {
+    use kernel::syscall_handlers::sys_populate;
+
+    with_user_context(|| {
+        if let TestResult::Failed(msg) = expect_err(sys_populate(0x1001, 4096, 0, 0, 0, 0), SysError::InvalidArgs) {
+            return TestResult::Failed(format!("misaligned: {msg}"));
+        }
+        expect_err(sys_populate(0x1000, 4096, 0, 0, 0, 0), SysError::InvalidArgs)
+    })
+}
+
+/// sys_debug_log_str logs valid, non-UTF-8 and over-long strings from user memory.
+pub fn test_sys_debug_log_str_from_user() -> TestResult {
+    use kernel::syscall_handlers::sys_debug_log_str;
+
+    with_user_context(|| {
+        let buf = kernel::syscall_handlers::sys_mmap(4096, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(buf) {
+            return TestResult::Failed("sys_mmap for string buffer failed".into());
+        }
+        let bytes = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, 4096) };
+        bytes.fill(b'x');
+        bytes[..5].copy_from_slice(b"hello");
+        bytes[6] = 0xFF;
+
+        let utf8 = sys_debug_log_str(buf, 5, 0, 0, 0, 0);
+        let not_utf8 = sys_debug_log_str(buf, 8, 0, 0, 0, 0);
+        // Far past the limit, and past the end of the mapping: only the
+        // first MAX_DEBUG_LOG_STR_LEN bytes may be touched.
+        let long = sys_debug_log_str(buf, 1 << 20, 0, 0, 0, 0);
+
+        if utf8 != 0 || not_utf8 != 0 || long != 0 {
+            return TestResult::Failed(format!(
+                "expected 0s, got utf8={utf8:#x} not_utf8={not_utf8:#x} long={long:#x}"
+            ));
+        }
+        TestResult::Ok
+    })
+}
+
+/// sys_random fills a user buffer with bytes that change from call to call,
+/// leaves the bytes after it alone, and rejects a kernel pointer.
+pub fn test_sys_random_fills_buffer() -> TestResult {
+    use kernel::syscall_handlers::sys_random;
+
+    with_user_context(|| {
+        let buf = kernel::syscall_handlers::sys_mmap(4096, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(buf) {
+            return TestResult::Failed("sys_mmap for random buffer failed".into());
+        }
+        let bytes = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, 4096) };
+        bytes.fill(0);
+
+        // An odd length, so the last word is cut short.
+        let (first, second) = (sys_random(buf, 61, 0, 0, 0, 0), sys_random(buf + 64, 61, 0, 0, 0, 0));
+        if first != 0 || second != 0 {
+            return TestResult::Failed(format!("expected 0s, got {first:#x} and {second:#x}"));
+        }
+        if bytes[..61] == bytes[64..125] {
+            return TestResult::Failed("two fills were identical".into());
+        }
+        if bytes[61..64].iter().chain(&bytes[125..]).any(|&b| b != 0) {
+            return TestResult::Failed("sys_random wrote past the end of the buffer".into());
+        }
+        let ones: u32 = bytes[..61].iter().map(|b| b.count_ones()).sum();
+        if !(150..=338).contains(&ones) {
+            return TestResult::Failed(format!("{ones} of 488 bits set"));
+        }
+
+        let kernel_ptr = &ones as *const u32 as u64;
+        let bad = sys_random(kernel_ptr, 4, 0, 0, 0, 0);
+        if bad != SysError::InvalidArgs as u64 {
+            return TestResult::Failed(format!("kernel pointer accepted: {bad:#x}"));
+        }
+        TestResult::Ok
+    })
+}
+
+/// sys_spawn rejects an image pointer into kernel memory, a length past the
+/// cap or past the end of the mapping, and a range that wraps, all with
+/// `SysError::InvalidArgs` and without reading the bytes.
+pub fn test_sys_spawn_rejects_bad_image_buffer() -> TestResult {
+    use kernel::syscall_handlers::sys_spawn;
+    use kernel_api_types::task::MAX_SPAWN_IMAGE_SIZE;
+
+    with_user_context(|| {
+        let buf = kernel::syscall_handlers::sys_mmap(4096, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(buf) {
+            return TestResult::Failed("sys_mmap for image buffer failed".into());
+        }
+        // A real ELF, but at its kernel (HHDM) address.
+        let kernel_elf = get_init_task_elf();
+        let cases = [
+            ("kernel pointer", kernel_elf.as_ptr() as u64, kernel_elf.len() as u64),
+            ("over the cap", buf, MAX_SPAWN_IMAGE_SIZE + 1),
+            ("past the mapping", buf, 1 << 20),
+            ("wrapping range", u64::MAX - 0xfff, 0x2000),
+        ];
+        for (what, ptr, len) in cases {
+            if let TestResult::Failed(msg) = expect_err(sys_spawn(ptr, len, 0, 0, 0, 0), SysError::InvalidArgs) {
+                return TestResult::Failed(format!("{what}: {msg}"));
+            }
+        }
+        TestResult::Ok
+    })
+}
+
 /// sys_channel_send (empty message) + sys_channel_recv roundtrip via the
 /// syscall layer.  An empty send bypasses the message-buffer pointer check
 /// while still exercising the IPC path end-to-end.
@@ -315,11 +586,11 @@ pub fn test_sys_channel_send_recv_roundtrip() -> TestResult {
     with_user_context(|| {
         // Allocate user memory for recv buffer and bytes_read output
         let recv_buf = kernel::syscall_handlers::sys_mmap(64, MMAP_WRITE, 0, 0, 0, 0);
-        if recv_buf == 0 {
+        if SysError::is_error(recv_buf) {
             return TestResult::Failed("sys_mmap for recv_buf failed".into());
         }
         let bytes_out = kernel::syscall_handlers::sys_mmap(8, MMAP_WRITE, 0, 0, 0, 0);
-        if bytes_out == 0 {
+        if SysError::is_error(bytes_out) {
             return TestResult::Failed("sys_mmap for bytes_out failed".into());
         }
 
@@ -329,7 +600,7 @@ pub fn test_sys_channel_send_recv_roundtrip() -> TestResult {
         // Send an empty message (msg_len=0 skips the message-buffer pointer check)
         let send_ret =
             kernel::syscall_handlers::sys_channel_send(send_id, 0, 0, 0, 0, 0);
-        if send_ret != IPC_OK {
+        if send_ret != 0 {
             let _ = ipc::close_endpoint(send_id);
             let _ = ipc::close_endpoint(recv_id);
             return TestResult::Failed(format!("sys_channel_send returned {send_ret:#x}"));
@@ -344,7 +615,7 @@ pub fn test_sys_channel_send_recv_roundtrip() -> TestResult {
         let _ = ipc::close_endpoint(send_id);
         let _ = ipc::close_endpoint(recv_id);
 
-        if recv_ret != IPC_OK {
+        if recv_ret != 0 {
             return TestResult::Failed(format!("sys_channel_recv returned {recv_ret:#x}"));
         }
         if bytes_read != 0 {
@@ -356,20 +627,211 @@ pub fn test_sys_channel_send_recv_roundtrip() -> TestResult {
     })
 }
 
+/// A message longer than the buffer is truncated: the bytes that fit are
+/// copied and the full length returned. It is consumed, unless the receiver
+/// asked with `RECV_KEEP_TRUNCATED`, in which case it stays queued whole.
+pub fn test_sys_channel_recv_truncates() -> TestResult {
+    use kernel::syscall_handlers::sys_channel_recv;
+    use kernel_api_types::RECV_KEEP_TRUNCATED;
+
+    with_user_context(|| {
+        let recv_buf = kernel::syscall_handlers::sys_mmap(64, MMAP_WRITE, 0, 0, 0, 0);
+        let bytes_out = kernel::syscall_handlers::sys_mmap(8, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(recv_buf) || SysError::is_error(bytes_out) {
+            return TestResult::Failed("sys_mmap for the recv buffers failed".into());
+        }
+        let msg: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let (send_id, recv_id) = ipc::create_channel(4);
+        let bytes_read = || unsafe { core::ptr::read(bytes_out as *const u64) };
+        let head = || unsafe { core::slice::from_raw_parts(recv_buf as *const u8, 4) == &msg[..4] };
+
+        let _ = ipc::try_send(send_id, &msg);
+        let dropped = (sys_channel_recv(recv_id, recv_buf, 4, bytes_out, 0, 0), bytes_read(), head());
+        let left_after_drop = ipc::poll(recv_id);
+
+        let _ = ipc::try_send(send_id, &msg);
+        let kept = (
+            sys_channel_recv(recv_id, recv_buf, 4, bytes_out, RECV_KEEP_TRUNCATED, 0),
+            bytes_read(),
+            head(),
+        );
+        let left_after_keep = ipc::poll(recv_id);
+        let whole = (sys_channel_recv(recv_id, recv_buf, 64, bytes_out, 0, 0), bytes_read());
+        let bad_flags = sys_channel_recv(recv_id, recv_buf, 64, bytes_out, 1 << 7, 0);
+
+        let _ = ipc::close_endpoint(send_id);
+        let _ = ipc::close_endpoint(recv_id);
+
+        if dropped != (16, 4, true) || left_after_drop != Ok(0) {
+            return TestResult::Failed(format!(
+                "Expected (len 16, 4 bytes read, head copied) and the message consumed, got {:?}, {:?} left",
+                dropped, left_after_drop
+            ));
+        }
+        if kept != (16, 4, true) || left_after_keep != Ok(1) {
+            return TestResult::Failed(format!(
+                "With RECV_KEEP_TRUNCATED expected (16, 4, true) and the message kept, got {:?}, {:?} left",
+                kept, left_after_keep
+            ));
+        }
+        if whole != (16, 16) {
+            return TestResult::Failed(format!("Expected the kept message whole on retry, got {:?}", whole));
+        }
+        if bad_flags != SysError::InvalidArgs as u64 {
+            return TestResult::Failed(format!("Expected InvalidArgs for unknown flags, got {bad_flags:#x}"));
+        }
+        TestResult::Ok
+    })
+}
+
+/// An endpoint sent with a message moves from the sender's owned endpoints
+/// to the receiver's and still works at the other end. A plain recv closes
+/// it instead, and the sender can't pass an endpoint it doesn't own.
+pub fn test_sys_channel_pass_endpoint_roundtrip() -> TestResult {
+    use kernel::syscall_handlers::{sys_channel_recv, sys_channel_recv_with_endpoint, sys_channel_send_with_endpoint};
+    use kernel_api_types::RecvInfo;
+
+    with_user_context(|| {
+        let send_buf = kernel::syscall_handlers::sys_mmap(64, MMAP_WRITE, 0, 0, 0, 0);
+        let recv_buf = kernel::syscall_handlers::sys_mmap(64, MMAP_WRITE, 0, 0, 0, 0);
+        let info_out = kernel::syscall_handlers::sys_mmap(core::mem::size_of::<RecvInfo>() as u64, MMAP_WRITE, 0, 0, 0, 0);
+        if [send_buf, recv_buf, info_out].into_iter().any(SysError::is_error) {
+            return TestResult::Failed("sys_mmap for the message buffers failed".into());
+        }
+        unsafe { core::ptr::copy_nonoverlapping(b"req".as_ptr(), send_buf as *mut u8, 3) };
+        let task = get_local().run_queue.get().unwrap().lock().current_task.clone().unwrap();
+        let owns = |ep: u64| task.inner.lock().owned_endpoints.contains(&ep);
+        let (req_send, req_recv) = ipc::create_channel(4);
+        let (reply_send, reply_recv) = ipc::create_channel(4);
+        task.inner.lock().owned_endpoints.push(reply_send);
+        let close_all = || {
+            for ep in [req_send, req_recv, reply_send, reply_recv] {
+                let _ = ipc::close_endpoint(ep);
+            }
+        };
+
+        let not_owned = sys_channel_send_with_endpoint(req_send, 0, 0, reply_recv, 0, 0);
+        let sent = sys_channel_send_with_endpoint(req_send, send_buf, 3, reply_send, 0, 0);
+        let owned_in_flight = owns(reply_send);
+        let received = sys_channel_recv_with_endpoint(req_recv, recv_buf, 64, info_out, 0, 0);
+        let info = unsafe { core::ptr::read(info_out as *const RecvInfo) };
+        let owned_after = owns(reply_send);
+        let reply_ok = ipc::try_send(info.endpoint, b"ok").is_ok()
+            && ipc::try_recv(reply_recv).is_ok_and(|m| m.as_slice() == b"ok");
+
+        if not_owned != SysError::InvalidEndpoint as u64 {
+            close_all();
+            return TestResult::Failed(format!("Expected InvalidEndpoint passing an unowned endpoint, got {not_owned:#x}"));
+        }
+        if sent != 0 || owned_in_flight {
+            close_all();
+            return TestResult::Failed(format!("Send returned {sent:#x}; sender still owns the endpoint: {owned_in_flight}"));
+        }
+        if received != 3 || info != (RecvInfo { bytes_read: 3, endpoint: reply_send }) || !owned_after {
+            close_all();
+            return TestResult::Failed(format!(
+                "Expected 3 bytes and endpoint {reply_send} now owned, got {received:#x}, {info:?}, owned: {owned_after}"
+            ));
+        }
+        if !reply_ok {
+            close_all();
+            return TestResult::Failed("Reply on the passed endpoint didn't arrive".into());
+        }
+
+        // Received without asking for it, the endpoint is closed.
+        let resent = sys_channel_send_with_endpoint(req_send, 0, 0, reply_send, 0, 0);
+        let plain = sys_channel_recv(req_recv, recv_buf, 64, info_out, 0, 0);
+        let reply_side = ipc::poll(reply_recv);
+        close_all();
+        if resent != 0 || plain != 0 || reply_side != Err(ipc::IpcError::PeerClosed) {
+            return TestResult::Failed(format!(
+                "Expected plain recv to close the passed endpoint, got send {resent:#x}, recv {plain:#x}, peer {reply_side:?}"
+            ));
+        }
+        TestResult::Ok
+    })
+}
+
+/// A channel is granted at most the capacity its creator's privilege allows:
+/// a user task asking for more than `MAX_USER_CHANNEL_CAPACITY` is clamped
+/// to it, while a system task gets what it asked for.
+pub fn test_sys_channel_create_capacity_by_privilege() -> TestResult {
+    let asked = ipc::MAX_USER_CHANNEL_CAPACITY * 4;
+    let granted = |privilege| {
+        let mut capacity = None;
+        let result = with_user_context_as(privilege, || {
+            let eps = kernel::syscall_handlers::sys_mmap(16, MMAP_WRITE, 0, 0, 0, 0);
+            if SysError::is_error(eps) {
+                return TestResult::Failed("sys_mmap for the endpoint IDs failed".into());
+            }
+            let ret = kernel::syscall_handlers::sys_channel_create(eps, eps + 8, asked as u64, 0, 0, 0);
+            if ret != 0 {
+                return TestResult::Failed(format!("sys_channel_create returned {ret:#x}"));
+            }
+            let (send_id, recv_id) = unsafe { (core::ptr::read(eps as *const u64), core::ptr::read((eps + 8) as *const u64)) };
+            capacity = ipc::channel_stats(send_id).ok().map(|(_, cap)| cap);
+            let _ = ipc::close_endpoint(send_id);
+            let _ = ipc::close_endpoint(recv_id);
+            TestResult::Ok
+        });
+        (result, capacity)
+    };
+
+    match granted(Privilege::User) {
+        (TestResult::Ok, Some(cap)) if cap == ipc::MAX_USER_CHANNEL_CAPACITY => {}
+        (TestResult::Failed(msg), _) => return TestResult::Failed(format!("user task: {msg}")),
+        (_, cap) => {
+            return TestResult::Failed(format!(
+                "Expected a user task asking for {asked} to get {}, got {cap:?}",
+                ipc::MAX_USER_CHANNEL_CAPACITY
+            ));
+        }
+    }
+    match granted(Privilege::System) {
+        (TestResult::Ok, Some(cap)) if cap == asked => TestResult::Ok,
+        (TestResult::Failed(msg), _) => TestResult::Failed(format!("system task: {msg}")),
+        (_, cap) => TestResult::Failed(format!("Expected a system task to get {asked}, got {cap:?}")),
+    }
+}
+
+/// Only a system task may spawn another with `SPAWN_SYSTEM`.
+pub fn test_sys_spawn_system_needs_privilege() -> TestResult {
+    use kernel::syscall_handlers::sys_spawn;
+    use kernel_api_types::task::SPAWN_SYSTEM;
+
+    with_user_context(|| {
+        let elf = get_init_task_elf();
+        let buf = kernel::syscall_handlers::sys_mmap(elf.len() as u64, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(buf) {
+            return TestResult::Failed("sys_mmap for the ELF image failed".into());
+        }
+        unsafe { core::ptr::copy_nonoverlapping(elf.as_ptr(), buf as *mut u8, elf.len()) };
+        if let TestResult::Failed(msg) =
+            expect_err(sys_spawn(buf, elf.len() as u64, 0, SPAWN_SYSTEM, 0, 0), SysError::PermissionDenied)
+        {
+            return TestResult::Failed(format!("SPAWN_SYSTEM from a user task: {msg}"));
+        }
+        if let TestResult::Failed(msg) = expect_err(sys_spawn(buf, elf.len() as u64, 0, 1 << 9, 0, 0), SysError::InvalidArgs) {
+            return TestResult::Failed(format!("unknown spawn flag: {msg}"));
+        }
+        TestResult::Ok
+    })
+}
+
 /// sys_get_display_info writes valid (non-zero) display dimensions into a user buffer.
 pub fn test_sys_get_display_info_success() -> TestResult {
     with_user_context(|| {
         let info_size =
             core::mem::size_of::<kernel_api_types::graphics::DisplayInfo>() as u64;
         let info_buf = kernel::syscall_handlers::sys_mmap(info_size, MMAP_WRITE, 0, 0, 0, 0);
-        if info_buf == 0 {
+        if SysError::is_error(info_buf) {
             return TestResult::Failed("sys_mmap for DisplayInfo buffer failed".into());
         }
 
         let ret =
             kernel::syscall_handlers::sys_get_display_info(info_buf, 0, 0, 0, 0, 0);
 
-        if ret != GraphicsResult::Ok as u64 {
+        if ret != 0 {
             return TestResult::Failed(format!("sys_get_display_info returned {ret:#x}"));
         }
 
@@ -386,3 +848,460 @@ pub fn test_sys_get_display_info_success() -> TestResult {
         TestResult::Ok
     })
 }
+
+/// Once the user address space is exhausted, sys_mmap fails with `OutOfMemory` without
+/// panicking, including for sizes larger than the whole user half, and a
+/// sys_munmap makes room for new allocations again.
+///
+/// The address space is filled by reserving ranges directly in the task's
+/// vaddr set (largest first, halving down to one page) so the test doesn't
+/// need terabytes of physical memory to get there.
+pub fn test_sys_mmap_vaddr_exhaustion() -> TestResult {
+    use kernel::memory::user_vaddr;
+    use kernel::syscall_handlers::{sys_mmap, sys_munmap};
+
+    with_user_context(|| {
+        if let TestResult::Failed(msg) =
+            expect_err(sys_mmap(u64::MAX, MMAP_WRITE, 0, 0, 0, 0), SysError::OutOfMemory)
+        {
+            return TestResult::Failed(format!("sys_mmap(u64::MAX): {msg}"));
+        }
+
+        let task = get_local().run_queue.get().unwrap().lock().current_task.clone().unwrap();
+        let mut last_page = None;
+        {
+            let mut inner = task.inner.lock();
+            let mut chunk = 1u64 << 40;
+            while chunk > 0 {
+                match user_vaddr::allocate_user_pages(&mut inner.user_vaddr_set, chunk) {
+                    Some(vaddr) if chunk == 1 => last_page = Some(vaddr),
+                    Some(_) => {}
+                    None => chunk /= 2,
+                }
+            }
+        }
+        let Some(last_page) = last_page else {
+            return TestResult::Failed("could not reserve any single pages".into());
+        };
+
+        let exhausted = sys_mmap(4096, MMAP_WRITE, 0, 0, 0, 0);
+        if exhausted != SysError::OutOfMemory as u64 {
+            return TestResult::Failed(format!(
+                "expected OutOfMemory with no address space left, got {exhausted:#x}"
+            ));
+        }
+
+        if sys_munmap(last_page, 4096, 0, 0, 0, 0) != 0 {
+            return TestResult::Failed(format!("sys_munmap({last_page:#x}) failed"));
+        }
+
+        let vaddr = sys_mmap(4096, MMAP_WRITE, 0, 0, 0, 0);
+        if vaddr != last_page {
+            return TestResult::Failed(format!(
+                "expected sys_mmap to reuse {last_page:#x} after munmap, got {vaddr:#x}"
+            ));
+        }
+        unsafe { core::ptr::write_volatile(vaddr as *mut u64, 1) };
+        TestResult::Ok
+    })
+}
+
+/// Look `vaddr` up in the page table rooted at `cr3`; returns the leaf flags
+/// and the first word of the page, read through the HHDM.
+fn inspect(cr3: u64, vaddr: u64) -> Option<(PageTableFlags, u64)> {
+    use x86_64::structures::paging::mapper::TranslateResult;
+    use x86_64::structures::paging::{OffsetPageTable, PageTable, Translate};
+    use x86_64::VirtAddr;
+
+    let hhdm = kernel::memory::hhdm_offset::hhdm_offset().as_u64();
+    let l4 = unsafe { &mut *((hhdm + cr3) as *mut PageTable) };
+    let mapper = unsafe { OffsetPageTable::new(l4, VirtAddr::new(hhdm)) };
+    match mapper.translate(VirtAddr::new(vaddr)) {
+        TranslateResult::Mapped { frame, offset, flags } => {
+            let phys = frame.start_address().as_u64() + offset;
+            Some((flags, unsafe { core::ptr::read((hhdm + phys) as *const u64) }))
+        }
+        _ => None,
+    }
+}
+
+/// sys_resize_shared_buf grows a buffer keeping its contents, then shrinks it back.
+pub fn test_sys_resize_shared_buf_preserves_data() -> TestResult {
+    use kernel::syscall_handlers::{
+        sys_create_shared_buf, sys_destroy_shared_buf, sys_resize_shared_buf,
+    };
+
+    with_user_context(|| {
+        let vaddr_out = kernel::syscall_handlers::sys_mmap(8, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(vaddr_out) {
+            return TestResult::Failed("sys_mmap for vaddr_out failed".into());
+        }
+
+        let id = sys_create_shared_buf(4096, vaddr_out, 0, 0, 0, 0);
+        if SysError::is_error(id) {
+            return TestResult::Failed("sys_create_shared_buf failed".into());
+        }
+        let vaddr = unsafe { core::ptr::read(vaddr_out as *const u64) };
+
+        const PATTERN: u64 = 0x5EED_CAFE_F00D_BEEF;
+        unsafe { core::ptr::write(vaddr as *mut u64, PATTERN) };
+
+        let grown = sys_resize_shared_buf(id, 3 * 4096, 0, 0, 0, 0);
+        if SysError::is_error(grown) {
+            sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+            return TestResult::Failed("growing to 3 pages failed".into());
+        }
+        let readback = unsafe { core::ptr::read(grown as *const u64) };
+        // The new pages must be mapped, writable and zeroed.
+        let tail = (grown + 2 * 4096) as *mut u64;
+        let tail_initial = unsafe { core::ptr::read(tail) };
+        unsafe { core::ptr::write(tail, !PATTERN) };
+
+        let shrunk = sys_resize_shared_buf(id, 4096, 0, 0, 0, 0);
+        let after_shrink = if !SysError::is_error(shrunk) {
+            unsafe { core::ptr::read(shrunk as *const u64) }
+        } else {
+            0
+        };
+
+        sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+
+        if readback != PATTERN {
+            return TestResult::Failed(format!(
+                "contents lost on grow: wrote {PATTERN:#x}, read {readback:#x}"
+            ));
+        }
+        if tail_initial != 0 {
+            return TestResult::Failed(format!("new page not zeroed: {tail_initial:#x}"));
+        }
+        if SysError::is_error(shrunk) || after_shrink != PATTERN {
+            return TestResult::Failed(format!(
+                "shrink failed or lost data: vaddr={shrunk:#x}, read {after_shrink:#x}"
+            ));
+        }
+        TestResult::Ok
+    })
+}
+
+/// When growing a buffer moves another task's mapping, its old range is left
+/// reserved but unmapped until the task asks for the new address again.
+pub fn test_sys_resize_shared_buf_guards_moved_range() -> TestResult {
+    use kernel::memory::user_vaddr::{free_user_pages, is_range_reserved, reserve_user_pages_at};
+    use kernel::shared_buf::map_shared_buf;
+    use kernel::syscall_handlers::{
+        sys_create_shared_buf, sys_destroy_shared_buf, sys_grant_shared_buf, sys_resize_shared_buf,
+    };
+
+    with_user_context(|| {
+        let vaddr_out = kernel::syscall_handlers::sys_mmap(8, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(vaddr_out) {
+            return TestResult::Failed("sys_mmap for vaddr_out failed".into());
+        }
+        let id = sys_create_shared_buf(4096, vaddr_out, 0, 0, 0, 0);
+        if SysError::is_error(id) {
+            return TestResult::Failed("sys_create_shared_buf failed".into());
+        }
+
+        let reader = match create_user_task_from_elf_bytes(get_init_task_elf(), 0) {
+            Ok(t) => Arc::new(t),
+            Err(e) => {
+                sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+                return TestResult::Failed(format!("failed to create reader task: {:?}", e));
+            }
+        };
+        sys_grant_shared_buf(id, reader.id.to_u64(), 0, 0, 0, 0);
+        let old_vaddr = match map_shared_buf(id, &reader, false) {
+            Ok(v) => v,
+            Err(e) => {
+                sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+                return TestResult::Failed(format!("reader map failed: {e:?}"));
+            }
+        };
+
+        // Take the page after the reader's mapping so growing has to move it.
+        let blocker = old_vaddr + 4096;
+        let blocked = reserve_user_pages_at(&mut reader.inner.lock().user_vaddr_set, blocker, 1);
+
+        let grown = sys_resize_shared_buf(id, 2 * 4096, 0, 0, 0, 0);
+        let old_view = inspect(reader.cr3, old_vaddr);
+        let (guarded, reserved) = {
+            let inner = reader.inner.lock();
+            (
+                inner.overlaps_unmapped_guard(old_vaddr, old_vaddr + 4096),
+                is_range_reserved(&inner.user_vaddr_set, old_vaddr, 4096),
+            )
+        };
+        let new_vaddr = map_shared_buf(id, &reader, false);
+        let (released, unguarded) = {
+            let inner = reader.inner.lock();
+            (
+                !is_range_reserved(&inner.user_vaddr_set, old_vaddr, 4096),
+                !inner.overlaps_unmapped_guard(old_vaddr, old_vaddr + 4096),
+            )
+        };
+
+        sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+        if blocked {
+            free_user_pages(&mut reader.inner.lock().user_vaddr_set, blocker, 4096);
+        }
+
+        if SysError::is_error(grown) {
+            return TestResult::Failed(format!("growing returned {grown:#x}"));
+        }
+        if let Some(view) = old_view {
+            return TestResult::Failed(format!("old range still mapped after the move: {view:?}"));
+        }
+        if !reserved || !guarded {
+            return TestResult::Failed(format!(
+                "old range not kept as a guard: reserved={reserved}, guarded={guarded}"
+            ));
+        }
+        match new_vaddr {
+            Ok(v) if v != old_vaddr => {}
+            other => {
+                return TestResult::Failed(format!("re-map should return the moved address, got {other:?}"));
+            }
+        }
+        if !released || !unguarded {
+            return TestResult::Failed(format!(
+                "re-map kept the old range: released={released}, unguarded={unguarded}"
+            ));
+        }
+        TestResult::Ok
+    })
+}
+
+/// A second task mapping a buffer with SHBUF_READONLY sees the creator's data
+/// through a page that is not writable, while the creator's mapping stays writable.
+///
+/// The write fault itself isn't exercised: a user page fault still panics the
+/// kernel, so the test checks the page-table flags instead.
+pub fn test_sys_map_shared_buf_readonly() -> TestResult {
+    use kernel::syscall_handlers::{
+        sys_create_shared_buf, sys_destroy_shared_buf, sys_grant_shared_buf,
+    };
+
+    with_user_context(|| {
+        let vaddr_out = kernel::syscall_handlers::sys_mmap(8, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(vaddr_out) {
+            return TestResult::Failed("sys_mmap for vaddr_out failed".into());
+        }
+        let id = sys_create_shared_buf(4096, vaddr_out, 0, 0, 0, 0);
+        if SysError::is_error(id) {
+            return TestResult::Failed("sys_create_shared_buf failed".into());
+        }
+        let owner_vaddr = unsafe { core::ptr::read(vaddr_out as *const u64) };
+
+        const PATTERN: u64 = 0x0BAD_F00D_1234_5678;
+        unsafe { core::ptr::write(owner_vaddr as *mut u64, PATTERN) };
+
+        let reader = match create_user_task_from_elf_bytes(get_init_task_elf(), 0) {
+            Ok(t) => Arc::new(t),
+            Err(e) => {
+                sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+                return TestResult::Failed(format!("failed to create reader task: {:?}", e));
+            }
+        };
+        sys_grant_shared_buf(id, reader.id.to_u64(), 0, 0, 0, 0);
+        let reader_vaddr = kernel::shared_buf::map_shared_buf(id, &reader, true);
+
+        let owner = get_local().run_queue.get().unwrap().lock().current_task.clone().unwrap();
+        let owner_view = inspect(owner.cr3, owner_vaddr);
+        let reader_view = reader_vaddr.ok().and_then(|v| inspect(reader.cr3, v));
+
+        sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+
+        let Some((reader_flags, reader_data)) = reader_view else {
+            return TestResult::Failed("read-only map_shared_buf failed".into());
+        };
+        if reader_flags.contains(PageTableFlags::WRITABLE) {
+            return TestResult::Failed(format!("read-only mapping is writable: {reader_flags:?}"));
+        }
+        if !reader_flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+            return TestResult::Failed(format!("read-only mapping not user-accessible: {reader_flags:?}"));
+        }
+        if !owner_view.is_some_and(|(f, _)| f.contains(PageTableFlags::WRITABLE)) {
+            return TestResult::Failed(format!("creator lost write access: {owner_view:?}"));
+        }
+        if reader_data != PATTERN {
+            return TestResult::Failed(format!(
+                "reader saw {reader_data:#x}, expected {PATTERN:#x}"
+            ));
+        }
+        TestResult::Ok
+    })
+}
+
+/// A task that hasn't been granted access can't map a buffer; only the owner
+/// can grant, and after a grant the map succeeds.
+pub fn test_sys_grant_shared_buf() -> TestResult {
+    use kernel::shared_buf::{grant_shared_buf, map_shared_buf};
+    use kernel::syscall_handlers::{
+        sys_create_shared_buf, sys_destroy_shared_buf, sys_grant_shared_buf,
+    };
+
+    with_user_context(|| {
+        let vaddr_out = kernel::syscall_handlers::sys_mmap(8, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(vaddr_out) {
+            return TestResult::Failed("sys_mmap for vaddr_out failed".into());
+        }
+        let id = sys_create_shared_buf(4096, vaddr_out, 0, 0, 0, 0);
+        if SysError::is_error(id) {
+            return TestResult::Failed("sys_create_shared_buf failed".into());
+        }
+        let other = match create_user_task_from_elf_bytes(get_init_task_elf(), 0) {
+            Ok(t) => Arc::new(t),
+            Err(e) => {
+                sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+                return TestResult::Failed(format!("failed to create second task: {:?}", e));
+            }
+        };
+
+        let before_grant = map_shared_buf(id, &other, false);
+        let self_grant = grant_shared_buf(id, &other, other.id);
+        let grant = sys_grant_shared_buf(id, other.id.to_u64(), 0, 0, 0, 0);
+        let after_grant = map_shared_buf(id, &other, false);
+        let unknown = sys_grant_shared_buf(u64::MAX - 1, other.id.to_u64(), 0, 0, 0, 0);
+
+        sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+
+        if before_grant != Err(SysError::PermissionDenied) {
+            return TestResult::Failed(format!("ungranted map: expected PermissionDenied, got {before_grant:?}"));
+        }
+        if self_grant != Err(SysError::PermissionDenied) {
+            return TestResult::Failed(format!("non-owner grant: expected PermissionDenied, got {self_grant:?}"));
+        }
+        if grant != 0 {
+            return TestResult::Failed(format!("owner grant returned {grant:#x}"));
+        }
+        if after_grant.is_err() {
+            return TestResult::Failed(format!("map failed after grant: {after_grant:?}"));
+        }
+        if let TestResult::Failed(msg) = expect_err(unknown, SysError::NotFound) {
+            return TestResult::Failed(format!("grant on unknown id: {msg}"));
+        }
+        TestResult::Ok
+    })
+}
+
+/// Only the owner can destroy a shared buffer; a second destroy fails.
+pub fn test_sys_destroy_shared_buf_owner_only() -> TestResult {
+    use kernel::syscall_handlers::{sys_create_shared_buf, sys_destroy_shared_buf};
+
+    with_user_context(|| {
+        let vaddr_out = kernel::syscall_handlers::sys_mmap(8, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(vaddr_out) {
+            return TestResult::Failed("sys_mmap for vaddr_out failed".into());
+        }
+        let id = sys_create_shared_buf(4096, vaddr_out, 0, 0, 0, 0);
+        if SysError::is_error(id) {
+            return TestResult::Failed("sys_create_shared_buf failed".into());
+        }
+        let other = match create_user_task_from_elf_bytes(get_init_task_elf(), 0) {
+            Ok(t) => t,
+            Err(e) => {
+                sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+                return TestResult::Failed(format!("failed to create second task: {:?}", e));
+            }
+        };
+
+        let by_other = kernel::shared_buf::destroy_shared_buf(id, &other);
+        let by_owner = sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+        let again = sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+
+        if by_other != Err(SysError::PermissionDenied) {
+            return TestResult::Failed(format!("non-owner destroy: expected PermissionDenied, got {by_other:?}"));
+        }
+        if by_owner != 0 {
+            return TestResult::Failed(format!("owner destroy returned {by_owner:#x}"));
+        }
+        expect_err(again, SysError::NotFound)
+    })
+}
+
+/// Destroying a buffer unmaps it from the owner and every grantee. The ranges
+/// stay reserved as guards that syscalls refuse, until `sys_munmap` frees them.
+pub fn test_sys_destroy_shared_buf_unmaps_all() -> TestResult {
+    use kernel::memory::user_vaddr::is_range_reserved;
+    use kernel::syscall_handlers::{
+        sys_create_shared_buf, sys_destroy_shared_buf, sys_grant_shared_buf, sys_munmap,
+    };
+
+    with_user_context(|| {
+        let vaddr_out = kernel::syscall_handlers::sys_mmap(8, MMAP_WRITE, 0, 0, 0, 0);
+        if SysError::is_error(vaddr_out) {
+            return TestResult::Failed("sys_mmap for vaddr_out failed".into());
+        }
+        let id = sys_create_shared_buf(4096, vaddr_out, 0, 0, 0, 0);
+        if SysError::is_error(id) {
+            return TestResult::Failed("sys_create_shared_buf failed".into());
+        }
+        let owner_vaddr = unsafe { core::ptr::read(vaddr_out as *const u64) };
+
+        let reader = match create_user_task_from_elf_bytes(get_init_task_elf(), 0) {
+            Ok(t) => Arc::new(t),
+            Err(e) => {
+                sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+                return TestResult::Failed(format!("failed to create reader task: {:?}", e));
+            }
+        };
+        sys_grant_shared_buf(id, reader.id.to_u64(), 0, 0, 0, 0);
+        let reader_vaddr = match kernel::shared_buf::map_shared_buf(id, &reader, true) {
+            Ok(v) => v,
+            Err(e) => {
+                sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+                return TestResult::Failed(format!("reader map failed: {e:?}"));
+            }
+        };
+
+        let destroyed = sys_destroy_shared_buf(id, 0, 0, 0, 0, 0);
+        if destroyed != 0 {
+            return TestResult::Failed(format!("destroy returned {destroyed:#x}"));
+        }
+
+        let owner = get_local().run_queue.get().unwrap().lock().current_task.clone().unwrap();
+        for (who, task, vaddr) in [("owner", &owner, owner_vaddr), ("reader", &reader, reader_vaddr)] {
+            if let Some(view) = inspect(task.cr3, vaddr) {
+                return TestResult::Failed(format!("{who} still maps the buffer: {view:?}"));
+            }
+            let inner = task.inner.lock();
+            if !is_range_reserved(&inner.user_vaddr_set, vaddr, 4096) {
+                return TestResult::Failed(format!("{who}'s range was released"));
+            }
+            if !inner.overlaps_unmapped_guard(vaddr, vaddr + 4096) {
+                return TestResult::Failed(format!("{who}'s range is not guarded"));
+            }
+        }
+
+        // A syscall handed a pointer into the guard fails instead of faulting.
+        let into_guard = sys_create_shared_buf(4096, owner_vaddr, 0, 0, 0, 0);
+        if let TestResult::Failed(msg) = expect_err(into_guard, SysError::InvalidArgs) {
+            return TestResult::Failed(format!("pointer into guard: {msg}"));
+        }
+
+        let unmapped = sys_munmap(owner_vaddr, 4096, 0, 0, 0, 0);
+        if unmapped != 0 {
+            return TestResult::Failed(format!("munmap of the guard returned {unmapped:#x}"));
+        }
+        if owner.inner.lock().overlaps_unmapped_guard(owner_vaddr, owner_vaddr + 4096) {
+            return TestResult::Failed("guard left behind after munmap".into());
+        }
+        TestResult::Ok
+    })
+}
+
+/// Unknown flag bits are rejected with `SysError::InvalidArgs`.
+pub fn test_sys_map_shared_buf_unknown_flags() -> TestResult {
+    with_user_context(|| {
+        let ret = kernel::syscall_handlers::sys_map_shared_buf(1, 1 << 7, 0, 0, 0, 0);
+        expect_err(ret, SysError::InvalidArgs)
+    })
+}
+
+/// Resizing an unknown buffer id fails with `SysError::NotFound`.
+pub fn test_sys_resize_shared_buf_unknown_id() -> TestResult {
+    with_user_context(|| {
+        let ret = kernel::syscall_handlers::sys_resize_shared_buf(u64::MAX - 1, 4096, 0, 0, 0, 0);
+        expect_err(ret, SysError::NotFound)
+    })
+}
@@ -1,14 +1,42 @@
-use kernel::time::tsc;
+use kernel::time;
+use kernel::time::{hpet, tsc};
+use kernel::time::rtc::{self, RawRtc};
+use kernel_api_types::DateTime;
 use kernel::time::pit;
 use crate::TestResult;
 use core::sync::atomic::Ordering;
 
 pub fn tsc_calibration() -> TestResult {
     let tsc_hz = tsc::TSC_HZ.load(Ordering::SeqCst);
-    if tsc_hz > 0 {
+    if tsc::PLAUSIBLE_TICKS_PER_MS.contains(&tsc_hz) {
         TestResult::Ok
     } else {
-        TestResult::Failed(alloc::format!("TSC not calibrated: {}", tsc_hz))
+        TestResult::Failed(alloc::format!("TSC calibrated to an implausible {} ticks per ms", tsc_hz))
+    }
+}
+
+pub fn tsc_robust_median() -> TestResult {
+    let mut samples = [3_000_000, 2_990_000, 30_000, 3_010_000, 9_000_000, 3_005_000, 2_995_000];
+    let median = tsc::robust_median(&mut samples);
+    if median != 3_000_000 {
+        return TestResult::Failed(alloc::format!("Expected 3000000, got {}", median));
+    }
+    if tsc::robust_median(&mut []) != 0 {
+        return TestResult::Failed("Median of no samples should be 0".into());
+    }
+    TestResult::Ok
+}
+
+/// A PIT sleep measured on the calibrated TSC takes about as long as asked.
+pub fn pit_sleep_duration() -> TestResult {
+    let ticks_per_ms = tsc::TSC_HZ.load(Ordering::SeqCst);
+    let start = tsc::value();
+    let _ = pit::sleep_qs(20_000);
+    let elapsed_us = (tsc::value() - start) * 1000 / ticks_per_ms.max(1);
+    if (18_000..=30_000).contains(&elapsed_us) {
+        TestResult::Ok
+    } else {
+        TestResult::Failed(alloc::format!("20ms PIT sleep measured as {}us", elapsed_us))
     }
 }
 
@@ -23,3 +51,87 @@ pub fn pit_sleep() -> TestResult {
         TestResult::Failed(alloc::format!("TSC did not advance during PIT sleep: start={}, end={}", start, end))
     }
 }
+
+pub fn hpet_discovered() -> TestResult {
+    match hpet::HPET.get() {
+        Some(h) if h.period_fs() > 0 => TestResult::Ok,
+        Some(h) => TestResult::Failed(alloc::format!("HPET has a zero period: {}", h.period_fs())),
+        None => TestResult::Failed("No HPET found in ACPI tables".into()),
+    }
+}
+
+pub fn hpet_counter_monotonic() -> TestResult {
+    let Some(hpet) = hpet::HPET.get() else {
+        return TestResult::Failed("No HPET found in ACPI tables".into());
+    };
+    let mut last = hpet.counter();
+    for _ in 0..1000 {
+        let now = hpet.counter();
+        if now < last {
+            return TestResult::Failed(alloc::format!("HPET went backwards: {} -> {}", last, now));
+        }
+        last = now;
+    }
+    let start = hpet.counter();
+    let _ = pit::sleep_qs(1000); // 1ms
+    let elapsed_ns = hpet.ticks_to_nanos(hpet.counter() - start);
+    if elapsed_ns > 0 {
+        TestResult::Ok
+    } else {
+        TestResult::Failed("HPET did not advance during a 1ms PIT sleep".into())
+    }
+}
+
+pub fn now_ns_monotonic() -> TestResult {
+    let a = time::now_ns();
+    let _ = pit::sleep_qs(1000); // 1ms
+    let b = time::now_ns();
+    if b > a {
+        TestResult::Ok
+    } else {
+        TestResult::Failed(alloc::format!("now_ns did not advance: {} -> {}", a, b))
+    }
+}
+
+pub fn rtc_year_plausible() -> TestResult {
+    let now = rtc::read_datetime();
+    let valid = now.year > 2020
+        && (1..=12).contains(&now.month)
+        && (1..=31).contains(&now.day)
+        && now.hour < 24
+        && now.minute < 60
+        && now.second < 60;
+    if valid {
+        TestResult::Ok
+    } else {
+        TestResult::Failed(alloc::format!("Implausible RTC time: {:?}", now))
+    }
+}
+
+pub fn rtc_decode_formats() -> TestResult {
+    const HOUR_24: u8 = 1 << 1;
+    const BINARY_MODE: u8 = 1 << 2;
+
+    // 2026-10-16 12:05:09 AM in BCD, 12-hour mode, with a century register.
+    let bcd_12h = RawRtc { second: 0x09, minute: 0x05, hour: 0x12, day: 0x16, month: 0x10, year: 0x26, century: 0x20 };
+    // 1999-12-31 11:59:58 PM in binary, 12-hour mode.
+    let bin_pm = RawRtc { second: 58, minute: 59, hour: 11 | 0x80, day: 31, month: 12, year: 99, century: 19 };
+    // 24-hour BCD without a century register.
+    let bcd_24h = RawRtc { second: 0x00, minute: 0x30, hour: 0x17, day: 0x01, month: 0x02, year: 0x25, century: 0 };
+
+    let got = [
+        rtc::decode(bcd_12h, 0),
+        rtc::decode(bin_pm, BINARY_MODE),
+        rtc::decode(bcd_24h, HOUR_24),
+    ];
+    let want = [
+        DateTime { year: 2026, month: 10, day: 16, hour: 0, minute: 5, second: 9 },
+        DateTime { year: 1999, month: 12, day: 31, hour: 23, minute: 59, second: 58 },
+        DateTime { year: 2025, month: 2, day: 1, hour: 17, minute: 30, second: 0 },
+    ];
+    if got == want {
+        TestResult::Ok
+    } else {
+        TestResult::Failed(alloc::format!("Decoded {:?}", got))
+    }
+}
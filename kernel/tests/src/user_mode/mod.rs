@@ -69,7 +69,10 @@ pub fn test_lower_half_end_canonical() -> TestResult {
 /// Inspect the CpuContext of a freshly created user task.
 /// Verifies the iretq frame values (CS, SS, RSP, RIP) are correct.
 pub fn test_user_task_iretq_frame() -> TestResult {
-    let task = kernel::user_task_from_elf::create_user_task_from_elf();
+    let task = match kernel::user_task_from_elf::create_user_task_from_elf() {
+        Ok(task) => task,
+        Err(e) => return TestResult::Failed(format!("create_user_task_from_elf failed: {:?}", e)),
+    };
     let inner = task.inner.lock();
 
     // CpuContext now holds all the registers and iretq frame values
@@ -134,7 +137,10 @@ pub fn test_user_task_iretq_frame() -> TestResult {
 
 /// Verify the user task is created with the correct kind.
 pub fn test_user_task_creation() -> TestResult {
-    let task = kernel::user_task_from_elf::create_user_task_from_elf();
+    let task = match kernel::user_task_from_elf::create_user_task_from_elf() {
+        Ok(task) => task,
+        Err(e) => return TestResult::Failed(format!("create_user_task_from_elf failed: {:?}", e)),
+    };
 
     if task.kind != TaskKind::User {
         return TestResult::Failed(format!(
@@ -156,11 +162,62 @@ pub fn test_user_task_creation() -> TestResult {
     TestResult::Ok
 }
 
+/// A new user stack has only its top page mapped; the rest, down to
+/// `USER_STACK_MAX_SIZE`, is a lazy region. The page below that is reserved in
+/// the task's address space, so nothing is placed there, but never mapped.
+pub fn test_user_stack_guard_page() -> TestResult {
+    use kernel::user_task_from_elf::{USER_STACK_MAX_SIZE, USER_STACK_TOP};
+    use x86_64::structures::paging::{OffsetPageTable, PageTable, Translate};
+    use x86_64::VirtAddr;
+
+    let task = match kernel::user_task_from_elf::create_user_task_from_elf() {
+        Ok(task) => task,
+        Err(e) => return TestResult::Failed(format!("create_user_task_from_elf failed: {:?}", e)),
+    };
+    let inner = task.inner.lock();
+    let Some(guard) = inner.user_stack_guard else {
+        return TestResult::Failed("user task has no stack guard page".into());
+    };
+    if guard != USER_STACK_TOP - USER_STACK_MAX_SIZE - 0x1000 {
+        return TestResult::Failed(format!("guard page {guard:#x} is not below the full stack"));
+    }
+
+    let hhdm = kernel::memory::hhdm_offset::hhdm_offset().as_u64();
+    let l4 = unsafe { &mut *((hhdm + task.cr3) as *mut PageTable) };
+    let mapper = unsafe { OffsetPageTable::new(l4, VirtAddr::new(hhdm)) };
+    let mapped = |vaddr: u64| mapper.translate_addr(VirtAddr::new(vaddr)).is_some();
+    if !mapped(USER_STACK_TOP - 8) {
+        return TestResult::Failed("top stack page is not mapped".into());
+    }
+    let below_top = USER_STACK_TOP - 0x1000 - 8;
+    if mapped(below_top) || !inner.lazy_regions.contains_point(below_top) {
+        return TestResult::Failed("stack below the top page is not left to demand paging".into());
+    }
+    if !inner.lazy_regions.contains_point(guard + 0x1000) {
+        return TestResult::Failed("lowest stack page is not a lazy page".into());
+    }
+
+    if mapped(guard) || inner.lazy_regions.contains_point(guard) {
+        return TestResult::Failed(format!("guard page {guard:#x} is mapped or lazy"));
+    }
+    if !inner.user_vaddr_set.contains_point(guard) {
+        return TestResult::Failed(format!("guard page {guard:#x} is not reserved"));
+    }
+    if !inner.overlaps_user_stack_guard(guard + 0xfff, guard + 0x1000) {
+        return TestResult::Failed("last byte of the guard page not detected".into());
+    }
+
+    TestResult::Ok
+}
+
 /// Verify that the user page table maps the kernel higher half.
 /// Actually switches CR3 to the user page table and reads back values
 /// from the kernel stack and GDT to verify they are accessible.
 pub fn test_user_page_table_kernel_mapped() -> TestResult {
-    let task = kernel::user_task_from_elf::create_user_task_from_elf();
+    let task = match kernel::user_task_from_elf::create_user_task_from_elf() {
+        Ok(task) => task,
+        Err(e) => return TestResult::Failed(format!("create_user_task_from_elf failed: {:?}", e)),
+    };
     let inner = task.inner.lock();
     let kernel_stack_top = inner.kernel_stack_top;
 
@@ -282,7 +339,10 @@ pub fn test_user_task_runs() -> TestResult {
     );
 
     // Create and spawn the user task
-    let user_task = kernel::user_task_from_elf::create_user_task_from_elf();
+    let user_task = match kernel::user_task_from_elf::create_user_task_from_elf() {
+        Ok(task) => task,
+        Err(e) => return TestResult::Failed(format!("create_user_task_from_elf failed: {:?}", e)),
+    };
     kernel::task::global_scheduler::spawn_task(user_task);
 
     // Spawn a checker task that verifies everything ran.
@@ -310,7 +370,7 @@ fn checker_task() -> ! {
     );
 
     let start = kernel::time::tsc::value();
-    let timeout = kernel::time::tsc::TSC_HZ.load(Ordering::SeqCst).saturating_mul(1000);
+    let timeout = kernel::time::tsc::TSC_HZ.load(Ordering::SeqCst) * 1000; // 1 s
 
     // Wait for both kernel tasks to have executed
     while KERNEL_TASK_COUNTER.load(Ordering::SeqCst) < 2 {
@@ -0,0 +1,78 @@
+//! CRC-32 (IEEE 802.3, as used by zlib and Ethernet) and the boot module
+//! checksum listing.
+//!
+//! The runner writes a `module_checksums` boot module with one line per
+//! module, `<name> <crc32 as 8 hex digits>`. The kernel checks every module
+//! against it at boot and refuses to hand out one that doesn't match.
+
+/// Name of the boot module holding the expected checksums.
+pub const MODULE_CHECKSUMS: &str = "module_checksums";
+
+const POLY: u32 = 0xEDB8_8320;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// The checksum `listing` records for module `name`, if any. Malformed lines
+/// are skipped.
+pub fn expected_checksum(listing: &str, name: &str) -> Option<u32> {
+    listing.lines().find_map(|line| {
+        let (module, crc) = line.trim().split_once(' ')?;
+        if module != name {
+            return None;
+        }
+        u32::from_str_radix(crc.trim(), 16).ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, expected_checksum};
+
+    #[test]
+    fn known_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn single_bit_flip_changes_checksum() {
+        let mut data = *b"the quick brown fox jumps over the lazy dog";
+        let good = crc32(&data);
+        for i in 0..data.len() * 8 {
+            data[i / 8] ^= 1 << (i % 8);
+            assert_ne!(crc32(&data), good, "flip of bit {i} went unnoticed");
+            data[i / 8] ^= 1 << (i % 8);
+        }
+    }
+
+    #[test]
+    fn listing_lookup() {
+        let listing = "init_task 0000abcd\ngarbage\ndisplay_server cbf43926\n";
+        assert_eq!(expected_checksum(listing, "display_server"), Some(0xCBF4_3926));
+        assert_eq!(expected_checksum(listing, "init_task"), Some(0xABCD));
+        assert_eq!(expected_checksum(listing, "init"), None);
+        assert_eq!(expected_checksum(listing, "garbage"), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Unified syscall error codes.
+//!
+//! Every syscall returns its result in `rax` using one convention: values in
+//! `[-MAX_ERRNO, -1]` (read as `i64`) are errors, everything else is a
+//! successful result (an address, an ID, a byte count, or 0). User addresses
+//! live in the lower half, so a valid result can never look like an error.
+
+/// Largest magnitude an error code can have.
+pub const MAX_ERRNO: u64 = 4095;
+
+#[repr(i64)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SysError {
+    /// A pointer, length, flag or other argument is invalid.
+    InvalidArgs = -1,
+    /// The endpoint ID doesn't exist or has been closed.
+    InvalidEndpoint = -2,
+    /// Sending on a receive endpoint, or receiving on a send endpoint.
+    WrongDirection = -3,
+    /// The other side of the channel has been closed.
+    PeerClosed = -4,
+    /// The operation would block (channel full or empty, no input yet), or a
+    /// blocking wait was interrupted by the scheduler.
+    WouldBlock = -5,
+    /// The message exceeds the kernel's per-message limit.
+    MessageTooLarge = -6,
+    /// The named object (service, module, task, shared buffer) doesn't exist.
+    NotFound = -7,
+    /// A service with that name is already registered.
+    AlreadyExists = -8,
+    /// Ran out of physical memory or user address space.
+    OutOfMemory = -9,
+    /// The caller isn't allowed to do this (not the owner, not granted, not a user task).
+    PermissionDenied = -10,
+    /// Unknown syscall number or ring opcode.
+    NoSys = -11,
+    /// The operation was not executed (e.g. skipped inside a batch).
+    Cancelled = -12,
+    /// The image isn't a loadable ELF (truncated, corrupt, or its segments
+    /// don't fit).
+    InvalidElf = -13,
+    /// A boot module doesn't match the checksum recorded for it.
+    Corrupted = -14,
+    /// An error code this version of the API doesn't know about.
+    Unknown = -4095,
+}
+
+impl SysError {
+    /// Map a negative error code back to its variant.
+    pub fn from_code(code: i64) -> Option<Self> {
+        Some(match code {
+            -1 => Self::InvalidArgs,
+            -2 => Self::InvalidEndpoint,
+            -3 => Self::WrongDirection,
+            -4 => Self::PeerClosed,
+            -5 => Self::WouldBlock,
+            -6 => Self::MessageTooLarge,
+            -7 => Self::NotFound,
+            -8 => Self::AlreadyExists,
+            -9 => Self::OutOfMemory,
+            -10 => Self::PermissionDenied,
+            -11 => Self::NoSys,
+            -12 => Self::Cancelled,
+            -13 => Self::InvalidElf,
+            -14 => Self::Corrupted,
+            -4095 => Self::Unknown,
+            _ => return None,
+        })
+    }
+
+    /// Whether a raw syscall return value encodes an error.
+    pub const fn is_error(ret: u64) -> bool {
+        ret > u64::MAX - MAX_ERRNO
+    }
+
+    /// Encode a result as a raw syscall return value.
+    pub fn encode(result: Result<u64, SysError>) -> u64 {
+        match result {
+            Ok(v) => v,
+            Err(e) => e as u64,
+        }
+    }
+
+    /// Decode a raw syscall return value.
+    pub fn from_ret(ret: u64) -> Result<u64, SysError> {
+        if Self::is_error(ret) {
+            Err(Self::from_code(ret as i64).unwrap_or(Self::Unknown))
+        } else {
+            Ok(ret)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SysError, MAX_ERRNO};
+
+    #[test]
+    fn errors_round_trip() {
+        for e in [SysError::InvalidArgs, SysError::WouldBlock, SysError::Cancelled, SysError::Unknown] {
+            assert_eq!(SysError::from_ret(SysError::encode(Err(e))), Err(e));
+        }
+    }
+
+    #[test]
+    fn success_values_are_not_errors() {
+        assert_eq!(SysError::from_ret(0), Ok(0));
+        assert_eq!(SysError::from_ret(0x7F00_0000_0000), Ok(0x7F00_0000_0000));
+        assert_eq!(SysError::from_ret(u64::MAX - MAX_ERRNO), Ok(u64::MAX - MAX_ERRNO));
+    }
+
+    #[test]
+    fn unassigned_codes_decode_as_unknown() {
+        assert_eq!(SysError::from_ret(-100i64 as u64), Err(SysError::Unknown));
+    }
+}
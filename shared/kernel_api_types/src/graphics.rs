@@ -4,6 +4,12 @@ pub type Rgb888Raw = u32; // 0x00RRGGBB
 /// This is a canonical lower-half address (user space).
 pub const FRAMEBUFFER_USER_VADDR: u64 = 0x7F00_0000_0000;
 
+/// Broadcast endpoint the kernel registers to announce framebuffer geometry
+/// changes. Subscribe with `sys_channel_subscribe`; each message is the new
+/// `DisplayInfo`, unframed. Any task can send on it, so treat a message as a
+/// prompt to call `sys_get_display_info` rather than as the mode itself.
+pub const KERNEL_DISPLAY_EVENTS_SERVICE: &[u8] = b"kernel_display_events";
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct Rect {
@@ -14,6 +20,7 @@ pub struct Rect {
 }
 
 /// Return code for graphics syscalls
+#[deprecated(note = "graphics syscalls now return `SysError` codes")]
 #[repr(u64)]
 #[derive(Clone, Copy, Debug)]
 pub enum GraphicsResult {
@@ -23,6 +30,7 @@ pub enum GraphicsResult {
     PermissionDenied = 3,
 }
 
+#[allow(deprecated)]
 impl GraphicsResult {
     pub fn from_u64(value: u64) -> Self {
         match value {
@@ -36,7 +44,7 @@ impl GraphicsResult {
 
 /// Display information returned by GetDisplayInfo syscall.
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct DisplayInfo {
     pub width: u32,
     pub height: u32,
@@ -0,0 +1,48 @@
+//! Input recording and playback, for reproducible GUI tests: `InputRecord`
+//! captures every keyboard and mouse event the kernel queues, with
+//! timestamps, and `InjectInput` queues an event as if the device had sent it.
+//! Both syscalls return `NoSys` in release kernels.
+
+use crate::{KeyEvent, MouseEvent};
+
+/// `InputEvent::kind` values.
+pub const INPUT_KEY: u8 = 0;
+pub const INPUT_MOUSE: u8 = 1;
+
+/// `InputRecord` commands.
+pub const INPUT_RECORD_START: u64 = 0;
+pub const INPUT_RECORD_STOP: u64 = 1;
+
+/// Events one recording holds; later ones are dropped.
+pub const MAX_RECORDED_INPUTS: usize = 256;
+
+/// A keyboard or mouse event; `kind` says which of `key` and `mouse` holds it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub kind:  u8,
+    pub key:   KeyEvent,
+    pub mouse: MouseEvent,
+}
+
+impl InputEvent {
+    pub const fn key(key: KeyEvent) -> Self {
+        Self { kind: INPUT_KEY, key, mouse: MouseEvent::EMPTY }
+    }
+
+    pub const fn mouse(mouse: MouseEvent) -> Self {
+        Self { kind: INPUT_MOUSE, key: KeyEvent::EMPTY, mouse }
+    }
+}
+
+/// A recorded event and when it was queued, in ms since recording started.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordedInput {
+    pub time_ms: u64,
+    pub event:   InputEvent,
+}
+
+impl RecordedInput {
+    pub const EMPTY: Self = Self { time_ms: 0, event: InputEvent::key(KeyEvent::EMPTY) };
+}
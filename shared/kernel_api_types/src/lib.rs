@@ -3,9 +3,18 @@
 #[cfg(test)]
 extern crate std;
 
+pub mod crc32;
+pub mod errno;
 pub mod graphics;
+pub mod input;
+pub mod net;
+pub mod ring;
+pub mod serial;
+pub mod task;
 pub mod window;
 
+pub use errno::SysError;
+
 #[repr(u64)]
 #[derive(Clone, Copy, Debug)]
 pub enum SysCallNumber {
@@ -32,44 +41,278 @@ pub enum SysCallNumber {
     CreateSharedBuf = 22,
     MapSharedBuf = 23,
     DestroySharedBuf = 24,
+    Null = 25,
+    ResizeSharedBuf = 26,
+    Batch = 27,
+    RingEnter = 28,
+    GrantSharedBuf = 29,
+    GetTaskId = 30,
+    SetSyscallTrace = 31,
+    DebugLogStr = 32,
+    Populate = 33,
+    Mincore = 34,
+    ChannelDup = 35,
+    Reboot = 36,
+    GetCycles = 37,
+    GetTscHz = 38,
+    GetWallClock = 39,
+    NetSend = 40,
+    NetRecv = 41,
+    NetGetMac = 42,
+    Sleep = 43,
+    SerialWrite = 44,
+    SerialRead = 45,
+    InputRecord = 46,
+    InjectInput = 47,
+    SetPriority = 48,
+    ChannelPoll = 49,
+    SetTaskName = 50,
+    ListTasks = 51,
+    Kill = 52,
+    BroadcastCreate = 53,
+    ChannelSubscribe = 54,
+    GetModuleChunk = 55,
+    HeapStats = 56,
+    ChannelSendWithEndpoint = 57,
+    ChannelRecvWithEndpoint = 58,
+    ChannelStats = 59,
+    Random = 60,
+    IsDisplayOwner = 61,
+}
+
+impl SysCallNumber {
+    pub fn from_u64(n: u64) -> Option<Self> {
+        use SysCallNumber::*;
+        Some(match n {
+            0 => GetBoundingBox,
+            3 => Exit,
+            4 => Spawn,
+            5 => ReadKey,
+            6 => Yield,
+            7 => Mmap,
+            8 => Munmap,
+            9 => ChannelCreate,
+            10 => ChannelSend,
+            11 => ChannelRecv,
+            12 => ChannelClose,
+            13 => TransferDisplay,
+            14 => GetModule,
+            15 => GetDisplayInfo,
+            16 => DebugLog,
+            17 => Waitpid,
+            18 => RegisterService,
+            19 => LookupService,
+            20 => ReadMouse,
+            21 => Shutdown,
+            22 => CreateSharedBuf,
+            23 => MapSharedBuf,
+            24 => DestroySharedBuf,
+            25 => Null,
+            26 => ResizeSharedBuf,
+            27 => Batch,
+            28 => RingEnter,
+            29 => GrantSharedBuf,
+            30 => GetTaskId,
+            31 => SetSyscallTrace,
+            32 => DebugLogStr,
+            33 => Populate,
+            34 => Mincore,
+            35 => ChannelDup,
+            36 => Reboot,
+            37 => GetCycles,
+            38 => GetTscHz,
+            39 => GetWallClock,
+            40 => NetSend,
+            41 => NetRecv,
+            42 => NetGetMac,
+            43 => Sleep,
+            44 => SerialWrite,
+            45 => SerialRead,
+            46 => InputRecord,
+            47 => InjectInput,
+            48 => SetPriority,
+            49 => ChannelPoll,
+            50 => SetTaskName,
+            51 => ListTasks,
+            52 => Kill,
+            53 => BroadcastCreate,
+            54 => ChannelSubscribe,
+            55 => GetModuleChunk,
+            56 => HeapStats,
+            57 => ChannelSendWithEndpoint,
+            58 => ChannelRecvWithEndpoint,
+            59 => ChannelStats,
+            60 => Random,
+            61 => IsDisplayOwner,
+            _ => return None,
+        })
+    }
 }
 
 pub const MAX_SERVICE_NAME_LEN: usize = 64;
 
+/// Longest task name, in bytes; `SetTaskName` truncates longer ones.
+pub const MAX_TASK_NAME_LEN: usize = 32;
+
+/// Largest message a channel accepts, in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// Longest string `DebugLogStr` logs; longer strings are cut off.
+pub const MAX_DEBUG_LOG_STR_LEN: usize = 256;
+
+/// Exit code the kernel gives a task it kills for running off the bottom of
+/// its user stack into the guard page.
+pub const EXIT_STACK_OVERFLOW: u64 = u64::MAX - 1;
+/// Exit code `Waitpid` reports for a task stopped with `Kill`.
+pub const EXIT_KILLED: u64 = u64::MAX - 2;
+
+/// Highest task priority `SetPriority` accepts; 0 is the lowest. The scheduler
+/// always runs the highest-priority ready task, round-robin among equals.
+pub const MAX_PRIORITY: u64 = 7;
+/// Priority every task starts with.
+pub const DEFAULT_PRIORITY: u64 = 4;
+
 pub const MOUSE_LEFT:   u8 = 1 << 0;
 pub const MOUSE_RIGHT:  u8 = 1 << 1;
 pub const MOUSE_MIDDLE: u8 = 1 << 2;
 
-/// A PS/2 mouse event passed between kernel and userland.
+/// `MouseEvent::flags`: the event carries a position in `abs_x`/`abs_y`
+/// rather than motion in `dx`/`dy`.
+pub const MOUSE_ABSOLUTE: u8 = 1 << 0;
+/// Largest absolute coordinate; `abs_x`/`abs_y` span the screen from 0 to this.
+pub const MOUSE_ABS_MAX: u16 = u16::MAX;
+
+/// A mouse event passed between kernel and userland.
+///
+/// PS/2 mice report relative motion in `dx`/`dy`. Absolute pointing devices
+/// set `MOUSE_ABSOLUTE` and report the pointer position in `abs_x`/`abs_y`,
+/// scaled to 0..=`MOUSE_ABS_MAX` regardless of the screen size.
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct MouseEvent {
     pub dx:      i16,
     pub dy:      i16,
     pub buttons: u8,
+    pub flags:   u8,
+    pub abs_x:   u16,
+    pub abs_y:   u16,
 }
 
 impl MouseEvent {
-    pub const EMPTY: Self = Self { dx: 0, dy: 0, buttons: 0 };
+    pub const EMPTY: Self = Self { dx: 0, dy: 0, buttons: 0, flags: 0, abs_x: 0, abs_y: 0 };
+}
+
+/// Wall-clock date and time from the CMOS real-time clock, as returned by
+/// `GetWallClock`. The RTC has no time zone; firmware usually keeps it in UTC
+/// (or local time on machines that also run Windows).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DateTime {
+    pub year:   u16,
+    /// 1-12
+    pub month:  u8,
+    /// 1-31
+    pub day:    u8,
+    /// 0-23
+    pub hour:   u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// Kernel heap usage, as returned by `HeapStats`. The counts run from boot;
+/// `allocation_count - free_count` is the number of live heap blocks.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    pub allocated_bytes: u64,
+    pub allocation_count: u64,
+    pub free_count: u64,
 }
 
-// IPC error codes
+// Legacy IPC / service status codes, kept as aliases of the `SysError` codes
+// the kernel now returns. New code should decode with `SysError::from_ret`.
+#[deprecated(note = "syscalls return 0 (or a non-negative value) on success")]
 pub const IPC_OK: u64 = 0;
-pub const IPC_ERR_INVALID_ENDPOINT: u64 = 1;
-pub const IPC_ERR_WRONG_DIRECTION: u64 = 2;
-pub const IPC_ERR_PEER_CLOSED: u64 = 3;
-pub const IPC_ERR_CHANNEL_FULL: u64 = 4;
-pub const IPC_ERR_INVALID_ARGS: u64 = 5;
-pub const IPC_ERR_MSG_TOO_LARGE: u64 = 6;
-
-// Service registry error codes
+#[deprecated(note = "use SysError::InvalidEndpoint")]
+pub const IPC_ERR_INVALID_ENDPOINT: u64 = SysError::InvalidEndpoint as u64;
+#[deprecated(note = "use SysError::WrongDirection")]
+pub const IPC_ERR_WRONG_DIRECTION: u64 = SysError::WrongDirection as u64;
+#[deprecated(note = "use SysError::PeerClosed")]
+pub const IPC_ERR_PEER_CLOSED: u64 = SysError::PeerClosed as u64;
+#[deprecated(note = "use SysError::WouldBlock")]
+pub const IPC_ERR_CHANNEL_FULL: u64 = SysError::WouldBlock as u64;
+#[deprecated(note = "use SysError::InvalidArgs")]
+pub const IPC_ERR_INVALID_ARGS: u64 = SysError::InvalidArgs as u64;
+#[deprecated(note = "use SysError::MessageTooLarge")]
+pub const IPC_ERR_MSG_TOO_LARGE: u64 = SysError::MessageTooLarge as u64;
+
+#[deprecated(note = "syscalls return 0 (or a non-negative value) on success")]
 pub const SVC_OK: u64 = 0;
-pub const SVC_ERR_NOT_FOUND: u64 = 10;
-pub const SVC_ERR_ALREADY_REGISTERED: u64 = 11;
-pub const SVC_ERR_INVALID_ARGS: u64 = 12;
+#[deprecated(note = "use SysError::NotFound")]
+pub const SVC_ERR_NOT_FOUND: u64 = SysError::NotFound as u64;
+#[deprecated(note = "use SysError::AlreadyExists")]
+pub const SVC_ERR_ALREADY_REGISTERED: u64 = SysError::AlreadyExists as u64;
+#[deprecated(note = "use SysError::InvalidArgs")]
+pub const SVC_ERR_INVALID_ARGS: u64 = SysError::InvalidArgs as u64;
 
 pub const MMAP_WRITE: u64 = 1 << 0;
 pub const MMAP_EXEC: u64 = 1 << 1;
+/// Only reserve the range; each page is allocated and mapped on first touch
+/// (or all at once with `Populate`).
+pub const MMAP_LAZY: u64 = 1 << 2;
+/// Back the range with 2 MiB pages where the size allows, falling back to
+/// 4 KiB pages for the rest or when no 2 MiB frame is free. Ignored with
+/// `MMAP_LAZY`.
+pub const MMAP_HUGE: u64 = 1 << 3;
+
+/// `MapSharedBuf` flag: map the buffer without write access.
+pub const SHBUF_READONLY: u64 = 1 << 0;
+
+/// `ChannelRecv` flag: a message longer than the buffer is copied as far as
+/// it fits but stays queued, so it can be received again with a larger
+/// buffer. Without it the rest of the message is dropped.
+pub const RECV_KEEP_TRUNCATED: u64 = 1 << 0;
+
+/// `RecvInfo::endpoint` for a message that came without one. Endpoint IDs
+/// start at 1.
+pub const NO_ENDPOINT: u64 = 0;
+
+/// What `ChannelRecvWithEndpoint` received besides the message bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecvInfo {
+    /// Bytes copied into the buffer.
+    pub bytes_read: u64,
+    /// The endpoint sent along with the message, now owned by the receiver,
+    /// or `NO_ENDPOINT`.
+    pub endpoint: u64,
+}
+
+/// Maximum number of operations accepted by a single `Batch` syscall.
+pub const MAX_BATCH_OPS: usize = 64;
+
+/// `SyscallOp::result` value for an op the kernel did not execute — it was
+/// rejected (nested batch, diverging or blocking syscall), or an earlier op
+/// blocked and the batch was cut short.
+pub const BATCH_OP_NOT_RUN: u64 = SysError::Cancelled as u64;
+
+/// One encoded syscall inside a `Batch` submission.
+///
+/// `number` and `args` mirror the registers of a normal syscall; the kernel
+/// writes the return value into `result`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SyscallOp {
+    pub number: u64,
+    pub args: [u64; 6],
+    pub result: u64,
+}
+
+impl SyscallOp {
+    pub const fn new(number: SysCallNumber, args: [u64; 6]) -> Self {
+        Self { number: number as u64, args, result: BATCH_OP_NOT_RUN }
+    }
+}
 
 /// Keyboard event types.
 #[repr(u8)]
@@ -88,7 +331,7 @@ pub enum KeyEventType {
 
 /// A keyboard event passed between kernel and userland.
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct KeyEvent {
     pub event_type: KeyEventType,
     /// The character for `Char` events, or `\0` for non-character events.
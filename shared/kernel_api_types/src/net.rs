@@ -0,0 +1,38 @@
+//! Raw Ethernet access: the `NetSend`/`NetRecv`/`NetGetMac` syscalls and the
+//! "net" service protocol built on them, which "loopback" also speaks.
+//!
+//! Frames are Ethernet II without the trailing CRC: destination MAC, source
+//! MAC, EtherType, payload.
+
+pub type MacAddress = [u8; 6];
+
+pub const BROADCAST_MAC: MacAddress = [0xff; 6];
+/// The "loopback" service's address (locally administered, unicast).
+pub const LOOPBACK_MAC: MacAddress = [0x02, 0, 0, 0, 0, 0x01];
+
+/// Spawn argument that starts `net_server` as the "loopback" service, which
+/// echoes every sent frame back to its receive queue, instead of "net".
+pub const NET_SERVER_LOOPBACK: u64 = 1;
+
+/// Destination, source and EtherType.
+pub const ETHERNET_HEADER_SIZE: usize = 14;
+/// Shortest frame the driver accepts; the NIC pads to the 60-byte minimum.
+pub const MIN_FRAME_SIZE: usize = ETHERNET_HEADER_SIZE;
+/// Header plus a 1500-byte payload.
+pub const MAX_FRAME_SIZE: usize = ETHERNET_HEADER_SIZE + 1500;
+
+/// Requests to the "net" and "loopback" services. Each is sent with `sys_channel_call`
+/// framed by `ulib::ipc`, and answered under the same tag:
+///
+/// | Request  | Payload     | Reply payload                                 |
+/// |----------|-------------|-----------------------------------------------|
+/// | `Send`   | frame bytes | `i64`: 0, or a negated `SysError` code        |
+/// | `Recv`   | empty       | next received frame; empty if none is queued  |
+/// | `GetMac` | empty       | `MacAddress`                                  |
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetMessageType {
+    Send = 0,
+    Recv = 1,
+    GetMac = 2,
+}
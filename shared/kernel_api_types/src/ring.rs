@@ -0,0 +1,86 @@
+//! Submission/completion ring for batching syscalls without one kernel entry
+//! per operation.
+//!
+//! The ring lives in ordinary user memory. Userspace fills submission entries
+//! and bumps `sq_tail`; a `RingEnter` syscall (the doorbell) makes the kernel
+//! drain everything between `sq_head` and `sq_tail`, posting one completion per
+//! submission. Userspace then consumes completions from `cq_head` up to `cq_tail`.
+//!
+//! Index ownership: userspace writes `sq_tail` and `cq_head`; the kernel writes
+//! `sq_head` and `cq_tail`. Indices increase monotonically and wrap at `u32::MAX`;
+//! the slot is `index % RING_ENTRIES`.
+
+use core::sync::atomic::AtomicU32;
+
+pub const RING_ENTRIES: usize = 32;
+
+/// Operations that may be posted to the ring. All of them are non-blocking
+/// here: a send on a full channel or a recv on an empty one completes
+/// immediately with `SysError::WouldBlock`. Results use the usual syscall
+/// encoding (decode with `SysError::from_ret`); an unknown opcode completes
+/// with `SysError::NoSys`.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RingOp {
+    /// args: size, flags. result: address.
+    Mmap = 0,
+    /// args: addr, size. result: 0.
+    Munmap = 1,
+    /// args: endpoint, msg_ptr, msg_len. result: 0.
+    ChannelSend = 2,
+    /// args: endpoint, buf_ptr, buf_cap. result: 0, aux: bytes read.
+    ChannelRecv = 3,
+}
+
+impl RingOp {
+    pub fn from_u32(v: u32) -> Option<Self> {
+        match v {
+            0 => Some(Self::Mmap),
+            1 => Some(Self::Munmap),
+            2 => Some(Self::ChannelSend),
+            3 => Some(Self::ChannelRecv),
+            _ => None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SubmissionEntry {
+    pub op: u32,
+    pub _reserved: u32,
+    /// Opaque value copied into the matching completion.
+    pub user_data: u64,
+    pub args: [u64; 4],
+}
+
+impl SubmissionEntry {
+    pub const EMPTY: Self = Self { op: 0, _reserved: 0, user_data: 0, args: [0; 4] };
+
+    pub const fn new(op: RingOp, user_data: u64, args: [u64; 4]) -> Self {
+        Self { op: op as u32, _reserved: 0, user_data, args }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CompletionEntry {
+    pub user_data: u64,
+    pub result: u64,
+    /// Secondary result (bytes read for `ChannelRecv`, otherwise 0).
+    pub aux: u64,
+}
+
+impl CompletionEntry {
+    pub const EMPTY: Self = Self { user_data: 0, result: 0, aux: 0 };
+}
+
+#[repr(C)]
+pub struct SyscallRing {
+    pub sq_head: AtomicU32,
+    pub sq_tail: AtomicU32,
+    pub cq_head: AtomicU32,
+    pub cq_tail: AtomicU32,
+    pub sq: [SubmissionEntry; RING_ENTRIES],
+    pub cq: [CompletionEntry; RING_ENTRIES],
+}
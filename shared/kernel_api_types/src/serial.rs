@@ -0,0 +1,23 @@
+//! Raw COM1 access: the `SerialWrite`/`SerialRead` syscalls and the "serial"
+//! service protocol built on them.
+
+/// Most bytes one `SerialWrite` or `SerialRead` syscall moves.
+pub const SERIAL_CHUNK_SIZE: usize = 256;
+
+/// Written to the "serial" service by utest; the test runner fails the run if
+/// it never shows up in QEMU's serial output.
+pub const SERIAL_TEST_MARKER: &str = "utest: hello from the serial service";
+
+/// Requests to the "serial" service. Each is sent with `sys_channel_call`
+/// framed by `ulib::ipc`, and answered under the same tag:
+///
+/// | Request | Payload     | Reply payload                                  |
+/// |---------|-------------|------------------------------------------------|
+/// | `Write` | bytes       | `i64`: 0, or a negated `SysError` code         |
+/// | `Read`  | empty       | bytes received so far; empty if none           |
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialMessageType {
+    Write = 0,
+    Read = 1,
+}
@@ -0,0 +1,145 @@
+//! Task listings, where the `ListTasks` syscall fills a buffer with
+//! `TaskInfo`s, and the ways `Spawn` can fail.
+
+use crate::{SysError, MAX_TASK_NAME_LEN};
+
+/// `TaskInfo::kind` values.
+pub const TASK_KIND_KERNEL: u8 = 0;
+pub const TASK_KIND_USER: u8 = 1;
+
+/// `TaskInfo::state` values.
+pub const TASK_STATE_INITIALIZING: u8 = 0;
+pub const TASK_STATE_RUNNING: u8 = 1;
+pub const TASK_STATE_READY: u8 = 2;
+pub const TASK_STATE_SLEEPING: u8 = 3;
+/// Exited, but not yet reaped by `Waitpid`.
+pub const TASK_STATE_ZOMBIE: u8 = 4;
+
+/// One task, as `ListTasks` reports it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: u64,
+    /// Scheduler quanta the task has used; one is about 1 ms.
+    pub cpu_ticks: u64,
+    pub kind: u8,
+    pub state: u8,
+    /// Effective priority, including any inherited over IPC.
+    pub priority: u8,
+    /// Bytes of `name` in use; 0 for an unnamed task.
+    pub name_len: u8,
+    /// The CPU the task runs on. Tasks stay on the CPU they were spawned on.
+    pub cpu: u32,
+    pub name: [u8; MAX_TASK_NAME_LEN],
+}
+
+impl TaskInfo {
+    pub const EMPTY: Self = Self {
+        id: 0,
+        cpu_ticks: 0,
+        kind: TASK_KIND_KERNEL,
+        state: TASK_STATE_INITIALIZING,
+        priority: 0,
+        name_len: 0,
+        cpu: 0,
+        name: [0; MAX_TASK_NAME_LEN],
+    };
+
+    /// The task's name, or "" if it has none or it isn't valid UTF-8.
+    pub fn name(&self) -> &str {
+        let len = (self.name_len as usize).min(MAX_TASK_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    /// Store `name`, cut to `MAX_TASK_NAME_LEN` bytes at a character boundary.
+    pub fn set_name(&mut self, name: &str) {
+        let mut len = name.len().min(MAX_TASK_NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        self.name_len = len as u8;
+    }
+}
+
+/// Largest ELF image `Spawn` accepts, in bytes.
+pub const MAX_SPAWN_IMAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// `Spawn` flag: start the task as a system task, which may create deeper
+/// channels than a user task. Only a system task may pass it on.
+pub const SPAWN_SYSTEM: u64 = 1 << 0;
+
+/// Why `Spawn` failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpawnError {
+    /// The image isn't a loadable ELF (`SysError::InvalidElf`).
+    InvalidElf,
+    /// Building the task ran out of memory (`SysError::OutOfMemory`).
+    OutOfMemory,
+    /// The image buffer is empty, larger than `MAX_SPAWN_IMAGE_SIZE`, or not
+    /// mapped user memory, or the flags are unknown (`SysError::InvalidArgs`).
+    BadPointer,
+    /// The caller isn't a user task, or asked for `SPAWN_SYSTEM` without
+    /// being a system task (`SysError::PermissionDenied`).
+    PermissionDenied,
+    /// Any other error code.
+    Other(SysError),
+}
+
+impl From<SysError> for SpawnError {
+    fn from(e: SysError) -> Self {
+        match e {
+            SysError::InvalidElf => Self::InvalidElf,
+            SysError::OutOfMemory => Self::OutOfMemory,
+            SysError::InvalidArgs => Self::BadPointer,
+            SysError::PermissionDenied => Self::PermissionDenied,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<SpawnError> for SysError {
+    fn from(e: SpawnError) -> Self {
+        match e {
+            SpawnError::InvalidElf => SysError::InvalidElf,
+            SpawnError::OutOfMemory => SysError::OutOfMemory,
+            SpawnError::BadPointer => SysError::InvalidArgs,
+            SpawnError::PermissionDenied => SysError::PermissionDenied,
+            SpawnError::Other(e) => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawn_errors_map_to_distinct_codes() {
+        let errors = [SpawnError::InvalidElf, SpawnError::OutOfMemory, SpawnError::BadPointer, SpawnError::PermissionDenied];
+        for (i, e) in errors.into_iter().enumerate() {
+            let code = SysError::from(e);
+            assert_eq!(SpawnError::from(code), e);
+            assert!(errors[..i].iter().all(|&earlier| SysError::from(earlier) != code));
+        }
+        assert_eq!(SpawnError::from(SysError::NoSys), SpawnError::Other(SysError::NoSys));
+    }
+
+    #[test]
+    fn name_round_trips() {
+        let mut info = TaskInfo::EMPTY;
+        assert_eq!(info.name(), "");
+        info.set_name("display_server");
+        assert_eq!(info.name(), "display_server");
+        // A corrupt length is clamped rather than read past the array.
+        info.name_len = u8::MAX;
+        assert_eq!(info.name().len(), MAX_TASK_NAME_LEN);
+    }
+
+    #[test]
+    fn long_names_are_cut_at_a_character_boundary() {
+        let mut info = TaskInfo::EMPTY;
+        info.set_name("xéééééééééééééééé");
+        assert_eq!(info.name(), "xééééééééééééééé");
+    }
+}
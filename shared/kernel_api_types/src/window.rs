@@ -1,3 +1,11 @@
+//! Window management IPC protocol for communicating with the display_server.
+//!
+//! Each message is framed with `ulib::ipc`: a `WindowMessageType` tag, then the
+//! request struct. Requests with a `*Response` are sent with `sys_channel_call`,
+//! and the server answers under the same tag.
+
+use crate::graphics::DisplayInfo;
+
 /// Tracks the bounding box of dirty (modified) pixels that need to be flushed to the compositor.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct DirtyRect {
@@ -19,11 +27,59 @@ impl DirtyRect {
         self.w = new_x2 - self.x;
         self.h = new_y2 - self.y;
     }
+
+    /// The part of this rect that is also inside `other`, or `None` if they
+    /// don't overlap. An edge past `u32::MAX` is taken to be at `u32::MAX`.
+    pub fn intersect(&self, other: &DirtyRect) -> Option<DirtyRect> {
+        let x0 = self.x.max(other.x);
+        let y0 = self.y.max(other.y);
+        let x1 = self.x.saturating_add(self.w).min(other.x.saturating_add(other.w));
+        let y1 = self.y.saturating_add(self.h).min(other.y.saturating_add(other.h));
+        (x0 < x1 && y0 < y1).then_some(DirtyRect { x: x0, y: y0, w: x1 - x0, h: y1 - y0 })
+    }
+
+    /// Whether this rect and `other` share at least one pixel. Rects that
+    /// only touch along an edge don't.
+    pub fn overlaps(&self, other: &DirtyRect) -> bool {
+        self.intersect(other).is_some()
+    }
+}
+
+/// Where a `w`×`h` blit placed at `(dst_x, dst_y)` lands on a `screen_w`×`screen_h`
+/// screen, and which part of the source that is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlitClip {
+    /// Destination rectangle, entirely on screen.
+    pub dst: DirtyRect,
+    /// Offset into the source of the first copied pixel.
+    pub src_x: u32,
+    pub src_y: u32,
+}
+
+/// Clip a blit to the screen. Returns `None` if nothing is visible.
+///
+/// The math is done in `i64`, so any `i32` position combined with any `u32`
+/// size clips correctly instead of wrapping.
+pub fn clip_blit(dst_x: i32, dst_y: i32, w: u32, h: u32, screen_w: u32, screen_h: u32) -> Option<BlitClip> {
+    let (x0, x1) = clip_span(dst_x, w, screen_w)?;
+    let (y0, y1) = clip_span(dst_y, h, screen_h)?;
+    Some(BlitClip {
+        dst: DirtyRect { x: x0, y: y0, w: x1 - x0, h: y1 - y0 },
+        src_x: (x0 as i64 - dst_x as i64) as u32,
+        src_y: (y0 as i64 - dst_y as i64) as u32,
+    })
+}
+
+/// Clip `[start, start + len)` to `[0, limit)`; `None` if empty.
+fn clip_span(start: i32, len: u32, limit: u32) -> Option<(u32, u32)> {
+    let lo = (start as i64).clamp(0, limit as i64);
+    let hi = (start as i64 + len as i64).clamp(0, limit as i64);
+    (lo < hi).then_some((lo as u32, hi as u32))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::DirtyRect;
+    use super::{clip_blit, BlitClip, CursorImage, DirtyRect, SizeLimits, MAX_CURSOR_SIZE, MAX_WINDOW_DIMENSION};
 
     #[test]
     fn expand_same_rect_is_noop() {
@@ -55,16 +111,124 @@ mod tests {
         d.expand(5, 5, 5, 5);
         assert_eq!(d, DirtyRect { x: 0, y: 0, w: 20, h: 20 });
     }
-}
 
-/// Window management IPC protocol for communicating with the display_server.
-///
-/// Clients send WindowMessage requests to the display_server via IPC channels,
-/// and the server responds with WindowResponse messages.
+    #[test]
+    fn intersect_overlapping() {
+        let a = DirtyRect { x: 0, y: 0, w: 10, h: 10 };
+        let b = DirtyRect { x: 5, y: 3, w: 10, h: 4 };
+        assert_eq!(a.intersect(&b), Some(DirtyRect { x: 5, y: 3, w: 5, h: 4 }));
+        assert_eq!(b.intersect(&a), a.intersect(&b));
+        assert!(a.overlaps(&b));
+    }
+
+    #[test]
+    fn intersect_contained_is_inner() {
+        let outer = DirtyRect { x: 0, y: 0, w: 20, h: 20 };
+        let inner = DirtyRect { x: 5, y: 5, w: 5, h: 5 };
+        assert_eq!(outer.intersect(&inner), Some(inner));
+    }
+
+    #[test]
+    fn touching_edges_dont_overlap() {
+        let a = DirtyRect { x: 0, y: 0, w: 10, h: 10 };
+        assert_eq!(a.intersect(&DirtyRect { x: 10, y: 0, w: 5, h: 10 }), None);
+        assert!(!a.overlaps(&DirtyRect { x: 0, y: 10, w: 10, h: 5 }));
+        assert!(!a.overlaps(&DirtyRect { x: 2, y: 2, w: 0, h: 5 }));
+    }
+
+    #[test]
+    fn intersect_saturates_huge_rects() {
+        let screen = DirtyRect { x: 0, y: 0, w: 800, h: 600 };
+        let huge = DirtyRect { x: 700, y: 500, w: u32::MAX, h: u32::MAX };
+        assert_eq!(huge.intersect(&screen), Some(DirtyRect { x: 700, y: 500, w: 100, h: 100 }));
+    }
+
+    #[test]
+    fn size_limits_clamp_both_ways() {
+        let limits = SizeLimits { min_width: 100, min_height: 50, max_width: 400, max_height: 0 };
+        assert_eq!(limits.clamp(10, 10), (100, 50));
+        assert_eq!(limits.clamp(1000, 9000), (400, MAX_WINDOW_DIMENSION));
+        assert_eq!(limits.clamp(200, 300), (200, 300));
+    }
+
+    #[test]
+    fn no_limits_still_keeps_a_pixel() {
+        assert_eq!(SizeLimits::NONE.clamp(0, 0), (1, 1));
+        assert!(SizeLimits::NONE.is_valid());
+    }
+
+    #[test]
+    fn crossed_limits_are_invalid() {
+        assert!(!SizeLimits { min_width: 300, max_width: 200, ..SizeLimits::NONE }.is_valid());
+        assert!(!SizeLimits { min_height: MAX_WINDOW_DIMENSION + 1, ..SizeLimits::NONE }.is_valid());
+    }
+
+    #[test]
+    fn cursor_size_and_hot_spot_are_checked() {
+        let cursor = CursorImage { width: 8, height: 16, hot_x: 7, hot_y: 0, mask: [0; MAX_CURSOR_SIZE], image: [0; MAX_CURSOR_SIZE] };
+        assert!(cursor.is_valid());
+        assert!(CursorImage { width: MAX_CURSOR_SIZE as u32, height: MAX_CURSOR_SIZE as u32, ..cursor }.is_valid());
+        assert!(!CursorImage { width: 0, ..cursor }.is_valid());
+        assert!(!CursorImage { height: MAX_CURSOR_SIZE as u32 + 1, ..cursor }.is_valid());
+        assert!(!CursorImage { hot_x: 8, ..cursor }.is_valid());
+        assert!(!CursorImage { hot_y: 16, ..cursor }.is_valid());
+    }
+
+    #[test]
+    fn clip_blit_partly_off_top_left() {
+        let c = clip_blit(-10, -5, 30, 20, 100, 50).unwrap();
+        assert_eq!(c, BlitClip { dst: DirtyRect { x: 0, y: 0, w: 20, h: 15 }, src_x: 10, src_y: 5 });
+    }
+
+    #[test]
+    fn clip_blit_fully_off_screen() {
+        assert_eq!(clip_blit(100, 0, 10, 10, 100, 50), None);
+        assert_eq!(clip_blit(-10, 0, 10, 10, 100, 50), None);
+        assert_eq!(clip_blit(0, 0, 0, 10, 100, 50), None);
+    }
+
+    #[test]
+    fn clip_blit_extreme_coordinates_stay_on_screen() {
+        let extremes = [i32::MIN, i32::MIN + 1, -1, 0, 1, 99, i32::MAX - 1, i32::MAX];
+        let sizes = [0, 1, 100, i32::MAX as u32, i32::MAX as u32 + 1, u32::MAX];
+        for &x in &extremes {
+            for &y in &extremes {
+                for &w in &sizes {
+                    for &h in &sizes {
+                        if let Some(c) = clip_blit(x, y, w, h, 100, 50) {
+                            assert!(c.dst.w > 0 && c.dst.h > 0);
+                            assert!(c.dst.x + c.dst.w <= 100 && c.dst.y + c.dst.h <= 50, "{x} {y} {w} {h} -> {c:?}");
+                            assert!(c.src_x as u64 + c.dst.w as u64 <= w as u64);
+                            assert!(c.src_y as u64 + c.dst.h as u64 <= h as u64);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clip_blit_huge_width_from_far_left_covers_screen() {
+        let c = clip_blit(i32::MIN, 0, u32::MAX, 1, 100, 50).unwrap();
+        assert_eq!(c.dst, DirtyRect { x: 0, y: 0, w: 100, h: 1 });
+        assert_eq!(c.src_x, 1u32 << 31);
+    }
+}
 
 /// Window ID assigned by the display server
 pub type WindowId = u64;
 
+/// Most windows the display server keeps open at once; creating another fails
+/// with `ErrorTooManyWindows`. Defaults to 32; set `BOS_MAX_WINDOWS` when
+/// building to change it.
+pub const MAX_WINDOWS: usize = match option_env!("BOS_MAX_WINDOWS") {
+    Some(v) => match usize::from_str_radix(v, 10) {
+        Ok(n) if n > 0 => n,
+        _ => panic!("BOS_MAX_WINDOWS must be a positive number"),
+    },
+    None => 32,
+};
+
 /// Client-to-server window management messages
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -77,24 +241,148 @@ pub enum WindowMessageType {
     CloseWindow = 2,
     /// Move a window to a new position
     MoveWindow = 3,
-    /// Resize a window
+    /// Resize a window, within its `SizeLimits`. Answered with a
+    /// `ResizeWindowResponse`.
     ResizeWindow = 4,
     /// Bring window to front (change z-order)
     RaiseWindow = 5,
     /// Send window to back (change z-order)
     LowerWindow = 6,
+    /// Query a window's current on-screen position and size
+    GetWindowBounds = 7,
+    /// Turn step mode on or off (for tests). While it is on, the server
+    /// handles requests and buffers input as usual but only composites when
+    /// asked to by `Step`. Answered with a `StepResponse`.
+    SetStepMode = 8,
+    /// In step mode, apply pending input and composite once. Answered with a
+    /// `StepResponse` once the frame is on screen.
+    Step = 9,
+    /// Move a window into or out of the always-on-top band, which stays
+    /// above every normal window however those are raised or lowered.
+    SetAlwaysOnTop = 10,
+    /// Query which window is visible at a screen position. Answered with a
+    /// `WindowAtResponse`.
+    WindowAt = 11,
+    /// Change the resolution the server composites at, up to the
+    /// framebuffer's own. Until the kernel can set modes this stands in for a
+    /// real mode change, e.g. in tests. Answered with a
+    /// `SetDisplayModeResponse`; subscribers to `DISPLAY_EVENTS_SERVICE` are
+    /// sent `DisplayChanged` first.
+    SetDisplayMode = 12,
+    /// Hand the server the send endpoint of a channel for this window's
+    /// `WindowEventType` notifications, e.g. throttle hints. The endpoint
+    /// follows the request struct, like a reply endpoint; it then belongs to
+    /// the server, which closes it with the window.
+    SetWindowEvents = 13,
+    /// Replace the mouse cursor with a `CursorImage` (fire-and-forget). The
+    /// cursor belongs to the window named in the request, and goes back to
+    /// the default arrow when that window closes. An image that isn't
+    /// `CursorImage::is_valid` restores the default at once.
+    SetCursor = 14,
+}
+
+/// Broadcast endpoint the display server registers for its events. Subscribe
+/// to it with `sys_channel_subscribe`; each message is framed with `ulib::ipc`
+/// under a `DisplayEventType` tag.
+pub const DISPLAY_EVENTS_SERVICE: &[u8] = b"display_events";
+
+/// Server-to-subscriber notifications on `DISPLAY_EVENTS_SERVICE`.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayEventType {
+    /// The resolution changed; carries a `DisplayChangedEvent`. Clients should
+    /// stop trusting a `DisplayInfo` they fetched earlier and size their
+    /// windows for the new one.
+    DisplayChanged = 0,
+}
+
+/// Payload of `DisplayEventType::DisplayChanged`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisplayChangedEvent {
+    pub info: DisplayInfo,
+}
+
+/// Server-to-client notifications about one window, sent on the channel
+/// given with `SetWindowEvents`. Framed with `ulib::ipc` like requests.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowEventType {
+    /// The server is falling behind and this window is one of the reasons;
+    /// carries a `ThrottleEvent`. A well-behaved client presents less often.
+    Throttle = 0,
+}
+
+/// Payload of `WindowEventType::Throttle`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThrottleEvent {
+    pub window_id: WindowId,
+    /// Updates from this window merged into a single composite.
+    pub coalesced_updates: u32,
+    /// Requests that were queued for the server when it fell behind.
+    pub backlog: u32,
+}
+
+/// Largest window width or height the display server accepts.
+pub const MAX_WINDOW_DIMENSION: u32 = 4096;
+
+/// Bounds on a window's size, kept for its lifetime. A 0 means that side is
+/// unconstrained beyond 1..=`MAX_WINDOW_DIMENSION`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeLimits {
+    pub min_width: u32,
+    pub min_height: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+}
+
+impl SizeLimits {
+    pub const NONE: SizeLimits = SizeLimits { min_width: 0, min_height: 0, max_width: 0, max_height: 0 };
+
+    fn range(min: u32, max: u32) -> (u32, u32) {
+        let max = if max == 0 { MAX_WINDOW_DIMENSION } else { max.min(MAX_WINDOW_DIMENSION) };
+        (min.max(1), max)
+    }
+
+    /// Whether some size satisfies the limits.
+    pub fn is_valid(&self) -> bool {
+        let (min_w, max_w) = Self::range(self.min_width, self.max_width);
+        let (min_h, max_h) = Self::range(self.min_height, self.max_height);
+        min_w <= max_w && min_h <= max_h
+    }
+
+    /// The size closest to `width`×`height` within the limits. They must be valid.
+    pub fn clamp(&self, width: u32, height: u32) -> (u32, u32) {
+        let (min_w, max_w) = Self::range(self.min_width, self.max_width);
+        let (min_h, max_h) = Self::range(self.min_height, self.max_height);
+        (width.clamp(min_w, max_w), height.clamp(min_h, max_h))
+    }
 }
 
 /// Create window request
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct CreateWindowRequest {
+    /// Requested size. 0 or more than `MAX_WINDOW_DIMENSION` is rejected;
+    /// anything else is clamped into `limits`.
     pub width: u32,
     pub height: u32,
     pub x: i32,
     pub y: i32,
+    /// Requesting task; the server grants it access to the window's shared buffer.
+    pub client_task_id: u64,
+    /// Also enforced on every `ResizeWindow`.
+    pub limits: SizeLimits,
 }
 
+/// `UpdateWindowRequest::flags` bit: the request carries a reply endpoint as
+/// its trailer (see `sys_channel_call`), and the server sends one byte on it
+/// once a composite has presented the update. A rejected update gets its
+/// endpoint closed instead.
+pub const UPDATE_WANTS_ACK: u32 = 1 << 0;
+
 /// Update window request — dirty-rect notification only (no pixel data).
 /// Pixels live in the shared buffer mapped at window creation time.
 #[repr(C)]
@@ -105,6 +393,8 @@ pub struct UpdateWindowRequest {
     pub dirty_y: u32,
     pub dirty_width: u32,
     pub dirty_height: u32,
+    /// `UPDATE_*` flags.
+    pub flags: u32,
 }
 
 /// Close window request
@@ -128,6 +418,18 @@ pub struct MoveWindowRequest {
 #[derive(Clone, Copy, Debug)]
 pub struct ResizeWindowRequest {
     pub window_id: WindowId,
+    /// Requested size, clamped into the window's `SizeLimits`.
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Response to ResizeWindow. On success the shared buffer holds
+/// `width`×`height` pixels; it may have moved, so the client maps it again.
+/// Its contents are not rearranged for the new width.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ResizeWindowResponse {
+    pub result: WindowResult,
     pub width: u32,
     pub height: u32,
 }
@@ -146,6 +448,147 @@ pub struct LowerWindowRequest {
     pub window_id: WindowId,
 }
 
+/// Always-on-top request. Raising or lowering the window afterwards moves it
+/// within its band only.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SetAlwaysOnTopRequest {
+    pub window_id: WindowId,
+    /// Nonzero to keep the window above normal windows, zero to make it normal.
+    pub enabled: u64,
+}
+
+/// Largest cursor width or height `SetCursor` accepts.
+pub const MAX_CURSOR_SIZE: usize = 32;
+
+/// A two-colour cursor of up to `MAX_CURSOR_SIZE`×`MAX_CURSOR_SIZE` pixels.
+/// Each row is a `u32` with bit 31 for the leftmost column; rows and bits
+/// past `height` and `width` are ignored.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CursorImage {
+    pub width: u32,
+    pub height: u32,
+    /// The pixel that sits on the pointer position.
+    pub hot_x: u32,
+    pub hot_y: u32,
+    /// 1 = drawn, 0 = transparent.
+    pub mask: [u32; MAX_CURSOR_SIZE],
+    /// Where `mask` is 1: 1 = white, 0 = black.
+    pub image: [u32; MAX_CURSOR_SIZE],
+}
+
+impl CursorImage {
+    /// Whether the size is 1..=`MAX_CURSOR_SIZE` both ways and the hot spot
+    /// is inside it.
+    pub fn is_valid(&self) -> bool {
+        let max = MAX_CURSOR_SIZE as u32;
+        (1..=max).contains(&self.width)
+            && (1..=max).contains(&self.height)
+            && self.hot_x < self.width
+            && self.hot_y < self.height
+    }
+}
+
+/// Cursor request.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SetCursorRequest {
+    pub window_id: WindowId,
+    pub cursor: CursorImage,
+}
+
+/// Window events request. The message carries the events channel's send
+/// endpoint after the request struct.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SetWindowEventsRequest {
+    pub window_id: WindowId,
+}
+
+/// Window-at-point request. Like CreateWindow, the message carries a reply
+/// endpoint after the request struct.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct WindowAtRequest {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Response to WindowAt
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct WindowAtResponse {
+    /// `ErrorInvalidWindowId` if only the background is there.
+    pub result: WindowResult,
+    /// The top-most window covering the point.
+    pub window_id: WindowId,
+}
+
+/// Display mode request. Like CreateWindow, the message carries a reply
+/// endpoint after the request struct.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SetDisplayModeRequest {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Response to SetDisplayMode
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SetDisplayModeResponse {
+    /// `ErrorInvalidDimensions` for a zero size or one larger than the framebuffer.
+    pub result: WindowResult,
+    /// The mode now in effect, changed or not.
+    pub info: DisplayInfo,
+}
+
+/// Window bounds request. Like CreateWindow, the message carries a reply
+/// endpoint (8 bytes, little-endian) after the request struct.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GetWindowBoundsRequest {
+    pub window_id: WindowId,
+}
+
+/// Step mode request. Like CreateWindow, the message carries a reply endpoint.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SetStepModeRequest {
+    /// Nonzero to enable step mode, zero to return to free-running.
+    pub enabled: u64,
+}
+
+/// Step request; the payload is empty apart from the reply endpoint.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct StepRequest;
+
+/// Response to SetStepMode and Step
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepResponse {
+    /// Composites performed since the server started. A composite is counted
+    /// only when there was damage to present, so a `Step` with nothing
+    /// pending leaves this unchanged.
+    pub composites: u64,
+    /// Window blits into the scene since the server started. A window
+    /// entirely off screen is never blitted.
+    pub window_blits: u64,
+}
+
+/// A window's position and size in screen coordinates. The position may be
+/// negative or past the screen edge if the window is partly off-screen.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
 /// Server-to-client response codes
 #[repr(u64)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -156,6 +599,8 @@ pub enum WindowResult {
     ErrorInvalidDimensions = 3,
     ErrorBufferTooLarge = 4,
     ErrorInvalidMessage = 5,
+    /// All `MAX_WINDOWS` slots are taken; closing a window frees one.
+    ErrorTooManyWindows = 6,
 }
 
 impl WindowResult {
@@ -167,6 +612,7 @@ impl WindowResult {
             3 => WindowResult::ErrorInvalidDimensions,
             4 => WindowResult::ErrorBufferTooLarge,
             5 => WindowResult::ErrorInvalidMessage,
+            6 => WindowResult::ErrorTooManyWindows,
             _ => WindowResult::ErrorInvalidMessage,
         }
     }
@@ -185,4 +631,15 @@ pub struct CreateWindowResponse {
     /// Opaque shared-buffer ID — client passes this to sys_map_shared_buf
     /// to get a writable pointer to the window's pixel backing store.
     pub shared_buf_id: u64,
+    /// The size granted, after clamping to the request's limits.
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Response to GetWindowBounds
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct GetWindowBoundsResponse {
+    pub result: WindowResult,
+    pub bounds: WindowBounds,
 }
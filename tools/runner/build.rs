@@ -4,6 +4,18 @@ use std::os::unix::fs::symlink;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::{env, io};
+use kernel_api_types::crc32::{crc32, MODULE_CHECKSUMS};
+
+/// Boot modules always put on the ISO, by file name.
+const MODULES: [&str; 7] = [
+    "init_task",
+    "display_server",
+    "net_server",
+    "net_stack",
+    "serial_server",
+    "bouncing_cube_1",
+    "bouncing_cube_2",
+];
 
 fn main() {
     check_command_exists("xorriso");
@@ -47,6 +59,12 @@ fn main() {
         Some("scheduler")
     } else if env::var("CARGO_FEATURE_TEST_ELF").is_ok() {
         Some("elf")
+    } else if env::var("CARGO_FEATURE_TEST_POWER").is_ok() {
+        Some("power")
+    } else if env::var("CARGO_FEATURE_TEST_PCI").is_ok() {
+        Some("pci")
+    } else if env::var("CARGO_FEATURE_TEST_NET").is_ok() {
+        Some("net")
     } else if env::var("CARGO_FEATURE_TEST_SCHED").is_ok() {
         Some("sched")
     } else if env::var("CARGO_FEATURE_TEST_SCHED_NOELF").is_ok() {
@@ -91,6 +109,18 @@ fn main() {
     let display_server_executable_file = env::var("CARGO_BIN_FILE_DISPLAY_SERVER").unwrap();
     ensure_symlink(display_server_executable_file, iso_dir.join("display_server")).unwrap();
 
+    // Net Server
+    let net_server_executable_file = env::var("CARGO_BIN_FILE_NET_SERVER").unwrap();
+    ensure_symlink(net_server_executable_file, iso_dir.join("net_server")).unwrap();
+
+    // Net Stack
+    let net_stack_executable_file = env::var("CARGO_BIN_FILE_NET_STACK").unwrap();
+    ensure_symlink(net_stack_executable_file, iso_dir.join("net_stack")).unwrap();
+
+    // Serial Server
+    let serial_server_executable_file = env::var("CARGO_BIN_FILE_SERIAL_SERVER").unwrap();
+    ensure_symlink(serial_server_executable_file, iso_dir.join("serial_server")).unwrap();
+
     // User Land: Bouncing Cube 1
     let bouncing_cube_1_executable_file = env::var("CARGO_BIN_FILE_USER_LAND_BOUNCING_CUBE_1")
         .or_else(|_| env::var("CARGO_BIN_FILE_USER_LAND_bouncing_cube_1"))
@@ -109,6 +139,17 @@ fn main() {
         ensure_symlink(utest, iso_dir.join("utest")).unwrap();
     }
 
+    // Record each module's CRC-32 so the kernel can detect a corrupt one at boot.
+    let mut checksums = String::new();
+    for module in MODULES.iter().chain(env::var("CARGO_FEATURE_USERSPACE_TEST").is_ok().then_some(&"utest")) {
+        let bytes = std::fs::read(iso_dir.join(module)).unwrap();
+        checksums += &format!("{module} {:08x}\n", crc32(&bytes));
+    }
+    let checksums_file = iso_dir.join(MODULE_CHECKSUMS);
+    // Don't write through a symlink left by an older build.
+    let _ = remove_file(&checksums_file);
+    std::fs::write(checksums_file, checksums).unwrap();
+
     // Copy files from the Limine packaeg into `boot/limine`
     let out_limine_dir = boot_dir.join("limine");
     create_dir_all(&out_limine_dir).unwrap();
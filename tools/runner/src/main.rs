@@ -1,5 +1,7 @@
+use kernel_api_types::serial::SERIAL_TEST_MARKER;
 use std::fs;
-use std::process::Command;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
 use std::{env, process};
 
 fn main() {
@@ -36,8 +38,45 @@ fn main() {
     qemu.arg("-serial").arg("stdio");
     qemu.arg("-device").arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
     qemu.arg("-cpu").arg("host");
+    // The mouse is relative PS/2 unless BOS_ABSOLUTE_MOUSE is set, which turns
+    // on QEMU's vmmouse absolute pointer (the kernel has no USB for usb-tablet).
+    let vmport = if env::var_os("BOS_ABSOLUTE_MOUSE").is_some() { "on" } else { "off" };
+    qemu.arg("-machine").arg(format!("vmport={vmport}"));
+    // e1000 is QEMU's default NIC; spell it out since the driver only knows that one.
+    qemu.arg("-nic").arg("user,model=e1000");
+    if cfg!(feature = "kernel_test") {
+        // QEMU's educational device: raises an MSI on request, for the PCI tests.
+        qemu.arg("-device").arg("edu");
+    }
     // qemu.arg("-display").arg("none");
 
+    if cfg!(feature = "userspace_test") {
+        process::exit(run_checking_serial_marker(qemu));
+    }
+
     let exit_status = qemu.status().expect("Failed to run QEMU");
     process::exit(exit_status.code().unwrap_or(1));
+}
+
+/// Run QEMU, passing its serial output through, and turn a passing utest run
+/// (exit code 33) into a failure (35) if the line utest writes through the
+/// "serial" service never reached the host.
+fn run_checking_serial_marker(mut qemu: Command) -> i32 {
+    let mut child = qemu.stdout(Stdio::piped()).spawn().expect("Failed to run QEMU");
+    let marker = SERIAL_TEST_MARKER.as_bytes();
+    let mut seen = false;
+    let mut stdout = io::stdout();
+    for line in BufReader::new(child.stdout.take().unwrap()).split(b'\n') {
+        let Ok(line) = line else { break };
+        let _ = stdout.write_all(&line);
+        let _ = stdout.write_all(b"\n");
+        let _ = stdout.flush();
+        seen |= line.windows(marker.len()).any(|w| w == marker);
+    }
+    let code = child.wait().expect("Failed to wait for QEMU").code().unwrap_or(1);
+    if code == 33 && !seen {
+        eprintln!("runner: utest's serial service output never reached the host");
+        return 35;
+    }
+    code
 }
\ No newline at end of file
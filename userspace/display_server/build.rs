@@ -1,3 +1,7 @@
 fn main() {
-    println!("cargo:rustc-link-arg=-eentry_point");
+    // Only the kernel-loaded binary starts at `entry_point`; host unit test
+    // builds keep the normal runtime entry.
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        println!("cargo:rustc-link-arg=-eentry_point");
+    }
 }
@@ -1,29 +1,59 @@
-use crate::cursor::{CURSOR_H, CURSOR_IMAGE, CURSOR_MASK, CURSOR_W};
-use crate::window::Window;
+use crate::cursor::{requested_or_default, DEFAULT_CURSOR};
+use crate::window::{KernelSharedBufs, Window};
+use crate::z_order::ZOrder;
+use embedded_graphics::draw_target::DrawTarget;
+use embedded_graphics::geometry::{OriginDimensions, Size};
+use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
+use kernel_api_types::graphics::DisplayInfo;
 use kernel_api_types::window::*;
-use kernel_api_types::{IPC_OK, MMAP_WRITE};
+use kernel_api_types::{MouseEvent, SysError, MMAP_HUGE, MMAP_WRITE, MOUSE_ABSOLUTE, MOUSE_ABS_MAX};
+use ulib::ipc::Frame;
 
-pub const MAX_WINDOWS: usize = 32;
 const MAX_MSG_SIZE: usize = 4096;
 
+/// Capacity of the request channel, in messages.
+pub const CHANNEL_CAPACITY: u64 = 16;
+/// Requests queued at the start of a drain that count as falling behind.
+const OVERLOAD_BACKLOG: u64 = CHANNEL_CAPACITY * 3 / 4;
+/// Updates from one window merged into a composite that make it a culprit.
+const THROTTLE_MIN_UPDATES: u32 = 2;
+/// Composites between two throttle hints to the same window.
+const THROTTLE_INTERVAL: u64 = 30;
+/// Present acks (see `UPDATE_WANTS_ACK`) held until the next flush.
+const MAX_PENDING_ACKS: usize = CHANNEL_CAPACITY as usize;
+
 pub struct Compositor {
     display: ulib::display::Display,
-    display_info: kernel_api_types::graphics::DisplayInfo,
+    display_info: DisplayInfo,
     windows: [Option<Window>; MAX_WINDOWS],
     next_window_id: WindowId,
     recv_endpoint: u64,
-    /// z_order[0] = bottom-most, z_order[n_windows-1] = top-most
-    z_order: [WindowId; MAX_WINDOWS],
-    n_windows: usize,
-    /// Pre-rendered gradient background (width × height pixels, native fb format)
-    background_buf: *mut u32,
+    /// Broadcast endpoint registered as `DISPLAY_EVENTS_SERVICE`, if it
+    /// could be created.
+    events_endpoint: Option<u64>,
+    /// Subscription to the kernel's `KERNEL_DISPLAY_EVENTS_SERVICE`, if it
+    /// could be made.
+    framebuffer_events: Option<u64>,
+    z_order: ZOrder,
+    /// Pre-rendered gradient background (width × height pixels, native fb format).
+    /// `None` if it couldn't be allocated; `background_pixel` is painted instead.
+    background_buf: Option<*mut u32>,
+    background_pixel: u32,
     /// Off-screen composite: background + all windows blended, no cursor.
     /// Cursor movement reads only this buffer — never touches window shared memory
     /// mid-render, eliminating tearing.
     scene_buf: *mut u32,
+    /// Pixels `scene_buf` and `background_buf` have room for.
+    buffer_pixels: usize,
+    /// Receive buffer for IPC messages (`MAX_MSG_SIZE` bytes)
+    msg_buf: *mut u8,
     /// Current cursor position (hot spot, clamped to screen)
     cursor_x: i32,
     cursor_y: i32,
+    /// The cursor drawn there, and the window that set it, if it isn't
+    /// `DEFAULT_CURSOR`.
+    cursor: CursorImage,
+    cursor_owner: Option<WindowId>,
     /// Cursor colours pre-built in native framebuffer pixel format
     cursor_black: u32,
     cursor_white: u32,
@@ -32,11 +62,45 @@ pub struct Compositor {
     pending_scene_update: bool,
     /// True when a full redraw is needed (window add/remove/reorder)
     pending_full_redraw: bool,
+    /// Only composite on an explicit `Step` request (see `WindowMessageType::SetStepMode`).
+    step_mode: bool,
+    /// Composites that presented something, reported to step-mode clients.
+    composites: u64,
+    /// Windows blitted into the scene, reported to step-mode clients.
+    window_blits: u64,
+    /// Reply endpoints of updates that asked for an ack, answered at the end
+    /// of the next flush.
+    pending_acks: [u64; MAX_PENDING_ACKS],
+    n_pending_acks: usize,
+}
+
+/// Buffers the compositor needs besides the display's own back buffer.
+struct Buffers {
+    msg: *mut u8,
+    scene: *mut u32,
+    background: Option<*mut u32>,
+}
+
+/// Allocate the compositor's buffers with `mmap`.
+///
+/// The message and scene buffers are required: without them the compositor can
+/// neither receive requests nor composite windows, so their failure is returned
+/// and the caller is expected to exit. The background is only a cache of the
+/// gradient; if it can't be allocated the compositor falls back to a solid colour.
+fn alloc_buffers(
+    screen_bytes: u64,
+    mut mmap: impl FnMut(u64) -> Result<*mut u8, SysError>,
+) -> Result<Buffers, SysError> {
+    let msg = mmap(MAX_MSG_SIZE as u64)?;
+    let scene = mmap(screen_bytes)? as *mut u32;
+    let background = mmap(screen_bytes).ok().map(|p| p as *mut u32);
+    Ok(Buffers { msg, scene, background })
 }
 
 impl Compositor {
-    pub fn new(recv_endpoint: u64) -> Self {
-        let display = ulib::display::Display::new();
+    /// Set up the compositor. Fails with `OutOfMemory` if a required buffer
+    /// couldn't be allocated (see `alloc_buffers`).
+    pub fn new(recv_endpoint: u64, events_endpoint: Option<u64>, framebuffer_events: Option<u64>) -> Result<Self, SysError> {
         let display_info = ulib::sys_get_display_info();
 
         const NONE_WINDOW: Option<Window> = None;
@@ -45,104 +109,78 @@ impl Compositor {
         let height = display_info.height as usize;
         let screen_pixels = width * height;
 
-        // Pre-render gradient background
-        let bg_bytes = (screen_pixels * 4) as u64;
-        let background_buf = ulib::sys_mmap(bg_bytes, MMAP_WRITE) as *mut u32;
-
-        if !background_buf.is_null() {
-            for y in 0..height {
-                let t = if height > 1 { y * 255 / (height - 1) } else { 0 } as u32;
-                let r = (0x1eu32 * (255 - t) + 0x0au32 * t) / 255;
-                let g = (0x3au32 * (255 - t) + 0x0au32 * t) / 255;
-                let b = (0x5fu32 * (255 - t) + 0x0fu32 * t) / 255;
-                let pixel = display_info.build_pixel(r as u8, g as u8, b as u8);
-                unsafe {
-                    for x in 0..width {
-                        *background_buf.add(y * width + x) = pixel;
-                    }
-                }
-            }
-        }
+        // Screen-sized buffers are megabytes; 2 MiB pages save mapping them 4 KiB at a time.
+        let buffers = alloc_buffers((screen_pixels * 4) as u64, |size| ulib::sys_mmap(size, MMAP_WRITE | MMAP_HUGE))?;
+        let display = ulib::display::Display::new();
+        let scene_buf = buffers.scene;
+        let background_buf = buffers.background;
+        let background_pixel = display_info.build_pixel(0x1e, 0x3a, 0x5f);
 
-        // Allocate scene buffer (same size as framebuffer); initialise with background.
-        let scene_buf = ulib::sys_mmap(bg_bytes, MMAP_WRITE) as *mut u32;
-        if !scene_buf.is_null() && !background_buf.is_null() {
-            unsafe { core::ptr::copy_nonoverlapping(background_buf, scene_buf, screen_pixels) };
+        if let Some(background_buf) = background_buf {
+            render_background(background_buf, &display_info);
+        } else {
+            ulib::sys_debug_log_str("display_server: no memory for background, using a solid colour");
         }
 
         let cursor_black = display_info.build_pixel(0, 0, 0);
         let cursor_white = display_info.build_pixel(255, 255, 255);
 
-        Compositor {
+        Ok(Compositor {
             display,
             display_info,
             windows: [NONE_WINDOW; MAX_WINDOWS],
             next_window_id: 1,
             recv_endpoint,
-            z_order: [0; MAX_WINDOWS],
-            n_windows: 0,
+            events_endpoint,
+            framebuffer_events,
+            z_order: ZOrder::new(),
             background_buf,
+            background_pixel,
             scene_buf,
+            buffer_pixels: screen_pixels,
+            msg_buf: buffers.msg,
             cursor_x: display_info.width as i32 / 2,
             cursor_y: display_info.height as i32 / 2,
+            cursor: DEFAULT_CURSOR,
+            cursor_owner: None,
             cursor_black,
             cursor_white,
             pending_damage: None,
             pending_scene_update: false,
             pending_full_redraw: false,
-        }
-    }
-
-    // --- Z-order helpers ---
-
-    fn z_push(&mut self, id: WindowId) {
-        if self.n_windows < MAX_WINDOWS {
-            self.z_order[self.n_windows] = id;
-            self.n_windows += 1;
-        }
-    }
-
-    fn z_remove(&mut self, id: WindowId) {
-        if let Some(pos) = self.z_order[..self.n_windows].iter().position(|&x| x == id) {
-            for i in pos..self.n_windows - 1 {
-                self.z_order[i] = self.z_order[i + 1];
-            }
-            self.n_windows -= 1;
-        }
-    }
-
-    fn z_raise(&mut self, id: WindowId) {
-        self.z_remove(id);
-        self.z_push(id);
-    }
-
-    fn z_lower(&mut self, id: WindowId) {
-        self.z_remove(id);
-        if self.n_windows < MAX_WINDOWS {
-            for i in (0..self.n_windows).rev() {
-                self.z_order[i + 1] = self.z_order[i];
-            }
-            self.z_order[0] = id;
-            self.n_windows += 1;
-        }
+            step_mode: false,
+            composites: 0,
+            window_blits: 0,
+            pending_acks: [0; MAX_PENDING_ACKS],
+            n_pending_acks: 0,
+        })
     }
 
     // --- Damage / pending state helpers ---
 
     fn screen_rect(&self, x: i32, y: i32, w: u32, h: u32) -> Option<DirtyRect> {
-        let x0 = x.max(0) as u32;
-        let y0 = y.max(0) as u32;
-        let x1 = ((x + w as i32).max(0) as u32).min(self.display_info.width);
-        let y1 = ((y + h as i32).max(0) as u32).min(self.display_info.height);
-        if x0 < x1 && y0 < y1 {
-            Some(DirtyRect { x: x0, y: y0, w: x1 - x0, h: y1 - y0 })
-        } else {
-            None
-        }
+        clip_blit(x, y, w, h, self.display_info.width, self.display_info.height).map(|c| c.dst)
     }
 
     fn cursor_rect(&self) -> Option<DirtyRect> {
-        self.screen_rect(self.cursor_x, self.cursor_y, CURSOR_W, CURSOR_H)
+        let x = self.cursor_x.saturating_sub_unsigned(self.cursor.hot_x);
+        let y = self.cursor_y.saturating_sub_unsigned(self.cursor.hot_y);
+        self.screen_rect(x, y, self.cursor.width, self.cursor.height)
+    }
+
+    /// Redraw where the cursor was, after moving it or changing its image,
+    /// and where it is now. No scene update is needed: the cursor is drawn
+    /// over scene_buf.
+    fn damage_cursor(&mut self, old_rect: Option<DirtyRect>) {
+        let cursor_damage = match (old_rect, self.cursor_rect()) {
+            (Some(mut a), Some(b)) => { a.expand(b.x, b.y, b.w, b.h); Some(a) }
+            (Some(a), None) => Some(a),
+            (None, Some(b)) => Some(b),
+            (None, None) => None,
+        };
+        if let Some(cd) = cursor_damage {
+            self.expand_pending(cd);
+        }
     }
 
     fn expand_pending(&mut self, rect: DirtyRect) {
@@ -165,87 +203,99 @@ impl Compositor {
 
     // --- Scene buffer compositing ---
 
-    /// Blit raw pixels from `src` into `scene_buf` (same clipping logic as Display::blit_raw).
-    fn blit_to_scene(&mut self, src: *const u32, src_width: u32, dst_x: i32, dst_y: i32, w: u32, h: u32) {
-        if self.scene_buf.is_null() {
-            return;
-        }
+    /// Blit the `w × h` rect at `(src_x, src_y)` of `src`, whose rows are
+    /// `src_stride` pixels apart, into `scene_buf` (same clipping logic as
+    /// Display::blit_raw_strided).
+    #[allow(clippy::too_many_arguments)]
+    fn blit_to_scene(
+        &mut self,
+        src: *const u32,
+        src_stride: u32,
+        src_x: u32,
+        src_y: u32,
+        dst_x: i32,
+        dst_y: i32,
+        w: u32,
+        h: u32,
+    ) {
         let screen_w = self.display_info.width as usize;
-        let screen_h = self.display_info.height as usize;
-
-        let x0 = dst_x.max(0) as usize;
-        let y0 = dst_y.max(0) as usize;
-        let x1 = ((dst_x + w as i32).max(0) as usize).min(screen_w);
-        let y1 = ((dst_y + h as i32).max(0) as usize).min(screen_h);
-        if x0 >= x1 || y0 >= y1 {
+        let Some(clip) = clip_blit(dst_x, dst_y, w, h, self.display_info.width, self.display_info.height) else {
             return;
-        }
-        let clipped_w = x1 - x0;
-        let src_x_off = (x0 as i32 - dst_x).max(0) as usize;
-        let src_y_off = (y0 as i32 - dst_y).max(0) as usize;
+        };
+        let d = clip.dst;
 
-        for row in 0..(y1 - y0) {
-            let src_off = (src_y_off + row) * src_width as usize + src_x_off;
-            let dst_off = (y0 + row) * screen_w + x0;
+        let src_x = src_x as usize + clip.src_x as usize;
+        let src_y = src_y as usize + clip.src_y as usize;
+        for row in 0..d.h as usize {
+            let src_off = (src_y + row) * src_stride as usize + src_x;
+            let dst_off = (d.y as usize + row) * screen_w + d.x as usize;
             unsafe {
-                core::ptr::copy_nonoverlapping(src.add(src_off), self.scene_buf.add(dst_off), clipped_w);
+                core::ptr::copy_nonoverlapping(src.add(src_off), self.scene_buf.add(dst_off), d.w as usize);
+            }
+        }
+    }
+
+    /// Paint the background into `rect` of scene_buf. `rect` must be on screen.
+    fn fill_background(&mut self, rect: DirtyRect) {
+        let screen_w = self.display_info.width;
+        match self.background_buf {
+            Some(bg) => {
+                self.blit_to_scene(bg, screen_w, rect.x, rect.y, rect.x as i32, rect.y as i32, rect.w, rect.h);
+            }
+            None => {
+                for row in rect.y..rect.y + rect.h {
+                    let off = row as usize * screen_w as usize + rect.x as usize;
+                    let dst = unsafe { core::slice::from_raw_parts_mut(self.scene_buf.add(off), rect.w as usize) };
+                    dst.fill(self.background_pixel);
+                }
             }
         }
     }
 
     /// Update scene_buf for `damage` region: blit background then all overlapping windows.
     fn update_scene_region(&mut self, damage: DirtyRect) {
-        // Background
-        if !self.background_buf.is_null() {
-            let screen_w = self.display_info.width;
-            let src = unsafe {
-                self.background_buf.add(damage.y as usize * screen_w as usize + damage.x as usize)
-            };
-            self.blit_to_scene(src, screen_w, damage.x as i32, damage.y as i32, damage.w, damage.h);
-        }
+        self.fill_background(damage);
 
         // Windows in z-order (only those overlapping damage)
-        for i in 0..self.n_windows {
-            let id = self.z_order[i];
+        for i in 0..self.z_order.ids().len() {
+            let id = self.z_order.ids()[i];
             // Collect fields to avoid borrow conflict with blit_to_scene(&mut self)
             let info = self.windows.iter()
                 .filter_map(|w| w.as_ref())
                 .find(|w| w.id == id)
-                .map(|w| (w.x, w.y, w.width, w.height, w.buffer as *const u32));
-
-            if let Some((wx, wy, ww, wh, wbuf)) = info {
-                let wx1 = wx + ww as i32;
-                let wy1 = wy + wh as i32;
-                let dx1 = damage.x as i32 + damage.w as i32;
-                let dy1 = damage.y as i32 + damage.h as i32;
-                if wx >= dx1 || wx1 <= damage.x as i32 || wy >= dy1 || wy1 <= damage.y as i32 {
+                .map(|w| (w.x, w.y, w.width, w.screen_rect, w.buffer as *const u32));
+
+            debug_assert!(info.is_some(), "z-order refers to closed window {id}");
+            if let Some((wx, wy, ww, screen_rect, wbuf)) = info {
+                // Blit only the part inside the damage: the rest of this window
+                // may be covered by windows above it that aren't redrawn.
+                let Some(r) = screen_rect.and_then(|r| r.intersect(&damage)) else {
                     continue;
-                }
-                self.blit_to_scene(wbuf, ww, wx, wy, ww, wh);
+                };
+                let (src_x, src_y) = ((r.x as i32 - wx) as u32, (r.y as i32 - wy) as u32);
+                self.blit_to_scene(wbuf, ww, src_x, src_y, r.x as i32, r.y as i32, r.w, r.h);
+                self.window_blits += 1;
             }
         }
     }
 
     /// Rebuild the entire scene_buf from background + all windows.
     fn update_scene_full(&mut self) {
-        if self.scene_buf.is_null() {
-            return;
-        }
-        // Copy background
-        if !self.background_buf.is_null() {
-            let n = self.display_info.width as usize * self.display_info.height as usize;
-            unsafe { core::ptr::copy_nonoverlapping(self.background_buf, self.scene_buf, n) };
-        }
+        let (w, h) = (self.display_info.width, self.display_info.height);
+        self.fill_background(DirtyRect { x: 0, y: 0, w, h });
         // Blit all windows in z-order
-        for i in 0..self.n_windows {
-            let id = self.z_order[i];
+        for i in 0..self.z_order.ids().len() {
+            let id = self.z_order.ids()[i];
             let info = self.windows.iter()
                 .filter_map(|w| w.as_ref())
                 .find(|w| w.id == id)
-                .map(|w| (w.x, w.y, w.width, w.height, w.buffer as *const u32));
+                .map(|w| (w.x, w.y, w.width, w.height, w.screen_rect, w.buffer as *const u32));
 
-            if let Some((wx, wy, ww, wh, wbuf)) = info {
-                self.blit_to_scene(wbuf, ww, wx, wy, ww, wh);
+            debug_assert!(info.is_some(), "z-order refers to closed window {id}");
+            // A window entirely off screen has nothing to blit.
+            if let Some((wx, wy, ww, wh, Some(_), wbuf)) = info {
+                self.blit_to_scene(wbuf, ww, 0, 0, wx, wy, ww, wh);
+                self.window_blits += 1;
             }
         }
     }
@@ -253,33 +303,35 @@ impl Compositor {
     /// Blit `damage` region from scene_buf into the display back buffer, draw cursor
     /// on top if it overlaps, then present.
     fn present_region(&mut self, damage: DirtyRect) {
-        if !self.scene_buf.is_null() {
-            let screen_w = self.display_info.width;
-            let src = unsafe {
-                self.scene_buf.add(damage.y as usize * screen_w as usize + damage.x as usize)
-            };
-            self.display.blit_raw(src, screen_w, damage.x as i32, damage.y as i32, damage.w, damage.h);
-        }
+        self.display.blit_raw_strided(
+            self.scene_buf,
+            self.display_info.width,
+            damage.x,
+            damage.y,
+            damage.x as i32,
+            damage.y as i32,
+            damage.w,
+            damage.h,
+        );
 
         // Cursor is always on top — draw it if it overlaps the damage rect
-        if let Some(cr) = self.cursor_rect() {
-            let dx1 = damage.x + damage.w;
-            let dy1 = damage.y + damage.h;
-            if cr.x < dx1 && cr.x + cr.w > damage.x && cr.y < dy1 && cr.y + cr.h > damage.y {
-                self.display.blit_cursor(
-                    self.cursor_x, self.cursor_y,
-                    &CURSOR_MASK, &CURSOR_IMAGE,
-                    CURSOR_W, CURSOR_H,
-                    self.cursor_black, self.cursor_white,
-                );
-            }
+        if self.cursor_rect().is_some_and(|cr| cr.overlaps(&damage)) {
+            self.display.blit_cursor(
+                self.cursor_x, self.cursor_y,
+                &self.cursor,
+                self.cursor_black, self.cursor_white,
+            );
         }
 
         self.display.present();
     }
 
-    /// Flush all pending damage: update scene if needed, then present.
+    /// Flush all pending damage: update scene if needed, then present, then
+    /// ack the updates that asked for it.
     fn flush(&mut self) {
+        if self.pending_full_redraw || self.pending_damage.is_some() {
+            self.composites += 1;
+        }
         if self.pending_full_redraw {
             self.pending_full_redraw = false;
             self.pending_scene_update = false;
@@ -295,271 +347,701 @@ impl Compositor {
             }
             self.present_region(damage);
         }
+        for &ep in &self.pending_acks[..self.n_pending_acks] {
+            let _ = ulib::sys_channel_send(ep, &[1]);
+            let _ = ulib::sys_channel_close(ep);
+        }
+        self.n_pending_acks = 0;
+    }
+
+    /// Ack `ep` once the next flush has presented its update.
+    fn queue_ack(&mut self, ep: u64) {
+        if self.n_pending_acks == MAX_PENDING_ACKS {
+            // Composite early rather than drop an ack a client is blocked on.
+            self.flush();
+        }
+        self.pending_acks[self.n_pending_acks] = ep;
+        self.n_pending_acks += 1;
     }
 
     // --- Window handlers ---
 
     fn handle_create_window(&mut self, req: &CreateWindowRequest, reply_ep: u64) {
-        if req.width == 0 || req.height == 0 || req.width > 4096 || req.height > 4096 {
-            self.send_response(reply_ep, &CreateWindowResponse {
+        if req.width == 0 || req.height == 0
+            || req.width > MAX_WINDOW_DIMENSION || req.height > MAX_WINDOW_DIMENSION
+            || !req.limits.is_valid()
+        {
+            self.send_response(reply_ep, WindowMessageType::CreateWindow, &CreateWindowResponse {
                 result: WindowResult::ErrorInvalidDimensions,
                 window_id: 0,
                 shared_buf_id: 0,
+                width: 0,
+                height: 0,
             });
             return;
         }
+        let (width, height) = req.limits.clamp(req.width, req.height);
 
         let slot_idx = match self.windows.iter().position(|w| w.is_none()) {
             Some(i) => i,
             None => {
-                self.send_response(reply_ep, &CreateWindowResponse {
-                    result: WindowResult::ErrorOutOfMemory,
+                self.send_response(reply_ep, WindowMessageType::CreateWindow, &CreateWindowResponse {
+                    result: WindowResult::ErrorTooManyWindows,
                     window_id: 0,
                     shared_buf_id: 0,
+                    width: 0,
+                    height: 0,
                 });
                 return;
             }
         };
 
         let window_id = self.next_window_id;
-        self.next_window_id += 1;
 
-        match Window::new(window_id, req.x, req.y, req.width, req.height) {
-            Some(window) => {
+        // Only the requesting client may map the window's pixels. A failure
+        // has already released the buffer; the slot and ID stay free.
+        match Window::new(window_id, req.x, req.y, width, height, req.client_task_id, &mut KernelSharedBufs) {
+            Ok(mut window) => {
+                window.screen_rect = self.screen_rect(window.x, window.y, window.width, window.height);
+                window.limits = req.limits;
                 let shared_buf_id = window.shared_buf_id;
+                self.next_window_id += 1;
                 self.windows[slot_idx] = Some(window);
-                self.z_push(window_id);
-                self.send_response(reply_ep, &CreateWindowResponse {
+                self.z_order.push(window_id, false);
+                self.send_response(reply_ep, WindowMessageType::CreateWindow, &CreateWindowResponse {
                     result: WindowResult::Ok,
                     window_id,
                     shared_buf_id,
+                    width,
+                    height,
                 });
                 self.mark_full_redraw();
             }
-            None => {
-                self.send_response(reply_ep, &CreateWindowResponse {
-                    result: WindowResult::ErrorOutOfMemory,
+            Err(e) => {
+                // Anything but running out of memory means the grant failed,
+                // i.e. `client_task_id` named no task.
+                let result = if e == SysError::OutOfMemory {
+                    WindowResult::ErrorOutOfMemory
+                } else {
+                    WindowResult::ErrorInvalidMessage
+                };
+                self.send_response(reply_ep, WindowMessageType::CreateWindow, &CreateWindowResponse {
+                    result,
                     window_id: 0,
                     shared_buf_id: 0,
+                    width: 0,
+                    height: 0,
                 });
             }
         }
     }
 
-    fn handle_update_window(&mut self, header: &UpdateWindowRequest) {
-        let pos = {
-            let window = match self.windows.iter_mut()
-                .filter_map(|w| w.as_mut())
-                .find(|w| w.id == header.window_id)
-            {
-                Some(w) => w,
-                None => return,
-            };
-            if header.dirty_x + header.dirty_width > window.width
-                || header.dirty_y + header.dirty_height > window.height
-            {
-                return;
+    /// Mark an updated part of a window for compositing. `ack_ep`, if the
+    /// client asked for an ack, is answered after the next flush, or closed
+    /// at once if the update is rejected.
+    fn handle_update_window(&mut self, header: &UpdateWindowRequest, ack_ep: Option<u64>) {
+        let pos = self.windows.iter_mut()
+            .filter_map(|w| w.as_mut())
+            .find(|w| w.id == header.window_id)
+            .filter(|w| {
+                header.dirty_x + header.dirty_width <= w.width && header.dirty_y + header.dirty_height <= w.height
+            })
+            .map(|window| {
+                window.pending_updates = window.pending_updates.saturating_add(1);
+                (window.x + header.dirty_x as i32, window.y + header.dirty_y as i32)
+            });
+        let Some(pos) = pos else {
+            if let Some(ep) = ack_ep {
+                let _ = ulib::sys_channel_close(ep);
             }
-            (window.x, window.y, window.width, window.height)
+            return;
         };
 
-        if let Some(rect) = self.screen_rect(pos.0, pos.1, pos.2, pos.3) {
+        // Only the updated part of the window needs compositing.
+        if let Some(rect) = self.screen_rect(pos.0, pos.1, header.dirty_width, header.dirty_height) {
             self.mark_damage(rect);
         }
+        if let Some(ep) = ack_ep {
+            self.queue_ack(ep);
+        }
     }
 
+    /// Close a window and free its buffer.
+    ///
+    /// An `UpdateWindow` for this window may have been handled earlier in the
+    /// same drain, leaving damage that the next flush would composite. The
+    /// window is therefore unlinked and that damage replaced by a full redraw
+    /// (which only reads live windows) *before* the buffer is unmapped, so no
+    /// flush can read the freed pages. An update arriving after the close finds
+    /// no window and is dropped.
     fn handle_close_window(&mut self, req: &CloseWindowRequest) {
-        for slot in self.windows.iter_mut() {
-            if let Some(window) = slot {
-                if window.id == req.window_id {
-                    ulib::sys_munmap(window.buffer as *mut u8, window.buf_size);
-                    let id = window.id;
-                    let shared_buf_id = window.shared_buf_id;
-                    *slot = None;
-                    self.z_remove(id);
-                    ulib::sys_destroy_shared_buf(shared_buf_id);
-                    self.mark_full_redraw();
-                    return;
-                }
-            }
+        let Some(slot) = self.windows.iter_mut().find(|w| w.as_ref().is_some_and(|w| w.id == req.window_id)) else {
+            return;
+        };
+        let window = slot.take().unwrap();
+        self.z_order.remove(window.id);
+        self.mark_full_redraw();
+        if self.cursor_owner == Some(window.id) {
+            self.set_cursor(DEFAULT_CURSOR, None);
         }
+
+        window.release(&mut KernelSharedBufs);
     }
 
     fn handle_move_window(&mut self, req: &MoveWindowRequest) {
-        let old_pos = self.windows.iter_mut()
+        let (screen_w, screen_h) = (self.display_info.width, self.display_info.height);
+        let Some(window) = self.windows.iter_mut()
             .filter_map(|w| w.as_mut())
             .find(|w| w.id == req.window_id)
-            .map(|window| {
-                let old = (window.x, window.y, window.width, window.height);
-                window.x = req.x;
-                window.y = req.y;
-                old
-            });
+        else {
+            return;
+        };
+        let old_rect = window.screen_rect;
+        (window.x, window.y) = clamp_window_position(req.x, req.y, window.width, window.height, screen_w, screen_h);
+        let new_rect = clip_blit(window.x, window.y, window.width, window.height, screen_w, screen_h).map(|c| c.dst);
+        window.screen_rect = new_rect;
 
-        if let Some((ox, oy, w, h)) = old_pos {
-            let mut damage = self.screen_rect(ox, oy, w, h);
-            if let Some(new_rect) = self.screen_rect(req.x, req.y, w, h) {
-                match &mut damage {
-                    Some(d) => d.expand(new_rect.x, new_rect.y, new_rect.w, new_rect.h),
-                    None => damage = Some(new_rect),
+        let mut damage = old_rect;
+        if let Some(r) = new_rect {
+            match &mut damage {
+                Some(d) => d.expand(r.x, r.y, r.w, r.h),
+                None => damage = Some(r),
+            }
+        }
+        if let Some(d) = damage {
+            self.mark_damage(d);
+        }
+    }
+
+    /// Resize a window within its limits. The whole scene is redrawn, since
+    /// the window may have shrunk off what it used to cover.
+    fn handle_resize_window(&mut self, req: &ResizeWindowRequest, reply_ep: u64) {
+        let (screen_w, screen_h) = (self.display_info.width, self.display_info.height);
+        let response = match self.windows.iter_mut().filter_map(|w| w.as_mut()).find(|w| w.id == req.window_id) {
+            Some(window) => match window.resize(req.width, req.height, &mut KernelSharedBufs) {
+                Ok((width, height)) => {
+                    window.screen_rect = clip_blit(window.x, window.y, width, height, screen_w, screen_h).map(|c| c.dst);
+                    ResizeWindowResponse { result: WindowResult::Ok, width, height }
                 }
+                Err(_) => ResizeWindowResponse { result: WindowResult::ErrorOutOfMemory, width: window.width, height: window.height },
+            },
+            None => ResizeWindowResponse { result: WindowResult::ErrorInvalidWindowId, width: 0, height: 0 },
+        };
+        if response.result.is_ok() {
+            self.mark_full_redraw();
+        }
+        self.send_response(reply_ep, WindowMessageType::ResizeWindow, &response);
+    }
+
+    fn handle_set_window_events(&mut self, req: &SetWindowEventsRequest, events_ep: u64) {
+        match self.windows.iter_mut().filter_map(|w| w.as_mut()).find(|w| w.id == req.window_id) {
+            Some(window) => {
+                if let Some(old) = window.events_endpoint.replace(events_ep) {
+                    let _ = ulib::sys_channel_close(old);
+                }
+            }
+            None => {
+                let _ = ulib::sys_channel_close(events_ep);
             }
-            if let Some(d) = damage {
-                self.mark_damage(d);
+        }
+    }
+
+    /// After a drain that started `backlog` requests deep, hint every window
+    /// that sent more updates than the coming composite can show to slow
+    /// down, at most once per `THROTTLE_INTERVAL` composites. Clears the
+    /// per-window update counts either way.
+    fn send_throttle_hints(&mut self, backlog: u64) {
+        let composites = self.composites;
+        for window in self.windows.iter_mut().filter_map(|w| w.as_mut()) {
+            let updates = core::mem::take(&mut window.pending_updates);
+            let Some(ep) = window.events_endpoint else {
+                continue;
+            };
+            if !should_throttle(backlog, updates, composites, window.last_throttle) {
+                continue;
             }
+            let event = ThrottleEvent {
+                window_id: window.id,
+                coalesced_updates: updates,
+                backlog: backlog as u32,
+            };
+            // A client too busy to read its hints has a full channel; try
+            // again next time.
+            if ulib::ipc::send_typed(ep, WindowEventType::Throttle as u16, &event).is_ok() {
+                window.last_throttle = Some(composites);
+            }
+        }
+    }
+
+    /// Draw the requested cursor from now on, or the default one if the
+    /// request isn't valid. Ignored for an unknown window.
+    fn handle_set_cursor(&mut self, req: &SetCursorRequest) {
+        if !self.windows.iter().flatten().any(|w| w.id == req.window_id) {
+            return;
         }
+        let cursor = requested_or_default(&req.cursor);
+        let owner = (cursor != DEFAULT_CURSOR).then_some(req.window_id);
+        self.set_cursor(cursor, owner);
+    }
+
+    fn set_cursor(&mut self, cursor: CursorImage, owner: Option<WindowId>) {
+        let old_rect = self.cursor_rect();
+        self.cursor = cursor;
+        self.cursor_owner = owner;
+        self.damage_cursor(old_rect);
     }
 
     fn handle_raise_window(&mut self, req: &RaiseWindowRequest) {
-        self.z_raise(req.window_id);
-        self.mark_full_redraw();
+        if self.z_order.raise(req.window_id) {
+            self.mark_full_redraw();
+        }
     }
 
     fn handle_lower_window(&mut self, req: &LowerWindowRequest) {
-        self.z_lower(req.window_id);
-        self.mark_full_redraw();
+        if self.z_order.lower(req.window_id) {
+            self.mark_full_redraw();
+        }
     }
 
-    fn send_response<T>(&self, reply_ep: u64, response: &T) {
-        let bytes = unsafe {
-            core::slice::from_raw_parts(
-                response as *const T as *const u8,
-                core::mem::size_of::<T>(),
-            )
+    fn handle_set_always_on_top(&mut self, req: &SetAlwaysOnTopRequest) {
+        if self.z_order.set_on_top(req.window_id, req.enabled != 0) {
+            self.mark_full_redraw();
+        }
+    }
+
+    fn handle_window_at(&mut self, req: &WindowAtRequest, reply_ep: u64) {
+        let (x, y) = (req.x as i64, req.y as i64);
+        let hit = self.z_order.ids().iter().rev().copied().find(|&id| {
+            self.windows.iter().filter_map(|w| w.as_ref()).find(|w| w.id == id).is_some_and(|w| {
+                (w.x as i64..w.x as i64 + w.width as i64).contains(&x)
+                    && (w.y as i64..w.y as i64 + w.height as i64).contains(&y)
+            })
+        });
+        let response = match hit {
+            Some(window_id) => WindowAtResponse { result: WindowResult::Ok, window_id },
+            None => WindowAtResponse { result: WindowResult::ErrorInvalidWindowId, window_id: 0 },
         };
-        ulib::sys_channel_send(reply_ep, bytes);
-        ulib::sys_channel_close(reply_ep);
+        self.send_response(reply_ep, WindowMessageType::WindowAt, &response);
     }
 
-    fn process_message(&mut self, msg: &[u8]) {
-        if msg.is_empty() {
+    fn handle_get_window_bounds(&mut self, req: &GetWindowBoundsRequest, reply_ep: u64) {
+        let window = self.windows.iter()
+            .filter_map(|w| w.as_ref())
+            .find(|w| w.id == req.window_id);
+        let response = match window {
+            Some(w) => GetWindowBoundsResponse {
+                result: WindowResult::Ok,
+                bounds: WindowBounds { x: w.x, y: w.y, width: w.width, height: w.height },
+            },
+            None => GetWindowBoundsResponse {
+                result: WindowResult::ErrorInvalidWindowId,
+                bounds: WindowBounds { x: 0, y: 0, width: 0, height: 0 },
+            },
+        };
+        self.send_response(reply_ep, WindowMessageType::GetWindowBounds, &response);
+    }
+
+    fn handle_set_step_mode(&mut self, req: &SetStepModeRequest, reply_ep: u64) {
+        // Present whatever is already pending, so the first step only covers
+        // what the client does after this reply.
+        self.drain_mouse();
+        self.flush();
+        self.step_mode = req.enabled != 0;
+        self.send_response(reply_ep, WindowMessageType::SetStepMode, &StepResponse { composites: self.composites, window_blits: self.window_blits });
+    }
+
+    fn handle_step(&mut self, reply_ep: u64) {
+        if self.step_mode {
+            self.drain_mouse();
+            self.flush();
+        }
+        self.send_response(reply_ep, WindowMessageType::Step, &StepResponse { composites: self.composites, window_blits: self.window_blits });
+    }
+
+    /// Composite at `req`'s size from now on, within the framebuffer, and
+    /// tell subscribers before answering, so the caller's subscriptions have
+    /// the event queued by the time it gets the reply.
+    fn handle_set_display_mode(&mut self, req: &SetDisplayModeRequest, reply_ep: u64) {
+        let native = ulib::sys_get_display_info();
+        let fits = (1..=native.width).contains(&req.width) && (1..=native.height).contains(&req.height);
+        if fits && (req.width, req.height) != (self.display_info.width, self.display_info.height) {
+            self.set_mode(DisplayInfo { width: req.width, height: req.height, ..self.display_info });
+        }
+        let result = if fits { WindowResult::Ok } else { WindowResult::ErrorInvalidDimensions };
+        self.send_response(reply_ep, WindowMessageType::SetDisplayMode, &SetDisplayModeResponse {
+            result,
+            info: self.display_info,
+        });
+    }
+
+    /// Handle every queued message from the kernel's display-change
+    /// broadcast. A message only says that something changed; anyone can send
+    /// on that broadcast, so the mode itself is read back from the kernel.
+    /// The compositor returns to the framebuffer's full mode, even if
+    /// `SetDisplayMode` had shrunk it.
+    fn drain_framebuffer_events(&mut self) {
+        let Some(ep) = self.framebuffer_events else {
+            return;
+        };
+        let mut changed = false;
+        while ulib::sys_channel_poll(ep).is_ok_and(|n| n > 0) {
+            let discard = unsafe { core::slice::from_raw_parts_mut(self.msg_buf, MAX_MSG_SIZE) };
+            if ulib::sys_channel_recv(ep, discard).is_err() {
+                break;
+            }
+            changed = true;
+        }
+        if !changed {
             return;
         }
+        let native = ulib::sys_get_display_info();
+        let pixels = native.width as usize * native.height as usize;
+        if pixels > self.buffer_pixels && !self.grow_buffers(pixels) {
+            ulib::sys_debug_log_str("display_server: no memory for the new display mode, keeping the old one");
+            return;
+        }
+        if self.display.size() != Size::new(native.width, native.height) {
+            // The framebuffer itself changed size. `Display` has no way to
+            // free its old back buffer, but mode changes are rare.
+            self.display = ulib::display::Display::new();
+        }
+        if native != self.display_info {
+            self.set_mode(native);
+        }
+    }
+
+    /// Replace the scene and background buffers with ones of `pixels`
+    /// pixels. Returns false, keeping the old buffers, if the scene buffer
+    /// couldn't be allocated.
+    fn grow_buffers(&mut self, pixels: usize) -> bool {
+        let bytes = pixels as u64 * 4;
+        let Ok(scene) = ulib::sys_mmap(bytes, MMAP_WRITE | MMAP_HUGE) else {
+            return false;
+        };
+        let background = ulib::sys_mmap(bytes, MMAP_WRITE | MMAP_HUGE).ok();
+        let old_bytes = self.buffer_pixels as u64 * 4;
+        let _ = ulib::sys_munmap(self.scene_buf as *mut u8, old_bytes);
+        if let Some(bg) = self.background_buf {
+            let _ = ulib::sys_munmap(bg as *mut u8, old_bytes);
+        }
+        self.scene_buf = scene as *mut u32;
+        self.background_buf = background.map(|p| p as *mut u32);
+        self.buffer_pixels = pixels;
+        true
+    }
 
-        let msg_type = msg[0];
+    /// Composite in `info`'s mode from now on and tell `DISPLAY_EVENTS_SERVICE`
+    /// subscribers. The buffers must already hold `info.width × info.height`
+    /// pixels.
+    fn set_mode(&mut self, info: DisplayInfo) {
+        // Black out what a smaller mode no longer covers.
+        let _ = self.display.clear(Rgb888::BLACK);
+        self.display_info = info;
+        self.background_pixel = info.build_pixel(0x1e, 0x3a, 0x5f);
+        self.cursor_black = info.build_pixel(0, 0, 0);
+        self.cursor_white = info.build_pixel(255, 255, 255);
+        if let Some(bg) = self.background_buf {
+            render_background(bg, &info);
+        }
+        for window in self.windows.iter_mut().flatten() {
+            window.screen_rect = clip_blit(window.x, window.y, window.width, window.height, info.width, info.height)
+                .map(|c| c.dst);
+        }
+        self.cursor_x = self.cursor_x.min(info.width as i32 - 1);
+        self.cursor_y = self.cursor_y.min(info.height as i32 - 1);
+        self.mark_full_redraw();
+        if let Some(ep) = self.events_endpoint {
+            let event = DisplayChangedEvent { info };
+            let _ = ulib::ipc::send_typed(ep, DisplayEventType::DisplayChanged as u16, &event);
+        }
+    }
 
-        match msg_type {
-            t if t == WindowMessageType::CreateWindow as u8 => {
-                if msg.len() < 1 + core::mem::size_of::<CreateWindowRequest>() + 8 {
-                    return;
+    /// Answer a request on its one-shot reply endpoint, framed under the request's
+    /// tag, then close the endpoint.
+    fn send_response<T: Copy>(&self, reply_ep: u64, msg_type: WindowMessageType, response: &T) {
+        let _ = ulib::ipc::send_typed(reply_ep, msg_type as u16, response);
+        let _ = ulib::sys_channel_close(reply_ep);
+    }
+
+    fn process_message(&mut self, msg: &[u8]) {
+        let Some(frame) = Frame::parse(msg) else {
+            return;
+        };
+
+        match frame.tag {
+            t if t == WindowMessageType::CreateWindow as u16 => {
+                if let (Some(req), Some(reply_ep)) = (frame.read::<CreateWindowRequest>(), frame.reply_endpoint()) {
+                    self.handle_create_window(&req, reply_ep);
                 }
-                let req: CreateWindowRequest = unsafe {
-                    core::ptr::read(msg.as_ptr().add(1) as *const CreateWindowRequest)
-                };
-                let reply_ep = u64::from_le_bytes([
-                    msg[1 + core::mem::size_of::<CreateWindowRequest>()],
-                    msg[2 + core::mem::size_of::<CreateWindowRequest>()],
-                    msg[3 + core::mem::size_of::<CreateWindowRequest>()],
-                    msg[4 + core::mem::size_of::<CreateWindowRequest>()],
-                    msg[5 + core::mem::size_of::<CreateWindowRequest>()],
-                    msg[6 + core::mem::size_of::<CreateWindowRequest>()],
-                    msg[7 + core::mem::size_of::<CreateWindowRequest>()],
-                    msg[8 + core::mem::size_of::<CreateWindowRequest>()],
-                ]);
-                self.handle_create_window(&req, reply_ep);
-            }
-            t if t == WindowMessageType::UpdateWindow as u8 => {
-                if msg.len() < 1 + core::mem::size_of::<UpdateWindowRequest>() {
-                    return;
+            }
+            t if t == WindowMessageType::UpdateWindow as u16 => {
+                if let Some(req) = frame.read::<UpdateWindowRequest>() {
+                    let ack_ep = if req.flags & UPDATE_WANTS_ACK != 0 { frame.reply_endpoint() } else { None };
+                    self.handle_update_window(&req, ack_ep);
                 }
-                let header: UpdateWindowRequest = unsafe {
-                    core::ptr::read_unaligned(msg.as_ptr().add(1) as *const UpdateWindowRequest)
-                };
-                self.handle_update_window(&header);
             }
-            t if t == WindowMessageType::CloseWindow as u8 => {
-                if msg.len() < 1 + core::mem::size_of::<CloseWindowRequest>() {
-                    return;
+            t if t == WindowMessageType::ResizeWindow as u16 => {
+                if let (Some(req), Some(reply_ep)) = (frame.read::<ResizeWindowRequest>(), frame.reply_endpoint()) {
+                    self.handle_resize_window(&req, reply_ep);
                 }
-                let req: CloseWindowRequest = unsafe {
-                    core::ptr::read(msg.as_ptr().add(1) as *const CloseWindowRequest)
-                };
-                self.handle_close_window(&req);
             }
-            t if t == WindowMessageType::MoveWindow as u8 => {
-                if msg.len() < 1 + core::mem::size_of::<MoveWindowRequest>() {
-                    return;
+            t if t == WindowMessageType::CloseWindow as u16 => {
+                if let Some(req) = frame.read::<CloseWindowRequest>() {
+                    self.handle_close_window(&req);
                 }
-                let req: MoveWindowRequest = unsafe {
-                    core::ptr::read_unaligned(msg.as_ptr().add(1) as *const MoveWindowRequest)
-                };
-                self.handle_move_window(&req);
             }
-            t if t == WindowMessageType::RaiseWindow as u8 => {
-                if msg.len() < 1 + core::mem::size_of::<RaiseWindowRequest>() {
-                    return;
+            t if t == WindowMessageType::MoveWindow as u16 => {
+                if let Some(req) = frame.read::<MoveWindowRequest>() {
+                    self.handle_move_window(&req);
                 }
-                let req: RaiseWindowRequest = unsafe {
-                    core::ptr::read_unaligned(msg.as_ptr().add(1) as *const RaiseWindowRequest)
-                };
-                self.handle_raise_window(&req);
             }
-            t if t == WindowMessageType::LowerWindow as u8 => {
-                if msg.len() < 1 + core::mem::size_of::<LowerWindowRequest>() {
-                    return;
+            t if t == WindowMessageType::SetWindowEvents as u16 => {
+                if let (Some(req), Some(events_ep)) = (frame.read::<SetWindowEventsRequest>(), frame.reply_endpoint()) {
+                    self.handle_set_window_events(&req, events_ep);
+                }
+            }
+            t if t == WindowMessageType::SetCursor as u16 => {
+                if let Some(req) = frame.read::<SetCursorRequest>() {
+                    self.handle_set_cursor(&req);
+                }
+            }
+            t if t == WindowMessageType::RaiseWindow as u16 => {
+                if let Some(req) = frame.read::<RaiseWindowRequest>() {
+                    self.handle_raise_window(&req);
+                }
+            }
+            t if t == WindowMessageType::LowerWindow as u16 => {
+                if let Some(req) = frame.read::<LowerWindowRequest>() {
+                    self.handle_lower_window(&req);
+                }
+            }
+            t if t == WindowMessageType::SetAlwaysOnTop as u16 => {
+                if let Some(req) = frame.read::<SetAlwaysOnTopRequest>() {
+                    self.handle_set_always_on_top(&req);
+                }
+            }
+            t if t == WindowMessageType::WindowAt as u16 => {
+                if let (Some(req), Some(reply_ep)) = (frame.read::<WindowAtRequest>(), frame.reply_endpoint()) {
+                    self.handle_window_at(&req, reply_ep);
+                }
+            }
+            t if t == WindowMessageType::GetWindowBounds as u16 => {
+                if let (Some(req), Some(reply_ep)) = (frame.read::<GetWindowBoundsRequest>(), frame.reply_endpoint()) {
+                    self.handle_get_window_bounds(&req, reply_ep);
+                }
+            }
+            t if t == WindowMessageType::SetStepMode as u16 => {
+                if let (Some(req), Some(reply_ep)) = (frame.read::<SetStepModeRequest>(), frame.reply_endpoint()) {
+                    self.handle_set_step_mode(&req, reply_ep);
+                }
+            }
+            t if t == WindowMessageType::Step as u16 => {
+                if let (Some(StepRequest), Some(reply_ep)) = (frame.read::<StepRequest>(), frame.reply_endpoint()) {
+                    self.handle_step(reply_ep);
+                }
+            }
+            t if t == WindowMessageType::SetDisplayMode as u16 => {
+                if let (Some(req), Some(reply_ep)) = (frame.read::<SetDisplayModeRequest>(), frame.reply_endpoint()) {
+                    self.handle_set_display_mode(&req, reply_ep);
                 }
-                let req: LowerWindowRequest = unsafe {
-                    core::ptr::read_unaligned(msg.as_ptr().add(1) as *const LowerWindowRequest)
-                };
-                self.handle_lower_window(&req);
             }
             _ => {}
         }
     }
 
-    pub fn run(&mut self) -> ! {
-        let msg_buf = ulib::sys_mmap(MAX_MSG_SIZE as u64, MMAP_WRITE);
-        if msg_buf.is_null() {
-            loop { ulib::sys_yield(); }
+    /// Drain all pending mouse events, accumulated into a single cursor move.
+    fn drain_mouse(&mut self) {
+        let mut cursor = (self.cursor_x, self.cursor_y);
+        while let Some(ev) = ulib::sys_read_mouse() {
+            cursor = apply_mouse_event(cursor, &ev, self.display_info.width, self.display_info.height);
         }
+        if cursor != (self.cursor_x, self.cursor_y) {
+            let old_rect = self.cursor_rect();
+            (self.cursor_x, self.cursor_y) = cursor;
+            self.damage_cursor(old_rect);
+        }
+    }
+
+    pub fn run(&mut self) -> ! {
+        let msg_buf = self.msg_buf;
 
         // Initial full composite
         self.mark_full_redraw();
 
         loop {
-            // Drain all pending IPC messages before compositing.
-            loop {
+            // Drain all pending IPC messages before compositing. Polling first
+            // keeps an empty channel from putting the compositor to sleep.
+            let backlog = ulib::sys_channel_poll(self.recv_endpoint).unwrap_or(0);
+            while ulib::sys_channel_poll(self.recv_endpoint).is_ok_and(|n| n > 0) {
                 let msg_slice = unsafe { core::slice::from_raw_parts_mut(msg_buf, MAX_MSG_SIZE) };
-                let (result, bytes_read) = ulib::sys_channel_recv(self.recv_endpoint, msg_slice);
-                if result != IPC_OK || bytes_read == 0 {
-                    break;
-                }
+                let bytes_read = match ulib::sys_channel_recv(self.recv_endpoint, msg_slice) {
+                    Ok(n) if n > 0 => n,
+                    _ => break,
+                };
                 let msg = unsafe { core::slice::from_raw_parts(msg_buf, bytes_read as usize) };
                 self.process_message(msg);
             }
+            self.send_throttle_hints(backlog);
+            self.drain_framebuffer_events();
 
-            // Drain all pending mouse events; accumulate into a single cursor move.
-            let mut total_dx = 0i32;
-            let mut total_dy = 0i32;
-            while let Some(ev) = ulib::sys_read_mouse() {
-                total_dx += ev.dx as i32;
-                total_dy += ev.dy as i32;
-            }
-            if total_dx != 0 || total_dy != 0 {
-                let old_rect = self.cursor_rect();
-                self.cursor_x = (self.cursor_x + total_dx)
-                    .clamp(0, self.display_info.width as i32 - 1);
-                self.cursor_y = (self.cursor_y + total_dy)
-                    .clamp(0, self.display_info.height as i32 - 1);
-                let new_rect = self.cursor_rect();
-
-                // Expand pending damage to cover old and new cursor positions.
-                // No scene update needed — cursor lives above scene_buf.
-                let cursor_damage = match (old_rect, new_rect) {
-                    (Some(mut a), Some(b)) => { a.expand(b.x, b.y, b.w, b.h); Some(a) }
-                    (Some(a), None) => Some(a),
-                    (None, Some(b)) => Some(b),
-                    (None, None) => None,
-                };
-                if let Some(cd) = cursor_damage {
-                    self.expand_pending(cd);
-                }
+            // In step mode, input waits in the kernel queue until the next `Step`.
+            if !self.step_mode {
+                self.drain_mouse();
+                // Single composite for everything accumulated this iteration.
+                self.flush();
             }
 
-            // Single composite for everything accumulated this iteration.
-            self.flush();
-
             ulib::sys_yield();
         }
     }
 }
+
+/// Whether a window whose `updates` were merged into one composite, after a
+/// drain that started `backlog` requests deep, should be told to slow down.
+/// `last_hint` is the composite count at its previous hint.
+fn should_throttle(backlog: u64, updates: u32, composites: u64, last_hint: Option<u64>) -> bool {
+    backlog >= OVERLOAD_BACKLOG
+        && updates >= THROTTLE_MIN_UPDATES
+        && last_hint.is_none_or(|last| composites - last >= THROTTLE_INTERVAL)
+}
+
+/// Pre-render the background gradient for `info`'s size into `buf`.
+fn render_background(buf: *mut u32, info: &DisplayInfo) {
+    let (width, height) = (info.width as usize, info.height as usize);
+    for y in 0..height {
+        let t = if height > 1 { y * 255 / (height - 1) } else { 0 } as u32;
+        let r = (0x1eu32 * (255 - t) + 0x0au32 * t) / 255;
+        let g = (0x3au32 * (255 - t) + 0x0au32 * t) / 255;
+        let b = (0x5fu32 * (255 - t) + 0x0fu32 * t) / 255;
+        let pixel = info.build_pixel(r as u8, g as u8, b as u8);
+        unsafe {
+            for x in 0..width {
+                *buf.add(y * width + x) = pixel;
+            }
+        }
+    }
+}
+
+/// Where a `w`×`h` window asked to move to `(x, y)` is put: no more than one
+/// screen beyond any edge. It can still be parked out of sight, but its
+/// coordinates stay small enough that the clipping arithmetic can't overflow
+/// and a move back is never more than a screen away.
+fn clamp_window_position(x: i32, y: i32, w: u32, h: u32, screen_w: u32, screen_h: u32) -> (i32, i32) {
+    let (w, h, screen_w, screen_h) = (w as i32, h as i32, screen_w as i32, screen_h as i32);
+    (x.clamp(-w - screen_w, 2 * screen_w), y.clamp(-h - screen_h, 2 * screen_h))
+}
+
+/// Where the cursor ends up after `ev`: moved by its motion, or, for an
+/// absolute pointer, placed at its position scaled to the screen. The result
+/// is always on screen.
+fn apply_mouse_event((x, y): (i32, i32), ev: &MouseEvent, width: u32, height: u32) -> (i32, i32) {
+    let (x, y) = if ev.flags & MOUSE_ABSOLUTE != 0 {
+        (scale_absolute(ev.abs_x, width), scale_absolute(ev.abs_y, height))
+    } else {
+        (x + ev.dx as i32, y + ev.dy as i32)
+    };
+    (x.clamp(0, width as i32 - 1), y.clamp(0, height as i32 - 1))
+}
+
+/// Map 0..=`MOUSE_ABS_MAX` onto the pixels 0..`extent`.
+fn scale_absolute(value: u16, extent: u32) -> i32 {
+    (value as u64 * extent.saturating_sub(1) as u64 / MOUSE_ABS_MAX as u64) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        alloc_buffers, apply_mouse_event, clamp_window_position, should_throttle, MAX_MSG_SIZE, OVERLOAD_BACKLOG,
+        THROTTLE_INTERVAL,
+    };
+    use core::ptr::NonNull;
+    use kernel_api_types::{MouseEvent, SysError, MOUSE_ABSOLUTE, MOUSE_ABS_MAX};
+
+    const SCREEN_BYTES: u64 = 800 * 600 * 4;
+
+    /// An mmap that fails on the `fail_call`th call (0-based) and succeeds otherwise.
+    fn mmap_failing_at(fail_call: usize) -> impl FnMut(u64) -> Result<*mut u8, SysError> {
+        let mut calls = 0;
+        move |_| {
+            calls += 1;
+            if calls - 1 == fail_call { Err(SysError::OutOfMemory) } else { Ok(NonNull::dangling().as_ptr()) }
+        }
+    }
+
+    #[test]
+    fn all_buffers_allocated() {
+        let mut sizes = [0u64; 3];
+        let mut n = 0;
+        let b = alloc_buffers(SCREEN_BYTES, |size| {
+            sizes[n] = size;
+            n += 1;
+            Ok(NonNull::dangling().as_ptr())
+        })
+        .unwrap();
+        assert!(b.background.is_some());
+        assert_eq!(sizes, [MAX_MSG_SIZE as u64, SCREEN_BYTES, SCREEN_BYTES]);
+    }
+
+    #[test]
+    fn scene_buf_failure_is_an_error() {
+        assert_eq!(alloc_buffers(SCREEN_BYTES, mmap_failing_at(1)).err(), Some(SysError::OutOfMemory));
+    }
+
+    #[test]
+    fn msg_buf_failure_is_an_error() {
+        assert_eq!(alloc_buffers(SCREEN_BYTES, mmap_failing_at(0)).err(), Some(SysError::OutOfMemory));
+    }
+
+    #[test]
+    fn background_failure_degrades() {
+        let b = alloc_buffers(SCREEN_BYTES, mmap_failing_at(2)).unwrap();
+        assert!(b.background.is_none());
+    }
+
+    fn relative(dx: i16, dy: i16) -> MouseEvent {
+        MouseEvent { dx, dy, ..MouseEvent::EMPTY }
+    }
+
+    fn absolute(abs_x: u16, abs_y: u16) -> MouseEvent {
+        MouseEvent { flags: MOUSE_ABSOLUTE, abs_x, abs_y, ..MouseEvent::EMPTY }
+    }
+
+    #[test]
+    fn relative_motion_moves_and_clamps() {
+        assert_eq!(apply_mouse_event((400, 300), &relative(5, -7), 800, 600), (405, 293));
+        assert_eq!(apply_mouse_event((10, 590), &relative(-50, 50), 800, 600), (0, 599));
+    }
+
+    #[test]
+    fn absolute_position_replaces_cursor() {
+        assert_eq!(apply_mouse_event((123, 456), &absolute(0, 0), 800, 600), (0, 0));
+        assert_eq!(apply_mouse_event((0, 0), &absolute(MOUSE_ABS_MAX, MOUSE_ABS_MAX), 800, 600), (799, 599));
+        let (x, y) = apply_mouse_event((0, 0), &absolute(MOUSE_ABS_MAX / 2, MOUSE_ABS_MAX / 4), 800, 600);
+        assert!((399..=400).contains(&x) && (149..=150).contains(&y), "({x}, {y})");
+    }
+
+    #[test]
+    fn window_may_be_parked_off_screen() {
+        assert_eq!(clamp_window_position(-300, 700, 200, 100, 800, 600), (-300, 700));
+        assert_eq!(clamp_window_position(10, 20, 200, 100, 800, 600), (10, 20));
+    }
+
+    #[test]
+    fn window_stays_within_a_screen_of_the_edges() {
+        assert_eq!(clamp_window_position(i32::MIN, i32::MAX, 200, 100, 800, 600), (-1000, 1200));
+        assert_eq!(clamp_window_position(i32::MAX, i32::MIN, 200, 100, 800, 600), (1600, -700));
+    }
+
+    #[test]
+    fn throttle_needs_backlog_and_repeated_updates() {
+        assert!(should_throttle(OVERLOAD_BACKLOG, 5, 100, None));
+        assert!(!should_throttle(OVERLOAD_BACKLOG - 1, 5, 100, None));
+        assert!(!should_throttle(OVERLOAD_BACKLOG, 1, 100, None));
+    }
+
+    #[test]
+    fn throttle_hints_are_rate_limited() {
+        assert!(!should_throttle(OVERLOAD_BACKLOG, 5, 100, Some(100 - THROTTLE_INTERVAL + 1)));
+        assert!(should_throttle(OVERLOAD_BACKLOG, 5, 100, Some(100 - THROTTLE_INTERVAL)));
+    }
+}
@@ -1,4 +1,4 @@
-// Cursor sprite — 14 × 20 pixels, hot spot at (0, 0)
+// Default cursor sprite — 14 × 20 pixels, hot spot at (0, 0)
 //
 // Each row is a u16 with bit 15 = col 0 (leftmost pixel).
 //
@@ -30,6 +30,8 @@
 //   row18:  ·  ·  ·  ·  ·  ·  ■  □  □  ■  ·  ·  ·  ·
 //   row19:  ·  ·  ·  ·  ·  ·  ·  ■  ■  ·  ·  ·  ·  ·
 
+use kernel_api_types::window::{CursorImage, MAX_CURSOR_SIZE};
+
 pub const CURSOR_W: u32 = 14;
 pub const CURSOR_H: u32 = 20;
 
@@ -80,3 +82,66 @@ pub const CURSOR_IMAGE: [u16; 20] = [
     0x0180, // row 18: . . . . . . . □ □ .
     0x0000, // row 19: black end
 ];
+
+/// The arrow above, as the `CursorImage` the compositor draws until a client
+/// sets its own and again once that client's window closes.
+pub const DEFAULT_CURSOR: CursorImage = CursorImage {
+    width: CURSOR_W,
+    height: CURSOR_H,
+    hot_x: 0,
+    hot_y: 0,
+    mask: widen(&CURSOR_MASK),
+    image: widen(&CURSOR_IMAGE),
+};
+
+/// `CursorImage` rows are 32 pixels wide, with col 0 in bit 31.
+const fn widen(rows: &[u16; CURSOR_H as usize]) -> [u32; MAX_CURSOR_SIZE] {
+    let mut wide = [0; MAX_CURSOR_SIZE];
+    let mut i = 0;
+    while i < rows.len() {
+        wide[i] = (rows[i] as u32) << 16;
+        i += 1;
+    }
+    wide
+}
+
+/// The cursor a `SetCursor` request asks for, or the default if it isn't
+/// valid.
+pub fn requested_or_default(cursor: &CursorImage) -> CursorImage {
+    if cursor.is_valid() { *cursor } else { DEFAULT_CURSOR }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{requested_or_default, DEFAULT_CURSOR};
+    use kernel_api_types::window::{CursorImage, MAX_CURSOR_SIZE};
+
+    #[test]
+    fn default_cursor_keeps_the_arrow() {
+        assert!(DEFAULT_CURSOR.is_valid());
+        assert_eq!(DEFAULT_CURSOR.mask[0], 0x8000_0000);
+        assert_eq!(DEFAULT_CURSOR.mask[13], 0xFFFC_0000);
+        assert_eq!(DEFAULT_CURSOR.image[12], 0x7FF0_0000);
+        assert!(DEFAULT_CURSOR.mask[20..].iter().all(|&row| row == 0));
+    }
+
+    #[test]
+    fn valid_request_is_used() {
+        // A 9 × 9 crosshair centred on its hot spot.
+        let mut crosshair = CursorImage { width: 9, height: 9, hot_x: 4, hot_y: 4, mask: [0; MAX_CURSOR_SIZE], image: [0; MAX_CURSOR_SIZE] };
+        for row in 0..9 {
+            crosshair.mask[row] = if row == 4 { 0xFF80_0000 } else { 0x0800_0000 };
+        }
+        assert_eq!(requested_or_default(&crosshair), crosshair);
+    }
+
+    #[test]
+    fn bad_request_falls_back_to_default() {
+        let big = CursorImage { width: 33, height: 8, hot_x: 0, hot_y: 0, mask: [!0; MAX_CURSOR_SIZE], image: [0; MAX_CURSOR_SIZE] };
+        assert_eq!(requested_or_default(&big), DEFAULT_CURSOR);
+        let empty = CursorImage { width: 0, height: 0, ..big };
+        assert_eq!(requested_or_default(&empty), DEFAULT_CURSOR);
+        let hot_outside = CursorImage { width: 8, hot_x: 8, ..big };
+        assert_eq!(requested_or_default(&hot_outside), DEFAULT_CURSOR);
+    }
+}
@@ -1,21 +1,61 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
 mod compositor;
 mod cursor;
 mod window;
+mod z_order;
 
-use compositor::Compositor;
+use compositor::{Compositor, CHANNEL_CAPACITY};
+use kernel_api_types::graphics::KERNEL_DISPLAY_EVENTS_SERVICE;
+use kernel_api_types::window::DISPLAY_EVENTS_SERVICE;
 
 #[unsafe(no_mangle)]
 unsafe extern "sysv64" fn entry_point(_arg: u64) -> ! {
-    let (send_ep, recv_ep) = ulib::sys_channel_create(16);
-    ulib::sys_register_service(b"display", send_ep);
+    let _ = ulib::sys_set_task_name("display_server");
+    let (send_ep, recv_ep) = ulib::sys_channel_create(CHANNEL_CAPACITY).expect("display_server: channel_create failed");
+    // Mode changes are only announced if this works; the windows don't need it.
+    let events_ep = ulib::sys_broadcast_create(0).ok();
+    // Without this the compositor keeps the mode it started in.
+    let framebuffer_events = ulib::sys_lookup_service(KERNEL_DISPLAY_EVENTS_SERVICE)
+        .and_then(ulib::sys_channel_subscribe)
+        .ok();
 
-    let mut compositor = Compositor::new(recv_ep);
+    // Exit before registering the service so clients get `NotFound` from
+    // lookup instead of queueing requests nobody will answer.
+    let mut compositor = match Compositor::new(recv_ep, events_ep, framebuffer_events) {
+        Ok(c) => c,
+        Err(_) => {
+            ulib::sys_debug_log_str("display_server: out of memory for scene buffers, exiting");
+            ulib::sys_exit(1)
+        }
+    };
+    ulib::sys_register_service(b"display", send_ep).expect("display_server: \"display\" already registered");
+    if let Some(ep) = events_ep {
+        let _ = ulib::sys_register_service(DISPLAY_EVENTS_SERVICE, ep);
+    }
+
+    wait_for_display();
     compositor.run()
 }
 
+/// How many timeslices to give init to hand the display over.
+const DISPLAY_WAIT_YIELDS: u32 = 1000;
+
+/// init transfers the display only after this task has spawned, so presents
+/// made before then would be refused. Wait for the transfer, but not forever:
+/// if it never comes, run anyway so clients still get answers.
+fn wait_for_display() {
+    for _ in 0..DISPLAY_WAIT_YIELDS {
+        if ulib::sys_is_display_owner() {
+            return;
+        }
+        ulib::sys_yield();
+    }
+    ulib::sys_debug_log_str("display_server: never got the display; running without it");
+}
+
+#[cfg(not(test))]
 #[panic_handler]
 fn rust_panic(info: &core::panic::PanicInfo) -> ! {
     ulib::default_panic(info)
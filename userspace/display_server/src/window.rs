@@ -1,4 +1,5 @@
-use kernel_api_types::window::WindowId;
+use kernel_api_types::window::{DirtyRect, SizeLimits, WindowId};
+use kernel_api_types::SysError;
 
 pub struct Window {
     pub id: WindowId,
@@ -6,31 +7,201 @@ pub struct Window {
     pub y: i32,
     pub width: u32,
     pub height: u32,
+    /// The part of the window on screen, or `None` if it is entirely off
+    /// screen. Set by the compositor when the window is created or moved and
+    /// when the display mode changes.
+    pub screen_rect: Option<DirtyRect>,
+    /// Size bounds from `CreateWindow`, enforced on every resize.
+    pub limits: SizeLimits,
     /// Pointer into the shared physical buffer (readable by the compositor).
     pub buffer: *mut u32,
     /// Opaque ID returned to the client in CreateWindowResponse so it can map the same pages.
     pub shared_buf_id: u64,
     /// Size in bytes — needed to call sys_munmap before destroying the shared buf.
     pub buf_size: u64,
+    /// Send endpoint for this window's `WindowEventType` notifications, if
+    /// the client asked for them.
+    pub events_endpoint: Option<u64>,
+    /// `UpdateWindow`s handled since the last composite.
+    pub pending_updates: u32,
+    /// Composite count when this window was last sent a throttle hint.
+    pub last_throttle: Option<u64>,
+}
+
+/// The shared-buffer syscalls behind a window's pixels. Tests substitute
+/// their own to make a step fail.
+pub trait SharedBufs {
+    /// Create a buffer of `size` bytes mapped into the compositor.
+    fn create(&mut self, size: u64) -> Result<(u64, *mut u8), SysError>;
+    /// Let `task_id` map buffer `id`.
+    fn grant(&mut self, id: u64, task_id: u64) -> Result<(), SysError>;
+    /// Grow or shrink buffer `id` to `size` bytes. Returns the compositor's
+    /// possibly moved mapping.
+    fn resize(&mut self, id: u64, size: u64) -> Result<*mut u8, SysError>;
+    /// Unmap the compositor's mapping of buffer `id` and destroy it.
+    fn release(&mut self, id: u64, buffer: *mut u8, size: u64);
+}
+
+/// `SharedBufs` backed by the real syscalls.
+pub struct KernelSharedBufs;
+
+impl SharedBufs for KernelSharedBufs {
+    fn create(&mut self, size: u64) -> Result<(u64, *mut u8), SysError> {
+        ulib::sys_create_shared_buf(size)
+    }
+
+    fn grant(&mut self, id: u64, task_id: u64) -> Result<(), SysError> {
+        ulib::sys_grant_shared_buf(id, task_id)
+    }
+
+    fn resize(&mut self, id: u64, size: u64) -> Result<*mut u8, SysError> {
+        ulib::sys_resize_shared_buf(id, size)
+    }
+
+    fn release(&mut self, id: u64, buffer: *mut u8, size: u64) {
+        let _ = ulib::sys_munmap(buffer, size);
+        let _ = ulib::sys_destroy_shared_buf(id);
+    }
 }
 
 impl Window {
-    pub fn new(id: WindowId, x: i32, y: i32, width: u32, height: u32) -> Option<Self> {
+    /// Create a window whose pixels only `client_task_id` may map. If any
+    /// step fails, whatever was already allocated is released before the
+    /// error is returned, so a failed creation leaves nothing behind.
+    pub fn new(
+        id: WindowId,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        client_task_id: u64,
+        bufs: &mut impl SharedBufs,
+    ) -> Result<Self, SysError> {
         let buf_size = (width as u64) * (height as u64) * 4;
-        let (shared_buf_id, buffer_ptr) = ulib::sys_create_shared_buf(buf_size);
-        if buffer_ptr.is_null() || shared_buf_id == u64::MAX {
-            return None;
+        let (shared_buf_id, buffer_ptr) = bufs.create(buf_size)?;
+        if let Err(e) = bufs.grant(shared_buf_id, client_task_id) {
+            bufs.release(shared_buf_id, buffer_ptr, buf_size);
+            return Err(e);
         }
-        Some(Window {
+        Ok(Window {
             id,
             x,
             y,
             width,
             height,
+            screen_rect: None,
+            limits: SizeLimits::NONE,
             buffer: buffer_ptr as *mut u32,
             shared_buf_id,
             buf_size,
+            events_endpoint: None,
+            pending_updates: 0,
+            last_throttle: None,
         })
     }
 
+    /// Resize to `width`×`height` clamped into the window's limits, and
+    /// return the size granted. On failure the window is unchanged.
+    pub fn resize(&mut self, width: u32, height: u32, bufs: &mut impl SharedBufs) -> Result<(u32, u32), SysError> {
+        let (width, height) = self.limits.clamp(width, height);
+        if (width, height) != (self.width, self.height) {
+            let buf_size = (width as u64) * (height as u64) * 4;
+            self.buffer = bufs.resize(self.shared_buf_id, buf_size)? as *mut u32;
+            self.buf_size = buf_size;
+            self.width = width;
+            self.height = height;
+        }
+        Ok((width, height))
+    }
+
+    /// Free the window's shared buffer and close its events endpoint.
+    pub fn release(self, bufs: &mut impl SharedBufs) {
+        if let Some(ep) = self.events_endpoint {
+            let _ = ulib::sys_channel_close(ep);
+        }
+        bufs.release(self.shared_buf_id, self.buffer as *mut u8, self.buf_size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SharedBufs, Window};
+    use core::ptr::NonNull;
+    use kernel_api_types::window::SizeLimits;
+    use kernel_api_types::SysError;
+
+    /// Counts live buffers; `fail_create`/`fail_grant`/`fail_resize` make
+    /// that step fail.
+    #[derive(Default)]
+    struct FakeBufs {
+        next_id: u64,
+        live: usize,
+        fail_create: bool,
+        fail_grant: bool,
+        fail_resize: bool,
+    }
+
+    impl SharedBufs for FakeBufs {
+        fn create(&mut self, _size: u64) -> Result<(u64, *mut u8), SysError> {
+            if self.fail_create {
+                return Err(SysError::OutOfMemory);
+            }
+            self.next_id += 1;
+            self.live += 1;
+            Ok((self.next_id, NonNull::dangling().as_ptr()))
+        }
+
+        fn grant(&mut self, _id: u64, _task_id: u64) -> Result<(), SysError> {
+            if self.fail_grant { Err(SysError::NotFound) } else { Ok(()) }
+        }
+
+        fn resize(&mut self, _id: u64, _size: u64) -> Result<*mut u8, SysError> {
+            if self.fail_resize { Err(SysError::OutOfMemory) } else { Ok(NonNull::dangling().as_ptr()) }
+        }
+
+        fn release(&mut self, _id: u64, _buffer: *mut u8, _size: u64) {
+            self.live -= 1;
+        }
+    }
+
+    #[test]
+    fn created_window_owns_one_buffer() {
+        let mut bufs = FakeBufs::default();
+        let window = Window::new(1, 0, 0, 8, 8, 7, &mut bufs).unwrap();
+        assert_eq!((window.buf_size, bufs.live), (8 * 8 * 4, 1));
+        window.release(&mut bufs);
+        assert_eq!(bufs.live, 0);
+    }
+
+    #[test]
+    fn failed_allocation_leaves_nothing() {
+        let mut bufs = FakeBufs { fail_create: true, ..Default::default() };
+        assert_eq!(Window::new(1, 0, 0, 8, 8, 7, &mut bufs).err(), Some(SysError::OutOfMemory));
+        assert_eq!(bufs.live, 0);
+    }
+
+    #[test]
+    fn failed_grant_releases_the_buffer() {
+        let mut bufs = FakeBufs { fail_grant: true, ..Default::default() };
+        assert_eq!(Window::new(1, 0, 0, 8, 8, 7, &mut bufs).err(), Some(SysError::NotFound));
+        assert_eq!(bufs.live, 0);
+    }
+
+    #[test]
+    fn resize_below_minimum_is_clamped() {
+        let mut bufs = FakeBufs::default();
+        let mut window = Window::new(1, 0, 0, 64, 64, 7, &mut bufs).unwrap();
+        window.limits = SizeLimits { min_width: 32, min_height: 16, ..SizeLimits::NONE };
+        assert_eq!(window.resize(8, 8, &mut bufs), Ok((32, 16)));
+        assert_eq!((window.width, window.height, window.buf_size), (32, 16, 32 * 16 * 4));
+    }
+
+    #[test]
+    fn failed_resize_keeps_the_old_size() {
+        let mut bufs = FakeBufs::default();
+        let mut window = Window::new(1, 0, 0, 64, 64, 7, &mut bufs).unwrap();
+        bufs.fail_resize = true;
+        assert_eq!(window.resize(128, 128, &mut bufs), Err(SysError::OutOfMemory));
+        assert_eq!((window.width, window.height, window.buf_size), (64, 64, 64 * 64 * 4));
+    }
 }
@@ -0,0 +1,224 @@
+use kernel_api_types::window::{WindowId, MAX_WINDOWS};
+
+/// Stacking order of the open windows, bottom-most first.
+///
+/// The top `on_top` entries form the always-on-top band; normal windows are
+/// raised and lowered beneath it, and band members only move within it.
+pub struct ZOrder {
+    ids: [WindowId; MAX_WINDOWS],
+    len: usize,
+    on_top: usize,
+}
+
+impl ZOrder {
+    pub const fn new() -> Self {
+        ZOrder { ids: [0; MAX_WINDOWS], len: 0, on_top: 0 }
+    }
+
+    /// The windows from bottom to top.
+    pub fn ids(&self) -> &[WindowId] {
+        &self.ids[..self.len]
+    }
+
+    pub fn contains(&self, id: WindowId) -> bool {
+        self.ids().contains(&id)
+    }
+
+    /// Put `id` at the top of its band. Returns false, changing nothing, if
+    /// the order is full or already holds `id`.
+    pub fn push(&mut self, id: WindowId, on_top: bool) -> bool {
+        if self.len == MAX_WINDOWS || self.contains(id) {
+            return false;
+        }
+        if on_top {
+            self.insert(self.len, id);
+            self.on_top += 1;
+        } else {
+            self.insert(self.band_start(), id);
+        }
+        true
+    }
+
+    /// Take `id` out of the order. Returns false if it wasn't in it.
+    pub fn remove(&mut self, id: WindowId) -> bool {
+        let Some(pos) = self.position(id) else {
+            return false;
+        };
+        if pos >= self.band_start() {
+            self.on_top -= 1;
+        }
+        self.ids.copy_within(pos + 1..self.len, pos);
+        self.len -= 1;
+        true
+    }
+
+    /// Move `id` to the top of its band. Returns false if it isn't open.
+    pub fn raise(&mut self, id: WindowId) -> bool {
+        let Some(on_top) = self.is_on_top(id) else {
+            return false;
+        };
+        self.remove(id);
+        self.push(id, on_top)
+    }
+
+    /// Move `id` to the bottom of its band. Returns false if it isn't open.
+    pub fn lower(&mut self, id: WindowId) -> bool {
+        let Some(on_top) = self.is_on_top(id) else {
+            return false;
+        };
+        self.remove(id);
+        if on_top {
+            self.insert(self.band_start(), id);
+            self.on_top += 1;
+        } else {
+            self.insert(0, id);
+        }
+        true
+    }
+
+    /// Move `id` to the top of the always-on-top band, or of the normal
+    /// windows beneath it. Returns false if it isn't open.
+    pub fn set_on_top(&mut self, id: WindowId, on_top: bool) -> bool {
+        if !self.remove(id) {
+            return false;
+        }
+        self.push(id, on_top)
+    }
+
+    fn position(&self, id: WindowId) -> Option<usize> {
+        self.ids().iter().position(|&x| x == id)
+    }
+
+    /// Index of the bottom of the always-on-top band.
+    fn band_start(&self) -> usize {
+        self.len - self.on_top
+    }
+
+    /// Whether `id` is in the always-on-top band, or `None` if it isn't open.
+    fn is_on_top(&self, id: WindowId) -> Option<bool> {
+        self.position(id).map(|pos| pos >= self.band_start())
+    }
+
+    /// Insert at `index`, shifting everything above it up. There must be room.
+    fn insert(&mut self, index: usize, id: WindowId) {
+        debug_assert!(self.len < MAX_WINDOWS);
+        self.ids.copy_within(index..self.len, index + 1);
+        self.ids[index] = id;
+        self.len += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ZOrder;
+    use kernel_api_types::window::{WindowId, MAX_WINDOWS};
+
+    fn order(normal: &[WindowId], on_top: &[WindowId]) -> ZOrder {
+        let mut z = ZOrder::new();
+        for &id in normal {
+            assert!(z.push(id, false));
+        }
+        for &id in on_top {
+            assert!(z.push(id, true));
+        }
+        z
+    }
+
+    #[test]
+    fn push_keeps_normal_windows_under_the_band() {
+        let mut z = order(&[1, 2], &[10]);
+        assert!(z.push(3, false));
+        assert_eq!(z.ids(), &[1, 2, 3, 10]);
+    }
+
+    #[test]
+    fn push_refuses_duplicates() {
+        let mut z = order(&[1, 2], &[]);
+        assert!(!z.push(1, true));
+        assert_eq!(z.ids(), &[1, 2]);
+    }
+
+    #[test]
+    fn push_when_full_changes_nothing() {
+        let mut z = ZOrder::new();
+        for id in 1..=MAX_WINDOWS as WindowId {
+            assert!(z.push(id, false));
+        }
+        assert!(!z.push(0, true));
+        assert_eq!(z.ids().len(), MAX_WINDOWS);
+        // A full order still raises: the band didn't grow behind its back.
+        assert!(z.raise(1));
+        assert_eq!(z.ids().last(), Some(&1));
+    }
+
+    #[test]
+    fn lower_when_full() {
+        let mut z = ZOrder::new();
+        for id in 1..MAX_WINDOWS as WindowId {
+            assert!(z.push(id, false));
+        }
+        assert!(z.push(MAX_WINDOWS as WindowId, true));
+        let top_normal = MAX_WINDOWS as WindowId - 1;
+        assert!(z.lower(top_normal));
+        assert_eq!(z.ids().len(), MAX_WINDOWS);
+        assert_eq!(z.ids()[0], top_normal);
+        assert_eq!(z.ids().last(), Some(&(MAX_WINDOWS as WindowId)));
+        // The band is still exactly the one window.
+        assert!(z.lower(MAX_WINDOWS as WindowId));
+        assert_eq!(z.ids().last(), Some(&(MAX_WINDOWS as WindowId)));
+    }
+
+    #[test]
+    fn lower_the_only_window() {
+        let mut z = order(&[7], &[]);
+        assert!(z.lower(7));
+        assert_eq!(z.ids(), &[7]);
+
+        let mut z = order(&[], &[7]);
+        assert!(z.lower(7));
+        assert!(z.push(8, false));
+        assert_eq!(z.ids(), &[8, 7]);
+    }
+
+    #[test]
+    fn raise_then_lower() {
+        let mut z = order(&[1, 2, 3], &[10, 11]);
+        assert!(z.raise(1));
+        assert_eq!(z.ids(), &[2, 3, 1, 10, 11]);
+        assert!(z.lower(1));
+        assert_eq!(z.ids(), &[1, 2, 3, 10, 11]);
+        assert!(z.raise(10));
+        assert_eq!(z.ids(), &[1, 2, 3, 11, 10]);
+        assert!(z.lower(10));
+        assert_eq!(z.ids(), &[1, 2, 3, 10, 11]);
+    }
+
+    #[test]
+    fn unknown_windows_are_not_added() {
+        let mut z = order(&[1], &[]);
+        assert!(!z.raise(99));
+        assert!(!z.lower(99));
+        assert!(!z.set_on_top(99, true));
+        assert!(!z.remove(99));
+        assert_eq!(z.ids(), &[1]);
+    }
+
+    #[test]
+    fn set_on_top_moves_between_bands() {
+        let mut z = order(&[1, 2], &[10]);
+        assert!(z.set_on_top(1, true));
+        assert_eq!(z.ids(), &[2, 10, 1]);
+        assert!(z.set_on_top(10, false));
+        assert_eq!(z.ids(), &[2, 10, 1]);
+        assert!(z.push(3, false));
+        assert_eq!(z.ids(), &[2, 10, 3, 1]);
+    }
+
+    #[test]
+    fn remove_from_band_shrinks_it() {
+        let mut z = order(&[1], &[10, 11]);
+        assert!(z.remove(10));
+        assert!(z.push(2, false));
+        assert_eq!(z.ids(), &[1, 2, 11]);
+    }
+}
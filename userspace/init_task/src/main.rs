@@ -1,6 +1,20 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
+mod supervisor;
+
+use kernel_api_types::net::NET_SERVER_LOOPBACK;
+use kernel_api_types::task::{TaskInfo, SPAWN_SYSTEM, TASK_STATE_ZOMBIE};
+use supervisor::{Event, Server, Supervisor};
+
+/// How often init checks that its servers are still running.
+const SUPERVISE_INTERVAL_MS: u64 = 500;
+
+/// Most tasks `is_running` looks through; with more than this, a server
+/// that isn't listed is assumed to be running.
+const MAX_LISTED_TASKS: usize = 64;
+
+#[cfg(not(test))]
 #[panic_handler]
 fn rust_panic(info: &core::panic::PanicInfo) -> ! {
     ulib::default_panic(info)
@@ -9,56 +23,107 @@ fn rust_panic(info: &core::panic::PanicInfo) -> ! {
 #[unsafe(no_mangle)]
 unsafe extern "sysv64" fn entry_point() -> ! {
     // Detect test mode: if a "/utest" Limine module is present, run integration tests.
-    let utest_size = ulib::sys_get_module("utest", core::ptr::null_mut(), 0);
+    let utest_size = ulib::sys_get_module("utest", core::ptr::null_mut(), 0).unwrap_or(0);
     let is_test_mode = utest_size > 0;
 
-    // Load and spawn display_server (it will self-register the "display" service)
-    let ds_size = ulib::sys_get_module("display_server", core::ptr::null_mut(), 0);
-    let ds_buf = ulib::sys_mmap(ds_size, kernel_api_types::MMAP_WRITE);
-    let _ = ulib::sys_get_module("display_server", ds_buf, ds_size);
+    // Servers that should never exit. The "net" instance of net_server isn't
+    // one: it exits by design when there is no NIC, and init can't tell that
+    // from a crash.
+    let mut servers = Supervisor::new([
+        Server::new("display_server", 0),
+        Server::new("net_server", NET_SERVER_LOOPBACK),
+        Server::new("serial_server", 0),
+    ]);
+    let spawn_server = |server: &Server| spawn_module(server.module, server.arg, SPAWN_SYSTEM);
 
-    let ds_elf_bytes = unsafe { core::slice::from_raw_parts(ds_buf, ds_size as usize) };
-    let ds_id = ulib::sys_spawn(ds_elf_bytes, 0);
-    ulib::sys_munmap(ds_buf, ds_size);
+    // Spawn display_server (it will self-register the "display" service)
+    let ds_id = servers.start("display_server", spawn_server);
 
-    // Transfer display ownership to display_server
-    ulib::sys_transfer_display(ds_id);
+    // Transfer display ownership to display_server. If it didn't start, init
+    // keeps the display rather than handing it to a task that doesn't exist.
+    match ds_id {
+        Some(ds_id) => {
+            if ulib::sys_transfer_display(ds_id).is_err() {
+                ulib::sys_debug_log_str("init: failed to transfer the display to display_server");
+            }
+        }
+        None => ulib::sys_debug_log_str("init: display_server did not start; keeping the display"),
+    }
 
-    if is_test_mode {
-        // Test mode: spawn utest; skip bouncing cubes
-        let utest_buf = ulib::sys_mmap(utest_size, kernel_api_types::MMAP_WRITE);
-        let _ = ulib::sys_get_module("utest", utest_buf, utest_size);
+    // Spawn net_server twice: once for "net" (it exits if there is no NIC)
+    // and once for the software "loopback" interface
+    let _ = spawn_module("net_server", 0, SPAWN_SYSTEM);
+    servers.start("net_server", spawn_server);
+
+    // Spawn serial_server (it will self-register the "serial" service)
+    servers.start("serial_server", spawn_server);
 
-        let utest_elf = unsafe { core::slice::from_raw_parts(utest_buf, utest_size as usize) };
-        let _ = ulib::sys_spawn(utest_elf, 0);
-        ulib::sys_munmap(utest_buf, utest_size);
+    if is_test_mode {
+        // Test mode: spawn utest; skip bouncing cubes. It is a system task so
+        // its children can outrank it in the scheduler tests.
+        let _ = spawn_module("utest", 0, SPAWN_SYSTEM);
     } else {
-        // Normal mode: spawn bouncing cube clients
-        let cube1_size = ulib::sys_get_module("bouncing_cube_1", core::ptr::null_mut(), 0);
-        if cube1_size > 0 {
-            let cube1_buf = ulib::sys_mmap(cube1_size, kernel_api_types::MMAP_WRITE);
-            let _ = ulib::sys_get_module("bouncing_cube_1", cube1_buf, cube1_size);
-
-            let cube1_elf =
-                unsafe { core::slice::from_raw_parts(cube1_buf, cube1_size as usize) };
-            let _ = ulib::sys_spawn(cube1_elf, 0);
-            ulib::sys_munmap(cube1_buf, cube1_size);
-        }
+        // Normal mode: spawn net_stack (ARP and ping responder). Tests talk to
+        // "net" directly, so it would only steal their frames there.
+        let _ = spawn_module("net_stack", 0, 0);
 
-        let cube2_size = ulib::sys_get_module("bouncing_cube_2", core::ptr::null_mut(), 0);
-        if cube2_size > 0 {
-            let cube2_buf = ulib::sys_mmap(cube2_size, kernel_api_types::MMAP_WRITE);
-            let _ = ulib::sys_get_module("bouncing_cube_2", cube2_buf, cube2_size);
+        // Spawn bouncing cube clients
+        let _ = spawn_module("bouncing_cube_1", 0, 0);
+        let _ = spawn_module("bouncing_cube_2", 0, 0);
+    }
 
-            let cube2_elf =
-                unsafe { core::slice::from_raw_parts(cube2_buf, cube2_size as usize) };
-            let _ = ulib::sys_spawn(cube2_elf, 0);
-            ulib::sys_munmap(cube2_buf, cube2_size);
+    // Restart any server that exits, for as long as the system runs
+    loop {
+        ulib::sys_sleep(SUPERVISE_INTERVAL_MS);
+        let mut tasks = [TaskInfo::EMPTY; MAX_LISTED_TASKS];
+        let Ok((listed, total)) = ulib::sys_list_tasks(&mut tasks) else { continue };
+        let complete = total <= listed.len();
+        let is_running = |id| {
+            !complete || listed.iter().any(|t| t.id == id && t.state != TASK_STATE_ZOMBIE)
+        };
+        servers.check(is_running, spawn_server, on_server_event);
+    }
+}
+
+/// Log what the supervisor did, and give a restarted display_server the
+/// display.
+fn on_server_event(server: &Server, event: Event) {
+    match event {
+        Event::Restarted(id) => {
+            log_server(server, " exited; restarted");
+            if server.module == "display_server" && ulib::sys_transfer_display(id).is_err() {
+                ulib::sys_debug_log_str("init: failed to transfer the display to the restarted display_server");
+            }
         }
+        Event::RestartFailed => log_server(server, " exited and could not be restarted"),
+        Event::GaveUp => log_server(server, " keeps exiting; leaving it down"),
+    }
+}
+
+fn log_server(server: &Server, what: &str) {
+    let mut line = [0u8; 96];
+    let mut len = 0;
+    for part in ["init: ", server.module, what] {
+        let n = part.len().min(line.len() - len);
+        line[len..len + n].copy_from_slice(&part.as_bytes()[..n]);
+        len += n;
     }
+    ulib::sys_debug_log_str(core::str::from_utf8(&line[..len]).unwrap_or("init: a server exited"));
+}
 
-    // Init task stays alive, yielding forever
-    loop {
-        ulib::sys_yield();
+/// Load boot module `name` and spawn it with `arg` and `Spawn` `flags`.
+/// Returns the new task's ID, or None if the module is missing or didn't
+/// start.
+fn spawn_module(name: &str, arg: u64, flags: u64) -> Option<u64> {
+    let size = ulib::sys_get_module(name, core::ptr::null_mut(), 0).unwrap_or(0);
+    if size == 0 {
+        return None;
     }
+    let buf = ulib::sys_mmap(size, kernel_api_types::MMAP_WRITE).ok()?;
+    let _ = ulib::sys_get_module(name, buf, size);
+
+    let elf = unsafe { core::slice::from_raw_parts(buf, size as usize) };
+    let id = ulib::sys_spawn_with_flags(elf, arg, flags).ok().filter(|&id| id != 0);
+    let _ = ulib::sys_munmap(buf, size);
+    id
 }
@@ -0,0 +1,192 @@
+//! Restarting the servers init starts when they exit.
+//!
+//! A server that exits, whether killed or run off its stack, takes its
+//! services with it; the new instance registers them again as it starts up.
+//! Each server is restarted at most `MAX_RESTARTS` times, so one that dies
+//! as soon as it starts doesn't keep init spawning it forever.
+
+/// Restarts each server gets before init leaves it down.
+pub const MAX_RESTARTS: u32 = 3;
+
+/// A server init keeps running: the boot module it is loaded from and the
+/// argument it is started with.
+pub struct Server {
+    pub module: &'static str,
+    pub arg: u64,
+    task_id: Option<u64>,
+    restarts: u32,
+    gave_up: bool,
+}
+
+impl Server {
+    pub const fn new(module: &'static str, arg: u64) -> Self {
+        Server { module, arg, task_id: None, restarts: 0, gave_up: false }
+    }
+
+    /// The task running the server, if it is up.
+    #[cfg(test)]
+    pub fn task_id(&self) -> Option<u64> {
+        self.task_id
+    }
+}
+
+/// What `Supervisor::check` did about a server that was down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Started again as this task.
+    Restarted(u64),
+    /// Spawning it again failed; the next check tries again.
+    RestartFailed,
+    /// It has used up its restarts and stays down.
+    GaveUp,
+}
+
+pub struct Supervisor<const N: usize> {
+    servers: [Server; N],
+}
+
+impl<const N: usize> Supervisor<N> {
+    pub const fn new(servers: [Server; N]) -> Self {
+        Supervisor { servers }
+    }
+
+    #[cfg(test)]
+    pub fn servers(&self) -> &[Server] {
+        &self.servers
+    }
+
+    /// Start the server for `module`. It is only restarted once it has been
+    /// started this way, whether or not that worked.
+    pub fn start(&mut self, module: &str, spawn: impl FnOnce(&Server) -> Option<u64>) -> Option<u64> {
+        let server = self.servers.iter_mut().find(|s| s.module == module)?;
+        server.task_id = spawn(server);
+        server.task_id
+    }
+
+    /// Restart every server that isn't running, reporting each one to
+    /// `on_event`. `is_running` says whether a task is still alive.
+    pub fn check(
+        &mut self,
+        is_running: impl Fn(u64) -> bool,
+        mut spawn: impl FnMut(&Server) -> Option<u64>,
+        mut on_event: impl FnMut(&Server, Event),
+    ) {
+        for server in self.servers.iter_mut() {
+            if server.gave_up || server.task_id.is_some_and(&is_running) {
+                continue;
+            }
+            if server.restarts == MAX_RESTARTS {
+                server.gave_up = true;
+                server.task_id = None;
+                on_event(server, Event::GaveUp);
+                continue;
+            }
+            server.restarts += 1;
+            server.task_id = spawn(server);
+            let event = match server.task_id {
+                Some(id) => Event::Restarted(id),
+                None => Event::RestartFailed,
+            };
+            on_event(server, event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Server, Supervisor, MAX_RESTARTS};
+    use std::cell::RefCell;
+    use std::vec::Vec;
+
+    /// Task IDs handed out by the fake spawner, and which are still alive.
+    #[derive(Default)]
+    struct Tasks {
+        next_id: u64,
+        alive: Vec<u64>,
+    }
+
+    impl Tasks {
+        fn spawn(&mut self) -> Option<u64> {
+            self.next_id += 1;
+            self.alive.push(self.next_id);
+            Some(self.next_id)
+        }
+
+        fn kill(&mut self, id: u64) {
+            self.alive.retain(|&a| a != id);
+        }
+    }
+
+    fn check(supervisor: &mut Supervisor<2>, tasks: &RefCell<Tasks>) -> Vec<(&'static str, Event)> {
+        let mut events = Vec::new();
+        supervisor.check(
+            |id| tasks.borrow().alive.contains(&id),
+            |_| tasks.borrow_mut().spawn(),
+            |server, event| events.push((server.module, event)),
+        );
+        events
+    }
+
+    fn started(tasks: &RefCell<Tasks>) -> Supervisor<2> {
+        let mut supervisor = Supervisor::new([Server::new("display_server", 0), Server::new("serial_server", 0)]);
+        supervisor.start("display_server", |_| tasks.borrow_mut().spawn());
+        supervisor.start("serial_server", |_| tasks.borrow_mut().spawn());
+        supervisor
+    }
+
+    #[test]
+    fn running_servers_are_left_alone() {
+        let tasks = RefCell::new(Tasks::default());
+        let mut supervisor = started(&tasks);
+        assert_eq!(check(&mut supervisor, &tasks), []);
+        assert_eq!(supervisor.servers()[0].task_id(), Some(1));
+        assert_eq!(supervisor.servers()[1].task_id(), Some(2));
+    }
+
+    #[test]
+    fn killed_server_is_respawned() {
+        let tasks = RefCell::new(Tasks::default());
+        let mut supervisor = started(&tasks);
+        tasks.borrow_mut().kill(1);
+        assert_eq!(check(&mut supervisor, &tasks), [("display_server", Event::Restarted(3))]);
+        assert_eq!(supervisor.servers()[0].task_id(), Some(3));
+        assert_eq!(check(&mut supervisor, &tasks), []);
+    }
+
+    #[test]
+    fn crash_loop_stops_after_max_restarts() {
+        let tasks = RefCell::new(Tasks::default());
+        let mut supervisor = started(&tasks);
+        for _ in 0..MAX_RESTARTS {
+            let id = supervisor.servers()[1].task_id().unwrap();
+            tasks.borrow_mut().kill(id);
+            let events = check(&mut supervisor, &tasks);
+            assert!(matches!(events[..], [("serial_server", Event::Restarted(_))]));
+        }
+        let id = supervisor.servers()[1].task_id().unwrap();
+        tasks.borrow_mut().kill(id);
+        assert_eq!(check(&mut supervisor, &tasks), [("serial_server", Event::GaveUp)]);
+        assert_eq!(check(&mut supervisor, &tasks), []);
+        assert_eq!(supervisor.servers()[1].task_id(), None);
+        // The other server is still looked after.
+        tasks.borrow_mut().kill(1);
+        assert!(matches!(check(&mut supervisor, &tasks)[..], [("display_server", Event::Restarted(_))]));
+    }
+
+    #[test]
+    fn failed_restart_is_retried() {
+        let tasks = RefCell::new(Tasks::default());
+        let mut supervisor = started(&tasks);
+        tasks.borrow_mut().kill(2);
+        let mut events = Vec::new();
+        supervisor.check(|id| tasks.borrow().alive.contains(&id), |_| None, |s, e| events.push((s.module, e)));
+        assert_eq!(events, [("serial_server", Event::RestartFailed)]);
+        assert_eq!(check(&mut supervisor, &tasks), [("serial_server", Event::Restarted(3))]);
+    }
+
+    #[test]
+    fn unknown_module_is_not_started() {
+        let mut supervisor = Supervisor::new([Server::new("display_server", 0)]);
+        assert_eq!(supervisor.start("fs_server", |_| Some(1)), None);
+    }
+}
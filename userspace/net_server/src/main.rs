@@ -0,0 +1,153 @@
+#![no_std]
+#![no_main]
+
+//! The "net" service: forwards raw Ethernet frames between clients and the
+//! kernel's NIC driver (`NetSend`/`NetRecv`), one request at a time.
+//!
+//! Spawned with `NET_SERVER_LOOPBACK` it instead registers "loopback", a
+//! software interface that hands every sent frame back to the next `Recv`.
+//! That lets the stack be tested deterministically without a NIC.
+
+use kernel_api_types::net::{MacAddress, LOOPBACK_MAC, MAX_FRAME_SIZE, MIN_FRAME_SIZE, NET_SERVER_LOOPBACK, NetMessageType};
+use kernel_api_types::{SysError, MAX_MESSAGE_SIZE, MMAP_WRITE};
+use ulib::ipc::{self, Frame, HEADER_SIZE};
+
+/// Frames the loopback interface holds before `Send` reports `WouldBlock`.
+const LOOPBACK_QUEUE_LEN: usize = 16;
+
+#[panic_handler]
+fn rust_panic(info: &core::panic::PanicInfo) -> ! {
+    ulib::default_panic(info)
+}
+
+/// Sent frames waiting to be received, oldest first.
+struct LoopbackQueue {
+    frames: [[u8; MAX_FRAME_SIZE]; LOOPBACK_QUEUE_LEN],
+    lens: [usize; LOOPBACK_QUEUE_LEN],
+    head: usize,
+    count: usize,
+}
+
+impl LoopbackQueue {
+    fn push(&mut self, frame: &[u8]) -> Result<(), SysError> {
+        if !(MIN_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&frame.len()) {
+            return Err(SysError::InvalidArgs);
+        }
+        if self.count == LOOPBACK_QUEUE_LEN {
+            return Err(SysError::WouldBlock);
+        }
+        let tail = (self.head + self.count) % LOOPBACK_QUEUE_LEN;
+        self.frames[tail][..frame.len()].copy_from_slice(frame);
+        self.lens[tail] = frame.len();
+        self.count += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self, buf: &mut [u8]) -> Result<usize, SysError> {
+        if self.count == 0 {
+            return Err(SysError::WouldBlock);
+        }
+        let len = self.lens[self.head].min(buf.len());
+        buf[..len].copy_from_slice(&self.frames[self.head][..len]);
+        self.head = (self.head + 1) % LOOPBACK_QUEUE_LEN;
+        self.count -= 1;
+        Ok(len)
+    }
+}
+
+/// Where frames go: the NIC, or straight back to the receive queue.
+enum Backend {
+    Nic,
+    Loopback(&'static mut LoopbackQueue),
+}
+
+impl Backend {
+    fn send(&mut self, frame: &[u8]) -> Result<(), SysError> {
+        match self {
+            Backend::Nic => ulib::sys_net_send(frame),
+            Backend::Loopback(queue) => queue.push(frame),
+        }
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize, SysError> {
+        match self {
+            Backend::Nic => ulib::sys_net_recv(buf),
+            Backend::Loopback(queue) => queue.pop(buf),
+        }
+    }
+
+    fn mac(&self) -> Result<MacAddress, SysError> {
+        match self {
+            Backend::Nic => ulib::sys_net_get_mac(),
+            Backend::Loopback(_) => Ok(LOOPBACK_MAC),
+        }
+    }
+}
+
+/// Answer a request on its one-shot reply endpoint, then close the endpoint.
+fn reply(reply_ep: u64, tag: u16, payload: &[u8]) {
+    let mut msg = [0u8; HEADER_SIZE + MAX_FRAME_SIZE];
+    if let Some(len) = ipc::frame_raw(tag, payload, &mut msg) {
+        let _ = ulib::sys_channel_send(reply_ep, &msg[..len]);
+    }
+    let _ = ulib::sys_channel_close(reply_ep);
+}
+
+fn handle(backend: &mut Backend, frame: &Frame) {
+    let Some(reply_ep) = frame.reply_endpoint() else {
+        return;
+    };
+    match frame.tag {
+        t if t == NetMessageType::Send as u16 => {
+            let code = match backend.send(frame.payload) {
+                Ok(()) => 0,
+                Err(e) => e as i64,
+            };
+            reply(reply_ep, t, &code.to_le_bytes());
+        }
+        t if t == NetMessageType::Recv as u16 => {
+            let mut buf = [0u8; MAX_FRAME_SIZE];
+            let len = backend.recv(&mut buf).unwrap_or(0);
+            reply(reply_ep, t, &buf[..len]);
+        }
+        t if t == NetMessageType::GetMac as u16 => match backend.mac() {
+            Ok(mac) => reply(reply_ep, t, &mac),
+            Err(_) => reply(reply_ep, t, &[]),
+        },
+        _ => {
+            let _ = ulib::sys_channel_close(reply_ep);
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "sysv64" fn entry_point(arg: u64) -> ! {
+    let _ = ulib::sys_set_task_name(if arg == NET_SERVER_LOOPBACK { "loopback" } else { "net_server" });
+    let (mut backend, name): (Backend, &[u8]) = if arg == NET_SERVER_LOOPBACK {
+        // The queue is tens of KiB, so it lives in its own pages. sys_mmap
+        // hands out zeroed pages, which is a valid empty queue.
+        let size = core::mem::size_of::<LoopbackQueue>() as u64;
+        let queue = ulib::sys_mmap(size, MMAP_WRITE).expect("net_server: no memory for the loopback queue");
+        (Backend::Loopback(unsafe { &mut *(queue as *mut LoopbackQueue) }), b"loopback")
+    } else {
+        // Without a NIC there is nothing to serve; let lookups fail with `NotFound`.
+        if let Err(SysError::NotFound) = ulib::sys_net_get_mac() {
+            ulib::sys_debug_log_str("net_server: no NIC, exiting");
+            ulib::sys_exit(0);
+        }
+        (Backend::Nic, b"net")
+    };
+
+    let (send_ep, recv_ep) = ulib::sys_channel_create(16).expect("net_server: channel_create failed");
+    ulib::sys_register_service(name, send_ep).expect("net_server: service already registered");
+
+    let mut msg = [0u8; MAX_MESSAGE_SIZE];
+    loop {
+        let Ok(n) = ulib::sys_channel_recv(recv_ep, &mut msg) else {
+            continue;
+        };
+        if let Some(frame) = Frame::parse(&msg[..n as usize]) {
+            handle(&mut backend, &frame);
+        }
+    }
+}
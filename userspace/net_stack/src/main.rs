@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+//! A minimal network stack: answers ARP requests for this host's address and
+//! replies to pings, using raw frames from the "net" service.
+
+use kernel_api_types::net::MAX_FRAME_SIZE;
+use kernel_api_types::SysError;
+use ulib::inet::{self, Ipv4Addr};
+use ulib::net::NetClient;
+
+/// QEMU user networking hands the guest this address.
+const HOST_IP: Ipv4Addr = [10, 0, 2, 15];
+
+/// How long to wait for net_server to register "net" before giving up.
+const CONNECT_ATTEMPTS: u32 = 100;
+const CONNECT_RETRY_MS: u64 = 10;
+
+#[panic_handler]
+fn rust_panic(info: &core::panic::PanicInfo) -> ! {
+    ulib::default_panic(info)
+}
+
+fn connect() -> Option<NetClient> {
+    for _ in 0..CONNECT_ATTEMPTS {
+        match NetClient::connect() {
+            Ok(net) => return Some(net),
+            Err(SysError::NotFound) => ulib::sys_sleep(CONNECT_RETRY_MS),
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "sysv64" fn entry_point(_arg: u64) -> ! {
+    let _ = ulib::sys_set_task_name("net_stack");
+    // net_server exits without registering when there is no NIC.
+    let Some(net) = connect() else {
+        ulib::sys_debug_log_str("net_stack: no \"net\" service, exiting");
+        ulib::sys_exit(0);
+    };
+    let mac = net.mac().expect("net_stack: GetMac failed");
+
+    let mut frame = [0u8; MAX_FRAME_SIZE];
+    let mut reply = [0u8; MAX_FRAME_SIZE];
+    loop {
+        let len = match net.recv(&mut frame) {
+            Ok(0) | Err(_) => {
+                // Nothing queued; the NIC is polled, so check again next tick.
+                ulib::sys_sleep(1);
+                continue;
+            }
+            Ok(len) => len,
+        };
+        if let Some(reply_len) = inet::respond(&frame[..len], mac, HOST_IP, &mut reply) {
+            let _ = net.send(&reply[..reply_len]);
+        }
+    }
+}
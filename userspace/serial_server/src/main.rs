@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+//! The "serial" service: lets userspace read and write COM1 through the
+//! kernel's `SerialWrite`/`SerialRead` syscalls, e.g. for a text console when
+//! there is no display. The kernel log shares the port, so output from here
+//! can appear between log lines, but never inside one.
+
+use kernel_api_types::serial::{SerialMessageType, SERIAL_CHUNK_SIZE};
+use kernel_api_types::MAX_MESSAGE_SIZE;
+use ulib::ipc::{self, Frame, HEADER_SIZE};
+
+#[panic_handler]
+fn rust_panic(info: &core::panic::PanicInfo) -> ! {
+    ulib::default_panic(info)
+}
+
+/// Answer a request on its one-shot reply endpoint, then close the endpoint.
+fn reply(reply_ep: u64, tag: u16, payload: &[u8]) {
+    let mut msg = [0u8; HEADER_SIZE + SERIAL_CHUNK_SIZE];
+    if let Some(len) = ipc::frame_raw(tag, payload, &mut msg) {
+        let _ = ulib::sys_channel_send(reply_ep, &msg[..len]);
+    }
+    let _ = ulib::sys_channel_close(reply_ep);
+}
+
+fn handle(frame: &Frame) {
+    let Some(reply_ep) = frame.reply_endpoint() else {
+        return;
+    };
+    match frame.tag {
+        t if t == SerialMessageType::Write as u16 => {
+            let code = frame
+                .payload
+                .chunks(SERIAL_CHUNK_SIZE)
+                .try_for_each(ulib::sys_serial_write)
+                .map_or_else(|e| e as i64, |()| 0);
+            reply(reply_ep, t, &code.to_le_bytes());
+        }
+        t if t == SerialMessageType::Read as u16 => {
+            let mut buf = [0u8; SERIAL_CHUNK_SIZE];
+            let len = ulib::sys_serial_read(&mut buf).unwrap_or(0);
+            reply(reply_ep, t, &buf[..len]);
+        }
+        _ => {
+            let _ = ulib::sys_channel_close(reply_ep);
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "sysv64" fn entry_point() -> ! {
+    let _ = ulib::sys_set_task_name("serial_server");
+    let (send_ep, recv_ep) = ulib::sys_channel_create(16).expect("serial_server: channel_create failed");
+    ulib::sys_register_service(b"serial", send_ep).expect("serial_server: service already registered");
+
+    let mut msg = [0u8; MAX_MESSAGE_SIZE];
+    loop {
+        let Ok(n) = ulib::sys_channel_recv(recv_ep, &mut msg) else {
+            continue;
+        };
+        if let Some(frame) = Frame::parse(&msg[..n as usize]) {
+            handle(&frame);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Timing curves for animations such as `Window::animate_move`.
+//!
+//! Everything here is integer math on a progress scale of
+//! `0..=PROGRESS_MAX`, so it can be used without floating point.
+
+/// Progress at the end of an animation; 0 is the start.
+pub const PROGRESS_MAX: u32 = 1000;
+
+/// How progress through an animation maps onto distance covered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Easing {
+    /// Constant speed.
+    Linear,
+    /// Accelerate from rest and decelerate to rest (smoothstep, `3t² - 2t³`).
+    EaseInOut,
+}
+
+impl Easing {
+    /// Map `progress` (clamped to `0..=PROGRESS_MAX`) to the fraction of the
+    /// distance covered, on the same scale. Both ends are fixed: 0 maps to 0
+    /// and `PROGRESS_MAX` to `PROGRESS_MAX`.
+    pub fn apply(self, progress: u32) -> u32 {
+        let t = progress.min(PROGRESS_MAX) as u64;
+        let max = PROGRESS_MAX as u64;
+        match self {
+            Easing::Linear => t as u32,
+            Easing::EaseInOut => ((3 * t * t * max - 2 * t * t * t) / (max * max)) as u32,
+        }
+    }
+}
+
+/// Progress after `elapsed_ms` of a `duration_ms` animation. A zero-length
+/// animation is already finished.
+pub fn progress(elapsed_ms: u64, duration_ms: u64) -> u32 {
+    if elapsed_ms >= duration_ms {
+        return PROGRESS_MAX;
+    }
+    (elapsed_ms * PROGRESS_MAX as u64 / duration_ms) as u32
+}
+
+/// The point `eased` (on the `0..=PROGRESS_MAX` scale) of the way from `from`
+/// to `to`, rounded towards `from`.
+pub fn lerp((x0, y0): (i32, i32), (x1, y1): (i32, i32), eased: u32) -> (i32, i32) {
+    let step = |a: i32, b: i32| {
+        let delta = (b as i64 - a as i64) * eased.min(PROGRESS_MAX) as i64 / PROGRESS_MAX as i64;
+        (a as i64 + delta) as i32
+    };
+    (step(x0, x1), step(y0, y1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lerp, progress, Easing, PROGRESS_MAX};
+
+    #[test]
+    fn endpoints_are_fixed() {
+        for easing in [Easing::Linear, Easing::EaseInOut] {
+            assert_eq!(easing.apply(0), 0);
+            assert_eq!(easing.apply(PROGRESS_MAX), PROGRESS_MAX);
+            assert_eq!(easing.apply(PROGRESS_MAX + 1), PROGRESS_MAX);
+        }
+    }
+
+    #[test]
+    fn linear_is_identity() {
+        for p in [1, 250, 500, 999] {
+            assert_eq!(Easing::Linear.apply(p), p);
+        }
+    }
+
+    #[test]
+    fn ease_in_out_is_monotonic_and_symmetric() {
+        let mut last = 0;
+        for p in 0..=PROGRESS_MAX {
+            let v = Easing::EaseInOut.apply(p);
+            assert!(v >= last, "{p}: {v} < {last}");
+            last = v;
+            // Symmetric about the midpoint, up to rounding.
+            let mirrored = PROGRESS_MAX - Easing::EaseInOut.apply(PROGRESS_MAX - p);
+            assert!(v.abs_diff(mirrored) <= 1, "{p}: {v} vs {mirrored}");
+        }
+        assert_eq!(Easing::EaseInOut.apply(PROGRESS_MAX / 2), PROGRESS_MAX / 2);
+    }
+
+    #[test]
+    fn ease_in_out_starts_and_ends_slowly() {
+        assert!(Easing::EaseInOut.apply(100) < Easing::Linear.apply(100));
+        assert!(Easing::EaseInOut.apply(900) > Easing::Linear.apply(900));
+    }
+
+    #[test]
+    fn progress_clamps_and_handles_zero_duration() {
+        assert_eq!(progress(0, 200), 0);
+        assert_eq!(progress(50, 200), 250);
+        assert_eq!(progress(500, 200), PROGRESS_MAX);
+        assert_eq!(progress(0, 0), PROGRESS_MAX);
+    }
+
+    #[test]
+    fn lerp_covers_the_segment() {
+        assert_eq!(lerp((10, 20), (110, -80), 0), (10, 20));
+        assert_eq!(lerp((10, 20), (110, -80), 500), (60, -30));
+        assert_eq!(lerp((10, 20), (110, -80), PROGRESS_MAX), (110, -80));
+        assert_eq!(lerp((i32::MIN, i32::MAX), (i32::MAX, i32::MIN), PROGRESS_MAX), (i32::MAX, i32::MIN));
+    }
+}
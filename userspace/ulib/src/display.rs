@@ -1,11 +1,13 @@
 use core::convert::Infallible;
 use embedded_graphics::draw_target::DrawTarget;
-use embedded_graphics::geometry::Size;
+use embedded_graphics::geometry::{Point, Size};
 use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
 use embedded_graphics::prelude::OriginDimensions;
 use embedded_graphics::Pixel;
 use kernel_api_types::graphics::{DisplayInfo, FRAMEBUFFER_USER_VADDR};
 use kernel_api_types::MMAP_WRITE;
+use kernel_api_types::window::{clip_blit, CursorImage, MAX_CURSOR_SIZE};
+use crate::raster;
 use crate::window::DirtyRect;
 
 pub struct Display {
@@ -15,6 +17,11 @@ pub struct Display {
     height: u32,
     info: DisplayInfo,
     dirty: Option<DirtyRect>,
+    /// Copy of the last frame written to VRAM, used by `present_diff`.
+    /// `None` until `enable_diff_present` is called.
+    shadow_buffer: Option<&'static mut [u32]>,
+    /// Total bytes written to the front buffer by `present`/`present_diff`.
+    vram_bytes_written: u64,
 }
 
 impl Display {
@@ -29,7 +36,7 @@ impl Display {
 
         // Allocate the Back Buffer (RAM)
         // This is just normal heap memory or anonymous mmap
-        let back_ptr = crate::sys_mmap(buf_size, MMAP_WRITE);
+        let back_ptr = crate::sys_mmap(buf_size, MMAP_WRITE).expect("Display::new: back buffer mmap failed");
         let back_buffer = unsafe { core::slice::from_raw_parts_mut(back_ptr as *mut u32, buf_size as usize / 4) };
 
         // Get the Front Buffer (VRAM) - mapped by TransferDisplay syscall
@@ -43,9 +50,32 @@ impl Display {
             height,
             info,
             dirty: None,
+            shadow_buffer: None,
+            vram_bytes_written: 0,
         }
     }
 
+    /// Allocate the shadow buffer `present_diff` compares against, seeded with
+    /// the current VRAM contents. Returns false if the allocation failed.
+    pub fn enable_diff_present(&mut self) -> bool {
+        if self.shadow_buffer.is_some() {
+            return true;
+        }
+        let len = self.front_buffer.len();
+        let Ok(ptr) = crate::sys_mmap(len as u64 * 4, MMAP_WRITE) else {
+            return false;
+        };
+        let shadow = unsafe { core::slice::from_raw_parts_mut(ptr as *mut u32, len) };
+        shadow.copy_from_slice(self.front_buffer);
+        self.shadow_buffer = Some(shadow);
+        true
+    }
+
+    /// Total bytes written to VRAM so far by `present` and `present_diff`.
+    pub fn vram_bytes_written(&self) -> u64 {
+        self.vram_bytes_written
+    }
+
     /// Flushes only the dirty region from the back buffer to the hardware front buffer.
     pub fn present(&mut self) {
         if let Some(dirty) = self.dirty.take() {
@@ -66,12 +96,60 @@ impl Display {
                         width,
                     );
                 }
+                // Keep the shadow in sync so a later present_diff compares against VRAM.
+                if let Some(shadow) = self.shadow_buffer.as_deref_mut() {
+                    shadow[offset..offset + width]
+                        .copy_from_slice(&self.back_buffer[offset..offset + width]);
+                }
             }
+            self.vram_bytes_written += (width * height * 4) as u64;
         }
     }
 
+    /// Like `present`, but compares each dirty row against the last presented
+    /// frame and only writes the spans that actually changed.
+    ///
+    /// Falls back to `present` if `enable_diff_present` hasn't been called.
+    /// Returns the number of bytes written to VRAM.
+    pub fn present_diff(&mut self) -> u64 {
+        let Some(shadow) = self.shadow_buffer.as_deref_mut() else {
+            let before = self.vram_bytes_written;
+            self.present();
+            return self.vram_bytes_written - before;
+        };
+        let Some(dirty) = self.dirty.take() else {
+            return 0;
+        };
+
+        let mut written = 0usize;
+        for y in dirty.y..dirty.y + dirty.h {
+            let row_start = y as usize * self.width as usize + dirty.x as usize;
+            let row_end = row_start + dirty.w as usize;
+
+            let mut x = row_start;
+            while x < row_end {
+                if self.back_buffer[x] == shadow[x] {
+                    x += 1;
+                    continue;
+                }
+                let span_start = x;
+                while x < row_end && self.back_buffer[x] != shadow[x] {
+                    x += 1;
+                }
+                let span = &self.back_buffer[span_start..x];
+                self.front_buffer[span_start..x].copy_from_slice(span);
+                shadow[span_start..x].copy_from_slice(span);
+                written += span.len() * 4;
+            }
+        }
+
+        self.vram_bytes_written += written as u64;
+        written as u64
+    }
+
     /// Blit raw u32 pixels directly into the back buffer (no color conversion).
-    /// Pixels are already in the native framebuffer format.
+    /// Pixels are already in the native framebuffer format. Any position and
+    /// size is accepted; only the on-screen part is copied.
     pub fn blit_raw(
         &mut self,
         src: *const u32,
@@ -81,84 +159,102 @@ impl Display {
         w: u32,
         h: u32,
     ) {
-        // Clip to display bounds
-        let x0 = dst_x.max(0) as u32;
-        let y0 = dst_y.max(0) as u32;
-        let x1 = ((dst_x + w as i32).max(0) as u32).min(self.width);
-        let y1 = ((dst_y + h as i32).max(0) as u32).min(self.height);
-        if x0 >= x1 || y0 >= y1 {
-            return;
-        }
-        let clipped_w = x1 - x0;
-        let src_x_off = (x0 as i32 - dst_x) as u32;
-        let src_y_off = (y0 as i32 - dst_y) as u32;
-
-        for row in 0..(y1 - y0) {
-            let src_offset = ((src_y_off + row) * src_width + src_x_off) as usize;
-            let dst_offset = ((y0 + row) as usize) * (self.width as usize) + x0 as usize;
-            unsafe {
-                core::ptr::copy_nonoverlapping(
-                    src.add(src_offset),
-                    self.back_buffer.as_mut_ptr().add(dst_offset),
-                    clipped_w as usize,
-                );
-            }
-        }
-        self.expand_dirty(x0, y0, clipped_w, y1 - y0);
+        self.blit_raw_strided(src, src_width, 0, 0, dst_x, dst_y, w, h);
     }
 
-    /// Blit a two-layer bitmask cursor sprite into the back buffer.
-    ///
-    /// `mask[row]`  — bit=1 (MSB=col 0) means the pixel is opaque.
-    /// `image[row]` — among opaque pixels, bit=1 → `white`, bit=0 → `black`.
-    ///
-    /// `black` and `white` must already be in the native framebuffer pixel format
-    /// (build them once with `DisplayInfo::build_pixel`).
-    pub fn blit_cursor(
+    /// Like `blit_raw`, but copies the `w × h` rectangle at `(src_x, src_y)`
+    /// out of a source with `src_stride` pixels per row, so a sub-region of a
+    /// larger buffer can be blitted without offsetting `src` by hand.
+    // Safe like `blit_raw`: callers pass pointers into their own buffers.
+    #[allow(clippy::too_many_arguments, clippy::not_unsafe_ptr_arg_deref)]
+    pub fn blit_raw_strided(
         &mut self,
-        cx: i32,
-        cy: i32,
-        mask: &[u16],
-        image: &[u16],
+        src: *const u32,
+        src_stride: u32,
+        src_x: u32,
+        src_y: u32,
+        dst_x: i32,
+        dst_y: i32,
         w: u32,
         h: u32,
-        black: u32,
-        white: u32,
     ) {
-        let sw = self.width as i32;
-        let sh = self.height as i32;
+        let touched = unsafe {
+            raster::blit_strided(
+                self.back_buffer,
+                self.width,
+                self.height,
+                src,
+                src_stride,
+                (src_x, src_y),
+                (dst_x, dst_y),
+                w,
+                h,
+            )
+        };
+        if let Some(d) = touched {
+            self.expand_dirty(d.x, d.y, d.w, d.h);
+        }
+    }
+
+    /// Blit a two-layer bitmask cursor sprite into the back buffer, with its
+    /// hot spot at `(x, y)`.
+    ///
+    /// `cursor.mask[row]`  — bit=1 (MSB=col 0) means the pixel is opaque.
+    /// `cursor.image[row]` — among opaque pixels, bit=1 → `white`, bit=0 → `black`.
+    ///
+    /// `black` and `white` must already be in the native framebuffer pixel format
+    /// (build them once with `DisplayInfo::build_pixel`). A size beyond
+    /// `MAX_CURSOR_SIZE` is cut to it.
+    pub fn blit_cursor(&mut self, x: i32, y: i32, cursor: &CursorImage, black: u32, white: u32) {
+        let w = cursor.width.min(MAX_CURSOR_SIZE as u32);
+        let h = cursor.height.min(MAX_CURSOR_SIZE as u32);
+        let (cx, cy) = (x.saturating_sub_unsigned(cursor.hot_x), y.saturating_sub_unsigned(cursor.hot_y));
+        let Some(clip) = clip_blit(cx, cy, w, h, self.width, self.height) else {
+            return;
+        };
+        let d = clip.dst;
 
         // Expand dirty to the cursor's clipped bounding rect upfront (one call, not per pixel).
-        let x0 = cx.max(0) as u32;
-        let y0 = cy.max(0) as u32;
-        let x1 = ((cx + w as i32).max(0)).min(sw) as u32;
-        let y1 = ((cy + h as i32).max(0)).min(sh) as u32;
-        if x0 < x1 && y0 < y1 {
-            self.expand_dirty(x0, y0, x1 - x0, y1 - y0);
-        }
+        self.expand_dirty(d.x, d.y, d.w, d.h);
 
-        for row in 0..h as i32 {
-            let sy = cy + row;
-            if sy < 0 || sy >= sh {
-                continue;
-            }
-            let row_mask = mask[row as usize];
-            let row_image = image[row as usize];
-            for col in 0..w as i32 {
-                if (row_mask >> (15 - col)) & 1 == 0 {
+        for r in 0..d.h {
+            let row = (clip.src_y + r) as usize;
+            let row_mask = cursor.mask[row];
+            let row_image = cursor.image[row];
+            for c in 0..d.w {
+                let col = clip.src_x + c;
+                if (row_mask >> (31 - col)) & 1 == 0 {
                     continue; // transparent
                 }
-                let sx = cx + col;
-                if sx < 0 || sx >= sw {
-                    continue;
-                }
-                let pixel = if (row_image >> (15 - col)) & 1 == 1 { white } else { black };
-                let off = sy as usize * self.width as usize + sx as usize;
+                let pixel = if (row_image >> (31 - col)) & 1 == 1 { white } else { black };
+                let off = (d.y + r) as usize * self.width as usize + (d.x + c) as usize;
                 self.back_buffer[off] = pixel;
             }
         }
     }
 
+    /// Draw a 1-pixel line from `p0` to `p1` (inclusive) directly into the back buffer.
+    pub fn draw_line(&mut self, p0: Point, p1: Point, color: Rgb888) {
+        let pixel = self.info.build_pixel(color.r(), color.g(), color.b());
+        let touched = raster::draw_line(
+            self.back_buffer, self.width, self.height, (p0.x, p0.y), (p1.x, p1.y), pixel,
+        );
+        if let Some(d) = touched {
+            self.expand_dirty(d.x, d.y, d.w, d.h);
+        }
+    }
+
+    /// Fill a circle of `radius` pixels around `center` directly into the back buffer.
+    pub fn fill_circle(&mut self, center: Point, radius: u32, color: Rgb888) {
+        let pixel = self.info.build_pixel(color.r(), color.g(), color.b());
+        let touched = raster::fill_circle(
+            self.back_buffer, self.width, self.height, (center.x, center.y), radius, pixel,
+        );
+        if let Some(d) = touched {
+            self.expand_dirty(d.x, d.y, d.w, d.h);
+        }
+    }
+
     fn expand_dirty(&mut self, x: u32, y: u32, w: u32, h: u32) {
         match &mut self.dirty {
             Some(d) => d.expand(x, y, w, h),
@@ -239,3 +335,75 @@ impl DrawTarget for Display {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Display;
+    use kernel_api_types::graphics::DisplayInfo;
+    use std::boxed::Box;
+    use std::vec;
+
+    const WIDTH: u32 = 16;
+    const HEIGHT: u32 = 8;
+
+    /// A display over heap buffers instead of VRAM, with a shadow buffer if `diff`.
+    fn test_display(diff: bool) -> Display {
+        let pixels = (WIDTH * HEIGHT) as usize;
+        let info = DisplayInfo {
+            width: WIDTH,
+            height: HEIGHT,
+            red_mask_size: 8,
+            red_mask_shift: 16,
+            green_mask_size: 8,
+            green_mask_shift: 8,
+            blue_mask_size: 8,
+            blue_mask_shift: 0,
+        };
+        Display {
+            back_buffer: Box::leak(vec![0; pixels].into_boxed_slice()),
+            front_buffer: Box::leak(vec![0; pixels].into_boxed_slice()),
+            width: WIDTH,
+            height: HEIGHT,
+            info,
+            dirty: None,
+            shadow_buffer: diff.then(|| Box::leak(vec![0; pixels].into_boxed_slice())),
+            vram_bytes_written: 0,
+        }
+    }
+
+    /// Change two pixels but mark the whole screen dirty, as a compositor
+    /// redrawing a blinking cursor's damage rect would.
+    fn blink(display: &mut Display) {
+        display.back_buffer.fill(0x0020_2020);
+        display.back_buffer[3] = 0x00ff_ffff;
+        display.back_buffer[(WIDTH + 3) as usize] = 0x00ff_ffff;
+        display.expand_dirty(0, 0, WIDTH, HEIGHT);
+    }
+
+    #[test]
+    fn present_diff_writes_only_changed_spans() {
+        let mut full = test_display(false);
+        let mut diff = test_display(true);
+        for display in [&mut full, &mut diff] {
+            display.back_buffer.fill(0x0020_2020);
+            display.expand_dirty(0, 0, WIDTH, HEIGHT);
+            display.present();
+            blink(display);
+        }
+
+        let before = full.vram_bytes_written();
+        full.present();
+        let diff_written = diff.present_diff();
+
+        assert_eq!(full.vram_bytes_written() - before, (WIDTH * HEIGHT * 4) as u64);
+        assert_eq!(diff_written, 2 * 4);
+        assert_eq!(full.front_buffer, diff.front_buffer);
+    }
+
+    #[test]
+    fn present_diff_without_shadow_falls_back_to_present() {
+        let mut display = test_display(false);
+        blink(&mut display);
+        assert_eq!(display.present_diff(), (WIDTH * HEIGHT * 4) as u64);
+    }
+}
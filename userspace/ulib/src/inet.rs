@@ -0,0 +1,358 @@
+//! Just enough Ethernet, ARP, IPv4 and ICMP to answer ARP requests and pings.
+//!
+//! Everything works on whole Ethernet frames without the CRC, as carried by
+//! the "net" service. Multi-byte header fields are big-endian on the wire.
+
+use kernel_api_types::net::{MacAddress, BROADCAST_MAC, ETHERNET_HEADER_SIZE};
+
+pub type Ipv4Addr = [u8; 4];
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+pub const ARP_REQUEST: u16 = 1;
+pub const ARP_REPLY: u16 = 2;
+const ARP_PACKET_SIZE: usize = 28;
+/// An Ethernet frame carrying one ARP packet.
+pub const ARP_FRAME_SIZE: usize = ETHERNET_HEADER_SIZE + ARP_PACKET_SIZE;
+
+const IPV4_HEADER_SIZE: usize = 20;
+const IP_PROTOCOL_ICMP: u8 = 1;
+const IP_FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const IP_FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const IP_FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
+const DEFAULT_TTL: u8 = 64;
+
+pub const ICMP_ECHO_REPLY: u8 = 0;
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_HEADER_SIZE: usize = 8;
+
+fn be16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn put_be16(bytes: &mut [u8], at: usize, value: u16) {
+    bytes[at..at + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// The Internet checksum (RFC 1071): the ones' complement of the ones'
+/// complement sum of `data` as big-endian 16-bit words. Summing a header that
+/// includes its own correct checksum gives 0.
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [last] = words.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+pub fn ethertype(frame: &[u8]) -> Option<u16> {
+    (frame.len() >= ETHERNET_HEADER_SIZE).then(|| be16(frame, 12))
+}
+
+fn write_ethernet_header(out: &mut [u8], destination: MacAddress, source: MacAddress, ethertype: u16) {
+    out[0..6].copy_from_slice(&destination);
+    out[6..12].copy_from_slice(&source);
+    put_be16(out, 12, ethertype);
+}
+
+/// An ARP packet for IPv4 over Ethernet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: u16,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        if ethertype(frame)? != ETHERTYPE_ARP || frame.len() < ARP_FRAME_SIZE {
+            return None;
+        }
+        let arp = &frame[ETHERNET_HEADER_SIZE..ARP_FRAME_SIZE];
+        // Hardware type Ethernet (1), protocol IPv4, 6-byte and 4-byte addresses.
+        if be16(arp, 0) != 1 || be16(arp, 2) != ETHERTYPE_IPV4 || arp[4] != 6 || arp[5] != 4 {
+            return None;
+        }
+        Some(Self {
+            operation: be16(arp, 6),
+            sender_mac: arp[8..14].try_into().unwrap(),
+            sender_ip: arp[14..18].try_into().unwrap(),
+            target_mac: arp[18..24].try_into().unwrap(),
+            target_ip: arp[24..28].try_into().unwrap(),
+        })
+    }
+
+    /// Write this packet, sent from `sender_mac` to `destination`, into `out`.
+    /// Returns the frame length, or None if `out` is too small.
+    pub fn write_frame(&self, destination: MacAddress, out: &mut [u8]) -> Option<usize> {
+        let out = out.get_mut(..ARP_FRAME_SIZE)?;
+        write_ethernet_header(out, destination, self.sender_mac, ETHERTYPE_ARP);
+        let arp = &mut out[ETHERNET_HEADER_SIZE..];
+        put_be16(arp, 0, 1);
+        put_be16(arp, 2, ETHERTYPE_IPV4);
+        arp[4] = 6;
+        arp[5] = 4;
+        put_be16(arp, 6, self.operation);
+        arp[8..14].copy_from_slice(&self.sender_mac);
+        arp[14..18].copy_from_slice(&self.sender_ip);
+        arp[18..24].copy_from_slice(&self.target_mac);
+        arp[24..28].copy_from_slice(&self.target_ip);
+        Some(ARP_FRAME_SIZE)
+    }
+
+    /// A broadcast "who has `target_ip`?" from `mac`/`ip`.
+    pub fn request(mac: MacAddress, ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+        Self { operation: ARP_REQUEST, sender_mac: mac, sender_ip: ip, target_mac: [0; 6], target_ip }
+    }
+}
+
+/// An ICMP echo request or reply in an unfragmented IPv4 packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IcmpEcho<'a> {
+    pub source_mac: MacAddress,
+    pub destination_mac: MacAddress,
+    pub source_ip: Ipv4Addr,
+    pub destination_ip: Ipv4Addr,
+    /// `ICMP_ECHO_REQUEST` or `ICMP_ECHO_REPLY`.
+    pub kind: u8,
+    pub identifier: u16,
+    pub sequence: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> IcmpEcho<'a> {
+    /// Parse an echo from `frame`, verifying both checksums. Options in the
+    /// IP header are skipped; fragments are rejected.
+    pub fn parse(frame: &'a [u8]) -> Option<Self> {
+        if ethertype(frame)? != ETHERTYPE_IPV4 {
+            return None;
+        }
+        let ip = &frame[ETHERNET_HEADER_SIZE..];
+        if ip.len() < IPV4_HEADER_SIZE || ip[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (ip[0] & 0x0f) as usize * 4;
+        let total_len = be16(ip, 2) as usize;
+        if header_len < IPV4_HEADER_SIZE || total_len < header_len || total_len > ip.len() {
+            return None;
+        }
+        let flags = be16(ip, 6);
+        if flags & (IP_FLAG_MORE_FRAGMENTS | IP_FRAGMENT_OFFSET_MASK) != 0
+            || ip[9] != IP_PROTOCOL_ICMP
+            || checksum(&ip[..header_len]) != 0
+        {
+            return None;
+        }
+
+        // The frame may carry Ethernet padding past `total_len`.
+        let icmp = &ip[header_len..total_len];
+        if icmp.len() < ICMP_ECHO_HEADER_SIZE || icmp[1] != 0 || checksum(icmp) != 0 {
+            return None;
+        }
+        if icmp[0] != ICMP_ECHO_REQUEST && icmp[0] != ICMP_ECHO_REPLY {
+            return None;
+        }
+        Some(Self {
+            destination_mac: frame[0..6].try_into().unwrap(),
+            source_mac: frame[6..12].try_into().unwrap(),
+            source_ip: ip[12..16].try_into().unwrap(),
+            destination_ip: ip[16..20].try_into().unwrap(),
+            kind: icmp[0],
+            identifier: be16(icmp, 4),
+            sequence: be16(icmp, 6),
+            payload: &icmp[ICMP_ECHO_HEADER_SIZE..],
+        })
+    }
+
+    /// Write this echo as a complete frame into `out`. Returns the frame
+    /// length, or None if `out` is too small.
+    pub fn write_frame(&self, out: &mut [u8]) -> Option<usize> {
+        let icmp_len = ICMP_ECHO_HEADER_SIZE + self.payload.len();
+        let total_len = IPV4_HEADER_SIZE + icmp_len;
+        let frame_len = ETHERNET_HEADER_SIZE + total_len;
+        let total_len = u16::try_from(total_len).ok()?;
+        let out = out.get_mut(..frame_len)?;
+
+        write_ethernet_header(out, self.destination_mac, self.source_mac, ETHERTYPE_IPV4);
+        let (ip, icmp) = out[ETHERNET_HEADER_SIZE..].split_at_mut(IPV4_HEADER_SIZE);
+        ip.fill(0);
+        ip[0] = 0x45; // Version 4, 5-word header.
+        put_be16(ip, 2, total_len);
+        put_be16(ip, 6, IP_FLAG_DONT_FRAGMENT);
+        ip[8] = DEFAULT_TTL;
+        ip[9] = IP_PROTOCOL_ICMP;
+        ip[12..16].copy_from_slice(&self.source_ip);
+        ip[16..20].copy_from_slice(&self.destination_ip);
+        let ip_checksum = checksum(ip);
+        put_be16(ip, 10, ip_checksum);
+
+        icmp[0] = self.kind;
+        icmp[1] = 0;
+        put_be16(icmp, 2, 0);
+        put_be16(icmp, 4, self.identifier);
+        put_be16(icmp, 6, self.sequence);
+        icmp[ICMP_ECHO_HEADER_SIZE..].copy_from_slice(self.payload);
+        let icmp_checksum = checksum(icmp);
+        put_be16(icmp, 2, icmp_checksum);
+        Some(frame_len)
+    }
+}
+
+/// The frame a host at `mac`/`ip` should answer `frame` with, written into
+/// `out`: an ARP reply to a request for `ip`, or an echo reply to a ping of
+/// `ip`. Returns the reply's length, or None if `frame` needs no answer.
+pub fn respond(frame: &[u8], mac: MacAddress, ip: Ipv4Addr, out: &mut [u8]) -> Option<usize> {
+    match ethertype(frame)? {
+        ETHERTYPE_ARP => {
+            let request = ArpPacket::parse(frame)?;
+            if request.operation != ARP_REQUEST || request.target_ip != ip {
+                return None;
+            }
+            let reply = ArpPacket {
+                operation: ARP_REPLY,
+                sender_mac: mac,
+                sender_ip: ip,
+                target_mac: request.sender_mac,
+                target_ip: request.sender_ip,
+            };
+            reply.write_frame(request.sender_mac, out)
+        }
+        ETHERTYPE_IPV4 => {
+            let ping = IcmpEcho::parse(frame)?;
+            let for_us = ping.destination_mac == mac || ping.destination_mac == BROADCAST_MAC;
+            if ping.kind != ICMP_ECHO_REQUEST || ping.destination_ip != ip || !for_us {
+                return None;
+            }
+            let reply = IcmpEcho {
+                source_mac: mac,
+                destination_mac: ping.source_mac,
+                source_ip: ip,
+                destination_ip: ping.source_ip,
+                kind: ICMP_ECHO_REPLY,
+                ..ping
+            };
+            reply.write_frame(out)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel_api_types::net::MAX_FRAME_SIZE;
+
+    const HOST_MAC: MacAddress = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+    const HOST_IP: Ipv4Addr = [10, 0, 2, 15];
+    const PEER_MAC: MacAddress = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+    const PEER_IP: Ipv4Addr = [10, 0, 2, 2];
+
+    fn ping(destination_ip: Ipv4Addr, payload: &[u8], out: &mut [u8]) -> usize {
+        let echo = IcmpEcho {
+            source_mac: PEER_MAC,
+            destination_mac: HOST_MAC,
+            source_ip: PEER_IP,
+            destination_ip,
+            kind: ICMP_ECHO_REQUEST,
+            identifier: 0x1234,
+            sequence: 7,
+            payload,
+        };
+        echo.write_frame(out).unwrap()
+    }
+
+    #[test]
+    fn checksum_matches_rfc1071_example() {
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(checksum(&data), !0xddf2);
+        // An odd trailing byte is padded with zero.
+        assert_eq!(checksum(&[0x12]), !0x1200);
+    }
+
+    #[test]
+    fn arp_request_for_us_gets_reply() {
+        let mut request = [0u8; ARP_FRAME_SIZE];
+        ArpPacket::request(PEER_MAC, PEER_IP, HOST_IP).write_frame(BROADCAST_MAC, &mut request).unwrap();
+
+        let mut reply = [0u8; MAX_FRAME_SIZE];
+        let len = respond(&request, HOST_MAC, HOST_IP, &mut reply).unwrap();
+        assert_eq!(&reply[0..6], &PEER_MAC);
+        assert_eq!(
+            ArpPacket::parse(&reply[..len]),
+            Some(ArpPacket {
+                operation: ARP_REPLY,
+                sender_mac: HOST_MAC,
+                sender_ip: HOST_IP,
+                target_mac: PEER_MAC,
+                target_ip: PEER_IP,
+            })
+        );
+    }
+
+    #[test]
+    fn arp_for_other_hosts_and_replies_are_ignored() {
+        let mut frame = [0u8; ARP_FRAME_SIZE];
+        let mut out = [0u8; MAX_FRAME_SIZE];
+        ArpPacket::request(PEER_MAC, PEER_IP, [10, 0, 2, 3]).write_frame(BROADCAST_MAC, &mut frame).unwrap();
+        assert_eq!(respond(&frame, HOST_MAC, HOST_IP, &mut out), None);
+
+        let mut reply = ArpPacket::request(PEER_MAC, PEER_IP, HOST_IP);
+        reply.operation = ARP_REPLY;
+        reply.write_frame(HOST_MAC, &mut frame).unwrap();
+        assert_eq!(respond(&frame, HOST_MAC, HOST_IP, &mut out), None);
+    }
+
+    #[test]
+    fn ping_gets_echo_reply() {
+        let mut request = [0u8; MAX_FRAME_SIZE];
+        let len = ping(HOST_IP, b"abcdefghijk", &mut request);
+        assert!(IcmpEcho::parse(&request[..len]).is_some());
+
+        let mut reply = [0u8; MAX_FRAME_SIZE];
+        let reply_len = respond(&request[..len], HOST_MAC, HOST_IP, &mut reply).unwrap();
+        let echo = IcmpEcho::parse(&reply[..reply_len]).unwrap();
+        assert_eq!(echo.kind, ICMP_ECHO_REPLY);
+        assert_eq!((echo.source_mac, echo.destination_mac), (HOST_MAC, PEER_MAC));
+        assert_eq!((echo.source_ip, echo.destination_ip), (HOST_IP, PEER_IP));
+        assert_eq!((echo.identifier, echo.sequence), (0x1234, 7));
+        assert_eq!(echo.payload, b"abcdefghijk");
+    }
+
+    #[test]
+    fn ping_with_ethernet_padding_parses() {
+        let mut frame = [0u8; 60];
+        let len = ping(HOST_IP, b"", &mut frame);
+        assert!(len < frame.len());
+        assert_eq!(IcmpEcho::parse(&frame).map(|e| e.payload.len()), Some(0));
+    }
+
+    #[test]
+    fn bad_or_foreign_pings_are_ignored() {
+        let mut frame = [0u8; MAX_FRAME_SIZE];
+        let mut out = [0u8; MAX_FRAME_SIZE];
+
+        let len = ping([10, 0, 2, 3], b"x", &mut frame);
+        assert_eq!(respond(&frame[..len], HOST_MAC, HOST_IP, &mut out), None);
+
+        let len = ping(HOST_IP, b"x", &mut frame);
+        frame[len - 1] ^= 0xff; // Corrupt the payload under the ICMP checksum.
+        assert_eq!(respond(&frame[..len], HOST_MAC, HOST_IP, &mut out), None);
+
+        let len = ping(HOST_IP, b"x", &mut frame);
+        frame[ETHERNET_HEADER_SIZE + 8] = 1; // TTL, under the IP checksum.
+        assert_eq!(respond(&frame[..len], HOST_MAC, HOST_IP, &mut out), None);
+
+        let len = ping(HOST_IP, b"x", &mut frame);
+        assert_eq!(respond(&frame[..len - 5], HOST_MAC, HOST_IP, &mut out), None);
+    }
+}
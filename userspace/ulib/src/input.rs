@@ -0,0 +1,19 @@
+//! Playback of recorded input (see `kernel_api_types::input`).
+
+use kernel_api_types::input::RecordedInput;
+use kernel_api_types::SysError;
+
+/// Inject `records` in order, each at its recorded time relative to now.
+/// Stops at the first event the kernel refuses.
+pub fn replay(records: &[RecordedInput]) -> Result<(), SysError> {
+    let tsc_hz = crate::sys_get_tsc_hz().max(1);
+    let start = crate::sys_get_cycles();
+    for record in records {
+        let elapsed_ms = (crate::sys_get_cycles() - start) * 1000 / tsc_hz;
+        if record.time_ms > elapsed_ms {
+            crate::sys_sleep(record.time_ms - elapsed_ms);
+        }
+        crate::sys_inject_input(&record.event)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,228 @@
+//! Typed message framing on top of raw channels.
+//!
+//! Wire format (all integers little-endian):
+//!
+//! | Offset      | Size  | Field                                             |
+//! |-------------|-------|---------------------------------------------------|
+//! | 0           | 2     | `tag`: message type, chosen by the protocol       |
+//! | 2           | 4     | `len`: payload length in bytes                    |
+//! | 6           | `len` | payload: a `#[repr(C)]` struct's raw bytes        |
+//! | 6 + `len`   | rest  | trailer, e.g. the reply endpoint `sys_channel_call` appends |
+//!
+//! The payload is the struct exactly as it sits in memory, so a receiver that
+//! skips the 6-byte header can still decode it with `read_unaligned`.
+
+use core::mem::size_of;
+use kernel_api_types::{SysError, MAX_MESSAGE_SIZE};
+
+/// Size of the tag + length header.
+pub const HEADER_SIZE: usize = 6;
+
+/// Largest payload a `Message` can carry.
+pub const MAX_PAYLOAD: usize = 128;
+
+/// A framed message ready to send.
+pub struct Message {
+    buf: [u8; HEADER_SIZE + MAX_PAYLOAD],
+    len: usize,
+}
+
+impl Message {
+    /// Frame `payload` under `tag`.
+    pub fn new<T: Copy>(tag: u16, payload: &T) -> Self {
+        const { assert!(size_of::<T>() <= MAX_PAYLOAD, "payload too large for ipc::Message") };
+        let size = size_of::<T>();
+        let mut buf = [0u8; HEADER_SIZE + MAX_PAYLOAD];
+        buf[0..2].copy_from_slice(&tag.to_le_bytes());
+        buf[2..6].copy_from_slice(&(size as u32).to_le_bytes());
+        unsafe {
+            core::ptr::copy_nonoverlapping(payload as *const T as *const u8, buf.as_mut_ptr().add(HEADER_SIZE), size);
+        }
+        Message { buf, len: HEADER_SIZE + size }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Frame a variable-length `payload` under `tag` into `buf`, for payloads too
+/// big for a `Message`. Returns the framed length, or `None` if `buf` is too small.
+pub fn frame_raw(tag: u16, payload: &[u8], buf: &mut [u8]) -> Option<usize> {
+    let len = HEADER_SIZE + payload.len();
+    let out = buf.get_mut(..len)?;
+    out[0..2].copy_from_slice(&tag.to_le_bytes());
+    out[2..6].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    out[HEADER_SIZE..].copy_from_slice(payload);
+    Some(len)
+}
+
+/// Frame the raw bytes of `payload` under `tag` into `buf`, like `Message::new`
+/// but for structs bigger than `MAX_PAYLOAD`. Returns the framed length, or
+/// `None` if `buf` is too small.
+pub fn frame_typed<T: Copy>(tag: u16, payload: &T, buf: &mut [u8]) -> Option<usize> {
+    let bytes = unsafe { core::slice::from_raw_parts(payload as *const T as *const u8, size_of::<T>()) };
+    frame_raw(tag, bytes, buf)
+}
+
+/// A received message split into its parts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub tag: u16,
+    pub payload: &'a [u8],
+    /// Bytes after the payload.
+    pub trailer: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Split `msg` into header, payload and trailer. Returns `None` if it is
+    /// shorter than the header or the length field runs past its end.
+    pub fn parse(msg: &'a [u8]) -> Option<Self> {
+        let header = msg.get(..HEADER_SIZE)?;
+        let tag = u16::from_le_bytes([header[0], header[1]]);
+        let len = u32::from_le_bytes([header[2], header[3], header[4], header[5]]) as usize;
+        let end = HEADER_SIZE.checked_add(len)?;
+        Some(Frame { tag, payload: msg.get(HEADER_SIZE..end)?, trailer: &msg[end..] })
+    }
+
+    /// Decode the payload as a `T`, if it is exactly `size_of::<T>()` bytes.
+    ///
+    /// `T` must be valid for any bit pattern of the right size, or the sender
+    /// must be trusted to have sent a real `T`.
+    pub fn read<T: Copy>(&self) -> Option<T> {
+        if self.payload.len() != size_of::<T>() {
+            return None;
+        }
+        Some(unsafe { core::ptr::read_unaligned(self.payload.as_ptr() as *const T) })
+    }
+
+    /// The reply endpoint appended by `sys_channel_call`, if the trailer is one.
+    pub fn reply_endpoint(&self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.trailer.try_into().ok()?))
+    }
+}
+
+/// Send `payload` framed under `tag`.
+pub fn send_typed<T: Copy>(ep: u64, tag: u16, payload: &T) -> Result<(), SysError> {
+    crate::sys_channel_send(ep, Message::new(tag, payload).as_bytes())
+}
+
+/// Like `send_typed`, but waits for room instead of failing on a full channel.
+pub fn send_typed_blocking<T: Copy>(ep: u64, tag: u16, payload: &T) -> Result<(), SysError> {
+    crate::sys_channel_send_blocking(ep, Message::new(tag, payload).as_bytes())
+}
+
+/// Like `send_typed_blocking`, for a `payload` too big for a `Message`: anything
+/// up to a whole channel message. Decode it with `Frame::read` as usual.
+pub fn send_large_blocking<T: Copy>(ep: u64, tag: u16, payload: &T) -> Result<(), SysError> {
+    const { assert!(HEADER_SIZE + size_of::<T>() <= MAX_MESSAGE_SIZE, "payload too large for a channel message") };
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    let len = frame_typed(tag, payload, &mut buf).ok_or(SysError::MessageTooLarge)?;
+    crate::sys_channel_send_blocking(ep, &buf[..len])
+}
+
+/// Receive one message and decode it as a `T` sent under `tag`. A message with a
+/// different tag or size is consumed and reported as `InvalidArgs`.
+pub fn recv_typed<T: Copy>(ep: u64, tag: u16) -> Result<T, SysError> {
+    let mut buf = [0u8; HEADER_SIZE + MAX_PAYLOAD];
+    let n = crate::sys_channel_recv(ep, &mut buf)? as usize;
+    decode(&buf[..n], tag)
+}
+
+/// Send `req` under `tag` with `sys_channel_call` and decode the reply, which
+/// must come back under the same tag.
+pub fn call_typed<Req: Copy, Resp: Copy>(ep: u64, tag: u16, req: &Req) -> Result<Resp, SysError> {
+    let mut buf = [0u8; HEADER_SIZE + MAX_PAYLOAD];
+    let n = crate::sys_channel_call(ep, Message::new(tag, req).as_bytes(), &mut buf)? as usize;
+    decode(&buf[..n], tag)
+}
+
+fn decode<T: Copy>(msg: &[u8], tag: u16) -> Result<T, SysError> {
+    Frame::parse(msg)
+        .filter(|f| f.tag == tag && f.trailer.is_empty())
+        .and_then(|f| f.read())
+        .ok_or(SysError::InvalidArgs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, Message, HEADER_SIZE};
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[repr(C)]
+    struct Req {
+        id: u64,
+        x: i32,
+    }
+
+    #[test]
+    fn round_trip() {
+        let req = Req { id: 7, x: -3 };
+        let msg = Message::new(5, &req);
+        assert_eq!(msg.as_bytes().len(), HEADER_SIZE + 16);
+        let frame = Frame::parse(msg.as_bytes()).unwrap();
+        assert_eq!(frame.tag, 5);
+        assert_eq!(frame.read::<Req>(), Some(req));
+        assert!(frame.trailer.is_empty());
+    }
+
+    #[test]
+    fn payload_starts_after_header() {
+        let msg = Message::new(1, &0x1122_3344u32);
+        assert_eq!(msg.as_bytes(), &[1, 0, 4, 0, 0, 0, 0x44, 0x33, 0x22, 0x11]);
+    }
+
+    #[test]
+    fn reply_endpoint_trailer() {
+        let mut bytes = [0u8; HEADER_SIZE + 4 + 8];
+        bytes[..HEADER_SIZE + 4].copy_from_slice(Message::new(2, &9u32).as_bytes());
+        bytes[HEADER_SIZE + 4..].copy_from_slice(&0xABCDu64.to_le_bytes());
+        let frame = Frame::parse(&bytes).unwrap();
+        assert_eq!(frame.read::<u32>(), Some(9));
+        assert_eq!(frame.reply_endpoint(), Some(0xABCD));
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        let msg = Message::new(3, &Req { id: 1, x: 2 });
+        let bytes = msg.as_bytes();
+        assert_eq!(Frame::parse(&bytes[..HEADER_SIZE - 1]), None);
+        assert_eq!(Frame::parse(&bytes[..bytes.len() - 1]), None);
+        // Claims a payload far beyond the message.
+        assert_eq!(Frame::parse(&[0, 0, 0xff, 0xff, 0xff, 0xff]), None);
+    }
+
+    #[test]
+    fn frame_raw_matches_message_layout() {
+        let mut buf = [0u8; 16];
+        let len = super::frame_raw(1, &0x1122_3344u32.to_le_bytes(), &mut buf).unwrap();
+        assert_eq!(&buf[..len], Message::new(1, &0x1122_3344u32).as_bytes());
+        assert_eq!(super::frame_raw(1, &[0; 11], &mut buf), None);
+    }
+
+    #[test]
+    fn frame_typed_carries_structs_past_max_payload() {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        #[repr(C)]
+        struct Big {
+            id: u64,
+            words: [u32; 64],
+        }
+        let big = Big { id: 3, words: core::array::from_fn(|i| i as u32) };
+        let mut buf = [0u8; HEADER_SIZE + 264];
+        let len = super::frame_typed(9, &big, &mut buf).unwrap();
+        assert_eq!(len, HEADER_SIZE + 264);
+        let frame = Frame::parse(&buf[..len]).unwrap();
+        assert_eq!((frame.tag, frame.read::<Big>()), (9, Some(big)));
+        assert_eq!(super::frame_typed(9, &big, &mut buf[..len - 1]), None);
+    }
+
+    #[test]
+    fn size_mismatch_does_not_decode() {
+        let msg = Message::new(4, &1u32);
+        let frame = Frame::parse(msg.as_bytes()).unwrap();
+        assert_eq!(frame.read::<u64>(), None);
+        assert_eq!(frame.read::<u16>(), None);
+        assert_eq!(frame.reply_endpoint(), None);
+    }
+}
@@ -1,12 +1,27 @@
 #![no_std]
 
+#[cfg(test)]
+extern crate std;
+
+pub mod animation;
 pub mod display;
+pub mod inet;
+pub mod input;
+pub mod ipc;
+pub mod net;
+pub mod serial;
 pub mod window;
 pub mod test_framework;
+pub mod ring;
+pub mod rng;
+mod raster;
 
 use core::arch::asm;
-use kernel_api_types::{SysCallNumber, SVC_ERR_NOT_FOUND, SVC_OK};
-use kernel_api_types::graphics::{DisplayInfo, GraphicsResult, Rect};
+use kernel_api_types::{AllocStats, DateTime, RecvInfo, SysCallNumber, SysError, MAX_MESSAGE_SIZE, NO_ENDPOINT};
+use kernel_api_types::graphics::{DisplayInfo, Rect};
+use kernel_api_types::input::{InputEvent, RecordedInput, INPUT_RECORD_START, INPUT_RECORD_STOP};
+use kernel_api_types::net::MacAddress;
+use kernel_api_types::task::{SpawnError, TaskInfo};
 
 pub fn syscall(inputs_and_ouputs: &mut [u64; 7]) {
     unsafe {
@@ -24,15 +39,14 @@ pub fn syscall(inputs_and_ouputs: &mut [u64; 7]) {
     }
 }
 
-pub fn sys_get_bounding_box(out_rect: &mut Rect) -> GraphicsResult {
+pub fn sys_get_bounding_box(out_rect: &mut Rect) -> Result<(), SysError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::GetBoundingBox as u64;
     args[1] = out_rect as *const Rect as u64;
 
     syscall(&mut args);
 
-    let ret = args[6];
-    GraphicsResult::from_u64(ret)
+    SysError::from_ret(args[6]).map(|_| ())
 }
 
 pub fn sys_get_display_info() -> DisplayInfo {
@@ -83,35 +97,275 @@ pub fn sys_yield() {
     syscall(&mut args);
 }
 
-pub fn sys_mmap(size: u64, flags: u64) -> *mut u8 {
+/// Block for at least `ms` milliseconds (1 ms resolution).
+pub fn sys_sleep(ms: u64) {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::Sleep as u64;
+    args[1] = ms;
+    syscall(&mut args);
+}
+
+pub fn sys_mmap(size: u64, flags: u64) -> Result<*mut u8, SysError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::Mmap as u64;
     args[1] = size;
     args[2] = flags;
     syscall(&mut args);
-    args[6] as *mut u8
+    SysError::from_ret(args[6]).map(|addr| addr as *mut u8)
 }
 
-pub fn sys_munmap(addr: *mut u8, size: u64) -> u64 {
+pub fn sys_munmap(addr: *mut u8, size: u64) -> Result<(), SysError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::Munmap as u64;
     args[1] = addr as u64;
     args[2] = size;
     syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Map every page of an `MMAP_LAZY` allocation now, so later accesses don't fault.
+pub fn sys_populate(addr: *mut u8, size: u64) -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::Populate as u64;
+    args[1] = addr as u64;
+    args[2] = size;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Fill `resident` with one byte per page of `[addr, addr + size)`: 1 if the page
+/// is mapped, 0 if not yet. `resident` needs at least `size.div_ceil(4096)` bytes.
+pub fn sys_mincore(addr: *mut u8, size: u64, resident: &mut [u8]) -> Result<(), SysError> {
+    if (resident.len() as u64) < size.div_ceil(4096) {
+        return Err(SysError::InvalidArgs);
+    }
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::Mincore as u64;
+    args[1] = addr as u64;
+    args[2] = size;
+    args[3] = resident.as_mut_ptr() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Return the calling task's ID.
+pub fn sys_get_task_id() -> u64 {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::GetTaskId as u64;
+    syscall(&mut args);
+    args[6]
+}
+
+/// Name the calling task in kernel logs and task listings. Names longer than
+/// `MAX_TASK_NAME_LEN` bytes are truncated.
+pub fn sys_set_task_name(name: &str) -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::SetTaskName as u64;
+    args[1] = name.as_ptr() as u64;
+    args[2] = name.len() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Describe every task in the system. Fills `buf` in task ID order and
+/// returns the part that was filled, plus the total number of tasks, which is
+/// larger when `buf` was too small to hold them all.
+pub fn sys_list_tasks(buf: &mut [TaskInfo]) -> Result<(&[TaskInfo], usize), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::ListTasks as u64;
+    args[1] = buf.as_mut_ptr() as u64;
+    args[2] = size_of_val(buf) as u64;
+    syscall(&mut args);
+    let total = SysError::from_ret(args[6])? as usize;
+    Ok((&buf[..total.min(buf.len())], total))
+}
+
+/// Set the calling task's priority, 0 (lowest) to `MAX_PRIORITY`; tasks start
+/// at `DEFAULT_PRIORITY`. Only system tasks may raise theirs; a user task gets
+/// `PermissionDenied`. A task waiting on a lower-priority peer over IPC lends
+/// it its priority for as long as the peer is serving it.
+pub fn sys_set_priority(priority: u64) -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::SetPriority as u64;
+    args[1] = priority;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Read the CPU's time stamp counter.
+pub fn sys_get_cycles() -> u64 {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::GetCycles as u64;
+    syscall(&mut args);
     args[6]
 }
 
-pub fn sys_spawn(elf_bytes: &[u8], child_arg: u64) -> u64 {
+/// The kernel's estimate of the TSC frequency in Hz, or 0 if unknown.
+///
+/// The estimate comes from one short interval at boot and can be well off,
+/// so for accurate timing measure the rate against a known interval yourself
+/// and use the raw `sys_get_cycles` values.
+pub fn sys_get_tsc_hz() -> u64 {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::GetTscHz as u64;
+    syscall(&mut args);
+    args[6]
+}
+
+/// Read the wall-clock date and time from the real-time clock.
+pub fn sys_get_wallclock() -> Result<DateTime, SysError> {
+    let mut now = DateTime::default();
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::GetWallClock as u64;
+    args[1] = &mut now as *mut DateTime as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| now)
+}
+
+/// Read the kernel heap's counters. Comparing two reads taken around some
+/// work shows whether it left kernel memory behind.
+pub fn sys_heap_stats() -> Result<AllocStats, SysError> {
+    let mut stats = AllocStats::default();
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::HeapStats as u64;
+    args[1] = &mut stats as *mut AllocStats as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| stats)
+}
+
+/// Fill `buf` with pseudo-random bytes from the kernel. Not for secrets.
+pub fn sys_random(buf: &mut [u8]) -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::Random as u64;
+    args[1] = buf.as_mut_ptr() as u64;
+    args[2] = buf.len() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Transmit one raw Ethernet frame (no CRC). Fails with `WouldBlock` if the
+/// NIC's transmit ring is full and `NotFound` if there is no NIC.
+pub fn sys_net_send(frame: &[u8]) -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::NetSend as u64;
+    args[1] = frame.as_ptr() as u64;
+    args[2] = frame.len() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Take the next received Ethernet frame into `buf`, returning its length.
+/// Fails with `WouldBlock` if nothing has arrived.
+pub fn sys_net_recv(buf: &mut [u8]) -> Result<usize, SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::NetRecv as u64;
+    args[1] = buf.as_mut_ptr() as u64;
+    args[2] = buf.len() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|n| n as usize)
+}
+
+pub fn sys_net_get_mac() -> Result<MacAddress, SysError> {
+    let mut mac = [0u8; 6];
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::NetGetMac as u64;
+    args[1] = mac.as_mut_ptr() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| mac)
+}
+
+/// Transmit up to `SERIAL_CHUNK_SIZE` raw bytes on the serial port.
+pub fn sys_serial_write(bytes: &[u8]) -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::SerialWrite as u64;
+    args[1] = bytes.as_ptr() as u64;
+    args[2] = bytes.len() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Take bytes the serial port has received into `buf`, returning how many.
+/// Fails with `WouldBlock` if nothing has arrived.
+pub fn sys_serial_read(buf: &mut [u8]) -> Result<usize, SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::SerialRead as u64;
+    args[1] = buf.as_mut_ptr() as u64;
+    args[2] = buf.len() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|n| n as usize)
+}
+
+/// Start recording keyboard and mouse input, discarding any previous
+/// recording. Fails with `NoSys` on a release kernel.
+pub fn sys_input_record_start() -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::InputRecord as u64;
+    args[1] = INPUT_RECORD_START;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Stop recording and copy the recorded events into `out`, returning how many.
+pub fn sys_input_record_stop(out: &mut [RecordedInput]) -> Result<usize, SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::InputRecord as u64;
+    args[1] = INPUT_RECORD_STOP;
+    args[2] = out.as_mut_ptr() as u64;
+    args[3] = out.len() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|n| n as usize)
+}
+
+/// Queue a keyboard or mouse event as if the device had sent it. Fails with
+/// `NoSys` on a release kernel.
+pub fn sys_inject_input(event: &InputEvent) -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::InjectInput as u64;
+    args[1] = event as *const InputEvent as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Spawn a task from an ELF image. Returns the new task's ID.
+pub fn sys_spawn(elf_bytes: &[u8], child_arg: u64) -> Result<u64, SpawnError> {
+    sys_spawn_with_flags(elf_bytes, child_arg, 0)
+}
+
+/// Like `sys_spawn`, with `Spawn` flags such as `task::SPAWN_SYSTEM`.
+pub fn sys_spawn_with_flags(elf_bytes: &[u8], child_arg: u64, flags: u64) -> Result<u64, SpawnError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::Spawn as u64;
     args[1] = elf_bytes.as_ptr() as u64;
     args[2] = elf_bytes.len() as u64;
     args[3] = child_arg;
+    args[4] = flags;
     syscall(&mut args);
-    args[6]
+    SysError::from_ret(args[6]).map_err(SpawnError::from)
+}
+
+/// Wait for a task to exit and return its exit code. A task that exits while
+/// nobody is waiting on it is reaped at once and reports `NotFound`.
+pub fn sys_waitpid(task_id: u64) -> Result<u64, SysError> {
+    let mut exit_code: u64 = 0;
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::Waitpid as u64;
+    args[1] = task_id;
+    args[2] = &mut exit_code as *mut u64 as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| exit_code)
+}
+
+/// Kill a task this task spawned. Its `sys_waitpid` reports `EXIT_KILLED`.
+pub fn sys_kill(task_id: u64) -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::Kill as u64;
+    args[1] = task_id;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
 }
 
-pub fn sys_channel_create(capacity: u64) -> (u64, u64) {
+/// Create a channel. Returns `(send_endpoint, recv_endpoint)`.
+pub fn sys_channel_create(capacity: u64) -> Result<(u64, u64), SysError> {
     let mut send_ep: u64 = 0;
     let mut recv_ep: u64 = 0;
     let mut args = [0u64; 7];
@@ -120,20 +374,38 @@ pub fn sys_channel_create(capacity: u64) -> (u64, u64) {
     args[2] = &mut recv_ep as *mut u64 as u64;
     args[3] = capacity;
     syscall(&mut args);
-    (send_ep, recv_ep)
+    SysError::from_ret(args[6]).map(|_| (send_ep, recv_ep))
 }
 
-pub fn sys_channel_send(endpoint_id: u64, data: &[u8]) -> u64 {
+pub fn sys_channel_send(endpoint_id: u64, data: &[u8]) -> Result<(), SysError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::ChannelSend as u64;
     args[1] = endpoint_id;
     args[2] = data.as_ptr() as u64;
     args[3] = data.len() as u64;
     syscall(&mut args);
-    args[6]
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Send `data`, waiting for room if the channel is full.
+///
+/// The kernel parks a sender on the channel's send waiters until a receiver
+/// frees a slot, but returns `WouldBlock` if that sleep is cut short; this
+/// retries until the message is actually queued.
+pub fn sys_channel_send_blocking(endpoint_id: u64, data: &[u8]) -> Result<(), SysError> {
+    retry_would_block(|| sys_channel_send(endpoint_id, data))
 }
 
-pub fn sys_channel_recv(endpoint_id: u64, buf: &mut [u8]) -> (u64, u64) {
+/// Receive one message into `buf`. Returns the number of bytes read; a
+/// message longer than `buf` is cut short.
+pub fn sys_channel_recv(endpoint_id: u64, buf: &mut [u8]) -> Result<u64, SysError> {
+    sys_channel_recv_flags(endpoint_id, buf, 0).map(|(bytes_read, _)| bytes_read)
+}
+
+/// Like `sys_channel_recv`, but returns both the bytes copied into `buf` and
+/// the full length of the message. They differ when the message didn't fit:
+/// it was truncated, and is gone unless `flags` has `RECV_KEEP_TRUNCATED`.
+pub fn sys_channel_recv_flags(endpoint_id: u64, buf: &mut [u8], flags: u64) -> Result<(u64, u64), SysError> {
     let mut bytes_read: u64 = 0;
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::ChannelRecv as u64;
@@ -141,24 +413,166 @@ pub fn sys_channel_recv(endpoint_id: u64, buf: &mut [u8]) -> (u64, u64) {
     args[2] = buf.as_mut_ptr() as u64;
     args[3] = buf.len() as u64;
     args[4] = &mut bytes_read as *mut u64 as u64;
+    args[5] = flags;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|len| (bytes_read, len))
+}
+
+/// Send `data` and hand `transfer_ep` to whoever receives it, e.g. a reply
+/// channel for a server. Once this returns `Ok`, the endpoint belongs to the
+/// receiver; the caller should not use it again.
+pub fn sys_channel_send_with_endpoint(endpoint_id: u64, data: &[u8], transfer_ep: u64) -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::ChannelSendWithEndpoint as u64;
+    args[1] = endpoint_id;
+    args[2] = data.as_ptr() as u64;
+    args[3] = data.len() as u64;
+    args[4] = transfer_ep;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Like `sys_channel_recv`, but also returns the endpoint sent along with
+/// the message by `sys_channel_send_with_endpoint`, which is now the
+/// caller's to use and close.
+pub fn sys_channel_recv_with_endpoint(endpoint_id: u64, buf: &mut [u8]) -> Result<(u64, Option<u64>), SysError> {
+    let mut info = RecvInfo::default();
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::ChannelRecvWithEndpoint as u64;
+    args[1] = endpoint_id;
+    args[2] = buf.as_mut_ptr() as u64;
+    args[3] = buf.len() as u64;
+    args[4] = &mut info as *mut RecvInfo as u64;
     syscall(&mut args);
-    (args[6], bytes_read)
+    SysError::from_ret(args[6])?;
+    let endpoint = (info.endpoint != NO_ENDPOINT).then_some(info.endpoint);
+    Ok((info.bytes_read, endpoint))
 }
 
-pub fn sys_channel_close(endpoint_id: u64) -> u64 {
+/// Number of messages waiting on a recv endpoint, without receiving any.
+/// Unlike `sys_channel_recv`, this never blocks.
+pub fn sys_channel_poll(endpoint_id: u64) -> Result<u64, SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::ChannelPoll as u64;
+    args[1] = endpoint_id;
+    syscall(&mut args);
+    SysError::from_ret(args[6])
+}
+
+/// Messages queued on the channel of `endpoint_id` (either end) and the
+/// capacity it was granted, as `(len, capacity)`. The difference is how many
+/// sends fit before one would block.
+pub fn sys_channel_stats(endpoint_id: u64) -> Result<(u64, u64), SysError> {
+    let mut len: u64 = 0;
+    let mut capacity: u64 = 0;
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::ChannelStats as u64;
+    args[1] = endpoint_id;
+    args[2] = &mut len as *mut u64 as u64;
+    args[3] = &mut capacity as *mut u64 as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| (len, capacity))
+}
+
+pub fn sys_channel_close(endpoint_id: u64) -> Result<(), SysError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::ChannelClose as u64;
     args[1] = endpoint_id;
     syscall(&mut args);
-    args[6]
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Create another endpoint for the same channel and direction as `endpoint_id`.
+/// The peer sees the channel close only once every copy has been closed.
+pub fn sys_channel_dup(endpoint_id: u64) -> Result<u64, SysError> {
+    let mut new_ep: u64 = 0;
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::ChannelDup as u64;
+    args[1] = endpoint_id;
+    args[2] = &mut new_ep as *mut u64 as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| new_ep)
 }
 
-pub fn sys_transfer_display(new_owner_task_id: u64) -> u64 {
+/// Create a broadcast channel with `capacity` messages queued per subscriber
+/// (0 for the default). Returns its broadcast endpoint: `sys_channel_send`
+/// on it reaches every subscriber, skipping any whose queue is full.
+pub fn sys_broadcast_create(capacity: u64) -> Result<u64, SysError> {
+    let mut ep: u64 = 0;
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::BroadcastCreate as u64;
+    args[1] = &mut ep as *mut u64 as u64;
+    args[2] = capacity;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ep)
+}
+
+/// Subscribe to the broadcast channel of `endpoint_id`. Returns a recv
+/// endpoint that gets every message broadcast from now on.
+pub fn sys_channel_subscribe(endpoint_id: u64) -> Result<u64, SysError> {
+    let mut recv_ep: u64 = 0;
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::ChannelSubscribe as u64;
+    args[1] = endpoint_id;
+    args[2] = &mut recv_ep as *mut u64 as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| recv_ep)
+}
+
+/// Send `request` to a server and wait for its one-message reply.
+///
+/// Framing: the server receives `request` followed by the 8-byte little-endian
+/// send endpoint of a fresh reply channel, and answers with a single message on
+/// that endpoint. `request` can therefore be at most `MAX_MESSAGE_SIZE - 8` bytes.
+///
+/// Returns the length of the reply written to `reply_buf`. The reply channel is
+/// closed before returning, on success or failure.
+pub fn sys_channel_call(server_ep: u64, request: &[u8], reply_buf: &mut [u8]) -> Result<u64, SysError> {
+    let len = request.len();
+    if len + 8 > MAX_MESSAGE_SIZE {
+        return Err(SysError::MessageTooLarge);
+    }
+
+    let (reply_send, reply_recv) = sys_channel_create(1)?;
+
+    let mut msg = [0u8; MAX_MESSAGE_SIZE];
+    msg[..len].copy_from_slice(request);
+    msg[len..len + 8].copy_from_slice(&reply_send.to_le_bytes());
+
+    let result = sys_channel_send_blocking(server_ep, &msg[..len + 8])
+        .and_then(|()| retry_would_block(|| sys_channel_recv(reply_recv, reply_buf)));
+
+    let _ = sys_channel_close(reply_send);
+    let _ = sys_channel_close(reply_recv);
+    result
+}
+
+/// Repeat `op` while it fails with `WouldBlock` (full/empty channel, or a
+/// blocking send/recv cut short), yielding between attempts.
+fn retry_would_block<T>(mut op: impl FnMut() -> Result<T, SysError>) -> Result<T, SysError> {
+    loop {
+        match op() {
+            Err(SysError::WouldBlock) => sys_yield(),
+            res => break res,
+        }
+    }
+}
+
+pub fn sys_transfer_display(new_owner_task_id: u64) -> Result<(), SysError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::TransferDisplay as u64;
     args[1] = new_owner_task_id;
     syscall(&mut args);
-    args[6]
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// Whether the calling task currently owns the display, e.g. to confirm a
+/// `sys_transfer_display` to it has happened before presenting.
+pub fn sys_is_display_owner() -> bool {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::IsDisplayOwner as u64;
+    syscall(&mut args);
+    args[6] == 1
 }
 
 /// Emit a debug value to the kernel serial console.
@@ -171,7 +585,48 @@ pub fn sys_debug_log(value: u64, tag: u64) {
     syscall(&mut args);
 }
 
-pub fn sys_get_module(name: &str, buf: *mut u8, buf_cap: u64) -> u64 {
+/// Log a string to the kernel serial console. Strings longer than
+/// `MAX_DEBUG_LOG_STR_LEN` bytes are cut off.
+pub fn sys_debug_log_str(s: &str) {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::DebugLogStr as u64;
+    args[1] = s.as_ptr() as u64;
+    args[2] = s.len() as u64;
+    syscall(&mut args);
+}
+
+/// Execute several syscalls in one kernel entry. Each op's raw return value is
+/// written to its `result` field (decode with `SysError::from_ret`); returns the
+/// number of ops executed.
+pub fn sys_batch(ops: &mut [kernel_api_types::SyscallOp]) -> Result<u64, SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::Batch as u64;
+    args[1] = ops.as_mut_ptr() as u64;
+    args[2] = ops.len() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6])
+}
+
+/// No-op syscall; returns immediately. Used to measure syscall entry/exit cost.
+pub fn sys_null() {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::Null as u64;
+    syscall(&mut args);
+}
+
+/// Turn kernel syscall tracing on or off. Returns whether it was on before,
+/// or `PermissionDenied` if the caller isn't a system task.
+pub fn sys_set_syscall_trace(enabled: bool) -> Result<bool, SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::SetSyscallTrace as u64;
+    args[1] = enabled as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|was_on| was_on != 0)
+}
+
+/// Load a boot module by name. With a null `buf` and zero `buf_cap`, returns
+/// the module's size; otherwise copies it into `buf` and returns the bytes written.
+pub fn sys_get_module(name: &str, buf: *mut u8, buf_cap: u64) -> Result<u64, SysError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::GetModule as u64;
     args[1] = name.as_ptr() as u64;
@@ -179,24 +634,39 @@ pub fn sys_get_module(name: &str, buf: *mut u8, buf_cap: u64) -> u64 {
     args[3] = buf as u64;
     args[4] = buf_cap;
     syscall(&mut args);
-    args[6]
+    SysError::from_ret(args[6])
+}
+
+/// Copy up to `buf.len()` bytes of a boot module, starting at `offset`, into
+/// `buf` and return how many were copied: 0 once `offset` reaches the end.
+/// Lets a large module be read through a small buffer.
+pub fn sys_get_module_chunk(name: &str, offset: u64, buf: &mut [u8]) -> Result<u64, SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::GetModuleChunk as u64;
+    args[1] = name.as_ptr() as u64;
+    args[2] = name.len() as u64;
+    args[3] = offset;
+    args[4] = buf.as_mut_ptr() as u64;
+    args[5] = buf.len() as u64;
+    syscall(&mut args);
+    SysError::from_ret(args[6])
 }
 
 /// Register a send endpoint under a human-readable service name.
-/// Returns `SVC_OK` on success or a `SVC_ERR_*` code on failure.
-pub fn sys_register_service(name: &[u8], send_ep: u64) -> u64 {
+/// Fails with `SysError::AlreadyExists` if the name is taken.
+pub fn sys_register_service(name: &[u8], send_ep: u64) -> Result<(), SysError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::RegisterService as u64;
     args[1] = name.as_ptr() as u64;
     args[2] = name.len() as u64;
     args[3] = send_ep;
     syscall(&mut args);
-    args[6]
+    SysError::from_ret(args[6]).map(|_| ())
 }
 
 /// Look up a service by name.
-/// Returns the send endpoint ID on success, or `SVC_ERR_NOT_FOUND` if not yet registered.
-pub fn sys_lookup_service(name: &[u8]) -> u64 {
+/// Returns the send endpoint ID, or `SysError::NotFound` if not yet registered.
+pub fn sys_lookup_service(name: &[u8]) -> Result<u64, SysError> {
     let mut ep_out: u64 = 0;
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::LookupService as u64;
@@ -204,42 +674,76 @@ pub fn sys_lookup_service(name: &[u8]) -> u64 {
     args[2] = name.len() as u64;
     args[3] = &mut ep_out as *mut u64 as u64;
     syscall(&mut args);
-    if args[6] == SVC_OK {
-        ep_out
-    } else {
-        SVC_ERR_NOT_FOUND
-    }
+    SysError::from_ret(args[6]).map(|_| ep_out)
 }
 
 /// Allocate a shared physical buffer of `size` bytes and map it into the caller's address space.
-/// Returns `(shared_buf_id, ptr)`. On failure `shared_buf_id` is `u64::MAX` and `ptr` is null.
-pub fn sys_create_shared_buf(size: u64) -> (u64, *mut u8) {
+/// Returns `(shared_buf_id, ptr)`.
+pub fn sys_create_shared_buf(size: u64) -> Result<(u64, *mut u8), SysError> {
     let mut vaddr_out: u64 = 0;
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::CreateSharedBuf as u64;
     args[1] = size;
     args[2] = &mut vaddr_out as *mut u64 as u64;
     syscall(&mut args);
-    (args[6], vaddr_out as *mut u8)
+    SysError::from_ret(args[6]).map(|id| (id, vaddr_out as *mut u8))
+}
+
+/// Allow task `task_id` to map a shared buffer the caller created.
+pub fn sys_grant_shared_buf(id: u64, task_id: u64) -> Result<(), SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::GrantSharedBuf as u64;
+    args[1] = id;
+    args[2] = task_id;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
 }
 
 /// Map a shared buffer (created by another task) into the caller's address space.
-/// Returns a writable pointer to the region, or null on failure.
-pub fn sys_map_shared_buf(id: u64) -> *mut u8 {
+/// The creator must have granted the caller access with `sys_grant_shared_buf`.
+/// `flags` is 0 for a writable mapping or `SHBUF_READONLY`.
+pub fn sys_map_shared_buf(id: u64, flags: u64) -> Result<*mut u8, SysError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::MapSharedBuf as u64;
     args[1] = id;
+    args[2] = flags;
     syscall(&mut args);
-    args[6] as *mut u8
+    SysError::from_ret(args[6]).map(|addr| addr as *mut u8)
 }
 
-/// Free the physical pages backing a shared buffer.
-/// Should be called by the creator after all other mappings have been removed.
-pub fn sys_destroy_shared_buf(id: u64) {
+/// Grow or shrink a shared buffer to `new_size` bytes, keeping its id and contents.
+/// Returns the caller's (possibly moved) pointer to the buffer. Other mappers whose
+/// mapping moved get the new pointer from `sys_map_shared_buf`; their old range
+/// faults if touched until then.
+pub fn sys_resize_shared_buf(id: u64, new_size: u64) -> Result<*mut u8, SysError> {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::ResizeSharedBuf as u64;
+    args[1] = id;
+    args[2] = new_size;
+    syscall(&mut args);
+    SysError::from_ret(args[6]).map(|addr| addr as *mut u8)
+}
+
+/// Free the physical pages backing a shared buffer. Only the creator may do this.
+/// Every mapping, including the creator's, is unmapped; the address range stays
+/// reserved (and faults if touched) until it is released with `sys_munmap`.
+pub fn sys_destroy_shared_buf(id: u64) -> Result<(), SysError> {
     let mut args = [0u64; 7];
     args[0] = SysCallNumber::DestroySharedBuf as u64;
     args[1] = id;
     syscall(&mut args);
+    SysError::from_ret(args[6]).map(|_| ())
+}
+
+/// End the calling task. `exit_code` is reported to a parent waiting on it.
+pub fn sys_exit(exit_code: u64) -> ! {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::Exit as u64;
+    args[1] = exit_code;
+    syscall(&mut args);
+    loop {
+        core::hint::spin_loop();
+    }
 }
 
 pub fn sys_shutdown(exit_code: u64) -> ! {
@@ -250,6 +754,16 @@ pub fn sys_shutdown(exit_code: u64) -> ! {
     loop {}
 }
 
+/// Reset the machine.
+pub fn sys_reboot() -> ! {
+    let mut args = [0u64; 7];
+    args[0] = SysCallNumber::Reboot as u64;
+    syscall(&mut args);
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
 pub fn default_panic(_info: &core::panic::PanicInfo) -> ! {
     loop {
         core::hint::spin_loop();
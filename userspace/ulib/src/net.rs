@@ -0,0 +1,61 @@
+//! Client for the "net" service, which owns the NIC and forwards raw Ethernet
+//! frames, or any service speaking the same protocol such as "loopback" (see
+//! `kernel_api_types::net::NetMessageType`).
+
+use kernel_api_types::net::{MacAddress, NetMessageType, MAX_FRAME_SIZE};
+use kernel_api_types::SysError;
+use crate::ipc::{self, Frame, HEADER_SIZE};
+
+pub struct NetClient {
+    send_endpoint: u64,
+}
+
+impl NetClient {
+    /// Look up the "net" service.
+    pub fn connect() -> Result<Self, SysError> {
+        Self::connect_to(b"net")
+    }
+
+    /// Look up a service speaking the "net" protocol by name, e.g. "loopback".
+    pub fn connect_to(service: &[u8]) -> Result<Self, SysError> {
+        Ok(NetClient { send_endpoint: crate::sys_lookup_service(service)? })
+    }
+
+    fn call(&self, tag: NetMessageType, payload: &[u8], reply: &mut [u8]) -> Result<usize, SysError> {
+        // Leave room for the reply endpoint `sys_channel_call` appends.
+        let mut request = [0u8; HEADER_SIZE + MAX_FRAME_SIZE];
+        let len = ipc::frame_raw(tag as u16, payload, &mut request).ok_or(SysError::MessageTooLarge)?;
+        let n = crate::sys_channel_call(self.send_endpoint, &request[..len], reply)? as usize;
+        let frame = Frame::parse(&reply[..n])
+            .filter(|f| f.tag == tag as u16 && f.trailer.is_empty())
+            .ok_or(SysError::InvalidArgs)?;
+        // Move the payload to the start of `reply` so callers see just the data.
+        let payload_len = frame.payload.len();
+        reply.copy_within(HEADER_SIZE..HEADER_SIZE + payload_len, 0);
+        Ok(payload_len)
+    }
+
+    /// Transmit a raw frame (no CRC).
+    pub fn send(&self, frame: &[u8]) -> Result<(), SysError> {
+        let mut reply = [0u8; HEADER_SIZE + 8];
+        let n = self.call(NetMessageType::Send, frame, &mut reply)?;
+        let code = i64::from_le_bytes(reply[..n].try_into().map_err(|_| SysError::InvalidArgs)?);
+        SysError::from_ret(code as u64).map(|_| ())
+    }
+
+    /// Copy the next received frame into `buf` and return its length; 0 if
+    /// none is queued. Frames longer than `buf` are truncated.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize, SysError> {
+        let mut reply = [0u8; HEADER_SIZE + MAX_FRAME_SIZE];
+        let n = self.call(NetMessageType::Recv, &[], &mut reply)?;
+        let len = n.min(buf.len());
+        buf[..len].copy_from_slice(&reply[..len]);
+        Ok(len)
+    }
+
+    pub fn mac(&self) -> Result<MacAddress, SysError> {
+        let mut reply = [0u8; HEADER_SIZE + 6];
+        let n = self.call(NetMessageType::GetMac, &[], &mut reply)?;
+        reply[..n].try_into().map_err(|_| SysError::InvalidArgs)
+    }
+}
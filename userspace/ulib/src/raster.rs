@@ -0,0 +1,179 @@
+//! Native rasterisation primitives shared by `Display` and `Window`.
+//!
+//! Both targets are a row-major `u32` buffer in the framebuffer's native pixel
+//! format, so the primitives write pixels directly instead of going through
+//! embedded-graphics' per-pixel `draw_iter`. Each function returns the clipped
+//! bounding rect of the pixels it touched so the caller can expand its dirty
+//! region with a single call.
+
+use kernel_api_types::window::{clip_blit, DirtyRect};
+
+/// Draw a line from `(x0, y0)` to `(x1, y1)` (both inclusive) using Bresenham's
+/// algorithm. Pixels outside `width × height` are skipped.
+pub(crate) fn draw_line(
+    buf: &mut [u32],
+    width: u32,
+    height: u32,
+    (x0, y0): (i32, i32),
+    (x1, y1): (i32, i32),
+    pixel: u32,
+) -> Option<DirtyRect> {
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+    let mut err = dx + dy;
+
+    let (mut x, mut y) = (x0, y0);
+    loop {
+        if x >= 0 && y >= 0 && x < width as i32 && y < height as i32 {
+            buf[y as usize * width as usize + x as usize] = pixel;
+        }
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+
+    clip(width, height, x0.min(x1), y0.min(y1), x0.max(x1) + 1, y0.max(y1) + 1)
+}
+
+/// Fill a circle of `radius` centred on `(cx, cy)`, one horizontal span per row.
+pub(crate) fn fill_circle(
+    buf: &mut [u32],
+    width: u32,
+    height: u32,
+    (cx, cy): (i32, i32),
+    radius: u32,
+    pixel: u32,
+) -> Option<DirtyRect> {
+    let r = radius as i32;
+    let dirty = clip(width, height, cx - r, cy - r, cx + r + 1, cy + r + 1)?;
+
+    let r2 = (r as i64) * (r as i64);
+    for y in dirty.y..dirty.y + dirty.h {
+        let dy = (y as i32 - cy) as i64;
+        // Widest |dx| on this row with dx² + dy² <= r²
+        let half = isqrt((r2 - dy * dy) as u64) as i32;
+        let x0 = (cx - half).max(0);
+        let x1 = (cx + half + 1).min(width as i32);
+        if x0 >= x1 {
+            continue;
+        }
+        let row = y as usize * width as usize;
+        buf[row + x0 as usize..row + x1 as usize].fill(pixel);
+    }
+
+    Some(dirty)
+}
+
+/// Copy a `w × h` rectangle whose top-left pixel is `(src_x, src_y)` in a
+/// source with `src_stride` pixels per row to `(dst_x, dst_y)`. Only the part
+/// that lands inside `width × height` is copied.
+///
+/// # Safety
+/// `src` must be readable for every pixel of the source rectangle that lands
+/// on the buffer.
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn blit_strided(
+    buf: &mut [u32],
+    width: u32,
+    height: u32,
+    src: *const u32,
+    src_stride: u32,
+    (src_x, src_y): (u32, u32),
+    (dst_x, dst_y): (i32, i32),
+    w: u32,
+    h: u32,
+) -> Option<DirtyRect> {
+    let clip = clip_blit(dst_x, dst_y, w, h, width, height)?;
+    let d = clip.dst;
+
+    let src_x = src_x as usize + clip.src_x as usize;
+    let src_y = src_y as usize + clip.src_y as usize;
+    for row in 0..d.h as usize {
+        let src_off = (src_y + row) * src_stride as usize + src_x;
+        let dst_off = (d.y as usize + row) * width as usize + d.x as usize;
+        let dst = &mut buf[dst_off..dst_off + d.w as usize];
+        unsafe {
+            core::ptr::copy_nonoverlapping(src.add(src_off), dst.as_mut_ptr(), dst.len());
+        }
+    }
+
+    Some(d)
+}
+
+/// Clip the half-open box `[x0, x1) × [y0, y1)` to the buffer bounds.
+fn clip(width: u32, height: u32, x0: i32, y0: i32, x1: i32, y1: i32) -> Option<DirtyRect> {
+    let x0 = x0.max(0) as u32;
+    let y0 = y0.max(0) as u32;
+    let x1 = (x1.max(0) as u32).min(width);
+    let y1 = (y1.max(0) as u32).min(height);
+    if x0 >= x1 || y0 >= y1 {
+        return None;
+    }
+    Some(DirtyRect { x: x0, y: y0, w: x1 - x0, h: y1 - y0 })
+}
+
+/// Integer square root (floor), via Newton iteration.
+fn isqrt(n: u64) -> u64 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = n;
+    let mut y = x.div_ceil(2);
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::blit_strided;
+    use kernel_api_types::window::DirtyRect;
+
+    /// A 10×10 source where each pixel holds `y * 10 + x`.
+    fn numbered_source() -> [u32; 100] {
+        core::array::from_fn(|i| i as u32)
+    }
+
+    #[test]
+    fn blit_strided_copies_sub_rect() {
+        let src = numbered_source();
+        let mut buf = [0u32; 5 * 5];
+        let touched = unsafe { blit_strided(&mut buf, 5, 5, src.as_ptr(), 10, (4, 6), (1, 2), 3, 3) };
+        assert_eq!(touched, Some(DirtyRect { x: 1, y: 2, w: 3, h: 3 }));
+        for y in 0..5 {
+            for x in 0..5 {
+                let expected = if (1..4).contains(&x) && (2..5).contains(&y) {
+                    (6 + y - 2) * 10 + (4 + x - 1)
+                } else {
+                    0
+                };
+                assert_eq!(buf[(y * 5 + x) as usize], expected, "pixel ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn blit_strided_clips_to_buffer() {
+        let src = numbered_source();
+        let mut buf = [0u32; 4 * 4];
+        // The sub-rect's first row and column fall off the top-left corner.
+        let touched = unsafe { blit_strided(&mut buf, 4, 4, src.as_ptr(), 10, (2, 3), (-1, -1), 3, 3) };
+        assert_eq!(touched, Some(DirtyRect { x: 0, y: 0, w: 2, h: 2 }));
+        assert_eq!(&buf[0..2], &[43, 44]);
+        assert_eq!(&buf[4..6], &[53, 54]);
+        assert_eq!(buf.iter().filter(|&&p| p != 0).count(), 4);
+    }
+}
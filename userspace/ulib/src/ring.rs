@@ -0,0 +1,63 @@
+//! Userspace side of the syscall submission/completion ring.
+//!
+//! Post operations with `submit`, ring the doorbell with `enter` (one syscall
+//! for everything queued), then drain results with `pop_completion`.
+
+use core::sync::atomic::Ordering;
+use kernel_api_types::MMAP_WRITE;
+use kernel_api_types::ring::{CompletionEntry, SubmissionEntry, SyscallRing, RING_ENTRIES};
+use kernel_api_types::SysCallNumber;
+
+pub struct Ring {
+    ring: &'static mut SyscallRing,
+}
+
+impl Ring {
+    /// Allocate a zeroed ring in fresh pages. Returns `None` if mmap fails.
+    pub fn new() -> Option<Self> {
+        let size = core::mem::size_of::<SyscallRing>() as u64;
+        let ptr = crate::sys_mmap(size, MMAP_WRITE).ok()? as *mut SyscallRing;
+        // sys_mmap hands out zeroed pages, which is a valid empty ring.
+        Some(Ring { ring: unsafe { &mut *ptr } })
+    }
+
+    /// Where the ring lives in this task's address space.
+    pub fn addr(&self) -> u64 {
+        &*self.ring as *const SyscallRing as u64
+    }
+
+    /// Queue a submission. Returns false if the submission queue is full.
+    pub fn submit(&mut self, sqe: SubmissionEntry) -> bool {
+        let tail = self.ring.sq_tail.load(Ordering::Relaxed);
+        let head = self.ring.sq_head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) as usize >= RING_ENTRIES {
+            return false;
+        }
+        self.ring.sq[tail as usize % RING_ENTRIES] = sqe;
+        self.ring.sq_tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Doorbell: have the kernel process every queued submission.
+    /// Returns the number of completions posted.
+    pub fn enter(&mut self) -> u64 {
+        let mut args = [0u64; 7];
+        args[0] = SysCallNumber::RingEnter as u64;
+        args[1] = self.ring as *mut SyscallRing as u64;
+        crate::syscall(&mut args);
+        args[6]
+    }
+
+    /// Take the next completion, if any. Decode its `result` with
+    /// `SysError::from_ret`.
+    pub fn pop_completion(&mut self) -> Option<CompletionEntry> {
+        let head = self.ring.cq_head.load(Ordering::Relaxed);
+        let tail = self.ring.cq_tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let cqe = self.ring.cq[head as usize % RING_ENTRIES];
+        self.ring.cq_head.store(head.wrapping_add(1), Ordering::Release);
+        Some(cqe)
+    }
+}
@@ -0,0 +1,19 @@
+//! Random numbers for demos and tests, from the kernel's `Random` syscall.
+
+/// A pseudo-random `u64`, or 0 if the kernel refused (it only does so for a
+/// bad buffer, which this isn't).
+pub fn next_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    let _ = crate::sys_random(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// A pseudo-random number in `0..bound`, or 0 if `bound` is 0. Slightly
+/// favours small values when `bound` doesn't divide 2^64, which no demo
+/// will notice.
+pub fn below(bound: u64) -> u64 {
+    if bound == 0 {
+        return 0;
+    }
+    next_u64() % bound
+}
@@ -0,0 +1,50 @@
+//! Client for the "serial" service, which owns COM1 (see
+//! `kernel_api_types::serial::SerialMessageType`).
+
+use kernel_api_types::serial::{SerialMessageType, SERIAL_CHUNK_SIZE};
+use kernel_api_types::SysError;
+use crate::ipc::{self, Frame, HEADER_SIZE};
+
+pub struct SerialClient {
+    send_endpoint: u64,
+}
+
+impl SerialClient {
+    /// Look up the "serial" service.
+    pub fn connect() -> Result<Self, SysError> {
+        Ok(SerialClient { send_endpoint: crate::sys_lookup_service(b"serial")? })
+    }
+
+    fn call(&self, tag: SerialMessageType, payload: &[u8], reply: &mut [u8]) -> Result<usize, SysError> {
+        // Leave room for the reply endpoint `sys_channel_call` appends.
+        let mut request = [0u8; HEADER_SIZE + SERIAL_CHUNK_SIZE];
+        let len = ipc::frame_raw(tag as u16, payload, &mut request).ok_or(SysError::MessageTooLarge)?;
+        let n = crate::sys_channel_call(self.send_endpoint, &request[..len], reply)? as usize;
+        let frame = Frame::parse(&reply[..n])
+            .filter(|f| f.tag == tag as u16 && f.trailer.is_empty())
+            .ok_or(SysError::InvalidArgs)?;
+        let payload_len = frame.payload.len();
+        reply.copy_within(HEADER_SIZE..HEADER_SIZE + payload_len, 0);
+        Ok(payload_len)
+    }
+
+    /// Transmit `bytes` as-is, in as many requests as it takes.
+    pub fn write(&self, bytes: &[u8]) -> Result<(), SysError> {
+        for chunk in bytes.chunks(SERIAL_CHUNK_SIZE) {
+            let mut reply = [0u8; HEADER_SIZE + 8];
+            let n = self.call(SerialMessageType::Write, chunk, &mut reply)?;
+            let code = i64::from_le_bytes(reply[..n].try_into().map_err(|_| SysError::InvalidArgs)?);
+            SysError::from_ret(code as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Copy bytes received so far into `buf` and return how many; 0 if none.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, SysError> {
+        let mut reply = [0u8; HEADER_SIZE + SERIAL_CHUNK_SIZE];
+        let n = self.call(SerialMessageType::Read, &[], &mut reply)?;
+        let len = n.min(buf.len());
+        buf[..len].copy_from_slice(&reply[..len]);
+        Ok(len)
+    }
+}
@@ -1,6 +1,8 @@
 /// Minimal sequential test runner for `no_std` userspace integration tests.
 ///
-/// Each test is a `fn() -> bool` that returns `true` on pass, `false` on fail.
+/// Each test is a `fn() -> TestResult`, run with `run_named` so the log says
+/// which test it was and, on failure, which check didn't hold. Plain
+/// `fn() -> bool` tests can still go through `run`.
 /// After all tests run, call `finish()` to exit QEMU with the appropriate code:
 ///   0x10 → all passed (QEMU exit 33)
 ///   0x11 → any failed  (QEMU exit 35)
@@ -10,9 +12,21 @@ pub struct TestRunner {
     index: u32,
 }
 
+/// Outcome of one test, as on the kernel side. The reason names the check
+/// that failed; userspace has no allocator, so it is a fixed string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestResult {
+    Ok,
+    Failed(&'static str),
+}
+
 const PASS_TAG: u64 = 0x5041_5353; // "PASS"
 const FAIL_TAG: u64 = 0x4641_494C; // "FAIL"
 
+/// Longest result line `run_named` logs; the kernel logs at most
+/// `MAX_DEBUG_LOG_STR_LEN` bytes of it anyway.
+const LINE_LEN: usize = kernel_api_types::MAX_DEBUG_LOG_STR_LEN;
+
 impl TestRunner {
     pub const fn new() -> Self {
         TestRunner { passed: 0, failed: 0, index: 0 }
@@ -23,14 +37,36 @@ impl TestRunner {
     /// Serial output:  `DBG[<test_index>]: 0x50415353`  (PASS)
     ///              or `DBG[<test_index>]: 0x4641494c`  (FAIL)
     pub fn run(&mut self, f: fn() -> bool) {
+        let result = if f() { TestResult::Ok } else { TestResult::Failed("returned false") };
+        self.record(result);
+    }
+
+    /// Run a single test function, log the result via `sys_debug_log` as
+    /// `run` does, then log its name and outcome via `sys_debug_log_str`.
+    ///
+    /// Serial output:  `DBG: PASS <name>`
+    ///              or `DBG: FAIL <name>: <reason>`
+    pub fn run_named(&mut self, name: &str, f: fn() -> TestResult) {
+        let result = f();
+        self.record(result);
+        match result {
+            TestResult::Ok => log_line(&["PASS ", name]),
+            TestResult::Failed(reason) => log_line(&["FAIL ", name, ": ", reason]),
+        }
+    }
+
+    fn record(&mut self, result: TestResult) {
         let idx = self.index;
         self.index += 1;
-        if f() {
-            self.passed += 1;
-            crate::sys_debug_log(PASS_TAG, idx as u64);
-        } else {
-            self.failed += 1;
-            crate::sys_debug_log(FAIL_TAG, idx as u64);
+        match result {
+            TestResult::Ok => {
+                self.passed += 1;
+                crate::sys_debug_log(PASS_TAG, idx as u64);
+            }
+            TestResult::Failed(_) => {
+                self.failed += 1;
+                crate::sys_debug_log(FAIL_TAG, idx as u64);
+            }
         }
     }
 
@@ -43,3 +79,20 @@ impl TestRunner {
         }
     }
 }
+
+/// Log `parts` as one line, cut to `LINE_LEN` bytes.
+fn log_line(parts: &[&str]) {
+    let mut buf = [0u8; LINE_LEN];
+    let mut len = 0;
+    for part in parts {
+        let n = part.len().min(LINE_LEN - len);
+        buf[len..len + n].copy_from_slice(&part.as_bytes()[..n]);
+        len += n;
+    }
+    // The cut may land inside a character; drop the partial one.
+    let line = match core::str::from_utf8(&buf[..len]) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&buf[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    crate::sys_debug_log_str(line);
+}
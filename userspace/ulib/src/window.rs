@@ -6,7 +6,7 @@
 
 use core::convert::Infallible;
 use embedded_graphics::draw_target::DrawTarget;
-use embedded_graphics::geometry::Size;
+use embedded_graphics::geometry::{Point, Size};
 use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
 use embedded_graphics::prelude::OriginDimensions;
 use embedded_graphics::Pixel;
@@ -14,8 +14,23 @@ use kernel_api_types::graphics::DisplayInfo;
 use kernel_api_types::window::{
     CreateWindowRequest, CreateWindowResponse, UpdateWindowRequest, WindowMessageType,
     WindowResult, WindowId, RaiseWindowRequest, LowerWindowRequest, MoveWindowRequest,
+    GetWindowBoundsRequest, GetWindowBoundsResponse, WindowBounds, CloseWindowRequest,
+    SetStepModeRequest, StepRequest, StepResponse, SetAlwaysOnTopRequest, WindowAtRequest,
+    WindowAtResponse, SetDisplayModeRequest, SetDisplayModeResponse, DisplayChangedEvent,
+    DisplayEventType, DISPLAY_EVENTS_SERVICE, SetWindowEventsRequest, ThrottleEvent, WindowEventType,
+    UPDATE_WANTS_ACK, SizeLimits, ResizeWindowRequest, ResizeWindowResponse, CursorImage, SetCursorRequest, MAX_CURSOR_SIZE,
 };
+use kernel_api_types::SysError;
 pub use kernel_api_types::window::DirtyRect;
+use crate::animation::{self, Easing};
+use crate::ipc::{self, Message};
+use crate::raster;
+
+/// Time between the moves `Window::animate_move` sends, roughly 60 per second.
+pub const ANIMATION_FRAME_MS: u64 = 16;
+
+/// Window events that can queue up before the server drops new ones.
+const EVENTS_CAPACITY: u64 = 4;
 
 /// A client window backed by shared physical memory.
 pub struct Window {
@@ -33,6 +48,8 @@ pub struct Window {
     height: u32,
     info: DisplayInfo,
     dirty: Option<DirtyRect>,
+    /// Receive side of the channel given to the server by `enable_events`.
+    events_endpoint: Option<u64>,
 }
 
 impl Window {
@@ -51,68 +68,58 @@ impl Window {
         x: i32,
         y: i32,
     ) -> Option<Self> {
-        let (our_send, our_recv) = crate::sys_channel_create(1);
-
-        const MSG_SIZE: usize = 1 + core::mem::size_of::<CreateWindowRequest>() + 8;
-        let mut msg = [0u8; MSG_SIZE];
-        msg[0] = WindowMessageType::CreateWindow as u8;
-
-        let req = CreateWindowRequest { width, height, x, y };
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                &req as *const CreateWindowRequest as *const u8,
-                msg.as_mut_ptr().add(1),
-                core::mem::size_of::<CreateWindowRequest>(),
-            );
-        }
-
-        let ep_offset = 1 + core::mem::size_of::<CreateWindowRequest>();
-        msg[ep_offset..ep_offset + 8].copy_from_slice(&our_send.to_le_bytes());
-
-        let result = crate::sys_channel_send(display_server_send_ep, &msg);
-        if result != kernel_api_types::IPC_OK {
-            crate::sys_channel_close(our_send);
-            crate::sys_channel_close(our_recv);
-            return None;
-        }
-
-        let mut response_buf = [0u8; core::mem::size_of::<CreateWindowResponse>()];
-        let (recv_result, bytes_read) = loop {
-            let (res, len) = crate::sys_channel_recv(our_recv, &mut response_buf);
-            if res == kernel_api_types::IPC_ERR_CHANNEL_FULL {
-                crate::sys_yield();
-                continue;
-            }
-            break (res, len);
-        };
-
-        crate::sys_channel_close(our_send);
-        crate::sys_channel_close(our_recv);
+        Self::try_new(display_server_send_ep, width, height, x, y).ok()
+    }
 
-        if recv_result != kernel_api_types::IPC_OK
-            || bytes_read != core::mem::size_of::<CreateWindowResponse>() as u64
-        {
-            return None;
-        }
+    /// Like `new`, but says why creation failed, e.g. `ErrorTooManyWindows`.
+    /// A request that never got a reply, or a buffer that couldn't be
+    /// mapped, reports `ErrorInvalidMessage` or `ErrorOutOfMemory`.
+    pub fn try_new(
+        display_server_send_ep: u64,
+        width: u32,
+        height: u32,
+        x: i32,
+        y: i32,
+    ) -> Result<Self, WindowResult> {
+        Self::try_new_with_limits(display_server_send_ep, width, height, x, y, SizeLimits::NONE)
+    }
 
-        let response: CreateWindowResponse = unsafe {
-            core::ptr::read(response_buf.as_ptr() as *const CreateWindowResponse)
-        };
+    /// Like `try_new`, but the window's size always stays within `limits`.
+    /// The requested size is clamped into them; `width`/`height` on the
+    /// result is what was granted.
+    pub fn try_new_with_limits(
+        display_server_send_ep: u64,
+        width: u32,
+        height: u32,
+        x: i32,
+        y: i32,
+        limits: SizeLimits,
+    ) -> Result<Self, WindowResult> {
+        let req = CreateWindowRequest { width, height, x, y, client_task_id: crate::sys_get_task_id(), limits };
+        let response: CreateWindowResponse =
+            ipc::call_typed(display_server_send_ep, WindowMessageType::CreateWindow as u16, &req)
+                .map_err(|_| WindowResult::ErrorInvalidMessage)?;
 
         if response.result != WindowResult::Ok {
-            return None;
+            return Err(response.result);
         }
 
         // Map the shared buffer the server created — zero-copy backing store.
+        // If that fails, close the window so the server frees it.
+        let (width, height) = (response.width, response.height);
         let buf_size = (width as u64) * (height as u64) * 4;
-        let buffer = crate::sys_map_shared_buf(response.shared_buf_id) as *mut u32;
-        if buffer.is_null() {
-            return None;
-        }
+        let buffer = match crate::sys_map_shared_buf(response.shared_buf_id, 0) {
+            Ok(ptr) => ptr as *mut u32,
+            Err(_) => {
+                let req = CloseWindowRequest { window_id: response.window_id };
+                let _ = ipc::send_typed_blocking(display_server_send_ep, WindowMessageType::CloseWindow as u16, &req);
+                return Err(WindowResult::ErrorOutOfMemory);
+            }
+        };
 
         let info = crate::sys_get_display_info();
 
-        Some(Window {
+        Ok(Window {
             window_id: response.window_id,
             send_endpoint: display_server_send_ep,
             buffer,
@@ -122,80 +129,276 @@ impl Window {
             height,
             info,
             dirty: None,
+            events_endpoint: None,
         })
     }
 
+    /// Ask the display server for a new size, which it clamps into the
+    /// window's limits, and return the size granted. The pixels are not
+    /// rearranged for the new width, so the client redraws afterwards; any
+    /// unpresented damage is dropped.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(u32, u32), WindowResult> {
+        let req = ResizeWindowRequest { window_id: self.window_id, width, height };
+        let response: ResizeWindowResponse =
+            ipc::call_typed(self.send_endpoint, WindowMessageType::ResizeWindow as u16, &req)
+                .map_err(|_| WindowResult::ErrorInvalidMessage)?;
+        if response.result != WindowResult::Ok {
+            return Err(response.result);
+        }
+        // The resize may have moved our mapping; mapping again finds it.
+        let buffer = crate::sys_map_shared_buf(self.shared_buf_id, 0).map_err(|_| WindowResult::ErrorOutOfMemory)?;
+        self.buffer = buffer as *mut u32;
+        self.width = response.width;
+        self.height = response.height;
+        self.buf_size = (self.width as u64) * (self.height as u64) * 4;
+        self.dirty = None;
+        Ok((self.width, self.height))
+    }
+
+    /// The ID the display server knows this window by.
+    pub fn id(&self) -> WindowId {
+        self.window_id
+    }
+
+    /// Keep this window above all normal windows, e.g. for a tooltip or
+    /// menu, or return it to them (fire-and-forget). `raise` and `lower`
+    /// then only move it among windows of the same kind.
+    pub fn set_always_on_top(&self, enabled: bool) {
+        let req = SetAlwaysOnTopRequest { window_id: self.window_id, enabled: enabled as u64 };
+        let _ = ipc::send_typed_blocking(self.send_endpoint, WindowMessageType::SetAlwaysOnTop as u16, &req);
+    }
+
+    /// Show `cursor` as the mouse cursor until this window closes or sets
+    /// another (fire-and-forget). The server draws the default arrow instead
+    /// if `cursor` isn't valid (see `CursorImage::is_valid`).
+    pub fn set_cursor(&self, cursor: &CursorImage) {
+        // The two bitmaps make this too big for an `ipc::Message`.
+        let req = SetCursorRequest { window_id: self.window_id, cursor: *cursor };
+        let _ = ipc::send_large_blocking(self.send_endpoint, WindowMessageType::SetCursor as u16, &req);
+    }
+
+    /// Go back to the default arrow cursor (fire-and-forget).
+    pub fn reset_cursor(&self) {
+        let cursor = CursorImage { width: 0, height: 0, hot_x: 0, hot_y: 0, mask: [0; MAX_CURSOR_SIZE], image: [0; MAX_CURSOR_SIZE] };
+        self.set_cursor(&cursor);
+    }
+
     /// Raise this window to the top of the z-order (fire-and-forget).
     pub fn raise(&self) {
-        const MSG_SIZE: usize = 1 + core::mem::size_of::<RaiseWindowRequest>();
-        let mut buf = [0u8; MSG_SIZE];
-        buf[0] = WindowMessageType::RaiseWindow as u8;
         let req = RaiseWindowRequest { window_id: self.window_id };
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                &req as *const RaiseWindowRequest as *const u8,
-                buf.as_mut_ptr().add(1),
-                core::mem::size_of::<RaiseWindowRequest>(),
-            );
-        }
-        crate::sys_channel_send(self.send_endpoint, &buf);
+        let _ = ipc::send_typed_blocking(self.send_endpoint, WindowMessageType::RaiseWindow as u16, &req);
     }
 
     /// Lower this window to the bottom of the z-order (fire-and-forget).
     pub fn lower(&self) {
-        const MSG_SIZE: usize = 1 + core::mem::size_of::<LowerWindowRequest>();
-        let mut buf = [0u8; MSG_SIZE];
-        buf[0] = WindowMessageType::LowerWindow as u8;
         let req = LowerWindowRequest { window_id: self.window_id };
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                &req as *const LowerWindowRequest as *const u8,
-                buf.as_mut_ptr().add(1),
-                core::mem::size_of::<LowerWindowRequest>(),
-            );
-        }
-        crate::sys_channel_send(self.send_endpoint, &buf);
+        let _ = ipc::send_typed_blocking(self.send_endpoint, WindowMessageType::LowerWindow as u16, &req);
     }
 
     /// Move this window to `(x, y)` (fire-and-forget).
     pub fn move_to(&self, x: i32, y: i32) {
-        const MSG_SIZE: usize = 1 + core::mem::size_of::<MoveWindowRequest>();
-        let mut buf = [0u8; MSG_SIZE];
-        buf[0] = WindowMessageType::MoveWindow as u8;
         let req = MoveWindowRequest { window_id: self.window_id, x, y };
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                &req as *const MoveWindowRequest as *const u8,
-                buf.as_mut_ptr().add(1),
-                core::mem::size_of::<MoveWindowRequest>(),
-            );
+        let _ = ipc::send_typed_blocking(self.send_endpoint, WindowMessageType::MoveWindow as u16, &req);
+    }
+
+    /// Slide this window from `from` to `to` over `duration_ms`, following
+    /// `easing`, by sending a `move_to` about every `ANIMATION_FRAME_MS`.
+    /// Blocks until the window has arrived; it always ends exactly at `to`.
+    ///
+    /// Returns the number of moves sent (steps that wouldn't change the
+    /// position are skipped).
+    pub fn animate_move(&self, from: Point, to: Point, duration_ms: u64, easing: Easing) -> u32 {
+        let tsc_hz = crate::sys_get_tsc_hz().max(1);
+        let start = crate::sys_get_cycles();
+        let mut last = None;
+        let mut moves = 0;
+        loop {
+            let elapsed_ms = (crate::sys_get_cycles() - start) * 1000 / tsc_hz;
+            let eased = easing.apply(animation::progress(elapsed_ms, duration_ms));
+            let (x, y) = animation::lerp((from.x, from.y), (to.x, to.y), eased);
+            if last != Some((x, y)) {
+                self.move_to(x, y);
+                last = Some((x, y));
+                moves += 1;
+            }
+            if elapsed_ms >= duration_ms {
+                return moves;
+            }
+            crate::sys_sleep(ANIMATION_FRAME_MS.min(duration_ms - elapsed_ms));
+        }
+    }
+
+    /// Ask the display server where this window currently is on screen.
+    ///
+    /// The server may move a window without the client asking (e.g. when the
+    /// user drags it), so this is the only reliable source of its position.
+    pub fn bounding_box(&self) -> Option<WindowBounds> {
+        let req = GetWindowBoundsRequest { window_id: self.window_id };
+        let response: GetWindowBoundsResponse =
+            ipc::call_typed(self.send_endpoint, WindowMessageType::GetWindowBounds as u16, &req).ok()?;
+        response.result.is_ok().then_some(response.bounds)
+    }
+
+    /// Have the display server send this window's events, such as throttle
+    /// hints, to a channel of our own; read them with `poll_throttle`.
+    /// Calling it again replaces the channel.
+    pub fn enable_events(&mut self) -> Result<(), SysError> {
+        let (send_ep, recv_ep) = crate::sys_channel_create(EVENTS_CAPACITY)?;
+        let req = SetWindowEventsRequest { window_id: self.window_id };
+        let msg = Message::new(WindowMessageType::SetWindowEvents as u16, &req);
+        // The send endpoint travels after the request, like a reply endpoint.
+        let mut buf = [0u8; ipc::HEADER_SIZE + size_of::<SetWindowEventsRequest>() + 8];
+        let (request, trailer) = buf.split_at_mut(msg.as_bytes().len());
+        request.copy_from_slice(msg.as_bytes());
+        trailer.copy_from_slice(&send_ep.to_le_bytes());
+        if let Err(e) = crate::sys_channel_send_blocking(self.send_endpoint, &buf) {
+            let _ = crate::sys_channel_close(send_ep);
+            let _ = crate::sys_channel_close(recv_ep);
+            return Err(e);
+        }
+        if let Some(old) = self.events_endpoint.replace(recv_ep) {
+            let _ = crate::sys_channel_close(old);
+        }
+        Ok(())
+    }
+
+    /// The next throttle hint already queued, if any. A hint means the
+    /// server merged several of our presents into one frame while it was
+    /// behind; present less often, e.g. by skipping frames or sleeping longer.
+    pub fn poll_throttle(&self) -> Option<ThrottleEvent> {
+        let ep = self.events_endpoint?;
+        match crate::sys_channel_poll(ep) {
+            Ok(n) if n > 0 => ipc::recv_typed(ep, WindowEventType::Throttle as u16).ok(),
+            _ => None,
         }
-        crate::sys_channel_send(self.send_endpoint, &buf);
     }
 
     /// Notify the display server of the dirty region — no pixel data is sent.
     /// Pixels were already written directly into the shared buffer.
     pub fn present(&mut self) {
-        if let Some(dirty) = self.dirty.take() {
-            let header = UpdateWindowRequest {
-                window_id: self.window_id,
-                dirty_x: dirty.x,
-                dirty_y: dirty.y,
-                dirty_width: dirty.w,
-                dirty_height: dirty.h,
-            };
-            const MSG_SIZE: usize = 1 + core::mem::size_of::<UpdateWindowRequest>();
-            let mut msg = [0u8; MSG_SIZE];
-            msg[0] = WindowMessageType::UpdateWindow as u8;
-            unsafe {
-                core::ptr::copy_nonoverlapping(
-                    &header as *const UpdateWindowRequest as *const u8,
-                    msg.as_mut_ptr().add(1),
-                    core::mem::size_of::<UpdateWindowRequest>(),
-                );
-            }
-            crate::sys_channel_send(self.send_endpoint, &msg);
+        if let Some(msg) = self.take_update_message() {
+            let _ = crate::sys_channel_send(self.send_endpoint, msg.as_bytes());
+        }
+    }
+
+    /// Like `present`, but wait until the display server has composited the
+    /// update. A client that draws a frame and then calls this is paced to
+    /// the compositor, and can't flood its request channel. Returns at once
+    /// if nothing is dirty.
+    pub fn present_sync(&mut self) -> Result<(), SysError> {
+        let Some(dirty) = self.dirty.take() else { return Ok(()) };
+        let msg = self.update_message(dirty, UPDATE_WANTS_ACK);
+        let mut ack = [0u8; 1];
+        crate::sys_channel_call(self.send_endpoint, msg.as_bytes(), &mut ack).map(|_| ())
+    }
+
+    /// Present only the part of the pending damage inside `region`, leaving
+    /// the rest for a later `present`. Lets a client flush one changed line
+    /// without resending the whole dirty span.
+    ///
+    /// Damage is tracked as one bounding box, so what stays dirty is the
+    /// bounding box of the damage outside `region`. It only shrinks when
+    /// `region` spans the damage's full width or height from one edge.
+    pub fn present_region(&mut self, region: DirtyRect) {
+        let Some(dirty) = self.dirty else { return };
+        let Some(flushed) = dirty.intersect(&region) else { return };
+        self.dirty = remaining_damage(dirty, flushed);
+        let msg = self.update_message(flushed, 0);
+        let _ = crate::sys_channel_send(self.send_endpoint, msg.as_bytes());
+    }
+
+    /// The damage not yet sent to the display server, in window coordinates.
+    pub fn dirty(&self) -> Option<DirtyRect> {
+        self.dirty
+    }
+
+    /// Present any pending damage and close the window.
+    ///
+    /// The update and the close are sent in one `sys_batch`, so they reach the
+    /// server back to back. Our own mapping of the pixels is removed first; the
+    /// server frees the pages when it handles the close.
+    pub fn close(mut self) {
+        use kernel_api_types::{SysCallNumber, SyscallOp};
+
+        let update = self.take_update_message();
+        let _ = crate::sys_munmap(self.buffer as *mut u8, self.buf_size);
+        if let Some(ep) = self.events_endpoint.take() {
+            let _ = crate::sys_channel_close(ep);
         }
+
+        let req = CloseWindowRequest { window_id: self.window_id };
+        let close = Message::new(WindowMessageType::CloseWindow as u16, &req);
+        let close = close.as_bytes();
+
+        let send = |msg: &[u8]| SyscallOp::new(
+            SysCallNumber::ChannelSend,
+            [self.send_endpoint, msg.as_ptr() as u64, msg.len() as u64, 0, 0, 0],
+        );
+        let (mut ops, n) = match &update {
+            Some(msg) => ([send(msg.as_bytes()), send(close)], 2),
+            None => ([send(close), send(close)], 1),
+        };
+        let _ = crate::sys_batch(&mut ops[..n]);
+    }
+
+    /// Encode the pending dirty rect as an `UpdateWindow` message, clearing it.
+    fn take_update_message(&mut self) -> Option<Message> {
+        let dirty = self.dirty.take()?;
+        Some(self.update_message(dirty, 0))
+    }
+
+    /// Encode an `UpdateWindow` message for `rect`, which must lie inside the
+    /// window: the server copies it out of the shared buffer at the window's
+    /// full width.
+    fn update_message(&self, rect: DirtyRect, flags: u32) -> Message {
+        let req = UpdateWindowRequest {
+            window_id: self.window_id,
+            dirty_x: rect.x,
+            dirty_y: rect.y,
+            dirty_width: rect.w,
+            dirty_height: rect.h,
+            flags,
+        };
+        Message::new(WindowMessageType::UpdateWindow as u16, &req)
+    }
+
+    /// Draw a 1-pixel line from `p0` to `p1` (inclusive) directly into the shared buffer.
+    pub fn draw_line(&mut self, p0: Point, p1: Point, color: Rgb888) {
+        let pixel = self.info.build_pixel(color.r(), color.g(), color.b());
+        let (width, height) = (self.width, self.height);
+        let touched = raster::draw_line(
+            self.pixels_mut(), width, height, (p0.x, p0.y), (p1.x, p1.y), pixel,
+        );
+        if let Some(d) = touched {
+            self.expand_dirty(d.x, d.y, d.w, d.h);
+        }
+    }
+
+    /// Fill a circle of `radius` pixels around `center` directly into the shared buffer.
+    pub fn fill_circle(&mut self, center: Point, radius: u32, color: Rgb888) {
+        let pixel = self.info.build_pixel(color.r(), color.g(), color.b());
+        let (width, height) = (self.width, self.height);
+        let touched = raster::fill_circle(
+            self.pixels_mut(), width, height, (center.x, center.y), radius, pixel,
+        );
+        if let Some(d) = touched {
+            self.expand_dirty(d.x, d.y, d.w, d.h);
+        }
+    }
+
+    /// Read back a pixel (native framebuffer format) from the shared buffer.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<u32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let offset = (y as usize) * (self.width as usize) + (x as usize);
+        Some(unsafe { *self.buffer.add(offset) })
+    }
+
+    fn pixels_mut(&mut self) -> &mut [u32] {
+        let len = (self.width as usize) * (self.height as usize);
+        // Safety: `buffer` maps `width * height` pixels for the window's lifetime.
+        unsafe { core::slice::from_raw_parts_mut(self.buffer, len) }
     }
 
     fn expand_dirty(&mut self, x: u32, y: u32, w: u32, h: u32) {
@@ -208,6 +411,30 @@ impl Window {
     }
 }
 
+/// Bounding box of `dirty` minus `flushed`, which lies inside it. `None` if
+/// nothing is left.
+fn remaining_damage(dirty: DirtyRect, flushed: DirtyRect) -> Option<DirtyRect> {
+    let full_width = flushed.x == dirty.x && flushed.w == dirty.w;
+    let full_height = flushed.y == dirty.y && flushed.h == dirty.h;
+    let mut rest = dirty;
+    match (full_width, full_height) {
+        (true, true) => return None,
+        (true, false) if flushed.y == dirty.y => {
+            rest.y += flushed.h;
+            rest.h -= flushed.h;
+        }
+        (true, false) if flushed.y + flushed.h == dirty.y + dirty.h => rest.h -= flushed.h,
+        (false, true) if flushed.x == dirty.x => {
+            rest.x += flushed.w;
+            rest.w -= flushed.w;
+        }
+        (false, true) if flushed.x + flushed.w == dirty.x + dirty.w => rest.w -= flushed.w,
+        // A hole in the middle or a corner: the bounding box doesn't shrink.
+        _ => {}
+    }
+    Some(rest)
+}
+
 impl OriginDimensions for Window {
     fn size(&self) -> Size {
         Size::new(self.width, self.height)
@@ -269,3 +496,115 @@ impl DrawTarget for Window {
         Ok(())
     }
 }
+
+/// The top-most window visible at screen position `(x, y)`, or `None` if
+/// only the background is there.
+pub fn window_at(display_server_send_ep: u64, x: i32, y: i32) -> Option<WindowId> {
+    let req = WindowAtRequest { x, y };
+    let response: WindowAtResponse =
+        ipc::call_typed(display_server_send_ep, WindowMessageType::WindowAt as u16, &req).ok()?;
+    response.result.is_ok().then_some(response.window_id)
+}
+
+/// Switch the display server in or out of step mode, where it composites only
+/// when asked by `step`. Returns the server's composite count at the switch;
+/// anything already pending has been presented by then.
+///
+/// Meant for tests that need deterministic frames: the server is global, so
+/// leave step mode when done or the screen stops updating.
+pub fn set_step_mode(display_server_send_ep: u64, enabled: bool) -> Option<u64> {
+    let req = SetStepModeRequest { enabled: enabled as u64 };
+    let response: StepResponse =
+        ipc::call_typed(display_server_send_ep, WindowMessageType::SetStepMode as u16, &req).ok()?;
+    Some(response.composites)
+}
+
+/// In step mode, have the display server apply pending input and composite
+/// once. Returns its counters afterwards; the composite count only advances
+/// if there was something to draw.
+pub fn step(display_server_send_ep: u64) -> Option<StepResponse> {
+    ipc::call_typed(display_server_send_ep, WindowMessageType::Step as u16, &StepRequest).ok()
+}
+
+/// Have the display server composite at `width`×`height`, which must fit the
+/// framebuffer. Returns the mode now in effect; subscribed `DisplayEvents`
+/// have already been sent it when this returns.
+pub fn set_display_mode(display_server_send_ep: u64, width: u32, height: u32) -> Result<DisplayInfo, WindowResult> {
+    let req = SetDisplayModeRequest { width, height };
+    let response: SetDisplayModeResponse =
+        ipc::call_typed(display_server_send_ep, WindowMessageType::SetDisplayMode as u16, &req)
+            .map_err(|_| WindowResult::ErrorInvalidMessage)?;
+    if response.result.is_ok() { Ok(response.info) } else { Err(response.result) }
+}
+
+/// A subscription to the display server's mode changes. Closed on drop.
+pub struct DisplayEvents {
+    recv_endpoint: u64,
+}
+
+impl DisplayEvents {
+    /// Subscribe to `DISPLAY_EVENTS_SERVICE`. Only changes made after this
+    /// are reported.
+    pub fn subscribe() -> Result<Self, SysError> {
+        let hub = crate::sys_lookup_service(DISPLAY_EVENTS_SERVICE)?;
+        Ok(DisplayEvents { recv_endpoint: crate::sys_channel_subscribe(hub)? })
+    }
+
+    /// Block until the display changes and return the new mode.
+    pub fn wait(&self) -> Result<DisplayInfo, SysError> {
+        ipc::recv_typed::<DisplayChangedEvent>(self.recv_endpoint, DisplayEventType::DisplayChanged as u16)
+            .map(|event| event.info)
+    }
+
+    /// The next change already queued, if any.
+    pub fn poll(&self) -> Option<DisplayInfo> {
+        match crate::sys_channel_poll(self.recv_endpoint) {
+            Ok(n) if n > 0 => self.wait().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for DisplayEvents {
+    fn drop(&mut self) {
+        let _ = crate::sys_channel_close(self.recv_endpoint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remaining_damage, DirtyRect};
+
+    const DIRTY: DirtyRect = DirtyRect { x: 10, y: 20, w: 100, h: 50 };
+
+    #[test]
+    fn flushing_everything_leaves_nothing() {
+        assert_eq!(remaining_damage(DIRTY, DIRTY), None);
+    }
+
+    #[test]
+    fn flushing_top_rows_shrinks_from_the_top() {
+        let top = DirtyRect { x: 10, y: 20, w: 100, h: 8 };
+        assert_eq!(remaining_damage(DIRTY, top), Some(DirtyRect { x: 10, y: 28, w: 100, h: 42 }));
+    }
+
+    #[test]
+    fn flushing_bottom_rows_shrinks_from_the_bottom() {
+        let bottom = DirtyRect { x: 10, y: 62, w: 100, h: 8 };
+        assert_eq!(remaining_damage(DIRTY, bottom), Some(DirtyRect { x: 10, y: 20, w: 100, h: 42 }));
+    }
+
+    #[test]
+    fn flushing_left_columns_shrinks_from_the_left() {
+        let left = DirtyRect { x: 10, y: 20, w: 30, h: 50 };
+        assert_eq!(remaining_damage(DIRTY, left), Some(DirtyRect { x: 40, y: 20, w: 70, h: 50 }));
+    }
+
+    #[test]
+    fn flushing_a_middle_line_keeps_the_box() {
+        let line = DirtyRect { x: 10, y: 40, w: 100, h: 8 };
+        assert_eq!(remaining_damage(DIRTY, line), Some(DIRTY));
+        let corner = DirtyRect { x: 10, y: 20, w: 5, h: 5 };
+        assert_eq!(remaining_damage(DIRTY, corner), Some(DIRTY));
+    }
+}
@@ -8,7 +8,6 @@ use embedded_graphics::prelude::OriginDimensions;
 use embedded_graphics::primitives::{Primitive, Rectangle, PrimitiveStyle};
 use embedded_graphics::Drawable;
 use ulib::sys_yield;
-use kernel_api_types::SVC_ERR_NOT_FOUND;
 
 #[panic_handler]
 fn rust_panic(_info: &core::panic::PanicInfo) -> ! {
@@ -23,8 +22,7 @@ const CUBE_SIZE: u32 = 20;
 unsafe extern "sysv64" fn entry_point(_arg: u64) -> ! {
     // Wait for the display service to register itself
     let display_server_ep = loop {
-        let ep = ulib::sys_lookup_service(b"display");
-        if ep != SVC_ERR_NOT_FOUND {
+        if let Ok(ep) = ulib::sys_lookup_service(b"display") {
             break ep;
         }
         sys_yield();
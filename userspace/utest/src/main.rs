@@ -4,127 +4,1172 @@
 use core::sync::atomic::{AtomicU64, Ordering};
 
 use kernel_api_types::{
-    IPC_ERR_CHANNEL_FULL, IPC_ERR_INVALID_ENDPOINT, IPC_ERR_PEER_CLOSED,
-    IPC_OK, MMAP_WRITE, SVC_ERR_NOT_FOUND, SVC_OK,
+    KeyEvent, MouseEvent, SysError, DEFAULT_PRIORITY, EXIT_KILLED, EXIT_STACK_OVERFLOW, MAX_PRIORITY, MMAP_LAZY, MMAP_WRITE,
 };
-use ulib::test_framework::TestRunner;
+use kernel_api_types::input::{InputEvent, RecordedInput};
+use kernel_api_types::net::{BROADCAST_MAC, LOOPBACK_MAC, MAX_FRAME_SIZE};
+use kernel_api_types::serial::SERIAL_TEST_MARKER;
+use kernel_api_types::task::{SpawnError, TaskInfo, SPAWN_SYSTEM, TASK_KIND_USER, TASK_STATE_RUNNING};
+use kernel_api_types::window::{WindowResult, MAX_WINDOWS};
+use ulib::inet::{self, ArpPacket, IcmpEcho, ARP_FRAME_SIZE, ARP_REPLY, ICMP_ECHO_REPLY, ICMP_ECHO_REQUEST};
+use ulib::net::NetClient;
+use ulib::serial::SerialClient;
+use ulib::test_framework::{TestResult, TestRunner};
 
 #[panic_handler]
 fn rust_panic(_info: &core::panic::PanicInfo) -> ! {
     ulib::default_panic(_info)
 }
 
+/// Fail the test with `reason` unless `cond` holds.
+macro_rules! ensure {
+    ($cond:expr, $reason:expr) => {
+        if !$cond {
+            return TestResult::Failed($reason);
+        }
+    };
+}
+
 // ---------------------------------------------------------------------------
 // Memory tests
 // ---------------------------------------------------------------------------
 
-fn mmap_nonzero() -> bool {
-    let ptr = ulib::sys_mmap(4096, MMAP_WRITE);
-    if ptr.is_null() {
-        return false;
-    }
+fn mmap_nonzero() -> TestResult {
+    let Ok(ptr) = ulib::sys_mmap(4096, MMAP_WRITE) else {
+        return TestResult::Failed("mmap failed");
+    };
     let is_aligned = (ptr as u64) % 4096 == 0;
-    ulib::sys_munmap(ptr, 4096);
-    is_aligned
+    let _ = ulib::sys_munmap(ptr, 4096);
+    ensure!(is_aligned, "mapping not page-aligned");
+    TestResult::Ok
 }
 
-fn mmap_writable() -> bool {
-    let ptr = ulib::sys_mmap(4096, MMAP_WRITE);
-    if ptr.is_null() {
-        return false;
-    }
-    unsafe {
+fn mmap_writable() -> TestResult {
+    let Ok(ptr) = ulib::sys_mmap(4096, MMAP_WRITE) else {
+        return TestResult::Failed("mmap failed");
+    };
+    let val = unsafe {
         core::ptr::write(ptr as *mut u32, 0xDEAD_BEEF);
-        let val = core::ptr::read(ptr as *const u32);
-        ulib::sys_munmap(ptr, 4096);
-        val == 0xDEAD_BEEF
+        core::ptr::read(ptr as *const u32)
+    };
+    let _ = ulib::sys_munmap(ptr, 4096);
+    ensure!(val == 0xDEAD_BEEF, "write not read back");
+    TestResult::Ok
+}
+
+fn mmap_independent() -> TestResult {
+    let (Ok(a), Ok(b)) = (ulib::sys_mmap(4096, MMAP_WRITE), ulib::sys_mmap(4096, MMAP_WRITE)) else {
+        return TestResult::Failed("mmap failed");
+    };
+    let different = a != b;
+    let _ = ulib::sys_munmap(a, 4096);
+    let _ = ulib::sys_munmap(b, 4096);
+    ensure!(different, "two mappings at the same address");
+    TestResult::Ok
+}
+
+fn munmap_ok() -> TestResult {
+    let Ok(ptr) = ulib::sys_mmap(4096, MMAP_WRITE) else {
+        return TestResult::Failed("mmap failed");
+    };
+    ensure!(ulib::sys_munmap(ptr, 4096).is_ok(), "munmap of a fresh mapping failed");
+    TestResult::Ok
+}
+
+fn munmap_bad_range() -> TestResult {
+    // Nothing is mapped at the very bottom of the address space.
+    ensure!(
+        ulib::sys_munmap(0x1000 as *mut u8, 4096) == Err(SysError::InvalidArgs),
+        "munmap of an unmapped range not rejected"
+    );
+    TestResult::Ok
+}
+
+fn mmap_lazy_populate() -> TestResult {
+    const PAGES: usize = 4;
+    let size = (PAGES * 4096) as u64;
+    let Ok(ptr) = ulib::sys_mmap(size, MMAP_WRITE | MMAP_LAZY) else {
+        return TestResult::Failed("lazy mmap failed");
+    };
+    let mut before = [0xffu8; PAGES];
+    let mut after = [0u8; PAGES];
+    let ok = ulib::sys_mincore(ptr, size, &mut before).is_ok()
+        && ulib::sys_populate(ptr, size).is_ok()
+        && ulib::sys_mincore(ptr, size, &mut after).is_ok();
+    let _ = ulib::sys_munmap(ptr, size);
+    ensure!(ok, "mincore or populate failed");
+    ensure!(before == [0; PAGES], "lazy pages resident before populate");
+    ensure!(after == [1; PAGES], "pages not resident after populate");
+    TestResult::Ok
+}
+
+/// Touching one page of a lazy mapping faults in that page only, zero-filled.
+fn mmap_lazy_fault_in() -> TestResult {
+    let size = 3 * 4096;
+    let Ok(ptr) = ulib::sys_mmap(size, MMAP_WRITE | MMAP_LAZY) else {
+        return TestResult::Failed("lazy mmap failed");
+    };
+    let first = unsafe { core::ptr::read_volatile(ptr.add(4096) as *const u32) };
+    unsafe { core::ptr::write_volatile(ptr.add(4096) as *mut u32, 0xC0FFEE) };
+    let readback = unsafe { core::ptr::read_volatile(ptr.add(4096) as *const u32) };
+    let mut resident = [0u8; 3];
+    let ok = ulib::sys_mincore(ptr, size, &mut resident).is_ok();
+    let _ = ulib::sys_munmap(ptr, size);
+    ensure!(ok, "mincore failed");
+    ensure!(first == 0, "faulted-in page not zero-filled");
+    ensure!(readback == 0xC0FFEE, "write not read back");
+    ensure!(resident == [0, 1, 0], "fault mapped pages other than the touched one");
+    TestResult::Ok
+}
+
+/// mincore's output may be an untouched lazy page: the kernel faults it in
+/// while writing the answer.
+fn mincore_into_lazy_page() -> TestResult {
+    let size = 2 * 4096;
+    let Ok(ptr) = ulib::sys_mmap(size, MMAP_WRITE | MMAP_LAZY) else {
+        return TestResult::Failed("lazy mmap failed");
+    };
+    // The second page holds the answer and hasn't been touched yet.
+    let out = unsafe { core::slice::from_raw_parts_mut(ptr.add(4096), 2) };
+    let ok = ulib::sys_mincore(ptr, size, out).is_ok();
+    let answer = [out[0], out[1]];
+    let _ = ulib::sys_munmap(ptr, size);
+    ensure!(ok, "mincore into a lazy page failed");
+    // The lookup ran before the write faulted the output page in.
+    ensure!(answer == [0, 0], "lazy pages reported resident");
+    TestResult::Ok
+}
+
+/// Spawn arguments that make a utest child run one stack check instead of
+/// the tests.
+const OVERFLOW_STACK: u64 = 1;
+const GROW_STACK: u64 = 2;
+/// Spawn argument for a utest child that spins until it is killed.
+const SPIN_FOREVER: u64 = 3;
+/// Spawn argument for the child side of `wakeup_preempts`.
+const WAKE_LATENCY: u64 = 4;
+/// Spawn argument for the echo server of `ipc_round_trip_latency`.
+const IPC_ECHO: u64 = 5;
+/// Spawn argument for a utest child that exits with `EXIT_AT_ONCE_CODE` at once.
+const EXIT_AT_ONCE: u64 = 6;
+const EXIT_AT_ONCE_CODE: u64 = 42;
+/// Spawn argument for the child side of `user_priority_only_lowers`.
+const LOWER_PRIORITY: u64 = 7;
+/// Spawn argument for the child side of `user_cannot_trace_syscalls`.
+const TRY_SYSCALL_TRACE: u64 = 8;
+
+/// Recurse with a 1 KiB frame, `limit` levels deep.
+fn recurse_deep(depth: u64, limit: u64) -> u64 {
+    let frame = core::hint::black_box([depth as u8; 1024]);
+    if depth >= limit {
+        return 0;
     }
+    recurse_deep(depth + 1, limit) + frame[0] as u64
+}
+
+/// Count the resident pages among the `PAGES` pages below the one holding
+/// `top`, or None if `mincore` fails.
+fn resident_stack_pages(top: u64) -> Option<usize> {
+    const PAGES: usize = 32;
+    let start = (top & !0xfff) - (PAGES as u64) * 4096;
+    let mut resident = [0u8; PAGES];
+    ulib::sys_mincore(start as *mut u8, resident.len() as u64 * 4096, &mut resident).ok()?;
+    Some(resident.iter().filter(|&&r| r != 0).count())
+}
+
+/// Child side of `stack_grows_on_demand`: only the top of a fresh stack is
+/// resident, and recursing 64 KiB deep maps the pages on the way down.
+fn grow_stack() -> bool {
+    let marker = 0u8;
+    let top = core::hint::black_box(&marker) as *const u8 as u64;
+    let Some(before) = resident_stack_pages(top) else { return false };
+    recurse_deep(0, 64);
+    let Some(after) = resident_stack_pages(top) else { return false };
+    before <= 2 && after >= 16
 }
 
-fn mmap_independent() -> bool {
-    let a = ulib::sys_mmap(4096, MMAP_WRITE);
-    let b = ulib::sys_mmap(4096, MMAP_WRITE);
-    let different = !a.is_null() && !b.is_null() && a != b;
-    ulib::sys_munmap(a, 4096);
-    ulib::sys_munmap(b, 4096);
-    different
+/// Spawn utest again with `arg` as a user task and return the child's task ID.
+fn spawn_child(arg: u64) -> Result<u64, SysError> {
+    spawn_child_with_flags(arg, 0)
 }
 
-fn munmap_ok() -> bool {
-    let ptr = ulib::sys_mmap(4096, MMAP_WRITE);
-    if ptr.is_null() {
-        return false;
+/// Like `spawn_child`, with `Spawn` flags such as `SPAWN_SYSTEM`.
+fn spawn_child_with_flags(arg: u64, flags: u64) -> Result<u64, SysError> {
+    let size = ulib::sys_get_module("utest", core::ptr::null_mut(), 0)?;
+    let buf = ulib::sys_mmap(size, MMAP_WRITE)?;
+    let elf = unsafe { core::slice::from_raw_parts(buf, size as usize) };
+    let result = ulib::sys_get_module("utest", buf, size)
+        .and_then(|_| ulib::sys_spawn_with_flags(elf, arg, flags).map_err(SysError::from));
+    let _ = ulib::sys_munmap(buf, size);
+    result
+}
+
+/// Reading a module 4 KiB at a time gives the same bytes as one full read,
+/// and an offset past the end is rejected.
+fn module_read_in_chunks() -> TestResult {
+    let Ok(size) = ulib::sys_get_module("utest", core::ptr::null_mut(), 0) else {
+        return TestResult::Failed("module size query failed");
+    };
+    let Ok(full) = ulib::sys_mmap(size, MMAP_WRITE) else { return TestResult::Failed("mmap failed") };
+    if ulib::sys_get_module("utest", full, size) != Ok(size) {
+        let _ = ulib::sys_munmap(full, size);
+        return TestResult::Failed("full module read failed");
+    }
+    let full_bytes = unsafe { core::slice::from_raw_parts(full, size as usize) };
+
+    let mut chunk = [0u8; 4096];
+    let mut offset = 0;
+    let mut result = TestResult::Ok;
+    while result == TestResult::Ok {
+        match ulib::sys_get_module_chunk("utest", offset, &mut chunk) {
+            Ok(0) => break,
+            Ok(n) => {
+                let end = offset + n;
+                if end > size || full_bytes[offset as usize..end as usize] != chunk[..n as usize] {
+                    result = TestResult::Failed("chunk differs from the full read");
+                }
+                offset = end;
+            }
+            Err(_) => result = TestResult::Failed("chunk read failed"),
+        }
     }
-    let ret = ulib::sys_munmap(ptr, 4096);
-    ret == 0
+    let _ = ulib::sys_munmap(full, size);
+    if result != TestResult::Ok {
+        return result;
+    }
+    ensure!(offset == size, "chunks stopped short of the module size");
+    ensure!(
+        ulib::sys_get_module_chunk("utest", size + 1, &mut chunk) == Err(SysError::InvalidArgs),
+        "offset past the end not rejected"
+    );
+    TestResult::Ok
+}
+
+/// `sys_spawn` says why it failed: a truncated image is not a loadable ELF,
+/// while an empty one is a bad buffer.
+fn spawn_error_codes() -> TestResult {
+    let Ok(size) = ulib::sys_get_module("utest", core::ptr::null_mut(), 0) else {
+        return TestResult::Failed("module size query failed");
+    };
+    let mut head = [0u8; 4096];
+    ensure!(ulib::sys_get_module_chunk("utest", 0, &mut head) == Ok(size.min(4096)), "module head read failed");
+    ensure!(ulib::sys_spawn(&head, 0) == Err(SpawnError::InvalidElf), "truncated ELF not reported as InvalidElf");
+    ensure!(ulib::sys_spawn(&[], 0) == Err(SpawnError::BadPointer), "empty image not reported as BadPointer");
+    TestResult::Ok
+}
+
+/// Spawn utest again with `arg` and return the child's exit code.
+fn run_child(arg: u64) -> Result<u64, SysError> {
+    spawn_child(arg).and_then(ulib::sys_waitpid)
+}
+
+/// Killing a child ends it with `EXIT_KILLED`; a task the caller didn't
+/// spawn, or one already gone, can't be killed.
+fn kill_child() -> TestResult {
+    let Ok(child) = spawn_child(SPIN_FOREVER) else { return TestResult::Failed("spawn failed") };
+    // Let it get running, possibly on another CPU.
+    ulib::sys_sleep(5);
+    let killed = ulib::sys_kill(child) == Ok(());
+    let code = ulib::sys_waitpid(child);
+    ensure!(killed, "kill failed");
+    ensure!(code == Ok(EXIT_KILLED), "killed child's exit code is not EXIT_KILLED");
+    ensure!(ulib::sys_kill(child) == Err(SysError::NotFound), "killing an exited child not NotFound");
+    ensure!(
+        ulib::sys_kill(ulib::sys_get_task_id()) == Err(SysError::PermissionDenied),
+        "killing a task not spawned by the caller not PermissionDenied"
+    );
+    TestResult::Ok
+}
+
+/// A child that exits before its parent waits stays a zombie until it is
+/// reaped, so the exit code isn't lost; after that it is gone.
+fn waitpid_after_child_exited() -> TestResult {
+    let Ok(child) = spawn_child(EXIT_AT_ONCE) else { return TestResult::Failed("spawn failed") };
+    // Let it exit well before the wait starts.
+    ulib::sys_sleep(20);
+    ensure!(ulib::sys_waitpid(child) == Ok(EXIT_AT_ONCE_CODE), "exit code lost before waitpid");
+    ensure!(ulib::sys_waitpid(child) == Err(SysError::NotFound), "reaped child still found");
+    TestResult::Ok
+}
+
+/// A user task may lower its priority but never raise it, so it can't
+/// outrank the servers.
+fn user_priority_only_lowers() -> TestResult {
+    ensure!(run_child(LOWER_PRIORITY) == Ok(0), "user task's priority changes not limited");
+    TestResult::Ok
+}
+
+/// Child side of `user_priority_only_lowers`: 0 if every request got the
+/// expected answer.
+fn check_priority_limits() -> u64 {
+    let denied = Err(SysError::PermissionDenied);
+    let ok = ulib::sys_set_priority(MAX_PRIORITY) == denied
+        && ulib::sys_set_priority(DEFAULT_PRIORITY) == Ok(())
+        && ulib::sys_set_priority(DEFAULT_PRIORITY - 1) == Ok(())
+        && ulib::sys_set_priority(DEFAULT_PRIORITY) == denied;
+    if ok { 0 } else { 1 }
+}
+
+/// Only system tasks may switch the global syscall trace.
+fn user_cannot_trace_syscalls() -> TestResult {
+    ensure!(run_child(TRY_SYSCALL_TRACE) == Ok(0), "user task switched syscall tracing");
+    TestResult::Ok
+}
+
+/// A task's stack starts as one page and grows as it is used.
+fn stack_grows_on_demand() -> TestResult {
+    ensure!(run_child(GROW_STACK) == Ok(0), "child's stack did not grow on demand");
+    TestResult::Ok
+}
+
+/// A child that grows its stack to the limit is killed with
+/// `EXIT_STACK_OVERFLOW` instead of taking the kernel down.
+fn stack_overflow_kills_task() -> TestResult {
+    ensure!(
+        run_child(OVERFLOW_STACK) == Ok(EXIT_STACK_OVERFLOW),
+        "overflowing child did not exit with EXIT_STACK_OVERFLOW"
+    );
+    TestResult::Ok
+}
+
+/// The task list includes this task, running and named, and a buffer too
+/// small for every task still reports the full count.
+fn list_tasks_includes_self() -> TestResult {
+    let mut buf = [TaskInfo::EMPTY; 64];
+    let Ok((tasks, total)) = ulib::sys_list_tasks(&mut buf) else {
+        return TestResult::Failed("list_tasks failed");
+    };
+    let me = ulib::sys_get_task_id();
+    let found = tasks.iter().any(|t| {
+        t.id == me && t.kind == TASK_KIND_USER && t.state == TASK_STATE_RUNNING && t.name() == "utest"
+    });
+    ensure!(found, "own task missing or wrong in the list");
+    // init, the idle task and this one, at least.
+    ensure!(total >= 3, "fewer than three tasks listed");
+    let mut one = [TaskInfo::EMPTY; 1];
+    ensure!(
+        ulib::sys_list_tasks(&mut one).is_ok_and(|(t, n)| t.len() == 1 && n >= 3),
+        "one-entry buffer did not report the full count"
+    );
+    TestResult::Ok
 }
 
 // ---------------------------------------------------------------------------
 // IPC tests
 // ---------------------------------------------------------------------------
 
-fn channel_create() -> bool {
-    let (send_ep, recv_ep) = ulib::sys_channel_create(1);
-    let ok = send_ep != 0 && recv_ep != 0 && send_ep != recv_ep;
-    ulib::sys_channel_close(send_ep);
-    ulib::sys_channel_close(recv_ep);
-    ok
+/// Create a channel for a test, or fail the test.
+macro_rules! channel {
+    ($capacity:expr) => {
+        match ulib::sys_channel_create($capacity) {
+            Ok(eps) => eps,
+            Err(_) => return TestResult::Failed("channel create failed"),
+        }
+    };
 }
 
-fn channel_loopback() -> bool {
-    let (send_ep, recv_ep) = ulib::sys_channel_create(4);
+fn channel_create() -> TestResult {
+    let (send_ep, recv_ep) = channel!(1);
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(send_ep != 0 && recv_ep != 0, "zero endpoint ID");
+    ensure!(send_ep != recv_ep, "both ends share an endpoint ID");
+    TestResult::Ok
+}
+
+fn channel_loopback() -> TestResult {
+    let (send_ep, recv_ep) = channel!(4);
     let data: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
-    let send_result = ulib::sys_channel_send(send_ep, &data);
-    if send_result != IPC_OK {
-        ulib::sys_channel_close(send_ep);
-        ulib::sys_channel_close(recv_ep);
-        return false;
+    if ulib::sys_channel_send(send_ep, &data).is_err() {
+        let _ = ulib::sys_channel_close(recv_ep);
+        return TestResult::Failed("send failed");
     }
     let mut buf = [0u8; 8];
-    let (recv_result, bytes_read) = ulib::sys_channel_recv(recv_ep, &mut buf);
-    ulib::sys_channel_close(send_ep);
-    ulib::sys_channel_close(recv_ep);
-    recv_result == IPC_OK && bytes_read == 8 && buf == data
+    let recv_result = ulib::sys_channel_recv(recv_ep, &mut buf);
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(recv_result == Ok(8), "recv did not return the 8 bytes sent");
+    ensure!(buf == data, "received bytes differ from those sent");
+    TestResult::Ok
 }
 
-fn channel_recv_size() -> bool {
-    let (send_ep, recv_ep) = ulib::sys_channel_create(4);
+fn channel_recv_size() -> TestResult {
+    let (send_ep, recv_ep) = channel!(4);
     let data = [0xABu8; 5];
-    ulib::sys_channel_send(send_ep, &data);
+    let _ = ulib::sys_channel_send(send_ep, &data);
     let mut buf = [0u8; 64];
-    let (result, bytes_read) = ulib::sys_channel_recv(recv_ep, &mut buf);
-    ulib::sys_channel_close(send_ep);
-    ulib::sys_channel_close(recv_ep);
-    result == IPC_OK && bytes_read == 5
+    let result = ulib::sys_channel_recv(recv_ep, &mut buf);
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(result == Ok(5), "recv into a larger buffer did not return the message size");
+    TestResult::Ok
+}
+
+/// A message longer than the buffer is cut to fit and its full length
+/// reported; it is consumed unless the receiver asks to keep it.
+fn channel_recv_truncated() -> TestResult {
+    use kernel_api_types::RECV_KEEP_TRUNCATED;
+
+    let (send_ep, recv_ep) = channel!(4);
+    let data: [u8; 16] = core::array::from_fn(|i| i as u8);
+    let mut small = [0u8; 4];
+    let mut large = [0u8; 64];
+    let _ = ulib::sys_channel_send(send_ep, &data);
+    let dropped = ulib::sys_channel_recv_flags(recv_ep, &mut small, 0);
+    let left_after_drop = ulib::sys_channel_poll(recv_ep);
+    let _ = ulib::sys_channel_send(send_ep, &data);
+    let kept = ulib::sys_channel_recv_flags(recv_ep, &mut small, RECV_KEEP_TRUNCATED);
+    let left_after_keep = ulib::sys_channel_poll(recv_ep);
+    let whole = ulib::sys_channel_recv_flags(recv_ep, &mut large, 0);
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(dropped == Ok((4, 16)), "truncated recv did not report 4 bytes of 16");
+    ensure!(small == data[..4], "truncated recv did not copy the head of the message");
+    ensure!(left_after_drop == Ok(0), "truncated message left queued");
+    ensure!(kept == Ok((4, 16)), "truncated recv with RECV_KEEP_TRUNCATED did not report 4 bytes of 16");
+    ensure!(left_after_keep == Ok(1), "RECV_KEEP_TRUNCATED dropped the message");
+    ensure!(whole == Ok((16, 16)) && large[..16] == data, "kept message not received whole");
+    TestResult::Ok
 }
 
-fn channel_full() -> bool {
-    // Create a channel with capacity 4; the 5th send should return IPC_ERR_CHANNEL_FULL
+fn channel_full() -> TestResult {
+    // Fill the channel to its granted capacity; the next send should fail with WouldBlock
     // via the timer-interrupt EINTR mechanism (blocks briefly, timer returns fallback rax).
-    let (send_ep, recv_ep) = ulib::sys_channel_create(4);
+    let (send_ep, recv_ep) = channel!(4);
     let data = [0u8; 1];
-    for _ in 0..4 {
-        if ulib::sys_channel_send(send_ep, &data) != IPC_OK {
-            ulib::sys_channel_close(send_ep);
-            ulib::sys_channel_close(recv_ep);
-            return false;
+    let Ok((0, capacity)) = ulib::sys_channel_stats(send_ep) else {
+        let _ = ulib::sys_channel_close(recv_ep);
+        return TestResult::Failed("stats of a new channel not (0, capacity)");
+    };
+    for _ in 0..capacity {
+        if ulib::sys_channel_send(send_ep, &data).is_err() {
+            let _ = ulib::sys_channel_close(recv_ep);
+            return TestResult::Failed("send within capacity failed");
         }
     }
-    // 5th send: channel is full → EINTR returns IPC_ERR_CHANNEL_FULL
+    let full = ulib::sys_channel_stats(recv_ep);
+    // One more: channel is full → EINTR returns WouldBlock
     let result = ulib::sys_channel_send(send_ep, &data);
-    ulib::sys_channel_close(send_ep);
-    ulib::sys_channel_close(recv_ep);
-    result == IPC_ERR_CHANNEL_FULL
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(capacity == 4, "channel not granted the capacity asked for");
+    ensure!(full == Ok((capacity, capacity)), "stats of a full channel not (capacity, capacity)");
+    ensure!(result == Err(SysError::WouldBlock), "send to a full channel not WouldBlock");
+    TestResult::Ok
 }
 
-fn channel_close_peer() -> bool {
-    let (send_ep, recv_ep) = ulib::sys_channel_create(4);
+fn channel_close_peer() -> TestResult {
+    let (send_ep, recv_ep) = channel!(4);
     // Close the receiving end (peer of send_ep)
-    ulib::sys_channel_close(recv_ep);
+    let _ = ulib::sys_channel_close(recv_ep);
     // Sending to a channel whose peer is closed should return PeerClosed or InvalidEndpoint
     let result = ulib::sys_channel_send(send_ep, &[1u8]);
-    ulib::sys_channel_close(send_ep);
-    result == IPC_ERR_PEER_CLOSED || result == IPC_ERR_INVALID_ENDPOINT
+    ensure!(
+        matches!(result, Err(SysError::PeerClosed | SysError::InvalidEndpoint)),
+        "send after the peer closed not rejected"
+    );
+    TestResult::Ok
+}
+
+fn channel_closed_endpoint() -> TestResult {
+    let (send_ep, recv_ep) = channel!(1);
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(
+        ulib::sys_channel_send(send_ep, &[1u8]) == Err(SysError::InvalidEndpoint),
+        "send on a closed channel not InvalidEndpoint"
+    );
+    TestResult::Ok
+}
+
+fn channel_dup() -> TestResult {
+    let (send_ep, recv_ep) = channel!(2);
+    let Ok(dup_ep) = ulib::sys_channel_dup(send_ep) else {
+        let _ = ulib::sys_channel_close(recv_ep);
+        return TestResult::Failed("dup failed");
+    };
+    // The original is gone, but the dup still delivers to the same receiver.
+    let sent = ulib::sys_channel_send(dup_ep, &[7u8]).is_ok();
+    let mut buf = [0u8; 1];
+    let received = ulib::sys_channel_recv(recv_ep, &mut buf) == Ok(1) && buf == [7];
+    let _ = ulib::sys_channel_close(dup_ep);
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(dup_ep != send_ep, "dup returned the original endpoint");
+    ensure!(sent, "send on the dup failed");
+    ensure!(received, "message sent on the dup not received");
+    TestResult::Ok
+}
+
+/// An endpoint sent with a message reaches the receiver, which can reply on it.
+fn channel_pass_endpoint() -> TestResult {
+    let (req_send, req_recv) = channel!(2);
+    let (reply_send, reply_recv) = channel!(2);
+    let sent = ulib::sys_channel_send_with_endpoint(req_send, b"req", reply_send);
+    let mut buf = [0u8; 8];
+    let received = ulib::sys_channel_recv_with_endpoint(req_recv, &mut buf);
+    let replied = match received {
+        Ok((_, Some(ep))) => ulib::sys_channel_send(ep, b"ok").is_ok(),
+        _ => false,
+    };
+    let mut reply = [0u8; 8];
+    let got_reply = ulib::sys_channel_recv(reply_recv, &mut reply) == Ok(2) && &reply[..2] == b"ok";
+    let _ = ulib::sys_channel_close(req_recv);
+    let _ = ulib::sys_channel_close(reply_send);
+    let _ = ulib::sys_channel_close(reply_recv);
+    ensure!(sent.is_ok(), "send with endpoint failed");
+    ensure!(received == Ok((3, Some(reply_send))), "endpoint not received with the message");
+    ensure!(&buf[..3] == b"req", "message bytes differ from those sent");
+    ensure!(replied && got_reply, "reply on the passed endpoint not received");
+    TestResult::Ok
+}
+
+/// Both subscribers of a broadcast channel get each message; closing the
+/// broadcast endpoint closes theirs.
+fn broadcast_two_subscribers() -> TestResult {
+    let Ok(broadcast_ep) = ulib::sys_broadcast_create(2) else {
+        return TestResult::Failed("broadcast create failed");
+    };
+    let subs = [ulib::sys_channel_subscribe(broadcast_ep), ulib::sys_channel_subscribe(broadcast_ep)];
+    let sent = ulib::sys_channel_send(broadcast_ep, b"hi").is_ok();
+    let _ = ulib::sys_channel_close(broadcast_ep);
+    ensure!(sent, "broadcast send failed");
+
+    let mut result = TestResult::Ok;
+    for sub in subs {
+        let Ok(recv_ep) = sub else { return TestResult::Failed("subscribe failed") };
+        let mut buf = [0u8; 4];
+        if ulib::sys_channel_recv(recv_ep, &mut buf) != Ok(2) || &buf[..2] != b"hi" {
+            result = TestResult::Failed("subscriber missed the message");
+        } else if ulib::sys_channel_recv(recv_ep, &mut buf) != Err(SysError::PeerClosed) {
+            result = TestResult::Failed("subscriber not closed with the broadcast");
+        }
+        let _ = ulib::sys_channel_close(recv_ep);
+    }
+    result
+}
+
+/// Syscalls that block or diverge are skipped inside a batch; the ones
+/// around them still run.
+fn batch_skips_blocking_ops() -> TestResult {
+    use kernel_api_types::{SysCallNumber, SyscallOp, BATCH_OP_NOT_RUN};
+
+    let mut ops = [
+        SyscallOp::new(SysCallNumber::Null, [0; 6]),
+        SyscallOp::new(SysCallNumber::Sleep, [1_000, 0, 0, 0, 0, 0]),
+        SyscallOp::new(SysCallNumber::Reboot, [0; 6]),
+        SyscallOp::new(SysCallNumber::Null, [0; 6]),
+    ];
+    let executed = ulib::sys_batch(&mut ops);
+    let results = ops.map(|op| op.result);
+    ensure!(executed == Ok(2), "batch ran a blocking or diverging op");
+    ensure!(results == [0, BATCH_OP_NOT_RUN, BATCH_OP_NOT_RUN, 0], "wrong per-op results");
+    TestResult::Ok
+}
+
+/// A batch that unmaps its own ops array finishes without the kernel
+/// faulting on the results.
+fn batch_unmaps_own_ops() -> TestResult {
+    use kernel_api_types::{SysCallNumber, SyscallOp};
+
+    let Ok(page) = ulib::sys_mmap(4096, MMAP_WRITE) else {
+        return TestResult::Failed("mmap failed");
+    };
+    let ops = unsafe { core::slice::from_raw_parts_mut(page as *mut SyscallOp, 2) };
+    ops[0] = SyscallOp::new(SysCallNumber::Munmap, [page as u64, 4096, 0, 0, 0, 0]);
+    ops[1] = SyscallOp::new(SysCallNumber::Null, [0; 6]);
+    // The array is gone afterwards; only the return value can be looked at.
+    let executed = ulib::sys_batch(ops);
+    ensure!(executed == Ok(2), "batch that unmapped its ops didn't run both");
+    TestResult::Ok
+}
+
+fn channel_batched_sends() -> TestResult {
+    use kernel_api_types::{SysCallNumber, SyscallOp};
+
+    let (send_ep, recv_ep) = channel!(4);
+    let msgs: [[u8; 2]; 3] = [[1, 1], [2, 2], [3, 3]];
+    let mut ops: [SyscallOp; 3] = core::array::from_fn(|i| {
+        SyscallOp::new(
+            SysCallNumber::ChannelSend,
+            [send_ep, msgs[i].as_ptr() as u64, msgs[i].len() as u64, 0, 0, 0],
+        )
+    });
+    // All three sends go through a single SYSCALL.
+    let executed = ulib::sys_batch(&mut ops);
+    let sends_ok = ops.iter().all(|op| SysError::from_ret(op.result).is_ok());
+
+    let mut in_order = true;
+    for expected in &msgs {
+        let mut buf = [0u8; 2];
+        in_order &= ulib::sys_channel_recv(recv_ep, &mut buf) == Ok(2) && &buf == expected;
+    }
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(executed == Ok(3), "batch did not execute all three ops");
+    ensure!(sends_ok, "a batched send failed");
+    ensure!(in_order, "batched messages not received in order");
+    TestResult::Ok
+}
+
+fn ring_completions() -> TestResult {
+    use kernel_api_types::ring::{RingOp, SubmissionEntry};
+
+    let mut ring = match ulib::ring::Ring::new() {
+        Some(r) => r,
+        None => return TestResult::Failed("ring setup failed"),
+    };
+    let (send_ep, recv_ep) = channel!(4);
+    let msg = [0x42u8; 3];
+    let mut buf = [0u8; 8];
+
+    // mmap, send, recv — one doorbell for all three.
+    let posted = ring.submit(SubmissionEntry::new(RingOp::Mmap, 1, [4096, MMAP_WRITE, 0, 0]))
+        && ring.submit(SubmissionEntry::new(
+            RingOp::ChannelSend, 2, [send_ep, msg.as_ptr() as u64, msg.len() as u64, 0],
+        ))
+        && ring.submit(SubmissionEntry::new(
+            RingOp::ChannelRecv, 3, [recv_ep, buf.as_mut_ptr() as u64, buf.len() as u64, 0],
+        ));
+    let entered = ring.enter();
+
+    let mut mmap_addr = 0;
+    let mut send_ok = false;
+    let mut recv_ok = false;
+    let mut count = 0;
+    while let Some(cqe) = ring.pop_completion() {
+        count += 1;
+        match cqe.user_data {
+            1 => mmap_addr = cqe.result,
+            2 => send_ok = SysError::from_ret(cqe.result).is_ok(),
+            3 => recv_ok = SysError::from_ret(cqe.result).is_ok() && cqe.aux == 3,
+            _ => return TestResult::Failed("completion with unknown user_data"),
+        }
+    }
+
+    // Tear down through the ring too.
+    let unmapped = mmap_addr != 0
+        && ring.submit(SubmissionEntry::new(RingOp::Munmap, 4, [mmap_addr, 4096, 0, 0]))
+        && ring.enter() == 1
+        && ring.pop_completion().is_some_and(|c| c.user_data == 4 && c.result == 0);
+
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(posted, "submission queue rejected an entry");
+    ensure!(entered == 3 && count == 3, "not every submission completed");
+    ensure!(send_ok, "ring send failed");
+    ensure!(recv_ok && buf[..3] == msg, "ring recv did not get the message");
+    ensure!(unmapped, "ring munmap failed");
+    TestResult::Ok
+}
+
+/// A ring can't unmap its own pages: the completion still has to land there.
+fn ring_munmap_of_itself_rejected() -> TestResult {
+    use kernel_api_types::ring::{RingOp, SubmissionEntry};
+
+    let mut ring = match ulib::ring::Ring::new() {
+        Some(r) => r,
+        None => return TestResult::Failed("ring setup failed"),
+    };
+    let posted = ring.submit(SubmissionEntry::new(RingOp::Munmap, 1, [ring.addr(), 4096, 0, 0]));
+    let entered = ring.enter();
+    let cqe = ring.pop_completion();
+
+    ensure!(posted && entered == 1, "ring munmap was not processed");
+    ensure!(
+        cqe.is_some_and(|c| c.user_data == 1 && SysError::from_ret(c.result) == Err(SysError::InvalidArgs)),
+        "munmap over the ring was not rejected"
+    );
+    TestResult::Ok
+}
+
+fn channel_call_oversized_request() -> TestResult {
+    let (send_ep, recv_ep) = channel!(1);
+    // No room left for the reply endpoint the call appends.
+    let request = [0u8; kernel_api_types::MAX_MESSAGE_SIZE - 7];
+    let mut reply = [0u8; 8];
+    let result = ulib::sys_channel_call(send_ep, &request, &mut reply);
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(result == Err(SysError::MessageTooLarge), "call without room for the reply endpoint not rejected");
+    TestResult::Ok
+}
+
+// ---------------------------------------------------------------------------
+// Syscall latency benchmark
+// ---------------------------------------------------------------------------
+
+const SYSCALL_BENCH_SAMPLES: usize = 256;
+const SYSCALL_BENCH_BATCH: u64 = 16;
+/// Generous ceiling on the median round-trip. A regression in the entry path
+/// that pushes us past this is almost certainly a bug, not noise.
+const SYSCALL_LATENCY_MAX_CYCLES: u64 = 20_000;
+const SYSCALL_LATENCY_TAG: u64 = 0x5359_534C; // "SYSL"
+
+/// Measure TSC cycles per `sys_null` round-trip and log the median.
+fn syscall_latency() -> TestResult {
+    use core::arch::x86_64::_rdtsc;
+
+    let mut samples = [0u64; SYSCALL_BENCH_SAMPLES];
+    for sample in samples.iter_mut() {
+        let start = unsafe { _rdtsc() };
+        for _ in 0..SYSCALL_BENCH_BATCH {
+            ulib::sys_null();
+        }
+        let end = unsafe { _rdtsc() };
+        *sample = end.wrapping_sub(start) / SYSCALL_BENCH_BATCH;
+    }
+
+    // The median discards batches that were hit by a timer interrupt or preemption.
+    samples.sort_unstable();
+    let median = samples[SYSCALL_BENCH_SAMPLES / 2];
+    ulib::sys_debug_log(median, SYSCALL_LATENCY_TAG);
+    ensure!(median > 0, "cycle counter did not advance");
+    ensure!(median < SYSCALL_LATENCY_MAX_CYCLES, "median sys_null round trip over the ceiling");
+    TestResult::Ok
+}
+
+/// The cycle counter advances and the kernel has a TSC rate to convert it with.
+fn cycles_advance() -> TestResult {
+    let a = ulib::sys_get_cycles();
+    for _ in 0..SYSCALL_BENCH_BATCH {
+        ulib::sys_null();
+    }
+    let b = ulib::sys_get_cycles();
+    ensure!(b > a, "cycle counter did not advance");
+    ensure!(ulib::sys_get_tsc_hz() > 0, "no TSC rate");
+    TestResult::Ok
+}
+
+/// `sys_sleep` waits at least the requested time, and not wildly longer.
+fn sleep_duration() -> TestResult {
+    let tsc_hz = ulib::sys_get_tsc_hz();
+    let start = ulib::sys_get_cycles();
+    ulib::sys_sleep(20);
+    let elapsed_ms = (ulib::sys_get_cycles() - start) * 1000 / tsc_hz.max(1);
+    ensure!(elapsed_ms >= 19, "sleep returned early");
+    ensure!(elapsed_ms < 200, "sleep overslept by more than 180 ms");
+    TestResult::Ok
+}
+
+const WAKE_ROUNDS: usize = 10;
+const WAKE_SERVICE: &[u8] = b"utest_wake";
+const WAKE_LATENCY_TAG: u64 = 0x5741_4B45; // "WAKE"
+
+fn spin_cycles(cycles: u64) {
+    let start = unsafe { core::arch::x86_64::_rdtsc() };
+    while unsafe { core::arch::x86_64::_rdtsc() }.wrapping_sub(start) < cycles {
+        core::hint::spin_loop();
+    }
+}
+
+/// A message wakes a higher-priority receiver within half a tick, even
+/// though the sender keeps spinning: on the sender's CPU the wakeup fires
+/// the scheduler early, and on another CPU a reschedule IPI does.
+fn wakeup_preempts() -> TestResult {
+    let half_tick = ulib::sys_get_tsc_hz() / 2000;
+    // Only a system task may raise its priority above the parent's.
+    let Ok(child) = spawn_child_with_flags(WAKE_LATENCY, SPAWN_SYSTEM) else {
+        return TestResult::Failed("spawn failed");
+    };
+    let mut send_ep = Err(SysError::NotFound);
+    for _ in 0..100 {
+        send_ep = ulib::sys_lookup_service(WAKE_SERVICE);
+        if send_ep.is_ok() {
+            break;
+        }
+        ulib::sys_sleep(1);
+    }
+    let Ok(send_ep) = send_ep else {
+        let _ = ulib::sys_kill(child);
+        let _ = ulib::sys_waitpid(child);
+        return TestResult::Failed("child never registered its service");
+    };
+
+    let mut sent = true;
+    for _ in 0..WAKE_ROUNDS {
+        // Two ticks' worth of spinning lets the child block in recv first.
+        spin_cycles(4 * half_tick);
+        let now = unsafe { core::arch::x86_64::_rdtsc() };
+        sent &= ulib::sys_channel_send(send_ep, &now.to_le_bytes()).is_ok();
+    }
+    spin_cycles(4 * half_tick);
+    let Ok(worst) = ulib::sys_waitpid(child) else { return TestResult::Failed("waitpid failed") };
+    ulib::sys_debug_log(worst, WAKE_LATENCY_TAG);
+    ensure!(sent, "timestamp send failed");
+    ensure!(worst != u64::MAX, "child failed to set up or receive");
+    ensure!(worst < half_tick, "wakeup took longer than half a tick");
+    TestResult::Ok
+}
+
+/// Child side of `wakeup_preempts`: outrank the parent, then receive its
+/// timestamps and exit with the longest delay between send and wakeup.
+fn measure_wake_latency() -> u64 {
+    if ulib::sys_set_priority(MAX_PRIORITY).is_err() {
+        return u64::MAX;
+    }
+    let Ok((send_ep, recv_ep)) = ulib::sys_channel_create(WAKE_ROUNDS as u64) else { return u64::MAX };
+    if ulib::sys_register_service(WAKE_SERVICE, send_ep).is_err() {
+        return u64::MAX;
+    }
+    let mut worst = 0;
+    for _ in 0..WAKE_ROUNDS {
+        let mut buf = [0u8; 8];
+        if ulib::sys_channel_recv(recv_ep, &mut buf) != Ok(8) {
+            return u64::MAX;
+        }
+        let now = unsafe { core::arch::x86_64::_rdtsc() };
+        worst = worst.max(now.saturating_sub(u64::from_le_bytes(buf)));
+    }
+    worst
+}
+
+const IPC_BENCH_SAMPLES: usize = 256;
+/// Children to spawn while looking for one on this task's CPU.
+const IPC_ECHO_SPAWN_TRIES: usize = 8;
+const IPC_ECHO_SERVICE: &[u8] = b"utest_echo";
+/// Generous ceiling on the same-CPU median round trip, as for `sys_null`.
+const IPC_LATENCY_MAX_CYCLES: u64 = 100_000;
+const IPC_SAME_CPU_TAG: u64 = 0x4950_4353; // "IPCS"
+const IPC_CROSS_CPU_TAG: u64 = 0x4950_4358; // "IPCX"
+
+/// The CPU that runs task `id`, from the task list.
+fn task_cpu(id: u64) -> Option<u32> {
+    let mut buf = [TaskInfo::EMPTY; 64];
+    let (tasks, _) = ulib::sys_list_tasks(&mut buf).ok()?;
+    tasks.iter().find(|t| t.id == id).map(|t| t.cpu)
+}
+
+/// Median TSC cycles of a request/reply round trip through an echo child
+/// (send, block, the child receives and replies, receive). Kills the child.
+fn echo_round_trip(child: u64) -> Option<u64> {
+    let median = echo_round_trip_median();
+    let _ = ulib::sys_kill(child);
+    let _ = ulib::sys_waitpid(child);
+    median
+}
+
+fn echo_round_trip_median() -> Option<u64> {
+    use core::arch::x86_64::_rdtsc;
+
+    let mut server_ep = Err(SysError::NotFound);
+    for _ in 0..100 {
+        server_ep = ulib::sys_lookup_service(IPC_ECHO_SERVICE);
+        if server_ep.is_ok() {
+            break;
+        }
+        ulib::sys_sleep(1);
+    }
+    let server_ep = server_ep.ok()?;
+    let (reply_send, reply_recv) = ulib::sys_channel_create(1).ok()?;
+
+    // The first message tells the child where to reply; it isn't timed.
+    let mut ok = ulib::sys_channel_send_blocking(server_ep, &reply_send.to_le_bytes()).is_ok();
+    let mut samples = [0u64; IPC_BENCH_SAMPLES];
+    for (i, sample) in samples.iter_mut().enumerate() {
+        if !ok {
+            break;
+        }
+        let mut buf = [0u8; 8];
+        let start = unsafe { _rdtsc() };
+        ok = ulib::sys_channel_send_blocking(server_ep, &(i as u64).to_le_bytes()).is_ok();
+        let received = loop {
+            match ulib::sys_channel_recv(reply_recv, &mut buf) {
+                Err(SysError::WouldBlock) => continue,
+                res => break res,
+            }
+        };
+        let end = unsafe { _rdtsc() };
+        ok &= received == Ok(8) && u64::from_le_bytes(buf) == i as u64;
+        *sample = end.wrapping_sub(start);
+    }
+    let _ = ulib::sys_channel_close(reply_send);
+    let _ = ulib::sys_channel_close(reply_recv);
+    if !ok {
+        return None;
+    }
+    // As in `syscall_latency`, the median discards round trips hit by a tick.
+    samples.sort_unstable();
+    Some(samples[IPC_BENCH_SAMPLES / 2])
+}
+
+/// Measure the round trip of a request and its reply between two tasks on
+/// this CPU and log the median. A child that lands on another CPU first is
+/// measured too and logged separately; that number includes an IPI.
+fn ipc_round_trip_latency() -> TestResult {
+    let Some(my_cpu) = task_cpu(ulib::sys_get_task_id()) else {
+        return TestResult::Failed("own task missing from the task list");
+    };
+    let mut same_cpu = None;
+    let mut cross_cpu = None;
+    for _ in 0..IPC_ECHO_SPAWN_TRIES {
+        let Ok(child) = spawn_child(IPC_ECHO) else { return TestResult::Failed("spawn failed") };
+        let slot = match task_cpu(child) {
+            Some(cpu) if cpu == my_cpu => &mut same_cpu,
+            Some(_) => &mut cross_cpu,
+            None => return TestResult::Failed("echo child missing from the task list"),
+        };
+        if slot.is_some() {
+            let _ = ulib::sys_kill(child);
+            let _ = ulib::sys_waitpid(child);
+            continue;
+        }
+        let Some(median) = echo_round_trip(child) else {
+            return TestResult::Failed("echo round trip failed");
+        };
+        *slot = Some(median);
+        if same_cpu.is_some() {
+            break;
+        }
+    }
+    if let Some(median) = cross_cpu {
+        ulib::sys_debug_log(median, IPC_CROSS_CPU_TAG);
+    }
+    let Some(median) = same_cpu else {
+        return TestResult::Failed("no echo child landed on this CPU");
+    };
+    ulib::sys_debug_log(median, IPC_SAME_CPU_TAG);
+    ensure!(median > 0, "cycle counter did not advance");
+    ensure!(median < IPC_LATENCY_MAX_CYCLES, "median same-CPU round trip over the ceiling");
+    TestResult::Ok
+}
+
+/// Child side of `ipc_round_trip_latency`: receive the parent's reply
+/// endpoint, then echo every message back until killed.
+fn run_echo_server() {
+    let Ok((send_ep, recv_ep)) = ulib::sys_channel_create(1) else { return };
+    if ulib::sys_register_service(IPC_ECHO_SERVICE, send_ep).is_err() {
+        return;
+    }
+    let mut buf = [0u8; 8];
+    let Ok(8) = ulib::sys_channel_recv(recv_ep, &mut buf) else { return };
+    let reply_ep = u64::from_le_bytes(buf);
+    loop {
+        match ulib::sys_channel_recv(recv_ep, &mut buf) {
+            Ok(n) => {
+                if ulib::sys_channel_send_blocking(reply_ep, &buf[..n as usize]).is_err() {
+                    return;
+                }
+            }
+            Err(SysError::WouldBlock) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+fn wallclock_plausible() -> TestResult {
+    let Ok(now) = ulib::sys_get_wallclock() else { return TestResult::Failed("wallclock read failed") };
+    ensure!(now.year > 2020 && (1..=12).contains(&now.month), "implausible wallclock date");
+    TestResult::Ok
+}
+
+/// Two fills from `sys_random` differ, and neither is stuck at one value.
+fn random_fills_differ() -> TestResult {
+    let mut a = [0u8; 64];
+    let mut b = [0u8; 64];
+    ensure!(ulib::sys_random(&mut a).is_ok() && ulib::sys_random(&mut b).is_ok(), "sys_random failed");
+    ensure!(a != b, "two fills were identical");
+    for fill in [&a, &b] {
+        // 512 bits: about 256 set, and far from 0 or 512.
+        let ones: u32 = fill.iter().map(|x| x.count_ones()).sum();
+        ensure!((192..=320).contains(&ones), "fill is badly biased");
+        ensure!(fill.windows(2).any(|w| w[0] != w[1]), "fill repeats one byte");
+    }
+    ensure!(ulib::rng::next_u64() != ulib::rng::next_u64(), "rng::next_u64 repeated itself");
+    ensure!((0..32).all(|_| ulib::rng::below(10) < 10), "rng::below out of range");
+    TestResult::Ok
+}
+
+// ---------------------------------------------------------------------------
+// Serial tests
+// ---------------------------------------------------------------------------
+
+/// Write the marker through the "serial" service. The test runner checks that
+/// it reaches the host's serial output; here we can only see it accepted.
+fn serial_service_write() -> TestResult {
+    for _ in 0..100 {
+        match SerialClient::connect() {
+            Ok(serial) => {
+                let line = [SERIAL_TEST_MARKER.as_bytes(), b"\r\n"];
+                ensure!(line.iter().all(|part| serial.write(part).is_ok()), "serial write failed");
+                return TestResult::Ok;
+            }
+            Err(SysError::NotFound) => ulib::sys_sleep(10),
+            Err(_) => return TestResult::Failed("serial connect failed"),
+        }
+    }
+    TestResult::Failed("serial service never registered")
+}
+
+/// A read never blocks; whatever was typed on the host (usually nothing) fits.
+fn serial_service_read() -> TestResult {
+    let Ok(serial) = SerialClient::connect() else { return TestResult::Failed("serial connect failed") };
+    let mut buf = [0u8; 16];
+    ensure!(serial.read(&mut buf).is_ok_and(|n| n <= buf.len()), "serial read failed");
+    TestResult::Ok
+}
+
+// ---------------------------------------------------------------------------
+// Input recording tests
+// ---------------------------------------------------------------------------
+
+/// Record a synthetic session of injected events 30 ms apart, replay it while
+/// recording again, and check the replay matches in order and, within 10 ms,
+/// in timing. Release kernels don't offer recording, so there it's skipped.
+fn input_record_replay() -> TestResult {
+    let events = [
+        InputEvent::key(KeyEvent::char('x')),
+        InputEvent::mouse(MouseEvent { dx: 3, dy: -2, ..MouseEvent::EMPTY }),
+        InputEvent::key(KeyEvent::enter()),
+    ];
+
+    match ulib::sys_input_record_start() {
+        Ok(()) => {}
+        Err(SysError::NoSys) => return TestResult::Ok,
+        Err(_) => return TestResult::Failed("record start failed"),
+    }
+    for (i, event) in events.iter().enumerate() {
+        if i > 0 {
+            ulib::sys_sleep(30);
+        }
+        if ulib::sys_inject_input(event).is_err() {
+            let _ = ulib::sys_input_record_stop(&mut []);
+            return TestResult::Failed("inject failed");
+        }
+    }
+    let mut recorded = [RecordedInput::EMPTY; 8];
+    let Ok(n) = ulib::sys_input_record_stop(&mut recorded) else {
+        return TestResult::Failed("record stop failed");
+    };
+    let recorded = &recorded[..n];
+
+    if ulib::sys_input_record_start().is_err() {
+        return TestResult::Failed("second record start failed");
+    }
+    let replayed_ok = ulib::input::replay(recorded).is_ok();
+    let mut replayed = [RecordedInput::EMPTY; 8];
+    let Ok(m) = ulib::sys_input_record_stop(&mut replayed) else {
+        return TestResult::Failed("second record stop failed");
+    };
+    let replayed = &replayed[..m];
+
+    // Compare gaps from the first event, which absorbs the replay's start-up.
+    let offset = |r: &[RecordedInput], i: usize| r[i].time_ms - r[0].time_ms;
+    ensure!(replayed_ok, "replay failed");
+    ensure!(recorded.iter().map(|r| r.event).eq(events), "recording differs from the injected events");
+    ensure!(replayed.iter().map(|r| r.event).eq(events), "replay differs from the recording");
+    ensure!(
+        (0..events.len()).all(|i| offset(recorded, i).abs_diff(offset(replayed, i)) <= 10),
+        "replay timing off by more than 10 ms"
+    );
+    TestResult::Ok
+}
+
+// ---------------------------------------------------------------------------
+// Network tests
+// ---------------------------------------------------------------------------
+
+/// Through the "net" service, ARP for QEMU's user-mode gateway (10.0.2.2) and
+/// wait for its reply. Passes vacuously when the machine has no NIC.
+fn net_arp_roundtrip() -> TestResult {
+    const GATEWAY_IP: [u8; 4] = [10, 0, 2, 2];
+    let net = match NetClient::connect() {
+        Ok(net) => net,
+        Err(SysError::NotFound) => return TestResult::Ok,
+        Err(_) => return TestResult::Failed("net connect failed"),
+    };
+    let Ok(mac) = net.mac() else { return TestResult::Failed("MAC query failed") };
+
+    let mut request = [0u8; ARP_FRAME_SIZE];
+    let Some(len) = ArpPacket::request(mac, [10, 0, 2, 15], GATEWAY_IP).write_frame(BROADCAST_MAC, &mut request) else {
+        return TestResult::Failed("ARP request does not fit a frame");
+    };
+    if net.send(&request[..len]).is_err() {
+        return TestResult::Failed("ARP send failed");
+    }
+
+    let mut frame = [0u8; MAX_FRAME_SIZE];
+    for _ in 0..10_000 {
+        match net.recv(&mut frame) {
+            Ok(n) => match ArpPacket::parse(&frame[..n]) {
+                Some(reply) if reply.operation == ARP_REPLY && reply.sender_ip == GATEWAY_IP => {
+                    return TestResult::Ok;
+                }
+                _ => ulib::sys_yield(),
+            },
+            Err(_) => return TestResult::Failed("net recv failed"),
+        }
+    }
+    TestResult::Failed("no ARP reply from the gateway")
+}
+
+/// The "loopback" service; init starts it just before utest, so allow it a
+/// moment to register.
+fn loopback() -> Option<NetClient> {
+    for _ in 0..100 {
+        match NetClient::connect_to(b"loopback") {
+            Ok(net) => return Some(net),
+            Err(SysError::NotFound) => ulib::sys_sleep(10),
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+/// Frames sent to loopback come back byte-for-byte, in order.
+fn loopback_echoes_frames() -> TestResult {
+    let Some(net) = loopback() else { return TestResult::Failed("loopback connect failed") };
+    ensure!(net.mac() == Ok(LOOPBACK_MAC), "wrong loopback MAC");
+
+    let mut frames = [[0u8; 100]; 3];
+    for (i, frame) in frames.iter_mut().enumerate() {
+        for (j, byte) in frame.iter_mut().enumerate() {
+            *byte = (i * 100 + j) as u8;
+        }
+        ensure!(net.send(frame).is_ok(), "loopback send failed");
+    }
+    let mut buf = [0u8; MAX_FRAME_SIZE];
+    for frame in &frames {
+        match net.recv(&mut buf) {
+            Ok(n) if buf[..n] == frame[..] => {}
+            _ => return TestResult::Failed("frame not echoed back in order"),
+        }
+    }
+    // Drained: nothing left to receive.
+    ensure!(net.recv(&mut buf) == Ok(0), "extra frame after the echoes");
+    TestResult::Ok
+}
+
+/// A ping sent over loopback reaches the ICMP responder, and its echo reply
+/// comes back the same way.
+fn loopback_ping_answered() -> TestResult {
+    const HOST_IP: [u8; 4] = [127, 0, 0, 1];
+    let Some(net) = loopback() else { return TestResult::Failed("loopback connect failed") };
+
+    let ping = IcmpEcho {
+        source_mac: LOOPBACK_MAC,
+        destination_mac: LOOPBACK_MAC,
+        source_ip: HOST_IP,
+        destination_ip: HOST_IP,
+        kind: ICMP_ECHO_REQUEST,
+        identifier: 0xb05,
+        sequence: 1,
+        payload: b"utest ping",
+    };
+    let mut frame = [0u8; MAX_FRAME_SIZE];
+    let Some(len) = ping.write_frame(&mut frame) else { return TestResult::Failed("ping does not fit a frame") };
+    ensure!(net.send(&frame[..len]).is_ok(), "ping send failed");
+
+    // Responder side: take the ping off the interface and answer it.
+    let mut reply = [0u8; MAX_FRAME_SIZE];
+    let Ok(n) = net.recv(&mut frame) else { return TestResult::Failed("ping not looped back") };
+    let Some(reply_len) = inet::respond(&frame[..n], LOOPBACK_MAC, HOST_IP, &mut reply) else {
+        return TestResult::Failed("responder did not answer the ping");
+    };
+    ensure!(net.send(&reply[..reply_len]).is_ok(), "echo reply send failed");
+
+    // Pinger side: the echo reply.
+    let Ok(n) = net.recv(&mut frame) else { return TestResult::Failed("echo reply not looped back") };
+    ensure!(
+        matches!(
+            IcmpEcho::parse(&frame[..n]),
+            Some(echo) if echo.kind == ICMP_ECHO_REPLY
+                && (echo.identifier, echo.sequence) == (ping.identifier, ping.sequence)
+                && echo.payload == ping.payload
+        ),
+        "echo reply does not match the ping"
+    );
+    TestResult::Ok
 }
 
 // ---------------------------------------------------------------------------
@@ -133,26 +1178,33 @@ fn channel_close_peer() -> bool {
 
 static UTEST_SERVICE_EP: AtomicU64 = AtomicU64::new(0);
 
-fn service_register() -> bool {
-    let (send_ep, _recv_ep) = ulib::sys_channel_create(1);
-    let result = ulib::sys_register_service(b"utest_dummy", send_ep);
-    if result == SVC_OK {
-        UTEST_SERVICE_EP.store(send_ep, Ordering::Relaxed);
-        true
-    } else {
-        ulib::sys_channel_close(send_ep);
-        false
-    }
+fn service_register() -> TestResult {
+    let (send_ep, _recv_ep) = channel!(1);
+    ensure!(ulib::sys_register_service(b"utest_dummy", send_ep).is_ok(), "register failed");
+    UTEST_SERVICE_EP.store(send_ep, Ordering::Relaxed);
+    TestResult::Ok
 }
 
-fn service_lookup() -> bool {
+fn service_lookup() -> TestResult {
     let expected = UTEST_SERVICE_EP.load(Ordering::Relaxed);
-    let found = ulib::sys_lookup_service(b"utest_dummy");
-    found == expected
+    ensure!(ulib::sys_lookup_service(b"utest_dummy") == Ok(expected), "lookup did not find the registered endpoint");
+    TestResult::Ok
 }
 
-fn service_lookup_missing() -> bool {
-    ulib::sys_lookup_service(b"no_such_service") == SVC_ERR_NOT_FOUND
+fn service_lookup_missing() -> TestResult {
+    ensure!(
+        ulib::sys_lookup_service(b"no_such_service") == Err(SysError::NotFound),
+        "lookup of an unknown service not NotFound"
+    );
+    TestResult::Ok
+}
+
+fn service_register_duplicate() -> TestResult {
+    let (send_ep, recv_ep) = channel!(1);
+    let result = ulib::sys_register_service(b"utest_dummy", send_ep);
+    let _ = ulib::sys_channel_close(recv_ep);
+    ensure!(result == Err(SysError::AlreadyExists), "duplicate register not AlreadyExists");
+    TestResult::Ok
 }
 
 // ---------------------------------------------------------------------------
@@ -164,8 +1216,7 @@ static DS_ENDPOINT: AtomicU64 = AtomicU64::new(0);
 /// Poll the service registry until the "display" service appears, then cache its endpoint.
 fn wait_for_display_service() {
     loop {
-        let ep = ulib::sys_lookup_service(b"display");
-        if ep != SVC_ERR_NOT_FOUND {
+        if let Ok(ep) = ulib::sys_lookup_service(b"display") {
             DS_ENDPOINT.store(ep, Ordering::Relaxed);
             return;
         }
@@ -173,27 +1224,31 @@ fn wait_for_display_service() {
     }
 }
 
-fn display_info() -> bool {
+fn display_info() -> TestResult {
     let info = ulib::sys_get_display_info();
-    info.width > 0 && info.height > 0
+    ensure!(info.width > 0 && info.height > 0, "empty display");
+    TestResult::Ok
 }
 
-fn display_registered() -> bool {
-    ulib::sys_lookup_service(b"display") != SVC_ERR_NOT_FOUND
+fn display_registered() -> TestResult {
+    ensure!(ulib::sys_lookup_service(b"display").is_ok(), "display service not registered");
+    TestResult::Ok
 }
 
-fn create_window_ok() -> bool {
+fn create_window_ok() -> TestResult {
     let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
-    ulib::window::Window::new(ds_ep, 100, 100, 0, 0).is_some()
+    ensure!(ulib::window::Window::new(ds_ep, 100, 100, 0, 0).is_some(), "create window failed");
+    TestResult::Ok
 }
 
-fn create_window_bad_dims() -> bool {
+fn create_window_bad_dims() -> TestResult {
     let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
     // Width=0 should be rejected by display server as ErrorInvalidDimensions
-    ulib::window::Window::new(ds_ep, 0, 100, 0, 0).is_none()
+    ensure!(ulib::window::Window::new(ds_ep, 0, 100, 0, 0).is_none(), "zero-width window not rejected");
+    TestResult::Ok
 }
 
-fn update_window() -> bool {
+fn update_window() -> TestResult {
     use embedded_graphics::{
         draw_target::DrawTarget,
         geometry::{Point, Size},
@@ -204,14 +1259,483 @@ fn update_window() -> bool {
     let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
     let mut window = match ulib::window::Window::new(ds_ep, 50, 50, 200, 200) {
         Some(w) => w,
-        None => return false,
+        None => return TestResult::Failed("create window failed"),
     };
 
     let red = Rgb888::RED;
     let area = Rectangle::new(Point::new(0, 0), Size::new(50, 50));
     let _ = window.fill_solid(&area, red);
     window.present();
-    true
+    TestResult::Ok
+}
+
+/// `present_region` sends one line of the damage and keeps the rest pending.
+fn window_present_region() -> TestResult {
+    use embedded_graphics::{
+        draw_target::DrawTarget,
+        geometry::{Point, Size},
+        pixelcolor::{Rgb888, RgbColor},
+        primitives::Rectangle,
+    };
+    use ulib::window::DirtyRect;
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let Some(mut window) = ulib::window::Window::new(ds_ep, 64, 48, 300, 200) else {
+        return TestResult::Failed("create window failed");
+    };
+    let _ = window.fill_solid(&Rectangle::new(Point::new(0, 0), Size::new(64, 48)), Rgb888::BLUE);
+
+    // The first 8-pixel line goes; the rest stays dirty.
+    window.present_region(DirtyRect { x: 0, y: 0, w: 64, h: 8 });
+    let after_line = window.dirty();
+    // A region outside the damage sends nothing.
+    window.present_region(DirtyRect { x: 100, y: 100, w: 8, h: 8 });
+    let after_miss = window.dirty();
+    window.present();
+
+    ensure!(after_line == Some(DirtyRect { x: 0, y: 8, w: 64, h: 40 }), "wrong damage left after one line");
+    ensure!(after_miss == after_line, "region outside the damage changed it");
+    ensure!(window.dirty().is_none(), "damage left after a full present");
+    TestResult::Ok
+}
+
+/// `present_sync` returns once the server has composited the update, and
+/// straight away when there is nothing to present.
+fn window_present_sync() -> TestResult {
+    use embedded_graphics::{
+        draw_target::DrawTarget,
+        geometry::{Point, Size},
+        pixelcolor::{Rgb888, RgbColor},
+        primitives::Rectangle,
+    };
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let Some(mut window) = ulib::window::Window::new(ds_ep, 32, 32, 340, 200) else {
+        return TestResult::Failed("create window failed");
+    };
+    let mut acked = true;
+    for _ in 0..3 {
+        let _ = window.fill_solid(&Rectangle::new(Point::new(0, 0), Size::new(32, 32)), Rgb888::YELLOW);
+        acked &= window.present_sync().is_ok();
+    }
+    let nothing_dirty = window.present_sync().is_ok();
+    window.close();
+    ensure!(acked, "present_sync not acknowledged");
+    ensure!(nothing_dirty, "present_sync with nothing dirty failed");
+    TestResult::Ok
+}
+
+fn window_draw_line() -> TestResult {
+    use embedded_graphics::{geometry::Point, pixelcolor::{Rgb888, RgbColor}};
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let mut window = match ulib::window::Window::new(ds_ep, 32, 32, 260, 200) {
+        Some(w) => w,
+        None => return TestResult::Failed("create window failed"),
+    };
+
+    let green = ulib::sys_get_display_info().build_pixel(0, 255, 0);
+    window.draw_line(Point::new(0, 0), Point::new(31, 31), Rgb888::GREEN);
+    window.present();
+
+    // Both endpoints and a point on the diagonal are lit; an off-diagonal pixel is not.
+    ensure!(window.pixel(0, 0) == Some(green), "start point not lit");
+    ensure!(window.pixel(16, 16) == Some(green), "midpoint not lit");
+    ensure!(window.pixel(31, 31) == Some(green), "end point not lit");
+    ensure!(window.pixel(31, 0) != Some(green), "off-diagonal pixel lit");
+    TestResult::Ok
+}
+
+/// A window's size limits clamp its creation and every resize, rather
+/// than failing them.
+fn window_size_limits_clamp() -> TestResult {
+    use embedded_graphics::{geometry::Point, pixelcolor::{Rgb888, RgbColor}};
+    use kernel_api_types::window::{SizeLimits, WindowBounds};
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let limits = SizeLimits { min_width: 40, min_height: 30, max_width: 120, max_height: 90 };
+    let Ok(mut window) = ulib::window::Window::try_new_with_limits(ds_ep, 10, 10, 380, 200, limits) else {
+        return TestResult::Failed("create window failed");
+    };
+    let created = window.bounding_box();
+    let below_min = window.resize(5, 5);
+    let in_range = window.resize(64, 48);
+    let above_max = window.resize(1000, 1000);
+    let bounds = window.bounding_box();
+    // The buffer was mapped again at the granted size: its far corner is drawable.
+    let red = ulib::sys_get_display_info().build_pixel(255, 0, 0);
+    window.fill_circle(Point::new(110, 80), 5, Rgb888::RED);
+    let lit = window.pixel(110, 80) == Some(red) && window.pixel(120, 0).is_none();
+    window.close();
+
+    ensure!(
+        created == Some(WindowBounds { x: 380, y: 200, width: 40, height: 30 }),
+        "creation not clamped to the minimum"
+    );
+    ensure!(below_min == Ok((40, 30)), "resize below the minimum not clamped");
+    ensure!(in_range == Ok((64, 48)), "resize within the limits changed");
+    ensure!(above_max == Ok((120, 90)), "resize above the maximum not clamped");
+    ensure!(
+        bounds == Some(WindowBounds { x: 380, y: 200, width: 120, height: 90 }),
+        "server bounds differ from the granted size"
+    );
+    ensure!(lit, "buffer not remapped at the granted size");
+    TestResult::Ok
+}
+
+fn window_bounding_box_tracks_moves() -> TestResult {
+    use kernel_api_types::window::WindowBounds;
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let window = match ulib::window::Window::new(ds_ep, 40, 30, 10, 20) {
+        Some(w) => w,
+        None => return TestResult::Failed("create window failed"),
+    };
+    let initial = window.bounding_box();
+
+    // The handle doesn't record where the window is, so after a move its only
+    // way to learn the new position is to ask the server. The query is queued
+    // behind the move on the same channel, so it sees the result.
+    window.move_to(-5, 70);
+    let moved = window.bounding_box();
+
+    ensure!(initial == Some(WindowBounds { x: 10, y: 20, width: 40, height: 30 }), "wrong initial bounds");
+    ensure!(moved == Some(WindowBounds { x: -5, y: 70, width: 40, height: 30 }), "bounds did not follow the move");
+    TestResult::Ok
+}
+
+/// Animate a window across the screen: besides the start and end positions at
+/// least one move in between must be sent, and the window must end up exactly
+/// at the target.
+fn window_animate_move() -> TestResult {
+    use embedded_graphics::geometry::Point;
+    use kernel_api_types::window::WindowBounds;
+    use ulib::animation::Easing;
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let window = match ulib::window::Window::new(ds_ep, 20, 20, 0, 400) {
+        Some(w) => w,
+        None => return TestResult::Failed("create window failed"),
+    };
+    let linear = window.animate_move(Point::new(0, 400), Point::new(200, 400), 100, Easing::Linear);
+    let eased = window.animate_move(Point::new(200, 400), Point::new(50, 450), 100, Easing::EaseInOut);
+
+    ensure!(linear > 2, "linear animation sent no intermediate move");
+    ensure!(eased > 2, "eased animation sent no intermediate move");
+    ensure!(
+        window.bounding_box() == Some(WindowBounds { x: 50, y: 450, width: 20, height: 20 }),
+        "window did not end at the target"
+    );
+    TestResult::Ok
+}
+
+/// In step mode, creating a window must not composite by itself; one step
+/// presents it, and a second step with nothing new presents nothing.
+fn compositor_step_mode() -> TestResult {
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let Some(start) = ulib::window::set_step_mode(ds_ep, true) else {
+        return TestResult::Failed("entering step mode failed");
+    };
+    let created = ulib::window::Window::new(ds_ep, 24, 24, 500, 300).is_some();
+    // `Window::new` is a call, so the server has handled it by now.
+    let before_step = ulib::window::step(ds_ep).map(|r| r.composites);
+    let idle_step = ulib::window::step(ds_ep).map(|r| r.composites);
+    let end = ulib::window::set_step_mode(ds_ep, false);
+
+    ensure!(created, "create window failed");
+    ensure!(before_step == Some(start + 1), "step did not present exactly once");
+    ensure!(idle_step == Some(start + 1), "idle step presented");
+    ensure!(end == Some(start + 1), "leaving step mode failed or presented");
+    TestResult::Ok
+}
+
+/// A window moved entirely off screen is left out of compositing, a move
+/// far past the edge is clamped, and the window can be moved back.
+fn offscreen_window_skipped() -> TestResult {
+    use kernel_api_types::window::WindowBounds;
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let info = ulib::sys_get_display_info();
+    let Some(window) = ulib::window::Window::new(ds_ep, 40, 40, 100, 100) else {
+        return TestResult::Failed("create window failed");
+    };
+    ensure!(ulib::window::set_step_mode(ds_ep, true).is_some(), "entering step mode failed");
+    // Raising redraws the whole scene, blitting every window on screen once.
+    let full_redraw_blits = || {
+        let before = ulib::window::step(ds_ep)?.window_blits;
+        window.raise();
+        Some(ulib::window::step(ds_ep)?.window_blits - before)
+    };
+    let with_window = full_redraw_blits();
+    window.move_to(info.width as i32 + 10, 100);
+    let parked = full_redraw_blits();
+
+    window.move_to(i32::MAX, 100);
+    let clamped = window.bounding_box();
+    window.move_to(100, 100);
+    let back = full_redraw_blits();
+    let _ = ulib::window::set_step_mode(ds_ep, false);
+    let bounds = window.bounding_box();
+    window.close();
+
+    let (Some(with_window), Some(parked), Some(back)) = (with_window, parked, back) else {
+        return TestResult::Failed("step failed");
+    };
+    ensure!(parked + 1 == with_window, "off-screen window still blitted");
+    ensure!(back == with_window, "window not blitted after moving back");
+    ensure!(clamped.is_some_and(|b| b.x == 2 * info.width as i32), "far move not clamped");
+    ensure!(bounds == Some(WindowBounds { x: 100, y: 100, width: 40, height: 40 }), "window not back in place");
+    TestResult::Ok
+}
+
+/// Send a framed request with `sys_channel_call` and check the server found the
+/// appended reply endpoint: asking about a window that doesn't exist must still
+/// get a (failed) answer rather than hang.
+fn channel_call_to_display_server() -> TestResult {
+    use kernel_api_types::window::{GetWindowBoundsRequest, GetWindowBoundsResponse, WindowMessageType, WindowResult};
+    use ulib::ipc::{Frame, Message};
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let tag = WindowMessageType::GetWindowBounds as u16;
+    let msg = Message::new(tag, &GetWindowBoundsRequest { window_id: u64::MAX });
+
+    let mut reply = [0u8; 64];
+    let Ok(n) = ulib::sys_channel_call(ds_ep, msg.as_bytes(), &mut reply) else {
+        return TestResult::Failed("call failed");
+    };
+    let Some(frame) = Frame::parse(&reply[..n as usize]) else {
+        return TestResult::Failed("reply is not a frame");
+    };
+    ensure!(frame.tag == tag, "reply has the wrong tag");
+    ensure!(
+        frame.read::<GetWindowBoundsResponse>().is_some_and(|r| r.result == WindowResult::ErrorInvalidWindowId),
+        "unknown window not reported as ErrorInvalidWindowId"
+    );
+    TestResult::Ok
+}
+
+/// Damage a window and close it in the same batch, so the server handles the
+/// update and the close in one drain before it flushes. If the flush read the
+/// window's buffer after the close unmapped it, the page fault would take the
+/// whole run down; a server that survives keeps answering requests.
+fn window_update_then_close() -> TestResult {
+    use embedded_graphics::{
+        draw_target::DrawTarget,
+        geometry::{Point, Size},
+        pixelcolor::{Rgb888, RgbColor},
+        primitives::Rectangle,
+    };
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let mut window = match ulib::window::Window::new(ds_ep, 64, 64, 300, 40) {
+        Some(w) => w,
+        None => return TestResult::Failed("create window failed"),
+    };
+    let _ = window.fill_solid(&Rectangle::new(Point::new(0, 0), Size::new(64, 64)), Rgb888::BLUE);
+    window.close();
+
+    // Same spot, so the next composite covers the closed window's old area.
+    let Some(w) = ulib::window::Window::new(ds_ep, 64, 64, 300, 40) else {
+        return TestResult::Failed("server stopped answering after the close");
+    };
+    ensure!(w.bounding_box().is_some(), "server stopped answering after the close");
+    TestResult::Ok
+}
+
+/// A mode change reaches every subscriber with the new size, and a size
+/// larger than the framebuffer is refused without notifying anyone.
+fn display_change_notifies_subscribers() -> TestResult {
+    use ulib::window::{set_display_mode, DisplayEvents};
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let native = ulib::sys_get_display_info();
+    let (Ok(a), Ok(b)) = (DisplayEvents::subscribe(), DisplayEvents::subscribe()) else {
+        return TestResult::Failed("subscribe failed");
+    };
+    let Ok(half) = set_display_mode(ds_ep, native.width / 2, native.height / 2) else {
+        return TestResult::Failed("shrinking the mode failed");
+    };
+    let resized = (half.width, half.height) == (native.width / 2, native.height / 2);
+    let notified = a.wait() == Ok(half) && b.wait() == Ok(half);
+
+    let too_big = set_display_mode(ds_ep, native.width + 1, native.height);
+    let restored = set_display_mode(ds_ep, native.width, native.height) == Ok(native);
+
+    ensure!(resized, "mode not the size asked for");
+    ensure!(notified, "a subscriber missed the shrink");
+    ensure!(too_big == Err(WindowResult::ErrorInvalidDimensions), "mode larger than the framebuffer not refused");
+    ensure!(restored, "restoring the native mode failed");
+    ensure!(a.wait() == Ok(native) && b.wait() == Ok(native), "a subscriber missed the restore");
+    ensure!(a.poll().is_none(), "refused mode change was announced");
+    TestResult::Ok
+}
+
+/// A prompt on the kernel's display-change broadcast makes the display
+/// server re-read the framebuffer mode: it drops a `SetDisplayMode` shrink and
+/// tells its own subscribers about the native mode.
+fn framebuffer_change_restores_native_mode() -> TestResult {
+    use kernel_api_types::graphics::KERNEL_DISPLAY_EVENTS_SERVICE;
+    use ulib::window::{set_display_mode, DisplayEvents};
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let native = ulib::sys_get_display_info();
+    let (Ok(kernel_events), Ok(events)) = (ulib::sys_lookup_service(KERNEL_DISPLAY_EVENTS_SERVICE), DisplayEvents::subscribe()) else {
+        return TestResult::Failed("kernel display events lookup or subscribe failed");
+    };
+    let Ok(half) = set_display_mode(ds_ep, native.width / 2, native.height / 2) else {
+        return TestResult::Failed("shrinking the mode failed");
+    };
+    let shrunk = events.wait() == Ok(half);
+
+    // Stands in for the kernel announcing a mode switch.
+    let info_bytes = unsafe {
+        core::slice::from_raw_parts(&native as *const _ as *const u8, core::mem::size_of_val(&native))
+    };
+    if ulib::sys_channel_send(kernel_events, info_bytes).is_err() {
+        let _ = set_display_mode(ds_ep, native.width, native.height);
+        return TestResult::Failed("send on the kernel broadcast failed");
+    }
+
+    ensure!(shrunk, "shrink not announced");
+    ensure!(events.wait() == Ok(native), "native mode not restored and announced");
+    ensure!(events.poll().is_none(), "extra display event");
+    TestResult::Ok
+}
+
+/// Fill every free window slot: the next create fails with
+/// `ErrorTooManyWindows`, and closing one of ours makes room again. Earlier
+/// tests may hold slots, so this fills whatever is left.
+/// An always-on-top window stays over a normal one that is raised after it.
+fn window_always_on_top() -> TestResult {
+    use ulib::window::{window_at, Window};
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let (Some(normal), Some(tooltip)) = (Window::new(ds_ep, 40, 40, 700, 20), Window::new(ds_ep, 40, 40, 720, 40)) else {
+        return TestResult::Failed("create window failed");
+    };
+    tooltip.set_always_on_top(true);
+    normal.raise();
+    // `window_at` is a call, so the server has handled both requests by now.
+    let overlap = window_at(ds_ep, 730, 50);
+    let normal_only = window_at(ds_ep, 705, 25);
+    tooltip.lower();
+    let after_lower = window_at(ds_ep, 730, 50);
+
+    let (normal_id, tooltip_id) = (normal.id(), tooltip.id());
+    normal.close();
+    tooltip.close();
+    ensure!(overlap == Some(tooltip_id), "raised normal window covered the always-on-top one");
+    ensure!(normal_only == Some(normal_id), "normal window not found where only it is");
+    ensure!(after_lower == Some(tooltip_id), "lowering took the window out of the always-on-top band");
+    TestResult::Ok
+}
+
+/// A window can set a cursor, a bad one, and reset it, and the server keeps
+/// answering; closing the window with its cursor still set is fine too.
+fn window_set_cursor() -> TestResult {
+    use kernel_api_types::window::{CursorImage, MAX_CURSOR_SIZE};
+    use ulib::window::{window_at, Window};
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let Some(window) = Window::new(ds_ep, 60, 60, 16, 16) else {
+        return TestResult::Failed("create window failed");
+    };
+    // An I-beam: 7 × 16, hot spot in the middle.
+    let mut ibeam = CursorImage { width: 7, height: 16, hot_x: 3, hot_y: 8, mask: [0; MAX_CURSOR_SIZE], image: [0; MAX_CURSOR_SIZE] };
+    for row in 0..16 {
+        ibeam.mask[row] = if row == 0 || row == 15 { 0xFE00_0000 } else { 0x1000_0000 };
+    }
+    window.set_cursor(&ibeam);
+    window.set_cursor(&CursorImage { width: MAX_CURSOR_SIZE as u32 + 1, ..ibeam });
+    window.reset_cursor();
+    window.set_cursor(&ibeam);
+    let id = window.id();
+    // `window_at` is a call, so the server has handled the cursors by now.
+    let hit = window_at(ds_ep, 70, 70);
+    window.close();
+    ensure!(hit == Some(id), "server stopped answering after SetCursor");
+    ensure!(window_at(ds_ep, 70, 70) != Some(id), "window still there after close");
+    TestResult::Ok
+}
+
+fn window_limit() -> TestResult {
+    use ulib::window::Window;
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let mut windows: [Option<Window>; MAX_WINDOWS] = [const { None }; MAX_WINDOWS];
+    let mut full = None;
+    for slot in windows.iter_mut() {
+        match Window::try_new(ds_ep, 1, 1, 0, 0) {
+            Ok(w) => *slot = Some(w),
+            Err(e) => {
+                full = Some(e);
+                break;
+            }
+        }
+    }
+    // All MAX_WINDOWS may have been ours; the next one must still fail.
+    if full.is_none() {
+        full = Window::try_new(ds_ep, 1, 1, 0, 0).err();
+    }
+
+    let mut result = TestResult::Ok;
+    if full != Some(WindowResult::ErrorTooManyWindows) {
+        result = TestResult::Failed("create past the limit not ErrorTooManyWindows");
+    }
+    match windows[0].take() {
+        Some(w) => {
+            w.close();
+            match Window::try_new(ds_ep, 1, 1, 0, 0) {
+                Ok(w) => windows[0] = Some(w),
+                Err(_) => result = TestResult::Failed("closing a window did not make room"),
+            }
+        }
+        None => result = TestResult::Failed("no window slot was free"),
+    }
+    for w in windows.iter_mut().filter_map(Option::take) {
+        w.close();
+    }
+    result
+}
+
+/// Present far faster than the server composites: with its channel backed up
+/// it must eventually hint this window to slow down, naming it.
+fn window_throttled_when_flooding() -> TestResult {
+    use embedded_graphics::{
+        draw_target::DrawTarget,
+        geometry::{Point, Size},
+        pixelcolor::{Rgb888, RgbColor},
+        primitives::Rectangle,
+    };
+
+    let ds_ep = DS_ENDPOINT.load(Ordering::Relaxed);
+    let Some(mut window) = ulib::window::Window::new(ds_ep, 16, 16, 600, 500) else {
+        return TestResult::Failed("create window failed");
+    };
+    if window.enable_events().is_err() {
+        window.close();
+        return TestResult::Failed("enabling window events failed");
+    }
+
+    let tsc_hz = ulib::sys_get_tsc_hz().max(1);
+    let start = ulib::sys_get_cycles();
+    let mut hint = None;
+    let mut i = 0u32;
+    while hint.is_none() && ulib::sys_get_cycles() - start < tsc_hz * 2 {
+        let color = if i % 2 == 0 { Rgb888::RED } else { Rgb888::GREEN };
+        let _ = window.fill_solid(&Rectangle::new(Point::new(0, 0), Size::new(16, 16)), color);
+        // A full channel just drops this present; the next one retries.
+        window.present();
+        hint = window.poll_throttle();
+        i += 1;
+    }
+
+    let id = window.id();
+    window.close();
+    let Some(hint) = hint else { return TestResult::Failed("no throttle hint within two seconds") };
+    ensure!(hint.window_id == id, "throttle hint names another window");
+    ensure!(hint.coalesced_updates >= 2, "throttle hint coalesced fewer than two updates");
+    TestResult::Ok
 }
 
 // ---------------------------------------------------------------------------
@@ -219,36 +1743,124 @@ fn update_window() -> bool {
 // ---------------------------------------------------------------------------
 
 #[unsafe(no_mangle)]
-unsafe extern "sysv64" fn entry_point(_arg: u64) -> ! {
+unsafe extern "sysv64" fn entry_point(arg: u64) -> ! {
+    let _ = ulib::sys_set_task_name("utest");
+    if arg == SPIN_FOREVER {
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+    if arg == WAKE_LATENCY {
+        let worst = measure_wake_latency();
+        ulib::sys_exit(worst);
+    }
+    if arg == LOWER_PRIORITY {
+        ulib::sys_exit(check_priority_limits());
+    }
+    if arg == TRY_SYSCALL_TRACE {
+        let denied = ulib::sys_set_syscall_trace(true) == Err(SysError::PermissionDenied);
+        ulib::sys_exit(if denied { 0 } else { 1 });
+    }
+    if arg == EXIT_AT_ONCE {
+        ulib::sys_exit(EXIT_AT_ONCE_CODE);
+    }
+    if arg == IPC_ECHO {
+        run_echo_server();
+        ulib::sys_exit(1);
+    }
+    if arg == OVERFLOW_STACK || arg == GROW_STACK {
+        if arg == GROW_STACK {
+            ulib::sys_exit(if grow_stack() { 0 } else { 1 });
+        }
+        recurse_deep(0, u64::MAX);
+        ulib::sys_exit(0);
+    }
+
     let mut runner = TestRunner::new();
 
     // Memory tests
-    runner.run(mmap_nonzero);
-    runner.run(mmap_writable);
-    runner.run(mmap_independent);
-    runner.run(munmap_ok);
+    runner.run_named("mmap_nonzero", mmap_nonzero);
+    runner.run_named("mmap_writable", mmap_writable);
+    runner.run_named("mmap_independent", mmap_independent);
+    runner.run_named("munmap_ok", munmap_ok);
+    runner.run_named("munmap_bad_range", munmap_bad_range);
+    runner.run_named("mmap_lazy_populate", mmap_lazy_populate);
+    runner.run_named("mmap_lazy_fault_in", mmap_lazy_fault_in);
+    runner.run_named("mincore_into_lazy_page", mincore_into_lazy_page);
+    runner.run_named("module_read_in_chunks", module_read_in_chunks);
+    runner.run_named("spawn_error_codes", spawn_error_codes);
+    runner.run_named("stack_grows_on_demand", stack_grows_on_demand);
+    runner.run_named("stack_overflow_kills_task", stack_overflow_kills_task);
+    runner.run_named("kill_child", kill_child);
+    runner.run_named("waitpid_after_child_exited", waitpid_after_child_exited);
+    runner.run_named("user_priority_only_lowers", user_priority_only_lowers);
+    runner.run_named("user_cannot_trace_syscalls", user_cannot_trace_syscalls);
+    runner.run_named("list_tasks_includes_self", list_tasks_includes_self);
 
     // IPC tests
-    runner.run(channel_create);
-    runner.run(channel_loopback);
-    runner.run(channel_recv_size);
-    runner.run(channel_full);
-    runner.run(channel_close_peer);
+    runner.run_named("channel_create", channel_create);
+    runner.run_named("channel_loopback", channel_loopback);
+    runner.run_named("channel_recv_size", channel_recv_size);
+    runner.run_named("channel_recv_truncated", channel_recv_truncated);
+    runner.run_named("channel_full", channel_full);
+    runner.run_named("channel_close_peer", channel_close_peer);
+    runner.run_named("channel_closed_endpoint", channel_closed_endpoint);
+    runner.run_named("channel_dup", channel_dup);
+    runner.run_named("channel_pass_endpoint", channel_pass_endpoint);
+    runner.run_named("broadcast_two_subscribers", broadcast_two_subscribers);
+    runner.run_named("channel_batched_sends", channel_batched_sends);
+    runner.run_named("batch_skips_blocking_ops", batch_skips_blocking_ops);
+    runner.run_named("batch_unmaps_own_ops", batch_unmaps_own_ops);
+    runner.run_named("ring_completions", ring_completions);
+    runner.run_named("ring_munmap_of_itself_rejected", ring_munmap_of_itself_rejected);
+    runner.run_named("channel_call_oversized_request", channel_call_oversized_request);
+
+    // Syscall latency benchmark
+    runner.run_named("syscall_latency", syscall_latency);
+    runner.run_named("cycles_advance", cycles_advance);
+    runner.run_named("sleep_duration", sleep_duration);
+    runner.run_named("wakeup_preempts", wakeup_preempts);
+    runner.run_named("ipc_round_trip_latency", ipc_round_trip_latency);
+    runner.run_named("wallclock_plausible", wallclock_plausible);
+    runner.run_named("random_fills_differ", random_fills_differ);
+    runner.run_named("serial_service_write", serial_service_write);
+    runner.run_named("serial_service_read", serial_service_read);
+    runner.run_named("input_record_replay", input_record_replay);
+    runner.run_named("net_arp_roundtrip", net_arp_roundtrip);
+    runner.run_named("loopback_echoes_frames", loopback_echoes_frames);
+    runner.run_named("loopback_ping_answered", loopback_ping_answered);
 
     // Service registry tests
-    runner.run(service_register);
-    runner.run(service_lookup);
-    runner.run(service_lookup_missing);
+    runner.run_named("service_register", service_register);
+    runner.run_named("service_lookup", service_lookup);
+    runner.run_named("service_lookup_missing", service_lookup_missing);
+    runner.run_named("service_register_duplicate", service_register_duplicate);
 
     // Wait for display server before running display tests
     wait_for_display_service();
 
     // Display server tests
-    runner.run(display_info);
-    runner.run(display_registered);
-    runner.run(create_window_ok);
-    runner.run(create_window_bad_dims);
-    runner.run(update_window);
+    runner.run_named("display_info", display_info);
+    runner.run_named("display_registered", display_registered);
+    runner.run_named("create_window_ok", create_window_ok);
+    runner.run_named("create_window_bad_dims", create_window_bad_dims);
+    runner.run_named("update_window", update_window);
+    runner.run_named("window_present_region", window_present_region);
+    runner.run_named("window_present_sync", window_present_sync);
+    runner.run_named("window_draw_line", window_draw_line);
+    runner.run_named("window_bounding_box_tracks_moves", window_bounding_box_tracks_moves);
+    runner.run_named("window_size_limits_clamp", window_size_limits_clamp);
+    runner.run_named("window_update_then_close", window_update_then_close);
+    runner.run_named("channel_call_to_display_server", channel_call_to_display_server);
+    runner.run_named("compositor_step_mode", compositor_step_mode);
+    runner.run_named("offscreen_window_skipped", offscreen_window_skipped);
+    runner.run_named("window_animate_move", window_animate_move);
+    runner.run_named("window_always_on_top", window_always_on_top);
+    runner.run_named("window_set_cursor", window_set_cursor);
+    runner.run_named("display_change_notifies_subscribers", display_change_notifies_subscribers);
+    runner.run_named("framebuffer_change_restores_native_mode", framebuffer_change_restores_native_mode);
+    runner.run_named("window_limit", window_limit);
+    runner.run_named("window_throttled_when_flooding", window_throttled_when_flooding);
 
     runner.finish()
 }